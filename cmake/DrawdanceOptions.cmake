@@ -22,3 +22,6 @@ endif()
 add_feature_info("Image library implementation (IMAGE_IMPL)" ON ${IMAGE_IMPL})
 add_feature_info("File I/O implementation (FILE_IO_IMPL)" ON ${FILE_IO_IMPL})
 add_feature_info("ZIP implementation (ZIP_IMPL)" ON ${ZIP_IMPL})
+
+set(TILE_SIZE 64 CACHE STRING "Canvas tile size in pixels (32, 64, 128, 256)")
+add_feature_info("Canvas tile size (TILE_SIZE)" ON ${TILE_SIZE})
@@ -470,6 +470,10 @@ QComboBox *SessionSettingsDialog::featureBox(DP_Feature f)
 		return m_ui->permTimeline;
 	case DP_FEATURE_MYPAINT:
 		return m_ui->permMyPaint;
+	case DP_FEATURE_CLASSIC_BRUSH:
+		return m_ui->permClassicBrush;
+	case DP_FEATURE_PIXEL_BRUSH:
+		return m_ui->permPixelBrush;
 	default:
 		Q_ASSERT_X(false, "featureBox", "unhandled case");
 		return nullptr;
@@ -498,6 +502,10 @@ void SessionSettingsDialog::onFeatureTiersChanged(
 	m_ui->permTimeline->setCurrentIndex(
 		int(features.tiers[DP_FEATURE_TIMELINE]));
 	m_ui->permMyPaint->setCurrentIndex(int(features.tiers[DP_FEATURE_MYPAINT]));
+	m_ui->permClassicBrush->setCurrentIndex(
+		int(features.tiers[DP_FEATURE_CLASSIC_BRUSH]));
+	m_ui->permPixelBrush->setCurrentIndex(
+		int(features.tiers[DP_FEATURE_PIXEL_BRUSH]));
 }
 
 const QByteArray SessionSettingsDialog::authExportPrefix =
@@ -689,6 +697,8 @@ void SessionSettingsDialog::setCompatibilityMode(bool compatibilityMode)
 		m_ui->permMyPaint->setEnabled(false);
 		m_ui->permTimeline->setEnabled(false);
 		m_ui->permMetadata->setEnabled(false);
+		m_ui->permClassicBrush->setEnabled(false);
+		m_ui->permPixelBrush->setEnabled(false);
 	}
 }
 
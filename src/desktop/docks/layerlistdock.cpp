@@ -407,7 +407,8 @@ void LayerList::updateLockedControls()
 	const bool enabled =
 		!locked && m_selectedId &&
 		(canEdit ||
-		 (ownLayers && (m_selectedId >> 8) == m_canvas->localUserId()));
+		 (ownLayers &&
+		  DP_layer_id_creator(m_selectedId) == m_canvas->localUserId()));
 
 	m_lockButton->setEnabled(enabled);
 	m_blendModeCombo->setEnabled(enabled);
@@ -503,7 +504,7 @@ void LayerList::selectLayerIndex(QModelIndex index, bool scrollTo)
 
 QString LayerList::layerCreatorName(uint16_t layerId) const
 {
-	return m_canvas->userlist()->getUsername((layerId >> 8) & 0xff);
+	return m_canvas->userlist()->getUsername(DP_layer_id_creator(layerId));
 }
 
 void LayerList::censorSelected(bool censor)
@@ -1111,7 +1112,7 @@ dialogs::LayerProperties *LayerList::makeLayerPropertiesDialog(
 	bool isOwnLayer;
 	if(index.isValid()) {
 		int layerId = index.data(canvas::LayerListModel::IdRole).toInt();
-		isOwnLayer = (layerId & 0xff00) >> 8 == m_canvas->localUserId();
+		isOwnLayer = DP_layer_id_creator(layerId) == m_canvas->localUserId();
 		connect(
 			dlg, &dialogs::LayerProperties::visibilityChanged, this,
 			&LayerList::setLayerVisibility);
@@ -3128,6 +3128,12 @@ void MainWindow::onFeatureAccessChange(DP_Feature feature, bool canUse)
 	case DP_FEATURE_MYPAINT:
 		m_dockToolSettings->brushSettings()->setMyPaintAllowed(canUse);
 		break;
+	case DP_FEATURE_CLASSIC_BRUSH:
+		m_dockToolSettings->brushSettings()->setClassicBrushAllowed(canUse);
+		break;
+	case DP_FEATURE_PIXEL_BRUSH:
+		m_dockToolSettings->brushSettings()->setPixelBrushAllowed(canUse);
+		break;
 	default: break;
 	}
 	updateLockWidget();
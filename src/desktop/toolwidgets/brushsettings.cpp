@@ -178,6 +178,8 @@ struct BrushSettings::Private {
 	bool presetsAttach = true;
 	bool updateInProgress = false;
 	bool myPaintAllowed = true;
+	bool classicBrushAllowed = true;
+	bool pixelBrushAllowed = true;
 	bool compatibilityMode = false;
 
 	Slot &slotAt(int i)
@@ -204,6 +206,12 @@ struct BrushSettings::Private {
 		return currentBrush().activeType() == brushes::ActiveBrush::MYPAINT;
 	}
 
+	bool currentIsPixel()
+	{
+		return !currentIsMyPaint() && currentBrush().classic().shape !=
+										  DP_BRUSH_SHAPE_CLASSIC_SOFT_ROUND;
+	}
+
 	brushes::StabilizationMode stabilizationMode()
 	{
 		if(smoothingAction->isChecked()) {
@@ -610,8 +618,16 @@ BrushSettings::Lock BrushSettings::getLock()
 				return Lock::IndirectCompat;
 			}
 		}
-	} else if(!d->myPaintAllowed && d->currentIsMyPaint()) {
-		return Lock::MyPaintPermission;
+	} else if(d->currentIsMyPaint()) {
+		if(!d->myPaintAllowed) {
+			return Lock::MyPaintPermission;
+		}
+	} else if(d->currentIsPixel()) {
+		if(!d->pixelBrushAllowed) {
+			return Lock::PixelBrushPermission;
+		}
+	} else if(!d->classicBrushAllowed) {
+		return Lock::ClassicBrushPermission;
 	}
 	return Lock::None;
 }
@@ -621,6 +637,10 @@ QString BrushSettings::getLockDescription(Lock lock)
 	switch(lock) {
 	case Lock::MyPaintPermission:
 		return tr("You don't have permission to use MyPaint brushes.");
+	case Lock::ClassicBrushPermission:
+		return tr("You don't have permission to use classic brushes.");
+	case Lock::PixelBrushPermission:
+		return tr("You don't have permission to use pixel brushes.");
 	case Lock::MyPaintCompat:
 		return tr("This session is hosted with Drawpile 2.1, MyPaint brushes "
 				  "are unavailable.");
@@ -641,6 +661,18 @@ void BrushSettings::setMyPaintAllowed(bool myPaintAllowed)
 	updateUi();
 }
 
+void BrushSettings::setClassicBrushAllowed(bool classicBrushAllowed)
+{
+	d->classicBrushAllowed = classicBrushAllowed;
+	updateUi();
+}
+
+void BrushSettings::setPixelBrushAllowed(bool pixelBrushAllowed)
+{
+	d->pixelBrushAllowed = pixelBrushAllowed;
+	updateUi();
+}
+
 void BrushSettings::setCompatibilityMode(bool compatibilityMode)
 {
 	d->compatibilityMode = compatibilityMode;
@@ -82,6 +82,8 @@ public:
 
 	bool isLocked() override;
 	void setMyPaintAllowed(bool myPaintAllowed);
+	void setClassicBrushAllowed(bool classicBrushAllowed);
+	void setPixelBrushAllowed(bool pixelBrushAllowed);
 	void setCompatibilityMode(bool compatibilityMode);
 
 public slots:
@@ -134,7 +136,14 @@ private slots:
 
 private:
 	enum class BrushType { PixelRound, PixelSquare, SoftRound, MyPaint };
-	enum class Lock { None, MyPaintPermission, MyPaintCompat, IndirectCompat };
+	enum class Lock {
+		None,
+		MyPaintPermission,
+		ClassicBrushPermission,
+		PixelBrushPermission,
+		MyPaintCompat,
+		IndirectCompat,
+	};
 
 	void changePresetBrush(const brushes::ActiveBrush &brush);
 	void updateChangesInCurrentBrushPreset();
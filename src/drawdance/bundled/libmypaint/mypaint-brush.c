@@ -6,8 +6,9 @@
  * avoid the dependency on the json-c library. The patches are marked with
  * DRAWPILE_UNWANTED_MYPAINT_FEATURES.
  *
- * There's also a fix for an off by one error when clearing smudge buckets.
- * That has been marked with "Drawpile Patch".
+ * There's also a fix for an off by one error when clearing smudge buckets and
+ * a function to reseed the random number generator. Those have been marked
+ * with "Drawpile Patch".
  *
  * Permission to use, copy, modify, and/or distribute this software for any
  * purpose with or without fee is hereby granted, provided that the above
@@ -315,6 +316,15 @@ mypaint_brush_reset(MyPaintBrush *self)
     self->reset_requested = TRUE;
 }
 
+// Drawpile Patch: allow reseeding the random number generator, so that strokes
+// can be made reproducible.
+void
+mypaint_brush_set_random_seed(MyPaintBrush *self, long seed)
+{
+    rng_double_set_seed(self->rng, seed);
+    self->random_input = 0;
+}
+
 /**
   * mypaint_brush_new_stroke:
   *
@@ -94,6 +94,16 @@ mypaint_brush_ref(MyPaintBrush *self);
 void
 mypaint_brush_reset(MyPaintBrush *self);
 
+/*!
+ * Reseed the random number generator used for random inputs and offsets.
+ *
+ * Drawpile Patch: used to make strokes reproducible.
+ *
+ * @memberof MyPaintBrush
+ */
+void
+mypaint_brush_set_random_seed(MyPaintBrush *self, long seed);
+
 /*!
  * Start a new stroke.
  *
@@ -0,0 +1,180 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include <dpcommon/common.h>
+#include <dpcommon/conversions.h>
+#include <dpcommon/cpu.h>
+#include <dpcommon/perf.h>
+#include <dpcommon/worker.h>
+#include <dpengine/canvas_state.h>
+#include <dpengine/draw_context.h>
+#include <dpengine/image.h>
+#include <dpengine/pixels.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <stdio.h>
+
+
+#define LAYER_COUNT 8
+#define RECT_COUNT  64
+
+
+struct Args {
+    int width;
+    int height;
+    int thread_count;
+    int iterations;
+};
+
+static bool parse_int_arg(const char *s, int min_inclusive, int max_inclusive,
+                          int *out_value)
+{
+    char *end;
+    long long value = strtoll(s, &end, 10);
+    if (*end != '\0') {
+        DP_warn("Can't parse '%s'", s);
+        return false;
+    }
+    else if (value < min_inclusive || value > max_inclusive) {
+        DP_warn("%lld out of bounds, min %d, max %d", value, min_inclusive,
+                max_inclusive);
+        return false;
+    }
+    else {
+        *out_value = DP_llong_to_int(value);
+        return true;
+    }
+}
+
+static bool parse_arguments(int argc, char **argv, struct Args *out_args)
+{
+    if (argc != 5) {
+        fprintf(stderr, "Usage: %s WIDTH HEIGHT THREAD_COUNT ITERATIONS\n",
+                argv[0]);
+        fprintf(stderr, "A THREAD_COUNT of 0 uses one thread per CPU.\n");
+        return false;
+    }
+    return parse_int_arg(argv[1], 1, 32767, &out_args->width)
+        && parse_int_arg(argv[2], 1, 32767, &out_args->height)
+        && parse_int_arg(argv[3], 0, 1024, &out_args->thread_count)
+        && parse_int_arg(argv[4], 1, 1000000, &out_args->iterations);
+}
+
+
+static DP_CanvasState *handle(DP_CanvasState *cs, DP_DrawContext *dc,
+                              DP_Message *msg)
+{
+    DP_CanvasState *next = DP_canvas_state_handle(cs, dc, NULL, msg);
+    DP_message_decref(msg);
+    if (!next) {
+        DP_panic("Error handling message: %s", DP_error());
+    }
+    DP_canvas_state_decref(cs);
+    return next;
+}
+
+static void set_background(size_t size, unsigned char *out, void *user)
+{
+    memcpy(out, user, size);
+}
+
+static DP_CanvasState *init_canvas_state(DP_DrawContext *dc,
+                                         const struct Args *args)
+{
+    DP_CanvasState *cs = DP_canvas_state_new();
+    cs = handle(cs, dc,
+                DP_msg_canvas_resize_new(1, 0, DP_int_to_int32(args->width),
+                                         DP_int_to_int32(args->height), 0));
+    unsigned char background[] = {0xff, 0xff, 0xff, 0xff};
+    cs = handle(cs, dc,
+                DP_msg_canvas_background_new(1, set_background,
+                                             sizeof(background), background));
+
+    for (int i = 0; i < LAYER_COUNT; ++i) {
+        cs = handle(cs, dc,
+                    DP_msg_layer_tree_create_new(
+                        1, DP_int_to_uint16(0x100 + i), 0, 0, 0, 0, "", 0));
+    }
+
+    int blend_modes[] = {DP_BLEND_MODE_NORMAL, DP_BLEND_MODE_MULTIPLY,
+                         DP_BLEND_MODE_SCREEN, DP_BLEND_MODE_ERASE};
+    srand(0);
+    for (int i = 0; i < RECT_COUNT; ++i) {
+        int x = rand() % args->width;
+        int y = rand() % args->height;
+        int w = rand() % (args->width - x) + 1;
+        int h = rand() % (args->height - y) + 1;
+        uint32_t color = 0x80000000u | (DP_int_to_uint32(rand()) & 0xffffffu);
+        cs = handle(cs, dc,
+                    DP_msg_fill_rect_new(
+                        1, DP_int_to_uint16(0x100 + i % LAYER_COUNT),
+                        DP_int_to_uint8(blend_modes[rand() % 4]),
+                        DP_int_to_uint32(x), DP_int_to_uint32(y),
+                        DP_int_to_uint32(w), DP_int_to_uint32(h), color));
+    }
+
+    return cs;
+}
+
+
+static unsigned long long bench_flatten(DP_CanvasState *cs, int thread_count,
+                                        int iterations, DP_Image **out_img)
+{
+    unsigned long long total = 0;
+    for (int i = 0; i < iterations; ++i) {
+        unsigned long long start = DP_perf_time();
+        DP_Image *img =
+            thread_count == 1
+                ? DP_canvas_state_to_flat_image(cs, DP_FLAT_IMAGE_RENDER_FLAGS,
+                                                NULL, NULL)
+                : DP_canvas_state_to_flat_image_parallel(
+                    cs, DP_FLAT_IMAGE_RENDER_FLAGS, NULL, NULL, thread_count);
+        total += DP_perf_time() - start;
+        DP_image_free(*out_img);
+        *out_img = img;
+    }
+    return total;
+}
+
+static void run(const struct Args *args)
+{
+    DP_cpu_support_init();
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = init_canvas_state(dc, args);
+    int thread_count = args->thread_count == 0 ? DP_worker_cpu_count(1024)
+                                               : args->thread_count;
+
+    DP_Image *serial = NULL;
+    DP_Image *parallel = NULL;
+    unsigned long long serial_time =
+        bench_flatten(cs, 1, args->iterations, &serial);
+    unsigned long long parallel_time =
+        bench_flatten(cs, thread_count, args->iterations, &parallel);
+
+    size_t size = sizeof(DP_Pixel8) * DP_int_to_size(args->width)
+                * DP_int_to_size(args->height);
+    if (memcmp(DP_image_pixels(serial), DP_image_pixels(parallel), size)
+        != 0) {
+        DP_warn("Serial and parallel images differ");
+    }
+
+    printf("threads\t%d\n", thread_count);
+    printf("serial\t%llu\n", serial_time / DP_int_to_ullong(args->iterations));
+    printf("parallel\t%llu\n",
+           parallel_time / DP_int_to_ullong(args->iterations));
+
+    DP_image_free(parallel);
+    DP_image_free(serial);
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+int main(int argc, char **argv)
+{
+    struct Args args;
+    if (parse_arguments(argc, argv, &args)) {
+        run(&args);
+        return 0;
+    }
+    else {
+        return 2;
+    }
+}
@@ -272,6 +272,7 @@ DP_AffectedArea DP_affected_area_make(DP_Message *msg,
         return make_layer_attrs(
             DP_msg_layer_retitle_id(DP_msg_layer_retitle_cast(msg)));
     case DP_MSG_LAYER_ORDER:
+    case DP_MSG_LAYER_ORDER_MOVE:
     case DP_MSG_LAYER_TREE_MOVE:
         // Moving a layer is dependent on the state of the source, parent and
         // sibling layer, which is beyond what we can represent.
@@ -397,6 +398,9 @@ DP_AffectedArea DP_affected_area_make(DP_Message *msg,
     case DP_MSG_ANNOTATION_EDIT:
         return make_annotations(
             DP_msg_annotation_edit_id(DP_msg_annotation_edit_cast(msg)));
+    case DP_MSG_ANNOTATION_TEXT_EDIT:
+        return make_annotations(DP_msg_annotation_text_edit_id(
+            DP_msg_annotation_text_edit_cast(msg)));
     case DP_MSG_ANNOTATION_DELETE:
         return make_annotations(
             DP_msg_annotation_delete_id(DP_msg_annotation_delete_cast(msg)));
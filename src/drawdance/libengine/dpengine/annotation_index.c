@@ -0,0 +1,313 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "annotation_index.h"
+#include "annotation.h"
+#include "annotation_list.h"
+#include <dpcommon/common.h>
+#include <dpcommon/conversions.h>
+#include <uthash_inc.h>
+
+#define CELL_SIZE 256
+// Annotations that would cover more cells than this go into the large list.
+#define MAX_CELLS_PER_ANNOTATION 64
+
+
+typedef struct DP_AnnotationIndexEntry {
+    int annotation_id;
+    int x, y, width, height;
+    // Insertion order, later ones are on top.
+    unsigned int order;
+    unsigned int generation;
+    bool large;
+    UT_hash_handle hh;
+} DP_AnnotationIndexEntry;
+
+typedef struct DP_AnnotationIndexList {
+    int count, capacity;
+    DP_AnnotationIndexEntry **entries;
+} DP_AnnotationIndexList;
+
+typedef struct DP_AnnotationIndexCell {
+    long long key;
+    DP_AnnotationIndexList list;
+    UT_hash_handle hh;
+} DP_AnnotationIndexCell;
+
+typedef struct DP_AnnotationIndexBounds {
+    int left, top, right, bottom;
+} DP_AnnotationIndexBounds;
+
+struct DP_AnnotationIndex {
+    DP_AnnotationIndexEntry *entries;
+    DP_AnnotationIndexCell *cells;
+    DP_AnnotationIndexList large;
+    unsigned int next_order;
+    unsigned int generation;
+};
+
+
+DP_AnnotationIndex *DP_annotation_index_new(void)
+{
+    DP_AnnotationIndex *ai = DP_malloc(sizeof(*ai));
+    *ai = (DP_AnnotationIndex){NULL, NULL, {0, 0, NULL}, 0, 0};
+    return ai;
+}
+
+void DP_annotation_index_free(DP_AnnotationIndex *ai)
+{
+    if (ai) {
+        DP_AnnotationIndexCell *cell, *tmp_cell;
+        HASH_ITER(hh, ai->cells, cell, tmp_cell) {
+            HASH_DEL(ai->cells, cell);
+            DP_free(cell->list.entries);
+            DP_free(cell);
+        }
+        DP_AnnotationIndexEntry *entry, *tmp_entry;
+        HASH_ITER(hh, ai->entries, entry, tmp_entry) {
+            HASH_DEL(ai->entries, entry);
+            DP_free(entry);
+        }
+        DP_free(ai->large.entries);
+        DP_free(ai);
+    }
+}
+
+int DP_annotation_index_count(DP_AnnotationIndex *ai)
+{
+    DP_ASSERT(ai);
+    return DP_uint_to_int(HASH_COUNT(ai->entries));
+}
+
+
+static void list_push(DP_AnnotationIndexList *list,
+                      DP_AnnotationIndexEntry *entry)
+{
+    if (list->count == list->capacity) {
+        int capacity = DP_max_int(4, list->capacity * 2);
+        list->entries = DP_realloc(
+            list->entries, sizeof(*list->entries) * DP_int_to_size(capacity));
+        list->capacity = capacity;
+    }
+    list->entries[list->count++] = entry;
+}
+
+static void list_remove(DP_AnnotationIndexList *list,
+                        DP_AnnotationIndexEntry *entry)
+{
+    for (int i = 0; i < list->count; ++i) {
+        if (list->entries[i] == entry) {
+            list->entries[i] = list->entries[--list->count];
+            return;
+        }
+    }
+    DP_UNREACHABLE();
+}
+
+// Division that rounds towards negative infinity, so that negative coordinates
+// end up in the correct cell.
+static int cell_of(long long c)
+{
+    return (int)(c >= 0 ? c / CELL_SIZE : -((-c - 1) / CELL_SIZE) - 1);
+}
+
+static long long cell_key(int col, int row)
+{
+    return (long long)(((unsigned long long)(unsigned int)col << 32)
+                       | (unsigned long long)(unsigned int)row);
+}
+
+static DP_AnnotationIndexCell *search_cell(DP_AnnotationIndex *ai, int col,
+                                           int row)
+{
+    long long key = cell_key(col, row);
+    DP_AnnotationIndexCell *cell;
+    HASH_FIND(hh, ai->cells, &key, sizeof(key), cell);
+    return cell;
+}
+
+static bool entry_empty(DP_AnnotationIndexEntry *entry)
+{
+    return entry->width <= 0 || entry->height <= 0;
+}
+
+static DP_AnnotationIndexBounds entry_bounds(DP_AnnotationIndexEntry *entry)
+{
+    return (DP_AnnotationIndexBounds){
+        cell_of(entry->x), cell_of(entry->y),
+        cell_of((long long)entry->x + (long long)entry->width - 1LL),
+        cell_of((long long)entry->y + (long long)entry->height - 1LL)};
+}
+
+static bool bounds_too_large(DP_AnnotationIndexBounds b)
+{
+    long long cols = (long long)b.right - (long long)b.left + 1LL;
+    long long rows = (long long)b.bottom - (long long)b.top + 1LL;
+    return cols * rows > MAX_CELLS_PER_ANNOTATION;
+}
+
+static void link_entry(DP_AnnotationIndex *ai, DP_AnnotationIndexEntry *entry)
+{
+    if (entry_empty(entry)) {
+        entry->large = false;
+        return;
+    }
+
+    DP_AnnotationIndexBounds b = entry_bounds(entry);
+    entry->large = bounds_too_large(b);
+    if (entry->large) {
+        list_push(&ai->large, entry);
+        return;
+    }
+
+    for (int row = b.top; row <= b.bottom; ++row) {
+        for (int col = b.left; col <= b.right; ++col) {
+            DP_AnnotationIndexCell *cell = search_cell(ai, col, row);
+            if (!cell) {
+                cell = DP_malloc(sizeof(*cell));
+                cell->key = cell_key(col, row);
+                cell->list = (DP_AnnotationIndexList){0, 0, NULL};
+                HASH_ADD(hh, ai->cells, key, sizeof(cell->key), cell);
+            }
+            list_push(&cell->list, entry);
+        }
+    }
+}
+
+static void unlink_entry(DP_AnnotationIndex *ai,
+                         DP_AnnotationIndexEntry *entry)
+{
+    if (entry_empty(entry)) {
+        return;
+    }
+    else if (entry->large) {
+        list_remove(&ai->large, entry);
+        return;
+    }
+
+    DP_AnnotationIndexBounds b = entry_bounds(entry);
+    for (int row = b.top; row <= b.bottom; ++row) {
+        for (int col = b.left; col <= b.right; ++col) {
+            DP_AnnotationIndexCell *cell = search_cell(ai, col, row);
+            DP_ASSERT(cell);
+            list_remove(&cell->list, entry);
+            if (cell->list.count == 0) {
+                HASH_DEL(ai->cells, cell);
+                DP_free(cell->list.entries);
+                DP_free(cell);
+            }
+        }
+    }
+}
+
+static DP_AnnotationIndexEntry *search_entry(DP_AnnotationIndex *ai,
+                                             int annotation_id)
+{
+    DP_AnnotationIndexEntry *entry;
+    HASH_FIND_INT(ai->entries, &annotation_id, entry);
+    return entry;
+}
+
+static DP_AnnotationIndexEntry *set_entry(DP_AnnotationIndex *ai,
+                                          int annotation_id, int x, int y,
+                                          int width, int height)
+{
+    DP_AnnotationIndexEntry *entry = search_entry(ai, annotation_id);
+    if (entry) {
+        if (entry->x == x && entry->y == y && entry->width == width
+            && entry->height == height) {
+            return entry; // Nothing changed.
+        }
+        unlink_entry(ai, entry);
+    }
+    else {
+        entry = DP_malloc(sizeof(*entry));
+        entry->annotation_id = annotation_id;
+        entry->order = ai->next_order++;
+        entry->generation = ai->generation;
+        HASH_ADD_INT(ai->entries, annotation_id, entry);
+    }
+    entry->x = x;
+    entry->y = y;
+    entry->width = width;
+    entry->height = height;
+    link_entry(ai, entry);
+    return entry;
+}
+
+static void remove_entry(DP_AnnotationIndex *ai,
+                         DP_AnnotationIndexEntry *entry)
+{
+    unlink_entry(ai, entry);
+    HASH_DEL(ai->entries, entry);
+    DP_free(entry);
+}
+
+void DP_annotation_index_set(DP_AnnotationIndex *ai, int annotation_id, int x,
+                             int y, int width, int height)
+{
+    DP_ASSERT(ai);
+    set_entry(ai, annotation_id, x, y, width, height);
+}
+
+void DP_annotation_index_remove(DP_AnnotationIndex *ai, int annotation_id)
+{
+    DP_ASSERT(ai);
+    DP_AnnotationIndexEntry *entry = search_entry(ai, annotation_id);
+    if (entry) {
+        remove_entry(ai, entry);
+    }
+}
+
+void DP_annotation_index_sync(DP_AnnotationIndex *ai, DP_AnnotationList *al)
+{
+    DP_ASSERT(ai);
+    DP_ASSERT(al);
+    unsigned int generation = ++ai->generation;
+    int count = DP_annotation_list_count(al);
+    for (int i = 0; i < count; ++i) {
+        DP_Annotation *a = DP_annotation_list_at_noinc(al, i);
+        DP_AnnotationIndexEntry *entry = set_entry(
+            ai, DP_annotation_id(a), DP_annotation_x(a), DP_annotation_y(a),
+            DP_annotation_width(a), DP_annotation_height(a));
+        entry->generation = generation;
+    }
+
+    DP_AnnotationIndexEntry *entry, *tmp;
+    HASH_ITER(hh, ai->entries, entry, tmp) {
+        if (entry->generation != generation) {
+            remove_entry(ai, entry);
+        }
+    }
+}
+
+
+static bool entry_contains(DP_AnnotationIndexEntry *entry, int x, int y)
+{
+    return x >= entry->x && y >= entry->y
+        && (long long)x < (long long)entry->x + (long long)entry->width
+        && (long long)y < (long long)entry->y + (long long)entry->height;
+}
+
+static DP_AnnotationIndexEntry *topmost_in(DP_AnnotationIndexList *list,
+                                           DP_AnnotationIndexEntry *best,
+                                           int x, int y)
+{
+    for (int i = 0; i < list->count; ++i) {
+        DP_AnnotationIndexEntry *entry = list->entries[i];
+        if ((!best || entry->order > best->order)
+            && entry_contains(entry, x, y)) {
+            best = entry;
+        }
+    }
+    return best;
+}
+
+int DP_annotation_index_at(DP_AnnotationIndex *ai, int x, int y)
+{
+    DP_ASSERT(ai);
+    DP_AnnotationIndexEntry *best = topmost_in(&ai->large, NULL, x, y);
+    DP_AnnotationIndexCell *cell = search_cell(ai, cell_of(x), cell_of(y));
+    if (cell) {
+        best = topmost_in(&cell->list, best, x, y);
+    }
+    return best ? best->annotation_id : -1;
+}
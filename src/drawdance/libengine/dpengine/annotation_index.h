@@ -0,0 +1,42 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#ifndef DPENGINE_ANNOTATION_INDEX_H
+#define DPENGINE_ANNOTATION_INDEX_H
+#include <dpcommon/common.h>
+
+typedef struct DP_AnnotationList DP_AnnotationList;
+
+
+// Spatial index of annotation rectangles for hit-testing, so that finding the
+// annotation under a point doesn't have to check every single one. It's a grid
+// of cells, each cell knows which annotations overlap it. Annotations too big
+// to reasonably put into cells are kept on the side and checked every time.
+//
+// The index is kept up to date incrementally, either by setting and removing
+// individual annotations as they're created, reshaped and deleted or by
+// syncing it with an annotation list, which only touches what changed.
+typedef struct DP_AnnotationIndex DP_AnnotationIndex;
+
+DP_AnnotationIndex *DP_annotation_index_new(void);
+
+void DP_annotation_index_free(DP_AnnotationIndex *ai);
+
+int DP_annotation_index_count(DP_AnnotationIndex *ai);
+
+// Inserts the annotation or updates its rectangle if it's already present.
+// Annotations with an empty rectangle are kept, but can't ever be hit.
+void DP_annotation_index_set(DP_AnnotationIndex *ai, int annotation_id, int x,
+                             int y, int width, int height);
+
+void DP_annotation_index_remove(DP_AnnotationIndex *ai, int annotation_id);
+
+// Brings the index in line with the given annotation list, inserting new
+// annotations, updating reshaped ones and removing those that are gone.
+void DP_annotation_index_sync(DP_AnnotationIndex *ai, DP_AnnotationList *al);
+
+// Returns the id of the annotation whose rectangle contains the given point or
+// -1 if there's none. If multiple annotations overlap there, the one that was
+// inserted last wins, which is the one on top when synced from a list.
+int DP_annotation_index_at(DP_AnnotationIndex *ai, int x, int y);
+
+
+#endif
@@ -1356,6 +1356,16 @@ static void stroke_to_classic(
 }
 
 
+// Random offsets and inputs are seeded from where the stroke starts, so the
+// same input always results in the same dabs instead of depending on whatever
+// strokes came before it.
+static long get_mypaint_random_seed(DP_BrushPoint bp)
+{
+    unsigned long x = (unsigned long)DP_float_to_int(bp.x * 4.0f);
+    unsigned long y = (unsigned long)DP_float_to_int(bp.y * 4.0f);
+    return (long)(((x * 73856093ul) ^ (y * 19349663ul)) & 0x3ffffffful);
+}
+
 static void stroke_to_mypaint(DP_BrushEngine *be, DP_BrushPoint bp)
 {
     MyPaintBrush *mb = be->mypaint_brush;
@@ -1371,6 +1381,7 @@ static void stroke_to_mypaint(DP_BrushEngine *be, DP_BrushPoint bp)
         be->stroke.in_progress = true;
         mypaint_brush_reset(mb);
         mypaint_brush_new_stroke(mb);
+        mypaint_brush_set_random_seed(mb, get_mypaint_random_seed(bp));
         // Generate a phantom stroke point with zero pressure and a really
         // high delta time to separate this stroke from the last.  This is
         // because libmypaint actually expects you to transmit strokes even
@@ -1459,7 +1470,7 @@ static void handle_stroke_stabilizer(DP_BrushEngine *be, DP_BrushPoint bp,
 }
 
 
-static DP_BrushPoint smoother_get(DP_BrushPoint *points, int size)
+static DP_BrushPoint smoother_get(const DP_BrushPoint *points, int size)
 {
     DP_ASSERT(size > 0);
     // A simple unweighted sliding-average smoother
@@ -1774,3 +1785,28 @@ void DP_brush_engine_offset_add(DP_BrushEngine *be, float x, float y)
         DP_UNREACHABLE();
     }
 }
+
+
+void DP_brush_points_smooth(int count, const DP_BrushPoint *points,
+                            float strength, DP_BrushPoint *out_points)
+{
+    DP_ASSERT(count >= 0);
+    DP_ASSERT(count == 0 || points);
+    DP_ASSERT(count == 0 || out_points);
+    DP_ASSERT(count == 0 || points + count <= out_points
+              || out_points + count <= points);
+    int max_radius = DP_float_to_int(
+        CLAMP(strength, 0.0f, 1.0f) * DP_BRUSH_POINTS_SMOOTH_MAX_RADIUS + 0.5f);
+    for (int i = 0; i < count; ++i) {
+        int radius = DP_min_int(max_radius, DP_min_int(i, count - i - 1));
+        if (radius == 0) {
+            out_points[i] = points[i];
+        }
+        else {
+            DP_BrushPoint bp =
+                smoother_get(points + i - radius, radius * 2 + 1);
+            bp.time_msec = points[i].time_msec;
+            out_points[i] = bp;
+        }
+    }
+}
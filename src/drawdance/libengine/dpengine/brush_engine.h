@@ -95,4 +95,17 @@ void DP_brush_engine_stroke_end(DP_BrushEngine *be, long long time_msec,
 void DP_brush_engine_offset_add(DP_BrushEngine *be, float x, float y);
 
 
+#define DP_BRUSH_POINTS_SMOOTH_MAX_RADIUS 8
+
+// Smooths out the jitter of a whole stroke worth of input points by averaging
+// each point with its neighbors, meant to preprocess points before passing
+// them to DP_brush_engine_stroke_to. Strength goes from 0 for no smoothing to
+// 1 for averaging over DP_BRUSH_POINTS_SMOOTH_MAX_RADIUS points on either side.
+// The window shrinks towards the ends of the stroke, so the first and last
+// point stay where they are. Timestamps are kept as they are. The output must
+// have room for count points and must not overlap with the input.
+void DP_brush_points_smooth(int count, const DP_BrushPoint *points,
+                            float strength, DP_BrushPoint *out_points);
+
+
 #endif
@@ -67,6 +67,7 @@ struct DP_CanvasHistory {
     DP_CanvasHistoryEntry *entries;
     DP_AffectedIndirectAreas aia;
     bool mark_command_done;
+    bool compact_undo_points;
     struct {
         bool starts_at_undo_point;
         int start;
@@ -508,6 +509,7 @@ DP_CanvasHistory *DP_canvas_history_new_inc(
         DP_malloc(entries_size),
         {0},
         true,
+        false,
         {false, 0, 0, DP_QUEUE_NULL},
         {save_point_fn, save_point_user},
         {0, {0}},
@@ -598,6 +600,19 @@ void DP_canvas_history_want_dump_set(DP_CanvasHistory *ch, bool want_dump)
     ch->dump.want = want_dump;
 }
 
+bool DP_canvas_history_compact_undo_points(DP_CanvasHistory *ch)
+{
+    DP_ASSERT(ch);
+    return ch->compact_undo_points;
+}
+
+void DP_canvas_history_compact_undo_points_set(DP_CanvasHistory *ch,
+                                               bool compact_undo_points)
+{
+    DP_ASSERT(ch);
+    ch->compact_undo_points = compact_undo_points;
+}
+
 DP_CanvasState *DP_canvas_history_get(DP_CanvasHistory *ch)
 {
     DP_ASSERT(ch);
@@ -909,6 +924,32 @@ DP_CanvasState *DP_canvas_history_stream_start_state_inc(DP_CanvasHistory *ch)
     DP_UNREACHABLE();
 }
 
+DP_CanvasMemoryUsage DP_canvas_history_memory_usage(DP_CanvasHistory *ch)
+{
+    DP_ASSERT(ch);
+    int used = ch->used;
+    DP_CanvasState **states =
+        DP_malloc(sizeof(*states) * (DP_int_to_size(used) + 1));
+    int state_count = 0;
+    states[state_count++] = ch->current_state;
+
+    size_t message_bytes = 0;
+    for (int i = 0; i < used; ++i) {
+        DP_CanvasHistoryEntry *entry = &ch->entries[i];
+        message_bytes += DP_message_length(entry->msg);
+        if (entry->state) {
+            states[state_count++] = entry->state;
+        }
+    }
+
+    DP_CanvasMemoryUsage usage =
+        DP_canvas_state_memory_usage(state_count, states);
+    usage.history_count = used;
+    usage.history_message_bytes = message_bytes;
+    DP_free(states);
+    return usage;
+}
+
 int DP_canvas_history_undo_depth_limit(DP_CanvasHistory *ch)
 {
     DP_ASSERT(ch);
@@ -1328,9 +1369,17 @@ static bool handle_command(DP_CanvasHistory *ch, DP_DrawContext *dc,
     DP_ASSERT(DP_message_type(msg) == type);
     switch (type) {
     case DP_MSG_UNDO_POINT:
-        HISTORY_DEBUG("Undo point for user %u at %d",
-                      DP_message_context_id(msg), index);
-        handle_undo_point(ch, index);
+        if (index < 0) {
+            // Redundant undo point that was collapsed into the previous one,
+            // there's nothing in history for it to act on.
+            HISTORY_DEBUG("Collapsed undo point for user %u",
+                          DP_message_context_id(msg));
+        }
+        else {
+            HISTORY_DEBUG("Undo point for user %u at %d",
+                          DP_message_context_id(msg), index);
+            handle_undo_point(ch, index);
+        }
         return true;
     case DP_MSG_UNDO:
         return handle_undo(ch, dc, msg);
@@ -1492,6 +1541,28 @@ static bool handle_remote_command(DP_CanvasHistory *ch, DP_DrawContext *dc,
     }
 }
 
+// An undo point is redundant if the user's last entry in the history is
+// already an undo point that's in effect, since they didn't do anything in
+// between. Appending it would just make for an empty undo step.
+static bool is_redundant_undo_point(DP_CanvasHistory *ch, DP_Message *msg)
+{
+    unsigned int context_id = DP_message_context_id(msg);
+    DP_CanvasHistoryEntry *entries = ch->entries;
+    int depth = 0;
+    int undo_depth_limit = ch->undo_depth_limit;
+    for (int i = ch->used - 1; i >= 0 && depth < undo_depth_limit; --i) {
+        DP_CanvasHistoryEntry *entry = &entries[i];
+        bool undo_point = is_undo_point_entry(entry);
+        if (DP_message_context_id(entry->msg) == context_id) {
+            return undo_point && entry->undo == DP_UNDO_DONE;
+        }
+        else if (undo_point) {
+            ++depth;
+        }
+    }
+    return false;
+}
+
 static bool handle_remote_message(DP_CanvasHistory *ch, DP_DrawContext *dc,
                                   DP_Message *msg, DP_MessageType type,
                                   bool local_drawing_in_progress)
@@ -1511,6 +1582,13 @@ static bool handle_remote_message(DP_CanvasHistory *ch, DP_DrawContext *dc,
             return handle_remote_command(ch, dc, msg, type,
                                          local_drawing_in_progress, false);
         }
+    case DP_MSG_UNDO_POINT:
+        if (ch->mark_command_done && ch->compact_undo_points
+            && is_redundant_undo_point(ch, msg)) {
+            return handle_remote_command(ch, dc, msg, type,
+                                         local_drawing_in_progress, false);
+        }
+        DP_FALLTHROUGH();
     default:
         if (ch->mark_command_done) {
             return handle_remote_command(ch, dc, msg, type,
@@ -98,6 +98,15 @@ bool DP_canvas_history_want_dump(DP_CanvasHistory *ch);
 
 void DP_canvas_history_want_dump_set(DP_CanvasHistory *ch, bool want_dump);
 
+// Whether an undo point right after another one by the same user gets dropped
+// instead of making for an empty undo step. Off by default, since it changes
+// what a later undo targets, so it must only be turned on if everyone in the
+// session or recording does it, which is the case from protocol dp:4.25 on.
+bool DP_canvas_history_compact_undo_points(DP_CanvasHistory *ch);
+
+void DP_canvas_history_compact_undo_points_set(DP_CanvasHistory *ch,
+                                               bool compact_undo_points);
+
 DP_CanvasState *DP_canvas_history_get(DP_CanvasHistory *ch);
 
 DP_CanvasState *
@@ -118,6 +127,12 @@ DP_CanvasState *DP_canvas_history_stream_start_state_inc(DP_CanvasHistory *ch);
 
 int DP_canvas_history_undo_depth_limit(DP_CanvasHistory *ch);
 
+// Memory usage of the current state and every state kept around in the
+// history for undo, with shared tiles only counted once, plus the number of
+// history entries and the size of their messages. Must be called from the
+// thread that handles messages, since it looks at the history directly.
+DP_CanvasMemoryUsage DP_canvas_history_memory_usage(DP_CanvasHistory *ch);
+
 void DP_canvas_history_undo_depth_limit_set(DP_CanvasHistory *ch,
                                             DP_DrawContext *dc,
                                             int undo_depth_limit);
@@ -38,6 +38,7 @@
 #include "ops.h"
 #include "paint.h"
 #include "selection_set.h"
+#include "text.h"
 #include "tile.h"
 #include "tile_iterator.h"
 #include "timeline.h"
@@ -48,9 +49,12 @@
 #include <dpcommon/conversions.h>
 #include <dpcommon/geom.h>
 #include <dpcommon/perf.h>
+#include <dpcommon/worker.h>
+#include <dpmsg/acl.h>
 #include <dpmsg/blend_mode.h>
 #include <dpmsg/message.h>
 #include <limits.h>
+#include <uthash_inc.h>
 
 #define DP_PERF_CONTEXT "canvas_state"
 
@@ -198,6 +202,43 @@ DP_CanvasState *DP_canvas_state_new_with_selections_noinc(DP_CanvasState *cs,
     return (DP_CanvasState *)tcs;
 }
 
+DP_CanvasState *DP_canvas_state_new_from_image(DP_DrawContext *dc,
+                                               DP_Image *img,
+                                               unsigned int context_id,
+                                               int layer_id, const char *title,
+                                               size_t title_length)
+{
+    DP_ASSERT(dc);
+    DP_ASSERT(img);
+    DP_ASSERT(title || title_length == 0);
+    DP_TransientCanvasState *tcs = DP_transient_canvas_state_new_init();
+
+    int width = DP_image_width(img);
+    int height = DP_image_height(img);
+    DP_transient_canvas_state_width_set(tcs, width);
+    DP_transient_canvas_state_height_set(tcs, height);
+
+    DP_TransientLayerContent *tlc =
+        DP_transient_layer_content_new_init(width, height, NULL);
+    DP_transient_layer_content_put_image(tlc, context_id, DP_BLEND_MODE_REPLACE,
+                                         0, 0, img);
+
+    DP_TransientLayerList *tll =
+        DP_transient_canvas_state_transient_layers(tcs, 1);
+    DP_transient_layer_list_insert_transient_content_noinc(tll, tlc, 0);
+
+    DP_TransientLayerProps *tlp =
+        DP_transient_layer_props_new_init(layer_id, false);
+    DP_transient_layer_props_title_set(tlp, title, title_length);
+
+    DP_TransientLayerPropsList *tlpl =
+        DP_transient_canvas_state_transient_layer_props(tcs, 1);
+    DP_transient_layer_props_list_insert_transient_noinc(tlpl, tlp, 0);
+
+    DP_transient_canvas_state_layer_routes_reindex(tcs, dc);
+    return DP_transient_canvas_state_persist(tcs);
+}
+
 DP_CanvasState *DP_canvas_state_incref(DP_CanvasState *cs)
 {
     DP_ASSERT(cs);
@@ -298,6 +339,13 @@ DP_LayerList *DP_canvas_state_layers_noinc(DP_CanvasState *cs)
     return cs->layers;
 }
 
+bool DP_canvas_state_content_bounds(DP_CanvasState *cs, DP_Rect *out_bounds)
+{
+    DP_ASSERT(cs);
+    DP_ASSERT(DP_atomic_get(&cs->refcount) > 0);
+    return DP_layer_list_bounds(cs->layers, out_bounds);
+}
+
 DP_LayerPropsList *DP_canvas_state_layer_props_noinc(DP_CanvasState *cs)
 {
     DP_ASSERT(cs);
@@ -312,6 +360,275 @@ DP_LayerRoutes *DP_canvas_state_layer_routes_noinc(DP_CanvasState *cs)
     return cs->layer_routes;
 }
 
+void DP_canvas_state_layer_stack_snapshot(DP_CanvasState *cs,
+                                          DP_LayerStackSnapshot *out_snapshot)
+{
+    DP_ASSERT(cs);
+    DP_ASSERT(DP_atomic_get(&cs->refcount) > 0);
+    DP_ASSERT(out_snapshot);
+    *out_snapshot = (DP_LayerStackSnapshot){
+        cs->width,
+        cs->height,
+        DP_layer_list_incref(cs->layers),
+        DP_layer_props_list_incref(cs->layer_props),
+    };
+}
+
+DP_CanvasState *
+DP_canvas_state_layer_stack_restore(DP_CanvasState *cs, DP_DrawContext *dc,
+                                    const DP_LayerStackSnapshot *snapshot)
+{
+    DP_ASSERT(cs);
+    DP_ASSERT(DP_atomic_get(&cs->refcount) > 0);
+    DP_ASSERT(dc);
+    DP_ASSERT(snapshot);
+    DP_ASSERT(snapshot->ll);
+    DP_ASSERT(snapshot->lpl);
+    DP_TransientCanvasState *tcs = DP_transient_canvas_state_new(cs);
+    if (tcs->width != snapshot->width || tcs->height != snapshot->height) {
+        tcs->width = snapshot->width;
+        tcs->height = snapshot->height;
+        DP_transient_canvas_state_transient_selections_clear(tcs);
+    }
+    DP_transient_canvas_state_layers_set_inc(tcs, snapshot->ll);
+    DP_transient_canvas_state_layer_props_set_inc(tcs, snapshot->lpl);
+    DP_transient_canvas_state_layer_routes_reindex(tcs, dc);
+    DP_transient_canvas_state_timeline_cleanup(tcs);
+    return DP_transient_canvas_state_persist(tcs);
+}
+
+void DP_layer_stack_snapshot_dispose(DP_LayerStackSnapshot *snapshot)
+{
+    DP_ASSERT(snapshot);
+    DP_layer_props_list_decref(snapshot->lpl);
+    DP_layer_list_decref(snapshot->ll);
+    snapshot->ll = NULL;
+    snapshot->lpl = NULL;
+}
+
+
+struct DP_ChangedTilesContext {
+    DP_LayerList *other_ll;
+    DP_LayerPropsList *other_lpl;
+    void (*fn)(void *, int, int, int);
+    void *user;
+    int count;
+};
+
+static DP_LayerContent *search_content(DP_LayerList *ll, DP_LayerPropsList *lpl,
+                                       int layer_id)
+{
+    int count = DP_layer_list_count(ll);
+    for (int i = 0; i < count; ++i) {
+        DP_LayerListEntry *lle = DP_layer_list_at_noinc(ll, i);
+        DP_LayerProps *lp = DP_layer_props_list_at_noinc(lpl, i);
+        if (DP_layer_list_entry_is_group(lle)) {
+            DP_LayerContent *lc = search_content(
+                DP_layer_group_children_noinc(
+                    DP_layer_list_entry_group_noinc(lle)),
+                DP_layer_props_children_noinc(lp), layer_id);
+            if (lc) {
+                return lc;
+            }
+        }
+        else if (DP_layer_props_id(lp) == layer_id) {
+            return DP_layer_list_entry_content_noinc(lle);
+        }
+    }
+    return NULL;
+}
+
+static DP_Tile *tile_at_or_null(DP_LayerContent *lc_or_null,
+                                DP_TileCounts counts, int x, int y)
+{
+    return lc_or_null && x < counts.x && y < counts.y
+             ? DP_layer_content_tile_at_noinc(lc_or_null, x, y)
+             : NULL;
+}
+
+static DP_TileCounts tile_counts_of(DP_LayerContent *lc_or_null)
+{
+    return lc_or_null
+             ? DP_tile_counts_round(DP_layer_content_width(lc_or_null),
+                                    DP_layer_content_height(lc_or_null))
+             : (DP_TileCounts){0, 0};
+}
+
+static void report_changed_tiles(struct DP_ChangedTilesContext *c, int layer_id,
+                                 DP_LayerContent *lc_or_null,
+                                 DP_LayerContent *other_lc_or_null)
+{
+    if (lc_or_null == other_lc_or_null) {
+        return;
+    }
+    DP_TileCounts counts = tile_counts_of(lc_or_null);
+    DP_TileCounts other_counts = tile_counts_of(other_lc_or_null);
+    int count_x = DP_max_int(counts.x, other_counts.x);
+    int count_y = DP_max_int(counts.y, other_counts.y);
+    for (int y = 0; y < count_y; ++y) {
+        for (int x = 0; x < count_x; ++x) {
+            DP_Tile *t = tile_at_or_null(lc_or_null, counts, x, y);
+            DP_Tile *other_t =
+                tile_at_or_null(other_lc_or_null, other_counts, x, y);
+            if (t != other_t) {
+                c->fn(c->user, layer_id, x, y);
+                ++c->count;
+            }
+        }
+    }
+}
+
+// Walks the layers of one snapshot and compares them against the other. The
+// other side's list at the same level is passed along if there is one, since
+// layers are usually in the same place and can be found without searching.
+// When walking the second snapshot, only layers missing from the first are
+// reported, since the others were already compared.
+static void changed_tiles_in(struct DP_ChangedTilesContext *c,
+                             DP_LayerList *ll, DP_LayerPropsList *lpl,
+                             DP_LayerList *other_ll_or_null,
+                             DP_LayerPropsList *other_lpl_or_null,
+                             bool missing_only)
+{
+    int count = DP_layer_list_count(ll);
+    int other_count =
+        other_ll_or_null ? DP_layer_list_count(other_ll_or_null) : 0;
+    for (int i = 0; i < count; ++i) {
+        DP_LayerListEntry *lle = DP_layer_list_at_noinc(ll, i);
+        DP_LayerProps *lp = DP_layer_props_list_at_noinc(lpl, i);
+        int layer_id = DP_layer_props_id(lp);
+        bool is_group = DP_layer_list_entry_is_group(lle);
+
+        DP_LayerListEntry *other_lle = NULL;
+        DP_LayerProps *other_lp = NULL;
+        if (i < other_count) {
+            DP_LayerProps *candidate_lp =
+                DP_layer_props_list_at_noinc(other_lpl_or_null, i);
+            DP_LayerListEntry *candidate_lle =
+                DP_layer_list_at_noinc(other_ll_or_null, i);
+            if (DP_layer_props_id(candidate_lp) == layer_id
+                && DP_layer_list_entry_is_group(candidate_lle) == is_group) {
+                other_lle = candidate_lle;
+                other_lp = candidate_lp;
+            }
+        }
+
+        if (is_group) {
+            DP_LayerGroup *lg = DP_layer_list_entry_group_noinc(lle);
+            DP_LayerList *child_ll = DP_layer_group_children_noinc(lg);
+            DP_LayerPropsList *child_lpl = DP_layer_props_children_noinc(lp);
+            if (other_lle) {
+                DP_LayerGroup *other_lg =
+                    DP_layer_list_entry_group_noinc(other_lle);
+                DP_LayerPropsList *other_child_lpl =
+                    DP_layer_props_children_noinc(other_lp);
+                if (lg != other_lg || child_lpl != other_child_lpl) {
+                    changed_tiles_in(c, child_ll, child_lpl,
+                                     DP_layer_group_children_noinc(other_lg),
+                                     other_child_lpl, missing_only);
+                }
+            }
+            else {
+                changed_tiles_in(c, child_ll, child_lpl, NULL, NULL,
+                                 missing_only);
+            }
+        }
+        else {
+            DP_LayerContent *lc = DP_layer_list_entry_content_noinc(lle);
+            DP_LayerContent *other_lc =
+                other_lle ? DP_layer_list_entry_content_noinc(other_lle)
+                          : search_content(c->other_ll, c->other_lpl, layer_id);
+            if (!missing_only) {
+                report_changed_tiles(c, layer_id, lc, other_lc);
+            }
+            else if (!other_lc) {
+                report_changed_tiles(c, layer_id, lc, NULL);
+            }
+        }
+    }
+}
+
+int DP_layer_stack_snapshot_changed_tiles(
+    const DP_LayerStackSnapshot *snapshot, const DP_LayerStackSnapshot *other,
+    void (*fn)(void *user, int layer_id, int tile_x, int tile_y), void *user)
+{
+    DP_ASSERT(snapshot);
+    DP_ASSERT(other);
+    DP_ASSERT(fn);
+    if (snapshot->ll == other->ll && snapshot->lpl == other->lpl) {
+        return 0;
+    }
+
+    struct DP_ChangedTilesContext c = {other->ll, other->lpl, fn, user, 0};
+    changed_tiles_in(&c, snapshot->ll, snapshot->lpl, other->ll, other->lpl,
+                     false);
+    c.other_ll = snapshot->ll;
+    c.other_lpl = snapshot->lpl;
+    changed_tiles_in(&c, other->ll, other->lpl, snapshot->ll, snapshot->lpl,
+                     true);
+    return c.count;
+}
+
+
+bool DP_canvas_transform_swaps_dimensions(DP_CanvasTransform transform)
+{
+    return transform == DP_CANVAS_TRANSFORM_ROTATE_CLOCKWISE
+        || transform == DP_CANVAS_TRANSFORM_ROTATE_COUNTERCLOCKWISE;
+}
+
+void DP_canvas_transform_point(DP_CanvasTransform transform, int width,
+                               int height, int x, int y, int *out_x,
+                               int *out_y)
+{
+    DP_ASSERT(out_x);
+    DP_ASSERT(out_y);
+    switch (transform) {
+    case DP_CANVAS_TRANSFORM_ROTATE_CLOCKWISE:
+        *out_x = height - 1 - y;
+        *out_y = x;
+        return;
+    case DP_CANVAS_TRANSFORM_ROTATE_COUNTERCLOCKWISE:
+        *out_x = y;
+        *out_y = width - 1 - x;
+        return;
+    case DP_CANVAS_TRANSFORM_ROTATE_180:
+        *out_x = width - 1 - x;
+        *out_y = height - 1 - y;
+        return;
+    case DP_CANVAS_TRANSFORM_FLIP_HORIZONTAL:
+        *out_x = width - 1 - x;
+        *out_y = y;
+        return;
+    case DP_CANVAS_TRANSFORM_FLIP_VERTICAL:
+        *out_x = x;
+        *out_y = height - 1 - y;
+        return;
+    }
+    DP_UNREACHABLE();
+}
+
+DP_CanvasState *DP_canvas_state_transform_all(DP_CanvasState *cs,
+                                              unsigned int context_id,
+                                              DP_CanvasTransform transform)
+{
+    DP_ASSERT(cs);
+    DP_ASSERT(DP_atomic_get(&cs->refcount) > 0);
+    DP_TransientCanvasState *tcs = DP_transient_canvas_state_new(cs);
+    if (DP_canvas_transform_swaps_dimensions(transform)) {
+        tcs->width = cs->height;
+        tcs->height = cs->width;
+    }
+    DP_transient_canvas_state_transient_selections_clear(tcs);
+
+    DP_LayerList *ll = cs->layers;
+    if (DP_layer_list_count(ll) > 0) {
+        DP_TransientLayerList *tll =
+            DP_layer_list_transform(ll, context_id, (int)transform);
+        DP_transient_canvas_state_transient_layers_set_noinc(tcs, tll);
+    }
+
+    return DP_transient_canvas_state_persist(tcs);
+}
+
 DP_AnnotationList *DP_canvas_state_annotations_noinc(DP_CanvasState *cs)
 {
     DP_ASSERT(cs);
@@ -395,6 +712,10 @@ static DP_CanvasState *handle_layer_create(DP_CanvasState *cs,
         DP_error_set("Create layer: layer id 0 is invalid");
         return NULL;
     }
+    else if (layer_id == DP_ACL_DEFAULT_LAYER_ID) {
+        DP_error_set("Create layer: layer id %d is reserved", layer_id);
+        return NULL;
+    }
 
     unsigned int flags = DP_msg_layer_create_flags(mlc);
     bool copy = flags & DP_MSG_LAYER_CREATE_FLAGS_COPY;
@@ -405,11 +726,15 @@ static DP_CanvasState *handle_layer_create(DP_CanvasState *cs,
 
     size_t title_length;
     const char *title = DP_msg_layer_create_title(mlc, &title_length);
+    char sanitized[DP_LAYER_TITLE_MAX_LENGTH];
+    size_t sanitized_length = DP_text_sanitize(
+        title, title_length, sanitized, DP_LAYER_TITLE_MAX_LENGTH);
 
     int source_id = DP_msg_layer_create_source(mlc);
     DP_CanvasState *next = DP_ops_layer_tree_create(
         cs, dc, DP_msg_layer_create_id(mlc), copy ? source_id : 0,
-        insert ? source_id : 0, tile, false, false, title, title_length);
+        insert ? source_id : 0, tile, false, false, sanitized,
+        sanitized_length);
 
     DP_tile_decref_nullable(tile);
     return next;
@@ -450,6 +775,37 @@ static DP_CanvasState *handle_layer_order(DP_CanvasState *cs,
     return DP_ops_layer_order(cs, dc, count, get_order_id, (void *)layer_ids);
 }
 
+static DP_CanvasState *handle_layer_order_move(DP_CanvasState *cs,
+                                               DP_DrawContext *dc,
+                                               DP_MsgLayerOrderMove *mlom)
+{
+    int layer_id = DP_msg_layer_order_move_layer(mlom);
+    int direction = DP_msg_layer_order_move_direction(mlom);
+    int index;
+    if (direction == DP_MSG_LAYER_ORDER_MOVE_DIRECTION_INDEX) {
+        index = DP_msg_layer_order_move_index(mlom);
+    }
+    else {
+        DP_LayerRoutesEntry *lre =
+            DP_layer_routes_search(cs->layer_routes, layer_id);
+        if (!lre) {
+            DP_error_set("Layer order move: id %d not found", layer_id);
+            return NULL;
+        }
+        else if (direction == DP_MSG_LAYER_ORDER_MOVE_DIRECTION_UP) {
+            index = DP_layer_routes_entry_index_last(lre) + 1;
+        }
+        else if (direction == DP_MSG_LAYER_ORDER_MOVE_DIRECTION_DOWN) {
+            index = DP_layer_routes_entry_index_last(lre) - 1;
+        }
+        else {
+            DP_error_set("Layer order move: unknown direction %d", direction);
+            return NULL;
+        }
+    }
+    return DP_ops_layer_move_to_index(cs, dc, layer_id, index);
+}
+
 
 static DP_CanvasState *handle_layer_retitle(DP_CanvasState *cs,
                                             DP_MsgLayerRetitle *mlr)
@@ -463,7 +819,11 @@ static DP_CanvasState *handle_layer_retitle(DP_CanvasState *cs,
     size_t title_length;
     const char *title = DP_msg_layer_retitle_title(mlr, &title_length);
 
-    return DP_ops_layer_retitle(cs, layer_id, title, title_length);
+    // Protect the UI and storage from unreasonable titles.
+    char sanitized[DP_LAYER_TITLE_MAX_LENGTH];
+    size_t sanitized_length = DP_text_sanitize(
+        title, title_length, sanitized, DP_LAYER_TITLE_MAX_LENGTH);
+    return DP_ops_layer_retitle(cs, layer_id, sanitized, sanitized_length);
 }
 
 static DP_CanvasState *handle_layer_delete(DP_CanvasState *cs,
@@ -589,6 +949,16 @@ selection_clear(DP_CanvasState *cs, unsigned int context_id, int selection_id)
     return DP_ops_selection_clear(cs, context_id, selection_id);
 }
 
+// Images that are partially outside of the canvas get clipped when they're
+// put. Ones that are entirely outside would have no effect, so they're rejected
+// before spending any effort on decompressing them.
+static bool put_image_outside(DP_CanvasState *cs, DP_MsgPutImage *mpi)
+{
+    return DP_uint32_to_int(DP_msg_put_image_x(mpi)) >= cs->width
+        || DP_uint32_to_int(DP_msg_put_image_y(mpi)) >= cs->height
+        || DP_msg_put_image_w(mpi) == 0 || DP_msg_put_image_h(mpi) == 0;
+}
+
 static DP_CanvasState *handle_put_image(DP_CanvasState *cs,
                                         DP_UserCursors *ucs_or_null,
                                         unsigned int context_id,
@@ -605,6 +975,11 @@ static DP_CanvasState *handle_put_image(DP_CanvasState *cs,
         return NULL;
     }
 
+    else if (put_image_outside(cs, mpi)) {
+        DP_error_set("Put image: image is outside of the canvas");
+        return NULL;
+    }
+
     size_t image_size;
     const unsigned char *image = DP_msg_put_image_image(mpi, &image_size);
     return DP_ops_put_image(
@@ -615,6 +990,21 @@ static DP_CanvasState *handle_put_image(DP_CanvasState *cs,
         DP_uint32_to_int(DP_msg_put_image_h(mpi)), image, image_size);
 }
 
+static bool fill_rect_clip(DP_CanvasState *cs, DP_MsgFillRect *mfr,
+                           int *out_left, int *out_top, int *out_right,
+                           int *out_bottom)
+{
+    int x = DP_uint32_to_int(DP_msg_fill_rect_x(mfr));
+    int y = DP_uint32_to_int(DP_msg_fill_rect_y(mfr));
+    int width = DP_uint32_to_int(DP_msg_fill_rect_w(mfr));
+    int height = DP_uint32_to_int(DP_msg_fill_rect_h(mfr));
+    *out_left = DP_max_int(x, 0);
+    *out_top = DP_max_int(y, 0);
+    *out_right = DP_min_int(x + width, cs->width);
+    *out_bottom = DP_min_int(y + height, cs->height);
+    return *out_left < *out_right && *out_top < *out_bottom;
+}
+
 static DP_CanvasState *handle_fill_rect(DP_CanvasState *cs,
                                         DP_UserCursors *ucs_or_null,
                                         unsigned int context_id,
@@ -631,15 +1021,8 @@ static DP_CanvasState *handle_fill_rect(DP_CanvasState *cs,
         return NULL;
     }
 
-    int x = DP_uint32_to_int(DP_msg_fill_rect_x(mfr));
-    int y = DP_uint32_to_int(DP_msg_fill_rect_y(mfr));
-    int width = DP_uint32_to_int(DP_msg_fill_rect_w(mfr));
-    int height = DP_uint32_to_int(DP_msg_fill_rect_h(mfr));
-    int left = DP_max_int(x, 0);
-    int top = DP_max_int(y, 0);
-    int right = DP_min_int(x + width, cs->width);
-    int bottom = DP_min_int(y + height, cs->height);
-    if (left >= right || top >= bottom) {
+    int left, top, right, bottom;
+    if (!fill_rect_clip(cs, mfr, &left, &top, &right, &bottom)) {
         DP_error_set("Fill rect: effective area to fill is zero");
         return NULL;
     }
@@ -717,7 +1100,8 @@ static DP_CanvasState *handle_move_region(DP_CanvasState *cs,
     // transform the "main" selection, since, at the time of writing, that's the
     // only selection that actually exists. This message only exists for
     // backward-compatibility reasons anyway, replaced by TransformRegion.
-    int dst_layer_id = src_layer_id == 0 ? ((1 << 8) | 1) : src_layer_id;
+    int dst_layer_id =
+        src_layer_id == 0 ? DP_layer_id_make(1, 1) : src_layer_id;
     size_t in_mask_size;
     const unsigned char *in_mask = DP_msg_move_region_mask(mmr, &in_mask_size);
     return handle_region(
@@ -732,15 +1116,20 @@ static DP_CanvasState *handle_move_region(DP_CanvasState *cs,
         in_mask_size, DP_image_new_from_compressed_monochrome);
 }
 
+static int put_tile_start(DP_CanvasState *cs, DP_MsgPutTile *mpt)
+{
+    DP_TileCounts tile_counts = DP_tile_counts_round(cs->width, cs->height);
+    return DP_msg_put_tile_row(mpt) * tile_counts.x + DP_msg_put_tile_col(mpt);
+}
+
 static DP_CanvasState *handle_put_tile(DP_CanvasState *cs, DP_DrawContext *dc,
                                        unsigned int context_id,
                                        DP_MsgPutTile *mpt)
 {
-    DP_TileCounts tile_counts = DP_tile_counts_round(cs->width, cs->height);
-    int tile_total = tile_counts.x * tile_counts.y;
+    int tile_total = DP_tile_total_round(cs->width, cs->height);
     int x = DP_msg_put_tile_col(mpt);
     int y = DP_msg_put_tile_row(mpt);
-    int start = y * tile_counts.x + x;
+    int start = put_tile_start(cs, mpt);
     if (start >= tile_total) {
         DP_error_set("Put tile: starting index %d beyond total %d", start,
                      tile_total);
@@ -837,6 +1226,17 @@ static DP_CanvasState *handle_annotation_edit(DP_CanvasState *cs,
                                   valign, text, text_length);
 }
 
+static DP_CanvasState *
+handle_annotation_text_edit(DP_CanvasState *cs, DP_MsgAnnotationTextEdit *mate)
+{
+    size_t text_length;
+    const char *text = DP_msg_annotation_text_edit_text(mate, &text_length);
+    return DP_ops_annotation_text_edit(
+        cs, DP_msg_annotation_text_edit_id(mate),
+        DP_msg_annotation_text_edit_offset(mate),
+        DP_msg_annotation_text_edit_count(mate), text, text_length);
+}
+
 static DP_CanvasState *handle_annotation_delete(DP_CanvasState *cs,
                                                 DP_MsgAnnotationDelete *mad)
 {
@@ -1083,6 +1483,10 @@ static DP_CanvasState *handle_layer_tree_create(DP_CanvasState *cs,
         DP_error_set("Create layer tree: layer id 0 is invalid");
         return NULL;
     }
+    else if (layer_id == DP_ACL_DEFAULT_LAYER_ID) {
+        DP_error_set("Create layer tree: layer id %d is reserved", layer_id);
+        return NULL;
+    }
 
     unsigned int flags = DP_msg_layer_tree_create_flags(mtlc);
     bool into = flags & DP_MSG_LAYER_TREE_CREATE_FLAGS_INTO;
@@ -1094,11 +1498,14 @@ static DP_CanvasState *handle_layer_tree_create(DP_CanvasState *cs,
 
     size_t title_length;
     const char *title = DP_msg_layer_tree_create_title(mtlc, &title_length);
+    char sanitized[DP_LAYER_TITLE_MAX_LENGTH];
+    size_t sanitized_length = DP_text_sanitize(
+        title, title_length, sanitized, DP_LAYER_TITLE_MAX_LENGTH);
 
     DP_CanvasState *next = DP_ops_layer_tree_create(
         cs, dc, layer_id, DP_msg_layer_tree_create_source(mtlc),
-        DP_msg_layer_tree_create_target(mtlc), tile, into, group, title,
-        title_length);
+        DP_msg_layer_tree_create_target(mtlc), tile, into, group, sanitized,
+        sanitized_length);
 
     DP_tile_decref_nullable(tile);
     return next;
@@ -1352,6 +1759,9 @@ static DP_CanvasState *handle(DP_CanvasState *cs, DP_DrawContext *dc,
         return handle_layer_attr(cs, DP_msg_layer_attributes_cast(msg));
     case DP_MSG_LAYER_ORDER:
         return handle_layer_order(cs, dc, DP_msg_layer_order_cast(msg));
+    case DP_MSG_LAYER_ORDER_MOVE:
+        return handle_layer_order_move(cs, dc,
+                                       DP_msg_layer_order_move_cast(msg));
     case DP_MSG_LAYER_RETITLE:
         return handle_layer_retitle(cs, DP_msg_layer_retitle_cast(msg));
     case DP_MSG_LAYER_DELETE:
@@ -1384,6 +1794,9 @@ static DP_CanvasState *handle(DP_CanvasState *cs, DP_DrawContext *dc,
                                          DP_msg_annotation_reshape_cast(msg));
     case DP_MSG_ANNOTATION_EDIT:
         return handle_annotation_edit(cs, DP_msg_annotation_edit_cast(msg));
+    case DP_MSG_ANNOTATION_TEXT_EDIT:
+        return handle_annotation_text_edit(
+            cs, DP_msg_annotation_text_edit_cast(msg));
     case DP_MSG_ANNOTATION_DELETE:
         return handle_annotation_delete(cs, DP_msg_annotation_delete_cast(msg));
     case DP_MSG_DRAW_DABS_CLASSIC:
@@ -1456,6 +1869,113 @@ DP_CanvasState *DP_canvas_state_handle(DP_CanvasState *cs, DP_DrawContext *dc,
     return next_cs;
 }
 
+static DP_PaintError layer_paint_error(DP_CanvasState *cs, int layer_id)
+{
+    return DP_layer_routes_search(cs->layer_routes, layer_id)
+             ? DP_PAINT_ERROR_OTHER
+             : DP_PAINT_ERROR_LAYER_NOT_FOUND;
+}
+
+static DP_PaintError tile_paint_error(DP_DrawContext *dc,
+                                      const unsigned char *image,
+                                      size_t image_size)
+{
+    if (DP_tile_compressed_checksum(image, image_size)
+        == DP_TILE_CHECKSUM_MISMATCH) {
+        return DP_PAINT_ERROR_TILE_CHECKSUM;
+    }
+    DP_Tile *tile = DP_tile_new_from_compressed(dc, 0, image, image_size);
+    if (tile) {
+        DP_tile_decref(tile);
+        return DP_PAINT_ERROR_NONE;
+    }
+    else {
+        return DP_PAINT_ERROR_TILE_DECODE;
+    }
+}
+
+// Figures out why a message failed to be handled, checking the same things in
+// the same order as the handlers do.
+static DP_PaintError paint_error(DP_CanvasState *cs, DP_DrawContext *dc,
+                                 DP_Message *msg, DP_MessageType type)
+{
+    switch (type) {
+    case DP_MSG_PUT_IMAGE: {
+        DP_MsgPutImage *mpi = DP_msg_put_image_cast(msg);
+        if (!DP_blend_mode_exists(DP_msg_put_image_mode(mpi))) {
+            return DP_PAINT_ERROR_INVALID_BLEND_MODE;
+        }
+        else if (put_image_outside(cs, mpi)) {
+            return DP_PAINT_ERROR_OUT_OF_BOUNDS;
+        }
+        else {
+            return layer_paint_error(cs, DP_msg_put_image_layer(mpi));
+        }
+    }
+    case DP_MSG_FILL_RECT: {
+        DP_MsgFillRect *mfr = DP_msg_fill_rect_cast(msg);
+        int blend_mode = DP_msg_fill_rect_mode(mfr);
+        int left, top, right, bottom;
+        if (!DP_blend_mode_exists(blend_mode)
+            || !DP_blend_mode_valid_for_brush(blend_mode)) {
+            return DP_PAINT_ERROR_INVALID_BLEND_MODE;
+        }
+        else if (!fill_rect_clip(cs, mfr, &left, &top, &right, &bottom)) {
+            return DP_PAINT_ERROR_OUT_OF_BOUNDS;
+        }
+        else {
+            return layer_paint_error(cs, DP_msg_fill_rect_layer(mfr));
+        }
+    }
+    case DP_MSG_PUT_TILE: {
+        DP_MsgPutTile *mpt = DP_msg_put_tile_cast(msg);
+        if (put_tile_start(cs, mpt)
+            >= DP_tile_total_round(cs->width, cs->height)) {
+            return DP_PAINT_ERROR_OUT_OF_BOUNDS;
+        }
+        size_t image_size;
+        const unsigned char *image = DP_msg_put_tile_image(mpt, &image_size);
+        DP_PaintError error = tile_paint_error(dc, image, image_size);
+        return error == DP_PAINT_ERROR_NONE
+                 ? layer_paint_error(cs, DP_msg_put_tile_layer(mpt))
+                 : error;
+    }
+    case DP_MSG_CANVAS_BACKGROUND: {
+        size_t image_size;
+        const unsigned char *image = DP_msg_canvas_background_image(
+            DP_msg_canvas_background_cast(msg), &image_size);
+        DP_PaintError error = tile_paint_error(dc, image, image_size);
+        return error == DP_PAINT_ERROR_NONE ? DP_PAINT_ERROR_OTHER : error;
+    }
+    default:
+        return DP_PAINT_ERROR_OTHER;
+    }
+}
+
+DP_CanvasState *DP_canvas_state_handle_with_error(DP_CanvasState *cs,
+                                                  DP_DrawContext *dc,
+                                                  DP_UserCursors *ucs_or_null,
+                                                  DP_Message *msg,
+                                                  DP_PaintError *out_error)
+{
+    DP_ASSERT(out_error);
+    DP_CanvasState *next_cs = DP_canvas_state_handle(cs, dc, ucs_or_null, msg);
+    if (next_cs) {
+        *out_error = DP_PAINT_ERROR_NONE;
+    }
+    else {
+        // Classifying may set errors of its own, keep the original one.
+        unsigned int error_count = DP_error_count();
+        char *error = DP_strdup(DP_error());
+        *out_error = paint_error(cs, dc, msg, DP_message_type(msg));
+        if (DP_error_count_since(error_count) != 0) {
+            DP_error_set("%s", error);
+        }
+        DP_free(error);
+    }
+    return next_cs;
+}
+
 DP_CanvasState *DP_canvas_state_handle_multidab(DP_CanvasState *cs,
                                                 DP_DrawContext *dc,
                                                 DP_UserCursors *ucs_or_null,
@@ -1471,6 +1991,173 @@ DP_CanvasState *DP_canvas_state_handle_multidab(DP_CanvasState *cs,
     return next_cs;
 }
 
+DP_Message *DP_canvas_state_msg_fill_layer_new(DP_CanvasState *cs,
+                                               unsigned int context_id,
+                                               int layer_id, uint32_t color)
+{
+    DP_ASSERT(cs);
+    int width = cs->width;
+    int height = cs->height;
+    if (width > 0 && height > 0) {
+        return DP_msg_fill_rect_new(context_id, DP_int_to_uint16(layer_id),
+                                    DP_BLEND_MODE_REPLACE, 0, 0,
+                                    DP_int_to_uint32(width),
+                                    DP_int_to_uint32(height), color);
+    }
+    else {
+        DP_error_set("Fill layer: canvas has no size");
+        return NULL;
+    }
+}
+
+typedef struct DP_MemoryUsageSeen {
+    const void *ptr;
+    UT_hash_handle hh;
+} DP_MemoryUsageSeen;
+
+typedef struct DP_MemoryUsageContext {
+    DP_MemoryUsageSeen *seen;
+    DP_CanvasMemoryUsage usage;
+} DP_MemoryUsageContext;
+
+static bool memory_usage_first_seen(DP_MemoryUsageContext *c, const void *ptr)
+{
+    DP_MemoryUsageSeen *seen;
+    HASH_FIND(hh, c->seen, &ptr, sizeof(ptr), seen);
+    if (seen) {
+        return false;
+    }
+    else {
+        seen = DP_malloc(sizeof(*seen));
+        seen->ptr = ptr;
+        HASH_ADD(hh, c->seen, ptr, sizeof(seen->ptr), seen);
+        return true;
+    }
+}
+
+static void memory_usage_tile(DP_MemoryUsageContext *c, DP_Tile *t_or_null)
+{
+    if (t_or_null && memory_usage_first_seen(c, t_or_null)) {
+        c->usage.tile_bytes += DP_TILE_BYTES;
+        if (DP_tile_blank(t_or_null)) {
+            ++c->usage.blank_tile_count;
+        }
+        else {
+            ++c->usage.tile_count;
+        }
+    }
+}
+
+static void memory_usage_layer_list(DP_MemoryUsageContext *c,
+                                    DP_LayerList *ll);
+
+static void memory_usage_layer_content(DP_MemoryUsageContext *c,
+                                       DP_LayerContent *lc)
+{
+    if (memory_usage_first_seen(c, lc)) {
+        ++c->usage.layer_count;
+        DP_TileCounts counts = DP_tile_counts_round(
+            DP_layer_content_width(lc), DP_layer_content_height(lc));
+        for (int y = 0; y < counts.y; ++y) {
+            for (int x = 0; x < counts.x; ++x) {
+                memory_usage_tile(c, DP_layer_content_tile_at_noinc(lc, x, y));
+            }
+        }
+        // Sublayers are only temporary, they don't count as layers.
+        int layer_count = c->usage.layer_count;
+        memory_usage_layer_list(c, DP_layer_content_sub_contents_noinc(lc));
+        c->usage.layer_count = layer_count;
+    }
+}
+
+static void memory_usage_layer_list(DP_MemoryUsageContext *c, DP_LayerList *ll)
+{
+    if (memory_usage_first_seen(c, ll)) {
+        int count = DP_layer_list_count(ll);
+        for (int i = 0; i < count; ++i) {
+            DP_LayerListEntry *lle = DP_layer_list_at_noinc(ll, i);
+            if (DP_layer_list_entry_is_group(lle)) {
+                DP_LayerGroup *lg = DP_layer_list_entry_group_noinc(lle);
+                if (memory_usage_first_seen(c, lg)) {
+                    ++c->usage.layer_count;
+                    memory_usage_layer_list(c,
+                                            DP_layer_group_children_noinc(lg));
+                }
+            }
+            else {
+                memory_usage_layer_content(
+                    c, DP_layer_list_entry_content_noinc(lle));
+            }
+        }
+    }
+}
+
+DP_CanvasMemoryUsage DP_canvas_state_memory_usage(int count,
+                                                  DP_CanvasState **states)
+{
+    DP_ASSERT(count >= 0);
+    DP_ASSERT(count == 0 || states);
+    DP_MemoryUsageContext c = {NULL, {0, 0, 0, 0, 0, 0}};
+    for (int i = 0; i < count; ++i) {
+        DP_CanvasState *cs = states[i];
+        DP_ASSERT(cs);
+        DP_ASSERT(DP_atomic_get(&cs->refcount) > 0);
+        memory_usage_tile(&c, cs->background_tile);
+        memory_usage_layer_list(&c, cs->layers);
+    }
+
+    DP_MemoryUsageSeen *seen, *tmp;
+    HASH_ITER(hh, c.seen, seen, tmp) {
+        HASH_DEL(c.seen, seen);
+        DP_free(seen);
+    }
+    return c.usage;
+}
+
+static bool annotation_fully_outside(int canvas_width, int canvas_height,
+                                     long long x, long long y, int width,
+                                     int height)
+{
+    return x >= canvas_width || y >= canvas_height
+        || x + DP_max_int(width, 1) <= 0 || y + DP_max_int(height, 1) <= 0;
+}
+
+static int clamp_annotation_position(int canvas_size, int pos, int size)
+{
+    return DP_max_int(0, DP_min_int(pos, canvas_size - DP_max_int(size, 1)));
+}
+
+DP_Message *
+DP_canvas_state_annotation_reshape_bounded(DP_CanvasState *cs, DP_Message *msg,
+                                           DP_AnnotationBoundsPolicy policy)
+{
+    DP_ASSERT(cs);
+    DP_ASSERT(msg);
+    DP_ASSERT(DP_message_type(msg) == DP_MSG_ANNOTATION_RESHAPE);
+    DP_MsgAnnotationReshape *mar = DP_msg_annotation_reshape_cast(msg);
+    int x = DP_msg_annotation_reshape_x(mar);
+    int y = DP_msg_annotation_reshape_y(mar);
+    int width = DP_msg_annotation_reshape_w(mar);
+    int height = DP_msg_annotation_reshape_h(mar);
+    if (policy == DP_ANNOTATION_BOUNDS_ALLOW
+        || !annotation_fully_outside(cs->width, cs->height, x, y, width,
+                                     height)) {
+        return DP_message_incref(msg);
+    }
+    else if (policy == DP_ANNOTATION_BOUNDS_CLAMP) {
+        return DP_msg_annotation_reshape_new(
+            DP_message_context_id(msg), DP_msg_annotation_reshape_id(mar),
+            clamp_annotation_position(cs->width, x, width),
+            clamp_annotation_position(cs->height, y, height),
+            DP_int_to_uint16(width), DP_int_to_uint16(height));
+    }
+    else {
+        DP_error_set("Annotation reshape: id %d would be outside of canvas",
+                     DP_msg_annotation_reshape_id(mar));
+        return NULL;
+    }
+}
+
 int DP_canvas_state_search_change_bounds(DP_CanvasState *cs,
                                          unsigned int context_id, int *out_x,
                                          int *out_y, int *out_width,
@@ -1511,59 +2198,41 @@ static DP_Tile *get_flat_background_tile_or_null(DP_CanvasState *cs,
     return include_background ? cs->background_tile : NULL;
 }
 
-static void init_flattening_tile(DP_TransientTile *tt, DP_Tile *background_tile)
+static void init_flattening_tile(DP_TransientTile *tt, DP_Tile *background_tile,
+                                 bool linear)
 {
     if (background_tile) {
         memcpy(DP_transient_tile_pixels(tt), DP_tile_pixels(background_tile),
                DP_TILE_BYTES);
+        if (linear) {
+            DP_pixels15_to_linear(DP_transient_tile_pixels(tt), DP_TILE_LENGTH);
+        }
     }
     else {
         memset(DP_transient_tile_pixels(tt), 0, DP_TILE_BYTES);
     }
 }
 
-DP_TransientLayerContent *
-DP_canvas_state_to_flat_layer(DP_CanvasState *cs, unsigned int flags,
-                              const DP_ViewModeFilter *vmf_or_null)
+static void finish_flattening_tile(DP_TransientTile *tt_or_null, bool linear)
 {
-    DP_ASSERT(cs);
-    DP_ASSERT(DP_atomic_get(&cs->refcount) > 0);
-    int width = cs->width;
-    int height = cs->height;
-    DP_TransientLayerContent *tlc =
-        DP_transient_layer_content_new_init(width, height, NULL);
-
-    DP_Tile *background_tile = get_flat_background_tile_or_null(cs, flags);
-    int wt = DP_tile_count_round(width);
-    bool include_sublayers = flags & DP_FLAT_IMAGE_INCLUDE_SUBLAYERS;
-    DP_ViewModeFilter vmf =
-        vmf_or_null ? *vmf_or_null : DP_view_mode_filter_make_default();
-
-    DP_TileIterator ti = DP_tile_iterator_make(
-        cs->width, cs->height, DP_rect_make(0, 0, width, height));
-    while (DP_tile_iterator_next(&ti)) {
-        DP_TransientTile *tt = DP_transient_tile_new_blank(0);
-        init_flattening_tile(tt, background_tile);
-        int i = ti.row * wt + ti.col;
-        DP_canvas_state_flatten_tile_to(cs, i, tt, include_sublayers, &vmf);
-        DP_transient_layer_content_transient_tile_set_noinc(tlc, tt, i);
+    if (tt_or_null && linear) {
+        DP_pixels15_to_srgb(DP_transient_tile_pixels(tt_or_null),
+                            DP_TILE_LENGTH);
     }
-
-    return tlc;
 }
 
 static DP_TransientTile *
 flatten_onion_skin(int tile_index, DP_TransientTile *tt, DP_LayerListEntry *lle,
                    DP_LayerProps *lp, uint16_t parent_opacity,
-                   bool include_sublayers, DP_ViewModeContext *vmc,
-                   const DP_OnionSkin *os)
+                   bool include_sublayers, bool linear,
+                   DP_ViewModeContext *vmc, const DP_OnionSkin *os)
 {
     DP_TransientTile *skin_tt = DP_layer_list_entry_flatten_tile_to(
         lle, lp, tile_index, DP_transient_tile_new_blank(0),
         DP_fix15_mul(parent_opacity, os->opacity), include_sublayers, false,
-        vmc);
+        linear, vmc);
 
-    DP_UPixel15 tint = os->tint;
+    DP_UPixel15 tint = linear ? DP_upixel15_to_linear(os->tint) : os->tint;
     if (tint.a != 0) {
         DP_transient_tile_brush_apply(skin_tt, tint, DP_BLEND_MODE_RECOLOR,
                                       DP_tile_opaque_mask(), tint.a, 0, 0,
@@ -1581,11 +2250,10 @@ flatten_onion_skin(int tile_index, DP_TransientTile *tt, DP_LayerListEntry *lle,
     }
 }
 
-DP_TransientTile *DP_canvas_state_flatten_tile_to(DP_CanvasState *cs,
-                                                  int tile_index,
-                                                  DP_TransientTile *tt_or_null,
-                                                  bool include_sublayers,
-                                                  const DP_ViewModeFilter *vmf)
+static DP_TransientTile *flatten_tile_to(DP_CanvasState *cs, int tile_index,
+                                         DP_TransientTile *tt_or_null,
+                                         bool include_sublayers, bool linear,
+                                         const DP_ViewModeFilter *vmf)
 {
     DP_ViewModeContextRoot vmcr = DP_view_mode_context_root_init(vmf, cs);
     DP_TransientTile *tt = tt_or_null;
@@ -1599,21 +2267,133 @@ DP_TransientTile *DP_canvas_state_flatten_tile_to(DP_CanvasState *cs,
         if (!DP_view_mode_context_excludes_everything(&vmc)) {
             if (os) {
                 tt = flatten_onion_skin(tile_index, tt, lle, lp, parent_opacity,
-                                        include_sublayers, &vmc, os);
+                                        include_sublayers, linear, &vmc, os);
             }
             else {
                 tt = DP_layer_list_entry_flatten_tile_to(
                     lle, lp, tile_index, tt, parent_opacity, include_sublayers,
-                    false, &vmc);
+                    false, linear, &vmc);
             }
         }
     }
     return tt;
 }
 
+DP_TransientTile *DP_canvas_state_flatten_tile_to(DP_CanvasState *cs,
+                                                  int tile_index,
+                                                  DP_TransientTile *tt_or_null,
+                                                  bool include_sublayers,
+                                                  const DP_ViewModeFilter *vmf)
+{
+    return flatten_tile_to(cs, tile_index, tt_or_null, include_sublayers, false,
+                           vmf);
+}
+
+DP_TransientLayerContent *
+DP_canvas_state_to_flat_layer(DP_CanvasState *cs, unsigned int flags,
+                              const DP_ViewModeFilter *vmf_or_null)
+{
+    DP_ASSERT(cs);
+    DP_ASSERT(DP_atomic_get(&cs->refcount) > 0);
+    int width = cs->width;
+    int height = cs->height;
+    DP_TransientLayerContent *tlc =
+        DP_transient_layer_content_new_init(width, height, NULL);
+
+    DP_Tile *background_tile = get_flat_background_tile_or_null(cs, flags);
+    int wt = DP_tile_count_round(width);
+    bool include_sublayers = flags & DP_FLAT_IMAGE_INCLUDE_SUBLAYERS;
+    bool linear = flags & DP_FLAT_IMAGE_LINEAR_BLENDING;
+    DP_ViewModeFilter vmf =
+        vmf_or_null ? *vmf_or_null : DP_view_mode_filter_make_default();
+
+    DP_TileIterator ti = DP_tile_iterator_make(
+        cs->width, cs->height, DP_rect_make(0, 0, width, height));
+    while (DP_tile_iterator_next(&ti)) {
+        DP_TransientTile *tt = DP_transient_tile_new_blank(0);
+        init_flattening_tile(tt, background_tile, linear);
+        int i = ti.row * wt + ti.col;
+        flatten_tile_to(cs, i, tt, include_sublayers, linear, &vmf);
+        finish_flattening_tile(tt, linear);
+        DP_transient_layer_content_transient_tile_set_noinc(tlc, tt, i);
+    }
+
+    return tlc;
+}
+
+struct DP_FlattenCanvasParams {
+    DP_CanvasState *cs;
+    DP_Tile *background_tile;
+    int wt;
+    bool include_sublayers;
+    bool linear;
+    const DP_ViewModeFilter *vmf;
+    void (*to_buffer)(void *, DP_TransientTile *, DP_TileIterator *);
+    void *buffer;
+    DP_TransientTile **tts;
+};
+
+struct DP_FlattenCanvasJob {
+    struct DP_FlattenCanvasParams *params;
+    DP_TileIterator ti;
+};
+
+static void flatten_canvas_tile(struct DP_FlattenCanvasParams *params,
+                                DP_TransientTile *tt, DP_TileIterator *ti)
+{
+    init_flattening_tile(tt, params->background_tile, params->linear);
+    int i = ti->row * params->wt + ti->col;
+    flatten_tile_to(params->cs, i, tt, params->include_sublayers,
+                    params->linear, params->vmf);
+    finish_flattening_tile(tt, params->linear);
+    params->to_buffer(params->buffer, tt, ti);
+}
+
+static void flatten_canvas_job(void *element, int thread_index)
+{
+    struct DP_FlattenCanvasJob *job = element;
+    struct DP_FlattenCanvasParams *params = job->params;
+    flatten_canvas_tile(params, params->tts[thread_index], &job->ti);
+}
+
+// Each tile only writes to its own part of the buffer, so they can be
+// flattened in any order and on any thread without further synchronization.
+static bool flatten_canvas_parallel(struct DP_FlattenCanvasParams *params,
+                                    DP_TileIterator ti, int tile_count,
+                                    int thread_count)
+{
+    DP_Worker *worker =
+        DP_worker_new(DP_int_to_size(tile_count),
+                      sizeof(struct DP_FlattenCanvasJob),
+                      DP_min_int(thread_count, tile_count), flatten_canvas_job);
+    if (!worker) {
+        return false;
+    }
+
+    int worker_thread_count = DP_worker_thread_count(worker);
+    params->tts = DP_malloc(sizeof(*params->tts)
+                            * DP_int_to_size(worker_thread_count));
+    for (int i = 0; i < worker_thread_count; ++i) {
+        params->tts[i] = DP_transient_tile_new_blank(0);
+    }
+
+    while (DP_tile_iterator_next(&ti)) {
+        struct DP_FlattenCanvasJob job = {params, ti};
+        DP_worker_push(worker, &job);
+    }
+    DP_worker_free_join(worker);
+
+    for (int i = 0; i < worker_thread_count; ++i) {
+        DP_transient_tile_decref(params->tts[i]);
+    }
+    DP_free(params->tts);
+    return true;
+}
+
 static void *flatten_canvas(
     DP_CanvasState *cs, unsigned int flags, const DP_Rect *area_or_null,
-    const DP_ViewModeFilter *vmf_or_null, void *(*get_buffer)(void *, int, int),
+    const DP_ViewModeFilter *vmf_or_null, int thread_count,
+    void *(*get_buffer)(void *, int, int),
     void (*to_buffer)(void *, DP_TransientTile *, DP_TileIterator *),
     void *user)
 {
@@ -1624,23 +2404,35 @@ static void *flatten_canvas(
         return NULL;
     }
 
-    DP_Tile *background_tile = get_flat_background_tile_or_null(cs, flags);
-    int wt = DP_tile_count_round(cs->width);
-    bool include_sublayers = flags & DP_FLAT_IMAGE_INCLUDE_SUBLAYERS;
     DP_ViewModeFilter vmf =
         vmf_or_null ? *vmf_or_null : DP_view_mode_filter_make_default();
+    struct DP_FlattenCanvasParams params = {
+        cs,
+        get_flat_background_tile_or_null(cs, flags),
+        DP_tile_count_round(cs->width),
+        flags & DP_FLAT_IMAGE_INCLUDE_SUBLAYERS,
+        flags & DP_FLAT_IMAGE_LINEAR_BLENDING,
+        &vmf,
+        to_buffer,
+        get_buffer(user, DP_rect_width(area), DP_rect_height(area)),
+        NULL,
+    };
 
-    void *buffer = get_buffer(user, DP_rect_width(area), DP_rect_height(area));
-    DP_TransientTile *tt = DP_transient_tile_new_blank(0);
     DP_TileIterator ti = DP_tile_iterator_make(cs->width, cs->height, area);
-    while (DP_tile_iterator_next(&ti)) {
-        init_flattening_tile(tt, background_tile);
-        int i = ti.row * wt + ti.col;
-        DP_canvas_state_flatten_tile_to(cs, i, tt, include_sublayers, &vmf);
-        to_buffer(buffer, tt, &ti);
+    int tile_count = DP_rect_width(ti.tile_area) * DP_rect_height(ti.tile_area);
+    bool parallel = thread_count > 1
+                 && tile_count >= DP_FLAT_IMAGE_PARALLEL_TILE_THRESHOLD
+                 && flatten_canvas_parallel(&params, ti, tile_count,
+                                            thread_count);
+    // Too small to be worth spinning up threads, or creating them failed.
+    if (!parallel) {
+        DP_TransientTile *tt = DP_transient_tile_new_blank(0);
+        while (DP_tile_iterator_next(&ti)) {
+            flatten_canvas_tile(&params, tt, &ti);
+        }
+        DP_transient_tile_decref(tt);
     }
-    DP_transient_tile_decref(tt);
-    return buffer;
+    return params.buffer;
 }
 
 DP_Image *DP_canvas_state_to_flat_image(DP_CanvasState *cs, unsigned int flags,
@@ -1724,7 +2516,7 @@ DP_Image *DP_canvas_state_into_flat_image(DP_CanvasState *cs,
 {
     DP_ASSERT(cs);
     DP_ASSERT(DP_atomic_get(&cs->refcount) > 0);
-    return flatten_canvas(cs, flags, area_or_null, vmf_or_null,
+    return flatten_canvas(cs, flags, area_or_null, vmf_or_null, 1,
                           into_flat_image_get_buffer,
                           flags & DP_FLAT_IMAGE_ONE_BIT_ALPHA
                               ? into_flat_image_to_buffer_one_bit_alpha
@@ -1732,6 +2524,21 @@ DP_Image *DP_canvas_state_into_flat_image(DP_CanvasState *cs,
                           inout_img_or_null);
 }
 
+DP_Image *DP_canvas_state_to_flat_image_parallel(
+    DP_CanvasState *cs, unsigned int flags, const DP_Rect *area_or_null,
+    const DP_ViewModeFilter *vmf_or_null, int thread_count)
+{
+    DP_ASSERT(cs);
+    DP_ASSERT(DP_atomic_get(&cs->refcount) > 0);
+    DP_ASSERT(thread_count > 0);
+    return flatten_canvas(cs, flags, area_or_null, vmf_or_null, thread_count,
+                          into_flat_image_get_buffer,
+                          flags & DP_FLAT_IMAGE_ONE_BIT_ALPHA
+                              ? into_flat_image_to_buffer_one_bit_alpha
+                              : into_flat_image_to_buffer,
+                          NULL);
+}
+
 static void *to_flat_separated_urgba8_get_buffer(void *user,
                                                  DP_UNUSED int width,
                                                  DP_UNUSED int height)
@@ -1764,7 +2571,7 @@ bool DP_canvas_state_to_flat_separated_urgba8(
 {
     DP_ASSERT(cs);
     DP_ASSERT(DP_atomic_get(&cs->refcount) > 0);
-    return flatten_canvas(cs, flags, area_or_null, vmf_or_null,
+    return flatten_canvas(cs, flags, area_or_null, vmf_or_null, 1,
                           to_flat_separated_urgba8_get_buffer,
                           to_flat_separated_urgba8_to_buffer, buffer);
 }
@@ -1782,10 +2589,15 @@ DP_canvas_state_flatten_tile(DP_CanvasState *cs, int tile_index,
                              ? DP_transient_tile_new(background_tile, 0)
                              : DP_transient_tile_new_blank(0);
     bool include_sublayers = flags & DP_FLAT_IMAGE_INCLUDE_SUBLAYERS;
+    bool linear = flags & DP_FLAT_IMAGE_LINEAR_BLENDING;
+    if (background_tile && linear) {
+        DP_pixels15_to_linear(DP_transient_tile_pixels(tt), DP_TILE_LENGTH);
+    }
     DP_ViewModeFilter vmf =
         vmf_or_null ? *vmf_or_null : DP_view_mode_filter_make_default();
-    return DP_canvas_state_flatten_tile_to(cs, tile_index, tt,
-                                           include_sublayers, &vmf);
+    tt = flatten_tile_to(cs, tile_index, tt, include_sublayers, linear, &vmf);
+    finish_flattening_tile(tt, linear);
+    return tt;
 }
 
 DP_TransientTile *
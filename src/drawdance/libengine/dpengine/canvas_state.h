@@ -46,9 +46,56 @@ typedef struct DP_ViewModeFilter DP_ViewModeFilter;
 #define DP_FLAT_IMAGE_INCLUDE_BACKGROUND (1u << 0u)
 #define DP_FLAT_IMAGE_INCLUDE_SUBLAYERS  (1u << 1u)
 #define DP_FLAT_IMAGE_ONE_BIT_ALPHA      (1u << 2u)
+#define DP_FLAT_IMAGE_LINEAR_BLENDING    (1u << 3u)
 #define DP_FLAT_IMAGE_RENDER_FLAGS \
     (DP_FLAT_IMAGE_INCLUDE_BACKGROUND | DP_FLAT_IMAGE_INCLUDE_SUBLAYERS)
 
+// Flattening images covering fewer tiles than this isn't worth parallelizing.
+#define DP_FLAT_IMAGE_PARALLEL_TILE_THRESHOLD 16
+
+// Why a message couldn't be applied to the canvas. Only messages that put
+// pixels onto a layer get the more specific kinds, anything else is other.
+typedef enum DP_PaintError {
+    DP_PAINT_ERROR_NONE,
+    DP_PAINT_ERROR_LAYER_NOT_FOUND,
+    DP_PAINT_ERROR_OUT_OF_BOUNDS,
+    DP_PAINT_ERROR_TILE_DECODE,
+    DP_PAINT_ERROR_TILE_CHECKSUM,
+    DP_PAINT_ERROR_INVALID_BLEND_MODE,
+    DP_PAINT_ERROR_OTHER,
+} DP_PaintError;
+
+// How much memory canvas states take up. Since states share their layers and
+// tiles until they get changed, anything used in multiple places or by
+// multiple states is only counted once. That also goes for layers duplicated
+// from another one, as long as neither was changed since. Only layer tiles and
+// the background tile are considered, smaller bookkeeping is left out.
+typedef struct DP_CanvasMemoryUsage {
+    size_t tile_bytes;
+    int tile_count;
+    int blank_tile_count;
+    int layer_count;
+    int history_count;
+    size_t history_message_bytes;
+} DP_CanvasMemoryUsage;
+
+// What to do with an annotation reshape that would put the annotation entirely
+// outside of the canvas, where it can't be reached anymore.
+typedef enum DP_AnnotationBoundsPolicy {
+    DP_ANNOTATION_BOUNDS_ALLOW,
+    DP_ANNOTATION_BOUNDS_CLAMP,
+    DP_ANNOTATION_BOUNDS_REJECT,
+} DP_AnnotationBoundsPolicy;
+
+// Rotations in steps of 90 degrees and flips, applied to the canvas as a whole.
+typedef enum DP_CanvasTransform {
+    DP_CANVAS_TRANSFORM_ROTATE_CLOCKWISE,
+    DP_CANVAS_TRANSFORM_ROTATE_COUNTERCLOCKWISE,
+    DP_CANVAS_TRANSFORM_ROTATE_180,
+    DP_CANVAS_TRANSFORM_FLIP_HORIZONTAL,
+    DP_CANVAS_TRANSFORM_FLIP_VERTICAL,
+} DP_CanvasTransform;
+
 typedef struct DP_CanvasState DP_CanvasState;
 
 #ifdef DP_NO_STRICT_ALIASING
@@ -79,6 +126,17 @@ DP_CanvasState *DP_canvas_state_new(void);
 DP_CanvasState *DP_canvas_state_new_with_selections_noinc(DP_CanvasState *cs,
                                                           DP_SelectionSet *ss);
 
+// Makes a canvas the size of the image with a single layer holding it, such as
+// for starting a session from a flat image. The image doesn't need to be
+// aligned to the tile size. To distribute the canvas, build a reset image from
+// it with DP_reset_image_build, which makes the canvas resize, layer creation
+// and put tile messages for it.
+DP_CanvasState *DP_canvas_state_new_from_image(DP_DrawContext *dc,
+                                               DP_Image *img,
+                                               unsigned int context_id,
+                                               int layer_id, const char *title,
+                                               size_t title_length);
+
 DP_CanvasState *DP_canvas_state_incref(DP_CanvasState *cs);
 
 DP_CanvasState *DP_canvas_state_incref_nullable(DP_CanvasState *cs_or_null);
@@ -105,10 +163,66 @@ bool DP_canvas_state_background_opaque(DP_CanvasState *cs);
 
 DP_LayerList *DP_canvas_state_layers_noinc(DP_CanvasState *cs);
 
+// Tight bounds of all non-transparent pixels on any layer, hidden or not. The
+// background isn't considered. Returns false if the canvas is blank.
+bool DP_canvas_state_content_bounds(DP_CanvasState *cs, DP_Rect *out_bounds);
+
 DP_LayerPropsList *DP_canvas_state_layer_props_noinc(DP_CanvasState *cs);
 
 DP_LayerRoutes *DP_canvas_state_layer_routes_noinc(DP_CanvasState *cs);
 
+// The layers of a canvas, along with their order, properties and the canvas
+// size they belong to. Layers are persistent and share their tiles, so taking
+// a snapshot just takes references to them, no pixels get copied.
+typedef struct DP_LayerStackSnapshot {
+    int width, height;
+    DP_LayerList *ll;
+    DP_LayerPropsList *lpl;
+} DP_LayerStackSnapshot;
+
+void DP_canvas_state_layer_stack_snapshot(DP_CanvasState *cs,
+                                          DP_LayerStackSnapshot *out_snapshot);
+
+// Makes a new canvas state with the layers and canvas size put back the way
+// they were in the snapshot. The background, annotations and metadata are left
+// alone. Key frames referring to layers that no longer exist are cleared and
+// selections get dropped if the canvas size changes.
+DP_CanvasState *
+DP_canvas_state_layer_stack_restore(DP_CanvasState *cs, DP_DrawContext *dc,
+                                    const DP_LayerStackSnapshot *snapshot);
+
+void DP_layer_stack_snapshot_dispose(DP_LayerStackSnapshot *snapshot);
+
+// Reports the tiles that differ between two snapshots as layer id and tile
+// coordinates, returning how many there were. Since layers and tiles are
+// shared between snapshots until they're changed, this only compares pointers,
+// so unchanged layers are skipped entirely. A tile that was replaced by one
+// with the same pixels counts as changed. Layers that are only in one of the
+// snapshots report every tile they have, as do tiles outside of the other
+// snapshot's bounds.
+int DP_layer_stack_snapshot_changed_tiles(
+    const DP_LayerStackSnapshot *snapshot, const DP_LayerStackSnapshot *other,
+    void (*fn)(void *user, int layer_id, int tile_x, int tile_y), void *user);
+
+// Whether the transform turns a width by height canvas into a height by width
+// one, which is the case for the 90 degree rotations.
+bool DP_canvas_transform_swaps_dimensions(DP_CanvasTransform transform);
+
+// Where the pixel at x, y of a width by height canvas ends up when transformed.
+void DP_canvas_transform_point(DP_CanvasTransform transform, int width,
+                               int height, int x, int y, int *out_x,
+                               int *out_y);
+
+// Makes a new canvas state with the pixels of every layer rotated or flipped,
+// changing the canvas size to fit for 90 degree rotations. The background,
+// annotations and timeline are left alone, selections get dropped. To bring
+// other clients in line, build the messages for it with
+// DP_reset_image_diff_build, passing the transformed state and this one as the
+// previous one, since the layers keep their ids.
+DP_CanvasState *DP_canvas_state_transform_all(DP_CanvasState *cs,
+                                              unsigned int context_id,
+                                              DP_CanvasTransform transform);
+
 DP_AnnotationList *DP_canvas_state_annotations_noinc(DP_CanvasState *cs);
 
 DP_Timeline *DP_canvas_state_timeline_noinc(DP_CanvasState *cs);
@@ -132,16 +246,53 @@ DP_CanvasState *DP_canvas_state_handle(DP_CanvasState *cs, DP_DrawContext *dc,
                                        DP_UserCursors *ucs_or_null,
                                        DP_Message *msg);
 
+// Like DP_canvas_state_handle, but on failure also reports the rough kind of
+// error, for logging and recovery after a message got through the ACLs. The
+// details are still in DP_error. On success, the error is set to none.
+DP_CanvasState *DP_canvas_state_handle_with_error(DP_CanvasState *cs,
+                                                  DP_DrawContext *dc,
+                                                  DP_UserCursors *ucs_or_null,
+                                                  DP_Message *msg,
+                                                  DP_PaintError *out_error);
+
 DP_CanvasState *DP_canvas_state_handle_multidab(DP_CanvasState *cs,
                                                 DP_DrawContext *dc,
                                                 DP_UserCursors *ucs_or_null,
                                                 int count, DP_Message **msgs);
 
+// Makes a single FillRect message that replaces the entire given layer with
+// the given color. The canvas handles this by sharing one solid tile across
+// the whole layer, or by clearing it if the color is transparent. Returns NULL
+// if the canvas has no size.
+DP_Message *DP_canvas_state_msg_fill_layer_new(DP_CanvasState *cs,
+                                               unsigned int context_id,
+                                               int layer_id, uint32_t color);
+
+// Memory usage of the given canvas states taken together, the history fields
+// are left at zero. Blank tiles are allocated, so they count towards the bytes,
+// but not towards the tile count.
+DP_CanvasMemoryUsage DP_canvas_state_memory_usage(int count,
+                                                  DP_CanvasState **states);
+
+// Checks an AnnotationReshape message against the canvas bounds before it gets
+// sent or accepted. This can't happen when handling the message, since every
+// client has to end up with the same result. Annotations that stay at least
+// partially on the canvas are always let through. Fully outside ones are moved
+// back inside along the nearest edges when clamping or cause an error when
+// rejecting, in which case this returns NULL. Otherwise returns the message,
+// with a new reference, or a new message with the clamped position.
+DP_Message *
+DP_canvas_state_annotation_reshape_bounded(DP_CanvasState *cs, DP_Message *msg,
+                                           DP_AnnotationBoundsPolicy policy);
+
 int DP_canvas_state_search_change_bounds(DP_CanvasState *cs,
                                          unsigned int context_id, int *out_x,
                                          int *out_y, int *out_width,
                                          int *out_height);
 
+// Flattening blends in sRGB, like the canvas view does, unless the flags
+// contain DP_FLAT_IMAGE_LINEAR_BLENDING. In that case, layers are converted to
+// linear light before blending them and the result is converted back to sRGB.
 DP_TransientLayerContent *
 DP_canvas_state_to_flat_layer(DP_CanvasState *cs, unsigned int flags,
                               const DP_ViewModeFilter *vmf_or_null);
@@ -150,6 +301,12 @@ DP_Image *DP_canvas_state_to_flat_image(DP_CanvasState *cs, unsigned int flags,
                                         const DP_Rect *area_or_null,
                                         const DP_ViewModeFilter *vmf_or_null);
 
+// Like DP_canvas_state_to_flat_image, but splits the tiles across up to the
+// given number of threads if the area is large enough. The result is the same.
+DP_Image *DP_canvas_state_to_flat_image_parallel(
+    DP_CanvasState *cs, unsigned int flags, const DP_Rect *area_or_null,
+    const DP_ViewModeFilter *vmf_or_null, int thread_count);
+
 DP_Image *DP_canvas_state_into_flat_image(DP_CanvasState *cs,
                                           unsigned int flags,
                                           const DP_Rect *area_or_null,
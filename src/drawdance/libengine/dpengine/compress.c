@@ -149,3 +149,10 @@ size_t DP_compress_deflate(const unsigned char *in, size_t in_size,
     free_deflate_z_stream(&stream);
     return out_used;
 }
+
+
+uint32_t DP_compress_crc32(const unsigned char *in, size_t in_size)
+{
+    uLong crc = crc32(0L, Z_NULL, 0);
+    return DP_ulong_to_uint32(crc32(crc, in, DP_size_to_uint(in_size)));
+}
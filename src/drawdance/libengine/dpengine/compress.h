@@ -32,5 +32,7 @@ size_t DP_compress_deflate(const unsigned char *in, size_t in_size,
                            unsigned char *(*get_output_buffer)(size_t, void *),
                            void *user);
 
+uint32_t DP_compress_crc32(const unsigned char *in, size_t in_size);
+
 
 #endif
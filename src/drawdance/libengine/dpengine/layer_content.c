@@ -600,7 +600,8 @@ static bool layer_content_crop(DP_LayerContent *lc, int *out_x, int *out_y,
     DP_TileIterator ti = DP_tile_iterator_make(lc->width, lc->height, dst);
     while (DP_tile_iterator_next(&ti)) {
         DP_Tile *t = DP_layer_content_tile_at_noinc(lc, ti.col, ti.row);
-        if (t) {
+        // Skip fully transparent tiles without looking at each pixel.
+        if (t && !DP_tile_blank(t)) {
             DP_TileIntoDstIterator tidi = DP_tile_into_dst_iterator_make(&ti);
             while (DP_tile_into_dst_iterator_next(&tidi)) {
                 bool crop_left = tidi.dst_x < crop.x1;
@@ -1003,13 +1004,14 @@ static DP_Tile *flatten_tile(DP_LayerContent *lc, int tile_index,
         DP_TransientTile *tt = DP_transient_tile_new(t, 0);
         DP_ViewModeContext vmc = DP_view_mode_context_make_default();
         DP_layer_list_flatten_tile_to(ll, lc->sub.props, tile_index, tt,
-                                      DP_BIT15, false, false, &vmc);
+                                      DP_BIT15, false, false, false, &vmc);
         return DP_transient_tile_persist(tt);
     }
     else {
         DP_ViewModeContext vmc = DP_view_mode_context_make_default();
         DP_TransientTile *tt_or_null = DP_layer_list_flatten_tile_to(
-            ll, lc->sub.props, tile_index, NULL, DP_BIT15, false, false, &vmc);
+            ll, lc->sub.props, tile_index, NULL, DP_BIT15, false, false, false,
+            &vmc);
         return tt_or_null ? DP_transient_tile_persist(tt_or_null) : NULL;
     }
 }
@@ -1051,14 +1053,26 @@ DP_Tile *DP_layer_content_flatten_tile(DP_LayerContent *lc, int tile_index,
 
 DP_TransientTile *DP_layer_content_flatten_tile_to(
     DP_LayerContent *lc, int tile_index, DP_TransientTile *tt_or_null,
-    uint16_t opacity, int blend_mode, bool censored, bool include_sublayers)
+    uint16_t opacity, int blend_mode, bool censored, bool include_sublayers,
+    bool linear)
 {
     DP_ASSERT(lc);
     DP_ASSERT(DP_atomic_get(&lc->refcount) > 0);
     DP_Tile *t = censored
                    ? flatten_censored_tile(lc, tile_index, include_sublayers)
                    : flatten_tile(lc, tile_index, include_sublayers);
-    if (t) {
+    if (t && linear) {
+        // The result is being blended in linear light, so the stored sRGB
+        // pixels have to be converted before merging them.
+        DP_TransientTile *ltt = DP_transient_tile_new(t, 0);
+        DP_tile_decref(t);
+        DP_pixels15_to_linear(DP_transient_tile_pixels(ltt), DP_TILE_LENGTH);
+        DP_TransientTile *tt = DP_transient_tile_merge_nullable(
+            tt_or_null, (DP_Tile *)ltt, opacity, blend_mode);
+        DP_transient_tile_decref(ltt);
+        return tt;
+    }
+    else if (t) {
         DP_TransientTile *tt = DP_transient_tile_merge_nullable(
             tt_or_null, t, opacity, blend_mode);
         DP_tile_decref(t);
@@ -1302,6 +1316,65 @@ DP_TransientLayerContent *DP_layer_content_resize(DP_LayerContent *lc,
     return tlc;
 }
 
+static void transform_tile(DP_TransientLayerContent *tlc, DP_Tile *t,
+                           unsigned int context_id, int transform, int width,
+                           int height, int tile_x, int tile_y)
+{
+    int left = tile_x * DP_TILE_SIZE;
+    int top = tile_y * DP_TILE_SIZE;
+    int tile_width = DP_min_int(DP_TILE_SIZE, width - left);
+    int tile_height = DP_min_int(DP_TILE_SIZE, height - top);
+    for (int y = 0; y < tile_height; ++y) {
+        for (int x = 0; x < tile_width; ++x) {
+            DP_Pixel15 pixel = DP_tile_pixel_at(t, x, y);
+            if (pixel.a != 0) {
+                int dst_x, dst_y;
+                DP_canvas_transform_point((DP_CanvasTransform)transform, width,
+                                          height, left + x, top + y, &dst_x,
+                                          &dst_y);
+                DP_transient_layer_content_pixel_at_set(tlc, context_id, dst_x,
+                                                        dst_y, pixel);
+            }
+        }
+    }
+}
+
+DP_TransientLayerContent *DP_layer_content_transform(DP_LayerContent *lc,
+                                                     unsigned int context_id,
+                                                     int transform)
+{
+    DP_ASSERT(lc);
+    DP_ASSERT(DP_atomic_get(&lc->refcount) > 0);
+
+    int width = lc->width;
+    int height = lc->height;
+    bool swap = DP_canvas_transform_swaps_dimensions(transform);
+    DP_TransientLayerContent *tlc = DP_transient_layer_content_new_init(
+        swap ? height : width, swap ? width : height, NULL);
+
+    DP_TileCounts counts = DP_tile_counts_round(width, height);
+    for (int tile_y = 0; tile_y < counts.y; ++tile_y) {
+        for (int tile_x = 0; tile_x < counts.x; ++tile_x) {
+            DP_Tile *t = lc->elements[tile_y * counts.x + tile_x].tile;
+            if (t) {
+                transform_tile(tlc, t, context_id, transform, width, height,
+                               tile_x, tile_y);
+            }
+        }
+    }
+
+    DP_LayerList *sub_ll = lc->sub.contents;
+    if (DP_layer_list_count(sub_ll) != 0) {
+        DP_layer_list_decref(tlc->sub.contents);
+        tlc->sub.transient_contents =
+            DP_layer_list_transform(sub_ll, context_id, transform);
+        DP_layer_props_list_decref(tlc->sub.props);
+        tlc->sub.props = DP_layer_props_list_incref(lc->sub.props);
+    }
+
+    return tlc;
+}
+
 bool DP_layer_content_has_sublayers(DP_LayerContent *lc)
 {
     DP_ASSERT(lc);
@@ -1734,6 +1807,16 @@ static int put_image_handle_pixel(int blend_mode, DP_Pixel8 pixel,
     }
 }
 
+// Setting a pixel to the value it already has doesn't need the tile to be made
+// transient, which would copy it and stop it from being shared.
+static bool put_image_pixel_unchanged(DP_Tile *tile_or_null, int x, int y,
+                                      DP_Pixel15 pixel)
+{
+    return tile_or_null
+             ? DP_pixel15_equal(DP_tile_pixel_at(tile_or_null, x, y), pixel)
+             : DP_pixel15_equal(DP_pixel15_zero(), pixel);
+}
+
 void DP_transient_layer_content_put_pixels(DP_TransientLayerContent *tlc,
                                            unsigned int context_id,
                                            int blend_mode, int left, int top,
@@ -1770,6 +1853,11 @@ void DP_transient_layer_content_put_pixels(DP_TransientLayerContent *tlc,
                     break;
                 case PUT_IMAGE_PIXEL_SET:
                     if (!tt) {
+                        if (put_image_pixel_unchanged(tlc->elements[i].tile,
+                                                      tidi.tile_x, tidi.tile_y,
+                                                      dst_pixel)) {
+                            break;
+                        }
                         tt = get_or_create_transient_tile(tlc, context_id, i);
                     }
                     DP_transient_tile_pixel_at_set(tt, tidi.tile_x, tidi.tile_y,
@@ -1854,9 +1942,14 @@ static void fill_entirely(DP_TransientLayerContent *tld,
         blend_mode == DP_BLEND_MODE_REPLACE
         || (blend_mode == DP_BLEND_MODE_NORMAL && pixel.a == DP_BIT15);
     if (is_replacement) {
-        DP_Tile *tile = DP_tile_new_from_upixel15(context_id, pixel);
         int tile_count = DP_tile_total_round(tld->width, tld->height);
-        DP_tile_incref_by(tile, tile_count - 1);
+        // Replacing with transparency just clears the layer.
+        DP_Tile *tile = pixel.a == 0
+                          ? NULL
+                          : DP_tile_new_from_upixel15(context_id, pixel);
+        if (tile) {
+            DP_tile_incref_by(tile, tile_count - 1);
+        }
         for (int i = 0; i < tile_count; ++i) {
             DP_tile_decref_nullable(tld->elements[i].tile);
             tld->elements[i].tile = tile;
@@ -144,6 +144,11 @@ DP_TransientLayerContent *DP_layer_content_resize(DP_LayerContent *lc,
                                                   int top, int right,
                                                   int bottom, int left);
 
+// Rotates or flips the pixels according to the given DP_CanvasTransform.
+DP_TransientLayerContent *DP_layer_content_transform(DP_LayerContent *lc,
+                                                     unsigned int context_id,
+                                                     int transform);
+
 bool DP_layer_content_has_sublayers(DP_LayerContent *lc);
 
 DP_LayerContent *DP_layer_content_merge_sublayers(DP_LayerContent *lc);
@@ -153,7 +158,8 @@ DP_Tile *DP_layer_content_flatten_tile(DP_LayerContent *lc, int tile_index,
 
 DP_TransientTile *DP_layer_content_flatten_tile_to(
     DP_LayerContent *lc, int tile_index, DP_TransientTile *tt_or_null,
-    uint16_t opacity, int blend_mode, bool censored, bool include_sublayers);
+    uint16_t opacity, int blend_mode, bool censored, bool include_sublayers,
+    bool linear);
 
 
 DP_TransientLayerContent *DP_transient_layer_content_new(DP_LayerContent *lc);
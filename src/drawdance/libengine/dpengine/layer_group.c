@@ -211,39 +211,7 @@ bool DP_layer_group_bounds(DP_LayerGroup *lg, DP_Rect *out_bounds)
 {
     DP_ASSERT(lg);
     DP_ASSERT(DP_atomic_get(&lg->refcount) > 0);
-    DP_Rect bounds;
-    bool have_bounds = false;
-
-    DP_LayerList *ll = lg->children;
-    int count = DP_layer_list_count(ll);
-    for (int i = 0; i < count; ++i) {
-        DP_Rect child_bounds;
-        bool have_child_bounds;
-        DP_LayerListEntry *lle = DP_layer_list_at_noinc(ll, i);
-        if (DP_layer_list_entry_is_group(lle)) {
-            have_child_bounds = DP_layer_group_bounds(
-                DP_layer_list_entry_group_noinc(lle), &child_bounds);
-        }
-        else {
-            have_child_bounds = DP_layer_content_bounds(
-                DP_layer_list_entry_content_noinc(lle), &child_bounds);
-        }
-
-        if (have_child_bounds) {
-            if (have_bounds) {
-                bounds = DP_rect_union(bounds, child_bounds);
-            }
-            else {
-                have_bounds = true;
-                bounds = child_bounds;
-            }
-        }
-    }
-
-    if (have_bounds && out_bounds) {
-        *out_bounds = bounds;
-    }
-    return have_bounds;
+    return DP_layer_list_bounds(lg->children, out_bounds);
 }
 
 int DP_layer_group_search_change_bounds(DP_LayerGroup *lg, DP_LayerProps *lp,
@@ -313,6 +281,40 @@ DP_TransientLayerGroup *DP_layer_group_resize(DP_LayerGroup *lg,
     return tlg;
 }
 
+DP_TransientLayerGroup *DP_layer_group_transform(DP_LayerGroup *lg,
+                                                 unsigned int context_id,
+                                                 int transform)
+{
+    DP_ASSERT(lg);
+    DP_ASSERT(DP_atomic_get(&lg->refcount) > 0);
+    bool swap = DP_canvas_transform_swaps_dimensions(transform);
+    int width = swap ? lg->height : lg->width;
+    int height = swap ? lg->width : lg->height;
+    DP_LayerList *ll = lg->children;
+    int count = DP_layer_list_count(ll);
+
+    DP_TransientLayerGroup *tlg =
+        DP_transient_layer_group_new_init(width, height, count);
+    DP_TransientLayerList *tll = tlg->transient_children;
+
+    for (int i = 0; i < count; ++i) {
+        DP_LayerListEntry *lle = DP_layer_list_at_noinc(ll, i);
+        if (DP_layer_list_entry_is_group(lle)) {
+            DP_TransientLayerGroup *ctlg = DP_layer_group_transform(
+                DP_layer_list_entry_group_noinc(lle), context_id, transform);
+            DP_transient_layer_list_insert_transient_group_noinc(tll, ctlg, i);
+        }
+        else {
+            DP_TransientLayerContent *ctlc = DP_layer_content_transform(
+                DP_layer_list_entry_content_noinc(lle), context_id, transform);
+            DP_transient_layer_list_insert_transient_content_noinc(tll, ctlc,
+                                                                   i);
+        }
+    }
+
+    return tlg;
+}
+
 DP_Pixel8 *DP_layer_group_to_pixels8(DP_LayerGroup *lg, DP_LayerProps *lp,
                                      int x, int y, int width, int height,
                                      bool reveal_censored)
@@ -396,14 +398,14 @@ DP_TransientTile *DP_layer_group_flatten_tile(DP_LayerGroup *lg,
     DP_ViewModeContext vmc = DP_view_mode_context_make_default();
     return DP_layer_list_flatten_tile_to(
         lg->children, DP_layer_props_children_noinc(lp), tile_index, NULL,
-        DP_BIT15, include_sublayers, false, &vmc);
+        DP_BIT15, include_sublayers, false, false, &vmc);
 }
 
 DP_TransientTile *
 DP_layer_group_flatten_tile_to(DP_LayerGroup *lg, DP_LayerProps *lp,
                                int tile_index, DP_TransientTile *tt_or_null,
                                uint16_t parent_opacity, bool include_sublayers,
-                               bool pass_through_censored,
+                               bool pass_through_censored, bool linear,
                                const DP_ViewModeContext *vmc)
 {
     DP_ASSERT(lg);
@@ -426,7 +428,7 @@ DP_layer_group_flatten_tile_to(DP_LayerGroup *lg, DP_LayerProps *lp,
         // merge the result with the group's blend mode and opacity.
         DP_TransientTile *gtt = DP_layer_list_flatten_tile_to(
             lg->children, lpl, tile_index, NULL, DP_BIT15, include_sublayers,
-            false, &vmr.child_vmc);
+            false, linear, &vmr.child_vmc);
         if (gtt) {
             DP_TransientTile *tt = DP_transient_tile_merge_nullable(
                 tt_or_null,
@@ -444,7 +446,7 @@ DP_layer_group_flatten_tile_to(DP_LayerGroup *lg, DP_LayerProps *lp,
         // mode, but taking the opacity into account individually.
         return DP_layer_list_flatten_tile_to(
             lg->children, lpl, tile_index, tt_or_null, vmr.opacity,
-            include_sublayers, censored, &vmr.child_vmc);
+            include_sublayers, censored, linear, &vmr.child_vmc);
     }
 }
 
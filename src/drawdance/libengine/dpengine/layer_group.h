@@ -82,6 +82,11 @@ DP_TransientLayerGroup *DP_layer_group_resize(DP_LayerGroup *lg,
                                               unsigned int context_id, int top,
                                               int right, int bottom, int left);
 
+// Applies a DP_CanvasTransform to every layer in the group.
+DP_TransientLayerGroup *DP_layer_group_transform(DP_LayerGroup *lg,
+                                                 unsigned int context_id,
+                                                 int transform);
+
 DP_Pixel8 *DP_layer_group_to_pixels8(DP_LayerGroup *lg, DP_LayerProps *lp,
                                      int x, int y, int width, int height,
                                      bool reveal_censored);
@@ -104,7 +109,7 @@ DP_TransientTile *
 DP_layer_group_flatten_tile_to(DP_LayerGroup *lg, DP_LayerProps *lp,
                                int tile_index, DP_TransientTile *tt_or_null,
                                uint16_t parent_opacity, bool include_sublayers,
-                               bool pass_through_censored,
+                               bool pass_through_censored, bool linear,
                                const DP_ViewModeContext *vmc);
 
 DP_TransientLayerGroup *DP_transient_layer_group_new(DP_LayerGroup *lg);
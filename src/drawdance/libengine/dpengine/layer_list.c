@@ -29,6 +29,7 @@
 #include <dpcommon/atomic.h>
 #include <dpcommon/common.h>
 #include <dpcommon/conversions.h>
+#include <dpcommon/geom.h>
 
 
 struct DP_LayerListEntry {
@@ -325,6 +326,44 @@ DP_LayerGroup *DP_layer_list_group_at_noinc(DP_LayerList *ll, int index)
     return DP_layer_list_entry_group_noinc(DP_layer_list_at_noinc(ll, index));
 }
 
+bool DP_layer_list_bounds(DP_LayerList *ll, DP_Rect *out_bounds)
+{
+    DP_ASSERT(ll);
+    DP_ASSERT(DP_atomic_get(&ll->refcount) > 0);
+    DP_Rect bounds;
+    bool have_bounds = false;
+
+    int count = ll->count;
+    for (int i = 0; i < count; ++i) {
+        DP_Rect child_bounds;
+        bool have_child_bounds;
+        DP_LayerListEntry *lle = &ll->elements[i];
+        if (DP_layer_list_entry_is_group(lle)) {
+            have_child_bounds = DP_layer_group_bounds(
+                DP_layer_list_entry_group_noinc(lle), &child_bounds);
+        }
+        else {
+            have_child_bounds = DP_layer_content_bounds(
+                DP_layer_list_entry_content_noinc(lle), &child_bounds);
+        }
+
+        if (have_child_bounds) {
+            if (have_bounds) {
+                bounds = DP_rect_union(bounds, child_bounds);
+            }
+            else {
+                have_bounds = true;
+                bounds = child_bounds;
+            }
+        }
+    }
+
+    if (have_bounds && out_bounds) {
+        *out_bounds = bounds;
+    }
+    return have_bounds;
+}
+
 DP_TransientLayerList *DP_layer_list_resize(DP_LayerList *ll,
                                             unsigned int context_id, int top,
                                             int right, int bottom, int left)
@@ -351,6 +390,32 @@ DP_TransientLayerList *DP_layer_list_resize(DP_LayerList *ll,
     return tll;
 }
 
+DP_TransientLayerList *DP_layer_list_transform(DP_LayerList *ll,
+                                               unsigned int context_id,
+                                               int transform)
+{
+    DP_ASSERT(ll);
+    DP_ASSERT(DP_atomic_get(&ll->refcount) > 0);
+    int count = ll->count;
+    DP_TransientLayerList *tll = allocate_layer_list(true, count);
+    for (int i = 0; i < count; ++i) {
+        DP_LayerListEntry *lle = &ll->elements[i];
+        if (lle->is_group) {
+            DP_TransientLayerGroup *tlg =
+                DP_layer_group_transform(lle->group, context_id, transform);
+            tll->elements[i] =
+                (DP_LayerListEntry){true, {.transient_group = tlg}};
+        }
+        else {
+            DP_TransientLayerContent *tlc =
+                DP_layer_content_transform(lle->content, context_id, transform);
+            tll->elements[i] =
+                (DP_LayerListEntry){false, {.transient_content = tlc}};
+        }
+    }
+    return tll;
+}
+
 void DP_layer_list_merge_to_flat_image(DP_LayerList *ll, DP_LayerPropsList *lpl,
                                        DP_TransientLayerContent *tlc,
                                        uint16_t parent_opacity,
@@ -400,12 +465,12 @@ void DP_layer_list_merge_to_flat_image(DP_LayerList *ll, DP_LayerPropsList *lpl,
 DP_TransientTile *DP_layer_list_entry_flatten_tile_to(
     DP_LayerListEntry *lle, DP_LayerProps *lp, int tile_index,
     DP_TransientTile *tt, uint16_t parent_opacity, bool include_sublayers,
-    bool pass_through_censored, const DP_ViewModeContext *vmc)
+    bool pass_through_censored, bool linear, const DP_ViewModeContext *vmc)
 {
     if (lle->is_group) {
-        return DP_layer_group_flatten_tile_to(lle->group, lp, tile_index, tt,
-                                              parent_opacity, include_sublayers,
-                                              pass_through_censored, vmc);
+        return DP_layer_group_flatten_tile_to(
+            lle->group, lp, tile_index, tt, parent_opacity, include_sublayers,
+            pass_through_censored, linear, vmc);
     }
     else if (DP_view_mode_context_should_flatten(vmc, lp, parent_opacity)) {
         uint16_t opacity =
@@ -414,7 +479,7 @@ DP_TransientTile *DP_layer_list_entry_flatten_tile_to(
         bool censored = pass_through_censored || DP_layer_props_censored(lp);
         return DP_layer_content_flatten_tile_to(lle->content, tile_index, tt,
                                                 opacity, blend_mode, censored,
-                                                include_sublayers);
+                                                include_sublayers, linear);
     }
     else {
         return tt;
@@ -425,7 +490,7 @@ DP_TransientTile *
 DP_layer_list_flatten_tile_to(DP_LayerList *ll, DP_LayerPropsList *lpl,
                               int tile_index, DP_TransientTile *tt_or_null,
                               uint16_t parent_opacity, bool include_sublayers,
-                              bool pass_through_censored,
+                              bool pass_through_censored, bool linear,
                               const DP_ViewModeContext *vmc)
 {
     DP_ASSERT(ll);
@@ -441,7 +506,7 @@ DP_layer_list_flatten_tile_to(DP_LayerList *ll, DP_LayerPropsList *lpl,
         DP_LayerProps *lp = DP_layer_props_list_at_noinc(lpl, i);
         tt = DP_layer_list_entry_flatten_tile_to(
             lle, lp, tile_index, tt, parent_opacity, include_sublayers,
-            pass_through_censored, vmc);
+            pass_through_censored, linear, vmc);
     }
     return tt;
 }
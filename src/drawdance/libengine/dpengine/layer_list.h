@@ -91,10 +91,19 @@ DP_LayerContent *DP_layer_list_content_at_noinc(DP_LayerList *ll, int index);
 
 DP_LayerGroup *DP_layer_list_group_at_noinc(DP_LayerList *ll, int index);
 
+// Union of the bounds of all non-transparent pixels of the layers in the list,
+// recursing into groups. Returns false if they're all blank.
+bool DP_layer_list_bounds(DP_LayerList *ll, DP_Rect *out_bounds);
+
 DP_TransientLayerList *DP_layer_list_resize(DP_LayerList *ll,
                                             unsigned int context_id, int top,
                                             int right, int bottom, int left);
 
+// Applies a DP_CanvasTransform to every layer in the list.
+DP_TransientLayerList *DP_layer_list_transform(DP_LayerList *ll,
+                                               unsigned int context_id,
+                                               int transform);
+
 
 void DP_layer_list_merge_to_flat_image(DP_LayerList *ll, DP_LayerPropsList *lpl,
                                        DP_TransientLayerContent *tlc,
@@ -106,13 +115,13 @@ void DP_layer_list_merge_to_flat_image(DP_LayerList *ll, DP_LayerPropsList *lpl,
 DP_TransientTile *DP_layer_list_entry_flatten_tile_to(
     DP_LayerListEntry *lle, DP_LayerProps *lp, int tile_index,
     DP_TransientTile *tt, uint16_t parent_opacity, bool include_sublayers,
-    bool pass_through_censored, const DP_ViewModeContext *vmc);
+    bool pass_through_censored, bool linear, const DP_ViewModeContext *vmc);
 
 DP_TransientTile *
 DP_layer_list_flatten_tile_to(DP_LayerList *ll, DP_LayerPropsList *lpl,
                               int tile_index, DP_TransientTile *tt_or_null,
                               uint16_t parent_opacity, bool include_sublayers,
-                              bool pass_through_censored,
+                              bool pass_through_censored, bool linear,
                               const DP_ViewModeContext *vmc);
 
 
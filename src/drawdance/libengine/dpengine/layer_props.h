@@ -23,6 +23,9 @@
 #define DPENGINE_LAYER_PROPS_H
 #include <dpcommon/common.h>
 
+// Longer layer titles get truncated, see DP_text_sanitize.
+#define DP_LAYER_TITLE_MAX_LENGTH 256
+
 
 #ifdef DP_NO_STRICT_ALIASING
 typedef struct DP_LayerProps DP_LayerProps;
@@ -0,0 +1,187 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "layer_thumbnails.h"
+#include "canvas_state.h"
+#include "image.h"
+#include "layer_content.h"
+#include "layer_routes.h"
+#include <dpcommon/common.h>
+#include <uthash_inc.h>
+
+
+typedef struct DP_LayerThumbnailEntry {
+    int layer_id;
+    int max_width, max_height;
+    DP_LayerContent *lc;
+    DP_Image *thumb;
+    UT_hash_handle hh;
+} DP_LayerThumbnailEntry;
+
+struct DP_LayerThumbnails {
+    DP_LayerThumbnailEntry *entries;
+};
+
+
+DP_LayerThumbnails *DP_layer_thumbnails_new(void)
+{
+    DP_LayerThumbnails *lt = DP_malloc(sizeof(*lt));
+    lt->entries = NULL;
+    return lt;
+}
+
+static void free_entry(DP_LayerThumbnailEntry *entry)
+{
+    DP_image_free(entry->thumb);
+    DP_layer_content_decref(entry->lc);
+    DP_free(entry);
+}
+
+void DP_layer_thumbnails_free(DP_LayerThumbnails *lt)
+{
+    if (lt) {
+        DP_LayerThumbnailEntry *entry, *tmp;
+        HASH_ITER(hh, lt->entries, entry, tmp) {
+            HASH_DEL(lt->entries, entry);
+            free_entry(entry);
+        }
+        DP_free(lt);
+    }
+}
+
+static DP_LayerContent *search_layer_content(DP_CanvasState *cs, int layer_id)
+{
+    DP_LayerRoutes *lr = DP_canvas_state_layer_routes_noinc(cs);
+    DP_LayerRoutesEntry *lre = DP_layer_routes_search(lr, layer_id);
+    if (!lre) {
+        DP_error_set("Layer thumbnail: id %d not found", layer_id);
+        return NULL;
+    }
+    else if (DP_layer_routes_entry_is_group(lre)) {
+        DP_error_set("Layer thumbnail: id %d is a group", layer_id);
+        return NULL;
+    }
+    else {
+        return DP_layer_routes_entry_content(lre, cs);
+    }
+}
+
+static DP_LayerThumbnailEntry *search_entry(DP_LayerThumbnails *lt,
+                                            int layer_id)
+{
+    DP_LayerThumbnailEntry *entry;
+    HASH_FIND_INT(lt->entries, &layer_id, entry);
+    return entry;
+}
+
+static bool entry_valid(DP_LayerThumbnailEntry *entry, DP_LayerContent *lc,
+                        int max_width, int max_height)
+{
+    return entry && entry->lc == lc && entry->max_width == max_width
+        && entry->max_height == max_height;
+}
+
+static DP_Image *make_thumbnail(DP_LayerContent *lc, DP_DrawContext *dc,
+                                int max_width, int max_height)
+{
+    DP_Image *img = DP_layer_content_to_image(lc);
+    if (!img) {
+        return NULL;
+    }
+
+    DP_Image *thumb;
+    if (DP_image_thumbnail(img, dc, max_width, max_height, &thumb)) {
+        if (thumb) {
+            DP_image_free(img);
+            return thumb;
+        }
+        else {
+            return img; // Already small enough.
+        }
+    }
+    else {
+        DP_image_free(img);
+        return NULL;
+    }
+}
+
+DP_Image *DP_layer_thumbnails_get(DP_LayerThumbnails *lt, DP_CanvasState *cs,
+                                  DP_DrawContext *dc, int layer_id,
+                                  int max_width, int max_height)
+{
+    DP_ASSERT(lt);
+    DP_ASSERT(cs);
+    DP_ASSERT(dc);
+    DP_ASSERT(max_width > 0);
+    DP_ASSERT(max_height > 0);
+    DP_LayerContent *lc = search_layer_content(cs, layer_id);
+    if (!lc) {
+        return NULL;
+    }
+
+    DP_LayerThumbnailEntry *entry = search_entry(lt, layer_id);
+    if (entry_valid(entry, lc, max_width, max_height)) {
+        return entry->thumb;
+    }
+
+    DP_Image *thumb = make_thumbnail(lc, dc, max_width, max_height);
+    if (!thumb) {
+        return NULL;
+    }
+
+    if (entry) {
+        DP_image_free(entry->thumb);
+        DP_layer_content_decref(entry->lc);
+    }
+    else {
+        entry = DP_malloc(sizeof(*entry));
+        entry->layer_id = layer_id;
+        HASH_ADD_INT(lt->entries, layer_id, entry);
+    }
+    entry->max_width = max_width;
+    entry->max_height = max_height;
+    entry->lc = DP_layer_content_incref(lc);
+    entry->thumb = thumb;
+    return thumb;
+}
+
+bool DP_layer_thumbnails_valid(DP_LayerThumbnails *lt, DP_CanvasState *cs,
+                               int layer_id, int max_width, int max_height)
+{
+    DP_ASSERT(lt);
+    DP_ASSERT(cs);
+    DP_LayerThumbnailEntry *entry = search_entry(lt, layer_id);
+    if (entry) {
+        DP_LayerRoutes *lr = DP_canvas_state_layer_routes_noinc(cs);
+        DP_LayerRoutesEntry *lre = DP_layer_routes_search(lr, layer_id);
+        return lre && !DP_layer_routes_entry_is_group(lre)
+            && entry_valid(entry, DP_layer_routes_entry_content(lre, cs),
+                           max_width, max_height);
+    }
+    else {
+        return false;
+    }
+}
+
+void DP_layer_thumbnails_remove(DP_LayerThumbnails *lt, int layer_id)
+{
+    DP_ASSERT(lt);
+    DP_LayerThumbnailEntry *entry = search_entry(lt, layer_id);
+    if (entry) {
+        HASH_DEL(lt->entries, entry);
+        free_entry(entry);
+    }
+}
+
+void DP_layer_thumbnails_prune(DP_LayerThumbnails *lt, DP_CanvasState *cs)
+{
+    DP_ASSERT(lt);
+    DP_ASSERT(cs);
+    DP_LayerRoutes *lr = DP_canvas_state_layer_routes_noinc(cs);
+    DP_LayerThumbnailEntry *entry, *tmp;
+    HASH_ITER(hh, lt->entries, entry, tmp) {
+        DP_LayerRoutesEntry *lre = DP_layer_routes_search(lr, entry->layer_id);
+        if (!lre || DP_layer_routes_entry_is_group(lre)) {
+            HASH_DEL(lt->entries, entry);
+            free_entry(entry);
+        }
+    }
+}
@@ -0,0 +1,41 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#ifndef DPENGINE_LAYER_THUMBNAILS_H
+#define DPENGINE_LAYER_THUMBNAILS_H
+#include <dpcommon/common.h>
+
+typedef struct DP_CanvasState DP_CanvasState;
+typedef struct DP_DrawContext DP_DrawContext;
+typedef struct DP_Image DP_Image;
+
+
+// Cache of layer thumbnails, keyed by layer id. Layer contents are immutable,
+// so a thumbnail stays valid as long as its layer still has the same content
+// it was generated from. Drawing on a layer gives it new content and thereby
+// invalidates its thumbnail, but not those of other layers.
+typedef struct DP_LayerThumbnails DP_LayerThumbnails;
+
+DP_LayerThumbnails *DP_layer_thumbnails_new(void);
+
+void DP_layer_thumbnails_free(DP_LayerThumbnails *lt);
+
+// Returns a thumbnail of the given layer that fits into the maximum
+// dimensions, regenerating it only if it's not valid anymore. The image
+// belongs to the cache and stays valid until that layer's thumbnail gets
+// regenerated or removed. Returns NULL and sets an error if the layer doesn't
+// exist or is a group.
+DP_Image *DP_layer_thumbnails_get(DP_LayerThumbnails *lt, DP_CanvasState *cs,
+                                  DP_DrawContext *dc, int layer_id,
+                                  int max_width, int max_height);
+
+// Whether a thumbnail with the given dimensions for the layer is cached and
+// still matches the layer's current content.
+bool DP_layer_thumbnails_valid(DP_LayerThumbnails *lt, DP_CanvasState *cs,
+                               int layer_id, int max_width, int max_height);
+
+void DP_layer_thumbnails_remove(DP_LayerThumbnails *lt, int layer_id);
+
+// Removes thumbnails of all layers that don't exist in the canvas anymore.
+void DP_layer_thumbnails_prune(DP_LayerThumbnails *lt, DP_CanvasState *cs);
+
+
+#endif
@@ -43,7 +43,9 @@
 #include <dpcommon/common.h>
 #include <dpcommon/conversions.h>
 #include <dpcommon/geom.h>
+#include <dpmsg/acl.h>
 #include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
 
 
 DP_CanvasState *DP_ops_canvas_resize(DP_CanvasState *cs,
@@ -95,34 +97,35 @@ DP_CanvasState *DP_ops_canvas_resize(DP_CanvasState *cs,
 }
 
 
-static void mark_used_layer_ids(DP_DrawContext *dc, int masked_id,
+static void mark_used_layer_ids(DP_DrawContext *dc, uint8_t creator_id,
                                 DP_LayerPropsList *lpl)
 {
     int count = DP_layer_props_list_count(lpl);
     for (int i = 0; i < count; ++i) {
         DP_LayerProps *lp = DP_layer_props_list_at_noinc(lpl, i);
         int layer_id = DP_layer_props_id(lp);
-        if ((layer_id & 0xff00) == masked_id) {
-            DP_draw_context_id_generator_mark_used(dc, layer_id & 0xff);
+        if (DP_layer_id_creator(layer_id) == creator_id) {
+            DP_draw_context_id_generator_mark_used(
+                dc, DP_layer_id_index(layer_id));
         }
         DP_LayerPropsList *child_lpl = DP_layer_props_children_noinc(lp);
         if (child_lpl) {
-            mark_used_layer_ids(dc, masked_id, child_lpl);
+            mark_used_layer_ids(dc, creator_id, child_lpl);
         }
     }
 }
 
 static DP_TransientLayerPropsList *
-clone_layer_props_list(DP_DrawContext *dc, int masked_id,
+clone_layer_props_list(DP_DrawContext *dc, uint8_t creator_id,
                        DP_LayerPropsList *lpl);
 
 static DP_TransientLayerProps *
-clone_layer_props(DP_DrawContext *dc, int masked_id, DP_LayerProps *lp)
+clone_layer_props(DP_DrawContext *dc, uint8_t creator_id, DP_LayerProps *lp)
 {
     DP_LayerPropsList *child_lpl = DP_layer_props_children_noinc(lp);
     if (child_lpl) {
         DP_TransientLayerPropsList *child_tlpl =
-            clone_layer_props_list(dc, masked_id, child_lpl);
+            clone_layer_props_list(dc, creator_id, child_lpl);
         if (child_tlpl) {
             return DP_transient_layer_props_new_with_children_noinc(lp,
                                                                     child_tlpl);
@@ -137,7 +140,7 @@ clone_layer_props(DP_DrawContext *dc, int masked_id, DP_LayerProps *lp)
 }
 
 static DP_TransientLayerPropsList *
-clone_layer_props_list(DP_DrawContext *dc, int masked_id,
+clone_layer_props_list(DP_DrawContext *dc, uint8_t creator_id,
                        DP_LayerPropsList *lpl)
 {
     int count = DP_layer_props_list_count(lpl);
@@ -147,10 +150,11 @@ clone_layer_props_list(DP_DrawContext *dc, int masked_id,
         int base_id = DP_draw_context_id_generator_next(dc);
         DP_TransientLayerProps *tlp;
         if (base_id != -1
-            && (tlp = clone_layer_props(dc, masked_id,
+            && (tlp = clone_layer_props(dc, creator_id,
                                         DP_layer_props_list_at_noinc(lpl, i)))
                    != NULL) {
-            DP_transient_layer_props_id_set(tlp, base_id | masked_id);
+            DP_transient_layer_props_id_set(
+                tlp, DP_layer_id_make(creator_id, DP_int_to_uint8(base_id)));
             DP_transient_layer_props_list_set_noinc(tlpl, (DP_LayerProps *)tlp,
                                                     i);
         }
@@ -221,12 +225,19 @@ DP_CanvasState *DP_ops_layer_tree_create(DP_CanvasState *cs, DP_DrawContext *dc,
         DP_LayerPropsList *source_child_lpl =
             DP_layer_props_children_noinc(source_lp);
         if (source_child_lpl) {
-            DP_draw_context_id_generator_reset(dc, layer_id & 0xff);
-            int masked_id = layer_id & 0xff00;
-            mark_used_layer_ids(dc, masked_id,
+            DP_draw_context_id_generator_reset(dc,
+                                               DP_layer_id_index(layer_id));
+            uint8_t creator_id = DP_layer_id_creator(layer_id);
+            mark_used_layer_ids(dc, creator_id,
                                 DP_canvas_state_layer_props_noinc(cs));
+            if (creator_id == 0) {
+                // Neither of these are valid layer ids, don't hand them out.
+                DP_draw_context_id_generator_mark_used(dc, 0);
+                DP_draw_context_id_generator_mark_used(
+                    dc, DP_ACL_DEFAULT_LAYER_ID);
+            }
             DP_TransientLayerPropsList *tlpl =
-                clone_layer_props_list(dc, masked_id, source_child_lpl);
+                clone_layer_props_list(dc, creator_id, source_child_lpl);
             if (tlpl) {
                 tlp = DP_transient_layer_props_new_with_children_noinc(
                     source_lp, tlpl);
@@ -589,6 +600,46 @@ DP_CanvasState *DP_ops_layer_tree_move(DP_CanvasState *cs, DP_DrawContext *dc,
     return DP_transient_canvas_state_persist(tcs);
 }
 
+DP_CanvasState *DP_ops_layer_move_to_index(DP_CanvasState *cs,
+                                           DP_DrawContext *dc, int layer_id,
+                                           int index)
+{
+    DP_LayerRoutes *lr = DP_canvas_state_layer_routes_noinc(cs);
+    DP_LayerRoutesEntry *lre = DP_layer_routes_search(lr, layer_id);
+    if (!lre) {
+        DP_error_set("Layer move to index: id %d not found", layer_id);
+        return NULL;
+    }
+
+    DP_LayerRoutesEntry *parent_lre = DP_layer_routes_entry_parent(lre);
+    DP_LayerPropsList *lpl;
+    if (parent_lre) {
+        DP_LayerList *ll;
+        DP_layer_routes_entry_children(parent_lre, cs, &ll, &lpl);
+    }
+    else {
+        lpl = DP_canvas_state_layer_props_noinc(cs);
+    }
+
+    int count = DP_layer_props_list_count(lpl);
+    int from_index = DP_layer_routes_entry_index_last(lre);
+    int to_index = DP_clamp_int(index, 0, count - 1);
+    if (from_index == to_index) {
+        return DP_canvas_state_incref(cs);
+    }
+
+    // A tree move inserts the layer below the given sibling after taking the
+    // layer out, so the sibling is one further up when moving upwards.
+    int sibling_index = to_index < from_index ? to_index : to_index + 1;
+    int sibling_id = 0;
+    if (sibling_index < count) {
+        DP_LayerProps *lp = DP_layer_props_list_at_noinc(lpl, sibling_index);
+        sibling_id = DP_layer_props_id(lp);
+    }
+    int parent_id = parent_lre ? DP_layer_routes_entry_layer_id(parent_lre) : 0;
+    return DP_ops_layer_tree_move(cs, dc, layer_id, parent_id, sibling_id);
+}
+
 
 DP_CanvasState *DP_ops_layer_retitle(DP_CanvasState *cs, int layer_id,
                                      const char *title, size_t title_length)
@@ -1049,9 +1100,10 @@ DP_CanvasState *DP_ops_move_region(DP_CanvasState *cs, DP_DrawContext *dc,
                                    DP_Image *mask)
 {
     if (src_layer_id == 0) {
-        return move_region_selection(cs, dc, context_id, dst_layer_id & 0xff,
-                                     (dst_layer_id >> 8) & 0xff, src_rect,
-                                     dst_quad, interpolation, mask);
+        return move_region_selection(
+            cs, dc, context_id, DP_layer_id_index(dst_layer_id),
+            DP_layer_id_creator(dst_layer_id), src_rect, dst_quad,
+            interpolation, mask);
     }
     else {
         return move_region_layer(cs, dc, ucs_or_null, context_id, src_layer_id,
@@ -1154,9 +1206,10 @@ DP_CanvasState *DP_ops_move_rect(DP_CanvasState *cs,
                                  int dst_x, int dst_y, DP_Image *mask)
 {
     if (src_layer_id == 0) {
-        return move_rect_selection(cs, context_id, dst_layer_id & 0xff,
-                                   (dst_layer_id >> 8) & 0xff, src_rect, dst_x,
-                                   dst_y, mask);
+        return move_rect_selection(cs, context_id,
+                                   DP_layer_id_index(dst_layer_id),
+                                   DP_layer_id_creator(dst_layer_id), src_rect,
+                                   dst_x, dst_y, mask);
     }
     else {
         return move_rect_layer(cs, ucs_or_null, context_id, src_layer_id,
@@ -1392,6 +1445,62 @@ DP_CanvasState *DP_ops_annotation_edit(DP_CanvasState *cs, int annotation_id,
     return DP_transient_canvas_state_persist(tcs);
 }
 
+static bool is_utf8_continuation(const char *text, size_t length, size_t i)
+{
+    return i < length && (((unsigned char)text[i]) & 0xc0) == 0x80;
+}
+
+DP_CanvasState *DP_ops_annotation_text_edit(DP_CanvasState *cs,
+                                            int annotation_id, size_t offset,
+                                            size_t count, const char *text,
+                                            size_t text_length)
+{
+    DP_AnnotationList *al = DP_canvas_state_annotations_noinc(cs);
+    int index = DP_annotation_list_index_by_id(al, annotation_id);
+    if (index < 0) {
+        DP_error_set("Annotation text edit: id %d not found", annotation_id);
+        return NULL;
+    }
+
+    size_t old_length;
+    const char *old_text = DP_annotation_text(
+        DP_annotation_list_at_noinc(al, index), &old_length);
+    size_t start = DP_min_size(offset, old_length);
+    size_t end = start + DP_min_size(count, old_length - start);
+    while (is_utf8_continuation(old_text, old_length, start)) {
+        --start;
+    }
+    if (count == 0) {
+        end = start;
+    }
+    else {
+        while (is_utf8_continuation(old_text, old_length, end)) {
+            ++end;
+        }
+    }
+
+    if (start == end && text_length == 0) {
+        return DP_canvas_state_incref(cs);
+    }
+
+    size_t new_length = old_length - (end - start) + text_length;
+    char *new_text = DP_malloc(new_length + 1);
+    memcpy(new_text, old_text, start);
+    memcpy(new_text + start, text, text_length);
+    memcpy(new_text + start + text_length, old_text + end, old_length - end);
+    new_text[new_length] = '\0';
+
+    DP_TransientCanvasState *tcs = DP_transient_canvas_state_new(cs);
+    DP_TransientAnnotationList *tal =
+        DP_transient_canvas_state_transient_annotations(tcs, 0);
+    DP_TransientAnnotation *ta =
+        DP_transient_annotation_list_transient_at_noinc(tal, index);
+    DP_transient_annotation_text_set(ta, new_text, new_length);
+    DP_free(new_text);
+
+    return DP_transient_canvas_state_persist(tcs);
+}
+
 DP_CanvasState *DP_ops_annotation_delete(DP_CanvasState *cs, int annotation_id)
 {
     DP_AnnotationList *al = DP_canvas_state_annotations_noinc(cs);
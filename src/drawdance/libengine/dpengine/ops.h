@@ -63,6 +63,10 @@ DP_CanvasState *DP_ops_layer_tree_move(DP_CanvasState *cs, DP_DrawContext *dc,
                                        int layer_id, int parent_id,
                                        int sibling_id);
 
+DP_CanvasState *DP_ops_layer_move_to_index(DP_CanvasState *cs,
+                                           DP_DrawContext *dc, int layer_id,
+                                           int index);
+
 DP_CanvasState *DP_ops_layer_retitle(DP_CanvasState *cs, int layer_id,
                                      const char *title, size_t title_length);
 
@@ -121,6 +125,14 @@ DP_CanvasState *DP_ops_annotation_edit(DP_CanvasState *cs, int annotation_id,
                                        int valign, const char *text,
                                        size_t text_length);
 
+// Replaces count bytes of the annotation's text at the given offset with the
+// given text. The range is clamped to the text and widened to not split any
+// UTF-8 sequences, so out of range edits end up at the end of the text.
+DP_CanvasState *DP_ops_annotation_text_edit(DP_CanvasState *cs,
+                                            int annotation_id, size_t offset,
+                                            size_t count, const char *text,
+                                            size_t text_length);
+
 DP_CanvasState *DP_ops_annotation_delete(DP_CanvasState *cs, int annotation_id);
 
 DP_CanvasState *DP_ops_draw_dabs(DP_CanvasState *cs, DP_DrawContext *dc,
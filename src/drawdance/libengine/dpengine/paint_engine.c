@@ -695,6 +695,9 @@ DP_PaintEngine *DP_paint_engine_new_inc(
     pe->ch = DP_canvas_history_new_inc(
         cs_or_null, save_point_fn, save_point_user, want_canvas_history_dump,
         canvas_history_dump_dir);
+    DP_canvas_history_compact_undo_points_set(
+        pe->ch,
+        !player_or_null || DP_player_compact_undo_points(player_or_null));
     pe->soft_reset.fn = soft_reset_fn;
     pe->soft_reset.user = soft_reset_user;
     pe->diff = DP_canvas_diff_new();
@@ -846,6 +849,19 @@ void DP_paint_engine_want_canvas_history_dump_set(DP_PaintEngine *pe,
     DP_canvas_history_want_dump_set(pe->ch, want_canvas_history_dump);
 }
 
+bool DP_paint_engine_compact_undo_points(DP_PaintEngine *pe)
+{
+    DP_ASSERT(pe);
+    return DP_canvas_history_compact_undo_points(pe->ch);
+}
+
+void DP_paint_engine_compact_undo_points_set(DP_PaintEngine *pe,
+                                             bool compact_undo_points)
+{
+    DP_ASSERT(pe);
+    DP_canvas_history_compact_undo_points_set(pe->ch, compact_undo_points);
+}
+
 
 bool DP_paint_engine_local_state_reset_image_build(
     DP_PaintEngine *pe, DP_LocalStateAcceptResetMessageFn fn, void *user)
@@ -126,6 +126,14 @@ bool DP_paint_engine_want_canvas_history_dump(DP_PaintEngine *pe);
 void DP_paint_engine_want_canvas_history_dump_set(
     DP_PaintEngine *pe, bool want_canvas_history_dump);
 
+// See DP_canvas_history_compact_undo_points. On by default, except when
+// playing back a recording from before dp:4.25, see
+// DP_player_compact_undo_points.
+bool DP_paint_engine_compact_undo_points(DP_PaintEngine *pe);
+
+void DP_paint_engine_compact_undo_points_set(DP_PaintEngine *pe,
+                                             bool compact_undo_points);
+
 bool DP_paint_engine_local_state_reset_image_build(
     DP_PaintEngine *pe, DP_LocalStateAcceptResetMessageFn fn, void *user);
 
@@ -726,6 +726,73 @@ DP_Pixel15 DP_pixel15_premultiply(DP_UPixel15 pixel)
     }
 }
 
+
+uint16_t DP_channel15_to_linear(uint16_t c)
+{
+    float f = DP_channel15_to_float(c);
+    return DP_channel_float_to_15(f <= 0.04045f
+                                      ? f / 12.92f
+                                      : powf((f + 0.055f) / 1.055f, 2.4f));
+}
+
+uint16_t DP_channel15_to_srgb(uint16_t c)
+{
+    float f = DP_channel15_to_float(c);
+    return DP_channel_float_to_15(f <= 0.0031308f
+                                      ? f * 12.92f
+                                      : 1.055f * powf(f, 1.0f / 2.4f) - 0.055f);
+}
+
+DP_UPixel15 DP_upixel15_to_linear(DP_UPixel15 pixel)
+{
+    return (DP_UPixel15){
+        .b = DP_channel15_to_linear(pixel.b),
+        .g = DP_channel15_to_linear(pixel.g),
+        .r = DP_channel15_to_linear(pixel.r),
+        .a = pixel.a,
+    };
+}
+
+DP_UPixel15 DP_upixel15_to_srgb(DP_UPixel15 pixel)
+{
+    return (DP_UPixel15){
+        .b = DP_channel15_to_srgb(pixel.b),
+        .g = DP_channel15_to_srgb(pixel.g),
+        .r = DP_channel15_to_srgb(pixel.r),
+        .a = pixel.a,
+    };
+}
+
+DP_Pixel15 DP_pixel15_to_linear(DP_Pixel15 pixel)
+{
+    return pixel.a == 0 ? pixel
+                        : DP_pixel15_premultiply(DP_upixel15_to_linear(
+                            DP_pixel15_unpremultiply(pixel)));
+}
+
+DP_Pixel15 DP_pixel15_to_srgb(DP_Pixel15 pixel)
+{
+    return pixel.a == 0 ? pixel
+                        : DP_pixel15_premultiply(DP_upixel15_to_srgb(
+                            DP_pixel15_unpremultiply(pixel)));
+}
+
+void DP_pixels15_to_linear(DP_Pixel15 *pixels, int count)
+{
+    DP_ASSERT(count <= 0 || pixels);
+    for (int i = 0; i < count; ++i) {
+        pixels[i] = DP_pixel15_to_linear(pixels[i]);
+    }
+}
+
+void DP_pixels15_to_srgb(DP_Pixel15 *pixels, int count)
+{
+    DP_ASSERT(count <= 0 || pixels);
+    for (int i = 0; i < count; ++i) {
+        pixels[i] = DP_pixel15_to_srgb(pixels[i]);
+    }
+}
+
 #ifdef DP_CPU_X64
 DP_TARGET_BEGIN("sse4.2")
 static void shuffle_load_sse42(__m128i source1, __m128i source2,
@@ -29,7 +29,12 @@
 
 #define DP_BIT15 (1 << 15)
 
-#define DP_TILE_SIZE   64
+// Can be overridden at compile time through the TILE_SIZE CMake option. Must
+// be a power of two between 32 and 256. PutTile messages and the compressed
+// tile format depend on it, so everyone in a session must use the same size.
+#ifndef DP_TILE_SIZE
+#    define DP_TILE_SIZE 64
+#endif
 #define DP_TILE_LENGTH (DP_TILE_SIZE * DP_TILE_SIZE)
 
 // Premultiplied 8 bit pixel
@@ -120,6 +125,18 @@ DP_UPixel15 DP_pixel15_unpremultiply(DP_Pixel15 pixel);
 DP_Pixel8 DP_pixel8_premultiply(DP_UPixel8 pixel);
 DP_Pixel15 DP_pixel15_premultiply(DP_UPixel15 pixel);
 
+// Conversions between the sRGB transfer function that colors are stored and
+// normally blended in and linear light. The alpha channel is left alone, pixels
+// that are premultiplied get unpremultiplied for the conversion.
+uint16_t DP_channel15_to_linear(uint16_t c);
+uint16_t DP_channel15_to_srgb(uint16_t c);
+DP_UPixel15 DP_upixel15_to_linear(DP_UPixel15 pixel);
+DP_UPixel15 DP_upixel15_to_srgb(DP_UPixel15 pixel);
+DP_Pixel15 DP_pixel15_to_linear(DP_Pixel15 pixel);
+DP_Pixel15 DP_pixel15_to_srgb(DP_Pixel15 pixel);
+void DP_pixels15_to_linear(DP_Pixel15 *pixels, int count);
+void DP_pixels15_to_srgb(DP_Pixel15 *pixels, int count);
+
 
 DP_INLINE DP_Pixel15 DP_pixel15_zero(void)
 {
@@ -21,9 +21,11 @@
  */
 #include "player.h"
 #include "canvas_history.h"
+#include "canvas_state.h"
 #include "dump_reader.h"
 #include "image.h"
 #include "local_state.h"
+#include "tile.h"
 #include <dpcommon/binary.h>
 #include <dpcommon/common.h>
 #include <dpcommon/conversions.h>
@@ -47,6 +49,14 @@ typedef union DP_PlayerReader {
     DP_DumpReader *dump;
 } DP_PlayerReader;
 
+typedef struct DP_PlayerOffset {
+    bool enabled;
+    int dx, dy;
+    int id_offset;
+    size_t capacity;
+    unsigned char *buffer;
+} DP_PlayerOffset;
+
 struct DP_Player {
     char *recording_path;
     char *index_path;
@@ -60,6 +70,7 @@ struct DP_Player {
     bool input_error;
     bool end;
     DP_PlayerIndex index;
+    DP_PlayerOffset offset;
 };
 
 
@@ -160,7 +171,8 @@ static DP_Player *make_player(DP_PlayerType type, char *recording_path,
                           false,
                           false,
                           false,
-                          {DP_BUFFERED_INPUT_NULL, 0, NULL, 0}};
+                          {DP_BUFFERED_INPUT_NULL, 0, NULL, 0},
+                          {false, 0, 0, 0, 0, NULL}};
     return player;
 }
 
@@ -243,7 +255,8 @@ static DP_Player *new_debug_dump_player(DP_Input *input)
                           false,
                           false,
                           false,
-                          {DP_BUFFERED_INPUT_NULL, 0, NULL, 0}};
+                          {DP_BUFFERED_INPUT_NULL, 0, NULL, 0},
+                          {false, 0, 0, 0, 0, NULL}};
     return player;
 }
 
@@ -320,6 +333,7 @@ void DP_player_free(DP_Player *player)
             break;
         }
         DP_protocol_version_free(player->protover);
+        DP_free(player->offset.buffer);
         DP_free(player->index_path);
         DP_free(player->recording_path);
         DP_free(player);
@@ -385,12 +399,31 @@ bool DP_player_compatible(DP_Player *player)
     }
 }
 
+bool DP_player_compact_undo_points(DP_Player *player)
+{
+    DP_ASSERT(player);
+    // Debug dumps come from live sessions, which compact undo points.
+    return player->type == DP_PLAYER_TYPE_DEBUG_DUMP
+        || DP_player_compatibility(player) == DP_PLAYER_COMPATIBLE;
+}
+
 void DP_player_acl_override_set(DP_Player *player, bool override)
 {
     DP_ASSERT(player);
     player->acl_override = override;
 }
 
+void DP_player_offset_set(DP_Player *player, int dx, int dy, int id_offset)
+{
+    DP_ASSERT(player);
+    DP_ASSERT(id_offset > -UINT16_MAX);
+    DP_ASSERT(id_offset < UINT16_MAX);
+    player->offset.enabled = dx != 0 || dy != 0 || id_offset != 0;
+    player->offset.dx = dx;
+    player->offset.dy = dy;
+    player->offset.id_offset = id_offset;
+}
+
 const char *DP_player_recording_path(DP_Player *player)
 {
     DP_ASSERT(player);
@@ -537,6 +570,10 @@ static bool emit_message(DP_Message *msg, DP_Message **out_msg)
     case DP_MSG_USER_ACL:
     case DP_MSG_FEATURE_ACCESS_LEVELS:
     case DP_MSG_DATA:
+    case DP_MSG_LAYER_ACL_EXCLUSIVE:
+    case DP_MSG_CLIENT_META:
+    case DP_MSG_COSMETIC_MUTE:
+    case DP_MSG_SESSION_LOCK_CATEGORIES:
         return false;
     // Layer ACL messages have a tier, which we want to retain, and a user
     // component, which we do not. So extract the former and leave the latter.
@@ -554,6 +591,256 @@ static bool emit_message(DP_Message *msg, DP_Message **out_msg)
     }
 }
 
+static bool offset_id_at(DP_PlayerOffset *po, unsigned char *body,
+                         size_t length, size_t offset)
+{
+    if (offset + 2 <= length) {
+        int id = DP_read_bigendian_uint16(body + offset);
+        // Zero means no layer, like the root group or a missing source.
+        if (id != 0) {
+            int offset_id = id + po->id_offset;
+            if (offset_id <= 0 || offset_id > UINT16_MAX) {
+                DP_error_set("Id 0x%x out of range when offset by %d", id,
+                             po->id_offset);
+                return false;
+            }
+            DP_write_bigendian_uint16((uint16_t)offset_id, body + offset);
+        }
+    }
+    return true;
+}
+
+static bool offset_ids_from(DP_PlayerOffset *po, unsigned char *body,
+                            size_t length, size_t offset, size_t stride)
+{
+    for (size_t i = offset; i + 2 <= length; i += stride) {
+        if (!offset_id_at(po, body, length, i)) {
+            return false;
+        }
+    }
+    return true;
+}
+
+static bool offset_coordinate_error(long long value)
+{
+    DP_error_set("Coordinate %lld out of range after offset", value);
+    return false;
+}
+
+static bool offset_int32_at(unsigned char *body, size_t length, size_t offset,
+                            long long delta)
+{
+    if (offset + 4 <= length) {
+        long long value = DP_read_bigendian_int32(body + offset) + delta;
+        if (value < INT32_MIN || value > INT32_MAX) {
+            return offset_coordinate_error(value);
+        }
+        DP_write_bigendian_int32((int32_t)value, body + offset);
+    }
+    return true;
+}
+
+static bool offset_uint32_at(unsigned char *body, size_t length,
+                             size_t offset, long long delta)
+{
+    if (offset + 4 <= length) {
+        long long value = DP_read_bigendian_uint32(body + offset) + delta;
+        if (value < 0 || value > UINT32_MAX) {
+            return offset_coordinate_error(value);
+        }
+        DP_write_bigendian_uint32((uint32_t)value, body + offset);
+    }
+    return true;
+}
+
+static bool offset_point_at(DP_PlayerOffset *po, unsigned char *body,
+                            size_t length, size_t offset, int scale)
+{
+    return offset_int32_at(body, length, offset, (long long)po->dx * scale)
+        && offset_int32_at(body, length, offset + 4,
+                           (long long)po->dy * scale);
+}
+
+// Quadrilaterals of region moves and transforms are four points in a row.
+static bool offset_quad_at(DP_PlayerOffset *po, unsigned char *body,
+                           size_t length, size_t offset)
+{
+    for (size_t i = 0; i < 4; ++i) {
+        if (!offset_point_at(po, body, length, offset + i * 8, 1)) {
+            return false;
+        }
+    }
+    return true;
+}
+
+// Tiles can only be shifted by whole tiles. Repeated tiles wrap around at the
+// edge of the canvas, which is a different one after the offset, so they
+// can't be shifted at all unless they stay where they are.
+static bool offset_put_tile(DP_PlayerOffset *po, unsigned char *body,
+                            size_t length)
+{
+    if (po->dx == 0 && po->dy == 0) {
+        return true;
+    }
+    else if (po->dx % DP_TILE_SIZE != 0 || po->dy % DP_TILE_SIZE != 0) {
+        DP_error_set("Can't offset tile by %d, %d, not a multiple of %d",
+                     po->dx, po->dy, DP_TILE_SIZE);
+        return false;
+    }
+    else if (length < 9 || DP_read_bigendian_uint16(body + 7) != 0) {
+        DP_error_set("Can't offset repeated tile");
+        return false;
+    }
+
+    int col = DP_read_bigendian_uint16(body + 3) + po->dx / DP_TILE_SIZE;
+    int row = DP_read_bigendian_uint16(body + 5) + po->dy / DP_TILE_SIZE;
+    if (col < 0 || col > UINT16_MAX || row < 0 || row > UINT16_MAX) {
+        DP_error_set("Tile %d, %d out of range after offset", col, row);
+        return false;
+    }
+    DP_write_bigendian_uint16((uint16_t)col, body + 3);
+    DP_write_bigendian_uint16((uint16_t)row, body + 5);
+    return true;
+}
+
+static bool offset_body(DP_PlayerOffset *po, DP_MessageType type,
+                        unsigned char *body, size_t length)
+{
+    switch (type) {
+    case DP_MSG_MOVE_POINTER:
+        // Like classic dabs, the pointer position is in quarter pixels.
+        return offset_point_at(po, body, length, 0, 4);
+    case DP_MSG_LAYER_ACL:
+        // The default layer ACL pseudo-layer stays where it is.
+        return (length >= 2
+                && DP_read_bigendian_uint16(body) == DP_ACL_DEFAULT_LAYER_ID)
+            || offset_id_at(po, body, length, 0);
+    case DP_MSG_DEFAULT_LAYER:
+    case DP_MSG_LAYER_ATTRIBUTES:
+    case DP_MSG_LAYER_RETITLE:
+    case DP_MSG_LAYER_DELETE:
+    case DP_MSG_LAYER_VISIBILITY:
+    case DP_MSG_ANNOTATION_EDIT:
+    case DP_MSG_ANNOTATION_TEXT_EDIT:
+    case DP_MSG_ANNOTATION_DELETE:
+    case DP_MSG_TRACK_RETITLE:
+    case DP_MSG_TRACK_DELETE:
+    case DP_MSG_KEY_FRAME_RETITLE:
+    case DP_MSG_LAYER_ORDER_MOVE:
+        return offset_id_at(po, body, length, 0);
+    case DP_MSG_PUT_IMAGE:
+    case DP_MSG_FILL_RECT:
+        return offset_id_at(po, body, length, 0)
+            && offset_uint32_at(body, length, 3, po->dx)
+            && offset_uint32_at(body, length, 7, po->dy);
+    case DP_MSG_ANNOTATION_CREATE:
+    case DP_MSG_ANNOTATION_RESHAPE:
+    case DP_MSG_DRAW_DABS_PIXEL:
+    case DP_MSG_DRAW_DABS_PIXEL_SQUARE:
+        return offset_id_at(po, body, length, 0)
+            && offset_point_at(po, body, length, 2, 1);
+    case DP_MSG_DRAW_DABS_CLASSIC:
+    case DP_MSG_DRAW_DABS_MYPAINT:
+        // These have their coordinates in quarter pixels.
+        return offset_id_at(po, body, length, 0)
+            && offset_point_at(po, body, length, 2, 4);
+    case DP_MSG_PUT_TILE:
+        return offset_id_at(po, body, length, 0)
+            && offset_put_tile(po, body, length);
+    case DP_MSG_SELECTION_PUT:
+        return offset_point_at(po, body, length, 2, 1);
+    case DP_MSG_MOVE_REGION:
+        return offset_id_at(po, body, length, 0)
+            && offset_point_at(po, body, length, 2, 1)
+            && offset_quad_at(po, body, length, 18);
+    case DP_MSG_MOVE_RECT:
+    case DP_MSG_TRANSFORM_REGION: {
+        // Without a source, the layer id holds a pair of selection ids.
+        bool from_layer =
+            length >= 4 && DP_read_bigendian_uint16(body + 2) != 0;
+        bool is_move_rect = type == DP_MSG_MOVE_RECT;
+        return (!from_layer
+                || (offset_id_at(po, body, length, 0)
+                    && offset_id_at(po, body, length, 2)))
+            && offset_point_at(po, body, length, 4, 1)
+            && (is_move_rect ? offset_point_at(po, body, length, 12, 1)
+                             : offset_quad_at(po, body, length, 20));
+    }
+    case DP_MSG_LAYER_CREATE:
+    case DP_MSG_LAYER_TREE_DELETE:
+        return offset_id_at(po, body, length, 0)
+            && offset_id_at(po, body, length, 2);
+    case DP_MSG_LAYER_TREE_CREATE:
+    case DP_MSG_LAYER_TREE_MOVE:
+    case DP_MSG_TRACK_CREATE:
+        return offset_id_at(po, body, length, 0)
+            && offset_id_at(po, body, length, 2)
+            && offset_id_at(po, body, length, 4);
+    case DP_MSG_KEY_FRAME_SET:
+    case DP_MSG_KEY_FRAME_DELETE:
+        // The track id, then a frame index and the other layer or track id.
+        return offset_id_at(po, body, length, 0)
+            && offset_id_at(po, body, length, 4);
+    case DP_MSG_LAYER_ORDER:
+    case DP_MSG_TRACK_ORDER:
+        return offset_ids_from(po, body, length, 0, 2);
+    case DP_MSG_KEY_FRAME_LAYER_ATTRIBUTES:
+        // Track id and frame index, followed by pairs of layer id and flags.
+        return offset_id_at(po, body, length, 0)
+            && offset_ids_from(po, body, length, 4, 4);
+    default:
+        return true;
+    }
+}
+
+static unsigned char *get_offset_buffer(void *user, size_t length)
+{
+    DP_PlayerOffset *po = user;
+    if (po->capacity < length) {
+        po->buffer = DP_realloc(po->buffer, length);
+        po->capacity = length;
+    }
+    return po->buffer;
+}
+
+// Returns a new reference to the message with the offset applied or NULL if it
+// can't be applied, in which case the message should be dropped.
+static DP_Message *offset_message(DP_PlayerOffset *po, DP_Message *msg)
+{
+    size_t length = DP_message_serialize(msg, true, get_offset_buffer, po);
+    if (length == 0) {
+        return NULL;
+    }
+
+    unsigned char *body = po->buffer + DP_MESSAGE_HEADER_LENGTH;
+    size_t body_length = length - DP_MESSAGE_HEADER_LENGTH;
+    if (offset_body(po, DP_message_type(msg), body, body_length)) {
+        return DP_message_deserialize(po->buffer, length, true);
+    }
+    else {
+        return NULL;
+    }
+}
+
+static bool emit_offset_message(DP_Player *player, DP_Message **inout_msg)
+{
+    DP_MessageType type = DP_message_type(*inout_msg);
+    // These would change the existing canvas the recording is replayed into.
+    if (type == DP_MSG_CANVAS_RESIZE || type == DP_MSG_CANVAS_BACKGROUND) {
+        DP_message_decref(*inout_msg);
+        return false;
+    }
+
+    DP_Message *msg = offset_message(&player->offset, *inout_msg);
+    if (!msg) {
+        DP_warn("Dropping recorded %s message: %s",
+                DP_message_type_enum_name_unprefixed(type), DP_error());
+    }
+    DP_message_decref(*inout_msg);
+    *inout_msg = msg;
+    return msg != NULL;
+}
+
 static DP_PlayerResult step_valid_message(DP_Player *player,
                                           DP_Message **out_msg)
 {
@@ -571,7 +858,11 @@ static DP_PlayerResult step_valid_message(DP_Player *player,
                     DP_message_context_id(msg));
             }
             else if (emit_message(msg, out_msg)) {
-                return result;
+                if (!player->offset.enabled
+                    || emit_offset_message(player, out_msg)) {
+                    return result;
+                }
+                continue; // Dropped while offsetting, which freed it already.
             }
             DP_message_decref(msg);
         }
@@ -714,3 +1005,73 @@ bool DP_player_seek_dump(DP_Player *player, long long position)
         return false;
     }
 }
+
+
+static bool render_final_replay(DP_Player *player, DP_DrawContext *dc,
+                                DP_CanvasHistory *ch)
+{
+    while (true) {
+        DP_Message *msg;
+        DP_PlayerResult result = DP_player_step(player, &msg);
+        if (result == DP_PLAYER_SUCCESS) {
+            if (DP_message_type_command(DP_message_type(msg))
+                && !DP_canvas_history_handle(ch, dc, msg)) {
+                DP_warn("Error handling recorded message: %s", DP_error());
+            }
+            DP_message_decref(msg);
+        }
+        else if (result == DP_PLAYER_RECORDING_END) {
+            return true;
+        }
+        else {
+            return false;
+        }
+    }
+}
+
+static DP_CanvasState *render_final_background(DP_CanvasState *cs,
+                                               uint32_t background_color)
+{
+    if ((background_color & 0xff000000u) == 0
+        || DP_canvas_state_background_tile_noinc(cs)) {
+        return cs;
+    }
+    else {
+        DP_TransientCanvasState *tcs = DP_transient_canvas_state_new(cs);
+        DP_canvas_state_decref(cs);
+        DP_Tile *tile = DP_tile_new_from_bgra(0, background_color);
+        DP_transient_canvas_state_background_tile_set_noinc(
+            tcs, tile, (background_color & 0xff000000u) == 0xff000000u);
+        return DP_transient_canvas_state_persist(tcs);
+    }
+}
+
+DP_Image *DP_player_render_final(DP_Player *player, DP_DrawContext *dc,
+                                 uint32_t background_color)
+{
+    DP_ASSERT(player);
+    DP_ASSERT(dc);
+    if (player->type == DP_PLAYER_TYPE_DEBUG_DUMP) {
+        DP_error_set("Can't render a debug dump");
+        return NULL;
+    }
+
+    bool acl_override = player->acl_override;
+    player->acl_override = true;
+    DP_CanvasHistory *ch = DP_canvas_history_new(NULL, NULL, false, NULL);
+    DP_canvas_history_compact_undo_points_set(
+        ch, DP_player_compact_undo_points(player));
+    bool ok = render_final_replay(player, dc, ch);
+    player->acl_override = acl_override;
+
+    DP_Image *img = NULL;
+    if (ok) {
+        DP_CanvasState *cs = render_final_background(
+            DP_canvas_history_get(ch), background_color);
+        img = DP_canvas_state_to_flat_image(cs, DP_FLAT_IMAGE_RENDER_FLAGS,
+                                            NULL, NULL);
+        DP_canvas_state_decref(cs);
+    }
+    DP_canvas_history_free(ch);
+    return img;
+}
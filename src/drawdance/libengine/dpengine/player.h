@@ -85,8 +85,26 @@ DP_PlayerCompatibility DP_player_compatibility(DP_Player *player);
 
 bool DP_player_compatible(DP_Player *player);
 
+// Whether the recording should be played back with compacted undo points, see
+// DP_canvas_history_compact_undo_points. Recordings from before dp:4.25 count
+// every undo point as an undo step, so they have to be played back like that
+// to come out the same.
+bool DP_player_compact_undo_points(DP_Player *player);
+
 void DP_player_acl_override_set(DP_Player *player, bool override);
 
+// Shifts the recording's content when stepping through it, for replaying it
+// into an existing canvas, such as when putting together a collage. The
+// coordinates of drawing commands, region moves, annotations and the like get
+// dx and dy added to them, the ids of layers, annotations and tracks get the
+// id offset added to them to avoid colliding with ones already on the canvas.
+// Ids of zero, meaning no layer, are left alone. Canvas resizes and background
+// changes are dropped, since they'd mess with the existing canvas. So are
+// messages that end up out of range and put tiles that can't be shifted, which
+// is the case unless the offset is a multiple of the tile size and the tile
+// isn't repeated. Passing all zeroes turns the offset off again.
+void DP_player_offset_set(DP_Player *player, int dx, int dy, int id_offset);
+
 const char *DP_player_recording_path(DP_Player *player);
 
 const char *DP_player_index_path(DP_Player *player);
@@ -117,5 +135,14 @@ bool DP_player_rewind(DP_Player *player);
 
 bool DP_player_seek_dump(DP_Player *player, long long position);
 
+// Replays the rest of the recording onto a blank canvas and flattens the
+// result, for rendering recordings without any user interface. Permissions are
+// ignored during the replay, meaning every recorded message applies. If the
+// recording doesn't set a canvas background, the given background color is used
+// instead, pass zero to leave the canvas transparent. Returns NULL and sets an
+// error on input errors or if the canvas ends up empty.
+DP_Image *DP_player_render_final(DP_Player *player, DP_DrawContext *dc,
+                                 uint32_t background_color);
+
 
 #endif
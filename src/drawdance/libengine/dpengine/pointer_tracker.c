@@ -0,0 +1,112 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "pointer_tracker.h"
+#include <dpcommon/common.h>
+#include <dpcommon/conversions.h>
+#include <dpmsg/message.h>
+
+
+void DP_pointer_tracker_init(DP_PointerTracker *pt)
+{
+    DP_ASSERT(pt);
+    pt->count = 0;
+    for (int i = 0; i < DP_POINTER_TRACKER_COUNT; ++i) {
+        pt->user_ids[i] = 0;
+        pt->active_by_user[i] = false;
+        pt->xs[i] = 0.0f;
+        pt->ys[i] = 0.0f;
+    }
+}
+
+bool DP_pointer_tracker_handle(DP_PointerTracker *pt, DP_Message *msg)
+{
+    DP_ASSERT(pt);
+    DP_ASSERT(msg);
+    unsigned int context_id = DP_message_context_id(msg);
+    switch (DP_message_type(msg)) {
+    case DP_MSG_MOVE_POINTER: {
+        DP_MsgMovePointer *mmp = DP_message_internal(msg);
+        DP_pointer_tracker_move(
+            pt, context_id,
+            DP_int32_to_float(DP_msg_move_pointer_x(mmp)) / 4.0f,
+            DP_int32_to_float(DP_msg_move_pointer_y(mmp)) / 4.0f);
+        return true;
+    }
+    case DP_MSG_LEAVE:
+        if (pt->active_by_user[context_id]) {
+            DP_pointer_tracker_remove(pt, context_id);
+            return true;
+        }
+        else {
+            return false;
+        }
+    default:
+        return false;
+    }
+}
+
+void DP_pointer_tracker_move(DP_PointerTracker *pt, unsigned int context_id,
+                             float x, float y)
+{
+    DP_ASSERT(pt);
+    DP_ASSERT(context_id < DP_POINTER_TRACKER_COUNT);
+    if (!pt->active_by_user[context_id]) {
+        pt->active_by_user[context_id] = true;
+        pt->user_ids[pt->count++] = DP_uint_to_uint8(context_id);
+    }
+    pt->xs[context_id] = x;
+    pt->ys[context_id] = y;
+}
+
+void DP_pointer_tracker_remove(DP_PointerTracker *pt, unsigned int context_id)
+{
+    DP_ASSERT(pt);
+    DP_ASSERT(context_id < DP_POINTER_TRACKER_COUNT);
+    if (pt->active_by_user[context_id]) {
+        pt->active_by_user[context_id] = false;
+        int count = pt->count;
+        for (int i = 0; i < count; ++i) {
+            if (pt->user_ids[i] == context_id) {
+                memmove(&pt->user_ids[i], &pt->user_ids[i + 1],
+                        DP_int_to_size(count - i - 1));
+                break;
+            }
+        }
+        pt->count = count - 1;
+    }
+}
+
+bool DP_pointer_tracker_position(DP_PointerTracker *pt, unsigned int context_id,
+                                 float *out_x, float *out_y)
+{
+    DP_ASSERT(pt);
+    DP_ASSERT(context_id < DP_POINTER_TRACKER_COUNT);
+    if (pt->active_by_user[context_id]) {
+        if (out_x) {
+            *out_x = pt->xs[context_id];
+        }
+        if (out_y) {
+            *out_y = pt->ys[context_id];
+        }
+        return true;
+    }
+    else {
+        return false;
+    }
+}
+
+int DP_pointer_tracker_count(DP_PointerTracker *pt)
+{
+    DP_ASSERT(pt);
+    return pt->count;
+}
+
+void DP_pointer_tracker_each(DP_PointerTracker *pt, DP_PointerTrackerFn fn,
+                             void *user)
+{
+    DP_ASSERT(pt);
+    DP_ASSERT(fn);
+    for (int i = 0; i < pt->count; ++i) {
+        unsigned int context_id = pt->user_ids[i];
+        fn(user, context_id, pt->xs[context_id], pt->ys[context_id]);
+    }
+}
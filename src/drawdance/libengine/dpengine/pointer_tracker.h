@@ -0,0 +1,46 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#ifndef DPENGINE_POINTER_TRACKER_H
+#define DPENGINE_POINTER_TRACKER_H
+#include <dpcommon/common.h>
+
+typedef struct DP_Message DP_Message;
+
+#define DP_POINTER_TRACKER_COUNT 256
+
+// Latest pointer position of each user, as sent via move pointer messages.
+// Positions are in canvas pixels, already divided by 4. Users are pruned when
+// they leave. Iteration goes in the order users first moved their pointers.
+typedef struct DP_PointerTracker {
+    int count;
+    uint8_t user_ids[DP_POINTER_TRACKER_COUNT];
+    bool active_by_user[DP_POINTER_TRACKER_COUNT];
+    float xs[DP_POINTER_TRACKER_COUNT];
+    float ys[DP_POINTER_TRACKER_COUNT];
+} DP_PointerTracker;
+
+typedef void (*DP_PointerTrackerFn)(void *user, unsigned int context_id,
+                                    float x, float y);
+
+
+void DP_pointer_tracker_init(DP_PointerTracker *pt);
+
+// Updates positions on move pointer messages, prunes users on leave
+// messages. Returns true if the message changed anything.
+bool DP_pointer_tracker_handle(DP_PointerTracker *pt, DP_Message *msg);
+
+void DP_pointer_tracker_move(DP_PointerTracker *pt, unsigned int context_id,
+                             float x, float y);
+
+void DP_pointer_tracker_remove(DP_PointerTracker *pt, unsigned int context_id);
+
+// Returns false and leaves the outputs alone if the user has no position.
+bool DP_pointer_tracker_position(DP_PointerTracker *pt, unsigned int context_id,
+                                 float *out_x, float *out_y);
+
+int DP_pointer_tracker_count(DP_PointerTracker *pt);
+
+void DP_pointer_tracker_each(DP_PointerTracker *pt, DP_PointerTrackerFn fn,
+                             void *user);
+
+
+#endif
@@ -26,6 +26,7 @@
 #include "layer_list.h"
 #include "layer_props.h"
 #include "layer_props_list.h"
+#include "layer_routes.h"
 #include "pixels.h"
 #include "tile.h"
 #include "timeline.h"
@@ -34,6 +35,7 @@
 #include <dpcommon/conversions.h>
 #include <dpcommon/queue.h>
 #include <dpcommon/threading.h>
+#include <dpmsg/acl.h>
 #include <dpmsg/message.h>
 
 
@@ -552,3 +554,292 @@ void DP_reset_image_build(DP_CanvasState *cs, unsigned int context_id,
     DP_free(c.output_buffer);
     DP_free(c.pixel_buffer);
 }
+
+
+static void set_blank_tile_data(DP_UNUSED size_t size, unsigned char *out,
+                                DP_UNUSED void *user)
+{
+    DP_ASSERT(size == 4);
+    memset(out, 0, 4);
+}
+
+static void tiles_to_reset_image_diff(struct DP_ResetImageContext *c,
+                                      DP_LayerContent *lc,
+                                      DP_LayerContent *prev_lc,
+                                      uint16_t layer_id)
+{
+    DP_TileCounts counts = DP_tile_counts_round(DP_layer_content_width(lc),
+                                                DP_layer_content_height(lc));
+    DP_TileCounts prev_counts = DP_tile_counts_round(
+        DP_layer_content_width(prev_lc), DP_layer_content_height(prev_lc));
+    for (int y = 0; y < counts.y; ++y) {
+        for (int x = 0; x < counts.x; ++x) {
+            DP_Tile *t = DP_layer_content_tile_at_noinc(lc, x, y);
+            DP_Tile *prev_t = x < prev_counts.x && y < prev_counts.y
+                                ? DP_layer_content_tile_at_noinc(prev_lc, x, y)
+                                : NULL;
+            if (!DP_tile_pixels_equal(t, prev_t)) {
+                uint16_t col = DP_int_to_uint16(x);
+                uint16_t row = DP_int_to_uint16(y);
+                if (t && !DP_tile_blank(t)) {
+                    size_t size = reset_image_maybe_compress_tile(c, t);
+                    if (size != 0) {
+                        reset_image_push(
+                            c, DP_msg_put_tile_new(c->context_id, layer_id, 0,
+                                                   col, row, 0, set_tile_data,
+                                                   size, c->output_buffer));
+                    }
+                }
+                else {
+                    reset_image_push(
+                        c, DP_msg_put_tile_new(c->context_id, layer_id, 0, col,
+                                               row, 0, set_blank_tile_data, 4,
+                                               NULL));
+                }
+            }
+        }
+    }
+}
+
+static void layers_to_reset_image_diff(struct DP_ResetImageContext *c,
+                                       DP_LayerList *ll, DP_LayerPropsList *lpl,
+                                       DP_CanvasState *prev)
+{
+    DP_LayerRoutes *prev_lr = DP_canvas_state_layer_routes_noinc(prev);
+    int count = DP_layer_list_count(ll);
+    DP_ASSERT(DP_layer_props_list_count(lpl) == count);
+    for (int i = 0; i < count; ++i) {
+        DP_LayerListEntry *lle = DP_layer_list_at_noinc(ll, i);
+        DP_LayerProps *lp = DP_layer_props_list_at_noinc(lpl, i);
+        if (DP_layer_list_entry_is_group(lle)) {
+            layers_to_reset_image_diff(
+                c,
+                DP_layer_group_children_noinc(
+                    DP_layer_list_entry_group_noinc(lle)),
+                DP_layer_props_children_noinc(lp), prev);
+        }
+        else {
+            int layer_id = DP_layer_props_id(lp);
+            DP_LayerRoutesEntry *lre =
+                DP_layer_routes_search(prev_lr, layer_id);
+            if (lre && !DP_layer_routes_entry_is_group(lre)) {
+                tiles_to_reset_image_diff(
+                    c, DP_layer_list_entry_content_noinc(lle),
+                    DP_layer_routes_entry_content(lre, prev),
+                    DP_int_to_uint16(layer_id));
+            }
+        }
+    }
+}
+
+void DP_reset_image_diff_build(DP_CanvasState *cs, DP_CanvasState *prev,
+                               unsigned int context_id,
+                               void (*push_message)(void *, DP_Message *),
+                               void *user)
+{
+    DP_ASSERT(cs);
+    DP_ASSERT(prev);
+    DP_ASSERT(push_message);
+    struct DP_ResetImageContext c = {
+        context_id, push_message,
+        user,       DP_malloc(sizeof(*c.pixel_buffer) * DP_TILE_LENGTH),
+        0,          NULL};
+
+    int width = DP_canvas_state_width(cs);
+    int height = DP_canvas_state_height(cs);
+    int prev_width = DP_canvas_state_width(prev);
+    int prev_height = DP_canvas_state_height(prev);
+    if (width != prev_width || height != prev_height) {
+        reset_image_push(&c, DP_msg_canvas_resize_new(
+                                 context_id, 0,
+                                 DP_int_to_int32(width - prev_width),
+                                 DP_int_to_int32(height - prev_height), 0));
+    }
+
+    layers_to_reset_image_diff(&c, DP_canvas_state_layers_noinc(cs),
+                               DP_canvas_state_layer_props_noinc(cs), prev);
+    DP_free(c.output_buffer);
+    DP_free(c.pixel_buffer);
+}
+
+
+typedef enum DP_ResetImageBuilderStep {
+    DP_RESET_IMAGE_BUILDER_STEP_ACL_SESSION,
+    DP_RESET_IMAGE_BUILDER_STEP_CANVAS,
+    DP_RESET_IMAGE_BUILDER_STEP_LAYERS,
+    DP_RESET_IMAGE_BUILDER_STEP_ANNOTATIONS,
+    DP_RESET_IMAGE_BUILDER_STEP_ACL_LAYERS,
+    DP_RESET_IMAGE_BUILDER_STEP_DONE,
+} DP_ResetImageBuilderStep;
+
+struct DP_ResetImageBuilder {
+    DP_CanvasState *cs;
+    DP_AclState *acls;
+    unsigned int acl_include_flags;
+    DP_ResetImageBuilderStep step;
+    int layer_index;
+    struct {
+        bool (*fn)(void *, DP_Message *);
+        void *user;
+        bool ok;
+    } push;
+    struct DP_ResetImageContext c;
+};
+
+static void reset_image_builder_push(void *user, DP_Message *msg)
+{
+    DP_ResetImageBuilder *rib = user;
+    if (rib->push.ok) {
+        rib->push.ok = rib->push.fn(rib->push.user, msg);
+    }
+    else {
+        DP_message_decref(msg);
+    }
+}
+
+DP_ResetImageBuilder *
+DP_reset_image_builder_new(DP_CanvasState *cs, DP_AclState *acls,
+                           unsigned int context_id,
+                           unsigned int acl_include_flags)
+{
+    DP_ASSERT(cs);
+    DP_ASSERT(acls);
+    DP_ResetImageBuilder *rib = DP_malloc(sizeof(*rib));
+    *rib = (DP_ResetImageBuilder){
+        DP_canvas_state_incref(cs),
+        DP_acl_state_new_clone(acls, 0),
+        acl_include_flags,
+        DP_RESET_IMAGE_BUILDER_STEP_ACL_SESSION,
+        0,
+        {NULL, NULL, true},
+        {context_id, reset_image_builder_push, rib,
+         DP_malloc(sizeof(*rib->c.pixel_buffer) * DP_TILE_LENGTH), 0, NULL}};
+    return rib;
+}
+
+void DP_reset_image_builder_free(DP_ResetImageBuilder *rib)
+{
+    if (rib) {
+        DP_free(rib->c.output_buffer);
+        DP_free(rib->c.pixel_buffer);
+        DP_acl_state_free(rib->acls);
+        DP_canvas_state_decref(rib->cs);
+        DP_free(rib);
+    }
+}
+
+bool DP_reset_image_builder_done(DP_ResetImageBuilder *rib)
+{
+    DP_ASSERT(rib);
+    return rib->step == DP_RESET_IMAGE_BUILDER_STEP_DONE;
+}
+
+static void reset_image_builder_step_canvas(DP_ResetImageBuilder *rib)
+{
+    struct DP_ResetImageContext *c = &rib->c;
+    DP_CanvasState *cs = rib->cs;
+    int width = DP_canvas_state_width(cs);
+    int height = DP_canvas_state_height(cs);
+    if (width > 0 && height > 0) {
+        reset_image_push(c, DP_msg_canvas_resize_new(
+                                c->context_id, 0, DP_int_to_int32(width),
+                                DP_int_to_int32(height), 0));
+    }
+
+    size_t size = reset_image_maybe_compress_tile(
+        c, DP_canvas_state_background_tile_noinc(cs));
+    if (size != 0) {
+        reset_image_push(c, DP_msg_canvas_background_new(c->context_id,
+                                                         set_tile_data, size,
+                                                         c->output_buffer));
+    }
+}
+
+// Returns whether there's more layers left after this one.
+static bool reset_image_builder_step_layer(DP_ResetImageBuilder *rib)
+{
+    DP_LayerList *ll = DP_canvas_state_layers_noinc(rib->cs);
+    DP_LayerPropsList *lpl = DP_canvas_state_layer_props_noinc(rib->cs);
+    int count = DP_layer_list_count(ll);
+    int i = rib->layer_index;
+    if (i < count) {
+        DP_LayerListEntry *lle = DP_layer_list_at_noinc(ll, i);
+        DP_LayerProps *lp = DP_layer_props_list_at_noinc(lpl, i);
+        if (DP_layer_list_entry_is_group(lle)) {
+            layer_group_to_reset_image(
+                &rib->c, 0, DP_layer_list_entry_group_noinc(lle), lp);
+        }
+        else {
+            layer_content_to_reset_image(
+                &rib->c, 0, DP_layer_list_entry_content_noinc(lle), lp);
+        }
+        rib->layer_index = i + 1;
+    }
+    return rib->layer_index < count;
+}
+
+static void reset_image_builder_step_annotations(DP_ResetImageBuilder *rib)
+{
+    struct DP_ResetImageContext *c = &rib->c;
+    DP_CanvasState *cs = rib->cs;
+    annotations_to_reset_image(c, DP_canvas_state_annotations_noinc(cs));
+    document_metadata_to_reset_image(c, DP_canvas_state_metadata_noinc(cs));
+    timeline_to_reset_image(c, DP_canvas_state_timeline_noinc(cs));
+}
+
+static bool reset_image_builder_push_acl(void *user, DP_Message *msg)
+{
+    DP_ResetImageBuilder *rib = user;
+    reset_image_builder_push(rib, msg);
+    return rib->push.ok;
+}
+
+static DP_ResetImageBuilderStep
+reset_image_builder_next_step(DP_ResetImageBuilder *rib)
+{
+    switch (rib->step) {
+    case DP_RESET_IMAGE_BUILDER_STEP_ACL_SESSION:
+        DP_acl_state_reset_image_build_session(
+            rib->acls, rib->c.context_id, rib->acl_include_flags,
+            reset_image_builder_push_acl, rib);
+        return DP_RESET_IMAGE_BUILDER_STEP_CANVAS;
+    case DP_RESET_IMAGE_BUILDER_STEP_CANVAS:
+        reset_image_builder_step_canvas(rib);
+        return DP_RESET_IMAGE_BUILDER_STEP_LAYERS;
+    case DP_RESET_IMAGE_BUILDER_STEP_LAYERS:
+        return reset_image_builder_step_layer(rib)
+                 ? DP_RESET_IMAGE_BUILDER_STEP_LAYERS
+                 : DP_RESET_IMAGE_BUILDER_STEP_ANNOTATIONS;
+    case DP_RESET_IMAGE_BUILDER_STEP_ANNOTATIONS:
+        reset_image_builder_step_annotations(rib);
+        return DP_RESET_IMAGE_BUILDER_STEP_ACL_LAYERS;
+    case DP_RESET_IMAGE_BUILDER_STEP_ACL_LAYERS:
+        DP_acl_state_reset_image_build_layers(
+            rib->acls, rib->c.context_id, rib->acl_include_flags,
+            reset_image_builder_push_acl, rib);
+        return DP_RESET_IMAGE_BUILDER_STEP_DONE;
+    case DP_RESET_IMAGE_BUILDER_STEP_DONE:
+        return DP_RESET_IMAGE_BUILDER_STEP_DONE;
+    }
+    DP_UNREACHABLE();
+}
+
+bool DP_reset_image_builder_step(DP_ResetImageBuilder *rib,
+                                 bool (*push_message)(void *, DP_Message *),
+                                 void *user)
+{
+    DP_ASSERT(rib);
+    DP_ASSERT(push_message);
+    rib->push.fn = push_message;
+    rib->push.user = user;
+    rib->step = reset_image_builder_next_step(rib);
+    rib->push.fn = NULL;
+    rib->push.user = NULL;
+    if (rib->push.ok) {
+        return true;
+    }
+    else {
+        rib->step = DP_RESET_IMAGE_BUILDER_STEP_DONE;
+        return false;
+    }
+}
@@ -18,6 +18,7 @@
 #define DPENGINE_SNAPSHOTS_H
 #include <dpcommon/common.h>
 
+typedef struct DP_AclState DP_AclState;
 typedef struct DP_CanvasHistory DP_CanvasHistory;
 typedef struct DP_CanvasState DP_CanvasState;
 typedef struct DP_Message DP_Message;
@@ -62,5 +63,45 @@ void DP_reset_image_build(DP_CanvasState *cs, unsigned int context_id,
                           void (*push_message)(void *, DP_Message *),
                           void *user);
 
+// Builds the canvas resize and put tile messages that bring prev's pixel
+// content in line with cs, for a server to send to a client that got out of
+// sync. Only tiles that differ are sent. Layers that don't exist in prev are
+// skipped, structural differences need a full reset instead.
+void DP_reset_image_diff_build(DP_CanvasState *cs, DP_CanvasState *prev,
+                               unsigned int context_id,
+                               void (*push_message)(void *, DP_Message *),
+                               void *user);
+
+
+// Builds the reset image for catching up a late joiner a piece at a time, so
+// that a server can interleave it with other work instead of dumping the whole
+// session at once. The pieces come in dependency order: first the session ACLs
+// from DP_acl_state_reset_image_build_session, then the canvas size and
+// background, each top-level layer tree with its tiles, then annotations,
+// document metadata and the timeline and finally the layer ACLs, after the
+// layers they apply to have been created. Replaying every piece into a fresh
+// session reconstructs the one the builder was made from.
+//
+// The builder keeps a reference to the canvas state and a copy of the ACL
+// state, so the originals can keep changing while it's being stepped through.
+typedef struct DP_ResetImageBuilder DP_ResetImageBuilder;
+
+DP_ResetImageBuilder *
+DP_reset_image_builder_new(DP_CanvasState *cs, DP_AclState *acls,
+                           unsigned int context_id,
+                           unsigned int acl_include_flags);
+
+void DP_reset_image_builder_free(DP_ResetImageBuilder *rib);
+
+// Whether every piece has been pushed.
+bool DP_reset_image_builder_done(DP_ResetImageBuilder *rib);
+
+// Pushes the messages of the next piece, if any. The push function takes
+// ownership of the messages. Returns false if it fails, after which the rest
+// of the piece is dropped and the builder is done.
+bool DP_reset_image_builder_step(DP_ResetImageBuilder *rib,
+                                 bool (*push_message)(void *, DP_Message *),
+                                 void *user) DP_MUST_CHECK;
+
 
 #endif
@@ -96,3 +96,60 @@ bool DP_text_equal(DP_Text *a_or_null, DP_Text *b_or_null)
         return a_length == b_length && memcmp(a, b, a_length) == 0;
     }
 }
+
+static bool is_control_char(unsigned char c)
+{
+    return c < 0x20 || c == 0x7f;
+}
+
+static bool is_utf8_continuation(unsigned char c)
+{
+    return (c & 0xc0) == 0x80;
+}
+
+static size_t utf8_sequence_length(unsigned char lead)
+{
+    if (lead >= 0xf0) {
+        return 4;
+    }
+    else if (lead >= 0xe0) {
+        return 3;
+    }
+    else if (lead >= 0xc0) {
+        return 2;
+    }
+    else {
+        return 1;
+    }
+}
+
+size_t DP_text_sanitize(const char *string, size_t length, char *out,
+                        size_t max_length)
+{
+    DP_ASSERT(string || length == 0);
+    DP_ASSERT(out || max_length == 0);
+    size_t out_length = 0;
+    for (size_t i = 0; i < length && out_length < max_length; ++i) {
+        unsigned char c = (unsigned char)string[i];
+        if (!is_control_char(c)) {
+            out[out_length++] = (char)c;
+        }
+    }
+
+    // If we cut off a multibyte sequence, drop the part that made it in.
+    if (out_length == max_length && out_length != 0) {
+        size_t end = out_length;
+        while (end > 0 && is_utf8_continuation((unsigned char)out[end - 1])) {
+            --end;
+        }
+        if (end > 0) {
+            size_t lead_index = end - 1;
+            size_t sequence_length =
+                utf8_sequence_length((unsigned char)out[lead_index]);
+            if (out_length - lead_index < sequence_length) {
+                out_length = lead_index;
+            }
+        }
+    }
+    return out_length;
+}
@@ -22,5 +22,12 @@ const char *DP_text_string(DP_Text *text_or_null, size_t *out_length);
 
 bool DP_text_equal(DP_Text *a_or_null, DP_Text *b_or_null);
 
+// Copies the given string into out, leaving out ASCII control characters and
+// truncating it to at most max_length bytes without splitting up a UTF-8
+// sequence. The out buffer must hold at least max_length bytes. Returns the
+// length of the resulting string, which isn't NUL-terminated.
+size_t DP_text_sanitize(const char *string, size_t length, char *out,
+                        size_t max_length);
+
 
 #endif
@@ -71,18 +71,35 @@ struct DP_Tile {
 #endif
 
 
-// We want to initialize a static buffer with the same value 4096 times, so this
-// is a goofy way to achieve that at compile time without spelling it all out.
+// We want to initialize a static buffer with the same value for every pixel in
+// a tile, so this is a goofy way to achieve that at compile time without
+// spelling it all out.
 #define DP_BIT15_4    DP_BIT15, DP_BIT15, DP_BIT15, DP_BIT15
 #define DP_BIT15_16   DP_BIT15_4, DP_BIT15_4, DP_BIT15_4, DP_BIT15_4
 #define DP_BIT15_64   DP_BIT15_16, DP_BIT15_16, DP_BIT15_16, DP_BIT15_16
 #define DP_BIT15_256  DP_BIT15_64, DP_BIT15_64, DP_BIT15_64, DP_BIT15_64
 #define DP_BIT15_1024 DP_BIT15_256, DP_BIT15_256, DP_BIT15_256, DP_BIT15_256
 #define DP_BIT15_4096 DP_BIT15_1024, DP_BIT15_1024, DP_BIT15_1024, DP_BIT15_1024
+#define DP_BIT15_16384 \
+    DP_BIT15_4096, DP_BIT15_4096, DP_BIT15_4096, DP_BIT15_4096
+#define DP_BIT15_65536 \
+    DP_BIT15_16384, DP_BIT15_16384, DP_BIT15_16384, DP_BIT15_16384
+
+#if DP_TILE_SIZE == 32
+#    define DP_BIT15_TILE DP_BIT15_1024
+#elif DP_TILE_SIZE == 64
+#    define DP_BIT15_TILE DP_BIT15_4096
+#elif DP_TILE_SIZE == 128
+#    define DP_BIT15_TILE DP_BIT15_16384
+#elif DP_TILE_SIZE == 256
+#    define DP_BIT15_TILE DP_BIT15_65536
+#else
+#    error "DP_TILE_SIZE must be 32, 64, 128 or 256"
+#endif
 
 const uint16_t *DP_tile_opaque_mask(void)
 {
-    static const uint16_t opaque_mask[DP_TILE_LENGTH] = {DP_BIT15_4096};
+    static const uint16_t opaque_mask[DP_TILE_LENGTH] = {DP_BIT15_TILE};
     return opaque_mask;
 }
 
@@ -192,6 +209,11 @@ DP_Tile *DP_tile_new_from_compressed(DP_DrawContext *dc,
         uint32_t bgra = DP_read_bigendian_uint32(image);
         return DP_tile_new_from_bgra(context_id, bgra);
     }
+    else if (DP_tile_compressed_checksum(image, image_size)
+             == DP_TILE_CHECKSUM_MISMATCH) {
+        DP_error_set("Tile checksum mismatch");
+        return NULL;
+    }
     else {
         struct DP_TileInflateArgs args = {
             DP_draw_context_tile8_buffer(dc),
@@ -376,6 +398,22 @@ bool DP_tile_same_pixel(DP_Tile *tile_or_null, DP_Pixel15 *out_pixel)
     return true;
 }
 
+bool DP_tile_pixels_equal(DP_Tile *a_or_null, DP_Tile *b_or_null)
+{
+    if (a_or_null == b_or_null) {
+        return true;
+    }
+    else if (!a_or_null) {
+        return DP_tile_blank(b_or_null);
+    }
+    else if (!b_or_null) {
+        return DP_tile_blank(a_or_null);
+    }
+    else {
+        return memcmp(a_or_null->pixels, b_or_null->pixels, DP_TILE_BYTES) == 0;
+    }
+}
+
 
 size_t DP_tile_compress(DP_Tile *tile, DP_Pixel8 *pixel_buffer,
                         unsigned char *(*get_output_buffer)(size_t, void *),
@@ -391,6 +429,60 @@ size_t DP_tile_compress(DP_Tile *tile, DP_Pixel8 *pixel_buffer,
 }
 
 
+static const unsigned char checksum_magic[] = {0xff, 0xff, 'D', 'P'};
+
+struct DP_TileChecksumArgs {
+    unsigned char *(*get_output_buffer)(size_t, void *);
+    void *user;
+    unsigned char *buffer;
+};
+
+static unsigned char *get_checksummed_output_buffer(size_t size, void *user)
+{
+    struct DP_TileChecksumArgs *args = user;
+    args->buffer = args->get_output_buffer(
+        size + DP_TILE_CHECKSUM_TRAILER_LENGTH, args->user);
+    return args->buffer;
+}
+
+size_t DP_tile_compress_checksummed(
+    DP_Tile *tile, DP_Pixel8 *pixel_buffer,
+    unsigned char *(*get_output_buffer)(size_t, void *), void *user)
+{
+    struct DP_TileChecksumArgs args = {get_output_buffer, user, NULL};
+    size_t size = DP_tile_compress(tile, pixel_buffer,
+                                   get_checksummed_output_buffer, &args);
+    if (size == 0) {
+        return 0;
+    }
+    unsigned char *trailer = args.buffer + size;
+    DP_write_bigendian_uint32(DP_compress_crc32(args.buffer, size), trailer);
+    memcpy(trailer + 4, checksum_magic, sizeof(checksum_magic));
+    return size + DP_TILE_CHECKSUM_TRAILER_LENGTH;
+}
+
+DP_TileChecksum DP_tile_compressed_checksum(const unsigned char *image,
+                                            size_t image_size)
+{
+    DP_ASSERT(image || image_size == 0);
+    // The smallest possible checksummed payload still needs the 4 byte size
+    // header in front of the trailer, which also excludes solid colors.
+    if (image_size > 4 + DP_TILE_CHECKSUM_TRAILER_LENGTH
+        && memcmp(image + image_size - sizeof(checksum_magic), checksum_magic,
+                  sizeof(checksum_magic))
+               == 0) {
+        size_t size = image_size - DP_TILE_CHECKSUM_TRAILER_LENGTH;
+        return DP_read_bigendian_uint32(image + size)
+                    == DP_compress_crc32(image, size)
+                 ? DP_TILE_CHECKSUM_VALID
+                 : DP_TILE_CHECKSUM_MISMATCH;
+    }
+    else {
+        return DP_TILE_CHECKSUM_NONE;
+    }
+}
+
+
 void DP_tile_copy_to_image(DP_Tile *tile_or_null, DP_Image *img, int x, int y)
 {
     DP_ASSERT(img);
@@ -23,6 +23,7 @@
 #define DPENGINE_TILE_H
 #include "pixels.h"
 #include <dpcommon/common.h>
+#include <dpcommon/geom.h>
 #include <dpcommon/memory_pool.h>
 
 typedef struct DP_DrawContext DP_DrawContext;
@@ -31,10 +32,27 @@ typedef struct DP_Image DP_Image;
 #define DP_TILE_BYTES            (DP_TILE_LENGTH * sizeof(DP_Pixel15))
 #define DP_TILE_COMPRESSED_BYTES (DP_TILE_LENGTH * sizeof(DP_Pixel8))
 
+// Compressed tile payloads may optionally end in a trailer consisting of a
+// big-endian CRC-32 of everything before it, followed by a magic value that
+// can't be confused with the Adler-32 that ends a zlib stream. Older decoders
+// stop at the end of the zlib stream and ignore the trailer. Solid color
+// payloads are only 4 bytes long and never have a trailer.
+#define DP_TILE_CHECKSUM_TRAILER_LENGTH 8
+
+typedef enum DP_TileChecksum {
+    DP_TILE_CHECKSUM_NONE,
+    DP_TILE_CHECKSUM_VALID,
+    DP_TILE_CHECKSUM_MISMATCH,
+} DP_TileChecksum;
+
 typedef struct DP_TileCounts {
     int x, y;
 } DP_TileCounts;
 
+typedef struct DP_TileCoords {
+    int x, y;
+} DP_TileCoords;
+
 #ifdef DP_NO_STRICT_ALIASING
 
 typedef struct DP_Tile DP_Tile;
@@ -77,6 +95,26 @@ DP_INLINE int DP_tile_total_round(int width, int height)
     return tile_counts.x * tile_counts.y;
 }
 
+// Index of the tile that the given pixel coordinate falls into. Rounds towards
+// negative infinity, so -1 is in tile -1, not in tile 0.
+DP_INLINE int DP_tile_index_floor(int i)
+{
+    return i < 0 ? (i + 1) / DP_TILE_SIZE - 1 : i / DP_TILE_SIZE;
+}
+
+DP_INLINE DP_TileCoords DP_tile_coords(int x, int y)
+{
+    DP_TileCoords tc = {DP_tile_index_floor(x), DP_tile_index_floor(y)};
+    return tc;
+}
+
+// The pixel area covered by the tile at the given tile coordinates.
+DP_INLINE DP_Rect DP_tile_rect(int tile_x, int tile_y)
+{
+    return DP_rect_make(tile_x * DP_TILE_SIZE, tile_y * DP_TILE_SIZE,
+                        DP_TILE_SIZE, DP_TILE_SIZE);
+}
+
 
 const uint16_t *DP_tile_opaque_mask(void);
 
@@ -95,6 +133,8 @@ DP_Tile *DP_tile_new_from_pixels8(unsigned int context_id,
 
 DP_Tile *DP_tile_new_from_bgra(unsigned int context_id, uint32_t bgra);
 
+// Returns NULL and sets an error if the payload can't be decompressed or if it
+// has a checksum trailer that doesn't match.
 DP_Tile *DP_tile_new_from_compressed(DP_DrawContext *dc,
                                      unsigned int context_id,
                                      const unsigned char *image,
@@ -136,11 +176,22 @@ bool DP_tile_opaque(DP_Tile *tile_or_null);
 
 bool DP_tile_same_pixel(DP_Tile *tile_or_null, DP_Pixel15 *out_pixel);
 
+// Compares the actual pixel contents, a NULL tile is equal to a blank one.
+bool DP_tile_pixels_equal(DP_Tile *a_or_null, DP_Tile *b_or_null);
+
 
 size_t DP_tile_compress(DP_Tile *tile, DP_Pixel8 *pixel_buffer,
                         unsigned char *(*get_output_buffer)(size_t, void *),
                         void *user);
 
+// Like DP_tile_compress, but appends a checksum trailer to the payload.
+size_t DP_tile_compress_checksummed(
+    DP_Tile *tile, DP_Pixel8 *pixel_buffer,
+    unsigned char *(*get_output_buffer)(size_t, void *), void *user);
+
+DP_TileChecksum DP_tile_compressed_checksum(const unsigned char *image,
+                                            size_t image_size);
+
 
 void DP_tile_copy_to_image(DP_Tile *tile_or_null, DP_Image *img, int x, int y);
 
@@ -0,0 +1,90 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "user_presence.h"
+#include <dpcommon/common.h>
+#include <dpmsg/message.h>
+
+
+void DP_user_presence_init(DP_UserPresence *up)
+{
+    DP_ASSERT(up);
+    for (int i = 0; i < DP_USER_PRESENCE_COUNT; ++i) {
+        up->has_color[i] = false;
+        up->user_colors[i] = 0;
+    }
+}
+
+static bool set_color(DP_UserPresence *up, unsigned int context_id,
+                      uint32_t color)
+{
+    bool had_color = up->has_color[context_id];
+    if ((color & 0xff000000u) == 0) {
+        DP_user_presence_remove(up, context_id);
+        return had_color;
+    }
+    else if (!had_color || up->user_colors[context_id] != color) {
+        up->has_color[context_id] = true;
+        up->user_colors[context_id] = color;
+        return true;
+    }
+    else {
+        return false;
+    }
+}
+
+bool DP_user_presence_handle(DP_UserPresence *up, DP_Message *msg)
+{
+    DP_ASSERT(up);
+    DP_ASSERT(msg);
+    unsigned int context_id = DP_message_context_id(msg);
+    switch (DP_message_type(msg)) {
+    case DP_MSG_CLIENT_META: {
+        // The ACL filter makes sure users only set their own color, so the
+        // user field is the same as the context id in anything that gets here.
+        DP_MsgClientMeta *mcm = DP_message_internal(msg);
+        return set_color(up, DP_msg_client_meta_user(mcm),
+                         DP_msg_client_meta_color(mcm));
+    }
+    case DP_MSG_LEAVE:
+        if (up->has_color[context_id]) {
+            DP_user_presence_remove(up, context_id);
+            return true;
+        }
+        else {
+            return false;
+        }
+    default:
+        return false;
+    }
+}
+
+void DP_user_presence_color_set(DP_UserPresence *up, unsigned int context_id,
+                                uint32_t color)
+{
+    DP_ASSERT(up);
+    DP_ASSERT(context_id < DP_USER_PRESENCE_COUNT);
+    set_color(up, context_id, color);
+}
+
+void DP_user_presence_remove(DP_UserPresence *up, unsigned int context_id)
+{
+    DP_ASSERT(up);
+    DP_ASSERT(context_id < DP_USER_PRESENCE_COUNT);
+    up->has_color[context_id] = false;
+    up->user_colors[context_id] = 0;
+}
+
+bool DP_user_presence_color(DP_UserPresence *up, unsigned int context_id,
+                            uint32_t *out_color)
+{
+    DP_ASSERT(up);
+    DP_ASSERT(context_id < DP_USER_PRESENCE_COUNT);
+    if (up->has_color[context_id]) {
+        if (out_color) {
+            *out_color = up->user_colors[context_id];
+        }
+        return true;
+    }
+    else {
+        return false;
+    }
+}
@@ -0,0 +1,36 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#ifndef DPENGINE_USER_PRESENCE_H
+#define DPENGINE_USER_PRESENCE_H
+#include <dpcommon/common.h>
+
+typedef struct DP_Message DP_Message;
+
+#define DP_USER_PRESENCE_COUNT 256
+
+// Presence information users send about themselves via client meta messages,
+// such as the color to tint their cursor and contributions with. Colors are
+// ARGB, like everywhere else in the protocol. Users are pruned when they leave.
+typedef struct DP_UserPresence {
+    bool has_color[DP_USER_PRESENCE_COUNT];
+    uint32_t user_colors[DP_USER_PRESENCE_COUNT];
+} DP_UserPresence;
+
+
+void DP_user_presence_init(DP_UserPresence *up);
+
+// Sets or unsets colors on client meta messages, prunes users on leave
+// messages. Returns true if the message changed anything.
+bool DP_user_presence_handle(DP_UserPresence *up, DP_Message *msg);
+
+// A color with an alpha of zero unsets it.
+void DP_user_presence_color_set(DP_UserPresence *up, unsigned int context_id,
+                                uint32_t color);
+
+void DP_user_presence_remove(DP_UserPresence *up, unsigned int context_id);
+
+// Returns false and leaves the output alone if the user has no color.
+bool DP_user_presence_color(DP_UserPresence *up, unsigned int context_id,
+                            uint32_t *out_color);
+
+
+#endif
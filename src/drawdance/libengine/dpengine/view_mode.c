@@ -763,13 +763,17 @@ static bool pick_frame(DP_CanvasState *cs, DP_LocalState *ls, int x, int y,
     return c.found;
 }
 
+static bool pick_in_bounds(DP_CanvasState *cs, int x, int y)
+{
+    return x >= 0 && y >= 0 && x < DP_canvas_state_width(cs)
+        && y < DP_canvas_state_height(cs);
+}
+
 DP_ViewModePick DP_view_mode_pick(DP_CanvasState *cs, DP_LocalState *ls, int x,
                                   int y)
 {
     DP_ASSERT(cs);
-    bool in_bounds = x >= 0 && y >= 0 && x < DP_canvas_state_width(cs)
-                  && y < DP_canvas_state_height(cs);
-    if (in_bounds) {
+    if (pick_in_bounds(cs, x, y)) {
         DP_ViewModePick pick;
         switch (DP_local_state_view_mode(ls)) {
         case DP_VIEW_MODE_NORMAL:
@@ -799,6 +803,18 @@ DP_ViewModePick DP_view_mode_pick(DP_CanvasState *cs, DP_LocalState *ls, int x,
     return (DP_ViewModePick){0, -1};
 }
 
+DP_ViewModePick DP_view_mode_pick_normal(DP_CanvasState *cs, int x, int y)
+{
+    DP_ASSERT(cs);
+    DP_ViewModePick pick;
+    if (pick_in_bounds(cs, x, y) && pick_normal(cs, x, y, &pick)) {
+        return pick;
+    }
+    else {
+        return (DP_ViewModePick){0, -1};
+    }
+}
+
 
 DP_OnionSkins *DP_onion_skins_new(bool wrap, int count_below, int count_above)
 {
@@ -164,6 +164,13 @@ void DP_view_mode_get_layers_visible_in_track_frame(DP_CanvasState *cs,
 DP_ViewModePick DP_view_mode_pick(DP_CanvasState *cs, DP_LocalState *ls, int x,
                                   int y);
 
+// Picks what's at the given point as seen in the normal view mode, without
+// needing any local state. This is how to find out who drew something, since
+// every tile remembers the last user that changed it. That attribution has
+// tile granularity: the user that touched a tile last is reported for all of
+// its pixels. Returns a layer id of -1 and context id 0 if there's nothing.
+DP_ViewModePick DP_view_mode_pick_normal(DP_CanvasState *cs, int x, int y);
+
 
 DP_OnionSkins *DP_onion_skins_new(bool wrap, int count_below, int count_above);
 DP_OnionSkins *DP_onion_skins_new_clone(DP_OnionSkins *oss);
@@ -0,0 +1,117 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include <dpcommon/common.h>
+#include <dpengine/brush.h>
+#include <dpengine/brush_engine.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+#include <mypaint-brush-settings.h>
+
+
+typedef struct DP_StrokeMessages {
+    int count;
+    DP_Message *msgs[256];
+} DP_StrokeMessages;
+
+static void push_stroke_message(void *user, DP_Message *msg)
+{
+    DP_StrokeMessages *sm = user;
+    if (sm->count < (int)DP_ARRAY_LENGTH(sm->msgs)) {
+        sm->msgs[sm->count++] = msg;
+    }
+    else {
+        DP_message_decref(msg);
+    }
+}
+
+static void dispose_stroke_messages(DP_StrokeMessages *sm)
+{
+    for (int i = 0; i < sm->count; ++i) {
+        DP_message_decref(sm->msgs[i]);
+    }
+    sm->count = 0;
+}
+
+static bool stroke_messages_equal(DP_StrokeMessages *a, DP_StrokeMessages *b)
+{
+    if (a->count != b->count) {
+        return false;
+    }
+    for (int i = 0; i < a->count; ++i) {
+        if (!DP_message_equals(a->msgs[i], b->msgs[i])) {
+            return false;
+        }
+    }
+    return true;
+}
+
+static void set_scattering_brush(DP_BrushEngine *be)
+{
+    static DP_MyPaintSettings settings;
+    for (int i = 0; i < MYPAINT_BRUSH_SETTINGS_COUNT; ++i) {
+        settings.mappings[i].base_value =
+            mypaint_brush_setting_info((MyPaintBrushSetting)i)->def;
+    }
+    settings.mappings[MYPAINT_BRUSH_SETTING_OPAQUE_MULTIPLY].base_value = 1.0f;
+    settings.mappings[MYPAINT_BRUSH_SETTING_OFFSET_BY_RANDOM].base_value = 2.0f;
+    settings.mappings[MYPAINT_BRUSH_SETTING_RADIUS_BY_RANDOM].base_value = 0.5f;
+
+    DP_MyPaintBrush brush = {{1.0f, 0.0f, 0.0f, 1.0f}, false, false, false};
+    DP_StrokeParams stroke = {0x101, false, 0, false, 0, false};
+    DP_brush_engine_mypaint_brush_set(be, &brush, &settings, &stroke, NULL,
+                                      false);
+}
+
+static void draw_stroke(DP_BrushEngine *be, float x)
+{
+    DP_brush_engine_stroke_begin(be, 1, false, 1.0f);
+    for (int i = 0; i < 20; ++i) {
+        DP_BrushPoint bp = {x + (float)i * 5.0f, 100.0f, 1.0f, 0.0f, 0.0f,
+                            0.0f, (long long)i * 10};
+        DP_brush_engine_stroke_to(be, bp, NULL);
+    }
+    DP_brush_engine_stroke_end(be, 200, NULL, false);
+}
+
+
+static void same_stroke_same_dabs(TEST_PARAMS)
+{
+    DP_StrokeMessages first = {0, {0}};
+    DP_BrushEngine *be = DP_brush_engine_new(push_stroke_message, NULL, &first);
+    set_scattering_brush(be);
+    draw_stroke(be, 50.0f);
+    OK(first.count > 0, "stroke produced messages");
+
+    DP_StrokeMessages second = {0, {0}};
+    DP_brush_engine_free(be);
+    be = DP_brush_engine_new(push_stroke_message, NULL, &second);
+    set_scattering_brush(be);
+    draw_stroke(be, 200.0f); // Advance the random number generator.
+    dispose_stroke_messages(&second);
+    draw_stroke(be, 50.0f);
+    OK(stroke_messages_equal(&first, &second),
+       "same stroke produces same dabs regardless of previous strokes");
+
+    DP_StrokeMessages third = {0, {0}};
+    DP_brush_engine_free(be);
+    be = DP_brush_engine_new(push_stroke_message, NULL, &third);
+    set_scattering_brush(be);
+    draw_stroke(be, 60.0f);
+    NOK(stroke_messages_equal(&first, &third),
+        "stroke elsewhere produces different dabs");
+
+    DP_brush_engine_free(be);
+    dispose_stroke_messages(&third);
+    dispose_stroke_messages(&second);
+    dispose_stroke_messages(&first);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(same_stroke_same_dabs);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,125 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include <dpcommon/common.h>
+#include <dpcommon/conversions.h>
+#include <dpengine/brush_engine.h>
+#include <dptest.h>
+
+#define POINT_COUNT 32
+
+
+// A horizontal line that zig-zags up and down, like a shaky hand would draw.
+static void make_jittery_stroke(DP_BrushPoint *points)
+{
+    for (int i = 0; i < POINT_COUNT; ++i) {
+        float jitter = i % 2 == 0 ? 3.0f : -3.0f;
+        points[i] = (DP_BrushPoint){DP_int_to_float(i) * 5.0f,
+                                    100.0f + jitter,
+                                    i % 3 == 0 ? 0.2f : 0.8f,
+                                    0.0f,
+                                    0.0f,
+                                    0.0f,
+                                    (long long)i * 10};
+    }
+}
+
+// Variance of the vectors from one point to the next, zero for evenly spaced
+// points along a straight line.
+static float step_variance(int count, const DP_BrushPoint *points)
+{
+    float n = DP_int_to_float(count - 1);
+    float mean_dx = (points[count - 1].x - points[0].x) / n;
+    float mean_dy = (points[count - 1].y - points[0].y) / n;
+    float variance = 0.0f;
+    for (int i = 1; i < count; ++i) {
+        float dx = points[i].x - points[i - 1].x - mean_dx;
+        float dy = points[i].y - points[i - 1].y - mean_dy;
+        variance += dx * dx + dy * dy;
+    }
+    return variance / n;
+}
+
+static bool points_equal(DP_BrushPoint a, DP_BrushPoint b)
+{
+    return a.x == b.x && a.y == b.y && a.pressure == b.pressure
+        && a.xtilt == b.xtilt && a.ytilt == b.ytilt && a.rotation == b.rotation
+        && a.time_msec == b.time_msec;
+}
+
+
+static void smooth_reduces_jitter(TEST_PARAMS)
+{
+    DP_BrushPoint points[POINT_COUNT];
+    DP_BrushPoint smoothed[POINT_COUNT];
+    make_jittery_stroke(points);
+    DP_brush_points_smooth(POINT_COUNT, points, 0.5f, smoothed);
+
+    OK(points_equal(smoothed[0], points[0]), "first point is fixed");
+    OK(points_equal(smoothed[POINT_COUNT - 1], points[POINT_COUNT - 1]),
+       "last point is fixed");
+    OK(step_variance(POINT_COUNT, smoothed)
+           < step_variance(POINT_COUNT, points) / 2.0f,
+       "smoothing reduces point-to-point variance");
+
+    bool times_kept = true;
+    for (int i = 0; i < POINT_COUNT; ++i) {
+        times_kept = times_kept && smoothed[i].time_msec == points[i].time_msec;
+    }
+    OK(times_kept, "timestamps are kept");
+
+    DP_BrushPoint stronger[POINT_COUNT];
+    DP_brush_points_smooth(POINT_COUNT, points, 1.0f, stronger);
+    OK(step_variance(POINT_COUNT, stronger)
+           <= step_variance(POINT_COUNT, smoothed),
+       "stronger smoothing doesn't increase variance");
+}
+
+static void smooth_strength_zero(TEST_PARAMS)
+{
+    DP_BrushPoint points[POINT_COUNT];
+    DP_BrushPoint smoothed[POINT_COUNT];
+    make_jittery_stroke(points);
+    DP_brush_points_smooth(POINT_COUNT, points, 0.0f, smoothed);
+    bool unchanged = true;
+    for (int i = 0; i < POINT_COUNT; ++i) {
+        unchanged = unchanged && points_equal(smoothed[i], points[i]);
+    }
+    OK(unchanged, "zero strength leaves points unchanged");
+}
+
+static void smooth_short_strokes(TEST_PARAMS)
+{
+    DP_BrushPoint points[POINT_COUNT];
+    DP_BrushPoint smoothed[POINT_COUNT];
+    make_jittery_stroke(points);
+
+    DP_brush_points_smooth(0, NULL, 1.0f, NULL);
+    OK(true, "smoothing nothing doesn't crash");
+
+    DP_brush_points_smooth(1, points, 1.0f, smoothed);
+    OK(points_equal(smoothed[0], points[0]), "single point is unchanged");
+
+    DP_brush_points_smooth(2, points, 1.0f, smoothed);
+    OK(points_equal(smoothed[0], points[0])
+           && points_equal(smoothed[1], points[1]),
+       "two points are unchanged");
+
+    DP_brush_points_smooth(3, points, 1.0f, smoothed);
+    OK(points_equal(smoothed[0], points[0])
+           && points_equal(smoothed[2], points[2]),
+       "three points keep their endpoints");
+    OK(smoothed[1].x == 5.0f && smoothed[1].y == 101.0f,
+       "middle of three points is averaged");
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(smooth_reduces_jitter);
+    REGISTER_TEST(smooth_strength_zero);
+    REGISTER_TEST(smooth_short_strokes);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,167 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include <dpcommon/common.h>
+#include <dpcommon/conversions.h>
+#include <dpengine/canvas_state.h>
+#include <dpengine/draw_context.h>
+#include <dpengine/image.h>
+#include <dpengine/layer_content.h>
+#include <dpengine/layer_list.h>
+#include <dpengine/layer_props.h>
+#include <dpengine/layer_props_list.h>
+#include <dpengine/pixels.h>
+#include <dpengine/snapshots.h>
+#include <dpengine/tile.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+
+
+// Not aligned to the tile size, so the right and bottom tiles are partial.
+#define IMAGE_WIDTH  (DP_TILE_SIZE + 36)
+#define IMAGE_HEIGHT (DP_TILE_SIZE + 6)
+#define MAX_MESSAGES 16
+
+
+typedef struct CollectedMessages {
+    int count;
+    DP_Message *msgs[MAX_MESSAGES];
+} CollectedMessages;
+
+static DP_Pixel8 pattern_at(int x, int y)
+{
+    return (DP_Pixel8){.b = DP_int_to_uint8(x), .g = DP_int_to_uint8(y),
+                       .r = DP_int_to_uint8((x + y) & 0xff), .a = 0xff};
+}
+
+static DP_Image *pattern_new(void)
+{
+    DP_Image *img = DP_image_new(IMAGE_WIDTH, IMAGE_HEIGHT);
+    for (int y = 0; y < IMAGE_HEIGHT; ++y) {
+        for (int x = 0; x < IMAGE_WIDTH; ++x) {
+            DP_image_pixel_at_set(img, x, y, pattern_at(x, y));
+        }
+    }
+    return img;
+}
+
+static int count_mismatches(DP_CanvasState *cs)
+{
+    DP_Image *img = DP_canvas_state_to_flat_image(
+        cs, DP_FLAT_IMAGE_RENDER_FLAGS, NULL, NULL);
+    if (!img) {
+        return -1;
+    }
+    int mismatches = 0;
+    for (int y = 0; y < IMAGE_HEIGHT; ++y) {
+        for (int x = 0; x < IMAGE_WIDTH; ++x) {
+            if (DP_image_pixel_at(img, x, y).color != pattern_at(x, y).color) {
+                ++mismatches;
+            }
+        }
+    }
+    DP_image_free(img);
+    return mismatches;
+}
+
+static void collect_message(void *user, DP_Message *msg)
+{
+    CollectedMessages *collected = user;
+    if (collected->count < MAX_MESSAGES) {
+        collected->msgs[collected->count++] = msg;
+    }
+    else {
+        DP_message_decref(msg);
+    }
+}
+
+
+static void canvas_from_image(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_Image *img = pattern_new();
+    DP_CanvasState *cs =
+        DP_canvas_state_new_from_image(dc, img, 1, 0x100, "image", 5);
+    DP_image_free(img);
+
+    INT_EQ_OK(DP_canvas_state_width(cs), IMAGE_WIDTH, "canvas has image width");
+    INT_EQ_OK(DP_canvas_state_height(cs), IMAGE_HEIGHT,
+              "canvas has image height");
+
+    DP_LayerPropsList *lpl = DP_canvas_state_layer_props_noinc(cs);
+    FATAL(INT_EQ_OK(DP_layer_props_list_count(lpl), 1, "canvas has one layer"));
+    DP_LayerProps *lp = DP_layer_props_list_at_noinc(lpl, 0);
+    INT_EQ_OK(DP_layer_props_id(lp), 0x100, "layer has the given id");
+    size_t title_length;
+    const char *title = DP_layer_props_title(lp, &title_length);
+    OK(title_length == 5 && memcmp(title, "image", 5) == 0,
+       "layer has the given title");
+
+    DP_LayerContent *lc = DP_layer_list_entry_content_noinc(
+        DP_layer_list_at_noinc(DP_canvas_state_layers_noinc(cs), 0));
+    for (int y = 0; y < 2; ++y) {
+        for (int x = 0; x < 2; ++x) {
+            NOT_NULL_OK(DP_layer_content_tile_at_noinc(lc, x, y),
+                        "tile %d, %d has content", x, y);
+        }
+    }
+    OK(DP_pixel15_to_8(DP_layer_content_pixel_at(lc, DP_TILE_SIZE - 1, 0))
+               .color
+           == pattern_at(DP_TILE_SIZE - 1, 0).color,
+       "last pixel of the first tile");
+    OK(DP_pixel15_to_8(DP_layer_content_pixel_at(lc, DP_TILE_SIZE, 0)).color
+           == pattern_at(DP_TILE_SIZE, 0).color,
+       "first pixel of the second tile");
+    OK(DP_pixel15_to_8(DP_layer_content_pixel_at(lc, IMAGE_WIDTH - 1,
+                                                 IMAGE_HEIGHT - 1))
+               .color
+           == pattern_at(IMAGE_WIDTH - 1, IMAGE_HEIGHT - 1).color,
+       "last pixel of the partial tile");
+    INT_EQ_OK(count_mismatches(cs), 0, "canvas matches the image");
+
+    CollectedMessages collected = {0, {NULL}};
+    DP_reset_image_build(cs, 1, collect_message, &collected);
+    int put_tile_count = 0;
+    bool tiles_seen[2][2] = {{false, false}, {false, false}};
+    DP_CanvasState *replayed = DP_canvas_state_new();
+    for (int i = 0; i < collected.count; ++i) {
+        DP_Message *msg = collected.msgs[i];
+        if (DP_message_type(msg) == DP_MSG_PUT_TILE) {
+            DP_MsgPutTile *mpt = DP_message_internal(msg);
+            int col = DP_msg_put_tile_col(mpt);
+            int row = DP_msg_put_tile_row(mpt);
+            if (col < 2 && row < 2) {
+                tiles_seen[row][col] = true;
+            }
+            ++put_tile_count;
+        }
+        if (replayed) {
+            DP_CanvasState *next =
+                DP_canvas_state_handle(replayed, dc, NULL, msg);
+            DP_canvas_state_decref(replayed);
+            replayed = next;
+        }
+        DP_message_decref(msg);
+    }
+    INT_EQ_OK(put_tile_count, 4, "one put tile message per tile");
+    OK(tiles_seen[0][0] && tiles_seen[0][1] && tiles_seen[1][0]
+           && tiles_seen[1][1],
+       "put tile messages cover all tiles");
+    if (NOT_NULL_OK(replayed, "replayed messages onto blank canvas")) {
+        INT_EQ_OK(count_mismatches(replayed), 0,
+                  "replayed canvas matches the image");
+        DP_canvas_state_decref(replayed);
+    }
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(canvas_from_image);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,23 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include <dpcommon/common.h>
+#include <dpengine/canvas_state.h>
+#include <dpengine/draw_context.h>
+#include <dpmsg/message.h>
+
+
+// Applies the message to the canvas state, taking ownership of both. If that
+// fails, it warns and hands back the canvas state it was given.
+DP_UNUSED static DP_CanvasState *handle(DP_CanvasState *cs, DP_DrawContext *dc,
+                                        DP_Message *msg)
+{
+    DP_CanvasState *next = DP_canvas_state_handle(cs, dc, NULL, msg);
+    DP_message_decref(msg);
+    if (next) {
+        DP_canvas_state_decref(cs);
+        return next;
+    }
+    else {
+        DP_warn("Handle: %s", DP_error());
+        return cs;
+    }
+}
@@ -0,0 +1,203 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "canvas_state_common.h"
+#include <dpcommon/common.h>
+#include <dpcommon/conversions.h>
+#include <dpengine/canvas_state.h>
+#include <dpengine/draw_context.h>
+#include <dpengine/image.h>
+#include <dpengine/pixels.h>
+#include <dpengine/snapshots.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+
+#define RED   0xffff0000u
+#define GREEN 0xff00ff00u
+
+
+static DP_CanvasState *fill_rect(DP_CanvasState *cs, DP_DrawContext *dc,
+                                 int layer_id, uint32_t x, uint32_t y,
+                                 uint32_t w, uint32_t h, uint32_t color)
+{
+    return handle(cs, dc,
+                  DP_msg_fill_rect_new(1, DP_int_to_uint16(layer_id),
+                                       DP_BLEND_MODE_NORMAL, x, y, w, h,
+                                       color));
+}
+
+// A canvas that's neither square nor a multiple of the tile size, with a red
+// rectangle on one layer and a green pixel in the top-left corner on another.
+static DP_CanvasState *canvas_new(DP_DrawContext *dc)
+{
+    DP_CanvasState *cs = DP_canvas_state_new();
+    cs = handle(cs, dc, DP_msg_canvas_resize_new(1, 0, 100, 60, 0));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "a", 1));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(1, 0x102, 0, 0, 0, 0, "b", 1));
+    cs = fill_rect(cs, dc, 0x101, 10, 5, 20, 8, RED);
+    cs = fill_rect(cs, dc, 0x102, 0, 0, 1, 1, GREEN);
+    return cs;
+}
+
+static DP_Image *flatten(DP_CanvasState *cs)
+{
+    return DP_canvas_state_to_flat_image(cs, DP_FLAT_IMAGE_RENDER_FLAGS, NULL,
+                                         NULL);
+}
+
+static uint32_t pixel_at(DP_Image *img, int x, int y)
+{
+    return DP_image_pixel_at(img, x, y).color;
+}
+
+// Checks that every pixel of the original ended up where the transform says.
+static void check_mapped(TEST_PARAMS, DP_Image *original, DP_Image *transformed,
+                         DP_CanvasTransform transform, const char *title)
+{
+    int width = DP_image_width(original);
+    int height = DP_image_height(original);
+    int mismatches = 0;
+    for (int y = 0; y < height; ++y) {
+        for (int x = 0; x < width; ++x) {
+            int tx, ty;
+            DP_canvas_transform_point(transform, width, height, x, y, &tx, &ty);
+            if (pixel_at(original, x, y) != pixel_at(transformed, tx, ty)) {
+                ++mismatches;
+            }
+        }
+    }
+    INT_EQ_OK(mismatches, 0, "%s: all pixels mapped", title);
+}
+
+static bool images_equal(DP_Image *a, DP_Image *b)
+{
+    int width = DP_image_width(a);
+    int height = DP_image_height(a);
+    if (width != DP_image_width(b) || height != DP_image_height(b)) {
+        return false;
+    }
+    for (int y = 0; y < height; ++y) {
+        for (int x = 0; x < width; ++x) {
+            if (pixel_at(a, x, y) != pixel_at(b, x, y)) {
+                return false;
+            }
+        }
+    }
+    return true;
+}
+
+typedef struct DP_Replay {
+    DP_CanvasState *cs;
+    DP_DrawContext *dc;
+} DP_Replay;
+
+static void replay_message(void *user, DP_Message *msg)
+{
+    DP_Replay *r = user;
+    r->cs = handle(r->cs, r->dc, msg);
+}
+
+
+static void canvas_transform_rotate_clockwise(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc);
+    DP_CanvasState *rotated = DP_canvas_state_transform_all(
+        cs, 1, DP_CANVAS_TRANSFORM_ROTATE_CLOCKWISE);
+
+    INT_EQ_OK(DP_canvas_state_width(rotated), 60, "width swapped");
+    INT_EQ_OK(DP_canvas_state_height(rotated), 100, "height swapped");
+
+    DP_Image *img = flatten(rotated);
+    // The rectangle from (10, 5) to (29, 12) turns into (47, 10) to (54, 29).
+    UINT_EQ_OK(pixel_at(img, 54, 10), RED, "rect's top-left turned top-right");
+    UINT_EQ_OK(pixel_at(img, 47, 29), RED,
+               "rect's bottom-right turned bottom-left");
+    UINT_EQ_OK(pixel_at(img, 46, 20), 0, "nothing left of rotated rect");
+    UINT_EQ_OK(pixel_at(img, 55, 20), 0, "nothing right of rotated rect");
+    UINT_EQ_OK(pixel_at(img, 50, 30), 0, "nothing below rotated rect");
+    UINT_EQ_OK(pixel_at(img, 10, 5), 0, "nothing at the rect's old spot");
+    UINT_EQ_OK(pixel_at(img, 59, 0), GREEN, "corner pixel on the other layer");
+    UINT_EQ_OK(pixel_at(img, 0, 0), 0, "nothing at corner pixel's old spot");
+
+    DP_Image *original = flatten(cs);
+    check_mapped(TEST_ARGS, original, img, DP_CANVAS_TRANSFORM_ROTATE_CLOCKWISE,
+                 "clockwise");
+    DP_image_free(original);
+    DP_image_free(img);
+
+    DP_CanvasState *back = DP_canvas_state_transform_all(
+        rotated, 1, DP_CANVAS_TRANSFORM_ROTATE_COUNTERCLOCKWISE);
+    img = flatten(back);
+    original = flatten(cs);
+    OK(images_equal(img, original), "rotating back restores the original");
+    DP_image_free(original);
+    DP_image_free(img);
+
+    DP_canvas_state_decref(back);
+    DP_canvas_state_decref(rotated);
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void canvas_transform_all_kinds(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc);
+    DP_Image *original = flatten(cs);
+
+    struct {
+        DP_CanvasTransform transform;
+        const char *title;
+        int width, height;
+    } cases[] = {
+        {DP_CANVAS_TRANSFORM_ROTATE_CLOCKWISE, "clockwise", 60, 100},
+        {DP_CANVAS_TRANSFORM_ROTATE_COUNTERCLOCKWISE, "counterclockwise", 60,
+         100},
+        {DP_CANVAS_TRANSFORM_ROTATE_180, "180 degrees", 100, 60},
+        {DP_CANVAS_TRANSFORM_FLIP_HORIZONTAL, "horizontal flip", 100, 60},
+        {DP_CANVAS_TRANSFORM_FLIP_VERTICAL, "vertical flip", 100, 60},
+    };
+
+    for (size_t i = 0; i < DP_ARRAY_LENGTH(cases); ++i) {
+        DP_CanvasState *transformed =
+            DP_canvas_state_transform_all(cs, 1, cases[i].transform);
+        INT_EQ_OK(DP_canvas_state_width(transformed), cases[i].width,
+                  "%s: width", cases[i].title);
+        INT_EQ_OK(DP_canvas_state_height(transformed), cases[i].height,
+                  "%s: height", cases[i].title);
+
+        DP_Image *img = flatten(transformed);
+        check_mapped(TEST_ARGS, original, img, cases[i].transform,
+                     cases[i].title);
+
+        // Replaying the diff on a copy of the original must give the same.
+        DP_Replay r = {DP_canvas_state_incref(cs), dc};
+        DP_reset_image_diff_build(transformed, cs, 1, replay_message, &r);
+        DP_Image *replayed_img = flatten(r.cs);
+        OK(images_equal(img, replayed_img), "%s: diff replays the transform",
+           cases[i].title);
+
+        DP_image_free(replayed_img);
+        DP_canvas_state_decref(r.cs);
+        DP_image_free(img);
+        DP_canvas_state_decref(transformed);
+    }
+
+    DP_image_free(original);
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(canvas_transform_rotate_clockwise);
+    REGISTER_TEST(canvas_transform_all_kinds);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,107 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include <dpcommon/common.h>
+#include <dpengine/brush.h>
+#include <dptest.h>
+
+#define TOLERANCE 0.01f
+
+
+// The curve y = x^2, which stays below the straight line and so makes the
+// brush respond less to light pressure.
+static void set_convex_curve(DP_ClassicBrushRange *cbr, float min, float max)
+{
+    cbr->min = min;
+    cbr->max = max;
+    float fmax = DP_CLASSIC_BRUSH_CURVE_VALUE_COUNT - 1;
+    for (int i = 0; i < DP_CLASSIC_BRUSH_CURVE_VALUE_COUNT; ++i) {
+        float x = DP_int_to_float(i) / fmax;
+        cbr->curve.values[i] = x * x;
+    }
+}
+
+static void set_linear_curve(DP_ClassicBrushRange *cbr, float min, float max)
+{
+    cbr->min = min;
+    cbr->max = max;
+    float fmax = DP_CLASSIC_BRUSH_CURVE_VALUE_COUNT - 1;
+    for (int i = 0; i < DP_CLASSIC_BRUSH_CURVE_VALUE_COUNT; ++i) {
+        cbr->curve.values[i] = DP_int_to_float(i) / fmax;
+    }
+}
+
+static DP_ClassicBrush pressure_brush(void)
+{
+    DP_ClassicBrush cb = {0};
+    cb.shape = DP_BRUSH_SHAPE_CLASSIC_SOFT_ROUND;
+    cb.spacing = 0.1f;
+    cb.brush_mode = DP_BLEND_MODE_NORMAL;
+    cb.erase_mode = DP_BLEND_MODE_ERASE;
+    cb.size_dynamic.type = DP_CLASSIC_BRUSH_DYNAMIC_PRESSURE;
+    cb.opacity_dynamic.type = DP_CLASSIC_BRUSH_DYNAMIC_PRESSURE;
+    set_linear_curve(&cb.size, 1.0f, 21.0f);
+    set_linear_curve(&cb.opacity, 0.0f, 1.0f);
+    return cb;
+}
+
+static bool float_ok(TEST_PARAMS, float actual, float expected,
+                     const char *what)
+{
+    return OK(actual >= expected - TOLERANCE && actual <= expected + TOLERANCE,
+              "%s is %f, expected %f", what, (double)actual, (double)expected);
+}
+
+
+static void classic_brush_curve_linear(TEST_PARAMS)
+{
+    DP_ClassicBrush cb = pressure_brush();
+    float_ok(TEST_ARGS, DP_classic_brush_size_at(&cb, 0.0f, 0.0f, 0.0f), 1.0f,
+             "size at no pressure");
+    float_ok(TEST_ARGS, DP_classic_brush_size_at(&cb, 0.5f, 0.0f, 0.0f),
+             11.0f, "size at half pressure");
+    float_ok(TEST_ARGS, DP_classic_brush_size_at(&cb, 1.0f, 0.0f, 0.0f),
+             21.0f, "size at full pressure");
+    float_ok(TEST_ARGS, DP_classic_brush_opacity_at(&cb, 0.5f, 0.0f, 0.0f),
+             0.5f, "opacity at half pressure");
+}
+
+static void classic_brush_curve_convex(TEST_PARAMS)
+{
+    DP_ClassicBrush cb = pressure_brush();
+    set_convex_curve(&cb.size, 1.0f, 21.0f);
+    set_convex_curve(&cb.opacity, 0.0f, 1.0f);
+
+    float_ok(TEST_ARGS, DP_classic_brush_size_at(&cb, 0.0f, 0.0f, 0.0f), 1.0f,
+             "size at no pressure");
+    // A quarter of the way from the minimum to the maximum.
+    float_ok(TEST_ARGS, DP_classic_brush_size_at(&cb, 0.5f, 0.0f, 0.0f), 6.0f,
+             "size at half pressure");
+    float_ok(TEST_ARGS, DP_classic_brush_size_at(&cb, 1.0f, 0.0f, 0.0f),
+             21.0f, "size at full pressure");
+    UINT_EQ_OK(DP_classic_brush_soft_dab_size_at(&cb, 0.5f, 0.0f, 0.0f),
+               6 * 256, "soft dab size at half pressure");
+
+    float_ok(TEST_ARGS, DP_classic_brush_opacity_at(&cb, 0.5f, 0.0f, 0.0f),
+             0.25f, "opacity at half pressure");
+}
+
+static void classic_brush_curve_dynamic_none(TEST_PARAMS)
+{
+    DP_ClassicBrush cb = pressure_brush();
+    set_convex_curve(&cb.size, 1.0f, 21.0f);
+    cb.size_dynamic.type = DP_CLASSIC_BRUSH_DYNAMIC_NONE;
+    float_ok(TEST_ARGS, DP_classic_brush_size_at(&cb, 0.5f, 0.0f, 0.0f),
+             21.0f, "size without dynamics ignores the curve");
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(classic_brush_curve_convex);
+    REGISTER_TEST(classic_brush_curve_dynamic_none);
+    REGISTER_TEST(classic_brush_curve_linear);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,97 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "canvas_state_common.h"
+#include <dpcommon/common.h>
+#include <dpcommon/geom.h>
+#include <dpcommon/output.h>
+#include <dpengine/canvas_state.h>
+#include <dpengine/draw_context.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+
+
+static DP_CanvasState *canvas_new(DP_DrawContext *dc)
+{
+    uint8_t group_flags = DP_MSG_LAYER_TREE_CREATE_FLAGS_GROUP;
+    uint8_t into_flags = DP_MSG_LAYER_TREE_CREATE_FLAGS_INTO;
+    DP_CanvasState *cs = DP_canvas_state_new();
+    cs = handle(cs, dc, DP_msg_canvas_resize_new(1, 0, 300, 200, 0));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "a", 1));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(1, 0x102, 0, 0, 0, group_flags,
+                                             "g", 1));
+    return handle(cs, dc,
+                  DP_msg_layer_tree_create_new(1, 0x103, 0, 0x102, 0,
+                                               into_flags, "b", 1));
+}
+
+static DP_Message *fill_rect_new(int layer_id, int blend_mode, int x, int y,
+                                 int width, int height)
+{
+    return DP_msg_fill_rect_new(
+        1, DP_int_to_uint16(layer_id), DP_int_to_uint8(blend_mode),
+        DP_int_to_uint32(x), DP_int_to_uint32(y), DP_int_to_uint32(width),
+        DP_int_to_uint32(height), 0xff000000u);
+}
+
+static void check_bounds(TEST_PARAMS, DP_CanvasState *cs, int x, int y,
+                         int width, int height, const char *title)
+{
+    DP_Rect bounds;
+    if (OK(DP_canvas_state_content_bounds(cs, &bounds), "%s: has bounds",
+           title)) {
+        INT_EQ_OK(DP_rect_x(bounds), x, "%s: x", title);
+        INT_EQ_OK(DP_rect_y(bounds), y, "%s: y", title);
+        INT_EQ_OK(DP_rect_width(bounds), width, "%s: width", title);
+        INT_EQ_OK(DP_rect_height(bounds), height, "%s: height", title);
+    }
+}
+
+
+static void content_bounds_empty(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc);
+    NOK(DP_canvas_state_content_bounds(cs, NULL), "blank canvas has no bounds");
+
+    cs = handle(cs, dc,
+                fill_rect_new(0x101, DP_BLEND_MODE_NORMAL, 5, 5, 10, 10));
+    cs = handle(cs, dc,
+                fill_rect_new(0x101, DP_BLEND_MODE_ERASE, 0, 0, 50, 50));
+    NOK(DP_canvas_state_content_bounds(cs, NULL),
+        "erased canvas has no bounds");
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void content_bounds_corner(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc);
+
+    cs = handle(cs, dc,
+                fill_rect_new(0x103, DP_BLEND_MODE_NORMAL, 290, 190, 10, 10));
+    check_bounds(TEST_ARGS, cs, 290, 190, 10, 10,
+                 "content in bottom right corner inside a group");
+
+    cs = handle(cs, dc, fill_rect_new(0x101, DP_BLEND_MODE_NORMAL, 0, 3, 1, 1));
+    check_bounds(TEST_ARGS, cs, 0, 3, 300, 197,
+                 "content in two corners on different layers");
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(content_bounds_empty);
+    REGISTER_TEST(content_bounds_corner);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,92 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "canvas_state_common.h"
+#include <dpcommon/binary.h>
+#include <dpcommon/common.h>
+#include <dpcommon/output.h>
+#include <dpengine/canvas_state.h>
+#include <dpengine/draw_context.h>
+#include <dpengine/image.h>
+#include <dpengine/pixels.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+
+
+static void set_background_color(DP_UNUSED size_t size, unsigned char *out,
+                                 void *user)
+{
+    DP_write_bigendian_uint32(*(uint32_t *)user, out);
+}
+
+// A single layer filled with red, then erased on its left half.
+static DP_CanvasState *canvas_new(DP_DrawContext *dc)
+{
+    DP_CanvasState *cs = DP_canvas_state_new();
+    cs = handle(cs, dc, DP_msg_canvas_resize_new(1, 0, 64, 64, 0));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "l", 1));
+    cs = handle(cs, dc,
+                DP_msg_fill_rect_new(1, 0x101, DP_BLEND_MODE_NORMAL, 0, 0, 64,
+                                     64, 0xffff0000u));
+    return handle(cs, dc,
+                  DP_msg_fill_rect_new(1, 0x101, DP_BLEND_MODE_ERASE, 0, 0, 32,
+                                       64, 0xff000000u));
+}
+
+static DP_CanvasState *set_background(DP_CanvasState *cs, DP_DrawContext *dc,
+                                      uint32_t color)
+{
+    return handle(cs, dc,
+                  DP_msg_canvas_background_new(1, set_background_color, 4,
+                                               &color));
+}
+
+static void check_pixel(TEST_PARAMS, DP_CanvasState *cs, unsigned int flags,
+                        int x, int y, uint32_t expected, const char *title)
+{
+    DP_Image *img = DP_canvas_state_to_flat_image(cs, flags, NULL, NULL);
+    if (NOT_NULL_OK(img, "%s: flattened", title)) {
+        UINT_EQ_OK(DP_image_pixel_at(img, x, y).color, expected, "%s", title);
+        DP_image_free(img);
+    }
+}
+
+
+static void erase_without_background(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc);
+    check_pixel(TEST_ARGS, cs, DP_FLAT_IMAGE_INCLUDE_BACKGROUND, 10, 10, 0,
+                "erased pixel is transparent without a background");
+    check_pixel(TEST_ARGS, cs, DP_FLAT_IMAGE_INCLUDE_BACKGROUND, 50, 10,
+                0xffff0000u, "unerased pixel is intact");
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void erase_with_background(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = set_background(canvas_new(dc), dc, 0xffffffffu);
+    OK(DP_canvas_state_background_opaque(cs), "background is opaque");
+    check_pixel(TEST_ARGS, cs, DP_FLAT_IMAGE_INCLUDE_BACKGROUND, 10, 10,
+                0xffffffffu, "erased pixel reveals the background");
+    check_pixel(TEST_ARGS, cs, DP_FLAT_IMAGE_INCLUDE_BACKGROUND, 50, 10,
+                0xffff0000u, "unerased pixel covers the background");
+    check_pixel(TEST_ARGS, cs, 0, 10, 10, 0,
+                "erased pixel is transparent when excluding the background");
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(erase_without_background);
+    REGISTER_TEST(erase_with_background);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
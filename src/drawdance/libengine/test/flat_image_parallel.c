@@ -0,0 +1,135 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "canvas_state_common.h"
+#include <dpcommon/common.h>
+#include <dpcommon/conversions.h>
+#include <dpcommon/geom.h>
+#include <dpengine/canvas_state.h>
+#include <dpengine/draw_context.h>
+#include <dpengine/image.h>
+#include <dpengine/pixels.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+
+
+static void set_background(size_t size, unsigned char *out, void *user)
+{
+    memcpy(out, user, size);
+}
+
+static DP_Message *fill_rect_new(int layer_id, int blend_mode, int x, int y,
+                                 int width, int height, uint32_t color)
+{
+    return DP_msg_fill_rect_new(
+        1, DP_int_to_uint16(layer_id), DP_int_to_uint8(blend_mode),
+        DP_int_to_uint32(x), DP_int_to_uint32(y), DP_int_to_uint32(width),
+        DP_int_to_uint32(height), color);
+}
+
+// A canvas with a few partially overlapping layers and blend modes, large
+// enough to be flattened in parallel.
+static DP_CanvasState *canvas_new(DP_DrawContext *dc, int width, int height)
+{
+    DP_CanvasState *cs = DP_canvas_state_new();
+    cs = handle(cs, dc,
+                DP_msg_canvas_resize_new(1, 0, DP_int_to_int32(width),
+                                         DP_int_to_int32(height), 0));
+    unsigned char background[] = {0xee, 0xdd, 0xcc, 0xff};
+    cs = handle(cs, dc,
+                DP_msg_canvas_background_new(1, set_background,
+                                             sizeof(background), background));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "a", 1));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(1, 0x102, 0, 0, 0, 0, "b", 1));
+    cs = handle(cs, dc,
+                fill_rect_new(0x101, DP_BLEND_MODE_NORMAL, 10, 20, width / 2,
+                              height - 40, 0xff3366ccu));
+    cs = handle(cs, dc,
+                fill_rect_new(0x102, DP_BLEND_MODE_NORMAL, width / 3, 0,
+                              width / 2, height / 2, 0x8033cc66u));
+    cs = handle(cs, dc,
+                fill_rect_new(0x102, DP_BLEND_MODE_MULTIPLY, 0, height / 3,
+                              width, height / 4, 0xc0cc3366u));
+    cs = handle(cs, dc,
+                fill_rect_new(0x101, DP_BLEND_MODE_ERASE, width / 4,
+                              height / 4, 77, 55, 0xff000000u));
+    return cs;
+}
+
+static bool images_equal(DP_Image *a, DP_Image *b)
+{
+    int width = DP_image_width(a);
+    int height = DP_image_height(a);
+    return width == DP_image_width(b) && height == DP_image_height(b)
+        && memcmp(DP_image_pixels(a), DP_image_pixels(b),
+                  sizeof(DP_Pixel8) * DP_int_to_size(width)
+                      * DP_int_to_size(height))
+               == 0;
+}
+
+static void check_flat_images_equal(TEST_PARAMS, DP_CanvasState *cs,
+                                    unsigned int flags, const DP_Rect *area,
+                                    int thread_count, const char *title)
+{
+    DP_Image *serial = DP_canvas_state_to_flat_image(cs, flags, area, NULL);
+    DP_Image *parallel = DP_canvas_state_to_flat_image_parallel(
+        cs, flags, area, NULL, thread_count);
+    if (NOT_NULL_OK(serial, "%s: serial image", title)
+        && NOT_NULL_OK(parallel, "%s: parallel image", title)) {
+        OK(images_equal(serial, parallel), "%s: images are identical", title);
+    }
+    DP_image_free(parallel);
+    DP_image_free(serial);
+}
+
+
+static void flat_image_parallel_identical(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc, 1000, 700);
+
+    check_flat_images_equal(TEST_ARGS, cs, DP_FLAT_IMAGE_RENDER_FLAGS, NULL, 4,
+                            "whole canvas");
+    check_flat_images_equal(TEST_ARGS, cs, DP_FLAT_IMAGE_INCLUDE_SUBLAYERS,
+                            NULL, 3, "without background");
+    check_flat_images_equal(
+        TEST_ARGS, cs, DP_FLAT_IMAGE_RENDER_FLAGS | DP_FLAT_IMAGE_ONE_BIT_ALPHA,
+        NULL, 8, "one bit alpha");
+
+    DP_Rect area = DP_rect_make(33, 17, 700, 600);
+    check_flat_images_equal(TEST_ARGS, cs, DP_FLAT_IMAGE_RENDER_FLAGS, &area, 4,
+                            "unaligned area");
+    DP_Rect outside = DP_rect_make(900, 600, 300, 300);
+    check_flat_images_equal(TEST_ARGS, cs, DP_FLAT_IMAGE_RENDER_FLAGS,
+                            &outside, 4, "area partly outside of canvas");
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void flat_image_parallel_small(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc, 100, 80);
+
+    check_flat_images_equal(TEST_ARGS, cs, DP_FLAT_IMAGE_RENDER_FLAGS, NULL, 4,
+                            "canvas below threshold");
+    check_flat_images_equal(TEST_ARGS, cs, DP_FLAT_IMAGE_RENDER_FLAGS, NULL, 1,
+                            "single thread");
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(flat_image_parallel_identical);
+    REGISTER_TEST(flat_image_parallel_small);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -20,7 +20,9 @@
  * SOFTWARE.
  */
 #include "handle_common.h"
+#include "canvas_state_common.h"
 #include <dpengine/annotation.h>
+#include <dpengine/annotation_index.h>
 #include <dpengine/annotation_list.h>
 #include <parson.h>
 
@@ -175,6 +177,323 @@ static void handle_annotations(DP_Output *output, DP_CanvasHistory *ch,
 }
 
 
+static DP_CanvasState *bounds_canvas_new(void)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = DP_canvas_state_new();
+    DP_Message *msg = DP_msg_canvas_resize_new(1, 0, 200, 100, 0);
+    DP_CanvasState *next = DP_canvas_state_handle(cs, dc, NULL, msg);
+    DP_message_decref(msg);
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+    return next;
+}
+
+static void check_reshape(TEST_PARAMS, DP_CanvasState *cs,
+                          DP_AnnotationBoundsPolicy policy, int x, int y,
+                          int expected_x, int expected_y, const char *title)
+{
+    DP_Message *msg = DP_msg_annotation_reshape_new(1, 0x101, x, y, 50, 20);
+    DP_Message *result =
+        DP_canvas_state_annotation_reshape_bounded(cs, msg, policy);
+    if (NOT_NULL_OK(result, "%s: reshape let through", title)) {
+        DP_MsgAnnotationReshape *mar = DP_msg_annotation_reshape_cast(result);
+        INT_EQ_OK(DP_msg_annotation_reshape_x(mar), expected_x, "%s: x",
+                  title);
+        INT_EQ_OK(DP_msg_annotation_reshape_y(mar), expected_y, "%s: y",
+                  title);
+        UINT_EQ_OK(DP_msg_annotation_reshape_w(mar), 50, "%s: width kept",
+                   title);
+        UINT_EQ_OK(DP_msg_annotation_reshape_h(mar), 20, "%s: height kept",
+                   title);
+        OK((result == msg) == (x == expected_x && y == expected_y),
+           "%s: message only replaced if moved", title);
+        DP_message_decref(result);
+    }
+    DP_message_decref(msg);
+}
+
+static void check_rejected(TEST_PARAMS, DP_CanvasState *cs, int x, int y,
+                           const char *title)
+{
+    DP_Message *msg = DP_msg_annotation_reshape_new(1, 0x101, x, y, 50, 20);
+    NULL_OK(DP_canvas_state_annotation_reshape_bounded(
+                cs, msg, DP_ANNOTATION_BOUNDS_REJECT),
+            "%s: reshape rejected", title);
+    DP_message_decref(msg);
+}
+
+
+static void annotation_bounds_partly_outside(TEST_PARAMS)
+{
+    DP_CanvasState *cs = bounds_canvas_new();
+    DP_AnnotationBoundsPolicy policies[] = {
+        DP_ANNOTATION_BOUNDS_ALLOW,
+        DP_ANNOTATION_BOUNDS_CLAMP,
+        DP_ANNOTATION_BOUNDS_REJECT,
+    };
+    for (size_t i = 0; i < DP_ARRAY_LENGTH(policies); ++i) {
+        check_reshape(TEST_ARGS, cs, policies[i], 10, 10, 10, 10, "inside");
+        check_reshape(TEST_ARGS, cs, policies[i], -49, -19, -49, -19,
+                      "top-left corner pixel on canvas");
+        check_reshape(TEST_ARGS, cs, policies[i], 199, 99, 199, 99,
+                      "bottom-right corner pixel on canvas");
+        check_reshape(TEST_ARGS, cs, policies[i], 180, 50, 180, 50,
+                      "sticking out the right side");
+    }
+    DP_canvas_state_decref(cs);
+}
+
+static void annotation_bounds_fully_outside(TEST_PARAMS)
+{
+    DP_CanvasState *cs = bounds_canvas_new();
+    check_reshape(TEST_ARGS, cs, DP_ANNOTATION_BOUNDS_ALLOW, 500, -500, 500,
+                  -500, "allowed outside");
+
+    check_reshape(TEST_ARGS, cs, DP_ANNOTATION_BOUNDS_CLAMP, -50, 10, 0, 10,
+                  "clamped from the left");
+    check_reshape(TEST_ARGS, cs, DP_ANNOTATION_BOUNDS_CLAMP, 200, 10, 150, 10,
+                  "clamped from the right");
+    check_reshape(TEST_ARGS, cs, DP_ANNOTATION_BOUNDS_CLAMP, 10, -20, 10, 0,
+                  "clamped from the top");
+    check_reshape(TEST_ARGS, cs, DP_ANNOTATION_BOUNDS_CLAMP, 10, 100, 10, 80,
+                  "clamped from the bottom");
+    check_reshape(TEST_ARGS, cs, DP_ANNOTATION_BOUNDS_CLAMP, 1000, -1000, 150,
+                  0, "clamped into the top-right corner");
+
+    check_rejected(TEST_ARGS, cs, -50, 10, "left");
+    check_rejected(TEST_ARGS, cs, 200, 10, "right");
+    check_rejected(TEST_ARGS, cs, 10, -20, "top");
+    check_rejected(TEST_ARGS, cs, 10, 100, "bottom");
+    DP_canvas_state_decref(cs);
+}
+
+
+static void annotation_index_hit_test(TEST_PARAMS)
+{
+    DP_AnnotationIndex *ai = DP_annotation_index_new();
+    INT_EQ_OK(DP_annotation_index_at(ai, 0, 0), -1, "empty index hits nothing");
+
+    DP_annotation_index_set(ai, 0x101, 10, 10, 100, 50);
+    DP_annotation_index_set(ai, 0x102, 500, 300, 40, 40);
+    DP_annotation_index_set(ai, 0x103, -300, -200, 100, 100);
+    // Big enough to go into the large list instead of cells.
+    DP_annotation_index_set(ai, 0x104, 2000, 0, 5000, 5000);
+    DP_annotation_index_set(ai, 0x105, 50, 50, 0, 0);
+    INT_EQ_OK(DP_annotation_index_count(ai), 5, "five annotations indexed");
+
+    INT_EQ_OK(DP_annotation_index_at(ai, 10, 10), 0x101, "top left corner");
+    INT_EQ_OK(DP_annotation_index_at(ai, 109, 59), 0x101,
+              "bottom right corner");
+    INT_EQ_OK(DP_annotation_index_at(ai, 110, 30), -1, "past the right edge");
+    INT_EQ_OK(DP_annotation_index_at(ai, 60, 60), -1, "past the bottom edge");
+    INT_EQ_OK(DP_annotation_index_at(ai, 520, 320), 0x102,
+              "annotation in another cell");
+    INT_EQ_OK(DP_annotation_index_at(ai, -250, -150), 0x103,
+              "annotation at negative coordinates");
+    INT_EQ_OK(DP_annotation_index_at(ai, -200, -150), -1,
+              "past negative annotation's right edge");
+    INT_EQ_OK(DP_annotation_index_at(ai, 6000, 4000), 0x104,
+              "large annotation");
+    INT_EQ_OK(DP_annotation_index_at(ai, 1999, 4000), -1,
+              "left of large annotation");
+    INT_EQ_OK(DP_annotation_index_at(ai, 300, 200), -1, "between annotations");
+
+    DP_annotation_index_set(ai, 0x106, 50, 20, 100, 100);
+    INT_EQ_OK(DP_annotation_index_at(ai, 60, 30), 0x106,
+              "later annotation wins where they overlap");
+    INT_EQ_OK(DP_annotation_index_at(ai, 20, 30), 0x101,
+              "earlier annotation outside of overlap");
+
+    DP_annotation_index_set(ai, 0x101, 600, 600, 20, 20);
+    INT_EQ_OK(DP_annotation_index_at(ai, 20, 30), -1,
+              "nothing at reshaped annotation's old spot");
+    INT_EQ_OK(DP_annotation_index_at(ai, 610, 610), 0x101,
+              "reshaped annotation at its new spot");
+    DP_annotation_index_set(ai, 0x101, 10, 10, 100, 50);
+    INT_EQ_OK(DP_annotation_index_at(ai, 60, 30), 0x106,
+              "reshaping keeps the stacking order");
+
+    DP_annotation_index_set(ai, 0x104, 0, 0, 20, 20);
+    INT_EQ_OK(DP_annotation_index_at(ai, 6000, 4000), -1,
+              "large annotation shrunk");
+    INT_EQ_OK(DP_annotation_index_at(ai, 5, 5), 0x104,
+              "shrunk annotation is hit in its cell");
+
+    DP_annotation_index_remove(ai, 0x106);
+    DP_annotation_index_remove(ai, 0x999);
+    INT_EQ_OK(DP_annotation_index_count(ai), 5, "one annotation removed");
+    INT_EQ_OK(DP_annotation_index_at(ai, 60, 30), 0x101,
+              "removed annotation isn't hit anymore");
+
+    DP_annotation_index_free(ai);
+}
+
+static void annotation_index_sync(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = DP_canvas_state_new();
+    cs = handle(cs, dc, DP_msg_canvas_resize_new(1, 0, 1000, 1000, 0));
+    cs = handle(cs, dc, DP_msg_annotation_create_new(1, 0x101, 10, 10, 50, 50));
+    cs = handle(cs, dc,
+                DP_msg_annotation_create_new(1, 0x102, 300, 300, 50, 50));
+    cs = handle(cs, dc, DP_msg_annotation_create_new(1, 0x103, 40, 40, 50, 50));
+
+    DP_AnnotationIndex *ai = DP_annotation_index_new();
+    DP_annotation_index_sync(ai, DP_canvas_state_annotations_noinc(cs));
+    INT_EQ_OK(DP_annotation_index_count(ai), 3, "synced three annotations");
+    INT_EQ_OK(DP_annotation_index_at(ai, 20, 20), 0x101, "first annotation");
+    INT_EQ_OK(DP_annotation_index_at(ai, 320, 320), 0x102, "second annotation");
+    INT_EQ_OK(DP_annotation_index_at(ai, 50, 50), 0x103,
+              "annotation later in the list is on top");
+    INT_EQ_OK(DP_annotation_index_at(ai, 500, 500), -1, "outside of all");
+
+    cs = handle(cs, dc,
+                DP_msg_annotation_reshape_new(1, 0x102, 700, 700, 50, 50));
+    cs = handle(cs, dc, DP_msg_annotation_delete_new(1, 0x103));
+    cs = handle(cs, dc,
+                DP_msg_annotation_create_new(1, 0x104, 100, 900, 50, 50));
+    DP_annotation_index_sync(ai, DP_canvas_state_annotations_noinc(cs));
+    INT_EQ_OK(DP_annotation_index_count(ai), 3, "synced changes");
+    INT_EQ_OK(DP_annotation_index_at(ai, 320, 320), -1,
+              "nothing at reshaped annotation's old spot");
+    INT_EQ_OK(DP_annotation_index_at(ai, 720, 720), 0x102,
+              "reshaped annotation at its new spot");
+    INT_EQ_OK(DP_annotation_index_at(ai, 50, 50), 0x101,
+              "deleted annotation isn't on top anymore");
+    INT_EQ_OK(DP_annotation_index_at(ai, 70, 70), -1,
+              "nothing left of deleted annotation");
+    INT_EQ_OK(DP_annotation_index_at(ai, 120, 920), 0x104,
+              "created annotation");
+
+    DP_annotation_index_free(ai);
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+
+static DP_CanvasState *text_canvas_new(DP_DrawContext *dc, const char *text)
+{
+    DP_CanvasState *cs = DP_canvas_state_new();
+    cs = handle(cs, dc, DP_msg_canvas_resize_new(1, 0, 100, 80, 0));
+    cs = handle(cs, dc, DP_msg_annotation_create_new(1, 0x101, 0, 0, 50, 50));
+    cs = handle(cs, dc,
+                DP_msg_annotation_edit_new(1, 0x101, 0, 0, 0, text,
+                                           strlen(text)));
+    return cs;
+}
+
+static DP_Message *text_edit_new(unsigned int context_id, uint16_t offset,
+                                 uint16_t count, const char *text)
+{
+    return DP_msg_annotation_text_edit_new(context_id, 0x101, offset, count,
+                                           text, strlen(text));
+}
+
+static bool text_equals(DP_CanvasState *cs, const char *expected)
+{
+    if (!cs) {
+        return false;
+    }
+    DP_AnnotationList *al = DP_canvas_state_annotations_noinc(cs);
+    DP_Annotation *a = DP_annotation_list_at_noinc(
+        al, DP_annotation_list_index_by_id(al, 0x101));
+    size_t length;
+    const char *text = DP_annotation_text(a, &length);
+    return length == strlen(expected) && memcmp(text, expected, length) == 0;
+}
+
+
+static void annotation_text_edit_concurrent(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+
+    // Both users edit "hello world" at the same time, the insertion near the
+    // end arrives first, then the deletion at the start.
+    DP_CanvasState *cs = text_canvas_new(dc, "hello world");
+    cs = handle(cs, dc, text_edit_new(1, 6, 0, "big "));
+    OK(text_equals(cs, "hello big world"), "first user inserted text");
+    cs = handle(cs, dc, text_edit_new(2, 0, 6, ""));
+    OK(text_equals(cs, "big world"), "both edits are merged");
+    DP_canvas_state_decref(cs);
+
+    // One user deletes the last word while another appends to it.
+    cs = text_canvas_new(dc, "hello world");
+    cs = handle(cs, dc, text_edit_new(1, 6, 5, ""));
+    cs = handle(cs, dc, text_edit_new(2, 11, 0, "!"));
+    OK(text_equals(cs, "hello !"), "insertion past the end is appended");
+    DP_canvas_state_decref(cs);
+
+    // Replacing a range is a deletion and insertion in one.
+    cs = text_canvas_new(dc, "hello world");
+    cs = handle(cs, dc, text_edit_new(1, 0, 5, "goodbye"));
+    cs = handle(cs, dc, text_edit_new(2, 14, 0, "!"));
+    OK(text_equals(cs, "goodbye world!"), "replacement and insertion merged");
+    DP_canvas_state_decref(cs);
+
+    DP_draw_context_free(dc);
+}
+
+static void annotation_text_edit_out_of_range(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = text_canvas_new(dc, "hello");
+
+    cs = handle(cs, dc, text_edit_new(1, 3, 999, ""));
+    OK(text_equals(cs, "hel"), "deletion is clamped to the end");
+    cs = handle(cs, dc, text_edit_new(1, 999, 999, "p"));
+    OK(text_equals(cs, "help"), "offset is clamped to the end");
+
+    DP_Message *msg = text_edit_new(1, 999, 1, "");
+    DP_CanvasState *next = DP_canvas_state_handle(cs, dc, NULL, msg);
+    OK(next == cs, "deleting past the end changes nothing");
+    DP_canvas_state_decref(next);
+    DP_message_decref(msg);
+
+    msg = DP_msg_annotation_text_edit_new(1, 0x102, 0, 0, "x", 1);
+    NOK(DP_canvas_state_handle(cs, dc, NULL, msg),
+        "can't edit nonexistent annotation");
+    DP_message_decref(msg);
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void annotation_text_edit_utf8(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    // An n with a tilde is two bytes long, starting at offset 1.
+    DP_CanvasState *cs = text_canvas_new(dc, "a\xc3\xb1"
+                                        "b");
+
+    cs = handle(cs, dc, text_edit_new(1, 2, 0, "x"));
+    OK(text_equals(cs, "ax\xc3\xb1"
+                       "b"),
+       "insertion inside a character goes before it");
+    cs = handle(cs, dc, text_edit_new(1, 3, 1, ""));
+    OK(text_equals(cs, "axb"), "deletion inside a character takes all of it");
+    cs = handle(cs, dc, text_edit_new(1, 1, 1, "\xc3\xb1\xc3\xb1"));
+    OK(text_equals(cs, "a\xc3\xb1\xc3\xb1"
+                       "b"),
+       "multibyte text is inserted as it is");
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    register_handle_tests(REGISTER_ARGS);
+    REGISTER_TEST(annotation_bounds_partly_outside);
+    REGISTER_TEST(annotation_bounds_fully_outside);
+    REGISTER_TEST(annotation_index_hit_test);
+    REGISTER_TEST(annotation_index_sync);
+    REGISTER_TEST(annotation_text_edit_concurrent);
+    REGISTER_TEST(annotation_text_edit_out_of_range);
+    REGISTER_TEST(annotation_text_edit_utf8);
+}
+
 int main(int argc, char **argv)
 {
     static DP_HandleTest tests[] = {
@@ -182,5 +501,5 @@ int main(int argc, char **argv)
          "test/data/handle_annotations", handle_annotations},
         {NULL, NULL, NULL, NULL},
     };
-    return DP_test_main(argc, argv, register_handle_tests, tests);
+    return DP_test_main(argc, argv, register_tests, tests);
 }
@@ -20,13 +20,22 @@
  * SOFTWARE.
  */
 #include "handle_common.h"
+#include "canvas_state_common.h"
+#include <dpengine/image.h>
 #include <dpengine/layer_content.h>
 #include <dpengine/layer_group.h>
 #include <dpengine/layer_list.h>
 #include <dpengine/layer_props.h>
 #include <dpengine/layer_props_list.h>
+#include <dpengine/layer_routes.h>
+#include <dpengine/pixels.h>
+#include <dpengine/tile.h>
 #include <dpmsg/blend_mode.h>
 
+#define OPACITY            128
+#define OPACITY_TOLERANCE  2
+#define OPACITY_BACKGROUND 0xff0000ffu
+
 
 static void dump_layer_list(DP_Output *output, DP_LayerList *ll,
                             DP_LayerPropsList *lpl, int indent,
@@ -156,6 +165,14 @@ static void add_layer_create(DP_Output *output, DP_CanvasHistory *ch,
                                              name ? strlen(name) : 0));
 }
 
+static void add_legacy_layer_create(DP_Output *output, DP_CanvasHistory *ch,
+                                    DP_DrawContext *dc, uint16_t id,
+                                    const char *name)
+{
+    add_message(output, ch, dc,
+                DP_msg_layer_create_new(1, id, 0, 0, 0, name, strlen(name)));
+}
+
 static void add_layer_attributes(DP_Output *output, DP_CanvasHistory *ch,
                                  DP_DrawContext *dc, uint16_t id,
                                  uint8_t sublayer, uint8_t flags,
@@ -327,13 +344,510 @@ static void handle_layers(DP_Output *output, DP_CanvasHistory *ch,
     dump_layers(output, ch, "delete group");
 }
 
+static void dump_layer_title(DP_Output *output, DP_CanvasHistory *ch,
+                             int layer_id)
+{
+    DP_CanvasState *cs = DP_canvas_history_compare_and_get(ch, NULL, NULL);
+    DP_LayerRoutesEntry *lre =
+        DP_layer_routes_search(DP_canvas_state_layer_routes_noinc(cs), layer_id);
+    size_t title_length;
+    const char *title =
+        DP_layer_props_title(DP_layer_routes_entry_props(lre, cs), &title_length);
+    DP_output_format(output, "title length: %zu\n", title_length);
+    DP_output_format(output, "title: \"%.*s\"\n", DP_size_to_int(title_length),
+                     title);
+    DP_output_flush(output);
+    DP_canvas_state_decref(cs);
+}
+
+static void handle_layer_retitle(DP_Output *output, DP_CanvasHistory *ch,
+                                 DP_DrawContext *dc)
+{
+    add_layer_create(output, ch, dc, 257, 0, 0, 0, 0, "Layer 1");
+    dump_layer_title(output, ch, 257);
+
+    add_layer_retitle(output, ch, dc, 257, "Con\ttrol\x1b[31m\x7f chars");
+    dump_layer_title(output, ch, 257);
+
+    char long_title[1024];
+    for (size_t i = 0; i < sizeof(long_title) - 1; ++i) {
+        long_title[i] = (char)('a' + (char)(i % 26));
+    }
+    long_title[sizeof(long_title) - 1] = '\0';
+    add_layer_retitle(output, ch, dc, 257, long_title);
+    dump_layer_title(output, ch, 257);
+
+    // The euro sign is three bytes long, 86 of them don't fit into the limit.
+    char euro_title[86 * 3 + 1];
+    for (size_t i = 0; i < 86; ++i) {
+        memcpy(euro_title + i * 3, "\xe2\x82\xac", 3);
+    }
+    euro_title[sizeof(euro_title) - 1] = '\0';
+    add_layer_retitle(output, ch, dc, 257, euro_title);
+    dump_layer_title(output, ch, 257);
+}
+
+static void handle_layer_create_title(DP_Output *output, DP_CanvasHistory *ch,
+                                      DP_DrawContext *dc)
+{
+    char long_title[1024];
+    for (size_t i = 0; i < sizeof(long_title) - 1; ++i) {
+        long_title[i] = (char)('a' + (char)(i % 26));
+    }
+    long_title[sizeof(long_title) - 1] = '\0';
+
+    add_layer_create(output, ch, dc, 257, 0, 0, 0, 0,
+                     "Con\ttrol\x1b[31m\x7f chars");
+    dump_layer_title(output, ch, 257);
+    add_layer_create(output, ch, dc, 258, 0, 0, 0, 0, long_title);
+    dump_layer_title(output, ch, 258);
+
+    add_legacy_layer_create(output, ch, dc, 259,
+                            "Con\ttrol\x1b[31m\x7f chars");
+    dump_layer_title(output, ch, 259);
+    add_legacy_layer_create(output, ch, dc, 260, long_title);
+    dump_layer_title(output, ch, 260);
+}
+
+static void handle_layer_reserved_id(DP_Output *output, DP_CanvasHistory *ch,
+                                     DP_DrawContext *dc)
+{
+    // The default layer ACL id can't be used for an actual layer.
+    add_layer_create(output, ch, dc, 0xff, 0, 0, 0, 0, "Reserved");
+    add_legacy_layer_create(output, ch, dc, 0xff, "Reserved");
+
+    // Cloning a group doesn't hand out that id or 0 to the children either.
+    add_layer_create(output, ch, dc, 0xfd, 0, 0, 0,
+                     DP_MSG_LAYER_TREE_CREATE_FLAGS_GROUP, "Group");
+    add_layer_create(output, ch, dc, 0xfe, 0, 0xfd, 0,
+                     DP_MSG_LAYER_TREE_CREATE_FLAGS_INTO, "Layer");
+    add_layer_create(output, ch, dc, 0xfc, 0xfd, 0xfd, 0, 0, "Group Copy");
+    dump_layers(output, ch, "clone group with user 0 ids");
+}
+
+
+// Four layers in the root, 0x101 at the bottom and 0x104 at the top, followed
+// by a group 0x105 containing 0x106 and 0x107.
+static DP_CanvasState *order_canvas_new(DP_DrawContext *dc)
+{
+    DP_CanvasState *cs = DP_canvas_state_new();
+    cs = handle(cs, dc, DP_msg_canvas_resize_new(1, 0, 100, 80, 0));
+    for (uint16_t layer_id = 0x101; layer_id <= 0x104; ++layer_id) {
+        cs = handle(cs, dc,
+                    DP_msg_layer_tree_create_new(1, layer_id, 0, 0, 0, 0, "",
+                                                 0));
+    }
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(
+                    1, 0x105, 0, 0, 0, DP_MSG_LAYER_TREE_CREATE_FLAGS_GROUP, "",
+                    0));
+    for (uint16_t layer_id = 0x106; layer_id <= 0x107; ++layer_id) {
+        cs = handle(cs, dc,
+                    DP_msg_layer_tree_create_new(
+                        1, layer_id, 0, 0x105, 0,
+                        DP_MSG_LAYER_TREE_CREATE_FLAGS_INTO, "", 0));
+    }
+    return cs;
+}
+
+static DP_Message *move_new(uint16_t layer_id, uint8_t direction,
+                            uint16_t index)
+{
+    return DP_msg_layer_order_move_new(1, layer_id, direction, index);
+}
+
+static bool order_equals(DP_LayerPropsList *lpl, int count, const int *ids)
+{
+    if (DP_layer_props_list_count(lpl) != count) {
+        return false;
+    }
+    for (int i = 0; i < count; ++i) {
+        if (DP_layer_props_id(DP_layer_props_list_at_noinc(lpl, i)) != ids[i]) {
+            return false;
+        }
+    }
+    return true;
+}
+
+static bool root_order_equals(DP_CanvasState *cs, int count, const int *ids)
+{
+    return order_equals(DP_canvas_state_layer_props_noinc(cs), count, ids);
+}
+
+static DP_LayerPropsList *group_children(DP_CanvasState *cs)
+{
+    DP_LayerPropsList *lpl = DP_canvas_state_layer_props_noinc(cs);
+    int count = DP_layer_props_list_count(lpl);
+    return DP_layer_props_children_noinc(
+        DP_layer_props_list_at_noinc(lpl, count - 1));
+}
+
+
+static void layer_order_move_up_and_down(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = order_canvas_new(dc);
+    FATAL(OK(root_order_equals(cs, 5, (int[]){0x101, 0x102, 0x103, 0x104,
+                                              0x105}),
+             "initial root order"));
+
+    cs = handle(cs, dc,
+                move_new(0x101, DP_MSG_LAYER_ORDER_MOVE_DIRECTION_UP, 0));
+    FATAL(NOT_NULL_OK(cs, "moved bottom layer up"));
+    OK(root_order_equals(cs, 5, (int[]){0x102, 0x101, 0x103, 0x104, 0x105}),
+       "bottom layer moved up one step");
+
+    cs = handle(cs, dc,
+                move_new(0x104, DP_MSG_LAYER_ORDER_MOVE_DIRECTION_DOWN, 0));
+    FATAL(NOT_NULL_OK(cs, "moved layer down"));
+    OK(root_order_equals(cs, 5, (int[]){0x102, 0x101, 0x104, 0x103, 0x105}),
+       "layer moved down one step");
+
+    cs = handle(cs, dc,
+                move_new(0x102, DP_MSG_LAYER_ORDER_MOVE_DIRECTION_DOWN, 0));
+    FATAL(NOT_NULL_OK(cs, "moved bottom layer down"));
+    OK(root_order_equals(cs, 5, (int[]){0x102, 0x101, 0x104, 0x103, 0x105}),
+       "moving bottom layer down does nothing");
+
+    cs = handle(cs, dc,
+                move_new(0x105, DP_MSG_LAYER_ORDER_MOVE_DIRECTION_UP, 0));
+    FATAL(NOT_NULL_OK(cs, "moved top layer up"));
+    OK(root_order_equals(cs, 5, (int[]){0x102, 0x101, 0x104, 0x103, 0x105}),
+       "moving top layer up does nothing");
+
+    cs = handle(cs, dc,
+                move_new(0x106, DP_MSG_LAYER_ORDER_MOVE_DIRECTION_UP, 0));
+    FATAL(NOT_NULL_OK(cs, "moved layer in group up"));
+    OK(order_equals(group_children(cs), 2, (int[]){0x107, 0x106}),
+       "layer moved up within its group");
+    OK(root_order_equals(cs, 5, (int[]){0x102, 0x101, 0x104, 0x103, 0x105}),
+       "moving within group leaves root alone");
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void layer_order_move_to_index(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = order_canvas_new(dc);
+
+    cs = handle(cs, dc,
+                move_new(0x101, DP_MSG_LAYER_ORDER_MOVE_DIRECTION_INDEX, 3));
+    FATAL(NOT_NULL_OK(cs, "moved layer up to index"));
+    OK(root_order_equals(cs, 5, (int[]){0x102, 0x103, 0x104, 0x101, 0x105}),
+       "layer moved up to explicit index");
+
+    cs = handle(cs, dc,
+                move_new(0x104, DP_MSG_LAYER_ORDER_MOVE_DIRECTION_INDEX, 0));
+    FATAL(NOT_NULL_OK(cs, "moved layer down to index"));
+    OK(root_order_equals(cs, 5, (int[]){0x104, 0x102, 0x103, 0x101, 0x105}),
+       "layer moved down to explicit index");
+
+    cs = handle(cs, dc,
+                move_new(0x102, DP_MSG_LAYER_ORDER_MOVE_DIRECTION_INDEX, 999));
+    FATAL(NOT_NULL_OK(cs, "moved layer past the top"));
+    OK(root_order_equals(cs, 5, (int[]){0x104, 0x103, 0x101, 0x105, 0x102}),
+       "index past the top is clamped");
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void layer_order_move_invalid(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = order_canvas_new(dc);
+
+    DP_Message *msg = move_new(0x108, DP_MSG_LAYER_ORDER_MOVE_DIRECTION_UP, 0);
+    NOK(DP_canvas_state_handle(cs, dc, NULL, msg),
+        "can't move nonexistent layer up");
+    DP_message_decref(msg);
+
+    msg = move_new(0x108, DP_MSG_LAYER_ORDER_MOVE_DIRECTION_INDEX, 0);
+    NOK(DP_canvas_state_handle(cs, dc, NULL, msg),
+        "can't move nonexistent layer to index");
+    DP_message_decref(msg);
+
+    msg = move_new(0x101, DP_MSG_LAYER_ORDER_MOVE_NUM_DIRECTION, 0);
+    NOK(DP_canvas_state_handle(cs, dc, NULL, msg),
+        "can't move layer in unknown direction");
+    DP_message_decref(msg);
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+
+
+
+
+static void set_background(size_t size, unsigned char *out, void *user)
+{
+    memcpy(out, user, size);
+}
+
+static DP_CanvasState *fill(DP_CanvasState *cs, DP_DrawContext *dc,
+                            int layer_id)
+{
+    // An opaque red pixel, a semi-transparent red one and an empty one.
+    uint16_t id = (uint16_t)layer_id;
+    cs = handle(cs, dc,
+                DP_msg_fill_rect_new(1, id, DP_BLEND_MODE_NORMAL, 0, 0, 1, 1,
+                                     0xffff0000u));
+    cs = handle(cs, dc,
+                DP_msg_fill_rect_new(1, id, DP_BLEND_MODE_NORMAL, 1, 0, 1, 1,
+                                     0x80ff0000u));
+    return cs;
+}
+
+// A red layer at half opacity over an opaque blue background.
+static DP_CanvasState *layer_canvas_new(DP_DrawContext *dc)
+{
+    DP_CanvasState *cs = DP_canvas_state_new();
+    cs = handle(cs, dc, DP_msg_canvas_resize_new(1, 0, 3, 1, 0));
+    unsigned char background[] = {0xff, 0x00, 0x00, 0xff};
+    cs = handle(cs, dc,
+                DP_msg_canvas_background_new(1, set_background,
+                                             sizeof(background), background));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "a", 1));
+    cs = handle(cs, dc,
+                DP_msg_layer_attributes_new(1, 0x101, 0, 0, OPACITY,
+                                            DP_BLEND_MODE_NORMAL));
+    return fill(cs, dc, 0x101);
+}
+
+// The same, but with the opacity on an isolated group around the layer.
+static DP_CanvasState *group_canvas_new(DP_DrawContext *dc)
+{
+    DP_CanvasState *cs = DP_canvas_state_new();
+    cs = handle(cs, dc, DP_msg_canvas_resize_new(1, 0, 3, 1, 0));
+    unsigned char background[] = {0xff, 0x00, 0x00, 0xff};
+    cs = handle(cs, dc,
+                DP_msg_canvas_background_new(1, set_background,
+                                             sizeof(background), background));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(
+                    1, 0x101, 0, 0, 0, DP_MSG_LAYER_TREE_CREATE_FLAGS_GROUP,
+                    "g", 1));
+    cs = handle(cs, dc,
+                DP_msg_layer_attributes_new(
+                    1, 0x101, 0, DP_MSG_LAYER_ATTRIBUTES_FLAGS_ISOLATED,
+                    OPACITY, DP_BLEND_MODE_NORMAL));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(
+                    1, 0x102, 0, 0x101, 0, DP_MSG_LAYER_TREE_CREATE_FLAGS_INTO,
+                    "a", 1));
+    return fill(cs, dc, 0x102);
+}
+
+// Straight source-over of an unpremultiplied color with the given alpha and
+// layer opacity onto an opaque background channel.
+static double expected_channel(double bg, double c, double a, double o)
+{
+    return bg * (1.0 - a * o) + c * a * o;
+}
+
+static bool channel_ok(TEST_PARAMS, int actual, double expected,
+                       const char *what)
+{
+    int rounded = (int)(expected + 0.5);
+    return OK(actual >= rounded - OPACITY_TOLERANCE
+              && actual <= rounded + OPACITY_TOLERANCE,
+              "%s is %d, expected %d", what, actual, rounded);
+}
+
+static void check_flattened(TEST_PARAMS, DP_CanvasState *cs)
+{
+    DP_Image *img = DP_canvas_state_to_flat_image(
+        cs, DP_FLAT_IMAGE_RENDER_FLAGS, NULL, NULL);
+    FATAL(NOT_NULL_OK(img, "flattened image"));
+    double o = OPACITY / 255.0;
+
+    DP_Pixel8 opaque = DP_image_pixel_at(img, 0, 0);
+    channel_ok(TEST_ARGS, opaque.r, expected_channel(0.0, 255.0, 1.0, o),
+               "opaque pixel red");
+    channel_ok(TEST_ARGS, opaque.g, 0.0, "opaque pixel green");
+    channel_ok(TEST_ARGS, opaque.b, expected_channel(255.0, 0.0, 1.0, o),
+               "opaque pixel blue");
+    UINT_EQ_OK(opaque.a, 255, "opaque pixel alpha");
+
+    double a = 0x80 / 255.0;
+    DP_Pixel8 semi = DP_image_pixel_at(img, 1, 0);
+    channel_ok(TEST_ARGS, semi.r, expected_channel(0.0, 255.0, a, o),
+               "semi-transparent pixel red");
+    channel_ok(TEST_ARGS, semi.g, 0.0, "semi-transparent pixel green");
+    channel_ok(TEST_ARGS, semi.b, expected_channel(255.0, 0.0, a, o),
+               "semi-transparent pixel blue");
+    UINT_EQ_OK(semi.a, 255, "semi-transparent pixel alpha");
+
+    UINT_EQ_OK(DP_image_pixel_at(img, 2, 0).color, OPACITY_BACKGROUND,
+               "background is untouched where the layer is empty");
+    DP_image_free(img);
+}
+
+// Without a background, the result must stay fully red in premultiplied
+// terms, only less opaque. Applying opacity to the color channels but not to
+// alpha, or the other way round, would show up as a dark or bright fringe.
+static void check_flattened_without_background(TEST_PARAMS, DP_CanvasState *cs)
+{
+    DP_Image *img = DP_canvas_state_to_flat_image(
+        cs, DP_FLAT_IMAGE_INCLUDE_SUBLAYERS, NULL, NULL);
+    FATAL(NOT_NULL_OK(img, "flattened image without background"));
+    double o = OPACITY / 255.0;
+    double a = 0x80 / 255.0;
+
+    DP_Pixel8 opaque = DP_image_pixel_at(img, 0, 0);
+    channel_ok(TEST_ARGS, opaque.a, 255.0 * o, "opaque pixel alpha");
+    INT_EQ_OK(opaque.r, opaque.a, "opaque pixel red matches its alpha");
+    UINT_EQ_OK(opaque.g, 0, "opaque pixel green");
+    UINT_EQ_OK(opaque.b, 0, "opaque pixel blue");
+
+    DP_Pixel8 semi = DP_image_pixel_at(img, 1, 0);
+    channel_ok(TEST_ARGS, semi.a, 255.0 * a * o, "semi-transparent alpha");
+    INT_EQ_OK(semi.r, semi.a, "semi-transparent red matches its alpha");
+    UINT_EQ_OK(semi.g, 0, "semi-transparent pixel green");
+    UINT_EQ_OK(semi.b, 0, "semi-transparent pixel blue");
+
+    UINT_EQ_OK(DP_image_pixel_at(img, 2, 0).color, 0,
+               "empty pixel stays transparent");
+    DP_image_free(img);
+}
+
+
+static void layer_opacity_blend(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = layer_canvas_new(dc);
+    check_flattened(TEST_ARGS, cs);
+    check_flattened_without_background(TEST_ARGS, cs);
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void layer_opacity_isolated_group(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = group_canvas_new(dc);
+    check_flattened(TEST_ARGS, cs);
+    check_flattened_without_background(TEST_ARGS, cs);
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+
+static DP_CanvasState *fill_canvas_new(DP_DrawContext *dc)
+{
+    DP_CanvasState *cs = DP_canvas_state_new();
+    cs = handle(cs, dc, DP_msg_canvas_resize_new(1, 0, 200, 150, 0));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "layer", 5));
+    return handle(cs, dc,
+                  DP_msg_fill_rect_new(1, 0x101, DP_BLEND_MODE_NORMAL, 10, 10,
+                                       20, 20, 0xff0000ffu));
+}
+
+static DP_LayerContent *layer_content(DP_CanvasState *cs, int layer_id)
+{
+    DP_LayerRoutes *lr = DP_canvas_state_layer_routes_noinc(cs);
+    DP_LayerRoutesEntry *lre = DP_layer_routes_search(lr, layer_id);
+    return DP_layer_routes_entry_content(lre, cs);
+}
+
+static int count_tiles(DP_LayerContent *lc, DP_Tile **out_first)
+{
+    int count = 0;
+    int tile_count = DP_tile_total_round(DP_layer_content_width(lc),
+                                         DP_layer_content_height(lc));
+    *out_first = DP_layer_content_tile_at_index_noinc(lc, 0);
+    for (int i = 0; i < tile_count; ++i) {
+        if (DP_layer_content_tile_at_index_noinc(lc, i) == *out_first) {
+            ++count;
+        }
+    }
+    return count;
+}
+
+
+static void fill_layer_solid(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = fill_canvas_new(dc);
+
+    DP_Message *msg =
+        DP_canvas_state_msg_fill_layer_new(cs, 1, 0x101, 0xff00ff00u);
+    if (NOT_NULL_OK(msg, "fill layer message created")) {
+        INT_EQ_OK(DP_message_type(msg), DP_MSG_FILL_RECT,
+                  "fill layer is a single fill rect");
+        cs = handle(cs, dc, msg);
+    }
+
+    DP_LayerContent *lc = layer_content(cs, 0x101);
+    DP_Tile *tile;
+    int tile_count = DP_tile_total_round(200, 150);
+    INT_EQ_OK(count_tiles(lc, &tile), tile_count,
+              "every tile shares the same tile");
+    DP_Pixel15 pixel;
+    OK(DP_tile_same_pixel(tile, &pixel), "shared tile is a solid color");
+    DP_UPixel15 expected = DP_upixel15_from_color(0xff00ff00u);
+    OK(pixel.g == expected.g && pixel.a == DP_BIT15 && pixel.r == 0,
+       "shared tile has the fill color");
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void fill_layer_transparent(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = fill_canvas_new(dc);
+    cs = handle(cs, dc, DP_canvas_state_msg_fill_layer_new(cs, 1, 0x101, 0));
+
+    DP_LayerContent *lc = layer_content(cs, 0x101);
+    DP_Tile *tile;
+    int tile_count = DP_tile_total_round(200, 150);
+    INT_EQ_OK(count_tiles(lc, &tile), tile_count, "every tile is the same");
+    NULL_OK(tile, "filling with transparency clears the layer");
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void fill_layer_empty_canvas(TEST_PARAMS)
+{
+    DP_CanvasState *cs = DP_canvas_state_new();
+    NULL_OK(DP_canvas_state_msg_fill_layer_new(cs, 1, 0x101, 0xff000000u),
+            "can't fill a layer on a canvas without size");
+    DP_canvas_state_decref(cs);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    register_handle_tests(REGISTER_ARGS);
+    REGISTER_TEST(layer_order_move_up_and_down);
+    REGISTER_TEST(layer_order_move_to_index);
+    REGISTER_TEST(layer_order_move_invalid);
+    REGISTER_TEST(layer_opacity_blend);
+    REGISTER_TEST(layer_opacity_isolated_group);
+    REGISTER_TEST(fill_layer_solid);
+    REGISTER_TEST(fill_layer_transparent);
+    REGISTER_TEST(fill_layer_empty_canvas);
+}
 
 int main(int argc, char **argv)
 {
     static DP_HandleTest tests[] = {
         {"handle_layers", "test/tmp/handle_layers", "test/data/handle_layers",
          handle_layers},
+        {"handle_layer_retitle", "test/tmp/handle_layer_retitle",
+         "test/data/handle_layer_retitle", handle_layer_retitle},
+        {"handle_layer_create_title", "test/tmp/handle_layer_create_title",
+         "test/data/handle_layer_create_title", handle_layer_create_title},
+        {"handle_layer_reserved_id", "test/tmp/handle_layer_reserved_id",
+         "test/data/handle_layer_reserved_id", handle_layer_reserved_id},
         {NULL, NULL, NULL, NULL},
     };
-    return DP_test_main(argc, argv, register_handle_tests, tests);
+    return DP_test_main(argc, argv, register_tests, tests);
 }
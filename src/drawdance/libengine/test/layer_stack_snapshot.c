@@ -0,0 +1,278 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "canvas_state_common.h"
+#include <dpcommon/common.h>
+#include <dpcommon/conversions.h>
+#include <dpengine/canvas_state.h>
+#include <dpengine/draw_context.h>
+#include <dpengine/image.h>
+#include <dpengine/layer_content.h>
+#include <dpengine/layer_props.h>
+#include <dpengine/layer_props_list.h>
+#include <dpengine/layer_routes.h>
+#include <dpengine/pixels.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+
+
+static DP_CanvasState *fill_rect(DP_CanvasState *cs, DP_DrawContext *dc,
+                                 int layer_id, int x, uint32_t color)
+{
+    return handle(cs, dc,
+                  DP_msg_fill_rect_new(1, DP_int_to_uint16(layer_id),
+                                       DP_BLEND_MODE_NORMAL,
+                                       DP_int_to_uint32(x), 10, 30, 30,
+                                       color));
+}
+
+static DP_CanvasState *two_layers_new(DP_DrawContext *dc)
+{
+    DP_CanvasState *cs = DP_canvas_state_new();
+    cs = handle(cs, dc, DP_msg_canvas_resize_new(1, 0, 100, 80, 0));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "a", 1));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(1, 0x102, 0, 0x101, 0, 0, "b", 1));
+    cs = fill_rect(cs, dc, 0x101, 10, 0xffff0000u);
+    cs = fill_rect(cs, dc, 0x102, 50, 0xff0000ffu);
+    return cs;
+}
+
+static bool images_equal(DP_Image *a, DP_Image *b)
+{
+    int width = DP_image_width(a);
+    int height = DP_image_height(a);
+    return width == DP_image_width(b) && height == DP_image_height(b)
+        && memcmp(DP_image_pixels(a), DP_image_pixels(b),
+                  DP_int_to_size(width) * DP_int_to_size(height)
+                      * sizeof(DP_Pixel8))
+               == 0;
+}
+
+static DP_Image *flatten(DP_CanvasState *cs)
+{
+    return DP_canvas_state_to_flat_image(cs, DP_FLAT_IMAGE_RENDER_FLAGS, NULL,
+                                         NULL);
+}
+
+static void check_layer(TEST_PARAMS, DP_CanvasState *cs, int index,
+                        int expected_id, const char *expected_title)
+{
+    DP_LayerPropsList *lpl = DP_canvas_state_layer_props_noinc(cs);
+    DP_LayerProps *lp = DP_layer_props_list_at_noinc(lpl, index);
+    size_t title_length;
+    const char *title = DP_layer_props_title(lp, &title_length);
+    INT_EQ_OK(DP_layer_props_id(lp), expected_id, "layer %d has id 0x%x",
+              index, (unsigned int)expected_id);
+    OK(title_length == strlen(expected_title)
+           && memcmp(title, expected_title, title_length) == 0,
+       "layer %d has title %s", index, expected_title);
+    UINT_EQ_OK(DP_layer_props_opacity(lp), DP_BIT15,
+               "layer %d is fully opaque", index);
+}
+
+typedef struct DP_ChangedTiles {
+    int count;
+    struct {
+        int layer_id, x, y;
+    } tiles[16];
+} DP_ChangedTiles;
+
+static void collect_changed_tile(void *user, int layer_id, int tile_x,
+                                 int tile_y)
+{
+    DP_ChangedTiles *ct = user;
+    if (ct->count < (int)DP_ARRAY_LENGTH(ct->tiles)) {
+        ct->tiles[ct->count].layer_id = layer_id;
+        ct->tiles[ct->count].x = tile_x;
+        ct->tiles[ct->count].y = tile_y;
+    }
+    ++ct->count;
+}
+
+static bool has_changed_tile(DP_ChangedTiles *ct, int layer_id, int x, int y)
+{
+    int count = DP_min_int(ct->count, (int)DP_ARRAY_LENGTH(ct->tiles));
+    for (int i = 0; i < count; ++i) {
+        if (ct->tiles[i].layer_id == layer_id && ct->tiles[i].x == x
+            && ct->tiles[i].y == y) {
+            return true;
+        }
+    }
+    return false;
+}
+
+static DP_LayerContent *search_content(DP_CanvasState *cs, int layer_id)
+{
+    DP_LayerRoutes *lr = DP_canvas_state_layer_routes_noinc(cs);
+    DP_LayerRoutesEntry *lre = DP_layer_routes_search(lr, layer_id);
+    return lre ? DP_layer_routes_entry_content(lre, cs) : NULL;
+}
+
+
+static void layer_stack_snapshot_restore(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = two_layers_new(dc);
+    DP_Image *before = flatten(cs);
+
+    DP_LayerStackSnapshot snapshot;
+    DP_canvas_state_layer_stack_snapshot(cs, &snapshot);
+
+    cs = fill_rect(cs, dc, 0x101, 20, 0xff00ff00u);
+    cs = fill_rect(cs, dc, 0x102, 60, 0xffffffffu);
+    cs = handle(cs, dc,
+                DP_msg_layer_attributes_new(1, 0x102, 0, 0, 128,
+                                            DP_BLEND_MODE_MULTIPLY));
+    cs = handle(cs, dc, DP_msg_layer_retitle_new(1, 0x101, "changed", 7));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(1, 0x103, 0, 0x102, 0xff000000u,
+                                             0, "c", 1));
+    cs = handle(cs, dc, DP_msg_layer_tree_delete_new(1, 0x101, 0));
+    FATAL(NOT_NULL_OK(cs, "mutated the canvas"));
+
+    DP_Image *mutated = flatten(cs);
+    NOK(images_equal(before, mutated), "mutations changed the pixels");
+    DP_image_free(mutated);
+
+    DP_CanvasState *restored =
+        DP_canvas_state_layer_stack_restore(cs, dc, &snapshot);
+    OK(DP_canvas_state_layers_noinc(restored) == snapshot.ll,
+       "restored layers are the snapshotted ones");
+    OK(DP_canvas_state_layer_props_noinc(restored) == snapshot.lpl,
+       "restored layer properties are the snapshotted ones");
+    INT_EQ_OK(DP_layer_props_list_count(
+                  DP_canvas_state_layer_props_noinc(restored)),
+              2, "two layers restored");
+    check_layer(TEST_ARGS, restored, 0, 0x101, "a");
+    check_layer(TEST_ARGS, restored, 1, 0x102, "b");
+
+    DP_LayerRoutes *lr = DP_canvas_state_layer_routes_noinc(restored);
+    OK(DP_layer_routes_search(lr, 0x101), "deleted layer is routed again");
+    NOK(DP_layer_routes_search(lr, 0x103), "created layer isn't routed");
+
+    DP_Image *after = flatten(restored);
+    OK(images_equal(before, after), "restored pixels are the same as before");
+    DP_image_free(after);
+
+    restored = fill_rect(restored, dc, 0x101, 0, 0xff00ff00u);
+    OK(restored != NULL, "can draw on restored layer");
+
+    DP_canvas_state_decref_nullable(restored);
+    DP_layer_stack_snapshot_dispose(&snapshot);
+    DP_image_free(before);
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void layer_stack_snapshot_resize(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = two_layers_new(dc);
+    DP_Image *before = flatten(cs);
+
+    DP_LayerStackSnapshot snapshot;
+    DP_canvas_state_layer_stack_snapshot(cs, &snapshot);
+    cs = handle(cs, dc, DP_msg_canvas_resize_new(1, 10, 50, 0, 20));
+    INT_EQ_OK(DP_canvas_state_width(cs), 170, "canvas got wider");
+
+    DP_CanvasState *restored =
+        DP_canvas_state_layer_stack_restore(cs, dc, &snapshot);
+    INT_EQ_OK(DP_canvas_state_width(restored), 100, "width is restored");
+    INT_EQ_OK(DP_canvas_state_height(restored), 80, "height is restored");
+    DP_Image *after = flatten(restored);
+    OK(images_equal(before, after), "pixels are restored along with size");
+    DP_image_free(after);
+
+    DP_canvas_state_decref(restored);
+    DP_layer_stack_snapshot_dispose(&snapshot);
+    DP_image_free(before);
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void layer_stack_snapshot_changed_tiles(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *before = two_layers_new(dc);
+    DP_LayerStackSnapshot a;
+    DP_canvas_state_layer_stack_snapshot(before, &a);
+
+    DP_ChangedTiles ct = {0};
+    INT_EQ_OK(DP_layer_stack_snapshot_changed_tiles(&a, &a,
+                                                    collect_changed_tile, &ct),
+              0, "snapshot doesn't differ from itself");
+    INT_EQ_OK(ct.count, 0, "no tiles reported for identical snapshots");
+
+    // Only touches the top-left tile of the first layer.
+    DP_CanvasState *after = fill_rect(DP_canvas_state_incref(before), dc,
+                                      0x101, 20, 0xff00ff00u);
+    DP_LayerStackSnapshot b;
+    DP_canvas_state_layer_stack_snapshot(after, &b);
+
+    DP_LayerContent *lc_before = search_content(before, 0x101);
+    DP_LayerContent *lc_after = search_content(after, 0x101);
+    OK(DP_layer_content_tile_at_noinc(lc_before, 0, 0)
+           != DP_layer_content_tile_at_noinc(lc_after, 0, 0),
+       "drawn on tile was replaced");
+    OK(DP_layer_content_tile_at_noinc(lc_before, 1, 0)
+           == DP_layer_content_tile_at_noinc(lc_after, 1, 0),
+       "tile next to drawn on one is shared");
+    OK(DP_layer_content_tile_at_noinc(lc_before, 1, 1)
+           == DP_layer_content_tile_at_noinc(lc_after, 1, 1),
+       "tile diagonal to drawn on one is shared");
+    OK(search_content(before, 0x102) == search_content(after, 0x102),
+       "untouched layer is shared");
+
+    ct.count = 0;
+    INT_EQ_OK(DP_layer_stack_snapshot_changed_tiles(&a, &b,
+                                                    collect_changed_tile, &ct),
+              1, "one tile changed");
+    OK(has_changed_tile(&ct, 0x101, 0, 0), "drawn on tile reported");
+
+    ct.count = 0;
+    INT_EQ_OK(DP_layer_stack_snapshot_changed_tiles(&b, &a,
+                                                    collect_changed_tile, &ct),
+              1, "one tile changed in reverse");
+    OK(has_changed_tile(&ct, 0x101, 0, 0), "drawn on tile reported in reverse");
+
+    // A new layer reports its tiles, a deleted one does too.
+    after = handle(after, dc,
+                   DP_msg_layer_tree_create_new(1, 0x103, 0, 0x102, 0, 0, "c",
+                                                1));
+    after = fill_rect(after, dc, 0x103, 70, 0xff0000ffu);
+    after = handle(after, dc, DP_msg_layer_tree_delete_new(1, 0x102, 0));
+    DP_LayerStackSnapshot c;
+    DP_canvas_state_layer_stack_snapshot(after, &c);
+
+    ct.count = 0;
+    int count = DP_layer_stack_snapshot_changed_tiles(
+        &b, &c, collect_changed_tile, &ct);
+    OK(has_changed_tile(&ct, 0x103, 1, 0), "created layer's tile reported");
+    OK(has_changed_tile(&ct, 0x102, 0, 0), "deleted layer's tile reported");
+    OK(has_changed_tile(&ct, 0x102, 1, 0),
+       "deleted layer's other tile reported");
+    NOK(has_changed_tile(&ct, 0x101, 0, 0), "unchanged layer not reported");
+    INT_EQ_OK(count, 3, "only the created and deleted layers' tiles");
+    INT_EQ_OK(ct.count, count, "callback called for each tile");
+
+    DP_layer_stack_snapshot_dispose(&c);
+    DP_layer_stack_snapshot_dispose(&b);
+    DP_layer_stack_snapshot_dispose(&a);
+    DP_canvas_state_decref(after);
+    DP_canvas_state_decref(before);
+    DP_draw_context_free(dc);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(layer_stack_snapshot_restore);
+    REGISTER_TEST(layer_stack_snapshot_resize);
+    REGISTER_TEST(layer_stack_snapshot_changed_tiles);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,109 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "canvas_state_common.h"
+#include <dpcommon/common.h>
+#include <dpcommon/output.h>
+#include <dpengine/canvas_state.h>
+#include <dpengine/draw_context.h>
+#include <dpengine/image.h>
+#include <dpengine/layer_thumbnails.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+
+
+static DP_CanvasState *canvas_new(DP_DrawContext *dc)
+{
+    DP_CanvasState *cs = DP_canvas_state_new();
+    cs = handle(cs, dc, DP_msg_canvas_resize_new(1, 0, 200, 100, 0));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "a", 1));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(1, 0x102, 0, 0, 0, 0, "b", 1));
+    uint8_t group_flags = DP_MSG_LAYER_TREE_CREATE_FLAGS_GROUP;
+    return handle(cs, dc,
+                  DP_msg_layer_tree_create_new(1, 0x103, 0, 0, 0, group_flags,
+                                               "g", 1));
+}
+
+static DP_Message *fill_rect_new(int layer_id)
+{
+    return DP_msg_fill_rect_new(1, DP_int_to_uint16(layer_id),
+                                DP_BLEND_MODE_NORMAL, 0, 0, 50, 50,
+                                0xff0000ffu);
+}
+
+
+static void layer_thumbnails_cached(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc);
+    DP_LayerThumbnails *lt = DP_layer_thumbnails_new();
+
+    NOK(DP_layer_thumbnails_valid(lt, cs, 0x101, 64, 64),
+        "nothing cached initially");
+    DP_Image *thumb = DP_layer_thumbnails_get(lt, cs, dc, 0x101, 64, 64);
+    if (NOT_NULL_OK(thumb, "thumbnail generated")) {
+        INT_EQ_OK(DP_image_width(thumb), 64, "thumbnail width");
+        INT_EQ_OK(DP_image_height(thumb), 32, "thumbnail height");
+    }
+    OK(DP_layer_thumbnails_valid(lt, cs, 0x101, 64, 64),
+       "thumbnail cached after generation");
+    OK(DP_layer_thumbnails_get(lt, cs, dc, 0x101, 64, 64) == thumb,
+       "cached thumbnail is returned again");
+    NOK(DP_layer_thumbnails_valid(lt, cs, 0x101, 32, 32),
+        "different dimensions aren't cached");
+
+    NULL_OK(DP_layer_thumbnails_get(lt, cs, dc, 0x103, 64, 64),
+            "no thumbnail for a group");
+    NULL_OK(DP_layer_thumbnails_get(lt, cs, dc, 0x999, 64, 64),
+            "no thumbnail for a nonexistent layer");
+
+    DP_layer_thumbnails_free(lt);
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void layer_thumbnails_invalidate(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc);
+    DP_LayerThumbnails *lt = DP_layer_thumbnails_new();
+    NOT_NULL_OK(DP_layer_thumbnails_get(lt, cs, dc, 0x101, 64, 64),
+                "thumbnail a");
+    NOT_NULL_OK(DP_layer_thumbnails_get(lt, cs, dc, 0x102, 64, 64),
+                "thumbnail b");
+
+    cs = handle(cs, dc, fill_rect_new(0x101));
+    NOK(DP_layer_thumbnails_valid(lt, cs, 0x101, 64, 64),
+        "drawing on a invalidates its thumbnail");
+    OK(DP_layer_thumbnails_valid(lt, cs, 0x102, 64, 64),
+       "drawing on a leaves thumbnail b valid");
+
+    NOT_NULL_OK(DP_layer_thumbnails_get(lt, cs, dc, 0x101, 64, 64),
+                "thumbnail a regenerated");
+    OK(DP_layer_thumbnails_valid(lt, cs, 0x101, 64, 64),
+       "regenerated thumbnail is valid");
+
+    cs = handle(cs, dc, DP_msg_layer_tree_delete_new(1, 0x102, 0));
+    DP_layer_thumbnails_prune(lt, cs);
+    NOK(DP_layer_thumbnails_valid(lt, cs, 0x102, 64, 64),
+        "thumbnail of deleted layer pruned");
+    OK(DP_layer_thumbnails_valid(lt, cs, 0x101, 64, 64),
+       "thumbnail of remaining layer kept");
+
+    DP_layer_thumbnails_free(lt);
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(layer_thumbnails_cached);
+    REGISTER_TEST(layer_thumbnails_invalidate);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,148 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "canvas_state_common.h"
+#include <dpcommon/common.h>
+#include <dpcommon/conversions.h>
+#include <dpengine/canvas_state.h>
+#include <dpengine/draw_context.h>
+#include <dpengine/image.h>
+#include <dpengine/pixels.h>
+#include <dpengine/tile.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+
+
+#define GRADIENT_WIDTH 256
+
+
+static void set_background(size_t size, unsigned char *out, void *user)
+{
+    memcpy(out, user, size);
+}
+
+// A black gradient going from transparent to opaque over a white background.
+static DP_CanvasState *gradient_new(DP_DrawContext *dc)
+{
+    DP_CanvasState *cs = DP_canvas_state_new();
+    cs = handle(cs, dc, DP_msg_canvas_resize_new(1, 0, GRADIENT_WIDTH, 1, 0));
+    unsigned char background[] = {0xff, 0xff, 0xff, 0xff};
+    cs = handle(cs, dc,
+                DP_msg_canvas_background_new(1, set_background,
+                                             sizeof(background), background));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "a", 1));
+    for (int x = 0; x < GRADIENT_WIDTH; ++x) {
+        cs = handle(cs, dc,
+                    DP_msg_fill_rect_new(1, 0x101, DP_BLEND_MODE_NORMAL,
+                                         DP_int_to_uint32(x), 0, 1, 1,
+                                         DP_int_to_uint32(x) << 24u));
+    }
+    return cs;
+}
+
+static int gray_at(DP_Image *img, int x)
+{
+    return DP_image_pixel_at(img, x, 0).g;
+}
+
+
+static void channel_round_trip(TEST_PARAMS)
+{
+    UINT_EQ_OK(DP_channel15_to_linear(0), 0, "black stays black in linear");
+    UINT_EQ_OK(DP_channel15_to_linear(DP_BIT15), DP_BIT15,
+               "white stays white in linear");
+    UINT_EQ_OK(DP_channel15_to_srgb(0), 0, "black stays black in sRGB");
+    UINT_EQ_OK(DP_channel15_to_srgb(DP_BIT15), DP_BIT15,
+               "white stays white in sRGB");
+    OK(DP_channel15_to_linear(DP_BIT15 / 2) < DP_BIT15 / 4,
+       "sRGB midpoint is darker in linear");
+
+    int mismatches = 0;
+    for (int i = 0; i <= 255; ++i) {
+        uint8_t c = DP_int_to_uint8(i);
+        uint16_t linear = DP_channel15_to_linear(DP_channel8_to_15(c));
+        if (DP_channel15_to_8(DP_channel15_to_srgb(linear)) != c) {
+            ++mismatches;
+        }
+    }
+    INT_EQ_OK(mismatches, 0, "8 bit channels survive a round trip");
+
+    DP_Pixel15 pixel = DP_pixel15_premultiply((DP_UPixel15){
+        .b = DP_BIT15, .g = DP_BIT15 / 2, .r = 0, .a = DP_BIT15 / 2});
+    DP_Pixel15 linear = DP_pixel15_to_linear(pixel);
+    UINT_EQ_OK(linear.a, pixel.a, "pixel alpha is unchanged in linear");
+    UINT_EQ_OK(linear.b, pixel.b, "premultiplied white stays the same");
+    OK(linear.g < pixel.g, "premultiplied gray gets darker");
+    DP_Pixel15 srgb = DP_pixel15_to_srgb(linear);
+    OK(DP_pixel15_to_8(srgb).color == DP_pixel15_to_8(pixel).color,
+       "pixel survives a round trip");
+}
+
+
+static void gradient_linear_vs_srgb(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = gradient_new(dc);
+
+    DP_Image *srgb = DP_canvas_state_to_flat_image(
+        cs, DP_FLAT_IMAGE_RENDER_FLAGS, NULL, NULL);
+    DP_Image *linear = DP_canvas_state_to_flat_image(
+        cs, DP_FLAT_IMAGE_RENDER_FLAGS | DP_FLAT_IMAGE_LINEAR_BLENDING, NULL,
+        NULL);
+    FATAL(NOT_NULL_OK(srgb, "got sRGB-blended image"));
+    FATAL(NOT_NULL_OK(linear, "got linear-blended image"));
+
+    INT_EQ_OK(gray_at(srgb, 0), 255, "sRGB gradient starts white");
+    INT_EQ_OK(gray_at(linear, 0), 255, "linear gradient starts white");
+    INT_EQ_OK(gray_at(srgb, 255), 0, "sRGB gradient ends black");
+    INT_EQ_OK(gray_at(linear, 255), 0, "linear gradient ends black");
+
+    // Half-transparent black over white is a medium gray when blending in
+    // sRGB, but half of the light is perceived as much brighter than that.
+    int srgb_mid = gray_at(srgb, 128);
+    int linear_mid = gray_at(linear, 128);
+    OK(srgb_mid >= 126 && srgb_mid <= 128, "sRGB midpoint %d is about 127",
+       srgb_mid);
+    OK(linear_mid >= 186 && linear_mid <= 189,
+       "linear midpoint %d is about 187", linear_mid);
+
+    bool linear_brighter = true;
+    bool monotonic = true;
+    for (int x = 1; x < GRADIENT_WIDTH - 1; ++x) {
+        if (gray_at(linear, x) < gray_at(srgb, x)) {
+            linear_brighter = false;
+        }
+        if (gray_at(linear, x) > gray_at(linear, x - 1)) {
+            monotonic = false;
+        }
+    }
+    OK(linear_brighter, "linear gradient is never darker than the sRGB one");
+    OK(monotonic, "linear gradient gets darker from left to right");
+
+    DP_TransientTile *tt = DP_canvas_state_flatten_tile(
+        cs, 128 / DP_TILE_SIZE,
+        DP_FLAT_IMAGE_RENDER_FLAGS | DP_FLAT_IMAGE_LINEAR_BLENDING, NULL);
+    if (NOT_NULL_OK(tt, "got linear-blended tile")) {
+        DP_Pixel8 pixel = DP_pixel15_to_8(
+            DP_transient_tile_pixel_at(tt, 128 % DP_TILE_SIZE, 0));
+        INT_EQ_OK(pixel.g, linear_mid, "flattened tile matches image");
+        DP_transient_tile_decref(tt);
+    }
+
+    DP_image_free(linear);
+    DP_image_free(srgb);
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(channel_round_trip);
+    REGISTER_TEST(gradient_linear_vs_srgb);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "canvas_state_common.h"
+#include <dpcommon/common.h>
+#include <dpengine/canvas_history.h>
+#include <dpengine/canvas_state.h>
+#include <dpengine/draw_context.h>
+#include <dpengine/tile.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+
+
+// Fills part of the tile at the given tile coordinates.
+static DP_Message *fill_tile_new(int layer_id, int tile_x, int tile_y)
+{
+    return DP_msg_fill_rect_new(
+        1, (uint16_t)layer_id, DP_BLEND_MODE_NORMAL,
+        (uint32_t)(tile_x * DP_TILE_SIZE + 1),
+        (uint32_t)(tile_y * DP_TILE_SIZE + 1), 10, 10, 0xffff0000u);
+}
+
+// A four by four tiles canvas with a single layer.
+static DP_CanvasState *canvas_new(DP_DrawContext *dc)
+{
+    DP_CanvasState *cs = DP_canvas_state_new();
+    cs = handle(cs, dc,
+                DP_msg_canvas_resize_new(1, 0, DP_TILE_SIZE * 4,
+                                         DP_TILE_SIZE * 4, 0));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "layer", 5));
+    return cs;
+}
+
+static DP_CanvasMemoryUsage usage_of(DP_CanvasState *cs)
+{
+    return DP_canvas_state_memory_usage(1, &cs);
+}
+
+
+static void memory_usage_unique_tiles(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc);
+    DP_CanvasMemoryUsage usage = usage_of(cs);
+    INT_EQ_OK(usage.tile_count, 0, "empty layer has no tiles");
+    UINT_EQ_OK(usage.tile_bytes, 0, "empty layer takes no tile bytes");
+    INT_EQ_OK(usage.layer_count, 1, "one layer");
+
+    cs = handle(cs, dc, fill_tile_new(0x101, 0, 0));
+    usage = usage_of(cs);
+    INT_EQ_OK(usage.tile_count, 1, "one tile after drawing");
+    UINT_EQ_OK(usage.tile_bytes, DP_TILE_BYTES, "one tile's worth of bytes");
+
+    cs = handle(cs, dc, fill_tile_new(0x101, 2, 3));
+    usage = usage_of(cs);
+    INT_EQ_OK(usage.tile_count, 2, "another tile grows the count");
+    UINT_EQ_OK(usage.tile_bytes, DP_TILE_BYTES * 2, "and the bytes");
+
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(1, 0x102, 0, 0, 0, 0, "two", 3));
+    cs = handle(cs, dc, fill_tile_new(0x102, 0, 0));
+    usage = usage_of(cs);
+    INT_EQ_OK(usage.tile_count, 3, "tile on another layer counts");
+    INT_EQ_OK(usage.layer_count, 2, "two layers");
+
+    DP_Message *msg =
+        DP_canvas_state_msg_fill_layer_new(cs, 1, 0x102, 0xff00ff00u);
+    cs = handle(cs, dc, msg);
+    usage = usage_of(cs);
+    INT_EQ_OK(usage.tile_count, 3,
+              "filled layer shares one tile across all positions");
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void memory_usage_shared_tiles(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc);
+    cs = handle(cs, dc, fill_tile_new(0x101, 0, 0));
+    cs = handle(cs, dc, fill_tile_new(0x101, 1, 0));
+    DP_CanvasState *before = DP_canvas_state_incref(cs);
+    cs = handle(cs, dc, fill_tile_new(0x101, 2, 0));
+
+    DP_CanvasState *states[] = {before, cs, before};
+    DP_CanvasMemoryUsage usage =
+        DP_canvas_state_memory_usage((int)DP_ARRAY_LENGTH(states), states);
+    INT_EQ_OK(usage.tile_count, 3, "shared tiles only counted once");
+    UINT_EQ_OK(usage.tile_bytes, DP_TILE_BYTES * 3, "shared bytes once");
+    INT_EQ_OK(usage.layer_count, 2,
+              "changed layer counts once for each version");
+
+    DP_CanvasState *same_states[] = {cs, cs};
+    usage = DP_canvas_state_memory_usage(2, same_states);
+    INT_EQ_OK(usage.tile_count, 3, "same state twice counts once");
+    INT_EQ_OK(usage.layer_count, 1, "same layer twice counts once");
+
+    cs = handle(cs, dc, fill_tile_new(0x101, 0, 0));
+    DP_CanvasState *changed_states[] = {before, cs};
+    usage = DP_canvas_state_memory_usage(2, changed_states);
+    INT_EQ_OK(usage.tile_count, 4, "redrawn tile is no longer shared");
+
+    DP_canvas_state_decref(before);
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void memory_usage_history(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasHistory *ch = DP_canvas_history_new(NULL, NULL, false, NULL);
+    DP_Message *msgs[] = {
+        DP_msg_canvas_resize_new(1, 0, DP_TILE_SIZE * 4, DP_TILE_SIZE * 4, 0),
+        DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "layer", 5),
+        DP_msg_undo_point_new(1),
+        fill_tile_new(0x101, 0, 0),
+        DP_msg_undo_point_new(1),
+        fill_tile_new(0x101, 1, 0),
+        DP_msg_undo_point_new(1),
+        fill_tile_new(0x101, 0, 0),
+    };
+    DP_CanvasMemoryUsage initial = DP_canvas_history_memory_usage(ch);
+    size_t message_bytes = 0;
+    for (size_t i = 0; i < DP_ARRAY_LENGTH(msgs); ++i) {
+        message_bytes += DP_message_length(msgs[i]);
+        OK(DP_canvas_history_handle(ch, dc, msgs[i]), "handle message %zu", i);
+        DP_message_decref(msgs[i]);
+    }
+
+    DP_CanvasMemoryUsage usage = DP_canvas_history_memory_usage(ch);
+    INT_EQ_OK(usage.history_count - initial.history_count,
+              (int)DP_ARRAY_LENGTH(msgs), "all messages are in the history");
+    UINT_EQ_OK(usage.history_message_bytes - initial.history_message_bytes,
+               message_bytes, "history message bytes");
+    // The tile at 0, 0 is drawn on twice, the undo point before the second
+    // time keeps the first version around. The tile at 1, 0 is shared.
+    INT_EQ_OK(usage.tile_count, 3, "undo states share unchanged tiles");
+
+    DP_canvas_history_free(ch);
+    DP_draw_context_free(dc);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(memory_usage_unique_tiles);
+    REGISTER_TEST(memory_usage_shared_tiles);
+    REGISTER_TEST(memory_usage_history);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,248 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "canvas_state_common.h"
+#include <dpcommon/common.h>
+#include <dpengine/canvas_state.h>
+#include <dpengine/draw_context.h>
+#include <dpengine/tile.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+
+
+static DP_CanvasState *canvas_new(DP_DrawContext *dc)
+{
+    DP_CanvasState *cs = DP_canvas_state_new();
+    cs = handle(cs, dc, DP_msg_canvas_resize_new(1, 0, 100, 80, 0));
+    return handle(
+        cs, dc,
+        DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "layer", 5));
+}
+
+static DP_PaintError handle_with_error(DP_CanvasState *cs, DP_DrawContext *dc,
+                                       DP_Message *msg)
+{
+    DP_PaintError error = DP_PAINT_ERROR_OTHER;
+    DP_CanvasState *next =
+        DP_canvas_state_handle_with_error(cs, dc, NULL, msg, &error);
+    DP_message_decref(msg);
+    DP_canvas_state_decref_nullable(next);
+    return error;
+}
+
+static void set_garbage(size_t size, unsigned char *out,
+                        DP_UNUSED void *user)
+{
+    memset(out, 0xff, size);
+}
+
+static void set_image(size_t size, unsigned char *out, void *user)
+{
+    memcpy(out, user, size);
+}
+
+static unsigned char *get_compress_buffer(size_t size, void *user)
+{
+    unsigned char **out_buffer = user;
+    *out_buffer = DP_malloc(size);
+    return *out_buffer;
+}
+
+static DP_Message *put_tile_new(const unsigned char *image, size_t size)
+{
+    return DP_msg_put_tile_new(1, 0x101, 0, 0, 0, 0, set_image, size,
+                               (void *)image);
+}
+
+
+static void paint_error_none(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc);
+
+    INT_EQ_OK(handle_with_error(cs, dc,
+                                DP_msg_fill_rect_new(1, 0x101,
+                                                     DP_BLEND_MODE_NORMAL, 0,
+                                                     0, 10, 10, 0xff000000u)),
+              DP_PAINT_ERROR_NONE, "successful fill rect has no error");
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void paint_error_layer_not_found(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc);
+
+    INT_EQ_OK(handle_with_error(cs, dc,
+                                DP_msg_fill_rect_new(1, 0x102,
+                                                     DP_BLEND_MODE_NORMAL, 0,
+                                                     0, 10, 10, 0xff000000u)),
+              DP_PAINT_ERROR_LAYER_NOT_FOUND,
+              "fill rect on nonexistent layer is layer not found");
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void paint_error_out_of_bounds(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc);
+
+    INT_EQ_OK(handle_with_error(cs, dc,
+                                DP_msg_fill_rect_new(1, 0x101,
+                                                     DP_BLEND_MODE_NORMAL, 100,
+                                                     0, 10, 10, 0xff000000u)),
+              DP_PAINT_ERROR_OUT_OF_BOUNDS,
+              "fill rect outside the canvas is out of bounds");
+    INT_EQ_OK(handle_with_error(cs, dc,
+                                DP_msg_put_image_new(1, 0x101,
+                                                     DP_BLEND_MODE_NORMAL, 0,
+                                                     80, 10, 10, set_garbage,
+                                                     16, NULL)),
+              DP_PAINT_ERROR_OUT_OF_BOUNDS,
+              "put image outside the canvas is out of bounds");
+    INT_EQ_OK(handle_with_error(cs, dc,
+                                DP_msg_put_tile_new(1, 0x101, 0, 0, 100, 0,
+                                                    set_garbage, 16, NULL)),
+              DP_PAINT_ERROR_OUT_OF_BOUNDS,
+              "put tile outside the canvas is out of bounds");
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void paint_error_tile_decode(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc);
+
+    INT_EQ_OK(handle_with_error(cs, dc,
+                                DP_msg_put_tile_new(1, 0x101, 0, 0, 0, 0,
+                                                    set_garbage, 16, NULL)),
+              DP_PAINT_ERROR_TILE_DECODE,
+              "put tile with garbage data is a decode error");
+    INT_EQ_OK(handle_with_error(
+                  cs, dc,
+                  DP_msg_canvas_background_new(1, set_garbage, 16, NULL)),
+              DP_PAINT_ERROR_TILE_DECODE,
+              "background with garbage data is a decode error");
+
+    // Valid tile data on a nonexistent layer is the layer's fault instead.
+    unsigned char color[] = {0xff, 0x00, 0x00, 0xff};
+    INT_EQ_OK(handle_with_error(cs, dc,
+                                DP_msg_put_tile_new(1, 0x102, 0, 0, 0, 0,
+                                                    set_image, sizeof(color),
+                                                    color)),
+              DP_PAINT_ERROR_LAYER_NOT_FOUND,
+              "put tile with valid data on nonexistent layer is not found");
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void paint_error_tile_checksum(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc);
+    DP_Tile *t = DP_tile_new_from_bgra(0, 0xff336699u);
+
+    unsigned char *legacy = NULL;
+    size_t legacy_size = DP_tile_compress(t, DP_draw_context_tile8_buffer(dc),
+                                          get_compress_buffer, &legacy);
+    FATAL(OK(legacy_size != 0, "compressed tile without checksum"));
+    INT_EQ_OK(DP_tile_compressed_checksum(legacy, legacy_size),
+              DP_TILE_CHECKSUM_NONE, "legacy tile has no checksum");
+    INT_EQ_OK(handle_with_error(cs, dc, put_tile_new(legacy, legacy_size)),
+              DP_PAINT_ERROR_NONE, "put tile without checksum still applies");
+
+    unsigned char *image = NULL;
+    size_t size = DP_tile_compress_checksummed(
+        t, DP_draw_context_tile8_buffer(dc), get_compress_buffer, &image);
+    FATAL(OK(size == legacy_size + DP_TILE_CHECKSUM_TRAILER_LENGTH,
+             "compressed tile with checksum trailer"));
+    INT_EQ_OK(DP_tile_compressed_checksum(image, size), DP_TILE_CHECKSUM_VALID,
+              "checksum of intact tile is valid");
+    INT_EQ_OK(handle_with_error(cs, dc, put_tile_new(image, size)),
+              DP_PAINT_ERROR_NONE, "put tile with correct checksum applies");
+    DP_Tile *decoded = DP_tile_new_from_compressed(dc, 0, image, size);
+    if (NOT_NULL_OK(decoded, "decoded tile with checksum")) {
+        OK(DP_tile_pixels_equal(t, decoded), "decoded tile is unchanged");
+        DP_tile_decref(decoded);
+    }
+
+    image[size / 2] ^= 0x20;
+    INT_EQ_OK(DP_tile_compressed_checksum(image, size),
+              DP_TILE_CHECKSUM_MISMATCH,
+              "checksum of corrupted tile mismatches");
+    INT_EQ_OK(handle_with_error(cs, dc, put_tile_new(image, size)),
+              DP_PAINT_ERROR_TILE_CHECKSUM,
+              "put tile with corrupted data is a checksum error");
+    INT_EQ_OK(handle_with_error(cs, dc,
+                                DP_msg_canvas_background_new(1, set_image,
+                                                             size, image)),
+              DP_PAINT_ERROR_TILE_CHECKSUM,
+              "background with corrupted data is a checksum error");
+    NOK(DP_tile_new_from_compressed(dc, 0, image, size),
+        "corrupted tile doesn't decode");
+
+    DP_free(image);
+    DP_free(legacy);
+    DP_tile_decref(t);
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void paint_error_invalid_blend_mode(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc);
+
+    INT_EQ_OK(handle_with_error(cs, dc,
+                                DP_msg_fill_rect_new(
+                                    1, 0x101, DP_BLEND_MODE_LAST_EXCEPT_REPLACE,
+                                    0, 0, 10, 10, 0)),
+              DP_PAINT_ERROR_INVALID_BLEND_MODE,
+              "fill rect with nonexistent blend mode is invalid");
+    INT_EQ_OK(handle_with_error(cs, dc,
+                                DP_msg_put_image_new(
+                                    1, 0x101, DP_BLEND_MODE_LAST_EXCEPT_REPLACE,
+                                    0, 0, 10, 10, set_garbage, 16, NULL)),
+              DP_PAINT_ERROR_INVALID_BLEND_MODE,
+              "put image with nonexistent blend mode is invalid");
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void paint_error_other(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc);
+
+    INT_EQ_OK(handle_with_error(cs, dc,
+                                DP_msg_layer_tree_delete_new(1, 0x102, 0)),
+              DP_PAINT_ERROR_OTHER,
+              "deleting a nonexistent layer is some other error");
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(paint_error_none);
+    REGISTER_TEST(paint_error_layer_not_found);
+    REGISTER_TEST(paint_error_out_of_bounds);
+    REGISTER_TEST(paint_error_tile_decode);
+    REGISTER_TEST(paint_error_tile_checksum);
+    REGISTER_TEST(paint_error_invalid_blend_mode);
+    REGISTER_TEST(paint_error_other);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,79 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "canvas_state_common.h"
+#include <dpcommon/common.h>
+#include <dpengine/canvas_state.h>
+#include <dpengine/draw_context.h>
+#include <dpengine/view_mode.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+
+
+static DP_CanvasState *fill_rect(DP_CanvasState *cs, DP_DrawContext *dc,
+                                 unsigned int context_id, uint16_t layer_id,
+                                 uint32_t x, uint32_t y, uint32_t w, uint32_t h)
+{
+    return handle(cs, dc,
+                  DP_msg_fill_rect_new(context_id, layer_id,
+                                       DP_BLEND_MODE_NORMAL, x, y, w, h,
+                                       0xff336699u));
+}
+
+static void check_pick(TEST_PARAMS, DP_CanvasState *cs, int x, int y,
+                       unsigned int expected_context_id,
+                       int expected_layer_id, const char *what)
+{
+    DP_ViewModePick pick = DP_view_mode_pick_normal(cs, x, y);
+    UINT_EQ_OK(pick.context_id, expected_context_id, "%s user", what);
+    INT_EQ_OK(pick.layer_id, expected_layer_id, "%s layer", what);
+}
+
+
+static void pick_attribution_last_writer(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = DP_canvas_state_new();
+    cs = handle(cs, dc,
+                DP_msg_canvas_resize_new(1, 0, DP_TILE_SIZE * 2, DP_TILE_SIZE,
+                                         0));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "a", 1));
+    // The first user draws across both tiles, the second one over part of
+    // what the first one drew in the second tile.
+    cs = fill_rect(cs, dc, 1, 0x101, 0, 0, 100, 10);
+    cs = fill_rect(cs, dc, 2, 0x101, 80, 0, 20, 10);
+
+    check_pick(TEST_ARGS, cs, 10, 5, 1, 0x101, "first user's pixel");
+    check_pick(TEST_ARGS, cs, 90, 5, 2, 0x101, "overlapping pixel");
+    check_pick(TEST_ARGS, cs, 70, 5, 2, 0x101,
+               "first user's pixel in a tile the second user touched last");
+    check_pick(TEST_ARGS, cs, 10, 40, 0, -1, "transparent pixel");
+    check_pick(TEST_ARGS, cs, -1, 0, 0, -1, "left of the canvas");
+    check_pick(TEST_ARGS, cs, DP_TILE_SIZE * 2, 0, 0, -1,
+               "right of the canvas");
+
+    cs = fill_rect(cs, dc, 1, 0x101, 90, 0, 1, 1);
+    check_pick(TEST_ARGS, cs, 90, 5, 1, 0x101,
+               "overlapping pixel drawn over again");
+
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(2, 0x201, 0, 0, 0, 0, "b", 1));
+    cs = fill_rect(cs, dc, 2, 0x201, 0, 0, 10, 10);
+    check_pick(TEST_ARGS, cs, 5, 5, 2, 0x201, "pixel on the layer on top");
+    check_pick(TEST_ARGS, cs, 20, 5, 1, 0x101,
+               "pixel through transparency on the layer on top");
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(pick_attribution_last_writer);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
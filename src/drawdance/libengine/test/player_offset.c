@@ -0,0 +1,257 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "canvas_state_common.h"
+#include <dpcommon/common.h>
+#include <dpcommon/input.h>
+#include <dpcommon/output.h>
+#include <dpengine/annotation.h>
+#include <dpengine/annotation_list.h>
+#include <dpengine/canvas_state.h>
+#include <dpengine/draw_context.h>
+#include <dpengine/image.h>
+#include <dpengine/layer_routes.h>
+#include <dpengine/pixels.h>
+#include <dpengine/player.h>
+#include <dpmsg/binary_writer.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dpmsg/protover.h>
+#include <dptest.h>
+#include <parson.h>
+
+#define RECORDING_PATH "test/tmp/player_offset.dprec"
+
+
+typedef struct DP_CollectedMessages {
+    int count;
+    DP_Message *msgs[16];
+} DP_CollectedMessages;
+
+static void write_recording(TEST_PARAMS, int count, DP_Message **msgs)
+{
+    DP_Output *output = DP_file_output_new_from_path(RECORDING_PATH);
+    FATAL(NOT_NULL_OK(output, "got output for %s", RECORDING_PATH));
+    DP_BinaryWriter *writer = DP_binary_writer_new(output);
+
+    JSON_Value *header_value = json_value_init_object();
+    JSON_Object *header = json_value_get_object(header_value);
+    json_object_set_string(header, "version", DP_PROTOCOL_VERSION);
+    OK(DP_binary_writer_write_header(writer, header), "wrote header");
+    json_value_free(header_value);
+
+    for (int i = 0; i < count; ++i) {
+        OK(DP_binary_writer_write_message(writer, msgs[i]) != 0,
+           "wrote message %d", i);
+        DP_message_decref(msgs[i]);
+    }
+    DP_binary_writer_free(writer);
+}
+
+static void replay_with_offset(TEST_PARAMS, int dx, int dy, int id_offset,
+                               DP_CollectedMessages *cm)
+{
+    DP_Input *input = DP_file_input_new_from_path(RECORDING_PATH);
+    FATAL(NOT_NULL_OK(input, "got input for %s", RECORDING_PATH));
+    DP_Player *player =
+        DP_player_new(DP_PLAYER_TYPE_BINARY, RECORDING_PATH, input, NULL);
+    FATAL(NOT_NULL_OK(player, "got player for %s", RECORDING_PATH));
+    DP_player_offset_set(player, dx, dy, id_offset);
+
+    cm->count = 0;
+    DP_Message *msg;
+    while (DP_player_step(player, &msg) == DP_PLAYER_SUCCESS) {
+        if (cm->count < (int)DP_ARRAY_LENGTH(cm->msgs)) {
+            cm->msgs[cm->count++] = msg;
+        }
+        else {
+            DP_message_decref(msg);
+        }
+    }
+    DP_player_free(player);
+}
+
+static void collected_messages_dispose(DP_CollectedMessages *cm)
+{
+    for (int i = 0; i < cm->count; ++i) {
+        DP_message_decref(cm->msgs[i]);
+    }
+}
+
+// The canvas the recording gets replayed into, which already has a layer with
+// the same id as the recording's.
+static DP_CanvasState *target_canvas_new(DP_DrawContext *dc)
+{
+    DP_CanvasState *cs = DP_canvas_state_new();
+    cs = handle(cs, dc, DP_msg_canvas_resize_new(1, 0, 100, 100, 0));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "target",
+                                             6));
+    return cs;
+}
+
+static DP_CanvasState *apply(DP_CanvasState *cs, DP_DrawContext *dc,
+                             DP_CollectedMessages *cm)
+{
+    for (int i = 0; i < cm->count; ++i) {
+        DP_Message *msg = cm->msgs[i];
+        if (DP_message_type_command(DP_message_type(msg))) {
+            cs = handle(cs, dc, DP_message_incref(msg));
+        }
+    }
+    return cs;
+}
+
+static DP_Message *find_message(DP_CollectedMessages *cm, DP_MessageType type)
+{
+    for (int i = 0; i < cm->count; ++i) {
+        if (DP_message_type(cm->msgs[i]) == type) {
+            return cm->msgs[i];
+        }
+    }
+    return NULL;
+}
+
+static uint32_t pixel_at(DP_CanvasState *cs, int x, int y)
+{
+    DP_Image *img = DP_canvas_state_to_flat_image(
+        cs, DP_FLAT_IMAGE_RENDER_FLAGS, NULL, NULL);
+    uint32_t color = img ? DP_image_pixel_at(img, x, y).color : 0;
+    DP_image_free(img);
+    return color;
+}
+
+static bool has_layer(DP_CanvasState *cs, int layer_id)
+{
+    return DP_layer_routes_search(DP_canvas_state_layer_routes_noinc(cs),
+                                  layer_id);
+}
+
+static void set_classic_dab(DP_UNUSED int count, DP_ClassicDab *dabs,
+                            DP_UNUSED void *user)
+{
+    DP_classic_dab_init(dabs, 0, 0, 0, 64, 255, 128);
+}
+
+static void set_tile_color(size_t size, unsigned char *out, void *user)
+{
+    memcpy(out, user, size);
+}
+
+
+static void player_offset_shifts_content(TEST_PARAMS)
+{
+    write_recording(
+        TEST_ARGS, 7,
+        (DP_Message *[]){
+            DP_msg_canvas_resize_new(1, 0, 64, 48, 0),
+            DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "layer", 5),
+            DP_msg_fill_rect_new(1, 0x101, DP_BLEND_MODE_NORMAL, 8, 8, 16, 16,
+                                 0xffcc3366u),
+            DP_msg_draw_dabs_classic_new(1, 0x101, 40, 40, 0xff000000u,
+                                         DP_BLEND_MODE_NORMAL, set_classic_dab,
+                                         1, NULL),
+            DP_msg_annotation_create_new(1, 0x101, 4, 4, 20, 10),
+            DP_msg_put_tile_new(1, 0x101, 0, 0, 0, 0, set_tile_color, 4,
+                                (uint8_t[]){0xff, 0x00, 0x00, 0xff}),
+            DP_msg_move_pointer_new(1, 40, 40),
+        });
+
+    DP_CollectedMessages cm;
+    replay_with_offset(TEST_ARGS, 20, 30, 0x1000, &cm);
+    NOK(find_message(&cm, DP_MSG_CANVAS_RESIZE), "canvas resize is dropped");
+    NOK(find_message(&cm, DP_MSG_PUT_TILE), "unaligned put tile is dropped");
+
+    DP_Message *msg = find_message(&cm, DP_MSG_FILL_RECT);
+    if (NOT_NULL_OK(msg, "fill rect is emitted")) {
+        DP_MsgFillRect *mfr = DP_msg_fill_rect_cast(msg);
+        UINT_EQ_OK(DP_msg_fill_rect_layer(mfr), 0x1101, "fill rect layer");
+        UINT_EQ_OK(DP_msg_fill_rect_x(mfr), 28, "fill rect x");
+        UINT_EQ_OK(DP_msg_fill_rect_y(mfr), 38, "fill rect y");
+    }
+    msg = find_message(&cm, DP_MSG_DRAW_DABS_CLASSIC);
+    if (NOT_NULL_OK(msg, "classic dabs are emitted")) {
+        DP_MsgDrawDabsClassic *mddc = DP_msg_draw_dabs_classic_cast(msg);
+        INT_EQ_OK(DP_msg_draw_dabs_classic_x(mddc), 40 + 20 * 4,
+                  "classic dabs x is offset in quarter pixels");
+        INT_EQ_OK(DP_msg_draw_dabs_classic_y(mddc), 40 + 30 * 4,
+                  "classic dabs y is offset in quarter pixels");
+    }
+    msg = find_message(&cm, DP_MSG_MOVE_POINTER);
+    if (NOT_NULL_OK(msg, "move pointer is emitted")) {
+        DP_MsgMovePointer *mmp = DP_msg_move_pointer_cast(msg);
+        INT_EQ_OK(DP_msg_move_pointer_x(mmp), 40 + 20 * 4,
+                  "move pointer x is offset in quarter pixels");
+        INT_EQ_OK(DP_msg_move_pointer_y(mmp), 40 + 30 * 4,
+                  "move pointer y is offset in quarter pixels");
+    }
+
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = apply(target_canvas_new(dc), dc, &cm);
+    INT_EQ_OK(DP_canvas_state_width(cs), 100, "target canvas keeps its width");
+    INT_EQ_OK(DP_canvas_state_height(cs), 100,
+              "target canvas keeps its height");
+    OK(has_layer(cs, 0x101), "target layer is still there");
+    OK(has_layer(cs, 0x1101), "recorded layer is created with offset id");
+    UINT_EQ_OK(pixel_at(cs, 40, 50), 0xffcc3366u, "rect lands shifted");
+    UINT_EQ_OK(pixel_at(cs, 10, 10), 0, "nothing at the original location");
+
+    DP_AnnotationList *al = DP_canvas_state_annotations_noinc(cs);
+    int index = DP_annotation_list_index_by_id(al, 0x1101);
+    if (OK(index >= 0, "annotation is created with offset id")) {
+        DP_Annotation *a = DP_annotation_list_at_noinc(al, index);
+        INT_EQ_OK(DP_annotation_x(a), 24, "annotation x is offset");
+        INT_EQ_OK(DP_annotation_y(a), 34, "annotation y is offset");
+    }
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+    collected_messages_dispose(&cm);
+}
+
+static void player_offset_out_of_range(TEST_PARAMS)
+{
+    write_recording(
+        TEST_ARGS, 4,
+        (DP_Message *[]){
+            DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "layer", 5),
+            DP_msg_fill_rect_new(1, 0x101, DP_BLEND_MODE_NORMAL, 8, 0, 16, 16,
+                                 0xffcc3366u),
+            DP_msg_fill_rect_new(1, 0x101, DP_BLEND_MODE_NORMAL, 80, 0, 16,
+                                 16, 0xff3366ccu),
+            DP_msg_put_tile_new(1, 0x101, 0, 1, 0, 0, set_tile_color, 4,
+                                (uint8_t[]){0xff, 0x00, 0x00, 0xff}),
+        });
+
+    DP_CollectedMessages cm;
+    replay_with_offset(TEST_ARGS, -DP_TILE_SIZE, 0, 0, &cm);
+    INT_EQ_OK(cm.count, 3, "fill rect moved past the left edge is dropped");
+    DP_Message *msg = find_message(&cm, DP_MSG_FILL_RECT);
+    if (NOT_NULL_OK(msg, "fill rect in range is emitted")) {
+        UINT_EQ_OK(DP_msg_fill_rect_x(DP_msg_fill_rect_cast(msg)), 16,
+                   "fill rect in range is shifted");
+    }
+    msg = find_message(&cm, DP_MSG_PUT_TILE);
+    if (NOT_NULL_OK(msg, "tile-aligned put tile is emitted")) {
+        DP_MsgPutTile *mpt = DP_msg_put_tile_cast(msg);
+        UINT_EQ_OK(DP_msg_put_tile_col(mpt), 0, "put tile is shifted a column");
+        UINT_EQ_OK(DP_msg_put_tile_layer(mpt), 0x101,
+                   "zero id offset leaves layer alone");
+    }
+    collected_messages_dispose(&cm);
+
+    replay_with_offset(TEST_ARGS, 0, 0, 0xff00, &cm);
+    NOK(find_message(&cm, DP_MSG_LAYER_TREE_CREATE),
+        "layer whose id would overflow is dropped");
+    collected_messages_dispose(&cm);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(player_offset_shifts_content);
+    REGISTER_TEST(player_offset_out_of_range);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,98 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include <dpcommon/common.h>
+#include <dpengine/pointer_tracker.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+
+
+static void handle(DP_PointerTracker *pt, DP_Message *msg)
+{
+    DP_pointer_tracker_handle(pt, msg);
+    DP_message_decref(msg);
+}
+
+static void check_position(TEST_PARAMS, DP_PointerTracker *pt,
+                           unsigned int context_id, float expected_x,
+                           float expected_y)
+{
+    float x = -1.0f, y = -1.0f;
+    OK(DP_pointer_tracker_position(pt, context_id, &x, &y),
+       "user %u has a position", context_id);
+    OK(x == expected_x && y == expected_y,
+       "user %u is at (%g, %g), got (%g, %g)", context_id,
+       (double)expected_x, (double)expected_y, (double)x, (double)y);
+}
+
+typedef struct CollectedCursors {
+    int count;
+    unsigned int context_ids[DP_POINTER_TRACKER_COUNT];
+} CollectedCursors;
+
+static void collect_cursor(void *user, unsigned int context_id,
+                           DP_UNUSED float x, DP_UNUSED float y)
+{
+    CollectedCursors *cc = user;
+    cc->context_ids[cc->count++] = context_id;
+}
+
+
+static void pointer_tracker_update(TEST_PARAMS)
+{
+    DP_PointerTracker pt;
+    DP_pointer_tracker_init(&pt);
+    NOK(DP_pointer_tracker_position(&pt, 1, NULL, NULL),
+        "no position before any movement");
+
+    handle(&pt, DP_msg_move_pointer_new(1, 40, 80));
+    check_position(TEST_ARGS, &pt, 1, 10.0f, 20.0f);
+    handle(&pt, DP_msg_move_pointer_new(2, -6, 2));
+    check_position(TEST_ARGS, &pt, 2, -1.5f, 0.5f);
+    handle(&pt, DP_msg_move_pointer_new(1, 400, 4));
+    check_position(TEST_ARGS, &pt, 1, 100.0f, 1.0f);
+    INT_EQ_OK(DP_pointer_tracker_count(&pt), 2, "two active cursors");
+
+    CollectedCursors cc = {0, {0}};
+    DP_pointer_tracker_each(&pt, collect_cursor, &cc);
+    INT_EQ_OK(cc.count, 2, "iteration visits both cursors");
+    UINT_EQ_OK(cc.context_ids[0], 1u, "first cursor is user 1");
+    UINT_EQ_OK(cc.context_ids[1], 2u, "second cursor is user 2");
+}
+
+static void pointer_tracker_prune(TEST_PARAMS)
+{
+    DP_PointerTracker pt;
+    DP_pointer_tracker_init(&pt);
+    handle(&pt, DP_msg_move_pointer_new(1, 4, 4));
+    handle(&pt, DP_msg_move_pointer_new(2, 8, 8));
+    handle(&pt, DP_msg_move_pointer_new(3, 12, 12));
+
+    DP_Message *leave = DP_msg_leave_new(2);
+    OK(DP_pointer_tracker_handle(&pt, leave), "leave prunes the user");
+    NOK(DP_pointer_tracker_handle(&pt, leave), "leaving again changes nothing");
+    DP_message_decref(leave);
+    NOK(DP_pointer_tracker_position(&pt, 2, NULL, NULL),
+        "pruned user has no position");
+    INT_EQ_OK(DP_pointer_tracker_count(&pt), 2, "two cursors remain");
+
+    CollectedCursors cc = {0, {0}};
+    DP_pointer_tracker_each(&pt, collect_cursor, &cc);
+    INT_EQ_OK(cc.count, 2, "iteration skips the pruned cursor");
+    UINT_EQ_OK(cc.context_ids[0], 1u, "first remaining cursor is user 1");
+    UINT_EQ_OK(cc.context_ids[1], 3u, "second remaining cursor is user 3");
+
+    handle(&pt, DP_msg_move_pointer_new(2, 16, 16));
+    check_position(TEST_ARGS, &pt, 2, 4.0f, 4.0f);
+    INT_EQ_OK(DP_pointer_tracker_count(&pt), 3, "rejoined user is tracked");
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(pointer_tracker_update);
+    REGISTER_TEST(pointer_tracker_prune);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,198 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "canvas_state_common.h"
+#include <dpcommon/common.h>
+#include <dpcommon/output.h>
+#include <dpengine/canvas_state.h>
+#include <dpengine/compress.h>
+#include <dpengine/draw_context.h>
+#include <dpengine/layer_content.h>
+#include <dpengine/layer_routes.h>
+#include <dpengine/pixels.h>
+#include <dpengine/tile.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+
+
+typedef struct CompressedImage {
+    size_t size;
+    unsigned char *buffer;
+} CompressedImage;
+
+static unsigned char *get_compressed_buffer(size_t size, void *user)
+{
+    CompressedImage *ci = user;
+    ci->buffer = DP_malloc(size);
+    return ci->buffer;
+}
+
+static void set_image(size_t size, unsigned char *out, void *user)
+{
+    memcpy(out, ((CompressedImage *)user)->buffer, size);
+}
+
+static DP_Message *put_image_color_new(int x, int y, int width, int height,
+                                       uint32_t color)
+{
+    size_t count = DP_int_to_size(width) * DP_int_to_size(height);
+    DP_Pixel8 *pixels = DP_malloc(sizeof(*pixels) * count);
+    for (size_t i = 0; i < count; ++i) {
+        pixels[i].color = color;
+    }
+    CompressedImage ci = {0, NULL};
+    ci.size = DP_compress_deflate((const unsigned char *)pixels,
+                                  sizeof(*pixels) * count,
+                                  get_compressed_buffer, &ci);
+    DP_free(pixels);
+    DP_Message *msg = DP_msg_put_image_new(
+        1, 0x101, DP_BLEND_MODE_NORMAL, DP_int_to_uint32(x),
+        DP_int_to_uint32(y), DP_int_to_uint32(width), DP_int_to_uint32(height),
+        set_image, ci.size, &ci);
+    DP_free(ci.buffer);
+    return msg;
+}
+
+static DP_Message *put_image_new(int x, int y, int width, int height)
+{
+    return put_image_color_new(x, y, width, height, 0xff0000ffu);
+}
+
+static DP_CanvasState *canvas_new(DP_DrawContext *dc)
+{
+    DP_CanvasState *cs = DP_canvas_state_new();
+    cs = handle(cs, dc, DP_msg_canvas_resize_new(1, 0, 100, 80, 0));
+    return handle(
+        cs, dc,
+        DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "layer", 5));
+}
+
+static DP_CanvasState *handle_put_image(DP_CanvasState *cs, DP_DrawContext *dc,
+                                        int x, int y, int width, int height)
+{
+    DP_Message *msg = put_image_new(x, y, width, height);
+    DP_CanvasState *next = DP_canvas_state_handle(cs, dc, NULL, msg);
+    DP_message_decref(msg);
+    return next;
+}
+
+// Takes ownership of the given canvas state.
+static DP_CanvasState *handle_put_image_color(DP_CanvasState *cs,
+                                              DP_DrawContext *dc, int x, int y,
+                                              int width, int height,
+                                              uint32_t color)
+{
+    return handle(cs, dc, put_image_color_new(x, y, width, height, color));
+}
+
+static DP_LayerContent *layer_content(DP_CanvasState *cs)
+{
+    DP_LayerRoutes *lr = DP_canvas_state_layer_routes_noinc(cs);
+    DP_LayerRoutesEntry *lre = DP_layer_routes_search(lr, 0x101);
+    return DP_layer_routes_entry_content(lre, cs);
+}
+
+static DP_Pixel15 pixel_at(DP_CanvasState *cs, int x, int y)
+{
+    return DP_layer_content_pixel_at(layer_content(cs), x, y);
+}
+
+static DP_Tile *tile_at(DP_CanvasState *cs, int x, int y)
+{
+    return DP_layer_content_tile_at_noinc(layer_content(cs), x, y);
+}
+
+
+static void put_image_partly_outside(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc);
+
+    DP_CanvasState *next = handle_put_image(cs, dc, 90, 70, 20, 20);
+    if (NOT_NULL_OK(next, "partly outside image is put")) {
+        UINT_EQ_OK(pixel_at(next, 90, 70).a, DP_BIT15,
+                   "pixel inside the canvas is painted");
+        UINT_EQ_OK(pixel_at(next, 99, 79).a, DP_BIT15,
+                   "pixel in the canvas corner is painted");
+        UINT_EQ_OK(pixel_at(next, 89, 69).a, 0,
+                   "pixel outside the image is untouched");
+        DP_canvas_state_decref(next);
+    }
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void put_image_fully_outside(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc);
+
+    NULL_OK(handle_put_image(cs, dc, 100, 0, 10, 10),
+            "image right of the canvas is rejected");
+    NULL_OK(handle_put_image(cs, dc, 0, 80, 10, 10),
+            "image below the canvas is rejected");
+    NULL_OK(handle_put_image(cs, dc, 500, 500, 4, 4),
+            "image far outside the canvas is rejected");
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void put_image_sub_tile(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = handle_put_image_color(canvas_new(dc), dc, 0, 0, 100,
+                                                80, 0xffff0000u);
+    FATAL(NOT_NULL_OK(cs, "canvas filled"));
+
+    DP_CanvasState *next =
+        handle_put_image_color(DP_canvas_state_incref(cs), dc, 70, 10, 3, 3,
+                               0xff0000ffu);
+    FATAL(NOT_NULL_OK(next, "3x3 image put"));
+    int changed = 0;
+    int mismatches = 0;
+    for (int y = 0; y < DP_TILE_SIZE; ++y) {
+        for (int x = DP_TILE_SIZE; x < 100; ++x) {
+            bool inside = x >= 70 && x < 73 && y >= 10 && y < 13;
+            DP_Pixel15 pixel = pixel_at(next, x, y);
+            if (!DP_pixel15_equal(pixel, pixel_at(cs, x, y))) {
+                ++changed;
+            }
+            if (pixel.b != (inside ? DP_BIT15 : 0)) {
+                ++mismatches;
+            }
+        }
+    }
+    INT_EQ_OK(changed, 9, "only the overlapped pixels changed");
+    INT_EQ_OK(mismatches, 0, "overlapped pixels have the image's color");
+    OK(tile_at(next, 1, 0) != tile_at(cs, 1, 0), "overlapped tile is copied");
+    OK(tile_at(next, 0, 0) == tile_at(cs, 0, 0)
+           && tile_at(next, 0, 1) == tile_at(cs, 0, 1)
+           && tile_at(next, 1, 1) == tile_at(cs, 1, 1),
+       "other tiles stay shared");
+
+    DP_CanvasState *again =
+        handle_put_image_color(DP_canvas_state_incref(next), dc, 70, 10, 3, 3,
+                               0xff0000ffu);
+    FATAL(NOT_NULL_OK(again, "same 3x3 image put again"));
+    OK(tile_at(again, 1, 0) == tile_at(next, 1, 0),
+       "putting identical pixels keeps the tile shared");
+
+    DP_canvas_state_decref(again);
+    DP_canvas_state_decref(next);
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(put_image_partly_outside);
+    REGISTER_TEST(put_image_fully_outside);
+    REGISTER_TEST(put_image_sub_tile);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,216 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include <dpcommon/common.h>
+#include <dpcommon/input.h>
+#include <dpcommon/output.h>
+#include <dpengine/canvas_state.h>
+#include <dpengine/draw_context.h>
+#include <dpengine/layer_routes.h>
+#include <dpmsg/binary_reader.h>
+#include <dpmsg/binary_writer.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dpmsg/recording_concat.h>
+#include <dptest.h>
+#include <parson.h>
+
+#define FIRST_PATH  "test/tmp/recording_concat_first.dprec"
+#define SECOND_PATH "test/tmp/recording_concat_second.dprec"
+#define OUTPUT_PATH "test/tmp/recording_concat_output.dprec"
+
+
+static void set_uint8s(int count, uint8_t *out, void *user)
+{
+    memcpy(out, user, DP_int_to_size(count));
+}
+
+static DP_Message *join_new(unsigned int user_id)
+{
+    return DP_msg_join_new(user_id, 0, "user", 4, NULL, 0, NULL);
+}
+
+static DP_Message *layer_create_new(unsigned int user_id, uint16_t layer_id)
+{
+    return DP_msg_layer_tree_create_new(user_id, layer_id, 0, 0, 0, 0, "layer",
+                                        5);
+}
+
+static DP_Message *fill_rect_new(unsigned int user_id, uint16_t layer_id)
+{
+    return DP_msg_fill_rect_new(user_id, layer_id, DP_BLEND_MODE_NORMAL, 0, 0,
+                                10, 10, 0xff000000u);
+}
+
+static void write_recording(TEST_PARAMS, const char *path, int count,
+                            DP_Message **msgs)
+{
+    DP_Output *output = DP_file_output_new_from_path(path);
+    FATAL(NOT_NULL_OK(output, "got output for %s", path));
+    DP_BinaryWriter *writer = DP_binary_writer_new(output);
+
+    JSON_Value *header_value = json_value_init_object();
+    JSON_Object *header = json_value_get_object(header_value);
+    json_object_set_string(header, "version", DP_PROTOCOL_VERSION);
+    json_object_set_string(header, "writer", path);
+    OK(DP_binary_writer_write_header(writer, header), "wrote header to %s",
+       path);
+    json_value_free(header_value);
+
+    for (int i = 0; i < count; ++i) {
+        OK(DP_binary_writer_write_message(writer, msgs[i]) != 0,
+           "wrote message %d to %s", i, path);
+        DP_message_decref(msgs[i]);
+    }
+    DP_binary_writer_free(writer);
+}
+
+static DP_BinaryReader *open_reader(TEST_PARAMS, const char *path)
+{
+    DP_Input *input = DP_file_input_new_from_path(path);
+    FATAL(NOT_NULL_OK(input, "got input for %s", path));
+    DP_BinaryReader *reader = DP_binary_reader_new(input, 0);
+    FATAL(NOT_NULL_OK(reader, "got reader for %s", path));
+    return reader;
+}
+
+static bool handle_command(DP_CanvasState **inout_cs, DP_DrawContext *dc,
+                           DP_Message *msg)
+{
+    if (DP_message_type(msg) < DP_MESSAGE_TYPE_RANGE_START_COMMAND) {
+        return true;
+    }
+    DP_CanvasState *next = DP_canvas_state_handle(*inout_cs, dc, NULL, msg);
+    if (next) {
+        DP_canvas_state_decref(*inout_cs);
+        *inout_cs = next;
+        return true;
+    }
+    else {
+        return false;
+    }
+}
+
+static bool has_layer(DP_CanvasState *cs, int layer_id)
+{
+    return DP_layer_routes_search(DP_canvas_state_layer_routes_noinc(cs),
+                                  layer_id);
+}
+
+
+static void concat_overlapping_users(TEST_PARAMS)
+{
+    write_recording(TEST_ARGS, FIRST_PATH, 7,
+                    (DP_Message *[]){
+                        join_new(1),
+                        DP_msg_canvas_resize_new(1, 0, 64, 64, 0),
+                        layer_create_new(1, 0x101),
+                        fill_rect_new(1, 0x101),
+                        join_new(2),
+                        layer_create_new(2, 0x201),
+                        fill_rect_new(2, 0x201),
+                    });
+    // Different users that happen to have ids colliding with the first one.
+    write_recording(
+        TEST_ARGS, SECOND_PATH, 8,
+        (DP_Message *[]){
+            join_new(3),
+            join_new(1),
+            DP_msg_session_owner_new(0, set_uint8s, 2, (uint8_t[]){1, 3}),
+            layer_create_new(1, 0x101),
+            fill_rect_new(1, 0x101),
+            fill_rect_new(1, 0x201),
+            DP_msg_layer_acl_exclusive_new(1, 0x101, 3, true),
+            DP_msg_client_meta_new(1, 1, 0xffff0000u),
+        });
+
+    DP_BinaryReader *readers[] = {
+        open_reader(TEST_ARGS, FIRST_PATH),
+        open_reader(TEST_ARGS, SECOND_PATH),
+    };
+    DP_Output *output = DP_file_output_new_from_path(OUTPUT_PATH);
+    FATAL(NOT_NULL_OK(output, "got output for %s", OUTPUT_PATH));
+    DP_BinaryWriter *writer = DP_binary_writer_new(output);
+    OK(DP_recording_concat(2, readers, writer), "concatenated recordings");
+    DP_binary_writer_free(writer);
+    DP_binary_reader_free(readers[1]);
+    DP_binary_reader_free(readers[0]);
+
+    DP_BinaryReader *reader = open_reader(TEST_ARGS, OUTPUT_PATH);
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = DP_canvas_state_new();
+    // User 1 of the second recording collides and gets remapped to the
+    // lowest free id, which is 4 because 3 is taken by the second recording.
+    unsigned int expected_context_ids[] = {1, 1, 1, 1, 2, 2, 2, 1, 2,
+                                           3, 4, 0, 4, 4, 4, 4, 4};
+    int expected_count = (int)DP_ARRAY_LENGTH(expected_context_ids);
+    int count = 0;
+    DP_Message *msg;
+    while (DP_binary_reader_read_message(reader, true, &msg)
+           == DP_BINARY_READER_SUCCESS) {
+        if (count < expected_count) {
+            UINT_EQ_OK(DP_message_context_id(msg),
+                       expected_context_ids[count],
+                       "message %d has expected context id", count);
+        }
+        if (count == 7 || count == 8) {
+            INT_EQ_OK(DP_message_type(msg), DP_MSG_LEAVE,
+                      "message %d is a leave between recordings", count);
+        }
+        else if (count == 11) {
+            int users_count;
+            const uint8_t *users = DP_msg_session_owner_users(
+                DP_message_cast(msg, DP_MSG_SESSION_OWNER), &users_count);
+            if (INT_EQ_OK(users_count, 2, "session owner has two users")) {
+                UINT_EQ_OK(users[0], 4, "remapped session owner");
+                UINT_EQ_OK(users[1], 3, "unchanged session owner");
+            }
+        }
+        else if (count == 15) {
+            DP_MsgLayerAclExclusive *mlae =
+                DP_message_cast(msg, DP_MSG_LAYER_ACL_EXCLUSIVE);
+            UINT_EQ_OK(DP_msg_layer_acl_exclusive_id(mlae), 0x401,
+                       "exclusive layer id is remapped");
+            UINT_EQ_OK(DP_msg_layer_acl_exclusive_user(mlae), 3,
+                       "exclusive user keeps non-colliding id");
+        }
+        else if (count == 16) {
+            UINT_EQ_OK(DP_msg_client_meta_user(
+                           DP_message_cast(msg, DP_MSG_CLIENT_META)),
+                       4, "client meta user is remapped");
+        }
+        OK(handle_command(&cs, dc, msg), "message %d applies to the canvas",
+           count);
+        DP_message_decref(msg);
+        ++count;
+    }
+    INT_EQ_OK(count, expected_count, "got all messages");
+
+    OK(has_layer(cs, 0x101), "first recording's layer of user 1 exists");
+    OK(has_layer(cs, 0x201), "first recording's layer of user 2 exists");
+    OK(has_layer(cs, 0x401), "second recording's layer is remapped");
+    NOK(has_layer(cs, 0x301), "no layer for non-layer-creating user");
+
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+    DP_binary_reader_free(reader);
+}
+
+static void concat_nothing(TEST_PARAMS)
+{
+    DP_Output *output = DP_file_output_new_from_path(OUTPUT_PATH);
+    FATAL(NOT_NULL_OK(output, "got output for %s", OUTPUT_PATH));
+    DP_BinaryWriter *writer = DP_binary_writer_new(output);
+    NOK(DP_recording_concat(0, NULL, writer), "can't concatenate nothing");
+    DP_binary_writer_free(writer);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(concat_overlapping_users);
+    REGISTER_TEST(concat_nothing);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
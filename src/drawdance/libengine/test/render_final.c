@@ -0,0 +1,145 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include <dpcommon/common.h>
+#include <dpcommon/conversions.h>
+#include <dpcommon/input.h>
+#include <dpcommon/output.h>
+#include <dpengine/draw_context.h>
+#include <dpengine/image.h>
+#include <dpengine/pixels.h>
+#include <dpengine/player.h>
+#include <dpmsg/binary_writer.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dpmsg/protover.h>
+#include <dptest.h>
+#include <parson.h>
+
+#define RECORDING_PATH "test/tmp/render_final.dprec"
+
+
+static void set_background(size_t size, unsigned char *out, void *user)
+{
+    memcpy(out, user, size);
+}
+
+static void write_recording(TEST_PARAMS, int count, DP_Message **msgs)
+{
+    DP_Output *output = DP_file_output_new_from_path(RECORDING_PATH);
+    FATAL(NOT_NULL_OK(output, "got output for %s", RECORDING_PATH));
+    DP_BinaryWriter *writer = DP_binary_writer_new(output);
+
+    JSON_Value *header_value = json_value_init_object();
+    JSON_Object *header = json_value_get_object(header_value);
+    json_object_set_string(header, "version", DP_PROTOCOL_VERSION);
+    OK(DP_binary_writer_write_header(writer, header), "wrote header");
+    json_value_free(header_value);
+
+    for (int i = 0; i < count; ++i) {
+        OK(DP_binary_writer_write_message(writer, msgs[i]) != 0,
+           "wrote message %d", i);
+        DP_message_decref(msgs[i]);
+    }
+    DP_binary_writer_free(writer);
+}
+
+// Nobody in these recordings is an operator, so the canvas resize would be
+// filtered out if permissions were applied, leaving nothing to render.
+static void write_fixture(TEST_PARAMS, DP_Message *background_or_null)
+{
+    DP_Message *msgs[] = {
+        DP_msg_join_new(1, 0, "user", 4, NULL, 0, NULL),
+        DP_msg_canvas_resize_new(1, 0, 64, 48, 0),
+        DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "layer", 5),
+        DP_msg_fill_rect_new(1, 0x101, DP_BLEND_MODE_NORMAL, 8, 8, 16, 16,
+                             0xffcc3366u),
+        DP_msg_fill_rect_new(1, 0x101, DP_BLEND_MODE_NORMAL, 16, 16, 32, 24,
+                             0x8033cc66u),
+        background_or_null,
+    };
+    int count = (int)DP_ARRAY_LENGTH(msgs) - (background_or_null ? 0 : 1);
+    write_recording(TEST_ARGS, count, msgs);
+}
+
+static DP_Image *render_final(TEST_PARAMS, uint32_t background_color)
+{
+    DP_Input *input = DP_file_input_new_from_path(RECORDING_PATH);
+    FATAL(NOT_NULL_OK(input, "got input for %s", RECORDING_PATH));
+    DP_Player *player =
+        DP_player_new(DP_PLAYER_TYPE_BINARY, RECORDING_PATH, input, NULL);
+    FATAL(NOT_NULL_OK(player, "got player for %s", RECORDING_PATH));
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_Image *img = DP_player_render_final(player, dc, background_color);
+    DP_draw_context_free(dc);
+    DP_player_free(player);
+    return img;
+}
+
+static uint32_t hash_pixels(DP_Image *img)
+{
+    size_t size = sizeof(DP_Pixel8) * DP_int_to_size(DP_image_width(img))
+                * DP_int_to_size(DP_image_height(img));
+    const unsigned char *bytes = (const unsigned char *)DP_image_pixels(img);
+    uint32_t hash = 2166136261u;
+    for (size_t i = 0; i < size; ++i) {
+        hash = (hash ^ bytes[i]) * 16777619u;
+    }
+    return hash;
+}
+
+static void check_pixel(TEST_PARAMS, DP_Image *img, int x, int y,
+                        uint32_t expected, const char *title)
+{
+    UINT_EQ_OK(DP_image_pixel_at(img, x, y).color, expected,
+               "%s pixel at %d, %d", title, x, y);
+}
+
+
+static void render_final_permissive(TEST_PARAMS)
+{
+    write_fixture(TEST_ARGS, NULL);
+    DP_Image *img = render_final(TEST_ARGS, 0xffffffffu);
+    FATAL(NOT_NULL_OK(img, "rendered recording"));
+    INT_EQ_OK(DP_image_width(img), 64, "image has width of resized canvas");
+    INT_EQ_OK(DP_image_height(img), 48, "image has height of resized canvas");
+    check_pixel(TEST_ARGS, img, 10, 10, 0xffcc3366u, "opaque rect");
+    check_pixel(TEST_ARGS, img, 60, 4, 0xffffffffu, "background");
+    UINT_EQ_OK(hash_pixels(img), 0xb68e1e45u,
+               "rendered image has expected hash");
+    DP_image_free(img);
+}
+
+static void render_final_transparent(TEST_PARAMS)
+{
+    write_fixture(TEST_ARGS, NULL);
+    DP_Image *img = render_final(TEST_ARGS, 0);
+    FATAL(NOT_NULL_OK(img, "rendered recording"));
+    check_pixel(TEST_ARGS, img, 10, 10, 0xffcc3366u, "opaque rect");
+    check_pixel(TEST_ARGS, img, 60, 4, 0, "transparent");
+    DP_image_free(img);
+}
+
+static void render_final_recorded_background(TEST_PARAMS)
+{
+    unsigned char background[] = {0xff, 0xee, 0xdd, 0xcc};
+    write_fixture(TEST_ARGS,
+                  DP_msg_canvas_background_new(1, set_background,
+                                               sizeof(background), background));
+    DP_Image *img = render_final(TEST_ARGS, 0xffffffffu);
+    FATAL(NOT_NULL_OK(img, "rendered recording"));
+    check_pixel(TEST_ARGS, img, 10, 10, 0xffcc3366u, "opaque rect");
+    check_pixel(TEST_ARGS, img, 60, 4, 0xffeeddccu, "recorded background");
+    DP_image_free(img);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(render_final_permissive);
+    REGISTER_TEST(render_final_transparent);
+    REGISTER_TEST(render_final_recorded_background);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,277 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include <dpcommon/common.h>
+#include <dpengine/annotation_list.h>
+#include <dpengine/canvas_state.h>
+#include <dpengine/draw_context.h>
+#include <dpengine/image.h>
+#include <dpengine/layer_routes.h>
+#include <dpengine/snapshots.h>
+#include <dpmsg/acl.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+
+
+typedef struct DP_Session {
+    DP_AclState *acls;
+    DP_CanvasState *cs;
+    DP_DrawContext *dc;
+} DP_Session;
+
+typedef struct DP_CollectedMessages {
+    int count;
+    DP_Message *msgs[64];
+} DP_CollectedMessages;
+
+static void session_init(DP_Session *s, DP_DrawContext *dc)
+{
+    s->acls = DP_acl_state_new();
+    s->cs = DP_canvas_state_new();
+    s->dc = dc;
+}
+
+static void session_dispose(DP_Session *s)
+{
+    DP_canvas_state_decref(s->cs);
+    DP_acl_state_free(s->acls);
+}
+
+static void session_handle(DP_Session *s, DP_Message *msg, bool override)
+{
+    uint8_t result = DP_acl_state_handle(s->acls, msg, override);
+    if (!(result & DP_ACL_STATE_FILTERED_BIT)
+        && DP_message_type(msg) >= DP_MESSAGE_TYPE_RANGE_START_COMMAND) {
+        DP_CanvasState *next = DP_canvas_state_handle(s->cs, s->dc, NULL, msg);
+        if (next) {
+            DP_canvas_state_decref(s->cs);
+            s->cs = next;
+        }
+        else {
+            DP_warn("Handle: %s", DP_error());
+        }
+    }
+    DP_message_decref(msg);
+}
+
+static void set_uint8s(int count, uint8_t *out, void *user)
+{
+    memcpy(out, user, DP_int_to_size(count));
+}
+
+static DP_Message *layer_create_new(uint16_t layer_id, uint16_t target_id,
+                                    uint8_t flags)
+{
+    return DP_msg_layer_tree_create_new(1, layer_id, 0, target_id, 0, flags,
+                                        "layer", 5);
+}
+
+static DP_Message *fill_rect_new(uint16_t layer_id, uint32_t x, uint32_t y,
+                                 uint32_t color)
+{
+    return DP_msg_fill_rect_new(1, layer_id, DP_BLEND_MODE_NORMAL, x, y, 20,
+                                20, color);
+}
+
+static void build_session(DP_Session *s)
+{
+    session_handle(
+        s, DP_msg_session_owner_new(0, set_uint8s, 1, (uint8_t[]){1}), true);
+    uint8_t tiers[DP_FEATURE_COUNT];
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        tiers[i] = DP_ACCESS_TIER_GUEST;
+    }
+    tiers[DP_FEATURE_PUT_IMAGE] = DP_ACCESS_TIER_OPERATOR;
+    session_handle(s,
+                   DP_msg_feature_access_levels_new(
+                       1, set_uint8s, DP_FEATURE_COUNT, tiers),
+                   false);
+    session_handle(s, DP_msg_canvas_resize_new(1, 0, 100, 80, 0), false);
+    session_handle(s, layer_create_new(0x101, 0, 0), false);
+    session_handle(s, fill_rect_new(0x101, 10, 10, 0xffff0000u), false);
+    session_handle(
+        s, layer_create_new(0x102, 0, DP_MSG_LAYER_TREE_CREATE_FLAGS_GROUP),
+        false);
+    session_handle(
+        s, layer_create_new(0x103, 0x102, DP_MSG_LAYER_TREE_CREATE_FLAGS_INTO),
+        false);
+    session_handle(s, fill_rect_new(0x103, 50, 40, 0xff0000ffu), false);
+    session_handle(s,
+                   DP_msg_layer_acl_new(1, 0x101, DP_ACCESS_TIER_TRUSTED, NULL,
+                                        0, NULL),
+                   false);
+    // Only applies to layers created from here on out, the existing ones
+    // must stay unlocked when replaying the reset image.
+    session_handle(s,
+                   DP_msg_layer_acl_new(1, DP_ACL_DEFAULT_LAYER_ID,
+                                        DP_ACL_ALL_LOCKED_BIT, NULL, 0, NULL),
+                   false);
+    session_handle(s, DP_msg_annotation_create_new(1, 0x101, 5, 5, 40, 20),
+                   false);
+    session_handle(s,
+                   DP_msg_annotation_edit_new(1, 0x101, 0, 0, 0, "hello", 5),
+                   false);
+}
+
+static bool collect_message(void *user, DP_Message *msg)
+{
+    DP_CollectedMessages *cm = user;
+    if (cm->count < (int)DP_ARRAY_LENGTH(cm->msgs)) {
+        cm->msgs[cm->count++] = msg;
+        return true;
+    }
+    else {
+        DP_message_decref(msg);
+        return false;
+    }
+}
+
+static DP_Image *flatten(DP_CanvasState *cs)
+{
+    return DP_canvas_state_to_flat_image(cs, DP_FLAT_IMAGE_RENDER_FLAGS, NULL,
+                                         NULL);
+}
+
+static bool images_equal(DP_CanvasState *a, DP_CanvasState *b)
+{
+    DP_Image *img_a = flatten(a);
+    DP_Image *img_b = flatten(b);
+    bool equal = false;
+    if (img_a && img_b) {
+        int width = DP_image_width(img_a);
+        int height = DP_image_height(img_a);
+        equal = width == DP_image_width(img_b)
+             && height == DP_image_height(img_b)
+             && memcmp(DP_image_pixels(img_a), DP_image_pixels(img_b),
+                       sizeof(DP_Pixel8) * DP_int_to_size(width * height))
+                    == 0;
+    }
+    DP_image_free(img_b);
+    DP_image_free(img_a);
+    return equal;
+}
+
+
+static void reset_image_builder_replay(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_Session original;
+    session_init(&original, dc);
+    build_session(&original);
+
+    DP_ResetImageBuilder *rib =
+        DP_reset_image_builder_new(original.cs, original.acls, 0,
+                                   DP_ACL_STATE_RESET_IMAGE_RECORDING_FLAGS);
+    DP_CollectedMessages cm = {0, {NULL}};
+    int steps = 0;
+    while (!DP_reset_image_builder_done(rib)) {
+        OK(DP_reset_image_builder_step(rib, collect_message, &cm),
+           "step %d succeeded", steps);
+        ++steps;
+    }
+    // Session ACLs, canvas, two top-level layers, annotations, layer ACLs.
+    INT_EQ_OK(steps, 6, "reset image built in pieces");
+    DP_reset_image_builder_free(rib);
+
+    FATAL(OK(cm.count > 2, "got reset image messages"));
+    INT_EQ_OK(DP_message_type(cm.msgs[0]), DP_MSG_FEATURE_ACCESS_LEVELS,
+              "feature tiers come first");
+    INT_EQ_OK(DP_message_type(cm.msgs[cm.count - 1]), DP_MSG_LAYER_ACL,
+              "layer ACLs come last");
+    int first_command = -1;
+    int last_layer_create = -1;
+    int first_layer_acl = -1;
+    for (int i = 0; i < cm.count; ++i) {
+        DP_MessageType type = DP_message_type(cm.msgs[i]);
+        if (first_command == -1
+            && type >= DP_MESSAGE_TYPE_RANGE_START_COMMAND) {
+            first_command = i;
+        }
+        if (type == DP_MSG_LAYER_TREE_CREATE) {
+            last_layer_create = i;
+        }
+        if (first_layer_acl == -1 && type == DP_MSG_LAYER_ACL) {
+            first_layer_acl = i;
+        }
+    }
+    OK(first_command > 0, "session ACLs come before commands");
+    OK(first_layer_acl > last_layer_create,
+       "layer ACLs come after creating the layers");
+
+    DP_Session replay;
+    session_init(&replay, dc);
+    for (int i = 0; i < cm.count; ++i) {
+        session_handle(&replay, cm.msgs[i], true);
+    }
+
+    OK(DP_acl_state_is_op(replay.acls, 1), "operator is restored");
+    INT_EQ_OK(DP_acl_state_feature_tiers(replay.acls)
+                  .tiers[DP_FEATURE_PUT_IMAGE],
+              DP_ACCESS_TIER_OPERATOR, "feature tiers are restored");
+    const DP_LayerAcl *l = DP_acl_state_default_layer_acl(replay.acls);
+    OK(l && l->locked, "default layer ACL is restored");
+    int layer_ids[] = {0x101, 0x102, 0x103};
+    for (int i = 0; i < (int)DP_ARRAY_LENGTH(layer_ids); ++i) {
+        for (uint8_t user_id = 1; user_id <= 2; ++user_id) {
+            OK(DP_acl_state_layer_locked_for(replay.acls, user_id, layer_ids[i])
+                   == DP_acl_state_layer_locked_for(original.acls, user_id,
+                                                    layer_ids[i]),
+               "layer %x lock for user %d is restored", layer_ids[i], user_id);
+        }
+    }
+    NOK(DP_acl_state_layer_locked_for(replay.acls, 2, 0x103),
+        "default layer ACL isn't applied to existing layers");
+
+    INT_EQ_OK(DP_canvas_state_width(replay.cs), 100, "canvas width restored");
+    INT_EQ_OK(DP_canvas_state_height(replay.cs), 80, "canvas height restored");
+    OK(DP_layer_routes_search(DP_canvas_state_layer_routes_noinc(replay.cs),
+                              0x103),
+       "nested layer is restored");
+    INT_EQ_OK(DP_annotation_list_count(
+                  DP_canvas_state_annotations_noinc(replay.cs)),
+              1, "annotation is restored");
+    OK(images_equal(original.cs, replay.cs), "canvas pixels are restored");
+
+    session_dispose(&replay);
+    session_dispose(&original);
+    DP_draw_context_free(dc);
+}
+
+static bool fail_push(void *user, DP_Message *msg)
+{
+    int *count = user;
+    ++*count;
+    DP_message_decref(msg);
+    return false;
+}
+
+static void reset_image_builder_push_failure(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_Session original;
+    session_init(&original, dc);
+    build_session(&original);
+
+    DP_ResetImageBuilder *rib =
+        DP_reset_image_builder_new(original.cs, original.acls, 0, 0);
+    int count = 0;
+    NOK(DP_reset_image_builder_step(rib, fail_push, &count),
+        "step fails when pushing fails");
+    INT_EQ_OK(count, 1, "no more messages pushed after failure");
+    OK(DP_reset_image_builder_done(rib), "builder is done after failure");
+    DP_reset_image_builder_free(rib);
+
+    session_dispose(&original);
+    DP_draw_context_free(dc);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(reset_image_builder_replay);
+    REGISTER_TEST(reset_image_builder_push_failure);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,154 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "canvas_state_common.h"
+#include <dpcommon/common.h>
+#include <dpcommon/output.h>
+#include <dpengine/canvas_state.h>
+#include <dpengine/draw_context.h>
+#include <dpengine/snapshots.h>
+#include <dpengine/tile.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+
+
+static DP_CanvasState *canvas_new(DP_DrawContext *dc)
+{
+    DP_CanvasState *cs = DP_canvas_state_new();
+    cs = handle(cs, dc, DP_msg_canvas_resize_new(1, 0, 256, 256, 0));
+    cs = handle(cs, dc,
+                DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "layer", 5));
+    return cs;
+}
+
+static DP_CanvasState *fill_rect(DP_CanvasState *cs, DP_DrawContext *dc,
+                                 uint32_t x, uint32_t y, uint32_t w, uint32_t h)
+{
+    return handle(cs, dc,
+                  DP_msg_fill_rect_new(1, 0x101, DP_BLEND_MODE_NORMAL, x, y, w,
+                                       h, 0xff0000ffu));
+}
+
+
+typedef struct DP_DiffMessages {
+    int count;
+    DP_Message *msgs[16];
+} DP_DiffMessages;
+
+static void push_diff_message(void *user, DP_Message *msg)
+{
+    DP_DiffMessages *dm = user;
+    if (dm->count < (int)DP_ARRAY_LENGTH(dm->msgs)) {
+        dm->msgs[dm->count++] = msg;
+    }
+    else {
+        DP_message_decref(msg);
+        ++dm->count;
+    }
+}
+
+static void dispose_diff_messages(DP_DiffMessages *dm)
+{
+    int count = DP_min_int(dm->count, (int)DP_ARRAY_LENGTH(dm->msgs));
+    for (int i = 0; i < count; ++i) {
+        DP_message_decref(dm->msgs[i]);
+    }
+}
+
+static DP_CanvasState *apply_diff_messages(DP_CanvasState *cs,
+                                           DP_DrawContext *dc,
+                                           DP_DiffMessages *dm)
+{
+    for (int i = 0; i < dm->count; ++i) {
+        cs = handle(cs, dc, DP_message_incref(dm->msgs[i]));
+    }
+    return cs;
+}
+
+
+static void diff_identical(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = fill_rect(canvas_new(dc), dc, 10, 10, 100, 100);
+    DP_CanvasState *prev = fill_rect(canvas_new(dc), dc, 10, 10, 100, 100);
+
+    DP_DiffMessages dm = {0, {0}};
+    DP_reset_image_diff_build(cs, prev, 0, push_diff_message, &dm);
+    INT_EQ_OK(dm.count, 0, "identical canvases produce no messages");
+
+    dispose_diff_messages(&dm);
+    DP_canvas_state_decref(prev);
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void diff_single_tile(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = fill_rect(canvas_new(dc), dc, 130, 70, 10, 10);
+    DP_CanvasState *prev = canvas_new(dc);
+
+    DP_DiffMessages dm = {0, {0}};
+    DP_reset_image_diff_build(cs, prev, 0, push_diff_message, &dm);
+    if (INT_EQ_OK(dm.count, 1, "one differing tile produces one message")) {
+        DP_Message *msg = dm.msgs[0];
+        INT_EQ_OK(DP_message_type(msg), DP_MSG_PUT_TILE, "message is put tile");
+        DP_MsgPutTile *mpt = DP_message_internal(msg);
+        INT_EQ_OK(DP_msg_put_tile_layer(mpt), 0x101, "put tile layer");
+        DP_TileCoords expected = DP_tile_coords(130, 70);
+        INT_EQ_OK(DP_msg_put_tile_col(mpt), expected.x, "put tile column");
+        INT_EQ_OK(DP_msg_put_tile_row(mpt), expected.y, "put tile row");
+    }
+
+    prev = apply_diff_messages(prev, dc, &dm);
+    DP_DiffMessages after = {0, {0}};
+    DP_reset_image_diff_build(cs, prev, 0, push_diff_message, &after);
+    INT_EQ_OK(after.count, 0, "no differences after applying diff");
+
+    dispose_diff_messages(&after);
+    dispose_diff_messages(&dm);
+    DP_canvas_state_decref(prev);
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+static void diff_cleared_tile_and_resize(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasState *cs = canvas_new(dc);
+    cs = handle(cs, dc, DP_msg_canvas_resize_new(1, 0, 64, 0, 0));
+    DP_CanvasState *prev = fill_rect(canvas_new(dc), dc, 0, 0, 10, 10);
+
+    DP_DiffMessages dm = {0, {0}};
+    DP_reset_image_diff_build(cs, prev, 0, push_diff_message, &dm);
+    if (INT_EQ_OK(dm.count, 2, "resize and clearing produce two messages")) {
+        INT_EQ_OK(DP_message_type(dm.msgs[0]), DP_MSG_CANVAS_RESIZE,
+                  "first message is canvas resize");
+        INT_EQ_OK(DP_message_type(dm.msgs[1]), DP_MSG_PUT_TILE,
+                  "second message is put tile");
+    }
+
+    prev = apply_diff_messages(prev, dc, &dm);
+    INT_EQ_OK(DP_canvas_state_width(prev), 320, "width matches after diff");
+    DP_DiffMessages after = {0, {0}};
+    DP_reset_image_diff_build(cs, prev, 0, push_diff_message, &after);
+    INT_EQ_OK(after.count, 0, "no differences after applying diff");
+
+    dispose_diff_messages(&after);
+    dispose_diff_messages(&dm);
+    DP_canvas_state_decref(prev);
+    DP_canvas_state_decref(cs);
+    DP_draw_context_free(dc);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(diff_identical);
+    REGISTER_TEST(diff_single_tile);
+    REGISTER_TEST(diff_cleared_tile_and_resize);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,99 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include <dpcommon/common.h>
+#include <dpcommon/geom.h>
+#include <dpengine/tile.h>
+#include <dptest.h>
+
+
+static void check_coords(TEST_PARAMS, int x, int y, int expected_x,
+                         int expected_y)
+{
+    DP_TileCoords tc = DP_tile_coords(x, y);
+    INT_EQ_OK(tc.x, expected_x, "tile x of (%d, %d)", x, y);
+    INT_EQ_OK(tc.y, expected_y, "tile y of (%d, %d)", x, y);
+}
+
+static void tile_coords_positive(TEST_PARAMS)
+{
+    check_coords(TEST_ARGS, 0, 0, 0, 0);
+    check_coords(TEST_ARGS, 1, 2, 0, 0);
+    check_coords(TEST_ARGS, DP_TILE_SIZE + 3, 3 * DP_TILE_SIZE + 8, 1, 3);
+    check_coords(TEST_ARGS, 15 * DP_TILE_SIZE + 8, 5, 15, 0);
+}
+
+static void tile_coords_negative(TEST_PARAMS)
+{
+    check_coords(TEST_ARGS, -1, -1, -1, -1);
+    check_coords(TEST_ARGS, -2, 3, -1, 0);
+    check_coords(TEST_ARGS, -DP_TILE_SIZE - 3, -3 * DP_TILE_SIZE - 8, -2, -4);
+}
+
+static void tile_coords_boundaries(TEST_PARAMS)
+{
+    check_coords(TEST_ARGS, DP_TILE_SIZE - 1, DP_TILE_SIZE, 0, 1);
+    check_coords(TEST_ARGS, -DP_TILE_SIZE, -DP_TILE_SIZE - 1, -1, -2);
+    check_coords(TEST_ARGS, 2 * DP_TILE_SIZE, 2 * DP_TILE_SIZE - 1, 2, 1);
+}
+
+
+static void tile_rect_contains_coords(TEST_PARAMS)
+{
+    DP_Rect rect = DP_tile_rect(2, -1);
+    INT_EQ_OK(DP_rect_left(rect), 2 * DP_TILE_SIZE, "rect left");
+    INT_EQ_OK(DP_rect_top(rect), -DP_TILE_SIZE, "rect top");
+    INT_EQ_OK(DP_rect_width(rect), DP_TILE_SIZE, "rect width");
+    INT_EQ_OK(DP_rect_height(rect), DP_TILE_SIZE, "rect height");
+
+    for (int tile_y = -2; tile_y <= 2; ++tile_y) {
+        for (int tile_x = -2; tile_x <= 2; ++tile_x) {
+            DP_Rect r = DP_tile_rect(tile_x, tile_y);
+            DP_TileCoords first = DP_tile_coords(r.x1, r.y1);
+            DP_TileCoords last = DP_tile_coords(r.x2, r.y2);
+            OK(first.x == tile_x && first.y == tile_y && last.x == tile_x
+                   && last.y == tile_y,
+               "tile rect (%d, %d) maps back to itself", tile_x, tile_y);
+        }
+    }
+}
+
+static void tile_rounding_consistent(TEST_PARAMS)
+{
+    bool counts_ok = true;
+    bool rounding_ok = true;
+    for (int i = 1; i <= 3 * DP_TILE_SIZE; ++i) {
+        int count = DP_tile_count_round(i);
+        DP_TileCoords last = DP_tile_coords(i - 1, i - 1);
+        if (count != last.x + 1 || count != DP_tile_size_round_up(i)) {
+            counts_ok = false;
+        }
+        int down = DP_tile_size_round_down(i);
+        if (down % DP_TILE_SIZE != 0 || down > i || i - down >= DP_TILE_SIZE
+            || DP_tile_index_floor(i) * DP_TILE_SIZE != down) {
+            rounding_ok = false;
+        }
+    }
+    OK(counts_ok, "tile counts cover the last pixel");
+    OK(rounding_ok, "rounding down lands on the tile's first pixel");
+
+    DP_TileCounts tc = DP_tile_counts_round(DP_TILE_SIZE * 2 + 1, 1);
+    INT_EQ_OK(tc.x, 3, "tile count x of one pixel past two tiles");
+    INT_EQ_OK(tc.y, 1, "tile count y of a single pixel");
+    INT_EQ_OK(DP_tile_total_round(DP_TILE_SIZE * 2 + 1, DP_TILE_SIZE), 3,
+              "tile total");
+    INT_EQ_OK(DP_TILE_LENGTH, DP_TILE_SIZE * DP_TILE_SIZE, "tile length");
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(tile_coords_positive);
+    REGISTER_TEST(tile_coords_negative);
+    REGISTER_TEST(tile_coords_boundaries);
+    REGISTER_TEST(tile_rect_contains_coords);
+    REGISTER_TEST(tile_rounding_consistent);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,191 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include <dpcommon/common.h>
+#include <dpengine/canvas_history.h>
+#include <dpengine/canvas_state.h>
+#include <dpengine/draw_context.h>
+#include <dpengine/image.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+
+
+#define STROKE_COUNT 5
+
+// Who makes each stroke, interleaved between the two users.
+static const unsigned int stroke_users[STROKE_COUNT] = {1, 2, 1, 2, 1};
+
+
+static bool handle(DP_CanvasHistory *ch, DP_DrawContext *dc, DP_Message *msg)
+{
+    bool ok = DP_canvas_history_handle(ch, dc, msg);
+    DP_message_decref(msg);
+    return ok;
+}
+
+// Each stroke is an undo point followed by a rectangle in its own column.
+static void stroke(TEST_PARAMS, DP_CanvasHistory *ch, DP_DrawContext *dc,
+                   int index)
+{
+    unsigned int context_id = stroke_users[index];
+    OK(handle(ch, dc, DP_msg_undo_point_new(context_id)),
+       "undo point for stroke %d", index);
+    OK(handle(ch, dc,
+              DP_msg_fill_rect_new(context_id, 0x101, DP_BLEND_MODE_NORMAL,
+                                   DP_int_to_uint32(index * 10), 0, 10, 10,
+                                   0xff000000u)),
+       "stroke %d", index);
+}
+
+static void check_strokes(TEST_PARAMS, DP_CanvasHistory *ch,
+                          const bool *expected, const char *title)
+{
+    DP_CanvasState *cs = DP_canvas_history_get(ch);
+    DP_Image *img = DP_canvas_state_to_flat_image(
+        cs, DP_FLAT_IMAGE_RENDER_FLAGS, NULL, NULL);
+    FATAL(NOT_NULL_OK(img, "%s: got flat image", title));
+    for (int i = 0; i < STROKE_COUNT; ++i) {
+        bool present = DP_image_pixel_at(img, i * 10 + 5, 5).a != 0;
+        OK(present == expected[i], "%s: stroke %d by user %u is %s", title, i,
+           stroke_users[i], expected[i] ? "present" : "undone");
+    }
+    DP_image_free(img);
+    DP_canvas_state_decref(cs);
+}
+
+static int history_count(DP_CanvasHistory *ch)
+{
+    DP_CanvasHistorySnapshot *chs = DP_canvas_history_snapshot_new(ch);
+    int count = DP_canvas_history_snapshot_history_count(chs);
+    DP_canvas_history_snapshot_decref(chs);
+    return count;
+}
+
+static DP_CanvasHistory *canvas_history_new(TEST_PARAMS, DP_DrawContext *dc)
+{
+    DP_CanvasHistory *ch = DP_canvas_history_new(NULL, NULL, false, NULL);
+    OK(handle(ch, dc, DP_msg_canvas_resize_new(1, 0, 64, 64, 0)),
+       "resize canvas");
+    OK(handle(ch, dc,
+              DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "layer", 5)),
+       "create layer");
+    return ch;
+}
+
+
+static void undo_other_user(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasHistory *ch = canvas_history_new(TEST_ARGS, dc);
+    for (int i = 0; i < STROKE_COUNT; ++i) {
+        stroke(TEST_ARGS, ch, dc, i);
+    }
+    check_strokes(TEST_ARGS, ch, (bool[]){true, true, true, true, true},
+                  "before undo");
+
+    // User 1 undoes the last action of user 2, which is stroke 3. Everything
+    // that user 1 did afterwards sticks around.
+    OK(handle(ch, dc, DP_msg_undo_new(1, 2, false)), "undo user 2");
+    check_strokes(TEST_ARGS, ch, (bool[]){true, true, true, false, true},
+                  "after undoing user 2");
+
+    OK(handle(ch, dc, DP_msg_undo_new(1, 2, false)), "undo user 2 again");
+    check_strokes(TEST_ARGS, ch, (bool[]){true, false, true, false, true},
+                  "after undoing user 2 twice");
+
+    OK(handle(ch, dc, DP_msg_undo_new(1, 2, true)), "redo user 2");
+    check_strokes(TEST_ARGS, ch, (bool[]){true, true, true, false, true},
+                  "after redoing user 2");
+
+    OK(handle(ch, dc, DP_msg_undo_new(1, 0, false)), "user 1 undoes own");
+    check_strokes(TEST_ARGS, ch, (bool[]){true, true, true, false, false},
+                  "after user 1 undoes own stroke");
+
+    DP_draw_context_free(dc);
+    DP_canvas_history_free(ch);
+}
+
+static void undo_redundant_points(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasHistory *ch = canvas_history_new(TEST_ARGS, dc);
+    DP_canvas_history_compact_undo_points_set(ch, true);
+    int count = history_count(ch);
+
+    for (int i = 0; i < 3; ++i) {
+        OK(handle(ch, dc, DP_msg_undo_point_new(1)), "undo point %d", i);
+    }
+    INT_EQ_OK(history_count(ch), count + 1, "repeated undo points collapse");
+
+    OK(handle(ch, dc, DP_msg_undo_point_new(2)), "undo point by user 2");
+    INT_EQ_OK(history_count(ch), count + 2,
+              "other user's undo point is kept");
+    OK(handle(ch, dc, DP_msg_undo_point_new(1)), "undo point by user 1");
+    INT_EQ_OK(history_count(ch), count + 2,
+              "other user's undo point in between doesn't prevent collapse");
+
+    stroke(TEST_ARGS, ch, dc, 0);
+    INT_EQ_OK(history_count(ch), count + 3,
+              "undo point before the first stroke is collapsed");
+    stroke(TEST_ARGS, ch, dc, 2);
+    INT_EQ_OK(history_count(ch), count + 5,
+              "undo point after a stroke is kept");
+
+    // Each undo takes back a stroke, none of them hit an empty step.
+    OK(handle(ch, dc, DP_msg_undo_new(1, 0, false)), "first undo");
+    check_strokes(TEST_ARGS, ch, (bool[]){true, false, false, false, false},
+                  "after first undo");
+    OK(handle(ch, dc, DP_msg_undo_new(1, 0, false)), "second undo");
+    check_strokes(TEST_ARGS, ch, (bool[]){false, false, false, false, false},
+                  "after second undo");
+
+    // An undo point after an undo must go through, since it's what makes the
+    // undone actions unredoable.
+    OK(handle(ch, dc, DP_msg_undo_point_new(1)), "undo point after undo");
+    NOK(handle(ch, dc, DP_msg_undo_new(1, 0, true)), "can't redo");
+    check_strokes(TEST_ARGS, ch, (bool[]){false, false, false, false, false},
+                  "after redo attempt");
+
+    DP_canvas_history_free(ch);
+    DP_draw_context_free(dc);
+}
+
+// Without compaction, which older clients and recordings don't do, every undo
+// point is its own undo step, even if there's nothing in it.
+static void undo_uncompacted_points(TEST_PARAMS)
+{
+    DP_DrawContext *dc = DP_draw_context_new();
+    DP_CanvasHistory *ch = canvas_history_new(TEST_ARGS, dc);
+    NOK(DP_canvas_history_compact_undo_points(ch), "no compaction by default");
+    int count = history_count(ch);
+
+    stroke(TEST_ARGS, ch, dc, 0);
+    OK(handle(ch, dc, DP_msg_undo_point_new(1)), "first empty undo point");
+    OK(handle(ch, dc, DP_msg_undo_point_new(1)), "second empty undo point");
+    INT_EQ_OK(history_count(ch), count + 4, "all undo points are kept");
+
+    OK(handle(ch, dc, DP_msg_undo_new(1, 0, false)), "first undo");
+    check_strokes(TEST_ARGS, ch, (bool[]){true, false, false, false, false},
+                  "first undo only takes back an empty step");
+    OK(handle(ch, dc, DP_msg_undo_new(1, 0, false)), "second undo");
+    check_strokes(TEST_ARGS, ch, (bool[]){true, false, false, false, false},
+                  "second undo only takes back an empty step");
+    OK(handle(ch, dc, DP_msg_undo_new(1, 0, false)), "third undo");
+    check_strokes(TEST_ARGS, ch, (bool[]){false, false, false, false, false},
+                  "third undo takes back the stroke");
+
+    DP_canvas_history_free(ch);
+    DP_draw_context_free(dc);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(undo_other_user);
+    REGISTER_TEST(undo_redundant_points);
+    REGISTER_TEST(undo_uncompacted_points);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
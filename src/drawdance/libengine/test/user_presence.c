@@ -0,0 +1,81 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include <dpcommon/common.h>
+#include <dpengine/user_presence.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+
+
+static bool handle(DP_UserPresence *up, DP_Message *msg)
+{
+    bool changed = DP_user_presence_handle(up, msg);
+    DP_message_decref(msg);
+    return changed;
+}
+
+static void check_color(TEST_PARAMS, DP_UserPresence *up,
+                        unsigned int context_id, uint32_t expected)
+{
+    uint32_t color = 0;
+    OK(DP_user_presence_color(up, context_id, &color), "user %u has a color",
+       context_id);
+    UINT_EQ_OK(color, expected, "user %u has color 0x%08x", context_id,
+               expected);
+}
+
+
+static void user_presence_set_color(TEST_PARAMS)
+{
+    DP_UserPresence up;
+    DP_user_presence_init(&up);
+    NOK(DP_user_presence_color(&up, 1, NULL), "no color before any is set");
+
+    OK(handle(&up, DP_msg_client_meta_new(1, 1, 0xffff0000u)),
+       "setting a color is a change");
+    check_color(TEST_ARGS, &up, 1, 0xffff0000u);
+    OK(handle(&up, DP_msg_client_meta_new(2, 2, 0x800000ffu)),
+       "setting another user's color is a change");
+    check_color(TEST_ARGS, &up, 2, 0x800000ffu);
+    NOK(handle(&up, DP_msg_client_meta_new(1, 1, 0xffff0000u)),
+        "setting the same color again changes nothing");
+    OK(handle(&up, DP_msg_client_meta_new(1, 1, 0xff00ff00u)),
+       "changing a color is a change");
+    check_color(TEST_ARGS, &up, 1, 0xff00ff00u);
+
+    OK(handle(&up, DP_msg_client_meta_new(1, 1, 0x00ffffffu)),
+       "transparent color unsets it");
+    NOK(DP_user_presence_color(&up, 1, NULL), "unset color is gone");
+    check_color(TEST_ARGS, &up, 2, 0x800000ffu);
+    NOK(handle(&up, DP_msg_move_pointer_new(1, 0, 0)),
+        "unrelated message changes nothing");
+}
+
+static void user_presence_prune(TEST_PARAMS)
+{
+    DP_UserPresence up;
+    DP_user_presence_init(&up);
+    handle(&up, DP_msg_client_meta_new(1, 1, 0xffff0000u));
+    handle(&up, DP_msg_client_meta_new(2, 2, 0xff00ff00u));
+
+    DP_Message *leave = DP_msg_leave_new(1);
+    OK(DP_user_presence_handle(&up, leave), "leave prunes the user");
+    NOK(DP_user_presence_handle(&up, leave), "leaving again changes nothing");
+    DP_message_decref(leave);
+    NOK(DP_user_presence_color(&up, 1, NULL), "pruned user has no color");
+    check_color(TEST_ARGS, &up, 2, 0xff00ff00u);
+
+    OK(handle(&up, DP_msg_client_meta_new(1, 1, 0xff0000ffu)),
+       "rejoined user can set a color again");
+    check_color(TEST_ARGS, &up, 1, 0xff0000ffu);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(user_presence_set_color);
+    REGISTER_TEST(user_presence_prune);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
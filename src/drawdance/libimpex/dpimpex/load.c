@@ -1245,37 +1245,13 @@ static DP_CanvasState *load_flat_image(DP_DrawContext *dc, DP_Input *input,
         return NULL;
     }
 
-    DP_TransientCanvasState *tcs = DP_transient_canvas_state_new_init();
-
-    int width = DP_image_width(img);
-    int height = DP_image_height(img);
-    DP_transient_canvas_state_width_set(tcs, width);
-    DP_transient_canvas_state_height_set(tcs, height);
-
-    DP_TransientLayerContent *tlc =
-        DP_transient_layer_content_new_init(width, height, NULL);
-    DP_transient_layer_content_put_image(tlc, 1, DP_BLEND_MODE_REPLACE, 0, 0,
-                                         img);
-    DP_image_free(img);
-
-    DP_TransientLayerList *tll =
-        DP_transient_canvas_state_transient_layers(tcs, 1);
-    DP_transient_layer_list_insert_transient_content_noinc(tll, tlc, 0);
-
-    DP_TransientLayerProps *tlp =
-        DP_transient_layer_props_new_init(START_ID, false);
     const char *title =
         flat_image_layer_title ? flat_image_layer_title : "Layer 1";
-    DP_transient_layer_props_title_set(tlp, title, strlen(title));
-
-    DP_TransientLayerPropsList *tlpl =
-        DP_transient_canvas_state_transient_layer_props(tcs, 1);
-    DP_transient_layer_props_list_insert_transient_noinc(tlpl, tlp, 0);
-
-    DP_transient_canvas_state_layer_routes_reindex(tcs, dc);
-
+    DP_CanvasState *cs = DP_canvas_state_new_from_image(dc, img, 1, START_ID,
+                                                        title, strlen(title));
+    DP_image_free(img);
     assign_load_result(out_result, DP_LOAD_RESULT_SUCCESS);
-    return DP_transient_canvas_state_persist(tcs);
+    return cs;
 }
 
 
@@ -42,10 +42,12 @@ static long long guess_message_msecs(DP_Message *msg, DP_MessageType type,
     case DP_MSG_LAYER_ATTRIBUTES:
     case DP_MSG_LAYER_RETITLE:
     case DP_MSG_LAYER_ORDER:
+    case DP_MSG_LAYER_ORDER_MOVE:
     case DP_MSG_LAYER_DELETE:
     case DP_MSG_LAYER_VISIBILITY:
     case DP_MSG_ANNOTATION_RESHAPE:
     case DP_MSG_ANNOTATION_EDIT:
+    case DP_MSG_ANNOTATION_TEXT_EDIT:
         return 20;
     case DP_MSG_CANVAS_RESIZE:
         return 60;
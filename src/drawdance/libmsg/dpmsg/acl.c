@@ -26,12 +26,17 @@
 #include <dpcommon/common.h>
 #include <dpcommon/conversions.h>
 #include <dpcommon/output.h>
+#include <parson.h>
 #include <uthash_inc.h>
+#include <time.h>
 
 
 typedef struct DP_LayerAclEntry {
     int layer_id;
     DP_LayerAcl layer_acl;
+    // Sequence number from which the ACL applies, see
+    // DP_acl_state_handle_sequenced. Zero means it applies to everything.
+    long long effective_from;
     UT_hash_handle hh;
 } DP_LayerAclEntry;
 
@@ -40,12 +45,91 @@ typedef struct DP_AnnotationAclEntry {
     UT_hash_handle hh;
 } DP_AnnotationAclEntry;
 
-typedef struct DP_AclState {
+// Layers that currently exist, along with their parent group, so that deleting
+// a group also gets rid of the layers inside of it.
+typedef struct DP_LiveLayerEntry {
+    int layer_id;
+    int parent_id;
+    bool doomed;
+    UT_hash_handle hh;
+} DP_LiveLayerEntry;
+
+// Users that drew on a layer, only tracked if enabled.
+typedef struct DP_LayerContributorEntry {
+    int layer_id;
+    DP_UserBits users;
+    UT_hash_handle hh;
+} DP_LayerContributorEntry;
+
+// Owners of layers that differ from the user id in the layer id, because the
+// layer was orphaned or transferred. An owner id of 0 means it's orphaned.
+typedef struct DP_LayerOwnerEntry {
+    int layer_id;
+    uint8_t owner_id;
+    UT_hash_handle hh;
+} DP_LayerOwnerEntry;
+
+typedef struct DP_LayerAclVersionEntry {
+    int layer_id;
+    unsigned int version;
+    UT_hash_handle hh;
+} DP_LayerAclVersionEntry;
+
+typedef struct DP_AclAuditLog {
+    DP_AclAuditEntry *entries;
+    int capacity;
+    int start;
+    int count;
+} DP_AclAuditLog;
+
+// Settings and bookkeeping that survive DP_acl_state_reset. Everything else in
+// the ACL state starts over from scratch.
+typedef struct DP_AclPersistentState {
     uint8_t local_user_id;
+    DP_AclOperatorPolicy operator_policy;
+    unsigned int join_counter;
+    // Sequence number of when each user joined, zero if they're not present.
+    unsigned int joined[256];
+    int max_layers;
+    int max_annotations;
+    int max_annotation_text_length;
+    int max_canvas_width;
+    int max_canvas_height;
+    bool strict;
+    bool lock_new_users;
+    int local_authority;
+    DP_AclAuditLog audit;
+    bool track_contributors;
+    DP_AclOrphanPolicy orphan_policy;
+    uint8_t orphan_heir_id;
+    bool frozen_features[DP_FEATURE_COUNT];
+} DP_AclPersistentState;
+
+typedef struct DP_AclState {
+    DP_AclPersistentState persistent;
     DP_UserAcls users;
     DP_LayerAclEntry *layers;
+    // Whether any of the layer ACLs lock the layer for anyone, so that checking
+    // for locked layers can skip the lookup when nothing is locked.
+    bool any_layer_locked;
     DP_AnnotationAclEntry *annotations;
     DP_FeatureTiers feature;
+    bool have_default_layer_acl;
+    DP_LayerAcl default_layer_acl;
+    DP_AclRejection last_rejection;
+    DP_LiveLayerEntry *live_layers;
+    DP_AnnotationAclEntry *live_annotations;
+    int canvas_width;
+    int canvas_height;
+    bool local_mode;
+    // The meta message that produced the most recent accepted change.
+    DP_Message *last_change;
+    DP_LayerContributorEntry *contributors;
+    DP_LayerAclVersionEntry *layer_acl_versions;
+    DP_LayerOwnerEntry *layer_owners;
+    // Sequence number of the message currently being handled, -1 if it's not
+    // sequenced. Only set during DP_acl_state_handle_sequenced.
+    long long sequence;
 } DP_AclState;
 
 typedef struct DP_AccessTierAttributes {
@@ -65,6 +149,33 @@ static const DP_AccessTierAttributes access_tier_attributes[] = {
     [DP_ACCESS_TIER_GUEST] = {"DP_ACCESS_TIER_GUEST", "guest"},
 };
 
+static const char *acl_rejection_enum_names[] = {
+    [DP_ACL_REJECTION_NONE] = "DP_ACL_REJECTION_NONE",
+    [DP_ACL_REJECTION_INVALID] = "DP_ACL_REJECTION_INVALID",
+    [DP_ACL_REJECTION_SESSION_LOCKED] = "DP_ACL_REJECTION_SESSION_LOCKED",
+    [DP_ACL_REJECTION_USER_LOCKED] = "DP_ACL_REJECTION_USER_LOCKED",
+    [DP_ACL_REJECTION_NOT_OPERATOR] = "DP_ACL_REJECTION_NOT_OPERATOR",
+    [DP_ACL_REJECTION_FEATURE_TIER] = "DP_ACL_REJECTION_FEATURE_TIER",
+    [DP_ACL_REJECTION_NOT_OWNER] = "DP_ACL_REJECTION_NOT_OWNER",
+    [DP_ACL_REJECTION_LAYER_LOCKED] = "DP_ACL_REJECTION_LAYER_LOCKED",
+    [DP_ACL_REJECTION_ANNOTATION_LOCKED] = "DP_ACL_REJECTION_ANNOTATION_LOCKED",
+    [DP_ACL_REJECTION_ALPHA_LOCKED] = "DP_ACL_REJECTION_ALPHA_LOCKED",
+    [DP_ACL_REJECTION_LAYER_LIMIT] = "DP_ACL_REJECTION_LAYER_LIMIT",
+    [DP_ACL_REJECTION_ANNOTATION_LIMIT] = "DP_ACL_REJECTION_ANNOTATION_LIMIT",
+    [DP_ACL_REJECTION_ANNOTATION_TOO_LONG] =
+        "DP_ACL_REJECTION_ANNOTATION_TOO_LONG",
+    [DP_ACL_REJECTION_NO_SUCH_LAYER] = "DP_ACL_REJECTION_NO_SUCH_LAYER",
+    [DP_ACL_REJECTION_NO_SUCH_ANNOTATION] =
+        "DP_ACL_REJECTION_NO_SUCH_ANNOTATION",
+    [DP_ACL_REJECTION_STALE_LAYER_ACL] = "DP_ACL_REJECTION_STALE_LAYER_ACL",
+    [DP_ACL_REJECTION_COSMETIC_MUTED] = "DP_ACL_REJECTION_COSMETIC_MUTED",
+    [DP_ACL_REJECTION_CANVAS_TOO_LARGE] = "DP_ACL_REJECTION_CANVAS_TOO_LARGE",
+    [DP_ACL_REJECTION_PREPROCESSOR_DROPPED] =
+        "DP_ACL_REJECTION_PREPROCESSOR_DROPPED",
+    [DP_ACL_REJECTION_LAYER_IMMUTABLE] = "DP_ACL_REJECTION_LAYER_IMMUTABLE",
+    [DP_ACL_REJECTION_CATEGORY_LOCKED] = "DP_ACL_REJECTION_CATEGORY_LOCKED",
+};
+
 static DP_FeatureAttributes feature_attributes[] = {
     [DP_FEATURE_PUT_IMAGE] = {"DP_FEATURE_PUT_IMAGE", "put_image"},
     [DP_FEATURE_REGION_MOVE] = {"DP_FEATURE_REGION_MOVE", "region_move"},
@@ -79,6 +190,8 @@ static DP_FeatureAttributes feature_attributes[] = {
     [DP_FEATURE_METADATA] = {"DP_FEATURE_METADATA", "metadata"},
     [DP_FEATURE_TIMELINE] = {"DP_FEATURE_TIMELINE", "timeline"},
     [DP_FEATURE_MYPAINT] = {"DP_FEATURE_MYPAINT", "mypaint"},
+    [DP_FEATURE_CLASSIC_BRUSH] = {"DP_FEATURE_CLASSIC_BRUSH", "classic_brush"},
+    [DP_FEATURE_PIXEL_BRUSH] = {"DP_FEATURE_PIXEL_BRUSH", "pixel_brush"},
 };
 
 int DP_access_tier_clamp(int tier)
@@ -94,6 +207,16 @@ int DP_access_tier_clamp(int tier)
     }
 }
 
+DP_AccessTier DP_access_tier_most_privileged(DP_AccessTier a, DP_AccessTier b)
+{
+    return a < b ? a : b;
+}
+
+DP_AccessTier DP_access_tier_least_privileged(DP_AccessTier a, DP_AccessTier b)
+{
+    return a < b ? b : a;
+}
+
 static const DP_AccessTierAttributes *access_tier_at(int tier)
 {
     if (tier >= 0 && tier < DP_ACCESS_TIER_COUNT) {
@@ -136,6 +259,48 @@ const char *DP_feature_enum_name(int feature)
 }
 
 
+const char *DP_acl_rejection_enum_name(int rejection)
+{
+    if (rejection >= 0 && rejection < DP_ACL_REJECTION_COUNT) {
+        return acl_rejection_enum_names[rejection];
+    }
+    else {
+        DP_error_set("Unknown acl rejection: %d", rejection);
+        return NULL;
+    }
+}
+
+
+bool DP_feature_tiers_equal(const DP_FeatureTiers *a, const DP_FeatureTiers *b)
+{
+    DP_ASSERT(a);
+    DP_ASSERT(b);
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        if (a->tiers[i] != b->tiers[i]) {
+            return false;
+        }
+    }
+    return true;
+}
+
+int DP_feature_tiers_delta(const DP_FeatureTiers *tiers,
+                           const DP_FeatureTiers *prev,
+                           DP_FeatureTierChange *out_changes)
+{
+    DP_ASSERT(tiers);
+    DP_ASSERT(prev);
+    DP_ASSERT(out_changes);
+    int count = 0;
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        DP_AccessTier tier = tiers->tiers[i];
+        if (tier != prev->tiers[i]) {
+            out_changes[count++] = (DP_FeatureTierChange){(DP_Feature)i, tier};
+        }
+    }
+    return count;
+}
+
+
 static uint8_t user_id_index(uint8_t user_id)
 {
     return user_id / 8;
@@ -195,6 +360,57 @@ void DP_user_bits_replace(uint8_t *users, int count, const uint8_t *user_ids)
     DP_user_bits_set(users, count, user_ids);
 }
 
+DP_UserBitsIterator DP_user_bits_iterator_make(const uint8_t *users)
+{
+    DP_ASSERT(users);
+    return (DP_UserBitsIterator){users, 0, 0};
+}
+
+bool DP_user_bits_iterator_next(DP_UserBitsIterator *ubi)
+{
+    DP_ASSERT(ubi);
+    while (ubi->next < 256) {
+        int i = ubi->next;
+        uint8_t bits = ubi->users[i / 8] >> (i % 8);
+        if (bits == 0) {
+            // Nothing left in this byte, skip to the next one.
+            ubi->next = (i / 8 + 1) * 8;
+        }
+        else if (bits & 1) {
+            ubi->user_id = DP_int_to_uint8(i);
+            ubi->next = i + 1;
+            return true;
+        }
+        else {
+            ++ubi->next;
+        }
+    }
+    return false;
+}
+
+int DP_user_bits_count(const uint8_t *users)
+{
+    DP_ASSERT(users);
+    DP_UserBitsIterator ubi = DP_user_bits_iterator_make(users);
+    int count = 0;
+    while (DP_user_bits_iterator_next(&ubi)) {
+        ++count;
+    }
+    return count;
+}
+
+int DP_user_bits_ids(const uint8_t *users, uint8_t *out_user_ids)
+{
+    DP_ASSERT(users);
+    DP_ASSERT(out_user_ids);
+    DP_UserBitsIterator ubi = DP_user_bits_iterator_make(users);
+    int count = 0;
+    while (DP_user_bits_iterator_next(&ubi)) {
+        out_user_ids[count++] = ubi.user_id;
+    }
+    return count;
+}
+
 
 bool DP_user_acls_is_op(const DP_UserAcls *users, uint8_t user_id)
 {
@@ -217,6 +433,11 @@ bool DP_user_acls_is_locked(const DP_UserAcls *users, uint8_t user_id)
     return DP_user_bit_get(users->locked, user_id);
 }
 
+bool DP_user_acls_is_cosmetic_muted(const DP_UserAcls *users, uint8_t user_id)
+{
+    return DP_user_bit_get(users->cosmetic_muted, user_id);
+}
+
 DP_AccessTier DP_user_acls_tier(const DP_UserAcls *users, uint8_t user_id)
 {
     DP_ASSERT(users);
@@ -227,13 +448,35 @@ DP_AccessTier DP_user_acls_tier(const DP_UserAcls *users, uint8_t user_id)
         return DP_ACCESS_TIER_TRUSTED;
     }
     else if (DP_user_acls_is_authenticated(users, user_id)) {
-        return DP_ACCESS_TIER_AUTHENTICATED;
+        return users->authenticated_are_trusted ? DP_ACCESS_TIER_TRUSTED
+                                                : DP_ACCESS_TIER_AUTHENTICATED;
     }
     else {
         return DP_ACCESS_TIER_GUEST;
     }
 }
 
+int DP_user_acls_elevated(const DP_UserAcls *users,
+                          DP_UserTier *out_user_tiers)
+{
+    DP_ASSERT(users);
+    DP_ASSERT(out_user_tiers);
+    int count = 0;
+    for (int i = 0; i < (int)sizeof(DP_UserBits); ++i) {
+        // Skip over whole bytes of guests at once.
+        if (users->operators[i] | users->trusted[i] | users->authenticated[i]) {
+            for (int j = 0; j < 8; ++j) {
+                uint8_t user_id = DP_int_to_uint8(i * 8 + j);
+                DP_AccessTier tier = DP_user_acls_tier(users, user_id);
+                if (user_id != 0 && tier != DP_ACCESS_TIER_GUEST) {
+                    out_user_tiers[count++] = (DP_UserTier){user_id, tier};
+                }
+            }
+        }
+    }
+    return count;
+}
+
 
 static DP_FeatureTiers null_feature_tiers(void)
 {
@@ -250,13 +493,141 @@ static DP_FeatureTiers null_feature_tiers(void)
         DP_ACCESS_TIER_OPERATOR,
         DP_ACCESS_TIER_GUEST,
         DP_ACCESS_TIER_GUEST,
+        DP_ACCESS_TIER_GUEST,
+        DP_ACCESS_TIER_GUEST,
     }};
 }
 
+DP_FeatureTiers DP_feature_tiers_default(void)
+{
+    return null_feature_tiers();
+}
+
+static DP_FeatureTiers uniform_feature_tiers(DP_AccessTier tier)
+{
+    DP_FeatureTiers feature;
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        feature.tiers[i] = tier;
+    }
+    return feature;
+}
+
+DP_FeatureTiers DP_feature_tiers_open(void)
+{
+    return uniform_feature_tiers(DP_ACCESS_TIER_GUEST);
+}
+
+DP_FeatureTiers DP_feature_tiers_moderated(void)
+{
+    DP_FeatureTiers feature = uniform_feature_tiers(DP_ACCESS_TIER_GUEST);
+    feature.tiers[DP_FEATURE_PUT_IMAGE] = DP_ACCESS_TIER_TRUSTED;
+    feature.tiers[DP_FEATURE_RESIZE] = DP_ACCESS_TIER_TRUSTED;
+    feature.tiers[DP_FEATURE_BACKGROUND] = DP_ACCESS_TIER_TRUSTED;
+    feature.tiers[DP_FEATURE_EDIT_LAYERS] = DP_ACCESS_TIER_TRUSTED;
+    feature.tiers[DP_FEATURE_OWN_LAYERS] = DP_ACCESS_TIER_TRUSTED;
+    feature.tiers[DP_FEATURE_METADATA] = DP_ACCESS_TIER_TRUSTED;
+    feature.tiers[DP_FEATURE_TIMELINE] = DP_ACCESS_TIER_TRUSTED;
+    return feature;
+}
+
+DP_FeatureTiers DP_feature_tiers_locked_down(void)
+{
+    return uniform_feature_tiers(DP_ACCESS_TIER_OPERATOR);
+}
+
+int DP_feature_tiers_non_default(const DP_FeatureTiers *tiers,
+                                 DP_FeatureTierChange *out_changes)
+{
+    DP_FeatureTiers default_tiers = null_feature_tiers();
+    return DP_feature_tiers_delta(tiers, &default_tiers, out_changes);
+}
+
+static bool tier_can_use(DP_AccessTier feature_tier, DP_AccessTier tier)
+{
+    return feature_tier == DP_ACCESS_TIER_GUEST || tier <= feature_tier;
+}
+
+int DP_feature_tiers_blocked_for(const DP_FeatureTiers *tiers,
+                                 DP_AccessTier tier,
+                                 DP_Feature *out_features_or_null)
+{
+    DP_ASSERT(tiers);
+    DP_ASSERT(tier >= 0);
+    DP_ASSERT(tier < DP_ACCESS_TIER_COUNT);
+    int count = 0;
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        if (!tier_can_use(tiers->tiers[i], tier)) {
+            if (out_features_or_null) {
+                out_features_or_null[count] = (DP_Feature)i;
+            }
+            ++count;
+        }
+    }
+    return count;
+}
+
+static bool validate_fail(int index, int *out_index_or_null)
+{
+    if (out_index_or_null) {
+        *out_index_or_null = index;
+    }
+    return false;
+}
+
+bool DP_feature_tiers_validate(const uint8_t *tiers, int count,
+                               int *out_index_or_null)
+{
+    DP_ASSERT(tiers || count == 0);
+    if (count != DP_FEATURE_COUNT) {
+        DP_error_set("Got %d feature tier(s), expected %d", count,
+                     DP_FEATURE_COUNT);
+        return validate_fail(-1, out_index_or_null);
+    }
+
+    for (int i = 0; i < count; ++i) {
+        uint8_t tier = tiers[i];
+        if (tier > DP_ACCESS_TIER_GUEST && tier != 255) {
+            DP_error_set("Invalid tier %d for %s", (int)tier,
+                         DP_feature_enum_name(i));
+            return validate_fail(i, out_index_or_null);
+        }
+    }
+    return true;
+}
+
 static DP_AclState null_acl_state(void)
 {
     return (DP_AclState){
-        0, {{0}, {0}, {0}, {0}, false}, NULL, NULL, null_feature_tiers()};
+        .persistent =
+            {
+                .operator_policy = DP_ACL_OPERATOR_POLICY_ALLOW_EMPTY,
+                .local_authority = -1,
+                .audit = {NULL, 0, 0, 0},
+                .orphan_policy = DP_ACL_ORPHAN_POLICY_IGNORE,
+            },
+        .users = {{0}, {0}, {0}, {0}, {0}, false, 0, false},
+        .feature = null_feature_tiers(),
+        .default_layer_acl = {false, false, false, DP_ACCESS_TIER_GUEST, {0}},
+        .last_rejection = DP_ACL_REJECTION_NONE,
+        .sequence = -1,
+    };
+}
+
+// Frozen features keep their current tier, whatever the new tiers say.
+static void keep_frozen_tiers(DP_AclState *acls, DP_FeatureTiers *feature)
+{
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        if (acls->persistent.frozen_features[i]) {
+            feature->tiers[i] = acls->feature.tiers[i];
+        }
+    }
+}
+
+static void set_feature_tiers_unfrozen(DP_AclState *acls,
+                                       DP_FeatureTiers feature)
+{
+    keep_frozen_tiers(acls, &feature);
+    acls->feature = feature;
 }
 
 DP_AclState *DP_acl_state_new(void)
@@ -276,6 +647,48 @@ DP_AclState *DP_acl_state_new_playback(void)
     return acls;
 }
 
+static bool user_bits_all_set(const uint8_t *users)
+{
+    for (size_t i = 0; i < sizeof(DP_UserBits); ++i) {
+        if (users[i] != 0xff) {
+            return false;
+        }
+    }
+    return true;
+}
+
+// Alpha locks only restrict what can be drawn, so they don't count.
+static bool layer_acl_locks_anyone(const DP_LayerAcl *l)
+{
+    return l->locked || l->immutable || l->tier != DP_ACCESS_TIER_GUEST
+        || !user_bits_all_set(l->exclusive);
+}
+
+// Must be called whenever a layer ACL is added, changed or removed.
+static void update_any_layer_locked(DP_AclState *acls)
+{
+    DP_LayerAclEntry *entry, *tmp;
+    HASH_ITER(hh, acls->layers, entry, tmp) {
+        if (layer_acl_locks_anyone(&entry->layer_acl)) {
+            acls->any_layer_locked = true;
+            return;
+        }
+    }
+    acls->any_layer_locked = false;
+}
+
+// Layer ACLs set while handling a sequenced message only apply to messages
+// from that point on, all others apply to everything.
+static void set_layer_acl_effective(DP_AclState *acls, DP_LayerAclEntry *entry)
+{
+    entry->effective_from = acls->sequence < 0 ? 0 : acls->sequence;
+}
+
+static bool layer_acl_in_effect(DP_AclState *acls, DP_LayerAclEntry *entry)
+{
+    return acls->sequence < 0 || acls->sequence >= entry->effective_from;
+}
+
 static void clone_layers(DP_AclState *acls, DP_AclState *clone)
 {
     DP_LayerAclEntry *entry, *tmp;
@@ -283,32 +696,86 @@ static void clone_layers(DP_AclState *acls, DP_AclState *clone)
         DP_LayerAclEntry *entry_clone = DP_malloc(sizeof(*entry_clone));
         entry_clone->layer_id = entry->layer_id;
         entry_clone->layer_acl = entry->layer_acl;
+        entry_clone->effective_from = entry->effective_from;
         HASH_ADD_INT(clone->layers, layer_id, entry_clone);
     }
+    clone->any_layer_locked = acls->any_layer_locked;
 }
 
-static void clone_annotations(DP_AclState *acls, DP_AclState *clone)
+static void clone_live_layers(DP_AclState *acls, DP_AclState *clone)
+{
+    DP_LiveLayerEntry *entry, *tmp;
+    HASH_ITER(hh, acls->live_layers, entry, tmp) {
+        DP_LiveLayerEntry *entry_clone = DP_malloc(sizeof(*entry_clone));
+        entry_clone->layer_id = entry->layer_id;
+        entry_clone->parent_id = entry->parent_id;
+        entry_clone->doomed = false;
+        HASH_ADD_INT(clone->live_layers, layer_id, entry_clone);
+    }
+}
+
+static void clone_annotation_entries(DP_AnnotationAclEntry *entries,
+                                     DP_AnnotationAclEntry **out_clones)
 {
     DP_AnnotationAclEntry *entry, *tmp;
-    HASH_ITER(hh, acls->annotations, entry, tmp) {
+    HASH_ITER(hh, entries, entry, tmp) {
         DP_AnnotationAclEntry *entry_clone = DP_malloc(sizeof(*entry_clone));
         entry_clone->annotation_id = entry->annotation_id;
-        HASH_ADD_INT(clone->annotations, annotation_id, entry_clone);
+        HASH_ADD_INT(*out_clones, annotation_id, entry_clone);
     }
 }
 
+static void clone_layer_owners(DP_AclState *acls, DP_AclState *clone)
+{
+    DP_LayerOwnerEntry *entry, *tmp;
+    HASH_ITER(hh, acls->layer_owners, entry, tmp) {
+        DP_LayerOwnerEntry *entry_clone = DP_malloc(sizeof(*entry_clone));
+        entry_clone->layer_id = entry->layer_id;
+        entry_clone->owner_id = entry->owner_id;
+        HASH_ADD_INT(clone->layer_owners, layer_id, entry_clone);
+    }
+}
+
+static void clone_annotations(DP_AclState *acls, DP_AclState *clone)
+{
+    clone_annotation_entries(acls->annotations, &clone->annotations);
+    clone_annotation_entries(acls->live_annotations,
+                             &clone->live_annotations);
+}
+
 DP_AclState *DP_acl_state_new_clone(DP_AclState *acls, uint8_t local_user_id)
 {
     DP_ASSERT(acls);
     DP_AclState *clone = DP_acl_state_new();
-    clone->local_user_id = local_user_id;
     clone->users = acls->users;
     clone_layers(acls, clone);
+    clone_live_layers(acls, clone);
+    clone_layer_owners(acls, clone);
     clone_annotations(acls, clone);
     clone->feature = acls->feature;
+    clone->have_default_layer_acl = acls->have_default_layer_acl;
+    clone->default_layer_acl = acls->default_layer_acl;
+    // The clone starts out with an empty audit log of its own and without
+    // contributor tracking.
+    DP_AclAuditLog audit = clone->persistent.audit;
+    bool track_contributors = clone->persistent.track_contributors;
+    clone->persistent = acls->persistent;
+    clone->persistent.local_user_id = local_user_id;
+    clone->persistent.audit = audit;
+    clone->persistent.track_contributors = track_contributors;
+    clone->canvas_width = acls->canvas_width;
+    clone->canvas_height = acls->canvas_height;
+    clone->local_mode =
+        acls->local_mode && acls->persistent.local_user_id == local_user_id;
     return clone;
 }
 
+static void clear_last_change(DP_AclState *acls)
+{
+    DP_message_decref_nullable(acls->last_change);
+    acls->last_change = NULL;
+}
+
 static void clear_layers(DP_AclState *acls)
 {
     DP_LayerAclEntry *entry, *tmp;
@@ -316,13 +783,56 @@ static void clear_layers(DP_AclState *acls)
         HASH_DEL(acls->layers, entry);
         DP_free(entry);
     }
+    acls->any_layer_locked = false;
 }
 
-static void clear_annotations(DP_AclState *acls)
+static void clear_live_layers(DP_AclState *acls)
+{
+    DP_LiveLayerEntry *entry, *tmp;
+    HASH_ITER(hh, acls->live_layers, entry, tmp) {
+        HASH_DEL(acls->live_layers, entry);
+        DP_free(entry);
+    }
+}
+
+static void clear_layer_owners(DP_AclState *acls)
+{
+    DP_LayerOwnerEntry *entry, *tmp;
+    HASH_ITER(hh, acls->layer_owners, entry, tmp) {
+        HASH_DEL(acls->layer_owners, entry);
+        DP_free(entry);
+    }
+}
+
+static void clear_annotation_entries(DP_AnnotationAclEntry **entries)
 {
     DP_AnnotationAclEntry *entry, *tmp;
-    HASH_ITER(hh, acls->annotations, entry, tmp) {
-        HASH_DEL(acls->annotations, entry);
+    HASH_ITER(hh, *entries, entry, tmp) {
+        HASH_DEL(*entries, entry);
+        DP_free(entry);
+    }
+}
+
+static void clear_annotations(DP_AclState *acls)
+{
+    clear_annotation_entries(&acls->annotations);
+    clear_annotation_entries(&acls->live_annotations);
+}
+
+static void clear_contributors(DP_AclState *acls)
+{
+    DP_LayerContributorEntry *entry, *tmp;
+    HASH_ITER(hh, acls->contributors, entry, tmp) {
+        HASH_DEL(acls->contributors, entry);
+        DP_free(entry);
+    }
+}
+
+static void clear_layer_acl_versions(DP_AclState *acls)
+{
+    DP_LayerAclVersionEntry *entry, *tmp;
+    HASH_ITER(hh, acls->layer_acl_versions, entry, tmp) {
+        HASH_DEL(acls->layer_acl_versions, entry);
         DP_free(entry);
     }
 }
@@ -331,65 +841,382 @@ void DP_acl_state_free(DP_AclState *acls)
 {
     if (acls) {
         clear_layers(acls);
+        clear_live_layers(acls);
+        clear_layer_owners(acls);
         clear_annotations(acls);
+        clear_contributors(acls);
+        clear_layer_acl_versions(acls);
+        DP_free(acls->persistent.audit.entries);
+        DP_message_decref_nullable(acls->last_change);
         DP_free(acls);
     }
 }
 
-void DP_acl_state_reset(DP_AclState *acls, uint8_t local_user_id)
+static int compare_snapshot_layers(const void *a, const void *b)
 {
-    DP_ASSERT(acls);
-    clear_layers(acls);
-    clear_annotations(acls);
-    *acls = null_acl_state();
-    acls->local_user_id = local_user_id;
-    if (local_user_id != 0) {
-        DP_user_bit_set(acls->users.operators, local_user_id);
-    }
+    int x = ((const DP_AclSnapshotLayer *)a)->layer_id;
+    int y = ((const DP_AclSnapshotLayer *)b)->layer_id;
+    return x < y ? -1 : x > y ? 1 : 0;
 }
 
-static void dump_user_bits(DP_Output *output, const char *title,
-                           const uint8_t *users)
+static int compare_snapshot_live_layers(const void *a, const void *b)
 {
-    if (title) {
-        DP_output_format(output, "    %s: ", title);
+    int x = ((const DP_AclSnapshotLiveLayer *)a)->layer_id;
+    int y = ((const DP_AclSnapshotLiveLayer *)b)->layer_id;
+    return x < y ? -1 : x > y ? 1 : 0;
+}
+
+static int compare_snapshot_ids(const void *a, const void *b)
+{
+    int x = *(const int *)a;
+    int y = *(const int *)b;
+    return x < y ? -1 : x > y ? 1 : 0;
+}
+
+static void snapshot_layers(DP_AclState *acls, DP_AclSnapshot *snapshot)
+{
+    int count = DP_uint_to_int(HASH_COUNT(acls->layers));
+    DP_AclSnapshotLayer *layers =
+        count == 0 ? NULL : DP_malloc(sizeof(*layers) * DP_int_to_size(count));
+    int i = 0;
+    DP_LayerAclEntry *entry, *tmp;
+    HASH_ITER(hh, acls->layers, entry, tmp) {
+        layers[i++] = (DP_AclSnapshotLayer){entry->layer_id, entry->layer_acl};
     }
-    bool first = true;
-    for (int i = 0; i < 256; ++i) {
-        if (DP_user_bit_get(users, DP_int_to_uint8(i))) {
-            DP_output_format(output, first ? "%d" : ", %d", i);
-            first = false;
-        }
+    if (count != 0) {
+        qsort(layers, DP_int_to_size(count), sizeof(*layers),
+              compare_snapshot_layers);
     }
-    DP_output_print(output, first ? "(none)\n" : "\n");
+    snapshot->layer_count = count;
+    snapshot->layers = layers;
 }
 
-static void dump_layer_acls(DP_Output *output, DP_AclState *acls)
+static void snapshot_live_layers(DP_AclState *acls, DP_AclSnapshot *snapshot)
 {
-    if (acls->layers) {
-        DP_OUTPUT_PRINT_LITERAL(output, "    layers:\n");
-        DP_LayerAclEntry *entry, *tmp;
-        HASH_ITER(hh, acls->layers, entry, tmp) {
-            DP_LayerAcl *la = &entry->layer_acl;
-            DP_output_format(
-                output, "        layer_id %d, locked %d, tier %s, exclusive: ",
-                entry->layer_id, la->locked ? 1 : 0,
-                access_tier_attributes[la->tier].name);
-            dump_user_bits(output, NULL, la->exclusive);
-        }
+    int count = DP_uint_to_int(HASH_COUNT(acls->live_layers));
+    DP_AclSnapshotLiveLayer *live_layers =
+        count == 0 ? NULL
+                   : DP_malloc(sizeof(*live_layers) * DP_int_to_size(count));
+    int i = 0;
+    DP_LiveLayerEntry *entry, *tmp;
+    HASH_ITER(hh, acls->live_layers, entry, tmp) {
+        live_layers[i++] =
+            (DP_AclSnapshotLiveLayer){entry->layer_id, entry->parent_id};
     }
-    else {
-        DP_OUTPUT_PRINT_LITERAL(output, "    layers: (none)\n");
+    if (count != 0) {
+        qsort(live_layers, DP_int_to_size(count), sizeof(*live_layers),
+              compare_snapshot_live_layers);
     }
+    snapshot->live_layer_count = count;
+    snapshot->live_layers = live_layers;
 }
 
-static void dump_annotation_acls(DP_Output *output, DP_AclState *acls)
+static int *snapshot_annotation_entries(DP_AnnotationAclEntry *entries,
+                                        int *out_count)
 {
-    DP_OUTPUT_PRINT_LITERAL(output, "    annotations: ");
-    bool first = true;
+    int count = DP_uint_to_int(HASH_COUNT(entries));
+    int *ids =
+        count == 0 ? NULL : DP_malloc(sizeof(*ids) * DP_int_to_size(count));
+    int i = 0;
     DP_AnnotationAclEntry *entry, *tmp;
-    HASH_ITER(hh, acls->annotations, entry, tmp) {
-        DP_output_format(output, first ? "%d" : ", %d", entry->annotation_id);
+    HASH_ITER(hh, entries, entry, tmp) {
+        ids[i++] = entry->annotation_id;
+    }
+    if (count != 0) {
+        qsort(ids, DP_int_to_size(count), sizeof(*ids), compare_snapshot_ids);
+    }
+    *out_count = count;
+    return ids;
+}
+
+void DP_acl_state_snapshot(DP_AclState *acls, DP_AclSnapshot *out_snapshot)
+{
+    DP_ASSERT(acls);
+    DP_ASSERT(out_snapshot);
+    out_snapshot->local_user_id = acls->persistent.local_user_id;
+    out_snapshot->users = acls->users;
+    out_snapshot->feature = acls->feature;
+    out_snapshot->have_default_layer_acl = acls->have_default_layer_acl;
+    out_snapshot->default_layer_acl = acls->default_layer_acl;
+    out_snapshot->operator_policy = acls->persistent.operator_policy;
+    out_snapshot->join_counter = acls->persistent.join_counter;
+    memcpy(out_snapshot->joined, acls->persistent.joined,
+           sizeof(out_snapshot->joined));
+    out_snapshot->max_layers = acls->persistent.max_layers;
+    out_snapshot->max_annotations = acls->persistent.max_annotations;
+    out_snapshot->max_annotation_text_length =
+        acls->persistent.max_annotation_text_length;
+    out_snapshot->max_canvas_width = acls->persistent.max_canvas_width;
+    out_snapshot->max_canvas_height = acls->persistent.max_canvas_height;
+    out_snapshot->canvas_width = acls->canvas_width;
+    out_snapshot->canvas_height = acls->canvas_height;
+    out_snapshot->strict = acls->persistent.strict;
+    out_snapshot->lock_new_users = acls->persistent.lock_new_users;
+    out_snapshot->local_authority = acls->persistent.local_authority;
+    out_snapshot->local_mode = acls->local_mode;
+    snapshot_layers(acls, out_snapshot);
+    snapshot_live_layers(acls, out_snapshot);
+    out_snapshot->annotation_ids = snapshot_annotation_entries(
+        acls->annotations, &out_snapshot->annotation_count);
+    out_snapshot->live_annotation_ids = snapshot_annotation_entries(
+        acls->live_annotations, &out_snapshot->live_annotation_count);
+}
+
+static void restore_annotation_entries(DP_AnnotationAclEntry **entries,
+                                       int count, const int *ids)
+{
+    for (int i = 0; i < count; ++i) {
+        DP_AnnotationAclEntry *entry = DP_malloc(sizeof(*entry));
+        entry->annotation_id = ids[i];
+        HASH_ADD_INT(*entries, annotation_id, entry);
+    }
+}
+
+void DP_acl_state_restore(DP_AclState *acls, const DP_AclSnapshot *snapshot)
+{
+    DP_ASSERT(acls);
+    DP_ASSERT(snapshot);
+    clear_layers(acls);
+    clear_live_layers(acls);
+    clear_layer_owners(acls);
+    clear_annotations(acls);
+    clear_contributors(acls);
+    clear_layer_acl_versions(acls);
+    clear_last_change(acls);
+    // The snapshot doesn't carry the audit log, orphan policy and frozen
+    // features, those stay as they are. The rest gets overwritten below.
+    DP_AclPersistentState persistent = acls->persistent;
+    DP_FeatureTiers feature = acls->feature;
+    *acls = null_acl_state();
+    acls->persistent = persistent;
+    acls->feature = feature;
+    acls->persistent.local_user_id = snapshot->local_user_id;
+    acls->users = snapshot->users;
+    set_feature_tiers_unfrozen(acls, snapshot->feature);
+    acls->have_default_layer_acl = snapshot->have_default_layer_acl;
+    acls->default_layer_acl = snapshot->default_layer_acl;
+    acls->persistent.operator_policy = snapshot->operator_policy;
+    acls->persistent.join_counter = snapshot->join_counter;
+    memcpy(acls->persistent.joined, snapshot->joined,
+           sizeof(acls->persistent.joined));
+    acls->persistent.max_layers = snapshot->max_layers;
+    acls->persistent.max_annotations = snapshot->max_annotations;
+    acls->persistent.max_annotation_text_length =
+        snapshot->max_annotation_text_length;
+    acls->persistent.max_canvas_width = snapshot->max_canvas_width;
+    acls->persistent.max_canvas_height = snapshot->max_canvas_height;
+    acls->canvas_width = snapshot->canvas_width;
+    acls->canvas_height = snapshot->canvas_height;
+    acls->persistent.strict = snapshot->strict;
+    acls->persistent.lock_new_users = snapshot->lock_new_users;
+    acls->persistent.local_authority = snapshot->local_authority;
+    acls->local_mode = snapshot->local_mode;
+
+    for (int i = 0; i < snapshot->layer_count; ++i) {
+        DP_LayerAclEntry *entry = DP_malloc(sizeof(*entry));
+        entry->layer_id = snapshot->layers[i].layer_id;
+        entry->layer_acl = snapshot->layers[i].layer_acl;
+        entry->effective_from = 0;
+        HASH_ADD_INT(acls->layers, layer_id, entry);
+    }
+    update_any_layer_locked(acls);
+
+    for (int i = 0; i < snapshot->live_layer_count; ++i) {
+        DP_LiveLayerEntry *entry = DP_malloc(sizeof(*entry));
+        entry->layer_id = snapshot->live_layers[i].layer_id;
+        entry->parent_id = snapshot->live_layers[i].parent_id;
+        entry->doomed = false;
+        HASH_ADD_INT(acls->live_layers, layer_id, entry);
+    }
+
+    restore_annotation_entries(&acls->annotations, snapshot->annotation_count,
+                               snapshot->annotation_ids);
+    restore_annotation_entries(&acls->live_annotations,
+                               snapshot->live_annotation_count,
+                               snapshot->live_annotation_ids);
+}
+
+static bool user_acls_equal(const DP_UserAcls *a, const DP_UserAcls *b)
+{
+    return memcmp(a->operators, b->operators, sizeof(DP_UserBits)) == 0
+        && memcmp(a->trusted, b->trusted, sizeof(DP_UserBits)) == 0
+        && memcmp(a->authenticated, b->authenticated, sizeof(DP_UserBits)) == 0
+        && memcmp(a->locked, b->locked, sizeof(DP_UserBits)) == 0
+        && memcmp(a->cosmetic_muted, b->cosmetic_muted, sizeof(DP_UserBits))
+               == 0
+        && a->all_locked == b->all_locked
+        && a->locked_categories == b->locked_categories
+        && a->authenticated_are_trusted == b->authenticated_are_trusted;
+}
+
+static bool layer_acls_equal(const DP_LayerAcl *a, const DP_LayerAcl *b)
+{
+    return a->locked == b->locked && a->alpha_locked == b->alpha_locked
+        && a->immutable == b->immutable && a->tier == b->tier
+        && memcmp(a->exclusive, b->exclusive, sizeof(DP_UserBits)) == 0;
+}
+
+static bool ids_equal(int count, const int *a, const int *b)
+{
+    return count == 0 || memcmp(a, b, sizeof(*a) * DP_int_to_size(count)) == 0;
+}
+
+bool DP_acl_snapshot_equal(const DP_AclSnapshot *a, const DP_AclSnapshot *b)
+{
+    DP_ASSERT(a);
+    DP_ASSERT(b);
+    if (a->local_user_id != b->local_user_id
+        || !user_acls_equal(&a->users, &b->users)
+        || !DP_feature_tiers_equal(&a->feature, &b->feature)
+        || a->have_default_layer_acl != b->have_default_layer_acl
+        || !layer_acls_equal(&a->default_layer_acl, &b->default_layer_acl)
+        || a->operator_policy != b->operator_policy
+        || a->join_counter != b->join_counter
+        || memcmp(a->joined, b->joined, sizeof(a->joined)) != 0
+        || a->max_layers != b->max_layers
+        || a->max_annotations != b->max_annotations
+        || a->max_annotation_text_length != b->max_annotation_text_length
+        || a->max_canvas_width != b->max_canvas_width
+        || a->max_canvas_height != b->max_canvas_height
+        || a->canvas_width != b->canvas_width
+        || a->canvas_height != b->canvas_height
+        || a->strict != b->strict || a->lock_new_users != b->lock_new_users
+        || a->local_authority != b->local_authority
+        || a->local_mode != b->local_mode
+        || a->layer_count != b->layer_count
+        || a->live_layer_count != b->live_layer_count
+        || a->annotation_count != b->annotation_count
+        || a->live_annotation_count != b->live_annotation_count) {
+        return false;
+    }
+
+    for (int i = 0; i < a->layer_count; ++i) {
+        if (a->layers[i].layer_id != b->layers[i].layer_id
+            || !layer_acls_equal(&a->layers[i].layer_acl,
+                                 &b->layers[i].layer_acl)) {
+            return false;
+        }
+    }
+
+    for (int i = 0; i < a->live_layer_count; ++i) {
+        if (a->live_layers[i].layer_id != b->live_layers[i].layer_id
+            || a->live_layers[i].parent_id != b->live_layers[i].parent_id) {
+            return false;
+        }
+    }
+
+    return ids_equal(a->annotation_count, a->annotation_ids, b->annotation_ids)
+        && ids_equal(a->live_annotation_count, a->live_annotation_ids,
+                     b->live_annotation_ids);
+}
+
+void DP_acl_snapshot_dispose(DP_AclSnapshot *snapshot)
+{
+    if (snapshot) {
+        DP_free(snapshot->layers);
+        DP_free(snapshot->live_layers);
+        DP_free(snapshot->annotation_ids);
+        DP_free(snapshot->live_annotation_ids);
+        snapshot->layer_count = 0;
+        snapshot->layers = NULL;
+        snapshot->live_layer_count = 0;
+        snapshot->live_layers = NULL;
+        snapshot->annotation_count = 0;
+        snapshot->annotation_ids = NULL;
+        snapshot->live_annotation_count = 0;
+        snapshot->live_annotation_ids = NULL;
+    }
+}
+
+void DP_acl_state_reset(DP_AclState *acls, uint8_t local_user_id)
+{
+    DP_ASSERT(acls);
+    clear_layers(acls);
+    clear_live_layers(acls);
+    clear_layer_owners(acls);
+    clear_annotations(acls);
+    clear_contributors(acls);
+    clear_layer_acl_versions(acls);
+    clear_last_change(acls);
+    // Users stay connected through a reset, so the operator policy still needs
+    // to know in which order they joined, and the session's limits and
+    // settings keep applying to them.
+    DP_AclPersistentState persistent = acls->persistent;
+    bool authenticated_are_trusted = acls->users.authenticated_are_trusted;
+    DP_FeatureTiers feature = acls->feature;
+    *acls = null_acl_state();
+    acls->persistent = persistent;
+    acls->persistent.local_user_id = local_user_id;
+    acls->users.authenticated_are_trusted = authenticated_are_trusted;
+    acls->feature = feature;
+    set_feature_tiers_unfrozen(acls, null_feature_tiers());
+    if (local_user_id != 0) {
+        DP_user_bit_set(acls->users.operators, local_user_id);
+        // The local user is the only operator and nothing is restricted, so
+        // their commands can't be rejected until something changes that.
+        acls->local_mode = persistent.max_layers == 0
+                        && persistent.max_annotations == 0
+                        && persistent.max_annotation_text_length == 0
+                        && persistent.max_canvas_width == 0
+                        && persistent.max_canvas_height == 0
+                        && !persistent.strict;
+    }
+}
+
+void DP_acl_state_reset_with_operators(DP_AclState *acls, uint8_t local_user_id,
+                                       int operator_count,
+                                       const uint8_t *operator_ids)
+{
+    DP_ASSERT(acls);
+    DP_ASSERT(operator_count >= 0);
+    DP_ASSERT(operator_count == 0 || operator_ids);
+    DP_acl_state_reset(acls, local_user_id);
+    DP_user_bits_set(acls->users.operators, operator_count, operator_ids);
+}
+
+static void dump_user_bits(DP_Output *output, const char *title,
+                           const uint8_t *users)
+{
+    if (title) {
+        DP_output_format(output, "    %s: ", title);
+    }
+    bool first = true;
+    DP_UserBitsIterator ubi = DP_user_bits_iterator_make(users);
+    while (DP_user_bits_iterator_next(&ubi)) {
+        DP_output_format(output, first ? "%d" : ", %d", (int)ubi.user_id);
+        first = false;
+    }
+    DP_output_print(output, first ? "(none)\n" : "\n");
+}
+
+static void dump_layer_acls(DP_Output *output, DP_AclState *acls)
+{
+    if (acls->layers) {
+        DP_OUTPUT_PRINT_LITERAL(output, "    layers:\n");
+        DP_LayerAclEntry *entry, *tmp;
+        HASH_ITER(hh, acls->layers, entry, tmp) {
+            DP_LayerAcl *la = &entry->layer_acl;
+            DP_output_format(output,
+                             "        layer_id %d, locked %d, alpha_locked %d, "
+                             "immutable %d, tier %s, exclusive: ",
+                             entry->layer_id, la->locked ? 1 : 0,
+                             la->alpha_locked ? 1 : 0, la->immutable ? 1 : 0,
+                             access_tier_attributes[la->tier].name);
+            dump_user_bits(output, NULL, la->exclusive);
+        }
+    }
+    else {
+        DP_OUTPUT_PRINT_LITERAL(output, "    layers: (none)\n");
+    }
+}
+
+static void dump_annotation_acls(DP_Output *output, DP_AclState *acls)
+{
+    DP_OUTPUT_PRINT_LITERAL(output, "    annotations: ");
+    bool first = true;
+    DP_AnnotationAclEntry *entry, *tmp;
+    HASH_ITER(hh, acls->annotations, entry, tmp) {
+        DP_output_format(output, first ? "%d" : ", %d", entry->annotation_id);
     }
     DP_output_print(output, first ? "(none)\n" : "\n");
 }
@@ -404,11 +1231,16 @@ char *DP_acl_state_dump(DP_AclState *acls)
     DP_output_format(output, "acl state %p\n", (void *)acls);
     DP_output_format(output, "    all_locked: %s\n",
                      acls->users.all_locked ? "true" : "false");
+    DP_output_format(output, "    locked_categories: 0x%x\n",
+                     (unsigned int)acls->users.locked_categories);
+    DP_output_format(output, "    authenticated_are_trusted: %s\n",
+                     acls->users.authenticated_are_trusted ? "true" : "false");
 
     dump_user_bits(output, "operators", acls->users.operators);
     dump_user_bits(output, "trusted", acls->users.trusted);
     dump_user_bits(output, "authenticated", acls->users.authenticated);
     dump_user_bits(output, "locked", acls->users.locked);
+    dump_user_bits(output, "cosmetic_muted", acls->users.cosmetic_muted);
 
     DP_OUTPUT_PRINT_LITERAL(output, "    features:\n");
     for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
@@ -417,6 +1249,19 @@ char *DP_acl_state_dump(DP_AclState *acls)
                          access_tier_attributes[acls->feature.tiers[i]].name);
     }
 
+    if (acls->have_default_layer_acl) {
+        DP_LayerAcl *la = &acls->default_layer_acl;
+        DP_output_format(output,
+                         "    default layer: locked %d, alpha_locked %d, "
+                         "tier %s, exclusive: ",
+                         la->locked ? 1 : 0, la->alpha_locked ? 1 : 0,
+                         access_tier_attributes[la->tier].name);
+        dump_user_bits(output, NULL, la->exclusive);
+    }
+    else {
+        DP_OUTPUT_PRINT_LITERAL(output, "    default layer: (none)\n");
+    }
+
     dump_layer_acls(output, acls);
     dump_annotation_acls(output, acls);
 
@@ -425,718 +1270,2645 @@ char *DP_acl_state_dump(DP_AclState *acls)
     return buffer;
 }
 
-uint8_t DP_acl_state_local_user_id(DP_AclState *acls)
+static void summarize_user_bits(DP_Output *output, const char *title,
+                                const uint8_t *users)
 {
-    DP_ASSERT(acls);
-    return acls->local_user_id;
+    DP_output_format(output, "%s: ", title);
+    bool first = true;
+    for (int i = 1; i < 256; ++i) {
+        if (DP_user_bit_get(users, DP_int_to_uint8(i))) {
+            DP_output_format(output, first ? "%d" : ", %d", i);
+            first = false;
+        }
+    }
+    DP_output_print(output, first ? "none\n" : "\n");
 }
 
-DP_UserAcls DP_acl_state_users(DP_AclState *acls)
+static void summarize_layer_acl(DP_Output *output, int layer_id,
+                                const DP_LayerAcl *la)
 {
-    DP_ASSERT(acls);
-    return acls->users;
+    DP_output_format(output, "\n    0x%04x:", DP_int_to_uint(layer_id));
+    // A locked layer is locked for everyone, the rest doesn't matter then.
+    if (la->immutable) {
+        DP_OUTPUT_PRINT_LITERAL(output, " immutable");
+        return;
+    }
+    else if (la->locked) {
+        DP_OUTPUT_PRINT_LITERAL(output, " locked");
+        return;
+    }
+    if (la->tier != DP_ACCESS_TIER_GUEST) {
+        DP_output_format(output, " %s only",
+                         access_tier_attributes[la->tier].name);
+    }
+    if (!user_bits_all_set(la->exclusive)) {
+        DP_OUTPUT_PRINT_LITERAL(output, " exclusive to");
+        bool first = true;
+        for (int i = 1; i < 256; ++i) {
+            if (DP_user_bit_get(la->exclusive, DP_int_to_uint8(i))) {
+                DP_output_format(output, first ? " %d" : ", %d", i);
+                first = false;
+            }
+        }
+        if (first) {
+            DP_OUTPUT_PRINT_LITERAL(output, " nobody");
+        }
+    }
 }
 
-DP_FeatureTiers DP_acl_state_feature_tiers(DP_AclState *acls)
+char *DP_acl_state_summary(DP_AclState *acls)
 {
     DP_ASSERT(acls);
-    return acls->feature;
-}
+    void **buffer_ptr;
+    size_t *size_ptr;
+    DP_Output *output = DP_mem_output_new(1024, false, &buffer_ptr, &size_ptr);
 
-void DP_acl_state_layers_each(DP_AclState *acls, DP_AclStateLayerFn fn,
-                              void *user)
-{
-    DP_ASSERT(acls);
+    summarize_user_bits(output, "Operators", acls->users.operators);
+    summarize_user_bits(output, "Trusted", acls->users.trusted);
+    if (acls->users.all_locked) {
+        DP_OUTPUT_PRINT_LITERAL(output, "Locked users: everyone\n");
+    }
+    else {
+        summarize_user_bits(output, "Locked users", acls->users.locked);
+    }
+
+    DP_OUTPUT_PRINT_LITERAL(output, "Features:");
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        DP_output_format(output, "%s %s %s", i == 0 ? "" : ",",
+                         feature_attributes[i].name,
+                         access_tier_attributes[acls->feature.tiers[i]].name);
+    }
+
+    DP_OUTPUT_PRINT_LITERAL(output, "\nLocked layers:");
+    bool any_layers = false;
     DP_LayerAclEntry *entry, *tmp;
     HASH_ITER(hh, acls->layers, entry, tmp) {
-        fn(user, entry->layer_id, &entry->layer_acl);
+        if (layer_acl_locks_anyone(&entry->layer_acl)) {
+            summarize_layer_acl(output, entry->layer_id, &entry->layer_acl);
+            any_layers = true;
+        }
     }
+    DP_output_print(output, any_layers ? "\n" : " none\n");
+
+    char *buffer = *buffer_ptr;
+    DP_output_free(output);
+    return buffer;
 }
 
-bool DP_acl_state_is_op(DP_AclState *acls, uint8_t user_id)
+uint8_t DP_acl_state_local_user_id(DP_AclState *acls)
 {
     DP_ASSERT(acls);
-    return DP_user_acls_is_op(&acls->users, user_id);
+    return acls->persistent.local_user_id;
 }
 
-DP_AccessTier DP_acl_state_user_tier(DP_AclState *acls, uint8_t user_id)
+DP_AclOperatorPolicy DP_acl_state_operator_policy(DP_AclState *acls)
 {
     DP_ASSERT(acls);
-    return DP_user_acls_tier(&acls->users, user_id);
+    return acls->persistent.operator_policy;
 }
 
-bool DP_acl_state_can_use_feature(DP_AclState *acls, DP_Feature feature,
-                                  uint8_t user_id)
+void DP_acl_state_operator_policy_set(DP_AclState *acls,
+                                      DP_AclOperatorPolicy policy)
 {
     DP_ASSERT(acls);
-    DP_ASSERT(feature >= 0);
-    DP_ASSERT(feature < DP_FEATURE_COUNT);
-    DP_AccessTier feature_tier = acls->feature.tiers[feature];
-    return feature_tier == DP_ACCESS_TIER_GUEST
-        || DP_acl_state_user_tier(acls, user_id) <= feature_tier;
+    acls->persistent.operator_policy = policy;
 }
 
-bool DP_acl_state_layer_locked_for(DP_AclState *acls, uint8_t user_id,
-                                   int layer_id)
+int DP_acl_state_max_layers(DP_AclState *acls)
 {
     DP_ASSERT(acls);
-    DP_LayerAclEntry *entry;
-    HASH_FIND_INT(acls->layers, &layer_id, entry);
-    if (entry) {
-        DP_LayerAcl *l = &entry->layer_acl;
-        return l->locked || !DP_user_bit_get(l->exclusive, user_id)
-            || l->tier < DP_acl_state_user_tier(acls, user_id);
-    }
-    else {
-        return false;
-    }
+    return acls->persistent.max_layers;
 }
 
-bool DP_acl_state_annotation_locked(DP_AclState *acls, int annotation_id)
+void DP_acl_state_max_layers_set(DP_AclState *acls, int max_layers)
 {
     DP_ASSERT(acls);
-    DP_AnnotationAclEntry *entry;
-    HASH_FIND_INT(acls->annotations, &annotation_id, entry);
-    return entry != NULL;
+    acls->persistent.max_layers = DP_max_int(0, max_layers);
+    if (max_layers > 0) {
+        acls->local_mode = false;
+    }
 }
 
+int DP_acl_state_layer_count(DP_AclState *acls)
+{
+    DP_ASSERT(acls);
+    return DP_uint_to_int(HASH_COUNT(acls->live_layers));
+}
 
-static uint8_t message_user_id(DP_Message *msg)
+int DP_acl_state_max_annotations(DP_AclState *acls)
 {
-    return DP_uint_to_uint8(DP_message_context_id(msg));
+    DP_ASSERT(acls);
+    return acls->persistent.max_annotations;
 }
 
-static uint8_t filter_unless(bool condition)
+void DP_acl_state_max_annotations_set(DP_AclState *acls, int max_annotations)
 {
-    return condition ? 0 : DP_ACL_STATE_FILTERED_BIT;
+    DP_ASSERT(acls);
+    acls->persistent.max_annotations = DP_max_int(0, max_annotations);
+    if (max_annotations > 0) {
+        acls->local_mode = false;
+    }
 }
 
-static bool owns_id(uint8_t user_id, int id)
+int DP_acl_state_max_annotation_text_length(DP_AclState *acls)
 {
-    return (id >> 8) == user_id;
+    DP_ASSERT(acls);
+    return acls->persistent.max_annotation_text_length;
 }
 
-static uint8_t handle_join(DP_AclState *acls, DP_Message *msg)
+void DP_acl_state_max_annotation_text_length_set(DP_AclState *acls,
+                                                 int max_length)
 {
-    DP_MsgJoin *mj = DP_msg_join_cast(msg);
-    if (DP_msg_join_flags(mj) & DP_MSG_JOIN_FLAGS_AUTH) {
-        DP_user_bit_set(acls->users.authenticated, message_user_id(msg));
-        return DP_ACL_STATE_CHANGE_USERS_BIT;
-    }
-    else {
-        return 0;
+    DP_ASSERT(acls);
+    acls->persistent.max_annotation_text_length = DP_max_int(0, max_length);
+    if (max_length > 0) {
+        acls->local_mode = false;
     }
 }
 
-static uint8_t handle_leave(DP_AclState *acls, DP_Message *msg)
+void DP_acl_state_max_canvas_size(DP_AclState *acls, int *out_width,
+                                  int *out_height)
 {
-    uint8_t user_id = message_user_id(msg);
-    DP_user_bit_unset(acls->users.operators, user_id);
-    DP_user_bit_unset(acls->users.trusted, user_id);
-    DP_user_bit_unset(acls->users.authenticated, user_id);
-    DP_user_bit_unset(acls->users.locked, user_id);
-    // TODO remove layer locks
-    return DP_ACL_STATE_CHANGE_USERS_BIT;
+    DP_ASSERT(acls);
+    if (out_width) {
+        *out_width = acls->persistent.max_canvas_width;
+    }
+    if (out_height) {
+        *out_height = acls->persistent.max_canvas_height;
+    }
 }
 
-static uint8_t handle_session_owner(DP_AclState *acls, DP_Message *msg)
+static int clamp_canvas_dimension(int dimension)
 {
-    DP_MsgSessionOwner *mso = DP_msg_session_owner_cast(msg);
-    int count;
-    const uint8_t *user_ids = DP_msg_session_owner_users(mso, &count);
-    DP_user_bits_replace(acls->users.operators, count, user_ids);
-    return DP_ACL_STATE_CHANGE_USERS_BIT;
+    return DP_clamp_int(dimension, 0, DP_ACL_MAX_CANVAS_DIMENSION);
 }
 
-static uint8_t handle_trusted_users(DP_AclState *acls, DP_Message *msg)
+void DP_acl_state_max_canvas_size_set(DP_AclState *acls, int max_width,
+                                      int max_height)
 {
-    DP_MsgTrustedUsers *mtu = DP_msg_trusted_users_cast(msg);
-    int count;
-    const uint8_t *user_ids = DP_msg_trusted_users_users(mtu, &count);
-    DP_user_bits_replace(acls->users.trusted, count, user_ids);
-    return DP_ACL_STATE_CHANGE_USERS_BIT;
+    DP_ASSERT(acls);
+    acls->persistent.max_canvas_width = clamp_canvas_dimension(max_width);
+    acls->persistent.max_canvas_height = clamp_canvas_dimension(max_height);
+    if (max_width > 0 || max_height > 0) {
+        acls->local_mode = false;
+    }
 }
 
-static uint8_t handle_internal(DP_AclState *acls, DP_Message *msg)
+void DP_acl_state_canvas_size(DP_AclState *acls, int *out_width,
+                              int *out_height)
 {
-    DP_MsgInternal *mi = DP_message_internal(msg);
-    DP_MsgInternalType type = DP_msg_internal_type(mi);
-    bool is_reset = type == DP_MSG_INTERNAL_TYPE_RESET
-                 || type == DP_MSG_INTERNAL_TYPE_RESET_TO_STATE;
-    if (is_reset) {
-        clear_layers(acls);
-        clear_annotations(acls);
-        acls->users.all_locked = false;
-        memset_userbits(acls->users.locked, 0);
-        acls->feature = null_feature_tiers();
-        return DP_ACL_STATE_CHANGE_MASK;
+    DP_ASSERT(acls);
+    if (out_width) {
+        *out_width = acls->canvas_width;
     }
-    else {
-        return 0;
+    if (out_height) {
+        *out_height = acls->canvas_height;
     }
 }
 
-static uint8_t handle_user_acl(DP_AclState *acls, DP_Message *msg,
-                               bool override)
+void DP_acl_state_canvas_size_set(DP_AclState *acls, int width, int height)
 {
-    if (override || DP_acl_state_is_op(acls, message_user_id(msg))) {
-        DP_MsgUserAcl *mua = DP_msg_user_acl_cast(msg);
-        int count;
-        const uint8_t *user_ids = DP_msg_user_acl_users(mua, &count);
-        DP_user_bits_replace(acls->users.locked, count, user_ids);
-        return DP_ACL_STATE_CHANGE_USERS_BIT;
-    }
-    else {
-        return DP_ACL_STATE_FILTERED_BIT;
-    }
+    DP_ASSERT(acls);
+    acls->canvas_width = clamp_canvas_dimension(width);
+    acls->canvas_height = clamp_canvas_dimension(height);
 }
 
-static bool can_edit_layer(DP_AclState *acls, uint8_t user_id, int layer_id)
+bool DP_acl_state_strict(DP_AclState *acls)
 {
-    return DP_acl_state_can_use_feature(acls, DP_FEATURE_EDIT_LAYERS, user_id)
-        || (DP_acl_state_can_use_feature(acls, DP_FEATURE_OWN_LAYERS, user_id)
-            && owns_id(user_id, layer_id));
+    DP_ASSERT(acls);
+    return acls->persistent.strict;
 }
 
-static void set_layer_acl(DP_AclState *acls, int layer_id,
-                          DP_LayerAclEntry *entry, uint8_t flags,
-                          int exclusive_count, const uint8_t *exclusive)
+void DP_acl_state_strict_set(DP_AclState *acls, bool strict)
 {
-    if (!entry) {
-        entry = DP_malloc(sizeof(*entry));
-        entry->layer_id = layer_id;
-        HASH_ADD_INT(acls->layers, layer_id, entry);
+    DP_ASSERT(acls);
+    acls->persistent.strict = strict;
+    if (strict) {
+        acls->local_mode = false;
     }
+}
 
-    DP_LayerAcl *l = &entry->layer_acl;
-    l->locked = flags & DP_ACL_ALL_LOCKED_BIT;
+bool DP_acl_state_lock_new_users(DP_AclState *acls)
+{
+    DP_ASSERT(acls);
+    return acls->persistent.lock_new_users;
+}
+
+void DP_acl_state_lock_new_users_set(DP_AclState *acls, bool lock_new_users)
+{
+    DP_ASSERT(acls);
+    acls->persistent.lock_new_users = lock_new_users;
+}
+
+bool DP_acl_state_authenticated_are_trusted(DP_AclState *acls)
+{
+    DP_ASSERT(acls);
+    return acls->users.authenticated_are_trusted;
+}
+
+void DP_acl_state_authenticated_are_trusted_set(DP_AclState *acls,
+                                                bool authenticated_are_trusted)
+{
+    DP_ASSERT(acls);
+    acls->users.authenticated_are_trusted = authenticated_are_trusted;
+}
+
+bool DP_acl_state_feature_frozen(DP_AclState *acls, DP_Feature feature)
+{
+    DP_ASSERT(acls);
+    DP_ASSERT(feature >= 0);
+    DP_ASSERT(feature < DP_FEATURE_COUNT);
+    return acls->persistent.frozen_features[feature];
+}
+
+uint8_t DP_acl_state_feature_freeze(DP_AclState *acls, DP_Feature feature,
+                                    DP_AccessTier tier)
+{
+    DP_ASSERT(acls);
+    DP_ASSERT(feature >= 0);
+    DP_ASSERT(feature < DP_FEATURE_COUNT);
+    DP_ASSERT(tier >= 0);
+    DP_ASSERT(tier < DP_ACCESS_TIER_COUNT);
+    acls->persistent.frozen_features[feature] = true;
+    if (acls->feature.tiers[feature] == tier) {
+        return 0;
+    }
+    else {
+        acls->feature.tiers[feature] = tier;
+        return DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT;
+    }
+}
+
+void DP_acl_state_feature_unfreeze(DP_AclState *acls, DP_Feature feature)
+{
+    DP_ASSERT(acls);
+    DP_ASSERT(feature >= 0);
+    DP_ASSERT(feature < DP_FEATURE_COUNT);
+    acls->persistent.frozen_features[feature] = false;
+}
+
+int DP_acl_state_local_authority(DP_AclState *acls)
+{
+    DP_ASSERT(acls);
+    return acls->persistent.local_authority;
+}
+
+void DP_acl_state_local_authority_set(DP_AclState *acls, int user_id)
+{
+    DP_ASSERT(acls);
+    DP_ASSERT(user_id >= -1);
+    DP_ASSERT(user_id <= UINT8_MAX);
+    acls->persistent.local_authority = user_id;
+}
+
+static bool is_local_authority(DP_AclState *acls, uint8_t user_id)
+{
+    return acls->persistent.local_authority == user_id;
+}
+
+bool DP_acl_state_local_mode(DP_AclState *acls)
+{
+    DP_ASSERT(acls);
+    return acls->local_mode;
+}
+
+int DP_acl_state_annotation_count(DP_AclState *acls)
+{
+    DP_ASSERT(acls);
+    return DP_uint_to_int(HASH_COUNT(acls->live_annotations));
+}
+
+int DP_acl_state_audit_capacity(DP_AclState *acls)
+{
+    DP_ASSERT(acls);
+    return acls->persistent.audit.capacity;
+}
+
+static DP_AclAuditEntry *audit_entry_at(DP_AclAuditLog *audit, int index)
+{
+    return &audit->entries[(audit->start + index) % audit->capacity];
+}
+
+void DP_acl_state_audit_capacity_set(DP_AclState *acls, int capacity)
+{
+    DP_ASSERT(acls);
+    DP_ASSERT(capacity >= 0);
+    DP_AclAuditLog *audit = &acls->persistent.audit;
+    if (capacity != audit->capacity) {
+        DP_AclAuditEntry *entries =
+            capacity == 0
+                ? NULL
+                : DP_malloc(sizeof(*entries) * DP_int_to_size(capacity));
+        // Keep the newest entries that fit into the new capacity.
+        int count = DP_min_int(audit->count, capacity);
+        int skip = audit->count - count;
+        for (int i = 0; i < count; ++i) {
+            entries[i] = *audit_entry_at(audit, skip + i);
+        }
+        DP_free(audit->entries);
+        *audit = (DP_AclAuditLog){entries, capacity, 0, count};
+    }
+}
+
+int DP_acl_state_audit_count(DP_AclState *acls)
+{
+    DP_ASSERT(acls);
+    return acls->persistent.audit.count;
+}
+
+const DP_AclAuditEntry *DP_acl_state_audit_at(DP_AclState *acls, int index)
+{
+    DP_ASSERT(acls);
+    DP_ASSERT(index >= 0);
+    DP_ASSERT(index < acls->persistent.audit.count);
+    return audit_entry_at(&acls->persistent.audit, index);
+}
+
+DP_Message *DP_acl_state_last_change_message_noinc(DP_AclState *acls)
+{
+    DP_ASSERT(acls);
+    return acls->last_change;
+}
+
+bool DP_acl_state_contributors_tracked(DP_AclState *acls)
+{
+    DP_ASSERT(acls);
+    return acls->persistent.track_contributors;
+}
+
+void DP_acl_state_contributors_tracked_set(DP_AclState *acls, bool tracked)
+{
+    DP_ASSERT(acls);
+    if (!tracked) {
+        clear_contributors(acls);
+    }
+    acls->persistent.track_contributors = tracked;
+}
+
+int DP_acl_state_layer_contributors(DP_AclState *acls, int layer_id,
+                                    uint8_t *out_user_ids)
+{
+    DP_ASSERT(acls);
+    DP_ASSERT(out_user_ids);
+    DP_LayerContributorEntry *entry;
+    HASH_FIND_INT(acls->contributors, &layer_id, entry);
+    int count = 0;
+    if (entry) {
+        count = DP_user_bits_ids(entry->users, out_user_ids);
+    }
+    return count;
+}
+
+DP_AclOrphanPolicy DP_acl_state_orphan_policy(DP_AclState *acls)
+{
+    DP_ASSERT(acls);
+    return acls->persistent.orphan_policy;
+}
+
+uint8_t DP_acl_state_orphan_heir_id(DP_AclState *acls)
+{
+    DP_ASSERT(acls);
+    return acls->persistent.orphan_heir_id;
+}
+
+void DP_acl_state_orphan_policy_set(DP_AclState *acls,
+                                    DP_AclOrphanPolicy policy,
+                                    uint8_t heir_id)
+{
+    DP_ASSERT(acls);
+    acls->persistent.orphan_policy = policy;
+    acls->persistent.orphan_heir_id = heir_id;
+}
+
+static DP_LayerOwnerEntry *search_layer_owner(DP_AclState *acls, int layer_id)
+{
+    DP_LayerOwnerEntry *entry;
+    HASH_FIND_INT(acls->layer_owners, &layer_id, entry);
+    return entry;
+}
+
+static void set_layer_owner(DP_AclState *acls, int layer_id, uint8_t owner_id)
+{
+    DP_LayerOwnerEntry *entry = search_layer_owner(acls, layer_id);
+    if (DP_layer_id_creator(layer_id) == owner_id) {
+        // Back to the owner from the layer id, no need to record that.
+        if (entry) {
+            HASH_DEL(acls->layer_owners, entry);
+            DP_free(entry);
+        }
+    }
+    else {
+        if (!entry) {
+            entry = DP_malloc(sizeof(*entry));
+            entry->layer_id = layer_id;
+            HASH_ADD_INT(acls->layer_owners, layer_id, entry);
+        }
+        entry->owner_id = owner_id;
+    }
+}
+
+uint8_t DP_acl_state_layer_owner(DP_AclState *acls, int layer_id)
+{
+    DP_ASSERT(acls);
+    DP_LayerOwnerEntry *entry = search_layer_owner(acls, layer_id);
+    return entry ? entry->owner_id : DP_layer_id_creator(layer_id);
+}
+
+bool DP_acl_state_layer_orphaned(DP_AclState *acls, int layer_id)
+{
+    DP_ASSERT(acls);
+    DP_LayerOwnerEntry *entry = search_layer_owner(acls, layer_id);
+    return entry && entry->owner_id == 0;
+}
+
+int DP_acl_state_orphaned_layers(DP_AclState *acls, int *out_layer_ids_or_null)
+{
+    DP_ASSERT(acls);
+    int count = 0;
+    DP_LayerOwnerEntry *entry, *tmp;
+    HASH_ITER(hh, acls->layer_owners, entry, tmp) {
+        if (entry->owner_id == 0) {
+            if (out_layer_ids_or_null) {
+                out_layer_ids_or_null[count] = entry->layer_id;
+            }
+            ++count;
+        }
+    }
+    return count;
+}
+
+uint8_t DP_acl_state_claim_layer(DP_AclState *acls, int layer_id,
+                                 uint8_t user_id)
+{
+    DP_ASSERT(acls);
+    if (DP_acl_state_layer_orphaned(acls, layer_id)
+        && DP_acl_state_is_op(acls, user_id)) {
+        set_layer_owner(acls, layer_id, user_id);
+        return DP_ACL_STATE_CHANGE_LAYERS_BIT;
+    }
+    else {
+        return 0;
+    }
+}
+
+int DP_acl_state_access_conflicts(DP_AclState *acls,
+                                  DP_AclAccessConflict *out_conflicts_or_null)
+{
+    DP_ASSERT(acls);
+    int count = 0;
+    DP_LayerAclEntry *entry, *tmp;
+    HASH_ITER(hh, acls->layers, entry, tmp) {
+        const uint8_t *exclusive = entry->layer_acl.exclusive;
+        uint8_t owner_id = DP_acl_state_layer_owner(acls, entry->layer_id);
+        if (owner_id != 0 && layer_acl_in_effect(acls, entry)
+            && !user_bits_all_set(exclusive)
+            && !DP_user_bit_get(exclusive, owner_id)) {
+            if (out_conflicts_or_null) {
+                out_conflicts_or_null[count] =
+                    (DP_AclAccessConflict){entry->layer_id, owner_id};
+            }
+            ++count;
+        }
+    }
+    return count;
+}
+
+DP_UserAcls DP_acl_state_users(DP_AclState *acls)
+{
+    DP_ASSERT(acls);
+    return acls->users;
+}
+
+DP_FeatureTiers DP_acl_state_feature_tiers(DP_AclState *acls)
+{
+    DP_ASSERT(acls);
+    return acls->feature;
+}
+
+uint8_t DP_acl_state_feature_tiers_restore(DP_AclState *acls,
+                                           const DP_FeatureTiers *tiers)
+{
+    DP_ASSERT(acls);
+    DP_ASSERT(tiers);
+    DP_FeatureTiers feature = *tiers;
+    keep_frozen_tiers(acls, &feature);
+    if (DP_feature_tiers_equal(&feature, &acls->feature)) {
+        return 0;
+    }
+    else {
+        acls->feature = feature;
+        return DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT;
+    }
+}
+
+void DP_acl_state_layers_each(DP_AclState *acls, DP_AclStateLayerFn fn,
+                              void *user)
+{
+    DP_ASSERT(acls);
+    DP_LayerAclEntry *entry, *tmp;
+    HASH_ITER(hh, acls->layers, entry, tmp) {
+        fn(user, entry->layer_id, &entry->layer_acl);
+    }
+}
+
+const DP_LayerAcl *DP_acl_state_default_layer_acl(DP_AclState *acls)
+{
+    DP_ASSERT(acls);
+    return acls->have_default_layer_acl ? &acls->default_layer_acl : NULL;
+}
+
+bool DP_acl_state_is_op(DP_AclState *acls, uint8_t user_id)
+{
+    DP_ASSERT(acls);
+    return is_local_authority(acls, user_id)
+        || DP_user_acls_is_op(&acls->users, user_id);
+}
+
+DP_AccessTier DP_acl_state_user_tier(DP_AclState *acls, uint8_t user_id)
+{
+    DP_ASSERT(acls);
+    return is_local_authority(acls, user_id)
+             ? DP_ACCESS_TIER_OPERATOR
+             : DP_user_acls_tier(&acls->users, user_id);
+}
+
+bool DP_acl_state_can_use_feature(DP_AclState *acls, DP_Feature feature,
+                                  uint8_t user_id)
+{
+    DP_ASSERT(acls);
+    DP_ASSERT(feature >= 0);
+    DP_ASSERT(feature < DP_FEATURE_COUNT);
+    DP_AccessTier feature_tier = acls->feature.tiers[feature];
+    return feature_tier == DP_ACCESS_TIER_GUEST
+        || DP_acl_state_user_tier(acls, user_id) <= feature_tier;
+}
+
+int DP_acl_state_blocked_features_for_tier(DP_AclState *acls,
+                                           DP_AccessTier tier,
+                                           DP_Feature *out_features_or_null)
+{
+    DP_ASSERT(acls);
+    return DP_feature_tiers_blocked_for(&acls->feature, tier,
+                                        out_features_or_null);
+}
+
+bool DP_acl_state_any_layer_locked(DP_AclState *acls)
+{
+    DP_ASSERT(acls);
+    return acls->any_layer_locked;
+}
+
+bool DP_acl_state_layer_locked_for(DP_AclState *acls, uint8_t user_id,
+                                   int layer_id)
+{
+    DP_ASSERT(acls);
+    if (!acls->any_layer_locked) {
+        return false;
+    }
+
+    DP_LayerAclEntry *entry;
+    HASH_FIND_INT(acls->layers, &layer_id, entry);
+    if (entry && !is_local_authority(acls, user_id)
+        && layer_acl_in_effect(acls, entry)) {
+        DP_LayerAcl *l = &entry->layer_acl;
+        return l->locked || l->immutable
+            || !DP_user_bit_get(l->exclusive, user_id)
+            || l->tier < DP_acl_state_user_tier(acls, user_id);
+    }
+    else {
+        return false;
+    }
+}
+
+int DP_acl_state_drawable_layers(DP_AclState *acls, uint8_t user_id,
+                                 int count, const int *layer_ids,
+                                 int *out_layer_ids)
+{
+    DP_ASSERT(acls);
+    DP_ASSERT(count >= 0);
+    DP_ASSERT(count == 0 || layer_ids);
+    DP_ASSERT(count == 0 || out_layer_ids);
+    int drawable_count = 0;
+    for (int i = 0; i < count; ++i) {
+        int layer_id = layer_ids[i];
+        if (!DP_acl_state_layer_locked_for(acls, user_id, layer_id)) {
+            out_layer_ids[drawable_count++] = layer_id;
+        }
+    }
+    return drawable_count;
+}
+
+bool DP_acl_state_layer_alpha_locked_for(DP_AclState *acls, uint8_t user_id,
+                                         int layer_id)
+{
+    DP_ASSERT(acls);
+    DP_LayerAclEntry *entry;
+    HASH_FIND_INT(acls->layers, &layer_id, entry);
+    return entry && entry->layer_acl.alpha_locked
+        && layer_acl_in_effect(acls, entry)
+        && DP_acl_state_layer_owner(acls, layer_id) != user_id
+        && !DP_acl_state_is_op(acls, user_id);
+}
+
+bool DP_acl_state_layer_immutable(DP_AclState *acls, int layer_id)
+{
+    DP_ASSERT(acls);
+    DP_LayerAclEntry *entry;
+    HASH_FIND_INT(acls->layers, &layer_id, entry);
+    return entry && entry->layer_acl.immutable
+        && layer_acl_in_effect(acls, entry);
+}
+
+bool DP_acl_state_annotation_locked(DP_AclState *acls, int annotation_id)
+{
+    DP_ASSERT(acls);
+    DP_AnnotationAclEntry *entry;
+    HASH_FIND_INT(acls->annotations, &annotation_id, entry);
+    return entry != NULL;
+}
+
+static int compare_report_layers(const void *a, const void *b)
+{
+    int x = ((const DP_AclPermissionReportLayer *)a)->layer_id;
+    int y = ((const DP_AclPermissionReportLayer *)b)->layer_id;
+    return x < y ? -1 : x > y ? 1 : 0;
+}
+
+void DP_acl_state_permission_report(DP_AclState *acls, uint8_t user_id,
+                                    DP_AclPermissionReport *out_report)
+{
+    DP_ASSERT(acls);
+    DP_ASSERT(out_report);
+    out_report->user_id = user_id;
+    out_report->tier = DP_acl_state_user_tier(acls, user_id);
+    out_report->op = DP_acl_state_is_op(acls, user_id);
+    out_report->locked = acls->users.all_locked
+                      || DP_user_bit_get(acls->users.locked, user_id);
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        out_report->features[i] =
+            DP_acl_state_can_use_feature(acls, (DP_Feature)i, user_id);
+    }
+
+    int count = DP_uint_to_int(HASH_COUNT(acls->layers));
+    out_report->layer_count = count;
+    if (count == 0) {
+        out_report->layers = NULL;
+    }
+    else {
+        DP_AclPermissionReportLayer *layers =
+            DP_malloc(sizeof(*layers) * DP_int_to_size(count));
+        int i = 0;
+        DP_LayerAclEntry *entry, *tmp;
+        HASH_ITER(hh, acls->layers, entry, tmp) {
+            int layer_id = entry->layer_id;
+            layers[i++] = (DP_AclPermissionReportLayer){
+                layer_id,
+                DP_acl_state_layer_locked_for(acls, user_id, layer_id),
+                DP_acl_state_layer_alpha_locked_for(acls, user_id, layer_id)};
+        }
+        qsort(layers, DP_int_to_size(count), sizeof(*layers),
+              compare_report_layers);
+        out_report->layers = layers;
+    }
+}
+
+void DP_acl_permission_report_dispose(DP_AclPermissionReport *report)
+{
+    if (report) {
+        DP_free(report->layers);
+        report->layers = NULL;
+        report->layer_count = 0;
+    }
+}
+
+JSON_Value *
+DP_acl_permission_report_to_json(const DP_AclPermissionReport *report)
+{
+    DP_ASSERT(report);
+    JSON_Value *value = json_value_init_object();
+    JSON_Object *obj = json_value_get_object(value);
+    json_object_set_number(obj, "user_id", report->user_id);
+    json_object_set_string(obj, "tier",
+                           access_tier_attributes[report->tier].name);
+    json_object_set_boolean(obj, "op", report->op);
+    json_object_set_boolean(obj, "locked", report->locked);
+
+    JSON_Value *features_value = json_value_init_object();
+    JSON_Object *features = json_value_get_object(features_value);
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        json_object_set_boolean(features, feature_attributes[i].name,
+                                report->features[i]);
+    }
+    json_object_set_value(obj, "features", features_value);
+
+    JSON_Value *layers_value = json_value_init_array();
+    JSON_Array *layers = json_value_get_array(layers_value);
+    for (int i = 0; i < report->layer_count; ++i) {
+        const DP_AclPermissionReportLayer *l = &report->layers[i];
+        JSON_Value *layer_value = json_value_init_object();
+        JSON_Object *layer = json_value_get_object(layer_value);
+        json_object_set_number(layer, "id", l->layer_id);
+        json_object_set_boolean(layer, "locked", l->locked);
+        json_object_set_boolean(layer, "alpha_locked", l->alpha_locked);
+        json_array_append_value(layers, layer_value);
+    }
+    json_object_set_value(obj, "layers", layers_value);
+    return value;
+}
+
+
+static uint8_t message_user_id(DP_Message *msg)
+{
+    return DP_uint_to_uint8(DP_message_context_id(msg));
+}
+
+static uint8_t filter_unless(bool condition)
+{
+    return condition ? 0 : DP_ACL_STATE_FILTERED_BIT;
+}
+
+static bool owns_id(uint8_t user_id, int id)
+{
+    return DP_layer_id_creator(id) == user_id;
+}
+
+// The check_* functions below record why they failed, so that the caller can
+// find out why a message was filtered out through DP_acl_state_last_rejection.
+
+static bool reject(DP_AclState *acls, DP_AclRejection rejection)
+{
+    acls->last_rejection = rejection;
+    return false;
+}
+
+static uint8_t filter_because(DP_AclState *acls, DP_AclRejection rejection)
+{
+    acls->last_rejection = rejection;
+    return DP_ACL_STATE_FILTERED_BIT;
+}
+
+static bool check_op(DP_AclState *acls, uint8_t user_id)
+{
+    return DP_acl_state_is_op(acls, user_id)
+        || reject(acls, DP_ACL_REJECTION_NOT_OPERATOR);
+}
+
+static bool check_feature(DP_AclState *acls, DP_Feature feature,
+                          uint8_t user_id)
+{
+    return DP_acl_state_can_use_feature(acls, feature, user_id)
+        || reject(acls, DP_ACL_REJECTION_FEATURE_TIER);
+}
+
+static bool check_owner_or_op(DP_AclState *acls, uint8_t user_id, int id)
+{
+    return owns_id(user_id, id) || DP_acl_state_is_op(acls, user_id)
+        || reject(acls, DP_ACL_REJECTION_NOT_OWNER);
+}
+
+// Undoing or redoing the actions of another user is a moderation tool. It only
+// affects that user's undo history, everyone else's work is kept.
+static bool check_undo_user(DP_AclState *acls, DP_MsgUndo *mu, uint8_t user_id)
+{
+    uint8_t override_user = DP_msg_undo_override_user(mu);
+    return override_user == 0 || override_user == user_id
+        || check_op(acls, user_id);
+}
+
+static bool check_layer_mutable(DP_AclState *acls, int layer_id)
+{
+    return !DP_acl_state_layer_immutable(acls, layer_id)
+        || reject(acls, DP_ACL_REJECTION_LAYER_IMMUTABLE);
+}
+
+static bool check_layer_unlocked(DP_AclState *acls, uint8_t user_id,
+                                 int layer_id)
+{
+    return check_layer_mutable(acls, layer_id)
+        && (!DP_acl_state_layer_locked_for(acls, user_id, layer_id)
+            || reject(acls, DP_ACL_REJECTION_LAYER_LOCKED));
+}
+
+static bool check_layer_exists(DP_AclState *acls, int layer_id)
+{
+    DP_LiveLayerEntry *entry = NULL;
+    if (acls->persistent.strict) {
+        HASH_FIND_INT(acls->live_layers, &layer_id, entry);
+    }
+    return !acls->persistent.strict || entry
+        || reject(acls, DP_ACL_REJECTION_NO_SUCH_LAYER);
+}
+
+static bool check_layer_paintable(DP_AclState *acls, uint8_t user_id,
+                                  int layer_id, int blend_mode)
+{
+    return check_layer_exists(acls, layer_id)
+        && check_layer_unlocked(acls, user_id, layer_id)
+        && (!DP_blend_mode_can_decrease_opacity(blend_mode)
+            || !DP_acl_state_layer_alpha_locked_for(acls, user_id, layer_id)
+            || reject(acls, DP_ACL_REJECTION_ALPHA_LOCKED));
+}
+
+// Mirrors DP_mypaint_brush_mode_extract in dpengine, which we can't use here.
+static int mypaint_dab_blend_mode(uint8_t mode)
+{
+    if (mode & 0x80) {
+        switch (mode & 0x3) {
+        case 0x1:
+            return DP_BLEND_MODE_NORMAL;
+        case 0x2:
+            return DP_BLEND_MODE_RECOLOR;
+        case 0x3:
+            return DP_BLEND_MODE_ERASE;
+        default:
+            break;
+        }
+    }
+    return DP_BLEND_MODE_NORMAL_AND_ERASER;
+}
+
+static bool user_bits_empty(const uint8_t *users)
+{
+    for (size_t i = 0; i < sizeof(DP_UserBits); ++i) {
+        if (users[i] != 0) {
+            return false;
+        }
+    }
+    return true;
+}
+
+static void promote_oldest_user(DP_AclState *acls)
+{
+    int oldest_user_id = -1;
+    unsigned int oldest_joined = 0;
+    for (int i = 0; i < 256; ++i) {
+        unsigned int joined = acls->persistent.joined[i];
+        if (joined != 0 && (oldest_user_id == -1 || joined < oldest_joined)) {
+            oldest_user_id = i;
+            oldest_joined = joined;
+        }
+    }
+
+    if (oldest_user_id != -1) {
+        DP_user_bit_set(acls->users.operators,
+                        DP_int_to_uint8(oldest_user_id));
+    }
+}
+
+// Called after the operator set was changed. If it ended up empty, the
+// operator policy decides what happens. The previous operators are needed for
+// the policy that keeps the last operators around.
+static void apply_operator_policy(DP_AclState *acls,
+                                  const uint8_t *previous_operators)
+{
+    if (user_bits_empty(acls->users.operators)
+        && !user_bits_empty(previous_operators)) {
+        switch (acls->persistent.operator_policy) {
+        case DP_ACL_OPERATOR_POLICY_ALLOW_EMPTY:
+            break;
+        case DP_ACL_OPERATOR_POLICY_KEEP_LAST:
+            memcpy(acls->users.operators, previous_operators,
+                   sizeof(DP_UserBits));
+            break;
+        case DP_ACL_OPERATOR_POLICY_PROMOTE_OLDEST:
+            promote_oldest_user(acls);
+            break;
+        }
+    }
+}
+
+static uint8_t handle_join(DP_AclState *acls, DP_Message *msg)
+{
+    uint8_t user_id = message_user_id(msg);
+    if (acls->persistent.joined[user_id] == 0) {
+        acls->persistent.joined[user_id] = ++acls->persistent.join_counter;
+    }
+
+    uint8_t result = 0;
+    if (acls->persistent.lock_new_users && user_id != 0
+        && !DP_acl_state_is_op(acls, user_id)) {
+        DP_user_bit_set(acls->users.locked, user_id);
+        result = DP_ACL_STATE_CHANGE_USERS_BIT;
+    }
+
+    DP_MsgJoin *mj = DP_msg_join_cast(msg);
+    if (DP_msg_join_flags(mj) & DP_MSG_JOIN_FLAGS_AUTH) {
+        DP_user_bit_set(acls->users.authenticated, user_id);
+        result = DP_ACL_STATE_CHANGE_USERS_BIT;
+    }
+    return result;
+}
+
+static uint8_t orphan_heir_id(DP_AclState *acls, uint8_t user_id)
+{
+    uint8_t heir_id = acls->persistent.orphan_heir_id;
+    bool can_inherit =
+        acls->persistent.orphan_policy == DP_ACL_ORPHAN_POLICY_TRANSFER
+        && heir_id != 0 && heir_id != user_id
+        && acls->persistent.joined[heir_id] != 0;
+    return can_inherit ? heir_id : 0;
+}
+
+// Only layers that still exist get orphaned, since they're the only ones
+// anyone could edit. The layers are found through the live layers.
+static uint8_t orphan_layers(DP_AclState *acls, uint8_t user_id)
+{
+    if (acls->persistent.orphan_policy == DP_ACL_ORPHAN_POLICY_IGNORE
+        || user_id == 0) {
+        return 0;
+    }
+
+    uint8_t heir_id = orphan_heir_id(acls, user_id);
+    uint8_t result = 0;
+    DP_LiveLayerEntry *entry, *tmp;
+    HASH_ITER(hh, acls->live_layers, entry, tmp) {
+        int layer_id = entry->layer_id;
+        if (DP_acl_state_layer_owner(acls, layer_id) == user_id) {
+            set_layer_owner(acls, layer_id, heir_id);
+            result = DP_ACL_STATE_CHANGE_LAYERS_BIT;
+        }
+    }
+    return result;
+}
+
+static uint8_t handle_leave(DP_AclState *acls, DP_Message *msg)
+{
+    uint8_t user_id = message_user_id(msg);
+    acls->persistent.joined[user_id] = 0;
+    DP_UserBits previous_operators;
+    memcpy(previous_operators, acls->users.operators, sizeof(DP_UserBits));
+    DP_user_bit_unset(acls->users.operators, user_id);
+    DP_user_bit_unset(acls->users.trusted, user_id);
+    DP_user_bit_unset(acls->users.authenticated, user_id);
+    DP_user_bit_unset(acls->users.locked, user_id);
+    DP_user_bit_unset(acls->users.cosmetic_muted, user_id);
+    apply_operator_policy(acls, previous_operators);
+    // TODO remove layer locks
+    return DP_ACL_STATE_CHANGE_USERS_BIT | orphan_layers(acls, user_id);
+}
+
+static uint8_t handle_session_owner(DP_AclState *acls, DP_Message *msg)
+{
+    DP_MsgSessionOwner *mso = DP_msg_session_owner_cast(msg);
+    int count;
+    const uint8_t *user_ids = DP_msg_session_owner_users(mso, &count);
+    DP_UserBits previous_operators;
+    memcpy(previous_operators, acls->users.operators, sizeof(DP_UserBits));
+    DP_user_bits_replace(acls->users.operators, count, user_ids);
+    apply_operator_policy(acls, previous_operators);
+    return DP_ACL_STATE_CHANGE_USERS_BIT;
+}
+
+static uint8_t handle_trusted_users(DP_AclState *acls, DP_Message *msg)
+{
+    DP_MsgTrustedUsers *mtu = DP_msg_trusted_users_cast(msg);
+    int count;
+    const uint8_t *user_ids = DP_msg_trusted_users_users(mtu, &count);
+    DP_user_bits_replace(acls->users.trusted, count, user_ids);
+    return DP_ACL_STATE_CHANGE_USERS_BIT;
+}
+
+static uint8_t handle_internal(DP_AclState *acls, DP_Message *msg)
+{
+    DP_MsgInternal *mi = DP_message_internal(msg);
+    DP_MsgInternalType type = DP_msg_internal_type(mi);
+    bool is_reset = type == DP_MSG_INTERNAL_TYPE_RESET
+                 || type == DP_MSG_INTERNAL_TYPE_RESET_TO_STATE;
+    if (is_reset) {
+        clear_layers(acls);
+        clear_live_layers(acls);
+        clear_annotations(acls);
+        acls->have_default_layer_acl = false;
+        acls->users.all_locked = false;
+        acls->users.locked_categories = 0;
+        memset_userbits(acls->users.locked, 0);
+        memset_userbits(acls->users.cosmetic_muted, 0);
+        acls->canvas_width = 0;
+        acls->canvas_height = 0;
+        set_feature_tiers_unfrozen(acls, null_feature_tiers());
+        return DP_ACL_STATE_CHANGE_MASK;
+    }
+    else {
+        return 0;
+    }
+}
+
+static uint8_t handle_user_acl(DP_AclState *acls, DP_Message *msg,
+                               bool override)
+{
+    if (override || check_op(acls, message_user_id(msg))) {
+        DP_MsgUserAcl *mua = DP_msg_user_acl_cast(msg);
+        int count;
+        const uint8_t *user_ids = DP_msg_user_acl_users(mua, &count);
+        DP_user_bits_replace(acls->users.locked, count, user_ids);
+        return DP_ACL_STATE_CHANGE_USERS_BIT;
+    }
+    else {
+        return DP_ACL_STATE_FILTERED_BIT;
+    }
+}
+
+static uint8_t handle_cosmetic_mute(DP_AclState *acls, DP_Message *msg,
+                                    bool override)
+{
+    if (override || check_op(acls, message_user_id(msg))) {
+        DP_MsgCosmeticMute *mcm = DP_msg_cosmetic_mute_cast(msg);
+        uint8_t target_id = DP_msg_cosmetic_mute_user(mcm);
+        bool mute = DP_msg_cosmetic_mute_mute(mcm);
+        if (DP_user_bit_get(acls->users.cosmetic_muted, target_id) == mute) {
+            return 0;
+        }
+        else if (mute) {
+            DP_user_bit_set(acls->users.cosmetic_muted, target_id);
+        }
+        else {
+            DP_user_bit_unset(acls->users.cosmetic_muted, target_id);
+        }
+        return DP_ACL_STATE_CHANGE_USERS_BIT;
+    }
+    else {
+        return DP_ACL_STATE_FILTERED_BIT;
+    }
+}
+
+static uint8_t handle_session_lock_categories(DP_AclState *acls,
+                                              DP_Message *msg, bool override)
+{
+    if (override || check_op(acls, message_user_id(msg))) {
+        DP_MsgSessionLockCategories *mslc =
+            DP_msg_session_lock_categories_cast(msg);
+        return DP_acl_state_lock_categories(
+            acls, DP_msg_session_lock_categories_categories(mslc));
+    }
+    else {
+        return DP_ACL_STATE_FILTERED_BIT;
+    }
+}
+
+// Only applies to laser trails, pointer movements and markers, which don't
+// affect the canvas.
+static bool check_cosmetic_unmuted(DP_AclState *acls, uint8_t user_id)
+{
+    return !DP_user_bit_get(acls->users.cosmetic_muted, user_id)
+        || reject(acls, DP_ACL_REJECTION_COSMETIC_MUTED);
+}
+
+static bool check_edit_layer(DP_AclState *acls, uint8_t user_id, int layer_id)
+{
+    if (DP_acl_state_can_use_feature(acls, DP_FEATURE_EDIT_LAYERS, user_id)) {
+        return true;
+    }
+    else if (DP_acl_state_can_use_feature(acls, DP_FEATURE_OWN_LAYERS,
+                                          user_id)) {
+        return DP_acl_state_layer_owner(acls, layer_id) == user_id
+            || reject(acls, DP_ACL_REJECTION_NOT_OWNER);
+    }
+    else {
+        return reject(acls, DP_ACL_REJECTION_FEATURE_TIER);
+    }
+}
+
+// Renaming, reconfiguring, moving or deleting a layer changes it too.
+static bool check_edit_mutable_layer(DP_AclState *acls, uint8_t user_id,
+                                     int layer_id)
+{
+    return check_layer_mutable(acls, layer_id)
+        && check_edit_layer(acls, user_id, layer_id);
+}
+
+// Immutability is meant to protect a layer from operators as well, so it
+// takes one to change the ACL of an immutable layer or to make one immutable.
+static bool check_edit_layer_acl(DP_AclState *acls, uint8_t user_id,
+                                 int layer_id, bool immutable)
+{
+    return immutable || DP_acl_state_layer_immutable(acls, layer_id)
+             ? check_op(acls, user_id)
+             : check_edit_layer(acls, user_id, layer_id);
+}
+
+static void init_layer_acl(DP_LayerAcl *l, uint8_t flags, int exclusive_count,
+                           const uint8_t *exclusive)
+{
+    l->locked = flags & DP_ACL_ALL_LOCKED_BIT;
+    l->alpha_locked = flags & DP_ACL_ALPHA_LOCKED_BIT;
+    l->immutable = flags & DP_ACL_IMMUTABLE_BIT;
     l->tier = DP_min_uint8(flags & DP_ACCESS_TIER_MASK, DP_ACCESS_TIER_GUEST);
 
-    // If no exclusive user ids are given, all users are allowed to use this.
-    // To lock it for all users, the locked flag needs to be set instead.
-    if (exclusive_count == 0) {
-        memset_userbits(l->exclusive, 0xff);
+    // If no exclusive user ids are given, all users are allowed to use this.
+    // To lock it for all users, the locked flag needs to be set instead.
+    if (exclusive_count == 0) {
+        memset_userbits(l->exclusive, 0xff);
+    }
+    else {
+        DP_user_bits_replace(l->exclusive, exclusive_count, exclusive);
+    }
+}
+
+static DP_LayerAclEntry *add_layer_acl_entry(DP_AclState *acls, int layer_id)
+{
+    DP_LayerAclEntry *entry = DP_malloc(sizeof(*entry));
+    entry->layer_id = layer_id;
+    set_layer_acl_effective(acls, entry);
+    HASH_ADD_INT(acls->layers, layer_id, entry);
+    return entry;
+}
+
+static void set_layer_acl(DP_AclState *acls, int layer_id,
+                          DP_LayerAclEntry *entry, uint8_t flags,
+                          int exclusive_count, const uint8_t *exclusive)
+{
+    if (!entry) {
+        entry = add_layer_acl_entry(acls, layer_id);
+    }
+    init_layer_acl(&entry->layer_acl, flags, exclusive_count, exclusive);
+    set_layer_acl_effective(acls, entry);
+    update_any_layer_locked(acls);
+}
+
+static uint8_t handle_layer_acl_session_lock(DP_AclState *acls,
+                                             DP_MsgLayerAcl *mla,
+                                             uint8_t user_id, bool override)
+{
+    if (override || check_op(acls, user_id)) {
+        uint8_t flags = DP_msg_layer_acl_flags(mla);
+        bool lock = flags & DP_ACL_ALL_LOCKED_BIT;
+        if (acls->users.all_locked == lock) {
+            return 0;
+        }
+        else {
+            acls->users.all_locked = lock;
+            return DP_ACL_STATE_CHANGE_USERS_BIT;
+        }
+    }
+    else {
+        return DP_ACL_STATE_FILTERED_BIT;
+    }
+}
+
+static uint8_t handle_layer_acl_default(DP_AclState *acls,
+                                        DP_MsgLayerAcl *mla, uint8_t user_id,
+                                        bool override)
+{
+    if (override || check_op(acls, user_id)) {
+        // New layers can't start out immutable, they'd be useless.
+        uint8_t flags =
+            DP_msg_layer_acl_flags(mla) & (uint8_t)~DP_ACL_IMMUTABLE_BIT;
+        int exclusive_count;
+        const uint8_t *exclusive =
+            DP_msg_layer_acl_exclusive(mla, &exclusive_count);
+
+        if (flags == DP_ACCESS_TIER_GUEST && exclusive_count == 0) {
+            if (acls->have_default_layer_acl) {
+                acls->have_default_layer_acl = false;
+                return DP_ACL_STATE_CHANGE_LAYERS_BIT;
+            }
+            else {
+                return 0;
+            }
+        }
+        else {
+            acls->have_default_layer_acl = true;
+            init_layer_acl(&acls->default_layer_acl, flags, exclusive_count,
+                           exclusive);
+            return DP_ACL_STATE_CHANGE_LAYERS_BIT;
+        }
+    }
+    else {
+        return DP_ACL_STATE_FILTERED_BIT;
+    }
+}
+
+static uint8_t handle_layer_acl_layer(DP_AclState *acls, DP_MsgLayerAcl *mla,
+                                      uint8_t user_id, int layer_id,
+                                      bool override)
+{
+    uint8_t flags = DP_msg_layer_acl_flags(mla);
+    if (override
+        || check_edit_layer_acl(acls, user_id, layer_id,
+                                flags & DP_ACL_IMMUTABLE_BIT)) {
+        int exclusive_count;
+        const uint8_t *exclusive =
+            DP_msg_layer_acl_exclusive(mla, &exclusive_count);
+
+        DP_LayerAclEntry *entry;
+        HASH_FIND_INT(acls->layers, &layer_id, entry);
+
+        if (flags == DP_ACCESS_TIER_GUEST && exclusive_count == 0) {
+            if (entry) {
+                HASH_DEL(acls->layers, entry);
+                DP_free(entry);
+                update_any_layer_locked(acls);
+                return DP_ACL_STATE_CHANGE_LAYERS_BIT;
+            }
+            else {
+                return 0;
+            }
+        }
+        else {
+            set_layer_acl(acls, layer_id, entry, flags, exclusive_count,
+                          exclusive);
+            return DP_ACL_STATE_CHANGE_LAYERS_BIT;
+        }
+    }
+    else {
+        return DP_ACL_STATE_FILTERED_BIT;
+    }
+}
+
+static uint8_t handle_layer_acl(DP_AclState *acls, DP_Message *msg,
+                                bool override)
+{
+    uint8_t user_id = message_user_id(msg);
+    DP_MsgLayerAcl *mla = DP_msg_layer_acl_cast(msg);
+    int layer_id = DP_msg_layer_acl_id(mla);
+    // Special case: layer 0 means lock or unlock the whole session.
+    if (layer_id == 0) {
+        return handle_layer_acl_session_lock(acls, mla, user_id, override);
+    }
+    else if (layer_id == DP_ACL_DEFAULT_LAYER_ID) {
+        return handle_layer_acl_default(acls, mla, user_id, override);
+    }
+    else {
+        return handle_layer_acl_layer(acls, mla, user_id, layer_id, override);
+    }
+}
+
+static bool user_bits_none_set(const uint8_t *users)
+{
+    for (size_t i = 0; i < sizeof(DP_UserBits); ++i) {
+        if (users[i] != 0) {
+            return false;
+        }
+    }
+    return true;
+}
+
+static bool change_layer_exclusive(DP_LayerAcl *l, uint8_t user_id, bool grant)
+{
+    // All bits being set means there's no exclusive list.
+    bool everyone = user_bits_all_set(l->exclusive);
+    if (grant) {
+        if (everyone) {
+            DP_user_bits_replace(l->exclusive, 1, &user_id);
+            return true;
+        }
+        else if (!DP_user_bit_get(l->exclusive, user_id)) {
+            DP_user_bit_set(l->exclusive, user_id);
+            return true;
+        }
+    }
+    else if (!everyone && DP_user_bit_get(l->exclusive, user_id)) {
+        DP_user_bit_unset(l->exclusive, user_id);
+        if (user_bits_none_set(l->exclusive)) {
+            memset_userbits(l->exclusive, 0xff);
+        }
+        return true;
+    }
+    return false;
+}
+
+static uint8_t handle_layer_acl_exclusive(DP_AclState *acls, DP_Message *msg,
+                                          bool override)
+{
+    uint8_t user_id = message_user_id(msg);
+    DP_MsgLayerAclExclusive *mlae = DP_msg_layer_acl_exclusive_cast(msg);
+    int layer_id = DP_msg_layer_acl_exclusive_id(mlae);
+    if (layer_id == 0 || layer_id == DP_ACL_DEFAULT_LAYER_ID) {
+        return filter_because(acls, DP_ACL_REJECTION_INVALID);
+    }
+    else if (override || check_edit_layer_acl(acls, user_id, layer_id, false)) {
+        uint8_t target_id = DP_msg_layer_acl_exclusive_user(mlae);
+        bool grant = DP_msg_layer_acl_exclusive_grant(mlae);
+        DP_LayerAclEntry *entry;
+        HASH_FIND_INT(acls->layers, &layer_id, entry);
+        if (entry) {
+            DP_LayerAcl *l = &entry->layer_acl;
+            if (!change_layer_exclusive(l, target_id, grant)) {
+                return 0;
+            }
+            set_layer_acl_effective(acls, entry);
+            // Remove the entry if it doesn't restrict anything anymore.
+            bool unrestricted = !l->locked && !l->alpha_locked
+                             && !l->immutable && l->tier == DP_ACCESS_TIER_GUEST
+                             && user_bits_all_set(l->exclusive);
+            if (unrestricted) {
+                HASH_DEL(acls->layers, entry);
+                DP_free(entry);
+            }
+            update_any_layer_locked(acls);
+            return DP_ACL_STATE_CHANGE_LAYERS_BIT;
+        }
+        else if (grant) {
+            set_layer_acl(acls, layer_id, NULL, DP_ACCESS_TIER_GUEST, 1,
+                          &target_id);
+            return DP_ACL_STATE_CHANGE_LAYERS_BIT;
+        }
+        else {
+            return 0;
+        }
+    }
+    else {
+        return DP_ACL_STATE_FILTERED_BIT;
+    }
+}
+
+static uint8_t handle_feature_access_levels(DP_AclState *acls, DP_Message *msg,
+                                            bool override)
+{
+    if (override || check_op(acls, message_user_id(msg))) {
+        DP_MsgFeatureAccessLevels *mfal =
+            DP_msg_feature_access_levels_cast(msg);
+        int feature_tiers_count;
+        const uint8_t *feature_tiers =
+            DP_msg_feature_access_levels_feature_tiers(mfal,
+                                                       &feature_tiers_count);
+
+        DP_FeatureTiers previous_feature = acls->feature;
+        int count = DP_min_int(feature_tiers_count, DP_FEATURE_COUNT);
+        for (int i = 0; i < count; ++i) {
+            uint8_t feature_tier = feature_tiers[i];
+            if (feature_tier != 255 && !acls->persistent.frozen_features[i]) {
+                acls->feature.tiers[i] =
+                    DP_min_uint8(feature_tier, DP_ACCESS_TIER_GUEST);
+            }
+        }
+
+        // Don't report a change if the tiers were just broadcast again.
+        return DP_feature_tiers_equal(&previous_feature, &acls->feature)
+                 ? 0
+                 : DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT;
+    }
+    else {
+        return DP_ACL_STATE_FILTERED_BIT;
+    }
+}
+
+static bool check_edit_any_or_own_layers(DP_AclState *acls, uint8_t user_id)
+{
+    return DP_acl_state_can_use_feature(acls, DP_FEATURE_EDIT_LAYERS, user_id)
+        || DP_acl_state_can_use_feature(acls, DP_FEATURE_OWN_LAYERS, user_id)
+        || reject(acls, DP_ACL_REJECTION_FEATURE_TIER);
+}
+
+static bool check_canvas_dimension(long long dimension, int max_dimension)
+{
+    return dimension
+        <= (max_dimension == 0 ? DP_ACL_MAX_CANVAS_DIMENSION : max_dimension);
+}
+
+static bool handle_canvas_resize(DP_AclState *acls, DP_Message *msg,
+                                 uint8_t user_id, bool override)
+{
+    DP_MsgCanvasResize *mcr = DP_msg_canvas_resize_cast(msg);
+    long long width = (long long)acls->canvas_width
+                    + DP_msg_canvas_resize_left(mcr)
+                    + DP_msg_canvas_resize_right(mcr);
+    long long height = (long long)acls->canvas_height
+                     + DP_msg_canvas_resize_top(mcr)
+                     + DP_msg_canvas_resize_bottom(mcr);
+    bool can_resize =
+        override
+        || (check_feature(acls, DP_FEATURE_RESIZE, user_id)
+            && ((check_canvas_dimension(width,
+                                        acls->persistent.max_canvas_width)
+                 && check_canvas_dimension(height,
+                                           acls->persistent.max_canvas_height))
+                || reject(acls, DP_ACL_REJECTION_CANVAS_TOO_LARGE)));
+    // Resizes to an invalid size fail on the canvas, so they don't count.
+    bool valid = width > 0 && height > 0
+              && width <= DP_ACL_MAX_CANVAS_DIMENSION
+              && height <= DP_ACL_MAX_CANVAS_DIMENSION;
+    if (can_resize && valid) {
+        acls->canvas_width = (int)width;
+        acls->canvas_height = (int)height;
+    }
+    return can_resize;
+}
+
+static bool check_layer_limit(DP_AclState *acls, int layer_id)
+{
+    int max_layers = acls->persistent.max_layers;
+    if (max_layers == 0 || DP_acl_state_layer_count(acls) < max_layers) {
+        return true;
+    }
+    else {
+        // Recreating an existing layer fails anyway, so let that through.
+        DP_LiveLayerEntry *entry;
+        HASH_FIND_INT(acls->live_layers, &layer_id, entry);
+        return entry || reject(acls, DP_ACL_REJECTION_LAYER_LIMIT);
+    }
+}
+
+static bool handle_layer_create(DP_AclState *acls, int layer_id,
+                                uint8_t user_id, bool override)
+{
+    // That id is reserved for setting the default layer ACL, a layer with it
+    // couldn't be locked or unlocked. The paint engine rejects it too.
+    if (layer_id == DP_ACL_DEFAULT_LAYER_ID) {
+        return reject(acls, DP_ACL_REJECTION_INVALID);
+    }
+    else if (override) {
+        return true;
+    }
+    // Only operators can create layers under a different owner.
+    return check_owner_or_op(acls, user_id, layer_id)
+        && check_edit_any_or_own_layers(acls, user_id)
+        && check_layer_limit(acls, layer_id);
+}
+
+static bool handle_layer_tree_move(DP_AclState *acls, DP_MsgLayerTreeMove *mltm,
+                                   uint8_t user_id, bool override)
+{
+    if (override) {
+        return true;
+    }
+    // Checking if the user is allowed to insert into the root is redundant,
+    // since if they're allowed to edit their own layer they'll also have that
+    // feature, but it's left in because it matches the idea behind it. Layer
+    // access control needs some rethinking anyway, it's too coarse.
+    int layer_id = DP_msg_layer_tree_move_layer(mltm);
+    int parent_id = DP_msg_layer_tree_move_parent(mltm);
+    return check_edit_mutable_layer(acls, user_id, layer_id)
+        && (parent_id == 0 ? check_edit_any_or_own_layers(acls, user_id)
+                           : check_edit_layer(acls, user_id, layer_id));
+}
+
+static bool check_delete_layer(DP_AclState *acls, uint8_t user_id,
+                               int layer_id, int merge_id)
+{
+    return check_edit_mutable_layer(acls, user_id, layer_id)
+        && (merge_id == 0 || check_layer_unlocked(acls, user_id, merge_id));
+}
+
+static bool handle_layer_delete(DP_AclState *acls, int layer_id, int merge_id,
+                                uint8_t user_id, bool override)
+{
+    if (override || check_delete_layer(acls, user_id, layer_id, merge_id)) {
+        DP_LayerAclEntry *entry;
+        HASH_FIND_INT(acls->layers, &layer_id, entry);
+        if (entry) {
+            HASH_DEL(acls->layers, entry);
+            DP_free(entry);
+            update_any_layer_locked(acls);
+        }
+        return true; // Layer is gone, so no need to report a change for it.
+    }
+    else {
+        return false;
+    }
+}
+
+static bool check_annotation_limit(DP_AclState *acls, int annotation_id)
+{
+    int max_annotations = acls->persistent.max_annotations;
+    if (max_annotations == 0
+        || DP_acl_state_annotation_count(acls) < max_annotations) {
+        return true;
+    }
+    else {
+        DP_AnnotationAclEntry *entry;
+        HASH_FIND_INT(acls->live_annotations, &annotation_id, entry);
+        return entry || reject(acls, DP_ACL_REJECTION_ANNOTATION_LIMIT);
+    }
+}
+
+static bool check_annotation_exists(DP_AclState *acls, int annotation_id)
+{
+    DP_AnnotationAclEntry *entry = NULL;
+    if (acls->persistent.strict) {
+        HASH_FIND_INT(acls->live_annotations, &annotation_id, entry);
+    }
+    return !acls->persistent.strict || entry
+        || reject(acls, DP_ACL_REJECTION_NO_SUCH_ANNOTATION);
+}
+
+static bool handle_annotation_create(DP_AclState *acls, DP_Message *msg,
+                                     uint8_t user_id, bool override)
+{
+    DP_MsgAnnotationCreate *mac = DP_msg_annotation_create_cast(msg);
+    int annotation_id = DP_msg_annotation_create_id(mac);
+    // Only operators can create annotations under a different owner, same
+    // as with layers. The prefix is checked first so that a spoofed one
+    // gets reported as such, rather than as a lack of permissions.
+    bool can_create =
+        override
+        || (check_owner_or_op(acls, user_id, annotation_id)
+            && check_feature(acls, DP_FEATURE_CREATE_ANNOTATION, user_id)
+            && check_annotation_limit(acls, annotation_id));
+    if (can_create) {
+        DP_AnnotationAclEntry *entry;
+        HASH_FIND_INT(acls->live_annotations, &annotation_id, entry);
+        if (!entry) {
+            entry = DP_malloc(sizeof(*entry));
+            entry->annotation_id = annotation_id;
+            HASH_ADD_INT(acls->live_annotations, annotation_id, entry);
+        }
+        return true;
+    }
+    else {
+        return false;
+    }
+}
+
+static bool handle_annotation_reshape(DP_AclState *acls, DP_Message *msg,
+                                      uint8_t user_id, bool override)
+{
+    if (override) {
+        return true;
+    }
+    DP_MsgAnnotationReshape *mar = DP_msg_annotation_reshape_cast(msg);
+    int annotation_id = DP_msg_annotation_reshape_id(mar);
+    return check_annotation_exists(acls, annotation_id)
+        && (owns_id(user_id, annotation_id)
+            || DP_acl_state_is_op(acls, user_id)
+            || !DP_acl_state_annotation_locked(acls, annotation_id)
+            || reject(acls, DP_ACL_REJECTION_ANNOTATION_LOCKED));
+}
+
+static bool handle_annotation_edit(DP_AclState *acls, DP_Message *msg,
+                                   uint8_t user_id, bool override,
+                                   uint8_t *out_changes)
+{
+    DP_MsgAnnotationEdit *mae = DP_msg_annotation_edit_cast(msg);
+    int annotation_id = DP_msg_annotation_edit_id(mae);
+    int max_length = acls->persistent.max_annotation_text_length;
+    bool can_edit =
+        override
+        || (check_annotation_exists(acls, annotation_id)
+            && check_owner_or_op(acls, user_id, annotation_id)
+            && (max_length == 0
+                || DP_msg_annotation_edit_text_len(mae)
+                       <= DP_int_to_size(max_length)
+                || reject(acls, DP_ACL_REJECTION_ANNOTATION_TOO_LONG)));
+    if (can_edit) {
+        DP_AnnotationAclEntry *entry;
+        HASH_FIND_INT(acls->annotations, &annotation_id, entry);
+        bool protect = DP_msg_annotation_edit_flags(mae)
+                     & DP_MSG_ANNOTATION_EDIT_FLAGS_PROTECT;
+        if (entry && !protect) {
+            HASH_DEL(acls->annotations, entry);
+            DP_free(entry);
+            *out_changes |= DP_ACL_STATE_CHANGE_ANNOTATIONS_BIT;
+        }
+        else if (!entry && protect) {
+            entry = DP_malloc(sizeof(*entry));
+            entry->annotation_id = annotation_id;
+            HASH_ADD_INT(acls->annotations, annotation_id, entry);
+            *out_changes |= DP_ACL_STATE_CHANGE_ANNOTATIONS_BIT;
+        }
+        return true;
+    }
+    else {
+        return false;
+    }
+}
+
+// The resulting length of the text isn't known here, so only the inserted
+// text is checked against the length limit.
+static bool handle_annotation_text_edit(DP_AclState *acls, DP_Message *msg,
+                                        uint8_t user_id, bool override)
+{
+    if (override) {
+        return true;
+    }
+    DP_MsgAnnotationTextEdit *mate = DP_msg_annotation_text_edit_cast(msg);
+    int annotation_id = DP_msg_annotation_text_edit_id(mate);
+    int max_length = acls->persistent.max_annotation_text_length;
+    return check_annotation_exists(acls, annotation_id)
+        && check_owner_or_op(acls, user_id, annotation_id)
+        && (max_length == 0
+            || DP_msg_annotation_text_edit_text_len(mate)
+                   <= DP_int_to_size(max_length)
+            || reject(acls, DP_ACL_REJECTION_ANNOTATION_TOO_LONG));
+}
+
+static bool handle_annotation_delete(DP_AclState *acls, DP_Message *msg,
+                                     uint8_t user_id, bool override,
+                                     uint8_t *out_changes)
+{
+    DP_MsgAnnotationDelete *mad = DP_msg_annotation_delete_cast(msg);
+    int annotation_id = DP_msg_annotation_delete_id(mad);
+    bool can_delete = override || owns_id(user_id, annotation_id)
+                   || DP_acl_state_is_op(acls, user_id)
+                   || !DP_acl_state_annotation_locked(acls, annotation_id)
+                   || reject(acls, DP_ACL_REJECTION_ANNOTATION_LOCKED);
+    if (can_delete) {
+        DP_AnnotationAclEntry *entry;
+        HASH_FIND_INT(acls->annotations, &annotation_id, entry);
+        if (entry) {
+            HASH_DEL(acls->annotations, entry);
+            DP_free(entry);
+            *out_changes |= DP_ACL_STATE_CHANGE_ANNOTATIONS_BIT;
+        }
+        HASH_FIND_INT(acls->live_annotations, &annotation_id, entry);
+        if (entry) {
+            HASH_DEL(acls->live_annotations, entry);
+            DP_free(entry);
+        }
+        return true;
+    }
+    else {
+        return false;
+    }
+}
+
+static bool handle_move(DP_AclState *acls, uint8_t user_id, bool override,
+                        int source_id, int target_id)
+{
+    if (override) {
+        return true;
+    }
+    return check_feature(acls, DP_FEATURE_REGION_MOVE, user_id)
+        && check_layer_unlocked(acls, user_id, source_id)
+        && check_layer_unlocked(acls, user_id, target_id);
+}
+
+static bool handle_move_region(DP_AclState *acls, DP_Message *msg,
+                               uint8_t user_id, bool override)
+{
+    DP_MsgMoveRegion *mmr = DP_message_internal(msg);
+    int layer_id = DP_msg_move_region_layer(mmr);
+    return handle_move(acls, user_id, override, layer_id, layer_id);
+}
+
+static bool handle_move_rect(DP_AclState *acls, DP_Message *msg,
+                             uint8_t user_id, bool override)
+{
+    DP_MsgMoveRect *mmr = DP_message_internal(msg);
+    return handle_move(acls, user_id, override, DP_msg_move_rect_source(mmr),
+                       DP_msg_move_rect_layer(mmr));
+}
+
+static bool handle_transform_region(DP_AclState *acls, DP_Message *msg,
+                                    uint8_t user_id, bool override)
+{
+    DP_MsgTransformRegion *mtr = DP_message_internal(msg);
+    return handle_move(acls, user_id, override,
+                       DP_msg_transform_region_source(mtr),
+                       DP_msg_transform_region_layer(mtr));
+}
+
+static bool handle_set_metadata_int(DP_AclState *acls, DP_Message *msg,
+                                    uint8_t user_id, bool override)
+{
+    if (override) {
+        return true;
+    }
+    DP_MsgSetMetadataInt *msmi = DP_msg_set_metadata_int_cast(msg);
+    DP_Feature feature;
+    switch (DP_msg_set_metadata_int_field(msmi)) {
+    case DP_MSG_SET_METADATA_INT_FIELD_FRAMERATE:
+    case DP_MSG_SET_METADATA_INT_FIELD_FRAME_COUNT:
+        feature = DP_FEATURE_TIMELINE;
+        break;
+    default:
+        feature = DP_FEATURE_METADATA;
+        break;
+    }
+    return check_feature(acls, feature, user_id);
+}
+
+static bool handle_track_create(DP_AclState *acls, int track_id,
+                                uint8_t user_id, bool override)
+{
+    if (override) {
+        return true;
+    }
+    // Only operators can create tracks under a different owner.
+    return check_owner_or_op(acls, user_id, track_id)
+        && check_feature(acls, DP_FEATURE_TIMELINE, user_id);
+}
+
+static bool handle_command_message(DP_AclState *acls, DP_Message *msg,
+                                   DP_MessageType type, uint8_t user_id,
+                                   bool override, uint8_t *out_changes)
+{
+    switch (type) {
+    case DP_MSG_CANVAS_RESIZE:
+        return handle_canvas_resize(acls, msg, user_id, override);
+    case DP_MSG_LAYER_CREATE:
+        return handle_layer_create(
+            acls, DP_msg_layer_create_id(DP_msg_layer_create_cast(msg)),
+            user_id, override);
+    case DP_MSG_LAYER_ATTRIBUTES:
+        return override
+            || check_edit_mutable_layer(acls, user_id,
+                                        DP_msg_layer_attributes_id(
+                                            DP_msg_layer_attributes_cast(msg)));
+    case DP_MSG_LAYER_RETITLE:
+        return override
+            || check_edit_mutable_layer(
+                   acls, user_id,
+                   DP_msg_layer_retitle_id(DP_msg_layer_retitle_cast(msg)));
+    case DP_MSG_LAYER_ORDER:
+        // Reordering moves everyone's layers, owning some isn't enough.
+        return override
+            || check_feature(acls, DP_FEATURE_EDIT_LAYERS, user_id);
+    case DP_MSG_LAYER_ORDER_MOVE:
+        // Moving a single layer still shifts the ones around it.
+        return override
+            || check_feature(acls, DP_FEATURE_EDIT_LAYERS, user_id);
+    case DP_MSG_LAYER_DELETE: {
+        DP_MsgLayerDelete *mld = DP_msg_layer_delete_cast(msg);
+        return handle_layer_delete(acls, DP_msg_layer_delete_id(mld), 0,
+                                   user_id, override);
+    }
+    case DP_MSG_LAYER_VISIBILITY:
+        return reject(acls, DP_ACL_REJECTION_INVALID); // Client-side.
+    case DP_MSG_PUT_IMAGE: {
+        DP_MsgPutImage *mpi = DP_message_internal(msg);
+        return override
+            // Compatibility hack: local match command disguised as put image.
+            || DP_msg_put_image_mode(mpi) == DP_BLEND_MODE_COMPAT_LOCAL_MATCH
+            || (check_feature(acls, DP_FEATURE_PUT_IMAGE, user_id)
+                && check_layer_paintable(acls, user_id,
+                                         DP_msg_put_image_layer(mpi),
+                                         DP_msg_put_image_mode(mpi)));
+    }
+    case DP_MSG_FILL_RECT: {
+        DP_MsgFillRect *mfr = DP_msg_fill_rect_cast(msg);
+        return override
+            || (check_feature(acls, DP_FEATURE_PUT_IMAGE, user_id)
+                && check_layer_paintable(acls, user_id,
+                                         DP_msg_fill_rect_layer(mfr),
+                                         DP_msg_fill_rect_mode(mfr)));
+    }
+    case DP_MSG_ANNOTATION_CREATE:
+        return handle_annotation_create(acls, msg, user_id, override);
+    case DP_MSG_ANNOTATION_RESHAPE:
+        return handle_annotation_reshape(acls, msg, user_id, override);
+    case DP_MSG_ANNOTATION_EDIT:
+        return handle_annotation_edit(acls, msg, user_id, override,
+                                      out_changes);
+    case DP_MSG_ANNOTATION_TEXT_EDIT:
+        return handle_annotation_text_edit(acls, msg, user_id, override);
+    case DP_MSG_ANNOTATION_DELETE:
+        return handle_annotation_delete(acls, msg, user_id, override,
+                                        out_changes);
+    case DP_MSG_MOVE_REGION:
+        return handle_move_region(acls, msg, user_id, override);
+    case DP_MSG_PUT_TILE:
+        return override
+            || (check_op(acls, user_id)
+                && check_layer_mutable(
+                    acls, DP_msg_put_tile_layer(DP_msg_put_tile_cast(msg))));
+    case DP_MSG_CANVAS_BACKGROUND:
+        return override
+            || check_feature(acls, DP_FEATURE_BACKGROUND, user_id);
+    case DP_MSG_DRAW_DABS_CLASSIC: {
+        DP_MsgDrawDabsClassic *mddc = DP_msg_draw_dabs_classic_cast(msg);
+        return override
+            || (check_feature(acls, DP_FEATURE_CLASSIC_BRUSH, user_id)
+                && check_layer_paintable(acls, user_id,
+                                         DP_msg_draw_dabs_classic_layer(mddc),
+                                         DP_msg_draw_dabs_classic_mode(mddc)));
+    }
+    case DP_MSG_DRAW_DABS_PIXEL:
+    case DP_MSG_DRAW_DABS_PIXEL_SQUARE: {
+        DP_MsgDrawDabsPixel *mddp = DP_message_internal(msg);
+        return override
+            || (check_feature(acls, DP_FEATURE_PIXEL_BRUSH, user_id)
+                && check_layer_paintable(acls, user_id,
+                                         DP_msg_draw_dabs_pixel_layer(mddp),
+                                         DP_msg_draw_dabs_pixel_mode(mddp)));
+    }
+    case DP_MSG_DRAW_DABS_MYPAINT: {
+        DP_MsgDrawDabsMyPaint *mddmp = DP_msg_draw_dabs_mypaint_cast(msg);
+        return override
+            || (check_feature(acls, DP_FEATURE_MYPAINT, user_id)
+                && check_layer_paintable(
+                    acls, user_id, DP_msg_draw_dabs_mypaint_layer(mddmp),
+                    mypaint_dab_blend_mode(
+                        DP_msg_draw_dabs_mypaint_mode(mddmp))));
+    }
+    case DP_MSG_MOVE_RECT:
+        return handle_move_rect(acls, msg, user_id, override);
+    case DP_MSG_SET_METADATA_INT:
+        return handle_set_metadata_int(acls, msg, user_id, override);
+    case DP_MSG_LAYER_TREE_CREATE:
+        return handle_layer_create(
+            acls,
+            DP_msg_layer_tree_create_id(DP_msg_layer_tree_create_cast(msg)),
+            user_id, override);
+    case DP_MSG_LAYER_TREE_MOVE:
+        return handle_layer_tree_move(acls, DP_msg_layer_tree_move_cast(msg),
+                                      user_id, override);
+    case DP_MSG_LAYER_TREE_DELETE: {
+        DP_MsgLayerTreeDelete *mltd = DP_msg_layer_tree_delete_cast(msg);
+        return handle_layer_delete(acls, DP_msg_layer_tree_delete_id(mltd),
+                                   DP_msg_layer_tree_delete_merge_to(mltd),
+                                   user_id, override);
+    }
+    case DP_MSG_TRANSFORM_REGION:
+        return handle_transform_region(acls, msg, user_id, override);
+    case DP_MSG_TRACK_CREATE:
+        return handle_track_create(
+            acls, DP_msg_track_create_id(DP_msg_track_create_cast(msg)),
+            user_id, override);
+    case DP_MSG_TRACK_RETITLE:
+    case DP_MSG_TRACK_DELETE:
+    case DP_MSG_TRACK_ORDER:
+    case DP_MSG_KEY_FRAME_SET:
+    case DP_MSG_KEY_FRAME_RETITLE:
+    case DP_MSG_KEY_FRAME_LAYER_ATTRIBUTES:
+    case DP_MSG_KEY_FRAME_DELETE:
+        return override || check_feature(acls, DP_FEATURE_TIMELINE, user_id);
+    case DP_MSG_UNDO:
+        return override
+            || (check_feature(acls, DP_FEATURE_UNDO, user_id)
+                && check_undo_user(acls, DP_msg_undo_cast(msg), user_id));
+    default:
+        return true;
+    }
+}
+
+static bool set_governing_feature(DP_Feature feature, DP_Feature *out_feature)
+{
+    if (out_feature) {
+        *out_feature = feature;
+    }
+    return true;
+}
+
+bool DP_message_governing_feature(DP_Message *msg, DP_Feature *out_feature)
+{
+    DP_ASSERT(msg);
+    switch (DP_message_type(msg)) {
+    case DP_MSG_LASER_TRAIL:
+        return set_governing_feature(DP_FEATURE_LASER, out_feature);
+    case DP_MSG_CANVAS_RESIZE:
+        return set_governing_feature(DP_FEATURE_RESIZE, out_feature);
+    case DP_MSG_LAYER_CREATE:
+    case DP_MSG_LAYER_ATTRIBUTES:
+    case DP_MSG_LAYER_RETITLE:
+    case DP_MSG_LAYER_DELETE:
+    case DP_MSG_LAYER_TREE_CREATE:
+    case DP_MSG_LAYER_TREE_MOVE:
+    case DP_MSG_LAYER_TREE_DELETE:
+        return set_governing_feature(DP_FEATURE_OWN_LAYERS, out_feature);
+    case DP_MSG_LAYER_ORDER:
+    case DP_MSG_LAYER_ORDER_MOVE:
+        return set_governing_feature(DP_FEATURE_EDIT_LAYERS, out_feature);
+    case DP_MSG_PUT_IMAGE:
+    case DP_MSG_FILL_RECT:
+        return set_governing_feature(DP_FEATURE_PUT_IMAGE, out_feature);
+    case DP_MSG_ANNOTATION_CREATE:
+        return set_governing_feature(DP_FEATURE_CREATE_ANNOTATION, out_feature);
+    case DP_MSG_MOVE_REGION:
+    case DP_MSG_MOVE_RECT:
+    case DP_MSG_TRANSFORM_REGION:
+        return set_governing_feature(DP_FEATURE_REGION_MOVE, out_feature);
+    case DP_MSG_CANVAS_BACKGROUND:
+        return set_governing_feature(DP_FEATURE_BACKGROUND, out_feature);
+    case DP_MSG_DRAW_DABS_MYPAINT:
+        return set_governing_feature(DP_FEATURE_MYPAINT, out_feature);
+    case DP_MSG_DRAW_DABS_CLASSIC:
+        return set_governing_feature(DP_FEATURE_CLASSIC_BRUSH, out_feature);
+    case DP_MSG_DRAW_DABS_PIXEL:
+    case DP_MSG_DRAW_DABS_PIXEL_SQUARE:
+        return set_governing_feature(DP_FEATURE_PIXEL_BRUSH, out_feature);
+    case DP_MSG_SET_METADATA_INT:
+        switch (DP_msg_set_metadata_int_field(
+            DP_msg_set_metadata_int_cast(msg))) {
+        case DP_MSG_SET_METADATA_INT_FIELD_FRAMERATE:
+        case DP_MSG_SET_METADATA_INT_FIELD_FRAME_COUNT:
+            return set_governing_feature(DP_FEATURE_TIMELINE, out_feature);
+        default:
+            return set_governing_feature(DP_FEATURE_METADATA, out_feature);
+        }
+    case DP_MSG_TRACK_CREATE:
+    case DP_MSG_TRACK_RETITLE:
+    case DP_MSG_TRACK_DELETE:
+    case DP_MSG_TRACK_ORDER:
+    case DP_MSG_KEY_FRAME_SET:
+    case DP_MSG_KEY_FRAME_RETITLE:
+    case DP_MSG_KEY_FRAME_LAYER_ATTRIBUTES:
+    case DP_MSG_KEY_FRAME_DELETE:
+        return set_governing_feature(DP_FEATURE_TIMELINE, out_feature);
+    case DP_MSG_UNDO:
+        return set_governing_feature(DP_FEATURE_UNDO, out_feature);
+    default:
+        return false;
+    }
+}
+
+uint8_t DP_message_lock_category(DP_Message *msg)
+{
+    DP_ASSERT(msg);
+    switch (DP_message_type(msg)) {
+    case DP_MSG_PUT_IMAGE:
+    case DP_MSG_FILL_RECT:
+    case DP_MSG_MOVE_REGION:
+    case DP_MSG_MOVE_RECT:
+    case DP_MSG_TRANSFORM_REGION:
+    case DP_MSG_PUT_TILE:
+    case DP_MSG_DRAW_DABS_CLASSIC:
+    case DP_MSG_DRAW_DABS_PIXEL:
+    case DP_MSG_DRAW_DABS_PIXEL_SQUARE:
+    case DP_MSG_DRAW_DABS_MYPAINT:
+        return DP_ACL_LOCK_CATEGORY_DRAW;
+    case DP_MSG_LAYER_CREATE:
+    case DP_MSG_LAYER_ATTRIBUTES:
+    case DP_MSG_LAYER_RETITLE:
+    case DP_MSG_LAYER_ORDER:
+    case DP_MSG_LAYER_ORDER_MOVE:
+    case DP_MSG_LAYER_DELETE:
+    case DP_MSG_LAYER_TREE_CREATE:
+    case DP_MSG_LAYER_TREE_MOVE:
+    case DP_MSG_LAYER_TREE_DELETE:
+        return DP_ACL_LOCK_CATEGORY_LAYERS;
+    case DP_MSG_ANNOTATION_CREATE:
+    case DP_MSG_ANNOTATION_RESHAPE:
+    case DP_MSG_ANNOTATION_EDIT:
+    case DP_MSG_ANNOTATION_TEXT_EDIT:
+    case DP_MSG_ANNOTATION_DELETE:
+        return DP_ACL_LOCK_CATEGORY_ANNOTATIONS;
+    default:
+        return 0;
+    }
+}
+
+static int created_layer_id(DP_Message *msg, DP_MessageType type)
+{
+    switch (type) {
+    case DP_MSG_LAYER_CREATE:
+        return DP_msg_layer_create_id(DP_msg_layer_create_cast(msg));
+    case DP_MSG_LAYER_TREE_CREATE:
+        return DP_msg_layer_tree_create_id(DP_msg_layer_tree_create_cast(msg));
+    default:
+        return 0;
+    }
+}
+
+static uint8_t apply_default_layer_acl(DP_AclState *acls, DP_Message *msg,
+                                       DP_MessageType type)
+{
+    if (acls->have_default_layer_acl) {
+        int layer_id = created_layer_id(msg, type);
+        if (layer_id != 0) {
+            DP_LayerAclEntry *entry;
+            HASH_FIND_INT(acls->layers, &layer_id, entry);
+            if (!entry) {
+                entry = add_layer_acl_entry(acls, layer_id);
+            }
+            entry->layer_acl = acls->default_layer_acl;
+            set_layer_acl_effective(acls, entry);
+            update_any_layer_locked(acls);
+            return DP_ACL_STATE_CHANGE_LAYERS_BIT;
+        }
+    }
+    return 0;
+}
+
+static DP_LiveLayerEntry *search_live_layer(DP_AclState *acls, int layer_id)
+{
+    DP_LiveLayerEntry *entry;
+    HASH_FIND_INT(acls->live_layers, &layer_id, entry);
+    return entry;
+}
+
+static void add_live_layer(DP_AclState *acls, int layer_id, int parent_id)
+{
+    if (layer_id != 0 && !search_live_layer(acls, layer_id)) {
+        DP_LiveLayerEntry *entry = DP_malloc(sizeof(*entry));
+        entry->layer_id = layer_id;
+        entry->parent_id = parent_id;
+        entry->doomed = false;
+        HASH_ADD_INT(acls->live_layers, layer_id, entry);
+    }
+}
+
+static void add_live_tree_layer(DP_AclState *acls, DP_MsgLayerTreeCreate *mltc)
+{
+    int target_id = DP_msg_layer_tree_create_target(mltc);
+    int parent_id;
+    if (DP_msg_layer_tree_create_flags(mltc)
+        & DP_MSG_LAYER_TREE_CREATE_FLAGS_INTO) {
+        parent_id = target_id;
     }
     else {
-        DP_user_bits_replace(l->exclusive, exclusive_count, exclusive);
+        DP_LiveLayerEntry *target = search_live_layer(acls, target_id);
+        parent_id = target ? target->parent_id : 0;
+    }
+    add_live_layer(acls, DP_msg_layer_tree_create_id(mltc), parent_id);
+}
+
+static void move_live_layer(DP_AclState *acls, DP_MsgLayerTreeMove *mltm)
+{
+    DP_LiveLayerEntry *entry =
+        search_live_layer(acls, DP_msg_layer_tree_move_layer(mltm));
+    if (entry) {
+        entry->parent_id = DP_msg_layer_tree_move_parent(mltm);
     }
 }
 
-static uint8_t handle_layer_acl_session_lock(DP_AclState *acls,
-                                             DP_MsgLayerAcl *mla,
-                                             uint8_t user_id, bool override)
+static void forget_contributors(DP_AclState *acls, int layer_id)
 {
-    if (override || DP_acl_state_is_op(acls, user_id)) {
-        uint8_t flags = DP_msg_layer_acl_flags(mla);
-        bool lock = flags & DP_ACL_ALL_LOCKED_BIT;
-        if (acls->users.all_locked == lock) {
-            return 0;
-        }
-        else {
-            acls->users.all_locked = lock;
-            return DP_ACL_STATE_CHANGE_USERS_BIT;
-        }
-    }
-    else {
-        return DP_ACL_STATE_FILTERED_BIT;
+    DP_LayerContributorEntry *entry;
+    HASH_FIND_INT(acls->contributors, &layer_id, entry);
+    if (entry) {
+        HASH_DEL(acls->contributors, entry);
+        DP_free(entry);
     }
 }
 
-static uint8_t handle_layer_acl_layer(DP_AclState *acls, DP_MsgLayerAcl *mla,
-                                      uint8_t user_id, int layer_id,
-                                      bool override)
+static void forget_layer_owner(DP_AclState *acls, int layer_id)
 {
-    if (override || can_edit_layer(acls, user_id, layer_id)) {
-        uint8_t flags = DP_msg_layer_acl_flags(mla);
-        int exclusive_count;
-        const uint8_t *exclusive =
-            DP_msg_layer_acl_exclusive(mla, &exclusive_count);
+    DP_LayerOwnerEntry *entry = search_layer_owner(acls, layer_id);
+    if (entry) {
+        HASH_DEL(acls->layer_owners, entry);
+        DP_free(entry);
+    }
+}
 
-        DP_LayerAclEntry *entry;
-        HASH_FIND_INT(acls->layers, &layer_id, entry);
+static void remove_live_layer(DP_AclState *acls, int layer_id)
+{
+    DP_LiveLayerEntry *root = search_live_layer(acls, layer_id);
+    if (root) {
+        // Doom the layer and everything nested in it, then sweep them up.
+        root->doomed = true;
+        DP_LiveLayerEntry *entry, *tmp;
+        bool changed;
+        do {
+            changed = false;
+            HASH_ITER(hh, acls->live_layers, entry, tmp) {
+                if (!entry->doomed && entry->parent_id != 0) {
+                    DP_LiveLayerEntry *parent =
+                        search_live_layer(acls, entry->parent_id);
+                    if (parent && parent->doomed) {
+                        entry->doomed = true;
+                        changed = true;
+                    }
+                }
+            }
+        } while (changed);
 
-        if (flags == DP_ACCESS_TIER_GUEST && exclusive_count == 0) {
-            if (entry) {
-                HASH_DEL(acls->layers, entry);
+        HASH_ITER(hh, acls->live_layers, entry, tmp) {
+            if (entry->doomed) {
+                forget_contributors(acls, entry->layer_id);
+                forget_layer_owner(acls, entry->layer_id);
+                HASH_DEL(acls->live_layers, entry);
                 DP_free(entry);
-                return DP_ACL_STATE_CHANGE_LAYERS_BIT;
             }
-            else {
-                return 0;
-            }
-        }
-        else {
-            set_layer_acl(acls, layer_id, entry, flags, exclusive_count,
-                          exclusive);
-            return DP_ACL_STATE_CHANGE_LAYERS_BIT;
         }
     }
-    else {
-        return DP_ACL_STATE_FILTERED_BIT;
-    }
 }
 
-static uint8_t handle_layer_acl(DP_AclState *acls, DP_Message *msg,
-                                bool override)
+// Layers created implicitly by duplicating a group aren't tracked, since the
+// ids of those are only assigned when the canvas handles the message.
+static void update_live_layers(DP_AclState *acls, DP_Message *msg,
+                               DP_MessageType type)
 {
-    uint8_t user_id = message_user_id(msg);
-    DP_MsgLayerAcl *mla = DP_msg_layer_acl_cast(msg);
-    int layer_id = DP_msg_layer_acl_id(mla);
-    // Special case: layer 0 means lock or unlock the whole session.
-    if (layer_id == 0) {
-        return handle_layer_acl_session_lock(acls, mla, user_id, override);
-    }
-    else {
-        return handle_layer_acl_layer(acls, mla, user_id, layer_id, override);
+    switch (type) {
+    case DP_MSG_LAYER_CREATE:
+        add_live_layer(
+            acls, DP_msg_layer_create_id(DP_msg_layer_create_cast(msg)), 0);
+        break;
+    case DP_MSG_LAYER_TREE_CREATE:
+        add_live_tree_layer(acls, DP_msg_layer_tree_create_cast(msg));
+        break;
+    case DP_MSG_LAYER_TREE_MOVE:
+        move_live_layer(acls, DP_msg_layer_tree_move_cast(msg));
+        break;
+    case DP_MSG_LAYER_DELETE:
+        remove_live_layer(
+            acls, DP_msg_layer_delete_id(DP_msg_layer_delete_cast(msg)));
+        break;
+    case DP_MSG_LAYER_TREE_DELETE:
+        remove_live_layer(
+            acls,
+            DP_msg_layer_tree_delete_id(DP_msg_layer_tree_delete_cast(msg)));
+        break;
+    default:
+        break;
     }
 }
 
-static uint8_t handle_feature_access_levels(DP_AclState *acls, DP_Message *msg,
-                                            bool override)
+static int painted_layer_id(DP_Message *msg, DP_MessageType type)
 {
-    if (override || DP_acl_state_is_op(acls, message_user_id(msg))) {
-        DP_MsgFeatureAccessLevels *mfal =
-            DP_msg_feature_access_levels_cast(msg);
-        int feature_tiers_count;
-        const uint8_t *feature_tiers =
-            DP_msg_feature_access_levels_feature_tiers(mfal,
-                                                       &feature_tiers_count);
+    switch (type) {
+    case DP_MSG_PUT_IMAGE:
+        return DP_msg_put_image_layer(DP_msg_put_image_cast(msg));
+    case DP_MSG_FILL_RECT:
+        return DP_msg_fill_rect_layer(DP_msg_fill_rect_cast(msg));
+    case DP_MSG_DRAW_DABS_CLASSIC:
+        return DP_msg_draw_dabs_classic_layer(
+            DP_msg_draw_dabs_classic_cast(msg));
+    case DP_MSG_DRAW_DABS_PIXEL:
+    case DP_MSG_DRAW_DABS_PIXEL_SQUARE:
+        return DP_msg_draw_dabs_pixel_layer(DP_message_internal(msg));
+    case DP_MSG_DRAW_DABS_MYPAINT:
+        return DP_msg_draw_dabs_mypaint_layer(
+            DP_msg_draw_dabs_mypaint_cast(msg));
+    default:
+        return 0;
+    }
+}
 
-        int count = DP_min_int(feature_tiers_count, DP_FEATURE_COUNT);
-        for (int i = 0; i < count; ++i) {
-            uint8_t feature_tier = feature_tiers[i];
-            if (feature_tier != 255) {
-                acls->feature.tiers[i] =
-                    DP_min_uint8(feature_tier, DP_ACCESS_TIER_GUEST);
-            }
+static void record_contributor(DP_AclState *acls, DP_Message *msg,
+                               DP_MessageType type, uint8_t user_id)
+{
+    int layer_id = painted_layer_id(msg, type);
+    if (layer_id != 0) {
+        DP_LayerContributorEntry *entry;
+        HASH_FIND_INT(acls->contributors, &layer_id, entry);
+        if (!entry) {
+            entry = DP_malloc(sizeof(*entry));
+            entry->layer_id = layer_id;
+            memset(entry->users, 0, sizeof(entry->users));
+            HASH_ADD_INT(acls->contributors, layer_id, entry);
         }
+        DP_user_bit_set(entry->users, user_id);
+    }
+}
 
-        return DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT;
+static uint8_t handle_command(DP_AclState *acls, DP_Message *msg,
+                              DP_MessageType type, uint8_t user_id,
+                              bool override)
+{
+    uint8_t changes = 0;
+    if (handle_command_message(acls, msg, type, user_id, override, &changes)) {
+        update_live_layers(acls, msg, type);
+        if (acls->persistent.track_contributors) {
+            record_contributor(acls, msg, type, user_id);
+        }
+        return changes | apply_default_layer_acl(acls, msg, type);
     }
     else {
         return DP_ACL_STATE_FILTERED_BIT;
     }
 }
 
-static bool can_edit_any_or_own_layers(DP_AclState *acls, uint8_t user_id)
+static uint8_t handle_default_layer(DP_AclState *acls, DP_Message *msg,
+                                   bool override)
 {
-    return DP_acl_state_can_use_feature(acls, DP_FEATURE_EDIT_LAYERS, user_id)
-        || DP_acl_state_can_use_feature(acls, DP_FEATURE_OWN_LAYERS, user_id);
+    // Layer 0 unsets the default layer. In strict mode, anything else has to
+    // have been created in the message stream. Otherwise it's let through,
+    // even if it was deleted, since layers can also come back through undo
+    // or come from duplicated groups or a reset, none of which the live
+    // layers follow. Telling those apart from a stale id would mean replaying
+    // the canvas history here, so without strict mode this isn't checked.
+    int layer_id = DP_msg_default_layer_id(DP_msg_default_layer_cast(msg));
+    return filter_unless(override
+                         || (check_op(acls, message_user_id(msg))
+                             && (layer_id == 0
+                                 || check_layer_exists(acls, layer_id))));
 }
 
-static bool handle_layer_create(DP_AclState *acls, int layer_id,
-                                uint8_t user_id, bool override)
+static uint8_t handle_client_meta(DP_AclState *acls, DP_Message *msg,
+                                  bool override)
 {
-    if (override) {
-        return true;
-    }
-    // Only operators can create layers under a different owner.
-    bool can_create =
-        (owns_id(user_id, layer_id) || DP_acl_state_is_op(acls, user_id))
-        && can_edit_any_or_own_layers(acls, user_id);
-    return can_create;
+    // Users may only set their own presence information.
+    uint8_t target_id = DP_msg_client_meta_user(DP_msg_client_meta_cast(msg));
+    return filter_unless(override || target_id == message_user_id(msg)
+                         || reject(acls, DP_ACL_REJECTION_INVALID));
 }
 
-static bool handle_layer_tree_move(DP_AclState *acls, DP_MsgLayerTreeMove *mltm,
-                                   uint8_t user_id, bool override)
+static uint8_t handle_meta(DP_AclState *acls, DP_Message *msg,
+                           DP_MessageType type, bool override)
 {
-    if (override) {
-        return true;
+    switch (type) {
+    case DP_MSG_JOIN:
+    case DP_MSG_LEAVE:
+    case DP_MSG_SESSION_OWNER:
+    case DP_MSG_TRUSTED_USERS:
+    case DP_MSG_USER_ACL:
+    case DP_MSG_LAYER_ACL:
+    case DP_MSG_LAYER_ACL_EXCLUSIVE:
+    case DP_MSG_FEATURE_ACCESS_LEVELS:
+    case DP_MSG_COSMETIC_MUTE:
+    case DP_MSG_SESSION_LOCK_CATEGORIES:
+        // These may introduce restrictions, so local mode has to end.
+        acls->local_mode = false;
+        break;
+    default:
+        break;
+    }
+    switch (type) {
+    case DP_MSG_JOIN:
+        return handle_join(acls, msg);
+    case DP_MSG_LEAVE:
+        return handle_leave(acls, msg);
+    case DP_MSG_SESSION_OWNER:
+        return handle_session_owner(acls, msg);
+    case DP_MSG_TRUSTED_USERS:
+        return handle_trusted_users(acls, msg);
+    case DP_MSG_INTERNAL:
+        return handle_internal(acls, msg);
+    case DP_MSG_LASER_TRAIL:
+        return filter_unless(
+            override
+            || (check_cosmetic_unmuted(acls, message_user_id(msg))
+                && check_feature(acls, DP_FEATURE_LASER,
+                                 message_user_id(msg))));
+    case DP_MSG_MOVE_POINTER:
+    case DP_MSG_MARKER:
+        return filter_unless(
+            override || check_cosmetic_unmuted(acls, message_user_id(msg)));
+    case DP_MSG_USER_ACL:
+        return handle_user_acl(acls, msg, override);
+    case DP_MSG_LAYER_ACL:
+        return handle_layer_acl(acls, msg, override);
+    case DP_MSG_LAYER_ACL_EXCLUSIVE:
+        return handle_layer_acl_exclusive(acls, msg, override);
+    case DP_MSG_FEATURE_ACCESS_LEVELS:
+        return handle_feature_access_levels(acls, msg, override);
+    case DP_MSG_DEFAULT_LAYER:
+        return handle_default_layer(acls, msg, override);
+    case DP_MSG_CLIENT_META:
+        return handle_client_meta(acls, msg, override);
+    case DP_MSG_COSMETIC_MUTE:
+        return handle_cosmetic_mute(acls, msg, override);
+    case DP_MSG_SESSION_LOCK_CATEGORIES:
+        return handle_session_lock_categories(acls, msg, override);
+    case DP_MSG_FILTERED:
+        return filter_because(acls, DP_ACL_REJECTION_INVALID);
+    case DP_MSG_UNDO_DEPTH:
+        return filter_unless(override || check_op(acls, message_user_id(msg)));
+    case DP_MSG_LOCAL_CHANGE:
+        return filter_unless(override || message_user_id(msg) == 0
+                             || reject(acls, DP_ACL_REJECTION_INVALID));
+    default:
+        return 0;
     }
-    // Checking if the user is allowed to insert into the root is redundant,
-    // since if they're allowed to edit their own layer they'll also have that
-    // feature, but it's left in because it matches the idea behind it. Layer
-    // access control needs some rethinking anyway, it's too coarse.
-    int layer_id = DP_msg_layer_tree_move_layer(mltm);
-    int parent_id = DP_msg_layer_tree_move_parent(mltm);
-    return can_edit_layer(acls, user_id, layer_id)
-        && (parent_id == 0 ? can_edit_any_or_own_layers(acls, user_id)
-                           : can_edit_layer(acls, user_id, layer_id));
 }
 
-static bool can_delete_layer(DP_AclState *acls, uint8_t user_id, int layer_id,
-                             int merge_id)
+static int audit_target_id(DP_Message *msg, DP_MessageType type)
 {
-    return can_edit_layer(acls, user_id, layer_id)
-        && (merge_id == 0
-            || !DP_acl_state_layer_locked_for(acls, user_id, merge_id));
+    switch (type) {
+    case DP_MSG_LAYER_ACL:
+        return DP_msg_layer_acl_id(DP_msg_layer_acl_cast(msg));
+    case DP_MSG_LAYER_ACL_EXCLUSIVE:
+        return DP_msg_layer_acl_exclusive_id(
+            DP_msg_layer_acl_exclusive_cast(msg));
+    default:
+        return 0;
+    }
 }
 
-static bool handle_layer_delete(DP_AclState *acls, int layer_id, int merge_id,
-                                uint8_t user_id, bool override)
+static void record_audit(DP_AclState *acls, DP_Message *msg,
+                         DP_MessageType type, uint8_t result)
 {
-    if (override || can_delete_layer(acls, user_id, layer_id, merge_id)) {
-        DP_LayerAclEntry *entry;
-        HASH_FIND_INT(acls->layers, &layer_id, entry);
-        if (entry) {
-            HASH_DEL(acls->layers, entry);
-            DP_free(entry);
+    DP_AclAuditLog *audit = &acls->persistent.audit;
+    uint8_t changes = result & DP_ACL_STATE_CHANGE_MASK;
+    if (audit->capacity != 0 && !(result & DP_ACL_STATE_FILTERED_BIT)
+        && changes != 0) {
+        DP_AclAuditEntry *entry;
+        if (audit->count < audit->capacity) {
+            entry = audit_entry_at(audit, audit->count++);
         }
-        return true; // Layer is gone, so no need to report a change for it.
+        else {
+            // Full, overwrite the oldest entry.
+            entry = audit_entry_at(audit, 0);
+            audit->start = (audit->start + 1) % audit->capacity;
+        }
+        *entry = (DP_AclAuditEntry){(long long)time(NULL),
+                                    message_user_id(msg), (int)type,
+                                    audit_target_id(msg, type), changes};
     }
-    else {
-        return false;
+}
+
+static void record_last_change(DP_AclState *acls, DP_Message *msg,
+                               uint8_t result)
+{
+    if (!(result & DP_ACL_STATE_FILTERED_BIT)
+        && (result & DP_ACL_STATE_CHANGE_MASK) != 0) {
+        DP_message_decref_nullable(acls->last_change);
+        acls->last_change = DP_message_incref(msg);
     }
 }
 
-static bool handle_annotation_create(DP_AclState *acls, DP_Message *msg,
-                                     uint8_t user_id, bool override)
+static void bump_layer_acl_version_for(DP_AclState *acls, int layer_id)
 {
-    if (override) {
-        return true;
+    DP_LayerAclVersionEntry *entry;
+    HASH_FIND_INT(acls->layer_acl_versions, &layer_id, entry);
+    if (!entry) {
+        entry = DP_malloc(sizeof(*entry));
+        entry->layer_id = layer_id;
+        entry->version = 0;
+        HASH_ADD_INT(acls->layer_acl_versions, layer_id, entry);
     }
-    DP_MsgAnnotationCreate *mac = DP_msg_annotation_create_cast(msg);
-    int annotation_id = DP_msg_annotation_create_id(mac);
-    return DP_acl_state_can_use_feature(acls, DP_FEATURE_CREATE_ANNOTATION,
-                                        user_id)
-        && (owns_id(user_id, annotation_id)
-            || DP_acl_state_is_op(acls, user_id));
+    ++entry->version;
 }
 
-static bool handle_annotation_reshape(DP_AclState *acls, DP_Message *msg,
-                                      uint8_t user_id, bool override)
+static void bump_layer_acl_version(DP_AclState *acls, DP_Message *msg,
+                                   DP_MessageType type, uint8_t result)
 {
-    if (override) {
-        return true;
+    bool layer_acl_changed =
+        (type == DP_MSG_LAYER_ACL || type == DP_MSG_LAYER_ACL_EXCLUSIVE)
+        && !(result & DP_ACL_STATE_FILTERED_BIT)
+        && (result & DP_ACL_STATE_CHANGE_LAYERS_BIT);
+    if (layer_acl_changed) {
+        bump_layer_acl_version_for(acls, audit_target_id(msg, type));
     }
-    DP_MsgAnnotationReshape *mar = DP_msg_annotation_reshape_cast(msg);
-    int annotation_id = DP_msg_annotation_reshape_id(mar);
-    return owns_id(user_id, annotation_id) || DP_acl_state_is_op(acls, user_id)
-        || !DP_acl_state_annotation_locked(acls, annotation_id);
 }
 
-static bool handle_annotation_edit(DP_AclState *acls, DP_Message *msg,
-                                   uint8_t user_id, bool override)
+uint8_t DP_acl_state_handle(DP_AclState *acls, DP_Message *msg, bool override)
 {
-    DP_MsgAnnotationEdit *mae = DP_msg_annotation_edit_cast(msg);
-    int annotation_id = DP_msg_annotation_edit_id(mae);
-    bool can_edit = override || owns_id(user_id, annotation_id)
-                 || DP_acl_state_is_op(acls, user_id);
-    if (can_edit) {
-        DP_AnnotationAclEntry *entry;
-        HASH_FIND_INT(acls->annotations, &annotation_id, entry);
-        bool protect = DP_msg_annotation_edit_flags(mae)
-                     & DP_MSG_ANNOTATION_EDIT_FLAGS_PROTECT;
-        if (entry && !protect) {
-            HASH_DEL(acls->annotations, entry);
-            DP_free(entry);
+    DP_ASSERT(acls);
+    DP_ASSERT(msg);
+    acls->last_rejection = DP_ACL_REJECTION_NONE;
+    DP_MessageType type = DP_message_type(msg);
+    // The local authority can do anything, like an override.
+    if (is_local_authority(acls, message_user_id(msg))) {
+        override = true;
+    }
+    // Command messages (128 and up) need common handling.
+    if (type < 128) {
+        // System messages skip the permission checks, but still apply.
+        uint8_t result = handle_meta(acls, msg, type,
+                                     override || DP_message_is_system(msg));
+        record_audit(acls, msg, type, result);
+        record_last_change(acls, msg, result);
+        bump_layer_acl_version(acls, msg, type, result);
+        return result;
+    }
+    else if (acls->local_mode
+             && message_user_id(msg) == acls->persistent.local_user_id) {
+        // Nothing can get rejected, skip straight to the bookkeeping.
+        return handle_command(acls, msg, type, acls->persistent.local_user_id,
+                              true);
+    }
+    else if (!override && acls->users.all_locked) {
+        return filter_because(acls, DP_ACL_REJECTION_SESSION_LOCKED);
+    }
+    else if (!override
+             && (acls->users.locked_categories
+                 & DP_message_lock_category(msg))) {
+        return filter_because(acls, DP_ACL_REJECTION_CATEGORY_LOCKED);
+    }
+    else {
+        uint8_t user_id = message_user_id(msg);
+        if (override || user_id == 0
+            || !DP_user_bit_get(acls->users.locked, user_id)) {
+            return handle_command(acls, msg, type, user_id, override);
         }
-        else if (!entry && protect) {
-            entry = DP_malloc(sizeof(*entry));
-            entry->annotation_id = annotation_id;
-            HASH_ADD_INT(acls->annotations, annotation_id, entry);
+        else {
+            return filter_because(acls, DP_ACL_REJECTION_USER_LOCKED);
         }
-        return true;
+    }
+}
+
+unsigned int DP_acl_state_layer_acl_version(DP_AclState *acls, int layer_id)
+{
+    DP_ASSERT(acls);
+    DP_LayerAclVersionEntry *entry;
+    HASH_FIND_INT(acls->layer_acl_versions, &layer_id, entry);
+    return entry ? entry->version : 0;
+}
+
+uint8_t DP_acl_state_handle_versioned(DP_AclState *acls, DP_Message *msg,
+                                      unsigned int expected_version,
+                                      bool override)
+{
+    DP_ASSERT(acls);
+    DP_ASSERT(msg);
+    DP_MessageType type = DP_message_type(msg);
+    bool stale =
+        (type == DP_MSG_LAYER_ACL || type == DP_MSG_LAYER_ACL_EXCLUSIVE)
+        && DP_acl_state_layer_acl_version(acls, audit_target_id(msg, type))
+               != expected_version;
+    if (stale) {
+        return filter_because(acls, DP_ACL_REJECTION_STALE_LAYER_ACL);
     }
     else {
-        return false;
+        return DP_acl_state_handle(acls, msg, override);
     }
 }
 
-static bool handle_annotation_delete(DP_AclState *acls, DP_Message *msg,
-                                     uint8_t user_id, bool override)
+uint8_t DP_acl_state_handle_sequenced(DP_AclState *acls, DP_Message *msg,
+                                      long long sequence, bool override)
 {
-    DP_MsgAnnotationDelete *mad = DP_msg_annotation_delete_cast(msg);
-    int annotation_id = DP_msg_annotation_delete_id(mad);
-    bool can_delete = override || owns_id(user_id, annotation_id)
-                   || DP_acl_state_is_op(acls, user_id)
-                   || !DP_acl_state_annotation_locked(acls, annotation_id);
-    if (can_delete) {
-        DP_AnnotationAclEntry *entry;
-        HASH_FIND_INT(acls->annotations, &annotation_id, entry);
-        if (entry) {
-            HASH_DEL(acls->annotations, entry);
-            DP_free(entry);
-        }
-        return true;
+    DP_ASSERT(acls);
+    DP_ASSERT(msg);
+    DP_ASSERT(sequence >= 0);
+    acls->sequence = sequence;
+    uint8_t result = DP_acl_state_handle(acls, msg, override);
+    acls->sequence = -1;
+    return result;
+}
+
+bool DP_acl_state_handle_fast(DP_AclState *acls, DP_Message *msg,
+                              bool override)
+{
+    uint8_t result = DP_acl_state_handle(acls, msg, override);
+    return !(result & DP_ACL_STATE_FILTERED_BIT);
+}
+
+uint8_t DP_acl_state_handle_preprocessed(DP_AclState *acls, DP_Message *msg,
+                                         bool override,
+                                         DP_AclStatePreprocessFn preprocess,
+                                         void *user, DP_Message **out_msg)
+{
+    DP_ASSERT(acls);
+    DP_ASSERT(msg);
+    DP_ASSERT(preprocess);
+    DP_ASSERT(out_msg);
+    DP_Message *preprocessed = preprocess(user, msg);
+    *out_msg = preprocessed;
+    if (preprocessed) {
+        return DP_acl_state_handle(acls, preprocessed, override);
     }
     else {
-        return false;
+        return filter_because(acls, DP_ACL_REJECTION_PREPROCESSOR_DROPPED);
     }
 }
 
-static bool handle_move(DP_AclState *acls, uint8_t user_id, bool override,
-                        int source_id, int target_id)
+uint8_t DP_acl_state_lock_layer(DP_AclState *acls, int layer_id,
+                                DP_AccessTier tier, int exclusive_count,
+                                const uint8_t *exclusive)
 {
-    if (override) {
-        return true;
+    DP_ASSERT(acls);
+    DP_ASSERT(layer_id > 0);
+    DP_ASSERT(layer_id != DP_ACL_DEFAULT_LAYER_ID);
+    DP_ASSERT(tier >= 0);
+    DP_ASSERT(tier < DP_ACCESS_TIER_COUNT);
+    DP_ASSERT(exclusive_count >= 0);
+    DP_ASSERT(exclusive_count == 0 || exclusive);
+    acls->local_mode = false;
+    // Without a tier or users to let through, the lock applies to everyone.
+    uint8_t flags = DP_int_to_uint8(tier);
+    if (tier == DP_ACCESS_TIER_GUEST && exclusive_count == 0) {
+        flags |= DP_ACL_ALL_LOCKED_BIT;
+    }
+
+    DP_LayerAclEntry *entry;
+    HASH_FIND_INT(acls->layers, &layer_id, entry);
+    if (entry && entry->layer_acl.immutable) {
+        flags |= DP_ACL_IMMUTABLE_BIT;
+    }
+
+    DP_LayerAcl l;
+    init_layer_acl(&l, flags, exclusive_count, exclusive);
+    if (entry && layer_acls_equal(&entry->layer_acl, &l)) {
+        return 0;
+    }
+    else {
+        if (!entry) {
+            entry = add_layer_acl_entry(acls, layer_id);
+        }
+        entry->layer_acl = l;
+        set_layer_acl_effective(acls, entry);
+        update_any_layer_locked(acls);
+        bump_layer_acl_version_for(acls, layer_id);
+        return DP_ACL_STATE_CHANGE_LAYERS_BIT;
+    }
+}
+
+uint8_t DP_acl_state_unlock_layer(DP_AclState *acls, int layer_id)
+{
+    DP_ASSERT(acls);
+    DP_ASSERT(layer_id > 0);
+    DP_ASSERT(layer_id != DP_ACL_DEFAULT_LAYER_ID);
+    DP_LayerAclEntry *entry;
+    HASH_FIND_INT(acls->layers, &layer_id, entry);
+    if (entry && entry->layer_acl.immutable) {
+        // Everything but the immutability goes away.
+        DP_LayerAcl l;
+        init_layer_acl(&l, DP_ACCESS_TIER_GUEST | DP_ACL_IMMUTABLE_BIT, 0,
+                       NULL);
+        if (layer_acls_equal(&entry->layer_acl, &l)) {
+            return 0;
+        }
+        else {
+            entry->layer_acl = l;
+            set_layer_acl_effective(acls, entry);
+            update_any_layer_locked(acls);
+            bump_layer_acl_version_for(acls, layer_id);
+            return DP_ACL_STATE_CHANGE_LAYERS_BIT;
+        }
     }
-    if (DP_acl_state_can_use_feature(acls, DP_FEATURE_REGION_MOVE, user_id)) {
-        return !DP_acl_state_layer_locked_for(acls, user_id, source_id)
-            && !DP_acl_state_layer_locked_for(acls, user_id, target_id);
+    else if (entry) {
+        HASH_DEL(acls->layers, entry);
+        DP_free(entry);
+        update_any_layer_locked(acls);
+        bump_layer_acl_version_for(acls, layer_id);
+        return DP_ACL_STATE_CHANGE_LAYERS_BIT;
     }
     else {
-        return false;
+        return 0;
     }
 }
 
-static bool handle_move_region(DP_AclState *acls, DP_Message *msg,
-                               uint8_t user_id, bool override)
+static uint8_t open_all_layers(DP_AclState *acls)
 {
-    DP_MsgMoveRegion *mmr = DP_message_internal(msg);
-    int layer_id = DP_msg_move_region_layer(mmr);
-    return handle_move(acls, user_id, override, layer_id, layer_id);
+    uint8_t changes = 0;
+    DP_LayerAclEntry *entry, *tmp;
+    HASH_ITER(hh, acls->layers, entry, tmp) {
+        changes |= DP_acl_state_unlock_layer(acls, entry->layer_id);
+    }
+    if (acls->have_default_layer_acl) {
+        acls->have_default_layer_acl = false;
+        changes |= DP_ACL_STATE_CHANGE_LAYERS_BIT;
+    }
+    return changes;
 }
 
-static bool handle_move_rect(DP_AclState *acls, DP_Message *msg,
-                             uint8_t user_id, bool override)
+uint8_t DP_acl_state_locked_categories(DP_AclState *acls)
 {
-    DP_MsgMoveRect *mmr = DP_message_internal(msg);
-    return handle_move(acls, user_id, override, DP_msg_move_rect_source(mmr),
-                       DP_msg_move_rect_layer(mmr));
+    DP_ASSERT(acls);
+    return acls->users.locked_categories;
 }
 
-static bool handle_transform_region(DP_AclState *acls, DP_Message *msg,
-                                    uint8_t user_id, bool override)
+uint8_t DP_acl_state_lock_categories(DP_AclState *acls, uint8_t categories)
 {
-    DP_MsgTransformRegion *mtr = DP_message_internal(msg);
-    return handle_move(acls, user_id, override,
-                       DP_msg_transform_region_source(mtr),
-                       DP_msg_transform_region_layer(mtr));
+    DP_ASSERT(acls);
+    categories &= DP_ACL_LOCK_CATEGORY_MASK;
+    if (categories != 0) {
+        acls->local_mode = false;
+    }
+
+    if (acls->users.locked_categories == categories) {
+        return 0;
+    }
+    else {
+        acls->users.locked_categories = categories;
+        return DP_ACL_STATE_CHANGE_USERS_BIT;
+    }
 }
 
-static bool handle_set_metadata_int(DP_AclState *acls, DP_Message *msg,
-                                    uint8_t user_id, bool override)
+static uint8_t open_all_users(DP_AclState *acls)
 {
-    if (override) {
-        return true;
+    if (acls->users.all_locked || acls->users.locked_categories != 0
+        || !user_bits_none_set(acls->users.locked)) {
+        acls->users.all_locked = false;
+        acls->users.locked_categories = 0;
+        memset_userbits(acls->users.locked, 0);
+        return DP_ACL_STATE_CHANGE_USERS_BIT;
     }
-    DP_MsgSetMetadataInt *msmi = DP_msg_set_metadata_int_cast(msg);
-    DP_Feature feature;
-    switch (DP_msg_set_metadata_int_field(msmi)) {
-    case DP_MSG_SET_METADATA_INT_FIELD_FRAMERATE:
-    case DP_MSG_SET_METADATA_INT_FIELD_FRAME_COUNT:
-        feature = DP_FEATURE_TIMELINE;
-        break;
-    default:
-        feature = DP_FEATURE_METADATA;
-        break;
+    else {
+        return 0;
     }
-    return DP_acl_state_can_use_feature(acls, feature, user_id);
 }
 
-static bool handle_track_create(DP_AclState *acls, int track_id,
-                                uint8_t user_id, bool override)
+static uint8_t open_all_features(DP_AclState *acls)
 {
-    if (override) {
-        return true;
+    DP_FeatureTiers previous_feature = acls->feature;
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        if (!acls->persistent.frozen_features[i]) {
+            acls->feature.tiers[i] = DP_ACCESS_TIER_GUEST;
+        }
     }
-    // Only operators can create tracks under a different owner.
-    return (owns_id(user_id, track_id) || DP_acl_state_is_op(acls, user_id))
-        && DP_acl_state_can_use_feature(acls, DP_FEATURE_TIMELINE, user_id);
+    return DP_feature_tiers_equal(&previous_feature, &acls->feature)
+             ? 0
+             : DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT;
 }
 
-static bool handle_command_message(DP_AclState *acls, DP_Message *msg,
-                                   DP_MessageType type, uint8_t user_id,
-                                   bool override)
+uint8_t DP_acl_state_open_all(DP_AclState *acls)
 {
-    switch (type) {
-    case DP_MSG_CANVAS_RESIZE:
-        return override
-            || DP_acl_state_can_use_feature(acls, DP_FEATURE_RESIZE, user_id);
-    case DP_MSG_LAYER_CREATE:
-        return handle_layer_create(
-            acls, DP_msg_layer_create_id(DP_msg_layer_create_cast(msg)),
-            user_id, override);
-    case DP_MSG_LAYER_ATTRIBUTES:
-        return override
-            || can_edit_layer(acls, user_id,
-                              DP_msg_layer_attributes_id(
-                                  DP_msg_layer_attributes_cast(msg)));
-    case DP_MSG_LAYER_RETITLE:
-        return override
-            || can_edit_layer(
-                   acls, user_id,
-                   DP_msg_layer_retitle_id(DP_msg_layer_retitle_cast(msg)));
-    case DP_MSG_LAYER_ORDER:
-        return override
-            || DP_acl_state_can_use_feature(acls, DP_FEATURE_EDIT_LAYERS,
-                                            user_id);
-    case DP_MSG_LAYER_DELETE: {
-        DP_MsgLayerDelete *mld = DP_msg_layer_delete_cast(msg);
-        return handle_layer_delete(acls, DP_msg_layer_delete_id(mld), 0,
-                                   user_id, override);
-    }
-    case DP_MSG_LAYER_VISIBILITY:
-        return false; // Layer hiding is client-side.
-    case DP_MSG_PUT_IMAGE: {
-        DP_MsgPutImage *mpi = DP_message_internal(msg);
-        return override
-            // Compatibility hack: local match command disguised as put image.
-            || DP_msg_put_image_mode(mpi) == DP_BLEND_MODE_COMPAT_LOCAL_MATCH
-            || (DP_acl_state_can_use_feature(acls, DP_FEATURE_PUT_IMAGE,
-                                             user_id)
-                && !DP_acl_state_layer_locked_for(acls, user_id,
-                                                  DP_msg_put_image_layer(mpi)));
-    }
-    case DP_MSG_FILL_RECT:
-        return override
-            || (DP_acl_state_can_use_feature(acls, DP_FEATURE_PUT_IMAGE,
-                                             user_id)
-                && !DP_acl_state_layer_locked_for(
-                    acls, user_id,
-                    DP_msg_fill_rect_layer(DP_msg_fill_rect_cast(msg))));
-    case DP_MSG_ANNOTATION_CREATE:
-        return handle_annotation_create(acls, msg, user_id, override);
-    case DP_MSG_ANNOTATION_RESHAPE:
-        return handle_annotation_reshape(acls, msg, user_id, override);
-    case DP_MSG_ANNOTATION_EDIT:
-        return handle_annotation_edit(acls, msg, user_id, override);
-    case DP_MSG_ANNOTATION_DELETE:
-        return handle_annotation_delete(acls, msg, user_id, override);
-    case DP_MSG_MOVE_REGION:
-        return handle_move_region(acls, msg, user_id, override);
-    case DP_MSG_PUT_TILE:
-        return override || DP_acl_state_is_op(acls, user_id);
-    case DP_MSG_CANVAS_BACKGROUND:
-        return override
-            || DP_acl_state_can_use_feature(acls, DP_FEATURE_BACKGROUND,
-                                            user_id);
-    case DP_MSG_DRAW_DABS_CLASSIC:
-        return override
-            || !DP_acl_state_layer_locked_for(
-                   acls, user_id,
-                   DP_msg_draw_dabs_classic_layer(
-                       DP_msg_draw_dabs_classic_cast(msg)));
-    case DP_MSG_DRAW_DABS_PIXEL:
-        return override
-            || !DP_acl_state_layer_locked_for(
-                   acls, user_id,
-                   DP_msg_draw_dabs_pixel_layer(
-                       DP_msg_draw_dabs_pixel_cast(msg)));
-    case DP_MSG_DRAW_DABS_PIXEL_SQUARE:
-        return override
-            || !DP_acl_state_layer_locked_for(
-                   acls, user_id,
-                   DP_msg_draw_dabs_pixel_layer(
-                       DP_msg_draw_dabs_pixel_square_cast(msg)));
-    case DP_MSG_DRAW_DABS_MYPAINT:
-        return override
-            || (DP_acl_state_can_use_feature(acls, DP_FEATURE_MYPAINT, user_id)
-                && !DP_acl_state_layer_locked_for(
-                    acls, user_id,
-                    DP_msg_draw_dabs_mypaint_layer(
-                        DP_msg_draw_dabs_mypaint_cast(msg))));
-    case DP_MSG_MOVE_RECT:
-        return handle_move_rect(acls, msg, user_id, override);
-    case DP_MSG_SET_METADATA_INT:
-        return handle_set_metadata_int(acls, msg, user_id, override);
-    case DP_MSG_LAYER_TREE_CREATE:
-        return handle_layer_create(
-            acls,
-            DP_msg_layer_tree_create_id(DP_msg_layer_tree_create_cast(msg)),
-            user_id, override);
-    case DP_MSG_LAYER_TREE_MOVE:
-        return handle_layer_tree_move(acls, DP_msg_layer_tree_move_cast(msg),
-                                      user_id, override);
-    case DP_MSG_LAYER_TREE_DELETE: {
-        DP_MsgLayerTreeDelete *mltd = DP_msg_layer_tree_delete_cast(msg);
-        return handle_layer_delete(acls, DP_msg_layer_tree_delete_id(mltd),
-                                   DP_msg_layer_tree_delete_merge_to(mltd),
-                                   user_id, override);
-    }
-    case DP_MSG_TRANSFORM_REGION:
-        return handle_transform_region(acls, msg, user_id, override);
-    case DP_MSG_TRACK_CREATE:
-        return handle_track_create(
-            acls, DP_msg_track_create_id(DP_msg_track_create_cast(msg)),
-            user_id, override);
-    case DP_MSG_TRACK_RETITLE:
-    case DP_MSG_TRACK_DELETE:
-    case DP_MSG_TRACK_ORDER:
-    case DP_MSG_KEY_FRAME_SET:
-    case DP_MSG_KEY_FRAME_RETITLE:
-    case DP_MSG_KEY_FRAME_LAYER_ATTRIBUTES:
-    case DP_MSG_KEY_FRAME_DELETE:
-        return override
-            || DP_acl_state_can_use_feature(acls, DP_FEATURE_TIMELINE, user_id);
-    case DP_MSG_UNDO:
-        return override
-            || DP_acl_state_can_use_feature(acls, DP_FEATURE_UNDO, user_id);
-    default:
-        return true;
-    }
+    DP_ASSERT(acls);
+    return open_all_layers(acls) | open_all_users(acls)
+         | open_all_features(acls);
 }
 
-uint8_t DP_acl_state_handle(DP_AclState *acls, DP_Message *msg, bool override)
+void DP_acl_state_dry_run(DP_AclState *acls, int count, DP_Message **msgs,
+                          bool *out_accepted)
 {
     DP_ASSERT(acls);
-    DP_ASSERT(msg);
-    DP_MessageType type = DP_message_type(msg);
-    // Command messages (128 and up) need common handling.
-    if (type < 128) {
-        switch (type) {
-        case DP_MSG_JOIN:
-            return handle_join(acls, msg);
-        case DP_MSG_LEAVE:
-            return handle_leave(acls, msg);
-        case DP_MSG_SESSION_OWNER:
-            return handle_session_owner(acls, msg);
-        case DP_MSG_TRUSTED_USERS:
-            return handle_trusted_users(acls, msg);
-        case DP_MSG_INTERNAL:
-            return handle_internal(acls, msg);
-        case DP_MSG_LASER_TRAIL:
-            return filter_unless(
-                override
-                || DP_acl_state_can_use_feature(acls, DP_FEATURE_LASER,
-                                                message_user_id(msg)));
-        case DP_MSG_USER_ACL:
-            return handle_user_acl(acls, msg, override);
-        case DP_MSG_LAYER_ACL:
-            return handle_layer_acl(acls, msg, override);
-        case DP_MSG_FEATURE_ACCESS_LEVELS:
-            return handle_feature_access_levels(acls, msg, override);
-        case DP_MSG_DEFAULT_LAYER:
-            return filter_unless(
-                override || DP_acl_state_is_op(acls, message_user_id(msg)));
-        case DP_MSG_FILTERED:
-            return DP_ACL_STATE_FILTERED_BIT;
-        case DP_MSG_UNDO_DEPTH:
-            return filter_unless(
-                override || DP_acl_state_is_op(acls, message_user_id(msg)));
-        case DP_MSG_LOCAL_CHANGE:
-            return filter_unless(override || message_user_id(msg) == 0);
-        default:
-            return 0;
-        }
-    }
-    else {
-        if (override || !acls->users.all_locked) {
-            uint8_t user_id = message_user_id(msg);
-            if (override || user_id == 0
-                || !DP_user_bit_get(acls->users.locked, user_id)) {
-                return filter_unless(
-                    handle_command_message(acls, msg, type, user_id, override));
-            }
-        }
-        return DP_ACL_STATE_FILTERED_BIT;
+    DP_ASSERT(count >= 0);
+    DP_ASSERT(count == 0 || msgs);
+    DP_ASSERT(count == 0 || out_accepted);
+    DP_AclSnapshot snapshot;
+    DP_acl_state_snapshot(acls, &snapshot);
+    DP_AclState *copy = DP_acl_state_new();
+    DP_acl_state_restore(copy, &snapshot);
+    DP_acl_snapshot_dispose(&snapshot);
+
+    for (int i = 0; i < count; ++i) {
+        uint8_t result = DP_acl_state_handle(copy, msgs[i], false);
+        out_accepted[i] = !(result & DP_ACL_STATE_FILTERED_BIT);
     }
+
+    DP_acl_state_free(copy);
 }
 
+DP_AclRejection DP_acl_state_last_rejection(DP_AclState *acls)
+{
+    DP_ASSERT(acls);
+    return acls->last_rejection;
+}
 
-static int count_user_bits(const uint8_t *users)
+bool DP_acl_state_required_tier(DP_AclState *acls, DP_Message *msg,
+                                DP_AccessTier *out_tier)
 {
-    int count = 0;
-    for (int i = 0; i < 256; ++i) {
-        if (DP_user_bit_get(users, DP_int_to_uint8(i))) {
-            ++count;
-        }
+    DP_ASSERT(acls);
+    DP_ASSERT(msg);
+    DP_ASSERT(out_tier);
+    DP_Feature feature;
+    if (DP_message_governing_feature(msg, &feature)) {
+        *out_tier = acls->feature.tiers[feature];
+        return true;
+    }
+    else {
+        return false;
     }
-    return count;
 }
 
+
 static void set_message_user_bits(DP_UNUSED int count, uint8_t *out, void *user)
 {
-    const uint8_t *users = user;
-    int out_index = 0;
-    for (int i = 0; i < 256; ++i) {
-        uint8_t user_id = DP_int_to_uint8(i);
-        if (DP_user_bit_get(users, user_id)) {
-            out[out_index++] = user_id;
-        }
-    }
-    DP_ASSERT(out_index == count);
+    DP_ASSERT(DP_user_bits_count(user) == count);
+    DP_user_bits_ids(user, out);
 }
 
 static void set_feature_tiers(int count, uint8_t *out, void *user)
@@ -1164,38 +3936,62 @@ static bool reset_image_push_users(
                                 int, void *),
     bool (*push_message)(void *, DP_Message *), void *user)
 {
-    int count = count_user_bits(users);
+    int count = DP_user_bits_count(users);
     DP_Message *user_acl_message =
         make_message(context_id, set_message_user_bits, count, users);
     return push_message(user, user_acl_message);
 }
 
-bool DP_acl_state_reset_image_build(DP_AclState *acls, unsigned int context_id,
-                                    unsigned int include_flags,
-                                    bool (*push_message)(void *, DP_Message *),
-                                    void *user)
+static bool reset_image_push_layer_acl(
+    unsigned int context_id, int layer_id, DP_LayerAcl *l,
+    bool include_exclusive, bool (*push_message)(void *, DP_Message *),
+    void *user)
+{
+    uint8_t flags =
+        DP_uint_to_uint8(l->tier | (l->locked ? DP_ACL_ALL_LOCKED_BIT : 0)
+                         | (l->alpha_locked ? DP_ACL_ALPHA_LOCKED_BIT : 0)
+                         | (l->immutable ? DP_ACL_IMMUTABLE_BIT : 0));
+    int exclusive_count = DP_user_bits_count(l->exclusive);
+    bool exclusive = include_exclusive && exclusive_count != 256;
+    DP_Message *layer_acl_msg = DP_msg_layer_acl_new(
+        context_id, DP_int_to_uint16(layer_id), flags,
+        exclusive ? set_message_user_bits : NULL,
+        exclusive ? exclusive_count : 0, l->exclusive);
+    return push_message(user, layer_acl_msg);
+}
+
+bool DP_acl_state_reset_image_build_layers(
+    DP_AclState *acls, unsigned int context_id, unsigned int include_flags,
+    bool (*push_message)(void *, DP_Message *), void *user)
 {
     DP_ASSERT(acls);
     DP_ASSERT(push_message);
-
-    DP_LayerAclEntry *entry, *tmp;
     bool include_exclusive =
         include_flags & DP_ACL_STATE_RESET_IMAGE_INCLUDE_LAYER_ACL_EXCLUSIVE;
+    if (acls->have_default_layer_acl
+        && !reset_image_push_layer_acl(
+            context_id, DP_ACL_DEFAULT_LAYER_ID, &acls->default_layer_acl,
+            include_exclusive, push_message, user)) {
+        return false;
+    }
+
+    DP_LayerAclEntry *entry, *tmp;
     HASH_ITER(hh, acls->layers, entry, tmp) {
-        DP_LayerAcl *l = &entry->layer_acl;
-        uint8_t flags =
-            DP_uint_to_uint8(l->tier | (l->locked ? DP_ACL_ALL_LOCKED_BIT : 0));
-        int exclusive_count = count_user_bits(l->exclusive);
-        bool exclusive = include_exclusive && exclusive_count != 256;
-        DP_Message *layer_acl_msg = DP_msg_layer_acl_new(
-            context_id, DP_int_to_uint16(entry->layer_id), flags,
-            exclusive ? set_message_user_bits : NULL,
-            exclusive ? exclusive_count : 0, l->exclusive);
-        if (!push_message(user, layer_acl_msg)) {
+        if (!reset_image_push_layer_acl(context_id, entry->layer_id,
+                                        &entry->layer_acl, include_exclusive,
+                                        push_message, user)) {
             return false;
         }
     }
+    return true;
+}
 
+bool DP_acl_state_reset_image_build_session(
+    DP_AclState *acls, unsigned int context_id, unsigned int include_flags,
+    bool (*push_message)(void *, DP_Message *), void *user)
+{
+    DP_ASSERT(acls);
+    DP_ASSERT(push_message);
     DP_Message *feature_access_levels_msg = DP_msg_feature_access_levels_new(
         context_id, set_feature_tiers, DP_FEATURE_COUNT, acls->feature.tiers);
     if (!push_message(user, feature_access_levels_msg)) {
@@ -1230,3 +4026,115 @@ bool DP_acl_state_reset_image_build(DP_AclState *acls, unsigned int context_id,
 
     return true;
 }
+
+bool DP_acl_state_reset_image_build(DP_AclState *acls, unsigned int context_id,
+                                    unsigned int include_flags,
+                                    bool (*push_message)(void *, DP_Message *),
+                                    void *user)
+{
+    return DP_acl_state_reset_image_build_layers(acls, context_id,
+                                                 include_flags, push_message,
+                                                 user)
+        && DP_acl_state_reset_image_build_session(acls, context_id,
+                                                  include_flags, push_message,
+                                                  user);
+}
+
+
+void DP_acl_state_policy_export(DP_AclState *acls, unsigned char *out)
+{
+    DP_ASSERT(acls);
+    DP_ASSERT(out);
+    out[0] = DP_ACL_POLICY_VERSION;
+    out[1] = DP_FEATURE_COUNT;
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        out[2 + i] = (unsigned char)acls->feature.tiers[i];
+    }
+
+    unsigned char default_flags;
+    if (acls->have_default_layer_acl) {
+        const DP_LayerAcl *l = &acls->default_layer_acl;
+        default_flags =
+            (unsigned char)(DP_ACL_POLICY_DEFAULT_LAYER_BIT
+                            | (l->locked ? DP_ACL_ALL_LOCKED_BIT : 0)
+                            | (l->alpha_locked ? DP_ACL_ALPHA_LOCKED_BIT : 0)
+                            | (unsigned char)l->tier);
+    }
+    else {
+        default_flags = 0;
+    }
+    out[2 + DP_FEATURE_COUNT] = default_flags;
+}
+
+uint8_t DP_acl_state_policy_import(DP_AclState *acls, const unsigned char *in,
+                                   size_t length)
+{
+    DP_ASSERT(acls);
+    DP_ASSERT(in || length == 0);
+    if (length < 2) {
+        DP_error_set("ACL policy too short: %zu", length);
+        return DP_ACL_STATE_FILTERED_BIT;
+    }
+
+    if (in[0] != DP_ACL_POLICY_VERSION) {
+        DP_error_set("ACL policy version mismatch: expected %d, got %d",
+                     DP_ACL_POLICY_VERSION, (int)in[0]);
+        return DP_ACL_STATE_FILTERED_BIT;
+    }
+
+    if (in[1] != DP_FEATURE_COUNT || length != DP_ACL_POLICY_LENGTH) {
+        DP_error_set("ACL policy has %d feature(s) and length %zu, expected "
+                     "%d and %d",
+                     (int)in[1], length, DP_FEATURE_COUNT,
+                     DP_ACL_POLICY_LENGTH);
+        return DP_ACL_STATE_FILTERED_BIT;
+    }
+
+    DP_FeatureTiers feature;
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        unsigned char tier = in[2 + i];
+        if (tier > DP_ACCESS_TIER_GUEST) {
+            DP_error_set("ACL policy has invalid tier %d for %s", (int)tier,
+                         DP_feature_enum_name(i));
+            return DP_ACL_STATE_FILTERED_BIT;
+        }
+        feature.tiers[i] = (DP_AccessTier)tier;
+    }
+
+    keep_frozen_tiers(acls, &feature);
+    uint8_t result = 0;
+    if (!DP_feature_tiers_equal(&feature, &acls->feature)) {
+        acls->feature = feature;
+        result |= DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT;
+    }
+
+    unsigned char default_flags = in[2 + DP_FEATURE_COUNT];
+    if (default_flags & DP_ACL_POLICY_DEFAULT_LAYER_BIT) {
+        DP_LayerAcl l;
+        init_layer_acl(&l,
+                       (uint8_t)(default_flags
+                                 & ~(DP_ACL_POLICY_DEFAULT_LAYER_BIT
+                                     | DP_ACL_IMMUTABLE_BIT)),
+                       0, NULL);
+        DP_LayerAcl *prev = &acls->default_layer_acl;
+        bool changed = !acls->have_default_layer_acl || prev->locked != l.locked
+                    || prev->alpha_locked != l.alpha_locked
+                    || prev->tier != l.tier
+                    || memcmp(prev->exclusive, l.exclusive, sizeof(l.exclusive))
+                           != 0;
+        if (changed) {
+            *prev = l;
+            acls->have_default_layer_acl = true;
+            result |= DP_ACL_STATE_CHANGE_LAYERS_BIT;
+        }
+    }
+    else if (acls->have_default_layer_acl) {
+        acls->have_default_layer_acl = false;
+        result |= DP_ACL_STATE_CHANGE_LAYERS_BIT;
+    }
+
+    if (result != 0) {
+        acls->local_mode = false;
+    }
+    return result;
+}
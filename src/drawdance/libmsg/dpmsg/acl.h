@@ -26,7 +26,24 @@
 typedef struct DP_Message DP_Message;
 
 
-#define DP_ACL_ALL_LOCKED_BIT 0x80
+#define DP_ACL_ALL_LOCKED_BIT   0x80
+#define DP_ACL_ALPHA_LOCKED_BIT 0x40
+// Only honored on actual layers, not on the default layer ACL or session lock.
+#define DP_ACL_IMMUTABLE_BIT    0x10
+
+// Categories of commands that can be locked for everyone in the session at
+// once, see DP_acl_state_lock_categories.
+#define DP_ACL_LOCK_CATEGORY_DRAW        (1 << 0)
+#define DP_ACL_LOCK_CATEGORY_LAYERS      (1 << 1)
+#define DP_ACL_LOCK_CATEGORY_ANNOTATIONS (1 << 2)
+#define DP_ACL_LOCK_CATEGORY_MASK                              \
+    (DP_ACL_LOCK_CATEGORY_DRAW | DP_ACL_LOCK_CATEGORY_LAYERS \
+     | DP_ACL_LOCK_CATEGORY_ANNOTATIONS)
+
+// Layer ACL messages targeting this ID set the default ACL that newly created
+// layers start out with. Creating a layer with this ID is rejected, both by
+// the ACL filter and by the paint engine.
+#define DP_ACL_DEFAULT_LAYER_ID 0xff
 
 #define DP_ACCESS_TIER_MASK                           \
     (DP_ACCESS_TIER_OPERATOR | DP_ACCESS_TIER_TRUSTED \
@@ -36,9 +53,20 @@ typedef struct DP_Message DP_Message;
 #define DP_ACL_STATE_CHANGE_USERS_BIT         (1 << 1)
 #define DP_ACL_STATE_CHANGE_LAYERS_BIT        (1 << 2)
 #define DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT (1 << 3)
+#define DP_ACL_STATE_CHANGE_ANNOTATIONS_BIT   (1 << 4)
 #define DP_ACL_STATE_CHANGE_MASK                                    \
     (DP_ACL_STATE_CHANGE_USERS_BIT | DP_ACL_STATE_CHANGE_LAYERS_BIT \
-     | DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT)
+     | DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT                        \
+     | DP_ACL_STATE_CHANGE_ANNOTATIONS_BIT)
+
+// Policy blobs hold the reusable configuration of an ACL state: feature tiers
+// and the default layer ACL. Layout: version, feature count, one tier per
+// feature, then a default layer ACL flag byte, 0 meaning no default. That
+// byte has the same layout as the layer ACL message's flags, plus
+// DP_ACL_POLICY_DEFAULT_LAYER_BIT.
+#define DP_ACL_POLICY_VERSION           1
+#define DP_ACL_POLICY_LENGTH            (3 + DP_FEATURE_COUNT)
+#define DP_ACL_POLICY_DEFAULT_LAYER_BIT 0x20
 
 #define DP_ACL_STATE_RESET_IMAGE_INCLUDE_SESSION_OWNER       (1 << 0)
 #define DP_ACL_STATE_RESET_IMAGE_INCLUDE_TRUSTED_USERS       (1 << 1)
@@ -61,6 +89,8 @@ typedef struct DP_Message DP_Message;
 typedef enum DP_AccessTier {
     DP_ACCESS_TIER_OPERATOR = 0,
     DP_ACCESS_TIER_TRUSTED = 1,
+    // Users that joined with the auth flag. Anyone else is a guest, so putting
+    // a feature on this tier restricts it to registered users.
     DP_ACCESS_TIER_AUTHENTICATED = 2,
     DP_ACCESS_TIER_GUEST = 3,
     DP_ACCESS_TIER_COUNT,
@@ -79,13 +109,75 @@ typedef enum DP_Feature {
     DP_FEATURE_METADATA,
     DP_FEATURE_TIMELINE,
     DP_FEATURE_MYPAINT,
+    // Classic and pixel dabs, for restricting one kind of brush but not the
+    // other. Both default to the guest tier, so only the layer locks apply.
+    DP_FEATURE_CLASSIC_BRUSH,
+    DP_FEATURE_PIXEL_BRUSH,
     DP_FEATURE_COUNT,
 } DP_Feature;
 
+// Why a message got filtered by the ACL state.
+typedef enum DP_AclRejection {
+    DP_ACL_REJECTION_NONE,
+    // The message is never supposed to be processed, e.g. filtered messages.
+    DP_ACL_REJECTION_INVALID,
+    DP_ACL_REJECTION_SESSION_LOCKED,
+    DP_ACL_REJECTION_USER_LOCKED,
+    DP_ACL_REJECTION_NOT_OPERATOR,
+    DP_ACL_REJECTION_FEATURE_TIER,
+    DP_ACL_REJECTION_NOT_OWNER,
+    DP_ACL_REJECTION_LAYER_LOCKED,
+    DP_ACL_REJECTION_ANNOTATION_LOCKED,
+    DP_ACL_REJECTION_ALPHA_LOCKED,
+    DP_ACL_REJECTION_LAYER_LIMIT,
+    DP_ACL_REJECTION_ANNOTATION_LIMIT,
+    DP_ACL_REJECTION_ANNOTATION_TOO_LONG,
+    // The message refers to a layer that doesn't exist.
+    DP_ACL_REJECTION_NO_SUCH_LAYER,
+    DP_ACL_REJECTION_NO_SUCH_ANNOTATION,
+    DP_ACL_REJECTION_STALE_LAYER_ACL,
+    DP_ACL_REJECTION_COSMETIC_MUTED,
+    DP_ACL_REJECTION_CANVAS_TOO_LARGE,
+    // A preprocessor dropped the message before it got to the ACL checks.
+    DP_ACL_REJECTION_PREPROCESSOR_DROPPED,
+    DP_ACL_REJECTION_LAYER_IMMUTABLE,
+    DP_ACL_REJECTION_CATEGORY_LOCKED,
+    DP_ACL_REJECTION_COUNT,
+} DP_AclRejection;
+
+// What to do when the last operator in a session would go away, be it through
+// leaving or through a session owner message that doesn't list anyone.
+typedef enum DP_AclOperatorPolicy {
+    // Let the operator set become empty.
+    DP_ACL_OPERATOR_POLICY_ALLOW_EMPTY,
+    // Keep the last operator around as a "ghost" until someone replaces them.
+    DP_ACL_OPERATOR_POLICY_KEEP_LAST,
+    // Promote the longest-connected remaining user to operator.
+    DP_ACL_OPERATOR_POLICY_PROMOTE_OLDEST,
+} DP_AclOperatorPolicy;
+
+// What to do with the layers of a user that leaves. Layer ownership goes by the
+// user id in the upper byte of the layer id, so nobody limited to editing their
+// own layers could touch them anymore.
+typedef enum DP_AclOrphanPolicy {
+    // Leave the layers to their absent owner.
+    DP_ACL_ORPHAN_POLICY_IGNORE,
+    // Record the layers as orphaned, so that an operator can claim them.
+    DP_ACL_ORPHAN_POLICY_RECORD,
+    // Transfer ownership to the designated heir, usually an operator, if
+    // they're present. Otherwise record the layers as orphaned.
+    DP_ACL_ORPHAN_POLICY_TRANSFER,
+} DP_AclOrphanPolicy;
+
 typedef struct DP_FeatureTiers {
     DP_AccessTier tiers[DP_FEATURE_COUNT];
 } DP_FeatureTiers;
 
+typedef struct DP_FeatureTierChange {
+    DP_Feature feature;
+    DP_AccessTier tier;
+} DP_FeatureTierChange;
+
 // Bitfield for storing user ids between 0 and 255. 255 / 8 = 32.
 typedef uint8_t DP_UserBits[32];
 
@@ -94,23 +186,138 @@ typedef struct DP_UserAcls {
     DP_UserBits trusted;
     DP_UserBits authenticated;
     DP_UserBits locked;
+    // Users whose laser trails, pointer movements and markers get dropped.
+    DP_UserBits cosmetic_muted;
     bool all_locked;
+    // DP_ACL_LOCK_CATEGORY_* bits of commands that are locked for everyone.
+    uint8_t locked_categories;
+    // Policy that puts authenticated users on the trusted tier, without them
+    // having to be promoted explicitly.
+    bool authenticated_are_trusted;
 } DP_UserAcls;
 
+// Goes through the set user ids in ascending order, without allocating.
+typedef struct DP_UserBitsIterator {
+    const uint8_t *users;
+    int next;        // next user id to look at, 256 when done
+    uint8_t user_id; // current user id, valid after next returned true
+} DP_UserBitsIterator;
+
+typedef struct DP_UserTier {
+    uint8_t user_id;
+    DP_AccessTier tier;
+} DP_UserTier;
+
 typedef struct DP_LayerAcl {
     bool locked;
+    // Only operators and the layer's owner may reduce opacity on the layer.
+    bool alpha_locked;
+    // Nobody may change the layer, not even operators, until an operator sends
+    // a layer ACL without the immutable bit. Only operators can set it.
+    bool immutable;
     DP_AccessTier tier;
     DP_UserBits exclusive;
 } DP_LayerAcl;
 
+// A layer whose exclusive list shuts out the user that owns it.
+typedef struct DP_AclAccessConflict {
+    int layer_id;
+    uint8_t owner_id;
+} DP_AclAccessConflict;
+
+// An accepted meta message that changed the ACL state. The timestamp is the
+// wall-clock time in seconds, the target is the layer id for layer ACL
+// messages and 0 otherwise. Changes is a set of DP_ACL_STATE_CHANGE_*_BITs.
+typedef struct DP_AclAuditEntry {
+    long long timestamp;
+    uint8_t actor_id;
+    int type;
+    int target_id;
+    uint8_t changes;
+} DP_AclAuditEntry;
+
+typedef struct DP_AclSnapshotLayer {
+    int layer_id;
+    DP_LayerAcl layer_acl;
+} DP_AclSnapshotLayer;
+
+typedef struct DP_AclSnapshotLiveLayer {
+    int layer_id;
+    int parent_id;
+} DP_AclSnapshotLiveLayer;
+
+// Plain-data copy of the full ACL state, except for the last rejection, which
+// only describes the outcome of the previous message. Entries are sorted by
+// id, so two snapshots of the same state compare equal.
+typedef struct DP_AclSnapshot {
+    uint8_t local_user_id;
+    DP_UserAcls users;
+    DP_FeatureTiers feature;
+    bool have_default_layer_acl;
+    DP_LayerAcl default_layer_acl;
+    DP_AclOperatorPolicy operator_policy;
+    unsigned int join_counter;
+    unsigned int joined[256];
+    int max_layers;
+    int max_annotations;
+    int max_annotation_text_length;
+    int max_canvas_width;
+    int max_canvas_height;
+    int canvas_width;
+    int canvas_height;
+    bool strict;
+    bool lock_new_users;
+    int local_authority;
+    bool local_mode;
+    int layer_count;
+    DP_AclSnapshotLayer *layers;
+    int live_layer_count;
+    DP_AclSnapshotLiveLayer *live_layers;
+    int annotation_count;
+    int *annotation_ids;
+    int live_annotation_count;
+    int *live_annotation_ids;
+} DP_AclSnapshot;
+
+// What a single user is allowed to do, for diagnostics. Only layers that have
+// an ACL are listed, all others are unlocked for everyone.
+typedef struct DP_AclPermissionReportLayer {
+    int layer_id;
+    bool locked;
+    bool alpha_locked;
+} DP_AclPermissionReportLayer;
+
+typedef struct DP_AclPermissionReport {
+    uint8_t user_id;
+    DP_AccessTier tier;
+    bool op;
+    bool locked;
+    bool features[DP_FEATURE_COUNT];
+    int layer_count;
+    DP_AclPermissionReportLayer *layers;
+} DP_AclPermissionReport;
+
 typedef struct DP_AclState DP_AclState;
+typedef struct json_value_t JSON_Value;
 
 typedef void (*DP_AclStateLayerFn)(void *user, int layer_id,
                                    const DP_LayerAcl *l);
 
+// Gets a message before the ACL state looks at it. Returns a new reference to
+// the message to handle instead, which may just be the given message itself,
+// or NULL to drop it.
+typedef DP_Message *(*DP_AclStatePreprocessFn)(void *user, DP_Message *msg);
+
 
 int DP_access_tier_clamp(int tier);
 
+// Lower tiers are more privileged, with operator being the most privileged of
+// all. These pick the tier that gives more or less privilege respectively, so
+// that compound checks don't have to get the inverted comparison right.
+DP_AccessTier DP_access_tier_most_privileged(DP_AccessTier a, DP_AccessTier b);
+DP_AccessTier DP_access_tier_least_privileged(DP_AccessTier a,
+                                              DP_AccessTier b);
+
 const char *DP_access_tier_enum_name(int tier);
 
 const char *DP_access_tier_name(int tier);
@@ -119,6 +326,61 @@ const char *DP_access_tier_name(int tier);
 const char *DP_feature_enum_name(int feature);
 
 
+const char *DP_acl_rejection_enum_name(int rejection);
+
+
+bool DP_feature_tiers_equal(const DP_FeatureTiers *a, const DP_FeatureTiers *b);
+
+// Writes the features whose tier differs from prev, along with their new tier,
+// to out_changes in feature order. It must have room for DP_FEATURE_COUNT
+// entries. Returns the number of changes written.
+int DP_feature_tiers_delta(const DP_FeatureTiers *tiers,
+                           const DP_FeatureTiers *prev,
+                           DP_FeatureTierChange *out_changes);
+
+// The tiers that a new ACL state starts out with.
+DP_FeatureTiers DP_feature_tiers_default(void);
+
+// Presets for commonly used permission setups. Open puts everything on the
+// guest tier. Moderated lets guests draw, but leaves layers, canvas resizing
+// and the like to trusted users. Locked down restricts everything to
+// operators.
+DP_FeatureTiers DP_feature_tiers_open(void);
+DP_FeatureTiers DP_feature_tiers_moderated(void);
+DP_FeatureTiers DP_feature_tiers_locked_down(void);
+
+// Like DP_feature_tiers_delta against the default tiers, for showing which
+// features a session has restricted or opened up compared to the defaults.
+int DP_feature_tiers_non_default(const DP_FeatureTiers *tiers,
+                                 DP_FeatureTierChange *out_changes);
+
+// Writes the features that a user on the given tier can't use under these
+// tiers to out_features in feature order, such as for showing what a user
+// would lose by being demoted. It must have room for DP_FEATURE_COUNT
+// entries, pass NULL to only count them. Returns how many there are.
+int DP_feature_tiers_blocked_for(const DP_FeatureTiers *tiers,
+                                 DP_AccessTier tier,
+                                 DP_Feature *out_features_or_null);
+
+// Checks tiers meant for a feature access levels message before sending it,
+// since the server rejects ones that don't make sense. There must be exactly
+// DP_FEATURE_COUNT of them, each a valid tier or 255 to leave that feature's
+// tier alone. If they're invalid, sets the error, puts the index of the first
+// invalid tier or -1 if the count is wrong into out_index and returns false.
+bool DP_feature_tiers_validate(const uint8_t *tiers, int count,
+                               int *out_index_or_null);
+
+// Which feature's access tier gates the given message. Returns false if it's
+// not gated by any feature, e.g. because it's operator-only or always allowed.
+// Layer commands that can be performed on one's own layers report
+// DP_FEATURE_OWN_LAYERS, DP_FEATURE_EDIT_LAYERS extends them to all layers.
+bool DP_message_governing_feature(DP_Message *msg, DP_Feature *out_feature);
+
+// Returns the DP_ACL_LOCK_CATEGORY_* bit the message falls under or 0 if it
+// isn't part of any category.
+uint8_t DP_message_lock_category(DP_Message *msg);
+
+
 bool DP_user_bit_get(const uint8_t *users, uint8_t user_id);
 void DP_user_bit_set(uint8_t *users, uint8_t user_id);
 void DP_user_bit_unset(uint8_t *users, uint8_t user_id);
@@ -126,13 +388,31 @@ void DP_user_bits_set(uint8_t *users, int count, const uint8_t *user_ids);
 void DP_user_bits_unset(uint8_t *users, int count, const uint8_t *user_ids);
 void DP_user_bits_replace(uint8_t *users, int count, const uint8_t *user_ids);
 
+DP_UserBitsIterator DP_user_bits_iterator_make(const uint8_t *users);
+
+bool DP_user_bits_iterator_next(DP_UserBitsIterator *ubi);
+
+int DP_user_bits_count(const uint8_t *users);
+
+// Writes the set user ids in ascending order. The buffer must have room for
+// 256 entries. Returns how many were written.
+int DP_user_bits_ids(const uint8_t *users, uint8_t *out_user_ids);
+
 bool DP_user_acls_is_op(const DP_UserAcls *users, uint8_t user_id);
 bool DP_user_acls_is_trusted(const DP_UserAcls *users, uint8_t user_id);
 bool DP_user_acls_is_authenticated(const DP_UserAcls *users, uint8_t user_id);
 bool DP_user_acls_is_locked(const DP_UserAcls *users, uint8_t user_id);
+bool DP_user_acls_is_cosmetic_muted(const DP_UserAcls *users, uint8_t user_id);
 
 DP_AccessTier DP_user_acls_tier(const DP_UserAcls *users, uint8_t user_id);
 
+// Writes every user whose tier is better than guest along with their highest
+// tier, in ascending order of user id. User 0 is the server, so it's not
+// included. The buffer must have room for 255 entries. Returns how many were
+// written.
+int DP_user_acls_elevated(const DP_UserAcls *users,
+                          DP_UserTier *out_user_tiers);
+
 
 DP_AclState *DP_acl_state_new(void);
 
@@ -145,20 +425,227 @@ DP_AclState *DP_acl_state_new_clone(DP_AclState *acls, uint8_t local_user_id);
 
 void DP_acl_state_free(DP_AclState *acls);
 
+// Fills the given snapshot with the current state. The snapshot owns its
+// arrays, release them with DP_acl_snapshot_dispose.
+void DP_acl_state_snapshot(DP_AclState *acls, DP_AclSnapshot *out_snapshot);
+
+// Replaces the entire state with the one in the snapshot, including the local
+// user and configuration. Unlike DP_acl_state_reset, nothing is retained. The
+// snapshot is left untouched and can be restored again.
+void DP_acl_state_restore(DP_AclState *acls, const DP_AclSnapshot *snapshot);
+
+bool DP_acl_snapshot_equal(const DP_AclSnapshot *a, const DP_AclSnapshot *b);
+
+void DP_acl_snapshot_dispose(DP_AclSnapshot *snapshot);
+
 void DP_acl_state_reset(DP_AclState *acls, uint8_t local_user_id);
 
+// Like DP_acl_state_reset, but also makes all the given users operators, so
+// that a restored session doesn't need a separate session owner message.
+void DP_acl_state_reset_with_operators(DP_AclState *acls, uint8_t local_user_id,
+                                       int operator_count,
+                                       const uint8_t *operator_ids);
+
 // Dumps a textural description of the ACL state to a DP_malloc'd string.
 char *DP_acl_state_dump(DP_AclState *acls);
 
+// Human-readable overview of the ACL state for admin commands and logging, as
+// a DP_malloc'd string. Lists operators, trusted and locked users, the tier of
+// each feature and the layers that are locked for anyone.
+char *DP_acl_state_summary(DP_AclState *acls);
+
 uint8_t DP_acl_state_local_user_id(DP_AclState *acls);
 
+// The operator policy is configuration, it survives resets of the ACL state.
+DP_AclOperatorPolicy DP_acl_state_operator_policy(DP_AclState *acls);
+
+void DP_acl_state_operator_policy_set(DP_AclState *acls,
+                                      DP_AclOperatorPolicy policy);
+
+// Maximum number of layers that may exist at once, creating more gets
+// rejected. Zero means no limit, which is the default. Like the operator
+// policy, this is configuration and survives resets.
+int DP_acl_state_max_layers(DP_AclState *acls);
+
+void DP_acl_state_max_layers_set(DP_AclState *acls, int max_layers);
+
+// Number of layers currently tracked as existing.
+int DP_acl_state_layer_count(DP_AclState *acls);
+
+// Maximum number of annotations that may exist at once and the maximum length
+// of annotation text in bytes. Zero means no limit. Survives resets as well.
+int DP_acl_state_max_annotations(DP_AclState *acls);
+
+void DP_acl_state_max_annotations_set(DP_AclState *acls, int max_annotations);
+
+int DP_acl_state_max_annotation_text_length(DP_AclState *acls);
+
+void DP_acl_state_max_annotation_text_length_set(DP_AclState *acls,
+                                                 int max_length);
+
+// Number of annotations currently tracked as existing.
+int DP_acl_state_annotation_count(DP_AclState *acls);
+
+// The canvas can never be larger than this in either direction.
+#define DP_ACL_MAX_CANVAS_DIMENSION INT16_MAX
+
+// Maximum canvas width and height, resizing the canvas beyond them gets
+// rejected. Zero means the hard ceiling of DP_ACL_MAX_CANVAS_DIMENSION, which
+// is the default, and larger values are clamped to it. Survives resets.
+void DP_acl_state_max_canvas_size(DP_AclState *acls, int *out_width,
+                                  int *out_height);
+
+void DP_acl_state_max_canvas_size_set(DP_AclState *acls, int max_width,
+                                      int max_height);
+
+// Canvas size as tracked from resize messages, starting out at zero and going
+// back to it on resets. Only needs to be set if the canvas got a size some
+// other way, such as when resetting to a state locally.
+void DP_acl_state_canvas_size(DP_AclState *acls, int *out_width,
+                              int *out_height);
+
+void DP_acl_state_canvas_size_set(DP_AclState *acls, int width, int height);
+
+// In strict mode, drawing on a layer, making it the default layer or editing
+// an annotation that hasn't been created in the message stream gets
+// rejected. Layers that only come into existence by duplicating a group
+// aren't tracked and so can't be drawn on either. Disabled by default. Like
+// the limits, this survives resets. Without strict mode, a default layer that
+// has been deleted is let through, since it may have come back through undo.
+bool DP_acl_state_strict(DP_AclState *acls);
+
+void DP_acl_state_strict_set(DP_AclState *acls, bool strict);
+
+// Locks every user that joins, so that their commands get dropped until an
+// operator vets and unlocks them. Users that are already operators when they
+// join aren't affected. Disabled by default, survives resets.
+bool DP_acl_state_lock_new_users(DP_AclState *acls);
+
+void DP_acl_state_lock_new_users_set(DP_AclState *acls, bool lock_new_users);
+
+// Treats every authenticated user as trusted, for sessions where logging in is
+// vetting enough. Disabled by default, survives resets.
+bool DP_acl_state_authenticated_are_trusted(DP_AclState *acls);
+
+void DP_acl_state_authenticated_are_trusted_set(DP_AclState *acls,
+                                                bool authenticated_are_trusted);
+
+// Freezing a feature sets it to the given tier and keeps it there, no matter
+// what feature access level messages say, even ones from operators. Other
+// features in the same message still get applied. Resets, snapshot restores
+// and imported policies leave frozen features alone too, so the host can use
+// this to enforce a fixed permission policy. Freezing returns
+// DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT if the tier changed, 0 otherwise.
+// Nothing is frozen by default. Survives resets.
+bool DP_acl_state_feature_frozen(DP_AclState *acls, DP_Feature feature);
+
+uint8_t DP_acl_state_feature_freeze(DP_AclState *acls, DP_Feature feature,
+                                    DP_AccessTier tier);
+
+void DP_acl_state_feature_unfreeze(DP_AclState *acls, DP_Feature feature);
+
+// The local authority is a user that can do anything, no matter what the ACLs
+// say, which is meant for tools that edit recordings. Its messages are handled
+// as if override were set. -1 means there's no such user, which is the default
+// and what live sessions should use. Survives resets.
+int DP_acl_state_local_authority(DP_AclState *acls);
+
+void DP_acl_state_local_authority_set(DP_AclState *acls, int user_id);
+
+// Local mode is entered on reset when the local user is the sole operator and
+// no limits or strict mode are configured, their commands then skip the
+// permission checks. It ends as soon as any message that could introduce
+// restrictions shows up.
+bool DP_acl_state_local_mode(DP_AclState *acls);
+
+// The audit log keeps the most recent ACL changes, up to the given capacity.
+// A capacity of zero disables it, which is the default. Setting a smaller
+// capacity drops the oldest entries. The log and its capacity survive resets
+// and restores, but aren't carried over to clones or into snapshots.
+int DP_acl_state_audit_capacity(DP_AclState *acls);
+
+void DP_acl_state_audit_capacity_set(DP_AclState *acls, int capacity);
+
+int DP_acl_state_audit_count(DP_AclState *acls);
+
+// Index 0 is the oldest entry still in the log.
+const DP_AclAuditEntry *DP_acl_state_audit_at(DP_AclState *acls, int index);
+
+// The meta message that produced the most recent accepted ACL change, or NULL
+// if there hasn't been one since the last reset or restore. Appending these to
+// a log as they come in gives a compact record of ACL transitions that can be
+// replayed to reconstruct the state, in lieu of storing full snapshots.
+DP_Message *DP_acl_state_last_change_message_noinc(DP_AclState *acls);
+
+// Contributor tracking records which users drew on each layer through dabs,
+// put image and fill rect commands that got through. It's disabled by default
+// and disabling it drops everything recorded so far. The setting survives
+// resets and restores, but the recorded contributors don't. Deleting a layer
+// forgets its contributors, since the id may get reused.
+bool DP_acl_state_contributors_tracked(DP_AclState *acls);
+
+void DP_acl_state_contributors_tracked_set(DP_AclState *acls, bool tracked);
+
+// Writes the ids of users that drew on the layer in ascending order, the
+// buffer must have room for 256 of them. Returns how many were written.
+int DP_acl_state_layer_contributors(DP_AclState *acls, int layer_id,
+                                    uint8_t *out_user_ids);
+
+// The orphan policy and heir are configuration, they survive resets and
+// restores. The ownership changes made because of them don't, they also get
+// dropped when the layer is deleted. The heir is only used when transferring.
+DP_AclOrphanPolicy DP_acl_state_orphan_policy(DP_AclState *acls);
+
+uint8_t DP_acl_state_orphan_heir_id(DP_AclState *acls);
+
+void DP_acl_state_orphan_policy_set(DP_AclState *acls,
+                                    DP_AclOrphanPolicy policy,
+                                    uint8_t heir_id);
+
+// Returns the id of the user that owns the layer for the purposes of the
+// own layers feature, 0 if the layer is orphaned.
+uint8_t DP_acl_state_layer_owner(DP_AclState *acls, int layer_id);
+
+bool DP_acl_state_layer_orphaned(DP_AclState *acls, int layer_id);
+
+// Writes the ids of orphaned layers in no particular order, the buffer must
+// have room for all of them. Returns how many there are, pass NULL to only
+// count them.
+int DP_acl_state_orphaned_layers(DP_AclState *acls, int *out_layer_ids_or_null);
+
+// Transfers ownership of an orphaned layer to the given operator. Returns
+// DP_ACL_STATE_CHANGE_LAYERS_BIT or 0 if the layer isn't orphaned or the user
+// isn't an operator.
+uint8_t DP_acl_state_claim_layer(DP_AclState *acls, int layer_id,
+                                 uint8_t user_id) DP_MUST_CHECK;
+
+// Writes the layers that have an exclusive list in effect that doesn't include
+// their owner, such as when an operator grants someone else exclusive access
+// to a layer created under the own layers feature, so that the UI can warn
+// about it. Orphaned layers aren't included, since they have no owner. The
+// buffer must have room for all of them. Returns how many there are, pass
+// NULL to only count them.
+int DP_acl_state_access_conflicts(DP_AclState *acls,
+                                  DP_AclAccessConflict *out_conflicts_or_null);
+
 DP_UserAcls DP_acl_state_users(DP_AclState *acls);
 
 DP_FeatureTiers DP_acl_state_feature_tiers(DP_AclState *acls);
 
+// Puts back feature tiers previously gotten from DP_acl_state_feature_tiers,
+// for trying out permission changes and then reverting them. Lighter than a
+// full snapshot, since nothing else is touched. This only changes the local
+// state, no messages are involved. Frozen features keep their tier. Returns
+// DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT if any tier changed, 0 otherwise.
+uint8_t DP_acl_state_feature_tiers_restore(DP_AclState *acls,
+                                           const DP_FeatureTiers *tiers);
+
 void DP_acl_state_layers_each(DP_AclState *acls, DP_AclStateLayerFn fn,
                               void *user);
 
+// Returns the ACL applied to newly created layers or NULL if there is none.
+const DP_LayerAcl *DP_acl_state_default_layer_acl(DP_AclState *acls);
+
 bool DP_acl_state_is_op(DP_AclState *acls, uint8_t user_id);
 
 DP_AccessTier DP_acl_state_user_tier(DP_AclState *acls, uint8_t user_id);
@@ -166,19 +653,145 @@ DP_AccessTier DP_acl_state_user_tier(DP_AclState *acls, uint8_t user_id);
 bool DP_acl_state_can_use_feature(DP_AclState *acls, DP_Feature feature,
                                   uint8_t user_id);
 
+// DP_feature_tiers_blocked_for with the current feature tiers.
+int DP_acl_state_blocked_features_for_tier(DP_AclState *acls,
+                                           DP_AccessTier tier,
+                                           DP_Feature *out_features_or_null);
+
+// Whether any layer ACL locks its layer for anyone. If not, every layer is
+// unlocked for everyone and DP_acl_state_layer_locked_for doesn't need to look
+// anything up. Alpha locks don't count.
+bool DP_acl_state_any_layer_locked(DP_AclState *acls);
+
 bool DP_acl_state_layer_locked_for(DP_AclState *acls, uint8_t user_id,
                                    int layer_id);
 
+// Filters the given layers down to the ones that aren't locked for the user,
+// keeping them in the same order. The output must have room for count layer
+// ids, it can be the same buffer as the input. Returns how many were written.
+// Only layer locks are considered, see DP_acl_state_layer_locked_for.
+int DP_acl_state_drawable_layers(DP_AclState *acls, uint8_t user_id,
+                                 int count, const int *layer_ids,
+                                 int *out_layer_ids);
+
+// Whether the user is barred from erasing on the layer due to an alpha lock.
+bool DP_acl_state_layer_alpha_locked_for(DP_AclState *acls, uint8_t user_id,
+                                         int layer_id);
+
+// Whether the layer is immutable, see DP_LayerAcl. Immutable layers are also
+// locked for everyone as far as DP_acl_state_layer_locked_for is concerned.
+bool DP_acl_state_layer_immutable(DP_AclState *acls, int layer_id);
+
 bool DP_acl_state_annotation_locked(DP_AclState *acls, int annotation_id);
 
+// Layers in the report are sorted by id. Dispose the report when done with it.
+void DP_acl_state_permission_report(DP_AclState *acls, uint8_t user_id,
+                                    DP_AclPermissionReport *out_report);
+
+void DP_acl_permission_report_dispose(DP_AclPermissionReport *report);
+
+// Features and tiers are keyed by their short names. Free the returned value
+// with json_value_free.
+JSON_Value *
+DP_acl_permission_report_to_json(const DP_AclPermissionReport *report);
+
 // Returns a set of flags describing the outcome. If DP_ACL_STATE_FILTERED_BIT
 // is set, the message was filtered out and should not be processed further. If
 // any of the DP_ACL_STATE_CHANGE_*_BITs are set, the ACL state itself changed
 // and UI elements should be updated as appropriate. If override is set,
-// permissions aren't checked, the message is always processed.
+// permissions aren't checked, the message is always processed. The same goes
+// for system messages, see DP_message_is_system.
 uint8_t DP_acl_state_handle(DP_AclState *acls, DP_Message *msg,
                             bool override) DP_MUST_CHECK;
 
+// Every accepted change to a layer's ACL bumps its version, starting from zero.
+// This includes the session lock on layer 0 and the default layer ACL. The
+// versions are cleared on resets and restores and aren't carried into clones.
+unsigned int DP_acl_state_layer_acl_version(DP_AclState *acls, int layer_id);
+
+// Like DP_acl_state_handle, but layer ACL messages are rejected if the layer's
+// ACL version doesn't match the expected one, meaning the edit was based on a
+// stale view. Clients can then pick up the current ACL and try again.
+uint8_t DP_acl_state_handle_versioned(DP_AclState *acls, DP_Message *msg,
+                                      unsigned int expected_version,
+                                      bool override) DP_MUST_CHECK;
+
+// Like DP_acl_state_handle, but the message is stamped with a sequence number
+// by the server, so that layer ACL changes don't retroactively reject commands
+// that users sent before they could know about them. The sequence a command is
+// stamped with is the one of the last message its sender had seen. A layer ACL
+// set by a sequenced message only applies to commands stamped with its
+// sequence number or later, earlier ones are checked as if the layer had no
+// ACL. ACLs set any other way and unsequenced commands aren't affected. Only
+// layer locks and alpha locks are deferred, the session lock isn't.
+uint8_t DP_acl_state_handle_sequenced(DP_AclState *acls, DP_Message *msg,
+                                      long long sequence,
+                                      bool override) DP_MUST_CHECK;
+
+// Like DP_acl_state_handle, but only returns whether the message was accepted,
+// for callers that don't display the ACL state, such as headless servers. The
+// state changes are exactly the same. The change bits themselves are nearly
+// free, since the audit log and layer ACL versions rely on them anyway, so
+// this just spares the caller from having to pick the result apart.
+bool DP_acl_state_handle_fast(DP_AclState *acls, DP_Message *msg,
+                              bool override) DP_MUST_CHECK;
+
+// Like DP_acl_state_handle, but the message goes through the preprocessor
+// first, so that servers can sanitize or normalize messages before the ACL
+// state judges them. The message that was actually handled is written to
+// out_msg as a new reference, that's the one to pass on if it isn't filtered.
+// If the preprocessor drops the message, out_msg is set to NULL and the result
+// is filtered with DP_ACL_REJECTION_PREPROCESSOR_DROPPED.
+uint8_t DP_acl_state_handle_preprocessed(DP_AclState *acls, DP_Message *msg,
+                                         bool override,
+                                         DP_AclStatePreprocessFn preprocess,
+                                         void *user,
+                                         DP_Message **out_msg) DP_MUST_CHECK;
+
+// Sets a layer's ACL directly, as if an operator had sent a layer ACL message
+// for it, but without needing to construct one. Drawing on the layer is then
+// limited to users of the given tier or better and, if any are given, to the
+// exclusive users. With the guest tier and no exclusive users, the layer is
+// locked for everyone. Returns DP_ACL_STATE_CHANGE_LAYERS_BIT or 0 if the ACL
+// was already like that. Unlocking drops the layer's ACL altogether. Neither
+// changes whether the layer is immutable, an immutable layer stays that way.
+uint8_t DP_acl_state_lock_layer(DP_AclState *acls, int layer_id,
+                                DP_AccessTier tier, int exclusive_count,
+                                const uint8_t *exclusive);
+
+uint8_t DP_acl_state_unlock_layer(DP_AclState *acls, int layer_id);
+
+// Locks the given DP_ACL_LOCK_CATEGORY_* bits for everyone, as if an operator
+// had sent a session lock categories message, and unlocks the others. Commands
+// in a locked category get rejected unless overridden, even from operators,
+// just like with the session lock. Other commands aren't affected. Cleared on
+// resets. Returns DP_ACL_STATE_CHANGE_USERS_BIT or 0 if nothing changed.
+uint8_t DP_acl_state_locked_categories(DP_AclState *acls);
+
+uint8_t DP_acl_state_lock_categories(DP_AclState *acls, uint8_t categories);
+
+// Opens up the session fully in one go: drops every layer ACL and the default
+// layer ACL, unlocks all users, lifts the session and category locks and puts
+// every feature on the guest tier, except for frozen ones. Returns the combined
+// DP_ACL_STATE_CHANGE_*_BITs of whatever actually changed.
+uint8_t DP_acl_state_open_all(DP_AclState *acls);
+
+// Runs the messages through a copy of the ACL state, writing whether each one
+// would be accepted to out_accepted. The ACL state itself is left untouched.
+void DP_acl_state_dry_run(DP_AclState *acls, int count, DP_Message **msgs,
+                          bool *out_accepted);
+
+// Why the last message given to DP_acl_state_handle was filtered out. Gives
+// DP_ACL_REJECTION_NONE if it wasn't filtered.
+DP_AclRejection DP_acl_state_last_rejection(DP_AclState *acls);
+
+// The tier a user needs to be on to send the given message, going by the tier
+// currently assigned to its governing feature. Returns false if the message
+// isn't governed by a feature, so no tier would let a user send it if it was
+// rejected. Meant for explaining DP_ACL_REJECTION_FEATURE_TIER rejections.
+bool DP_acl_state_required_tier(DP_AclState *acls, DP_Message *msg,
+                                DP_AccessTier *out_tier);
+
 DP_Message *DP_acl_state_msg_feature_access_all_new(unsigned int context_id);
 
 bool DP_acl_state_reset_image_build(DP_AclState *acls, unsigned int context_id,
@@ -186,5 +799,28 @@ bool DP_acl_state_reset_image_build(DP_AclState *acls, unsigned int context_id,
                                     bool (*push_message)(void *, DP_Message *),
                                     void *user);
 
+// The two halves of DP_acl_state_reset_image_build. Creating a layer applies
+// the default layer ACL to it, so when the reset image also creates layers,
+// the layer ACLs must come after them, while the session part, feature tiers
+// and user permissions, should come before any commands.
+bool DP_acl_state_reset_image_build_layers(
+    DP_AclState *acls, unsigned int context_id, unsigned int include_flags,
+    bool (*push_message)(void *, DP_Message *), void *user);
+
+bool DP_acl_state_reset_image_build_session(
+    DP_AclState *acls, unsigned int context_id, unsigned int include_flags,
+    bool (*push_message)(void *, DP_Message *), void *user);
+
+// Writes DP_ACL_POLICY_LENGTH bytes of policy to out. Exclusive users of the
+// default layer ACL aren't included, since that's transient user state.
+void DP_acl_state_policy_export(DP_AclState *acls, unsigned char *out);
+
+// Replaces the feature tiers and default layer ACL with the given policy blob.
+// Returns a set of DP_ACL_STATE_CHANGE_*_BITs on success. On failure, e.g.
+// because of a version mismatch, sets an error, leaves the ACL state
+// unchanged and returns DP_ACL_STATE_FILTERED_BIT.
+uint8_t DP_acl_state_policy_import(DP_AclState *acls, const unsigned char *in,
+                                   size_t length) DP_MUST_CHECK;
+
 
 #endif
@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "acl_change_accumulator.h"
+#include "acl.h"
+#include <dpcommon/common.h>
+
+
+DP_AclChangeAccumulator DP_acl_change_accumulator_make(long long quiet_ms,
+                                                       int max_count)
+{
+    DP_ASSERT(quiet_ms >= 0);
+    return (DP_AclChangeAccumulator){quiet_ms, max_count, 0, 0, 0};
+}
+
+bool DP_acl_change_accumulator_pending(const DP_AclChangeAccumulator *acc)
+{
+    DP_ASSERT(acc);
+    return acc->changes != 0;
+}
+
+long long
+DP_acl_change_accumulator_deadline_ms(const DP_AclChangeAccumulator *acc)
+{
+    DP_ASSERT(acc);
+    return acc->changes == 0 ? -1 : acc->last_ms + acc->quiet_ms;
+}
+
+uint8_t DP_acl_change_accumulator_flush(DP_AclChangeAccumulator *acc)
+{
+    DP_ASSERT(acc);
+    uint8_t changes = acc->changes;
+    acc->changes = 0;
+    acc->count = 0;
+    return changes;
+}
+
+uint8_t DP_acl_change_accumulator_push(DP_AclChangeAccumulator *acc,
+                                       uint8_t changes, long long now_ms)
+{
+    DP_ASSERT(acc);
+    uint8_t masked = changes & DP_ACL_STATE_CHANGE_MASK;
+    if (masked == 0) {
+        return 0;
+    }
+
+    acc->changes |= masked;
+    acc->last_ms = now_ms;
+    ++acc->count;
+    if (acc->max_count > 0 && acc->count >= acc->max_count) {
+        return DP_acl_change_accumulator_flush(acc);
+    }
+    else {
+        return 0;
+    }
+}
+
+uint8_t DP_acl_change_accumulator_poll(DP_AclChangeAccumulator *acc,
+                                       long long now_ms)
+{
+    DP_ASSERT(acc);
+    if (acc->changes != 0 && now_ms - acc->last_ms >= acc->quiet_ms) {
+        return DP_acl_change_accumulator_flush(acc);
+    }
+    else {
+        return 0;
+    }
+}
@@ -0,0 +1,47 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#ifndef DPMSG_ACL_CHANGE_ACCUMULATOR_H
+#define DPMSG_ACL_CHANGE_ACCUMULATOR_H
+#include <dpcommon/common.h>
+
+
+// Collects the DP_ACL_STATE_CHANGE_*_BITs returned by the ACL filter so that a
+// user interface can refresh once for a burst of changes, rather than for each
+// message of an operator clicking through permissions. Changes are emitted once
+// no more have come in for the quiet period or when max_count nonzero changes
+// have piled up, whichever happens first. A max_count of zero or less means
+// there's no limit on the number of changes.
+typedef struct DP_AclChangeAccumulator {
+    long long quiet_ms;
+    int max_count;
+    uint8_t changes;
+    int count;
+    long long last_ms;
+} DP_AclChangeAccumulator;
+
+DP_AclChangeAccumulator DP_acl_change_accumulator_make(long long quiet_ms,
+                                                       int max_count);
+
+// Whether there's any changes waiting to be emitted.
+bool DP_acl_change_accumulator_pending(const DP_AclChangeAccumulator *acc);
+
+// When the pending changes will be emitted if nothing else comes in, for
+// scheduling a timer to poll at. Returns -1 if there's nothing pending.
+long long
+DP_acl_change_accumulator_deadline_ms(const DP_AclChangeAccumulator *acc);
+
+// Adds the given changes, ignoring the filtered bit. Returns the coalesced
+// changes if this push reached the maximum count, 0 otherwise. Calls without
+// any changes are ignored and don't extend the quiet period.
+uint8_t DP_acl_change_accumulator_push(DP_AclChangeAccumulator *acc,
+                                       uint8_t changes, long long now_ms);
+
+// Returns the coalesced changes if the quiet period has passed since the last
+// nonzero push, 0 otherwise.
+uint8_t DP_acl_change_accumulator_poll(DP_AclChangeAccumulator *acc,
+                                       long long now_ms);
+
+// Returns the pending changes right away, e.g. when the session ends.
+uint8_t DP_acl_change_accumulator_flush(DP_AclChangeAccumulator *acc);
+
+
+#endif
@@ -22,8 +22,8 @@ static const unsigned char *local_match_data(DP_Message *msg, size_t *out_size)
 static void extract_selection_ids(int layer_id, uint8_t *out_source_id,
                                   uint8_t *out_target_id)
 {
-    *out_source_id = DP_uint16_to_uint8(layer_id & 0xff);
-    *out_target_id = DP_int_to_uint8((layer_id >> 8) & 0xff);
+    *out_source_id = DP_layer_id_index(layer_id);
+    *out_target_id = DP_layer_id_creator(layer_id);
 }
 
 
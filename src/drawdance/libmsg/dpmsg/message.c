@@ -24,6 +24,7 @@
 #include <dpcommon/atomic.h>
 #include <dpcommon/binary.h>
 #include <dpcommon/common.h>
+#include <dpcommon/conversions.h>
 
 #define FLAG_NONE            0x0
 #define FLAG_OPAQUE          0x1
@@ -182,6 +183,16 @@ bool DP_message_opaque(DP_Message *msg)
     return msg->flags & FLAG_OPAQUE;
 }
 
+bool DP_message_is_system(DP_Message *msg)
+{
+    DP_ASSERT(msg);
+    DP_ASSERT(DP_atomic_get(&msg->refcount) > 0);
+    // Filtered messages stand in for something that got rejected, so they
+    // don't get to pass through just because the server sent them.
+    return msg->context_id == 0 && msg->type < 128
+        && msg->type != DP_MSG_FILTERED;
+}
+
 const char *DP_message_name(DP_Message *msg)
 {
     return DP_message_type_name(DP_message_type(msg));
@@ -194,6 +205,15 @@ unsigned int DP_message_context_id(DP_Message *msg)
     return msg->context_id;
 }
 
+unsigned int DP_message_origin_user(DP_Message *msg)
+{
+    DP_ASSERT(msg);
+    DP_ASSERT(DP_atomic_get(&msg->refcount) > 0);
+    return DP_message_type_control((DP_MessageType)msg->type)
+             ? 0
+             : msg->context_id;
+}
+
 void DP_message_context_id_set(DP_Message *msg, unsigned int context_id)
 {
     DP_ASSERT(msg);
@@ -324,31 +344,37 @@ bool DP_message_payload_equals(DP_Message *msg, DP_Message *other)
 }
 
 
-DP_Message *DP_message_deserialize_length(const unsigned char *buf,
-                                          size_t bufsize, size_t body_length,
-                                          bool decode_opaque)
+static DP_Message *deserialize_length(const unsigned char *buf, size_t bufsize,
+                                      size_t body_length, bool decode_opaque,
+                                      DP_MessageUnknownPolicy unknown_policy)
 {
     DP_ASSERT(buf);
     size_t total_length = 2 + body_length;
-    if (bufsize >= total_length) {
-        return DP_message_deserialize_body(buf[0], buf[1], buf + 2, body_length,
-                                           decode_opaque);
-    }
-    else {
+    if (bufsize < total_length) {
         DP_error_set("Buffer size %zu shorter than message length %zu", bufsize,
                      total_length);
         return NULL;
     }
+    else if (unknown_policy == DP_MESSAGE_UNKNOWN_PASSTHROUGH
+             && !DP_message_type_known(buf[0])) {
+        return DP_message_new_opaque((DP_MessageType)buf[0], buf[1], buf + 2,
+                                     body_length);
+    }
+    else {
+        return DP_message_deserialize_body(buf[0], buf[1], buf + 2, body_length,
+                                           decode_opaque);
+    }
 }
 
-DP_Message *DP_message_deserialize(const unsigned char *buf, size_t bufsize,
-                                   bool decode_opaque)
+static DP_Message *deserialize(const unsigned char *buf, size_t bufsize,
+                               bool decode_opaque,
+                               DP_MessageUnknownPolicy unknown_policy)
 {
     if (bufsize >= DP_MESSAGE_HEADER_LENGTH) {
         DP_ASSERT(buf);
         size_t body_length = DP_read_bigendian_uint16(buf);
-        return DP_message_deserialize_length(buf + 2, bufsize - 2, body_length,
-                                             decode_opaque);
+        return deserialize_length(buf + 2, bufsize - 2, body_length,
+                                  decode_opaque, unknown_policy);
     }
     else {
         DP_error_set("Buffer size %zu too short for message header", bufsize);
@@ -356,6 +382,69 @@ DP_Message *DP_message_deserialize(const unsigned char *buf, size_t bufsize,
     }
 }
 
+DP_Message *DP_message_deserialize_length(const unsigned char *buf,
+                                          size_t bufsize, size_t body_length,
+                                          bool decode_opaque)
+{
+    return deserialize_length(buf, bufsize, body_length, decode_opaque,
+                              DP_MESSAGE_UNKNOWN_ERROR);
+}
+
+DP_Message *DP_message_deserialize(const unsigned char *buf, size_t bufsize,
+                                   bool decode_opaque)
+{
+    return deserialize(buf, bufsize, decode_opaque, DP_MESSAGE_UNKNOWN_ERROR);
+}
+
+DP_Message *
+DP_message_deserialize_with_policy(const unsigned char *buf, size_t bufsize,
+                                   bool decode_opaque,
+                                   DP_MessageUnknownPolicy unknown_policy)
+{
+    return deserialize(buf, bufsize, decode_opaque, unknown_policy);
+}
+
+DP_Message *DP_message_deserialize_exact(const unsigned char *buf,
+                                         size_t bufsize)
+{
+    if (!buf || bufsize < DP_MESSAGE_HEADER_LENGTH) {
+        DP_error_set("Buffer size %zu too short for message header", bufsize);
+        return NULL;
+    }
+
+    size_t body_length = DP_read_bigendian_uint16(buf);
+    size_t total_length = DP_MESSAGE_HEADER_LENGTH + body_length;
+    if (bufsize != total_length) {
+        DP_error_set("Buffer size %zu doesn't match message length %zu",
+                     bufsize, total_length);
+        return NULL;
+    }
+
+    return DP_message_deserialize_body(buf[2], buf[3],
+                                       buf + DP_MESSAGE_HEADER_LENGTH,
+                                       body_length, true);
+}
+
+
+int DP_layer_id_make(uint8_t creator_id, uint8_t index)
+{
+    return (creator_id << 8) | index;
+}
+
+uint8_t DP_layer_id_creator(int layer_id)
+{
+    DP_ASSERT(layer_id >= 0);
+    DP_ASSERT(layer_id <= UINT16_MAX);
+    return DP_int_to_uint8((layer_id >> 8) & 0xff);
+}
+
+uint8_t DP_layer_id_index(int layer_id)
+{
+    DP_ASSERT(layer_id >= 0);
+    DP_ASSERT(layer_id <= UINT16_MAX);
+    return DP_int_to_uint8(layer_id & 0xff);
+}
+
 
 bool DP_message_compat_flag_indirect(DP_Message *msg)
 {
@@ -62,10 +62,21 @@ DP_MessageType DP_message_type(DP_Message *msg);
 
 bool DP_message_opaque(DP_Message *msg);
 
+// System messages are meta messages originating from the server itself, as
+// signified by context id 0. They always pass the ACL filter. Commands from
+// context id 0 don't count, those are just trusted as coming from an operator.
+bool DP_message_is_system(DP_Message *msg);
+
 const char *DP_message_name(DP_Message *msg);
 
 unsigned int DP_message_context_id(DP_Message *msg);
 
+// The user that sent the message, regardless of its category. Returns 0 if
+// there is none, which is the case for control messages, system messages and
+// commands from context id 0. Messages that the server sends on behalf of a
+// user, like joins and leaves, carry that user as their context id.
+unsigned int DP_message_origin_user(DP_Message *msg);
+
 void DP_message_context_id_set(DP_Message *msg, unsigned int context_id);
 
 void *DP_message_internal(DP_Message *msg);
@@ -96,6 +107,38 @@ DP_Message *DP_message_deserialize_length(const unsigned char *buf,
 DP_Message *DP_message_deserialize(const unsigned char *buf, size_t bufsize,
                                    bool decode_opaque);
 
+// What to do when deserializing a message type that's not known, such as one
+// sent by a newer client. Erroring is what the other deserialization functions
+// do. Passing through keeps the raw body in an opaque message instead, so that
+// a relay can forward it without understanding it. DP_message_type_known tells
+// such messages apart from ones that were left opaque on purpose.
+typedef enum DP_MessageUnknownPolicy {
+    DP_MESSAGE_UNKNOWN_ERROR,
+    DP_MESSAGE_UNKNOWN_PASSTHROUGH,
+} DP_MessageUnknownPolicy;
+
+DP_Message *
+DP_message_deserialize_with_policy(const unsigned char *buf, size_t bufsize,
+                                   bool decode_opaque,
+                                   DP_MessageUnknownPolicy unknown_policy);
+
+// Decodes exactly one message, including opaque ones, from an untrusted
+// buffer. Unlike the other deserialization functions, trailing bytes are an
+// error. Never crashes on malformed input, it returns NULL and sets an error
+// instead. Intended as the single entry point for fuzzing the decoder.
+DP_Message *DP_message_deserialize_exact(const unsigned char *buf,
+                                         size_t bufsize);
+
+
+// Layer ids carry the id of the user that created the layer in their high
+// byte and an index picked by that user in their low byte. Use these instead
+// of shifting bits around by hand.
+int DP_layer_id_make(uint8_t creator_id, uint8_t index);
+
+uint8_t DP_layer_id_creator(int layer_id);
+
+uint8_t DP_layer_id_index(int layer_id);
+
 
 bool DP_message_compat_flag_indirect(DP_Message *msg);
 void DP_message_compat_flag_indirect_set(DP_Message *msg);
@@ -76,6 +76,10 @@ bool DP_message_type_client_meta(DP_MessageType type)
     case DP_MSG_UNDO_DEPTH:
     case DP_MSG_DATA:
     case DP_MSG_LOCAL_CHANGE:
+    case DP_MSG_LAYER_ACL_EXCLUSIVE:
+    case DP_MSG_CLIENT_META:
+    case DP_MSG_COSMETIC_MUTE:
+    case DP_MSG_SESSION_LOCK_CATEGORIES:
         return true;
     default:
         return false;
@@ -126,6 +130,91 @@ bool DP_message_type_command(DP_MessageType type)
     case DP_MSG_SELECTION_PUT:
     case DP_MSG_SELECTION_CLEAR:
     case DP_MSG_LOCAL_MATCH:
+    case DP_MSG_LAYER_ORDER_MOVE:
+    case DP_MSG_ANNOTATION_TEXT_EDIT:
+    case DP_MSG_UNDO:
+        return true;
+    default:
+        return false;
+    }
+}
+
+bool DP_message_type_known(int type)
+{
+    switch (type) {
+    case DP_MSG_SERVER_COMMAND:
+    case DP_MSG_DISCONNECT:
+    case DP_MSG_PING:
+    case DP_MSG_KEEP_ALIVE:
+    case DP_MSG_INTERNAL:
+    case DP_MSG_JOIN:
+    case DP_MSG_LEAVE:
+    case DP_MSG_SESSION_OWNER:
+    case DP_MSG_CHAT:
+    case DP_MSG_TRUSTED_USERS:
+    case DP_MSG_SOFT_RESET:
+    case DP_MSG_PRIVATE_CHAT:
+    case DP_MSG_RESET_STREAM:
+    case DP_MSG_INTERVAL:
+    case DP_MSG_LASER_TRAIL:
+    case DP_MSG_MOVE_POINTER:
+    case DP_MSG_MARKER:
+    case DP_MSG_USER_ACL:
+    case DP_MSG_LAYER_ACL:
+    case DP_MSG_FEATURE_ACCESS_LEVELS:
+    case DP_MSG_DEFAULT_LAYER:
+    case DP_MSG_FILTERED:
+    case DP_MSG_EXTENSION:
+    case DP_MSG_UNDO_DEPTH:
+    case DP_MSG_DATA:
+    case DP_MSG_LOCAL_CHANGE:
+    case DP_MSG_LAYER_ACL_EXCLUSIVE:
+    case DP_MSG_CLIENT_META:
+    case DP_MSG_COSMETIC_MUTE:
+    case DP_MSG_SESSION_LOCK_CATEGORIES:
+    case DP_MSG_UNDO_POINT:
+    case DP_MSG_CANVAS_RESIZE:
+    case DP_MSG_LAYER_CREATE:
+    case DP_MSG_LAYER_ATTRIBUTES:
+    case DP_MSG_LAYER_RETITLE:
+    case DP_MSG_LAYER_ORDER:
+    case DP_MSG_LAYER_DELETE:
+    case DP_MSG_LAYER_VISIBILITY:
+    case DP_MSG_PUT_IMAGE:
+    case DP_MSG_FILL_RECT:
+    case DP_MSG_TOOL_CHANGE:
+    case DP_MSG_PEN_MOVE:
+    case DP_MSG_PEN_UP:
+    case DP_MSG_ANNOTATION_CREATE:
+    case DP_MSG_ANNOTATION_RESHAPE:
+    case DP_MSG_ANNOTATION_EDIT:
+    case DP_MSG_ANNOTATION_DELETE:
+    case DP_MSG_MOVE_REGION:
+    case DP_MSG_PUT_TILE:
+    case DP_MSG_CANVAS_BACKGROUND:
+    case DP_MSG_DRAW_DABS_CLASSIC:
+    case DP_MSG_DRAW_DABS_PIXEL:
+    case DP_MSG_DRAW_DABS_PIXEL_SQUARE:
+    case DP_MSG_DRAW_DABS_MYPAINT:
+    case DP_MSG_MOVE_RECT:
+    case DP_MSG_SET_METADATA_INT:
+    case DP_MSG_LAYER_TREE_CREATE:
+    case DP_MSG_LAYER_TREE_MOVE:
+    case DP_MSG_LAYER_TREE_DELETE:
+    case DP_MSG_TRANSFORM_REGION:
+    case DP_MSG_TRACK_CREATE:
+    case DP_MSG_TRACK_RETITLE:
+    case DP_MSG_TRACK_DELETE:
+    case DP_MSG_TRACK_ORDER:
+    case DP_MSG_KEY_FRAME_SET:
+    case DP_MSG_KEY_FRAME_RETITLE:
+    case DP_MSG_KEY_FRAME_LAYER_ATTRIBUTES:
+    case DP_MSG_KEY_FRAME_DELETE:
+    case DP_MSG_SELECTION_PUT:
+    case DP_MSG_SELECTION_CLEAR:
+    case DP_MSG_LOCAL_MATCH:
+    case DP_MSG_LAYER_ORDER_MOVE:
+    case DP_MSG_ANNOTATION_TEXT_EDIT:
     case DP_MSG_UNDO:
         return true;
     default:
@@ -188,6 +277,14 @@ const char *DP_message_type_name(DP_MessageType type)
         return "data";
     case DP_MSG_LOCAL_CHANGE:
         return "localchange";
+    case DP_MSG_LAYER_ACL_EXCLUSIVE:
+        return "layeraclexclusive";
+    case DP_MSG_CLIENT_META:
+        return "clientmeta";
+    case DP_MSG_COSMETIC_MUTE:
+        return "cosmeticmute";
+    case DP_MSG_SESSION_LOCK_CATEGORIES:
+        return "sessionlockcategories";
     case DP_MSG_UNDO_POINT:
         return "undopoint";
     case DP_MSG_CANVAS_RESIZE:
@@ -270,6 +367,10 @@ const char *DP_message_type_name(DP_MessageType type)
         return "selectionclear";
     case DP_MSG_LOCAL_MATCH:
         return "localmatch";
+    case DP_MSG_LAYER_ORDER_MOVE:
+        return "layerordermove";
+    case DP_MSG_ANNOTATION_TEXT_EDIT:
+        return "annotationtextedit";
     case DP_MSG_UNDO:
         return "undo";
     default:
@@ -332,6 +433,14 @@ const char *DP_message_type_enum_name(DP_MessageType type)
         return "DP_MSG_DATA";
     case DP_MSG_LOCAL_CHANGE:
         return "DP_MSG_LOCAL_CHANGE";
+    case DP_MSG_LAYER_ACL_EXCLUSIVE:
+        return "DP_MSG_LAYER_ACL_EXCLUSIVE";
+    case DP_MSG_CLIENT_META:
+        return "DP_MSG_CLIENT_META";
+    case DP_MSG_COSMETIC_MUTE:
+        return "DP_MSG_COSMETIC_MUTE";
+    case DP_MSG_SESSION_LOCK_CATEGORIES:
+        return "DP_MSG_SESSION_LOCK_CATEGORIES";
     case DP_MSG_UNDO_POINT:
         return "DP_MSG_UNDO_POINT";
     case DP_MSG_CANVAS_RESIZE:
@@ -414,6 +523,10 @@ const char *DP_message_type_enum_name(DP_MessageType type)
         return "DP_MSG_SELECTION_CLEAR";
     case DP_MSG_LOCAL_MATCH:
         return "DP_MSG_LOCAL_MATCH";
+    case DP_MSG_LAYER_ORDER_MOVE:
+        return "DP_MSG_LAYER_ORDER_MOVE";
+    case DP_MSG_ANNOTATION_TEXT_EDIT:
+        return "DP_MSG_ANNOTATION_TEXT_EDIT";
     case DP_MSG_UNDO:
         return "DP_MSG_UNDO";
     default:
@@ -501,6 +614,18 @@ DP_MessageType DP_message_type_from_name(const char *type_name,
     else if (DP_str_equal(type_name, "localchange")) {
         return DP_MSG_LOCAL_CHANGE;
     }
+    else if (DP_str_equal(type_name, "layeraclexclusive")) {
+        return DP_MSG_LAYER_ACL_EXCLUSIVE;
+    }
+    else if (DP_str_equal(type_name, "clientmeta")) {
+        return DP_MSG_CLIENT_META;
+    }
+    else if (DP_str_equal(type_name, "cosmeticmute")) {
+        return DP_MSG_COSMETIC_MUTE;
+    }
+    else if (DP_str_equal(type_name, "sessionlockcategories")) {
+        return DP_MSG_SESSION_LOCK_CATEGORIES;
+    }
     else if (DP_str_equal(type_name, "undopoint")) {
         return DP_MSG_UNDO_POINT;
     }
@@ -618,6 +743,12 @@ DP_MessageType DP_message_type_from_name(const char *type_name,
     else if (DP_str_equal(type_name, "localmatch")) {
         return DP_MSG_LOCAL_MATCH;
     }
+    else if (DP_str_equal(type_name, "layerordermove")) {
+        return DP_MSG_LAYER_ORDER_MOVE;
+    }
+    else if (DP_str_equal(type_name, "annotationtextedit")) {
+        return DP_MSG_ANNOTATION_TEXT_EDIT;
+    }
     else if (DP_str_equal(type_name, "undo")) {
         return DP_MSG_UNDO;
     }
@@ -703,6 +834,16 @@ DP_Message *DP_message_deserialize_body(int type, unsigned int context_id,
             return DP_msg_data_deserialize(context_id, buf, length);
         case DP_MSG_LOCAL_CHANGE:
             return DP_msg_local_change_deserialize(context_id, buf, length);
+        case DP_MSG_LAYER_ACL_EXCLUSIVE:
+            return DP_msg_layer_acl_exclusive_deserialize(context_id, buf,
+                                                          length);
+        case DP_MSG_CLIENT_META:
+            return DP_msg_client_meta_deserialize(context_id, buf, length);
+        case DP_MSG_COSMETIC_MUTE:
+            return DP_msg_cosmetic_mute_deserialize(context_id, buf, length);
+        case DP_MSG_SESSION_LOCK_CATEGORIES:
+            return DP_msg_session_lock_categories_deserialize(context_id, buf,
+                                                              length);
         case DP_MSG_UNDO_POINT:
             return DP_msg_undo_point_deserialize(context_id, buf, length);
         case DP_MSG_CANVAS_RESIZE:
@@ -800,6 +941,11 @@ DP_Message *DP_message_deserialize_body(int type, unsigned int context_id,
             return DP_msg_selection_clear_deserialize(context_id, buf, length);
         case DP_MSG_LOCAL_MATCH:
             return DP_msg_local_match_deserialize(context_id, buf, length);
+        case DP_MSG_LAYER_ORDER_MOVE:
+            return DP_msg_layer_order_move_deserialize(context_id, buf, length);
+        case DP_MSG_ANNOTATION_TEXT_EDIT:
+            return DP_msg_annotation_text_edit_deserialize(context_id, buf,
+                                                           length);
         case DP_MSG_UNDO:
             return DP_msg_undo_deserialize(context_id, buf, length);
         default:
@@ -871,6 +1017,14 @@ DP_Message *DP_message_parse_body(DP_MessageType type, unsigned int context_id,
         return DP_msg_data_parse(context_id, reader);
     case DP_MSG_LOCAL_CHANGE:
         return DP_msg_local_change_parse(context_id, reader);
+    case DP_MSG_LAYER_ACL_EXCLUSIVE:
+        return DP_msg_layer_acl_exclusive_parse(context_id, reader);
+    case DP_MSG_CLIENT_META:
+        return DP_msg_client_meta_parse(context_id, reader);
+    case DP_MSG_COSMETIC_MUTE:
+        return DP_msg_cosmetic_mute_parse(context_id, reader);
+    case DP_MSG_SESSION_LOCK_CATEGORIES:
+        return DP_msg_session_lock_categories_parse(context_id, reader);
     case DP_MSG_UNDO_POINT:
         return DP_msg_undo_point_parse(context_id, reader);
     case DP_MSG_CANVAS_RESIZE:
@@ -956,6 +1110,10 @@ DP_Message *DP_message_parse_body(DP_MessageType type, unsigned int context_id,
         return DP_msg_selection_clear_parse(context_id, reader);
     case DP_MSG_LOCAL_MATCH:
         return DP_msg_local_match_parse(context_id, reader);
+    case DP_MSG_LAYER_ORDER_MOVE:
+        return DP_msg_layer_order_move_parse(context_id, reader);
+    case DP_MSG_ANNOTATION_TEXT_EDIT:
+        return DP_msg_annotation_text_edit_parse(context_id, reader);
     case DP_MSG_UNDO:
         return DP_msg_undo_parse(context_id, reader);
     default:
@@ -3656,6 +3814,413 @@ size_t DP_msg_local_change_body_size(const DP_MsgLocalChange *mlc)
 }
 
 
+/* DP_MSG_LAYER_ACL_EXCLUSIVE */
+
+struct DP_MsgLayerAclExclusive {
+    uint16_t id;
+    uint8_t user;
+    bool grant;
+};
+
+static size_t msg_layer_acl_exclusive_payload_length(DP_UNUSED DP_Message *msg)
+{
+    return ((size_t)4);
+}
+
+static size_t msg_layer_acl_exclusive_serialize_payload(DP_Message *msg,
+                                                        unsigned char *data)
+{
+    DP_MsgLayerAclExclusive *mlae = DP_message_internal(msg);
+    size_t written = 0;
+    written += DP_write_bigendian_uint16(mlae->id, data + written);
+    written += DP_write_bigendian_uint8(mlae->user, data + written);
+    written += DP_write_bigendian_uint8(mlae->grant, data + written);
+    DP_ASSERT(written == msg_layer_acl_exclusive_payload_length(msg));
+    return written;
+}
+
+static bool msg_layer_acl_exclusive_write_payload_text(DP_Message *msg,
+                                                       DP_TextWriter *writer)
+{
+    DP_MsgLayerAclExclusive *mlae = DP_message_internal(msg);
+    return DP_text_writer_write_bool(writer, "grant", mlae->grant)
+        && DP_text_writer_write_uint(writer, "id", mlae->id, true)
+        && DP_text_writer_write_uint(writer, "user", mlae->user, false);
+}
+
+static bool msg_layer_acl_exclusive_equals(DP_Message *DP_RESTRICT msg,
+                                           DP_Message *DP_RESTRICT other)
+{
+    DP_MsgLayerAclExclusive *a = DP_message_internal(msg);
+    DP_MsgLayerAclExclusive *b = DP_message_internal(other);
+    return a->id == b->id && a->user == b->user && a->grant == b->grant;
+}
+
+static const DP_MessageMethods msg_layer_acl_exclusive_methods = {
+    msg_layer_acl_exclusive_payload_length,
+    msg_layer_acl_exclusive_serialize_payload,
+    msg_layer_acl_exclusive_write_payload_text,
+    msg_layer_acl_exclusive_equals,
+};
+
+DP_Message *DP_msg_layer_acl_exclusive_new(unsigned int context_id,
+                                           uint16_t id, uint8_t user,
+                                           bool grant)
+{
+    DP_Message *msg = DP_message_new(DP_MSG_LAYER_ACL_EXCLUSIVE, context_id,
+                                     &msg_layer_acl_exclusive_methods,
+                                     sizeof(DP_MsgLayerAclExclusive));
+    DP_MsgLayerAclExclusive *mlae = DP_message_internal(msg);
+    mlae->id = id;
+    mlae->user = user;
+    mlae->grant = grant;
+    return msg;
+}
+
+DP_Message *DP_msg_layer_acl_exclusive_deserialize(unsigned int context_id,
+                                                   const unsigned char *buffer,
+                                                   size_t length)
+{
+    if (length != 4) {
+        DP_error_set("Wrong length for layeraclexclusive message; "
+                     "expected 4, got %zu",
+                     length);
+        return NULL;
+    }
+    size_t read = 0;
+    uint16_t id = read_uint16(buffer + read, &read);
+    uint8_t user = read_uint8(buffer + read, &read);
+    bool grant = read_bool(buffer + read, &read);
+    return DP_msg_layer_acl_exclusive_new(context_id, id, user, grant);
+}
+
+DP_Message *DP_msg_layer_acl_exclusive_parse(unsigned int context_id,
+                                             DP_TextReader *reader)
+{
+    uint16_t id =
+        (uint16_t)DP_text_reader_get_ulong_hex(reader, "id", UINT16_MAX);
+    uint8_t user = (uint8_t)DP_text_reader_get_ulong(reader, "user", UINT8_MAX);
+    bool grant = DP_text_reader_get_bool(reader, "grant");
+    return DP_msg_layer_acl_exclusive_new(context_id, id, user, grant);
+}
+
+DP_MsgLayerAclExclusive *DP_msg_layer_acl_exclusive_cast(DP_Message *msg)
+{
+    return DP_message_cast(msg, DP_MSG_LAYER_ACL_EXCLUSIVE);
+}
+
+uint16_t DP_msg_layer_acl_exclusive_id(const DP_MsgLayerAclExclusive *mlae)
+{
+    DP_ASSERT(mlae);
+    return mlae->id;
+}
+
+uint8_t DP_msg_layer_acl_exclusive_user(const DP_MsgLayerAclExclusive *mlae)
+{
+    DP_ASSERT(mlae);
+    return mlae->user;
+}
+
+bool DP_msg_layer_acl_exclusive_grant(const DP_MsgLayerAclExclusive *mlae)
+{
+    DP_ASSERT(mlae);
+    return mlae->grant;
+}
+
+
+/* DP_MSG_CLIENT_META */
+
+struct DP_MsgClientMeta {
+    uint8_t user;
+    uint32_t color;
+};
+
+static size_t msg_client_meta_payload_length(DP_UNUSED DP_Message *msg)
+{
+    return ((size_t)5);
+}
+
+static size_t msg_client_meta_serialize_payload(DP_Message *msg,
+                                                unsigned char *data)
+{
+    DP_MsgClientMeta *mcm = DP_message_internal(msg);
+    size_t written = 0;
+    written += DP_write_bigendian_uint8(mcm->user, data + written);
+    written += DP_write_bigendian_uint32(mcm->color, data + written);
+    DP_ASSERT(written == msg_client_meta_payload_length(msg));
+    return written;
+}
+
+static bool msg_client_meta_write_payload_text(DP_Message *msg,
+                                               DP_TextWriter *writer)
+{
+    DP_MsgClientMeta *mcm = DP_message_internal(msg);
+    return DP_text_writer_write_argb_color(writer, "color", mcm->color)
+        && DP_text_writer_write_uint(writer, "user", mcm->user, false);
+}
+
+static bool msg_client_meta_equals(DP_Message *DP_RESTRICT msg,
+                                   DP_Message *DP_RESTRICT other)
+{
+    DP_MsgClientMeta *a = DP_message_internal(msg);
+    DP_MsgClientMeta *b = DP_message_internal(other);
+    return a->user == b->user && a->color == b->color;
+}
+
+static const DP_MessageMethods msg_client_meta_methods = {
+    msg_client_meta_payload_length,
+    msg_client_meta_serialize_payload,
+    msg_client_meta_write_payload_text,
+    msg_client_meta_equals,
+};
+
+DP_Message *DP_msg_client_meta_new(unsigned int context_id, uint8_t user,
+                                   uint32_t color)
+{
+    DP_Message *msg =
+        DP_message_new(DP_MSG_CLIENT_META, context_id, &msg_client_meta_methods,
+                       sizeof(DP_MsgClientMeta));
+    DP_MsgClientMeta *mcm = DP_message_internal(msg);
+    mcm->user = user;
+    mcm->color = color;
+    return msg;
+}
+
+DP_Message *DP_msg_client_meta_deserialize(unsigned int context_id,
+                                           const unsigned char *buffer,
+                                           size_t length)
+{
+    if (length != 5) {
+        DP_error_set("Wrong length for clientmeta message; "
+                     "expected 5, got %zu",
+                     length);
+        return NULL;
+    }
+    size_t read = 0;
+    uint8_t user = read_uint8(buffer + read, &read);
+    uint32_t color = read_uint32(buffer + read, &read);
+    return DP_msg_client_meta_new(context_id, user, color);
+}
+
+DP_Message *DP_msg_client_meta_parse(unsigned int context_id,
+                                     DP_TextReader *reader)
+{
+    uint8_t user = (uint8_t)DP_text_reader_get_ulong(reader, "user", UINT8_MAX);
+    uint32_t color = DP_text_reader_get_argb_color(reader, "color");
+    return DP_msg_client_meta_new(context_id, user, color);
+}
+
+DP_MsgClientMeta *DP_msg_client_meta_cast(DP_Message *msg)
+{
+    return DP_message_cast(msg, DP_MSG_CLIENT_META);
+}
+
+uint8_t DP_msg_client_meta_user(const DP_MsgClientMeta *mcm)
+{
+    DP_ASSERT(mcm);
+    return mcm->user;
+}
+
+uint32_t DP_msg_client_meta_color(const DP_MsgClientMeta *mcm)
+{
+    DP_ASSERT(mcm);
+    return mcm->color;
+}
+
+
+/* DP_MSG_COSMETIC_MUTE */
+
+struct DP_MsgCosmeticMute {
+    uint8_t user;
+    bool mute;
+};
+
+static size_t msg_cosmetic_mute_payload_length(DP_UNUSED DP_Message *msg)
+{
+    return ((size_t)2);
+}
+
+static size_t msg_cosmetic_mute_serialize_payload(DP_Message *msg,
+                                                  unsigned char *data)
+{
+    DP_MsgCosmeticMute *mcm = DP_message_internal(msg);
+    size_t written = 0;
+    written += DP_write_bigendian_uint8(mcm->user, data + written);
+    written += DP_write_bigendian_uint8(mcm->mute, data + written);
+    DP_ASSERT(written == msg_cosmetic_mute_payload_length(msg));
+    return written;
+}
+
+static bool msg_cosmetic_mute_write_payload_text(DP_Message *msg,
+                                                 DP_TextWriter *writer)
+{
+    DP_MsgCosmeticMute *mcm = DP_message_internal(msg);
+    return DP_text_writer_write_bool(writer, "mute", mcm->mute)
+        && DP_text_writer_write_uint(writer, "user", mcm->user, false);
+}
+
+static bool msg_cosmetic_mute_equals(DP_Message *DP_RESTRICT msg,
+                                     DP_Message *DP_RESTRICT other)
+{
+    DP_MsgCosmeticMute *a = DP_message_internal(msg);
+    DP_MsgCosmeticMute *b = DP_message_internal(other);
+    return a->user == b->user && a->mute == b->mute;
+}
+
+static const DP_MessageMethods msg_cosmetic_mute_methods = {
+    msg_cosmetic_mute_payload_length,
+    msg_cosmetic_mute_serialize_payload,
+    msg_cosmetic_mute_write_payload_text,
+    msg_cosmetic_mute_equals,
+};
+
+DP_Message *DP_msg_cosmetic_mute_new(unsigned int context_id, uint8_t user,
+                                     bool mute)
+{
+    DP_Message *msg = DP_message_new(DP_MSG_COSMETIC_MUTE, context_id,
+                                     &msg_cosmetic_mute_methods,
+                                     sizeof(DP_MsgCosmeticMute));
+    DP_MsgCosmeticMute *mcm = DP_message_internal(msg);
+    mcm->user = user;
+    mcm->mute = mute;
+    return msg;
+}
+
+DP_Message *DP_msg_cosmetic_mute_deserialize(unsigned int context_id,
+                                             const unsigned char *buffer,
+                                             size_t length)
+{
+    if (length != 2) {
+        DP_error_set("Wrong length for cosmeticmute message; "
+                     "expected 2, got %zu",
+                     length);
+        return NULL;
+    }
+    size_t read = 0;
+    uint8_t user = read_uint8(buffer + read, &read);
+    bool mute = read_bool(buffer + read, &read);
+    return DP_msg_cosmetic_mute_new(context_id, user, mute);
+}
+
+DP_Message *DP_msg_cosmetic_mute_parse(unsigned int context_id,
+                                       DP_TextReader *reader)
+{
+    uint8_t user = (uint8_t)DP_text_reader_get_ulong(reader, "user", UINT8_MAX);
+    bool mute = DP_text_reader_get_bool(reader, "mute");
+    return DP_msg_cosmetic_mute_new(context_id, user, mute);
+}
+
+DP_MsgCosmeticMute *DP_msg_cosmetic_mute_cast(DP_Message *msg)
+{
+    return DP_message_cast(msg, DP_MSG_COSMETIC_MUTE);
+}
+
+uint8_t DP_msg_cosmetic_mute_user(const DP_MsgCosmeticMute *mcm)
+{
+    DP_ASSERT(mcm);
+    return mcm->user;
+}
+
+bool DP_msg_cosmetic_mute_mute(const DP_MsgCosmeticMute *mcm)
+{
+    DP_ASSERT(mcm);
+    return mcm->mute;
+}
+
+
+/* DP_MSG_SESSION_LOCK_CATEGORIES */
+
+struct DP_MsgSessionLockCategories {
+    uint8_t categories;
+};
+
+static size_t
+msg_session_lock_categories_payload_length(DP_UNUSED DP_Message *msg)
+{
+    return ((size_t)1);
+}
+
+static size_t msg_session_lock_categories_serialize_payload(DP_Message *msg,
+                                                            unsigned char *data)
+{
+    DP_MsgSessionLockCategories *mslc = DP_message_internal(msg);
+    size_t written = 0;
+    written += DP_write_bigendian_uint8(mslc->categories, data + written);
+    DP_ASSERT(written == msg_session_lock_categories_payload_length(msg));
+    return written;
+}
+
+static bool msg_session_lock_categories_write_payload_text(
+    DP_Message *msg, DP_TextWriter *writer)
+{
+    DP_MsgSessionLockCategories *mslc = DP_message_internal(msg);
+    return DP_text_writer_write_uint(writer, "categories", mslc->categories,
+                                     false);
+}
+
+static bool msg_session_lock_categories_equals(DP_Message *DP_RESTRICT msg,
+                                               DP_Message *DP_RESTRICT other)
+{
+    DP_MsgSessionLockCategories *a = DP_message_internal(msg);
+    DP_MsgSessionLockCategories *b = DP_message_internal(other);
+    return a->categories == b->categories;
+}
+
+static const DP_MessageMethods msg_session_lock_categories_methods = {
+    msg_session_lock_categories_payload_length,
+    msg_session_lock_categories_serialize_payload,
+    msg_session_lock_categories_write_payload_text,
+    msg_session_lock_categories_equals,
+};
+
+DP_Message *DP_msg_session_lock_categories_new(unsigned int context_id,
+                                               uint8_t categories)
+{
+    DP_Message *msg = DP_message_new(DP_MSG_SESSION_LOCK_CATEGORIES,
+                                     context_id,
+                                     &msg_session_lock_categories_methods,
+                                     sizeof(DP_MsgSessionLockCategories));
+    DP_MsgSessionLockCategories *mslc = DP_message_internal(msg);
+    mslc->categories = categories;
+    return msg;
+}
+
+DP_Message *DP_msg_session_lock_categories_deserialize(
+    unsigned int context_id, const unsigned char *buffer, size_t length)
+{
+    if (length != 1) {
+        DP_error_set("Wrong length for sessionlockcategories message; "
+                     "expected 1, got %zu",
+                     length);
+        return NULL;
+    }
+    size_t read = 0;
+    uint8_t categories = read_uint8(buffer + read, &read);
+    return DP_msg_session_lock_categories_new(context_id, categories);
+}
+
+DP_Message *DP_msg_session_lock_categories_parse(unsigned int context_id,
+                                                 DP_TextReader *reader)
+{
+    uint8_t categories =
+        (uint8_t)DP_text_reader_get_ulong(reader, "categories", UINT8_MAX);
+    return DP_msg_session_lock_categories_new(context_id, categories);
+}
+
+DP_MsgSessionLockCategories *
+DP_msg_session_lock_categories_cast(DP_Message *msg)
+{
+    return DP_message_cast(msg, DP_MSG_SESSION_LOCK_CATEGORIES);
+}
+
+uint8_t DP_msg_session_lock_categories_categories(
+    const DP_MsgSessionLockCategories *mslc)
+{
+    DP_ASSERT(mslc);
+    return mslc->categories;
+}
+
+
 /* DP_MSG_UNDO_POINT */
 
 DP_Message *DP_msg_undo_point_new(unsigned int context_id)
@@ -9832,6 +10397,293 @@ size_t DP_msg_local_match_data_size(const DP_MsgLocalMatch *mlm)
 }
 
 
+/* DP_MSG_LAYER_ORDER_MOVE */
+
+const char *DP_msg_layer_order_move_direction_variant_name(unsigned int value)
+{
+    switch (value) {
+    case DP_MSG_LAYER_ORDER_MOVE_DIRECTION_UP:
+        return "Up";
+    case DP_MSG_LAYER_ORDER_MOVE_DIRECTION_DOWN:
+        return "Down";
+    case DP_MSG_LAYER_ORDER_MOVE_DIRECTION_INDEX:
+        return "Index";
+    default:
+        return NULL;
+    }
+}
+
+struct DP_MsgLayerOrderMove {
+    uint16_t layer;
+    uint8_t direction;
+    uint16_t index;
+};
+
+static size_t msg_layer_order_move_payload_length(DP_UNUSED DP_Message *msg)
+{
+    return ((size_t)5);
+}
+
+static size_t msg_layer_order_move_serialize_payload(DP_Message *msg,
+                                                     unsigned char *data)
+{
+    DP_MsgLayerOrderMove *mlom = DP_message_internal(msg);
+    size_t written = 0;
+    written += DP_write_bigendian_uint16(mlom->layer, data + written);
+    written += DP_write_bigendian_uint8(mlom->direction, data + written);
+    written += DP_write_bigendian_uint16(mlom->index, data + written);
+    DP_ASSERT(written == msg_layer_order_move_payload_length(msg));
+    return written;
+}
+
+static bool msg_layer_order_move_write_payload_text(DP_Message *msg,
+                                                    DP_TextWriter *writer)
+{
+    DP_MsgLayerOrderMove *mlom = DP_message_internal(msg);
+    return DP_text_writer_write_uint(writer, "direction", mlom->direction,
+                                     false)
+        && DP_text_writer_write_uint(writer, "index", mlom->index, false)
+        && DP_text_writer_write_uint(writer, "layer", mlom->layer, true);
+}
+
+static bool msg_layer_order_move_equals(DP_Message *DP_RESTRICT msg,
+                                        DP_Message *DP_RESTRICT other)
+{
+    DP_MsgLayerOrderMove *a = DP_message_internal(msg);
+    DP_MsgLayerOrderMove *b = DP_message_internal(other);
+    return a->layer == b->layer && a->direction == b->direction
+        && a->index == b->index;
+}
+
+static const DP_MessageMethods msg_layer_order_move_methods = {
+    msg_layer_order_move_payload_length,
+    msg_layer_order_move_serialize_payload,
+    msg_layer_order_move_write_payload_text,
+    msg_layer_order_move_equals,
+};
+
+DP_Message *DP_msg_layer_order_move_new(unsigned int context_id,
+                                        uint16_t layer, uint8_t direction,
+                                        uint16_t index)
+{
+    DP_Message *msg = DP_message_new(DP_MSG_LAYER_ORDER_MOVE, context_id,
+                                     &msg_layer_order_move_methods,
+                                     sizeof(DP_MsgLayerOrderMove));
+    DP_MsgLayerOrderMove *mlom = DP_message_internal(msg);
+    mlom->layer = layer;
+    mlom->direction = direction;
+    mlom->index = index;
+    return msg;
+}
+
+DP_Message *DP_msg_layer_order_move_deserialize(unsigned int context_id,
+                                                const unsigned char *buffer,
+                                                size_t length)
+{
+    if (length != 5) {
+        DP_error_set("Wrong length for layerordermove message; "
+                     "expected 5, got %zu",
+                     length);
+        return NULL;
+    }
+    size_t read = 0;
+    uint16_t layer = read_uint16(buffer + read, &read);
+    uint8_t direction = read_uint8(buffer + read, &read);
+    uint16_t index = read_uint16(buffer + read, &read);
+    return DP_msg_layer_order_move_new(context_id, layer, direction, index);
+}
+
+DP_Message *DP_msg_layer_order_move_parse(unsigned int context_id,
+                                          DP_TextReader *reader)
+{
+    uint16_t layer =
+        (uint16_t)DP_text_reader_get_ulong_hex(reader, "layer", UINT16_MAX);
+    uint8_t direction =
+        (uint8_t)DP_text_reader_get_ulong(reader, "direction", UINT8_MAX);
+    uint16_t index =
+        (uint16_t)DP_text_reader_get_ulong(reader, "index", UINT16_MAX);
+    return DP_msg_layer_order_move_new(context_id, layer, direction, index);
+}
+
+DP_MsgLayerOrderMove *DP_msg_layer_order_move_cast(DP_Message *msg)
+{
+    return DP_message_cast(msg, DP_MSG_LAYER_ORDER_MOVE);
+}
+
+uint16_t DP_msg_layer_order_move_layer(const DP_MsgLayerOrderMove *mlom)
+{
+    DP_ASSERT(mlom);
+    return mlom->layer;
+}
+
+uint8_t DP_msg_layer_order_move_direction(const DP_MsgLayerOrderMove *mlom)
+{
+    DP_ASSERT(mlom);
+    return mlom->direction;
+}
+
+uint16_t DP_msg_layer_order_move_index(const DP_MsgLayerOrderMove *mlom)
+{
+    DP_ASSERT(mlom);
+    return mlom->index;
+}
+
+
+/* DP_MSG_ANNOTATION_TEXT_EDIT */
+
+struct DP_MsgAnnotationTextEdit {
+    uint16_t id;
+    uint16_t offset;
+    uint16_t count;
+    uint16_t text_len;
+    char text[];
+};
+
+static size_t msg_annotation_text_edit_payload_length(DP_Message *msg)
+{
+    DP_MsgAnnotationTextEdit *mate = DP_message_internal(msg);
+    return ((size_t)6) + DP_uint16_to_size(mate->text_len);
+}
+
+static size_t msg_annotation_text_edit_serialize_payload(DP_Message *msg,
+                                                         unsigned char *data)
+{
+    DP_MsgAnnotationTextEdit *mate = DP_message_internal(msg);
+    size_t written = 0;
+    written += DP_write_bigendian_uint16(mate->id, data + written);
+    written += DP_write_bigendian_uint16(mate->offset, data + written);
+    written += DP_write_bigendian_uint16(mate->count, data + written);
+    written += DP_write_bytes(mate->text, 1, mate->text_len, data + written);
+    DP_ASSERT(written == msg_annotation_text_edit_payload_length(msg));
+    return written;
+}
+
+static bool msg_annotation_text_edit_write_payload_text(DP_Message *msg,
+                                                        DP_TextWriter *writer)
+{
+    DP_MsgAnnotationTextEdit *mate = DP_message_internal(msg);
+    return DP_text_writer_write_uint(writer, "count", mate->count, false)
+        && DP_text_writer_write_uint(writer, "id", mate->id, true)
+        && DP_text_writer_write_uint(writer, "offset", mate->offset, false)
+        && DP_text_writer_write_string(writer, "text", mate->text);
+}
+
+static bool msg_annotation_text_edit_equals(DP_Message *DP_RESTRICT msg,
+                                            DP_Message *DP_RESTRICT other)
+{
+    DP_MsgAnnotationTextEdit *a = DP_message_internal(msg);
+    DP_MsgAnnotationTextEdit *b = DP_message_internal(other);
+    return a->id == b->id && a->offset == b->offset && a->count == b->count
+        && a->text_len == b->text_len
+        && memcmp(a->text, b->text, a->text_len) == 0;
+}
+
+static const DP_MessageMethods msg_annotation_text_edit_methods = {
+    msg_annotation_text_edit_payload_length,
+    msg_annotation_text_edit_serialize_payload,
+    msg_annotation_text_edit_write_payload_text,
+    msg_annotation_text_edit_equals,
+};
+
+DP_Message *DP_msg_annotation_text_edit_new(unsigned int context_id,
+                                            uint16_t id, uint16_t offset,
+                                            uint16_t count,
+                                            const char *text_value,
+                                            size_t text_len)
+{
+    DP_Message *msg =
+        DP_message_new(DP_MSG_ANNOTATION_TEXT_EDIT, context_id,
+                       &msg_annotation_text_edit_methods,
+                       DP_FLEX_SIZEOF(DP_MsgAnnotationTextEdit, text,
+                                      text_len + 1));
+    DP_MsgAnnotationTextEdit *mate = DP_message_internal(msg);
+    mate->id = id;
+    mate->offset = offset;
+    mate->count = count;
+    mate->text_len = DP_size_to_uint16(text_len);
+    assign_string(mate->text, text_value, mate->text_len);
+    return msg;
+}
+
+DP_Message *DP_msg_annotation_text_edit_deserialize(unsigned int context_id,
+                                                    const unsigned char *buffer,
+                                                    size_t length)
+{
+    if (length < 6 || length > 65535) {
+        DP_error_set("Wrong length for annotationtextedit message; "
+                     "expected between 6 and 65535, got %zu",
+                     length);
+        return NULL;
+    }
+    size_t read = 0;
+    uint16_t id = read_uint16(buffer + read, &read);
+    uint16_t offset = read_uint16(buffer + read, &read);
+    uint16_t count = read_uint16(buffer + read, &read);
+    size_t text_bytes = length - read;
+    uint16_t text_len = DP_size_to_uint16(text_bytes);
+    const char *text = (const char *)buffer + read;
+    return DP_msg_annotation_text_edit_new(context_id, id, offset,
+                                           count, text, text_len);
+}
+
+DP_Message *DP_msg_annotation_text_edit_parse(unsigned int context_id,
+                                              DP_TextReader *reader)
+{
+    uint16_t id =
+        (uint16_t)DP_text_reader_get_ulong_hex(reader, "id", UINT16_MAX);
+    uint16_t offset =
+        (uint16_t)DP_text_reader_get_ulong(reader, "offset", UINT16_MAX);
+    uint16_t count =
+        (uint16_t)DP_text_reader_get_ulong(reader, "count", UINT16_MAX);
+    uint16_t text_len;
+    const char *text = DP_text_reader_get_string(reader, "text", &text_len);
+    return DP_msg_annotation_text_edit_new(context_id, id, offset, count,
+                                           text, text_len);
+}
+
+DP_MsgAnnotationTextEdit *DP_msg_annotation_text_edit_cast(DP_Message *msg)
+{
+    return DP_message_cast(msg, DP_MSG_ANNOTATION_TEXT_EDIT);
+}
+
+uint16_t DP_msg_annotation_text_edit_id(const DP_MsgAnnotationTextEdit *mate)
+{
+    DP_ASSERT(mate);
+    return mate->id;
+}
+
+uint16_t
+DP_msg_annotation_text_edit_offset(const DP_MsgAnnotationTextEdit *mate)
+{
+    DP_ASSERT(mate);
+    return mate->offset;
+}
+
+uint16_t
+DP_msg_annotation_text_edit_count(const DP_MsgAnnotationTextEdit *mate)
+{
+    DP_ASSERT(mate);
+    return mate->count;
+}
+
+const char *
+DP_msg_annotation_text_edit_text(const DP_MsgAnnotationTextEdit *mate,
+                                 size_t *out_len)
+{
+    DP_ASSERT(mate);
+    if (out_len) {
+        *out_len = mate->text_len;
+    }
+    return mate->text;
+}
+
+size_t
+DP_msg_annotation_text_edit_text_len(const DP_MsgAnnotationTextEdit *mate)
+{
+    return mate->text_len;
+}
+
+
 /* DP_MSG_UNDO */
 
 struct DP_MsgUndo {
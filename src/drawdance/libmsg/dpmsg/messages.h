@@ -29,9 +29,9 @@ typedef struct DP_TextWriter DP_TextWriter;
 
 #define DP_PROTOCOL_VERSION_NAMESPACE "dp"
 #define DP_PROTOCOL_VERSION_SERVER    4
-#define DP_PROTOCOL_VERSION_MAJOR     24
+#define DP_PROTOCOL_VERSION_MAJOR     25
 #define DP_PROTOCOL_VERSION_MINOR     0
-#define DP_PROTOCOL_VERSION           "dp:4.24.0"
+#define DP_PROTOCOL_VERSION           "dp:4.25.0"
 #define DP_UNDO_DEPTH_DEFAULT         30
 
 typedef struct DP_MessageMethods {
@@ -68,6 +68,10 @@ typedef enum DP_MessageType {
     DP_MSG_UNDO_DEPTH = 74,
     DP_MSG_DATA = 75,
     DP_MSG_LOCAL_CHANGE = 76,
+    DP_MSG_LAYER_ACL_EXCLUSIVE = 77,
+    DP_MSG_CLIENT_META = 78,
+    DP_MSG_COSMETIC_MUTE = 79,
+    DP_MSG_SESSION_LOCK_CATEGORIES = 80,
     DP_MSG_UNDO_POINT = 128,
     DP_MSG_CANVAS_RESIZE = 129,
     DP_MSG_LAYER_CREATE = 130,
@@ -109,6 +113,8 @@ typedef enum DP_MessageType {
     DP_MSG_SELECTION_PUT = 174,
     DP_MSG_SELECTION_CLEAR = 175,
     DP_MSG_LOCAL_MATCH = 176,
+    DP_MSG_LAYER_ORDER_MOVE = 177,
+    DP_MSG_ANNOTATION_TEXT_EDIT = 178,
     DP_MSG_UNDO = 255,
     DP_MSG_TYPE_COUNT,
 } DP_MessageType;
@@ -121,6 +127,9 @@ bool DP_message_type_server_meta(DP_MessageType type);
 
 bool DP_message_type_command(DP_MessageType type);
 
+// Whether the given type is one this version of the protocol knows.
+bool DP_message_type_known(int type);
+
 const char *DP_message_type_name(DP_MessageType type);
 
 const char *DP_message_type_enum_name(DP_MessageType type);
@@ -778,9 +787,17 @@ int DP_msg_user_acl_users_count(const DP_MsgUserAcl *mua);
  * Using layer ID 0 sets or clears a general canvaswide lock. The tier and
  * exclusive user list is not used in this case.
  *
+ * Using layer ID 255 sets or clears the default ACL that newly created layers
+ * start out with.
+ *
  * The eighth bit of the flags field (0x80) indicates whether the layer is
- * locked in general. The first three bits (0x07) indicate the access tier
- * level.
+ * locked in general. The seventh bit (0x40) alpha locks the layer: only
+ * operators and the layer's owner may erase or otherwise reduce opacity on
+ * it, everyone else can only paint. The fifth bit (0x10) makes the layer
+ * immutable: nobody may change it, not even operators, until the bit is
+ * cleared again. Only operators can set or clear it. It only applies to
+ * actual layers, not to the canvaswide lock or the default layer ACL. The
+ * first three bits (0x07) indicate the access tier level.
  */
 
 #define DP_MSG_LAYER_ACL_STATIC_LENGTH 3
@@ -1048,6 +1065,144 @@ const unsigned char *DP_msg_local_change_body(const DP_MsgLocalChange *mlc,
 size_t DP_msg_local_change_body_size(const DP_MsgLocalChange *mlc);
 
 
+/*
+ * DP_MSG_LAYER_ACL_EXCLUSIVE
+ *
+ * Grant or revoke exclusive access to a layer for a single user
+ *
+ * This is an opaque meta command. It adds or removes one user from
+ * the exclusive access list of a layer set via LayerACL, so that two
+ * operators changing the list at the same time don't clobber each
+ * other's changes. The same permissions as with LayerACL apply.
+ *
+ * Granting access on a layer without an exclusive list makes that
+ * user the only one with access. Revoking access from the last user
+ * on the list removes the list, making the layer accessible to all.
+ */
+
+#define DP_MSG_LAYER_ACL_EXCLUSIVE_STATIC_LENGTH 4
+
+typedef struct DP_MsgLayerAclExclusive DP_MsgLayerAclExclusive;
+
+DP_Message *DP_msg_layer_acl_exclusive_new(unsigned int context_id,
+                                           uint16_t id, uint8_t user,
+                                           bool grant);
+
+DP_Message *DP_msg_layer_acl_exclusive_deserialize(unsigned int context_id,
+                                                   const unsigned char *buffer,
+                                                   size_t length);
+
+DP_Message *DP_msg_layer_acl_exclusive_parse(unsigned int context_id,
+                                             DP_TextReader *reader);
+
+DP_MsgLayerAclExclusive *DP_msg_layer_acl_exclusive_cast(DP_Message *msg);
+
+uint16_t DP_msg_layer_acl_exclusive_id(const DP_MsgLayerAclExclusive *mlae);
+
+uint8_t DP_msg_layer_acl_exclusive_user(const DP_MsgLayerAclExclusive *mlae);
+
+bool DP_msg_layer_acl_exclusive_grant(const DP_MsgLayerAclExclusive *mlae);
+
+
+/*
+ * DP_MSG_CLIENT_META
+ *
+ * Set presence information about a user
+ *
+ * This is an opaque meta message. Clients use it to tell others
+ * what color they'd like their cursor and contributions tinted
+ * with. Users can only set their own color, so the user field must
+ * match the context id. A color with an alpha of zero unsets it.
+ */
+
+#define DP_MSG_CLIENT_META_STATIC_LENGTH 5
+
+typedef struct DP_MsgClientMeta DP_MsgClientMeta;
+
+DP_Message *DP_msg_client_meta_new(unsigned int context_id, uint8_t user,
+                                   uint32_t color);
+
+DP_Message *DP_msg_client_meta_deserialize(unsigned int context_id,
+                                           const unsigned char *buffer,
+                                           size_t length);
+
+DP_Message *DP_msg_client_meta_parse(unsigned int context_id,
+                                     DP_TextReader *reader);
+
+DP_MsgClientMeta *DP_msg_client_meta_cast(DP_Message *msg);
+
+uint8_t DP_msg_client_meta_user(const DP_MsgClientMeta *mcm);
+
+uint32_t DP_msg_client_meta_color(const DP_MsgClientMeta *mcm);
+
+
+/*
+ * DP_MSG_COSMETIC_MUTE
+ *
+ * Mute or unmute a user's cosmetic messages
+ *
+ * This is an opaque meta command that can only be sent by session
+ * operators. Laser trails, pointer movements and markers from a
+ * muted user are dropped, but their drawing goes through as usual.
+ * This is a lighter alternative to locking the user entirely. Mutes
+ * are temporary, they end when the user leaves or the session gets
+ * reset, and they aren't part of reset images.
+ */
+
+#define DP_MSG_COSMETIC_MUTE_STATIC_LENGTH 2
+
+typedef struct DP_MsgCosmeticMute DP_MsgCosmeticMute;
+
+DP_Message *DP_msg_cosmetic_mute_new(unsigned int context_id, uint8_t user,
+                                     bool mute);
+
+DP_Message *DP_msg_cosmetic_mute_deserialize(unsigned int context_id,
+                                             const unsigned char *buffer,
+                                             size_t length);
+
+DP_Message *DP_msg_cosmetic_mute_parse(unsigned int context_id,
+                                       DP_TextReader *reader);
+
+DP_MsgCosmeticMute *DP_msg_cosmetic_mute_cast(DP_Message *msg);
+
+uint8_t DP_msg_cosmetic_mute_user(const DP_MsgCosmeticMute *mcm);
+
+bool DP_msg_cosmetic_mute_mute(const DP_MsgCosmeticMute *mcm);
+
+
+/*
+ * DP_MSG_SESSION_LOCK_CATEGORIES
+ *
+ * Lock categories of commands for everyone in the session
+ *
+ * This is an opaque meta command that can only be sent by session
+ * operators. The categories are a set of bits: 0x1 locks drawing,
+ * 0x2 locks layer operations and 0x4 locks annotations. Chat, laser
+ * trails and pointer movements are never affected. Unlike the
+ * session lock, this doesn't stop all commands. Like it, the locked
+ * categories get cleared on reset and aren't part of reset images.
+ */
+
+#define DP_MSG_SESSION_LOCK_CATEGORIES_STATIC_LENGTH 1
+
+typedef struct DP_MsgSessionLockCategories DP_MsgSessionLockCategories;
+
+DP_Message *DP_msg_session_lock_categories_new(unsigned int context_id,
+                                               uint8_t categories);
+
+DP_Message *DP_msg_session_lock_categories_deserialize(
+    unsigned int context_id, const unsigned char *buffer, size_t length);
+
+DP_Message *DP_msg_session_lock_categories_parse(unsigned int context_id,
+                                                 DP_TextReader *reader);
+
+DP_MsgSessionLockCategories *
+DP_msg_session_lock_categories_cast(DP_Message *msg);
+
+uint8_t DP_msg_session_lock_categories_categories(
+    const DP_MsgSessionLockCategories *mslc);
+
+
 /*
  * DP_MSG_UNDO_POINT
  *
@@ -1772,6 +1927,12 @@ size_t DP_msg_move_region_mask_size(const DP_MsgMoveRegion *mmr);
  * PutTile can target sublayers as well. This is used when generating a reset
  * image with incomplete indirect strokes. Sending a PenUp command will merge
  * the sublayer.
+ *
+ * A compressed image may be followed by an optional 8 byte trailer: a
+ * big-endian CRC-32 of all preceding image bytes, then the bytes FF FF 44 50.
+ * Tiles whose checksum doesn't match are rejected. Clients that don't know
+ * about the trailer ignore it, since it comes after the end of the DEFLATE
+ * stream.
  */
 
 #define DP_MSG_PUT_TILE_STATIC_LENGTH 9
@@ -2904,6 +3065,114 @@ const unsigned char *DP_msg_local_match_data(const DP_MsgLocalMatch *mlm,
 size_t DP_msg_local_match_data_size(const DP_MsgLocalMatch *mlm);
 
 
+/*
+ * DP_MSG_LAYER_ORDER_MOVE
+ *
+ * Move a layer relative to its current position
+ *
+ * Unlike LayerOrder, this doesn't send the entire order along, so two
+ * users reordering different layers at the same time don't undo each
+ * other's changes. The layer stays in its parent group and is moved one
+ * step up or down or to the given index, counted from the bottom of the
+ * group. The index is ignored unless moving to an index and is clamped
+ * to the group, so moving the topmost layer up has no effect.
+ *
+ * This requires the same permission as LayerOrder.
+ */
+
+#define DP_MSG_LAYER_ORDER_MOVE_STATIC_LENGTH 5
+
+#define DP_MSG_LAYER_ORDER_MOVE_DIRECTION_UP    0
+#define DP_MSG_LAYER_ORDER_MOVE_DIRECTION_DOWN  1
+#define DP_MSG_LAYER_ORDER_MOVE_DIRECTION_INDEX 2
+
+#define DP_MSG_LAYER_ORDER_MOVE_NUM_DIRECTION 3
+#define DP_MSG_LAYER_ORDER_MOVE_ALL_DIRECTION                                  \
+    DP_MSG_LAYER_ORDER_MOVE_DIRECTION_UP,                                      \
+        DP_MSG_LAYER_ORDER_MOVE_DIRECTION_DOWN,                                \
+        DP_MSG_LAYER_ORDER_MOVE_DIRECTION_INDEX
+
+const char *DP_msg_layer_order_move_direction_variant_name(unsigned int value);
+
+typedef struct DP_MsgLayerOrderMove DP_MsgLayerOrderMove;
+
+DP_Message *DP_msg_layer_order_move_new(unsigned int context_id,
+                                        uint16_t layer, uint8_t direction,
+                                        uint16_t index);
+
+DP_Message *DP_msg_layer_order_move_deserialize(unsigned int context_id,
+                                                const unsigned char *buffer,
+                                                size_t length);
+
+DP_Message *DP_msg_layer_order_move_parse(unsigned int context_id,
+                                          DP_TextReader *reader);
+
+DP_MsgLayerOrderMove *DP_msg_layer_order_move_cast(DP_Message *msg);
+
+uint16_t DP_msg_layer_order_move_layer(const DP_MsgLayerOrderMove *mlom);
+
+uint8_t DP_msg_layer_order_move_direction(const DP_MsgLayerOrderMove *mlom);
+
+uint16_t DP_msg_layer_order_move_index(const DP_MsgLayerOrderMove *mlom);
+
+
+/*
+ * DP_MSG_ANNOTATION_TEXT_EDIT
+ *
+ * Replace a range of an annotation's text
+ *
+ * Deletes count bytes of the text starting at the given offset and
+ * inserts the given text in their place, so a count of zero is a plain
+ * insertion and an empty text is a plain deletion. Unlike AnnotationEdit,
+ * this only touches the part of the text that changed, so users editing
+ * different parts of the same annotation at the same time don't
+ * overwrite each other's changes.
+ *
+ * Offset and count are in bytes of UTF-8 and are clamped to the
+ * existing text. A range that ends up in the middle of a character is
+ * widened to cover the whole character.
+ *
+ * This requires the same permission as AnnotationEdit.
+ */
+
+#define DP_MSG_ANNOTATION_TEXT_EDIT_STATIC_LENGTH 6
+
+#define DP_MSG_ANNOTATION_TEXT_EDIT_TEXT_MIN_LEN 0
+#define DP_MSG_ANNOTATION_TEXT_EDIT_TEXT_MAX_LEN 65529
+
+typedef struct DP_MsgAnnotationTextEdit DP_MsgAnnotationTextEdit;
+
+DP_Message *DP_msg_annotation_text_edit_new(unsigned int context_id,
+                                            uint16_t id, uint16_t offset,
+                                            uint16_t count,
+                                            const char *text_value,
+                                            size_t text_len);
+
+DP_Message *DP_msg_annotation_text_edit_deserialize(unsigned int context_id,
+                                                    const unsigned char *buffer,
+                                                    size_t length);
+
+DP_Message *DP_msg_annotation_text_edit_parse(unsigned int context_id,
+                                              DP_TextReader *reader);
+
+DP_MsgAnnotationTextEdit *DP_msg_annotation_text_edit_cast(DP_Message *msg);
+
+uint16_t DP_msg_annotation_text_edit_id(const DP_MsgAnnotationTextEdit *mate);
+
+uint16_t
+DP_msg_annotation_text_edit_offset(const DP_MsgAnnotationTextEdit *mate);
+
+uint16_t
+DP_msg_annotation_text_edit_count(const DP_MsgAnnotationTextEdit *mate);
+
+const char *
+DP_msg_annotation_text_edit_text(const DP_MsgAnnotationTextEdit *mate,
+                                 size_t *out_len);
+
+size_t
+DP_msg_annotation_text_edit_text_len(const DP_MsgAnnotationTextEdit *mate);
+
+
 /*
  * DP_MSG_UNDO
  *
@@ -298,6 +298,11 @@ DP_protocol_version_client_compatibility(const DP_ProtocolVersion *protover)
                 return DP_PROTOCOL_COMPATIBILITY_MINOR_INCOMPATIBILITY;
             }
         }
+        else if (protover->major == 24) {
+            // Drawpile 2.2 recording. The messages are the same, but some ACL
+            // decisions changed since, so playback may differ in places.
+            return DP_PROTOCOL_COMPATIBILITY_MINOR_INCOMPATIBILITY;
+        }
         else if (protover->major == 21 && protover->minor == 2) {
             // Drawpile 2.1 recording
             return DP_PROTOCOL_COMPATIBILITY_BACKWARD_COMPATIBLE;
@@ -0,0 +1,371 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "recording_concat.h"
+#include "binary_reader.h"
+#include "binary_writer.h"
+#include "message.h"
+#include <dpcommon/binary.h>
+#include <dpcommon/common.h>
+#include <dpcommon/conversions.h>
+#include <parson.h>
+
+
+#define USER_ID_COUNT 256
+
+typedef struct DP_RecordingConcat {
+    DP_BinaryWriter *writer;
+    // User ids taken by any stream so far, in the output id space.
+    bool taken[USER_ID_COUNT];
+    // Users currently joined, in the output id space.
+    bool joined[USER_ID_COUNT];
+    // User ids that appear in the current stream and what they map to.
+    bool users[USER_ID_COUNT];
+    uint8_t map[USER_ID_COUNT];
+    bool remapping;
+    struct {
+        size_t capacity;
+        unsigned char *data;
+    } buffer;
+} DP_RecordingConcat;
+
+
+static bool read_message(DP_BinaryReader *reader, int index,
+                         DP_Message **out_msg, bool *out_end)
+{
+    DP_BinaryReaderResult result =
+        DP_binary_reader_read_message(reader, false, out_msg);
+    switch (result) {
+    case DP_BINARY_READER_SUCCESS:
+        *out_end = false;
+        return true;
+    case DP_BINARY_READER_INPUT_END:
+        *out_end = true;
+        return true;
+    default:
+        DP_error_set("Error reading recording %d: %s", index, DP_error());
+        return false;
+    }
+}
+
+static bool rewind_reader(DP_BinaryReader *reader, int index)
+{
+    if (DP_binary_reader_seek(reader, DP_binary_reader_body_offset(reader))) {
+        return true;
+    }
+    else {
+        DP_error_set("Error rewinding recording %d: %s", index, DP_error());
+        return false;
+    }
+}
+
+static bool collect_users(DP_RecordingConcat *c, DP_BinaryReader *reader,
+                          int index)
+{
+    memset(c->users, 0, sizeof(c->users));
+    while (true) {
+        DP_Message *msg;
+        bool end;
+        if (!read_message(reader, index, &msg, &end)) {
+            return false;
+        }
+        else if (end) {
+            return rewind_reader(reader, index);
+        }
+        else {
+            unsigned int context_id = DP_message_context_id(msg);
+            if (context_id != 0) {
+                c->users[context_id] = true;
+            }
+            DP_message_decref(msg);
+        }
+    }
+}
+
+static int next_free_user_id(DP_RecordingConcat *c)
+{
+    for (int i = 1; i < USER_ID_COUNT; ++i) {
+        if (!c->taken[i]) {
+            return i;
+        }
+    }
+    return -1;
+}
+
+static bool assign_user_ids(DP_RecordingConcat *c, int index)
+{
+    bool collides[USER_ID_COUNT];
+    for (int i = 0; i < USER_ID_COUNT; ++i) {
+        collides[i] = c->users[i] && c->taken[i];
+        c->map[i] = DP_int_to_uint8(i);
+    }
+
+    // Users that don't collide keep their ids, so take those first to avoid
+    // remapping anyone onto them.
+    for (int i = 1; i < USER_ID_COUNT; ++i) {
+        if (c->users[i] && !collides[i]) {
+            c->taken[i] = true;
+        }
+    }
+
+    c->remapping = false;
+    for (int i = 1; i < USER_ID_COUNT; ++i) {
+        if (collides[i]) {
+            int user_id = next_free_user_id(c);
+            if (user_id == -1) {
+                DP_error_set("No free user id to remap user %d of recording "
+                             "%d to",
+                             i, index);
+                return false;
+            }
+            c->taken[user_id] = true;
+            c->map[i] = DP_int_to_uint8(user_id);
+            c->remapping = true;
+        }
+    }
+    return true;
+}
+
+
+static uint8_t remap_user(DP_RecordingConcat *c, uint8_t user_id)
+{
+    return c->users[user_id] ? c->map[user_id] : user_id;
+}
+
+static void remap_user_at(DP_RecordingConcat *c, unsigned char *body,
+                          size_t length, size_t offset)
+{
+    if (offset < length) {
+        body[offset] = remap_user(c, body[offset]);
+    }
+}
+
+static void remap_users_from(DP_RecordingConcat *c, unsigned char *body,
+                             size_t length, size_t offset)
+{
+    for (size_t i = offset; i < length; ++i) {
+        body[i] = remap_user(c, body[i]);
+    }
+}
+
+// Layers, annotations and tracks have the id of the user that created them in
+// the upper byte of their id.
+static void remap_prefix_at(DP_RecordingConcat *c, unsigned char *body,
+                            size_t length, size_t offset)
+{
+    if (offset + 2 <= length) {
+        body[offset] = remap_user(c, body[offset]);
+    }
+}
+
+static void remap_prefixes_from(DP_RecordingConcat *c, unsigned char *body,
+                                size_t length, size_t offset, size_t stride)
+{
+    for (size_t i = offset; i + 2 <= length; i += stride) {
+        remap_prefix_at(c, body, length, i);
+    }
+}
+
+static void remap_body(DP_RecordingConcat *c, DP_MessageType type,
+                       unsigned char *body, size_t length)
+{
+    switch (type) {
+    case DP_MSG_SESSION_OWNER:
+    case DP_MSG_TRUSTED_USERS:
+    case DP_MSG_USER_ACL:
+        remap_users_from(c, body, length, 0);
+        break;
+    case DP_MSG_PRIVATE_CHAT:
+    case DP_MSG_CLIENT_META:
+    case DP_MSG_COSMETIC_MUTE:
+    case DP_MSG_UNDO:
+        remap_user_at(c, body, length, 0);
+        break;
+    case DP_MSG_LAYER_ACL:
+        remap_prefix_at(c, body, length, 0);
+        remap_users_from(c, body, length, 3);
+        break;
+    case DP_MSG_LAYER_ACL_EXCLUSIVE:
+        remap_prefix_at(c, body, length, 0);
+        remap_user_at(c, body, length, 2);
+        break;
+    case DP_MSG_DEFAULT_LAYER:
+    case DP_MSG_LAYER_ATTRIBUTES:
+    case DP_MSG_LAYER_RETITLE:
+    case DP_MSG_LAYER_DELETE:
+    case DP_MSG_LAYER_VISIBILITY:
+    case DP_MSG_PUT_IMAGE:
+    case DP_MSG_FILL_RECT:
+    case DP_MSG_ANNOTATION_CREATE:
+    case DP_MSG_ANNOTATION_RESHAPE:
+    case DP_MSG_ANNOTATION_EDIT:
+    case DP_MSG_ANNOTATION_TEXT_EDIT:
+    case DP_MSG_ANNOTATION_DELETE:
+    case DP_MSG_MOVE_REGION:
+    case DP_MSG_PUT_TILE:
+    case DP_MSG_DRAW_DABS_CLASSIC:
+    case DP_MSG_DRAW_DABS_PIXEL:
+    case DP_MSG_DRAW_DABS_PIXEL_SQUARE:
+    case DP_MSG_DRAW_DABS_MYPAINT:
+    case DP_MSG_TRACK_RETITLE:
+    case DP_MSG_TRACK_DELETE:
+    case DP_MSG_KEY_FRAME_RETITLE:
+    case DP_MSG_LAYER_ORDER_MOVE:
+        remap_prefix_at(c, body, length, 0);
+        break;
+    case DP_MSG_LAYER_CREATE:
+    case DP_MSG_MOVE_RECT:
+    case DP_MSG_LAYER_TREE_DELETE:
+    case DP_MSG_TRANSFORM_REGION:
+        remap_prefix_at(c, body, length, 0);
+        remap_prefix_at(c, body, length, 2);
+        break;
+    case DP_MSG_LAYER_TREE_CREATE:
+    case DP_MSG_LAYER_TREE_MOVE:
+    case DP_MSG_TRACK_CREATE:
+        remap_prefix_at(c, body, length, 0);
+        remap_prefix_at(c, body, length, 2);
+        remap_prefix_at(c, body, length, 4);
+        break;
+    case DP_MSG_KEY_FRAME_SET:
+    case DP_MSG_KEY_FRAME_DELETE:
+        // The track id, then a frame index and the other layer or track id.
+        remap_prefix_at(c, body, length, 0);
+        remap_prefix_at(c, body, length, 4);
+        break;
+    case DP_MSG_LAYER_ORDER:
+    case DP_MSG_TRACK_ORDER:
+        remap_prefixes_from(c, body, length, 0, 2);
+        break;
+    case DP_MSG_KEY_FRAME_LAYER_ATTRIBUTES:
+        // Track id and frame index, followed by pairs of layer id and flags.
+        remap_prefix_at(c, body, length, 0);
+        remap_prefixes_from(c, body, length, 4, 4);
+        break;
+    default:
+        break;
+    }
+}
+
+static unsigned char *get_serialize_buffer(void *user, size_t length)
+{
+    DP_RecordingConcat *c = user;
+    if (c->buffer.capacity < length) {
+        c->buffer.data = DP_realloc(c->buffer.data, length);
+        c->buffer.capacity = length;
+    }
+    return c->buffer.data;
+}
+
+// Returns a new reference to the message with its ids remapped, or NULL on
+// error. Messages without ids to remap are passed through as they are.
+static DP_Message *remap_message(DP_RecordingConcat *c, DP_Message *msg)
+{
+    unsigned int context_id = DP_message_context_id(msg);
+    DP_MessageType type = DP_message_type(msg);
+    if (type == DP_MSG_JOIN) {
+        c->joined[remap_user(c, DP_uint_to_uint8(context_id))] = true;
+    }
+    else if (type == DP_MSG_LEAVE) {
+        c->joined[remap_user(c, DP_uint_to_uint8(context_id))] = false;
+    }
+
+    if (!c->remapping) {
+        return DP_message_incref(msg);
+    }
+
+    size_t length = DP_message_serialize(msg, true, get_serialize_buffer, c);
+    if (length == 0) {
+        return NULL;
+    }
+
+    unsigned char *data = c->buffer.data;
+    data[3] = remap_user(c, data[3]);
+    remap_body(c, type, data + DP_MESSAGE_HEADER_LENGTH,
+               length - DP_MESSAGE_HEADER_LENGTH);
+    return DP_message_deserialize(data, length, false);
+}
+
+static bool write_message_dec(DP_RecordingConcat *c, DP_Message *msg)
+{
+    size_t written = DP_binary_writer_write_message(c->writer, msg);
+    DP_message_decref(msg);
+    return written != 0;
+}
+
+static bool write_leaves(DP_RecordingConcat *c)
+{
+    for (int i = 1; i < USER_ID_COUNT; ++i) {
+        if (c->joined[i]) {
+            c->joined[i] = false;
+            if (!write_message_dec(c, DP_msg_leave_new(DP_int_to_uint(i)))) {
+                return false;
+            }
+        }
+    }
+    return true;
+}
+
+static bool copy_stream(DP_RecordingConcat *c, DP_BinaryReader *reader,
+                        int index)
+{
+    while (true) {
+        DP_Message *msg;
+        bool end;
+        if (!read_message(reader, index, &msg, &end)) {
+            return false;
+        }
+        else if (end) {
+            return true;
+        }
+
+        DP_Message *remapped = remap_message(c, msg);
+        DP_message_decref(msg);
+        if (!remapped) {
+            DP_error_set("Error remapping message in recording %d: %s", index,
+                         DP_error());
+            return false;
+        }
+        else if (!write_message_dec(c, remapped)) {
+            return false;
+        }
+    }
+}
+
+static bool write_header(DP_BinaryWriter *writer, DP_BinaryReader *reader)
+{
+    JSON_Object *header =
+        json_value_get_object(DP_binary_reader_header(reader));
+    if (header) {
+        return DP_binary_writer_write_header(writer, header);
+    }
+    else {
+        DP_error_set("First recording has no header");
+        return false;
+    }
+}
+
+bool DP_recording_concat(int count, DP_BinaryReader **readers,
+                         DP_BinaryWriter *writer)
+{
+    DP_ASSERT(count >= 0);
+    DP_ASSERT(count == 0 || readers);
+    DP_ASSERT(writer);
+    if (count == 0) {
+        DP_error_set("No recordings to concatenate");
+        return false;
+    }
+    else if (!write_header(writer, readers[0])) {
+        return false;
+    }
+
+    DP_RecordingConcat *c = DP_malloc_zeroed(sizeof(*c));
+    c->writer = writer;
+    bool ok = true;
+    for (int i = 0; ok && i < count; ++i) {
+        DP_BinaryReader *reader = readers[i];
+        ok = (i == 0 || write_leaves(c)) && collect_users(c, reader, i)
+          && assign_user_ids(c, i) && copy_stream(c, reader, i);
+    }
+    DP_free(c->buffer.data);
+    DP_free(c);
+    return ok;
+}
@@ -0,0 +1,31 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#ifndef DPMSG_RECORDING_CONCAT_H
+#define DPMSG_RECORDING_CONCAT_H
+#include <dpcommon/common.h>
+
+typedef struct DP_BinaryReader DP_BinaryReader;
+typedef struct DP_BinaryWriter DP_BinaryWriter;
+
+
+// Stitches binary recordings together into a single one, e.g. to fill the gaps
+// left by reconnecting. Every stream is assumed to continue the canvas where
+// the previous one left off, so any reset image at the start of a later stream
+// has to be dealt with by the caller.
+//
+// Users of different streams are treated as different people. If a user id is
+// already taken by an earlier stream, it's remapped to the lowest id that's
+// still free, along with the owner prefix of the layers, annotations and tracks
+// created by that user and the user id fields in messages. References to ids
+// not used in the stream itself are left alone, since they belong to earlier
+// streams. Users still joined at the end of a stream get a leave message, so
+// that the next stream starts out with a clean slate.
+//
+// The header is taken from the first stream. The readers must be positioned at
+// the start of their body and get rewound to it for a second pass, so they must
+// be seekable. Returns false and sets an error if reading, writing or remapping
+// fails, such as when there's no more free user ids to remap to.
+bool DP_recording_concat(int count, DP_BinaryReader **readers,
+                         DP_BinaryWriter *writer) DP_MUST_CHECK;
+
+
+#endif
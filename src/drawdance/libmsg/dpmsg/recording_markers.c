@@ -0,0 +1,49 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "recording_markers.h"
+#include "binary_reader.h"
+#include "message.h"
+#include <dpcommon/common.h>
+
+
+static void report_marker(DP_Message *msg, long long position, size_t offset,
+                          void (*handle_marker)(void *,
+                                                const DP_RecordingMarker *),
+                          void *user)
+{
+    if (handle_marker) {
+        size_t text_length;
+        const char *text = DP_msg_marker_text(
+            DP_message_cast(msg, DP_MSG_MARKER), &text_length);
+        DP_RecordingMarker marker = {position, offset, text, text_length};
+        handle_marker(user, &marker);
+    }
+}
+
+int DP_recording_markers(DP_BinaryReader *reader,
+                         void (*handle_marker)(void *,
+                                               const DP_RecordingMarker *),
+                         void *user)
+{
+    DP_ASSERT(reader);
+    int marker_count = 0;
+    for (long long position = 0;; ++position) {
+        size_t offset = DP_binary_reader_tell(reader);
+        DP_Message *msg;
+        DP_BinaryReaderResult result =
+            DP_binary_reader_read_message(reader, true, &msg);
+        if (result == DP_BINARY_READER_INPUT_END) {
+            return marker_count;
+        }
+        else if (result != DP_BINARY_READER_SUCCESS) {
+            DP_error_set("Error reading message %lld: %s", position,
+                         DP_error());
+            return -1;
+        }
+
+        if (DP_message_type(msg) == DP_MSG_MARKER) {
+            report_marker(msg, position, offset, handle_marker, user);
+            ++marker_count;
+        }
+        DP_message_decref(msg);
+    }
+}
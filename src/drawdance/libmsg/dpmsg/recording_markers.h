@@ -0,0 +1,32 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#ifndef DPMSG_RECORDING_MARKERS_H
+#define DPMSG_RECORDING_MARKERS_H
+#include <dpcommon/common.h>
+
+typedef struct DP_BinaryReader DP_BinaryReader;
+
+
+typedef struct DP_RecordingMarker {
+    // Position of the marker message in the recording, counting from zero.
+    long long position;
+    // Where the marker message starts in the recording. Together with the
+    // position, this can be passed to DP_player_seek to jump to the marker.
+    size_t offset;
+    // The marker's text, not null-terminated. Only valid for the duration of
+    // the callback, copy it to keep it around.
+    const char *text;
+    size_t text_length;
+} DP_RecordingMarker;
+
+// Scans a recording for Marker messages, for showing a list of chapters or
+// bookmarks to jump between during playback. The reader must be positioned at
+// the start of the body, it's left at the end. Calls handle_marker, if given,
+// for each marker in the order they occur. Returns the number of markers or
+// -1 and sets an error if reading the recording fails.
+int DP_recording_markers(DP_BinaryReader *reader,
+                         void (*handle_marker)(void *,
+                                               const DP_RecordingMarker *),
+                         void *user);
+
+
+#endif
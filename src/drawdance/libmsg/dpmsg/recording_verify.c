@@ -0,0 +1,80 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include "recording_verify.h"
+#include "acl.h"
+#include "binary_reader.h"
+#include "message.h"
+#include <dpcommon/common.h>
+
+
+static DP_Message *unwrap_filtered(DP_Message *msg, int index)
+{
+    size_t size;
+    const unsigned char *data = DP_msg_filtered_message(
+        DP_message_cast(msg, DP_MSG_FILTERED), &size);
+    DP_Message *inner = DP_message_deserialize_exact(data, size);
+    if (!inner) {
+        DP_error_set("Error decoding filtered message %d: %s", index,
+                     DP_error());
+    }
+    return inner;
+}
+
+static bool verify_message(DP_AclState *acls, DP_Message *msg, int index,
+                           bool recorded_filtered,
+                           void (*handle_mismatch)(
+                               void *, const DP_RecordingFilterMismatch *),
+                           void *user)
+{
+    uint8_t result = DP_acl_state_handle(acls, msg, false);
+    bool filtered = result & DP_ACL_STATE_FILTERED_BIT;
+    if (filtered == recorded_filtered) {
+        return false;
+    }
+    else {
+        if (handle_mismatch) {
+            DP_RecordingFilterMismatch mismatch = {
+                index, msg, recorded_filtered,
+                DP_acl_state_last_rejection(acls)};
+            handle_mismatch(user, &mismatch);
+        }
+        return true;
+    }
+}
+
+int DP_recording_verify_filter(
+    DP_BinaryReader *reader, DP_AclState *acls,
+    void (*handle_mismatch)(void *, const DP_RecordingFilterMismatch *),
+    void *user)
+{
+    DP_ASSERT(reader);
+    DP_ASSERT(acls);
+    int mismatch_count = 0;
+    for (int index = 0;; ++index) {
+        DP_Message *msg;
+        DP_BinaryReaderResult result =
+            DP_binary_reader_read_message(reader, true, &msg);
+        if (result == DP_BINARY_READER_INPUT_END) {
+            return mismatch_count;
+        }
+        else if (result != DP_BINARY_READER_SUCCESS) {
+            DP_error_set("Error reading message %d: %s", index, DP_error());
+            return -1;
+        }
+
+        bool recorded_filtered = DP_message_type(msg) == DP_MSG_FILTERED;
+        if (recorded_filtered) {
+            DP_Message *inner = unwrap_filtered(msg, index);
+            DP_message_decref(msg);
+            if (!inner) {
+                return -1;
+            }
+            msg = inner;
+        }
+
+        if (verify_message(acls, msg, index, recorded_filtered,
+                           handle_mismatch, user)) {
+            ++mismatch_count;
+        }
+        DP_message_decref(msg);
+    }
+}
@@ -0,0 +1,44 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#ifndef DPMSG_RECORDING_VERIFY_H
+#define DPMSG_RECORDING_VERIFY_H
+#include "acl.h"
+#include <dpcommon/common.h>
+
+typedef struct DP_BinaryReader DP_BinaryReader;
+typedef struct DP_Message DP_Message;
+
+
+typedef struct DP_RecordingFilterMismatch {
+    // Position of the message in the recording, counting from zero.
+    int index;
+    // The message as it was recorded, unwrapped if it was filtered. Only valid
+    // for the duration of the callback, take a reference to keep it around.
+    DP_Message *msg;
+    // Whether the recording has the message as filtered. If so, the filter
+    // accepts it now, otherwise it filters it out.
+    bool recorded_filtered;
+    // Why the filter rejects the message now, DP_ACL_REJECTION_NONE if it
+    // accepts it.
+    DP_AclRejection rejection;
+} DP_RecordingFilterMismatch;
+
+// Re-runs the ACL filter over a recording and reports messages for which it
+// comes to a different decision than when the recording was made. Messages
+// that got filtered out are expected to be recorded as Filtered messages
+// carrying the whole serialized message, header included. Everything else is
+// expected to pass the filter.
+//
+// The ACL state should be fresh, or the one the recording started out with.
+// It's updated as the messages are handled, so the decisions about later
+// messages take into account the effects of earlier ones. The reader must be
+// positioned at the start of the body. Calls handle_mismatch, if given, for
+// each disagreement in the order they occur. Returns the number of mismatches
+// or -1 and sets an error if reading the recording or decoding a Filtered
+// message fails.
+int DP_recording_verify_filter(
+    DP_BinaryReader *reader, DP_AclState *acls,
+    void (*handle_mismatch)(void *, const DP_RecordingFilterMismatch *),
+    void *user);
+
+
+#endif
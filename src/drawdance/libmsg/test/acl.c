@@ -0,0 +1,3054 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include <dpmsg/acl.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+#include <parson.h>
+#include <time.h>
+
+
+static uint8_t handle(DP_AclState *acls, DP_Message *msg)
+{
+    uint8_t result = DP_acl_state_handle(acls, msg, false);
+    DP_message_decref(msg);
+    return result;
+}
+
+static void set_uint8s(int count, uint8_t *out, void *user)
+{
+    memcpy(out, user, DP_int_to_size(count));
+}
+
+static void set_uint16s(int count, uint16_t *out, void *user)
+{
+    memcpy(out, user, sizeof(*out) * DP_int_to_size(count));
+}
+
+static DP_Message *join_new(uint8_t user_id, uint8_t flags)
+{
+    return DP_msg_join_new(user_id, flags, "user", 4, NULL, 0, NULL);
+}
+
+static DP_Message *session_owner_new(int count, uint8_t *user_ids)
+{
+    return DP_msg_session_owner_new(0, set_uint8s, count, user_ids);
+}
+
+static DP_Message *layer_acl_new(unsigned int context_id, int layer_id,
+                                 uint8_t flags, int exclusive_count,
+                                 uint8_t *exclusive)
+{
+    return DP_msg_layer_acl_new(context_id, DP_int_to_uint16(layer_id), flags,
+                                exclusive_count == 0 ? NULL : set_uint8s,
+                                exclusive_count, exclusive);
+}
+
+static DP_Message *layer_create_new(unsigned int context_id, int layer_id)
+{
+    return DP_msg_layer_create_new(context_id, DP_int_to_uint16(layer_id), 0,
+                                   0, 0, "layer", 5);
+}
+
+static DP_Message *feature_access_levels_new(unsigned int context_id,
+                                             uint8_t *tiers)
+{
+    return DP_msg_feature_access_levels_new(context_id, set_uint8s,
+                                            DP_FEATURE_COUNT, tiers);
+}
+
+
+static DP_AclState *operator_policy_setup(DP_AclOperatorPolicy policy)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    DP_acl_state_operator_policy_set(acls, policy);
+    for (uint8_t user_id = 1; user_id <= 3; ++user_id) {
+        (void)handle(acls, join_new(user_id, 0));
+    }
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    return acls;
+}
+
+static void operator_policy_allow_empty(TEST_PARAMS)
+{
+    DP_AclState *acls =
+        operator_policy_setup(DP_ACL_OPERATOR_POLICY_ALLOW_EMPTY);
+    OK(DP_acl_state_is_op(acls, 1), "user 1 is op");
+
+    (void)handle(acls, DP_msg_leave_new(1));
+    NOK(DP_acl_state_is_op(acls, 1), "user 1 is no longer op after leaving");
+    NOK(DP_acl_state_is_op(acls, 2), "user 2 did not get promoted");
+    NOK(DP_acl_state_is_op(acls, 3), "user 3 did not get promoted");
+
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){2}));
+    (void)handle(acls, session_owner_new(0, NULL));
+    NOK(DP_acl_state_is_op(acls, 2), "empty session owner demotes user 2");
+    DP_acl_state_free(acls);
+}
+
+static void operator_policy_keep_last(TEST_PARAMS)
+{
+    DP_AclState *acls = operator_policy_setup(DP_ACL_OPERATOR_POLICY_KEEP_LAST);
+    (void)handle(acls, DP_msg_leave_new(1));
+    OK(DP_acl_state_is_op(acls, 1), "user 1 is kept as a ghost op");
+    NOK(DP_acl_state_is_op(acls, 2), "user 2 did not get promoted");
+
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){2}));
+    NOK(DP_acl_state_is_op(acls, 1), "ghost op 1 was replaced");
+    OK(DP_acl_state_is_op(acls, 2), "user 2 is op");
+
+    (void)handle(acls, session_owner_new(0, NULL));
+    OK(DP_acl_state_is_op(acls, 2), "empty session owner is refused");
+
+    DP_acl_state_operator_policy_set(acls, DP_ACL_OPERATOR_POLICY_ALLOW_EMPTY);
+    DP_acl_state_reset(acls, 0);
+    INT_EQ_OK(DP_acl_state_operator_policy(acls),
+              DP_ACL_OPERATOR_POLICY_ALLOW_EMPTY, "policy survives reset");
+    DP_acl_state_free(acls);
+}
+
+static void operator_policy_promote_oldest(TEST_PARAMS)
+{
+    DP_AclState *acls =
+        operator_policy_setup(DP_ACL_OPERATOR_POLICY_PROMOTE_OLDEST);
+    (void)handle(acls, DP_msg_leave_new(1));
+    NOK(DP_acl_state_is_op(acls, 1), "user 1 is no longer op after leaving");
+    OK(DP_acl_state_is_op(acls, 2), "longest-connected user 2 got promoted");
+    NOK(DP_acl_state_is_op(acls, 3), "user 3 did not get promoted");
+
+    (void)handle(acls, join_new(1, 0));
+    (void)handle(acls, DP_msg_leave_new(2));
+    OK(DP_acl_state_is_op(acls, 3), "user 3 got promoted over rejoined 1");
+    NOK(DP_acl_state_is_op(acls, 1), "rejoined user 1 did not get promoted");
+
+    (void)handle(acls, session_owner_new(0, NULL));
+    OK(DP_acl_state_is_op(acls, 3), "empty session owner promotes user 3");
+
+    (void)handle(acls, DP_msg_leave_new(1));
+    (void)handle(acls, DP_msg_leave_new(3));
+    NOK(DP_acl_state_is_op(acls, 3), "nobody left to promote");
+    DP_acl_state_free(acls);
+}
+
+static void operator_policy_promote_oldest_after_reset(TEST_PARAMS)
+{
+    DP_AclState *acls =
+        operator_policy_setup(DP_ACL_OPERATOR_POLICY_PROMOTE_OLDEST);
+    DP_acl_state_reset(acls, 0);
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, DP_msg_leave_new(1));
+    OK(DP_acl_state_is_op(acls, 2), "user 2 got promoted after reset");
+    NOK(DP_acl_state_is_op(acls, 3), "user 3 did not get promoted");
+    DP_acl_state_free(acls);
+}
+
+static void reset_with_operators(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){9}));
+    DP_acl_state_reset_with_operators(acls, 1, 3, (uint8_t[]){2, 4, 6});
+    OK(DP_acl_state_is_op(acls, 1), "local user is op");
+    OK(DP_acl_state_is_op(acls, 2), "user 2 is op");
+    OK(DP_acl_state_is_op(acls, 4), "user 4 is op");
+    OK(DP_acl_state_is_op(acls, 6), "user 6 is op");
+    NOK(DP_acl_state_is_op(acls, 3), "user 3 is not op");
+    NOK(DP_acl_state_is_op(acls, 9), "previous op was reset");
+    DP_acl_state_free(acls);
+}
+
+
+static void feature_tiers_change_detection(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    DP_FeatureTiers before = DP_acl_state_feature_tiers(acls);
+    OK(DP_feature_tiers_equal(&before, &before), "tiers equal themselves");
+
+    uint8_t tiers[DP_FEATURE_COUNT];
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        tiers[i] = DP_ACCESS_TIER_TRUSTED;
+    }
+
+    uint8_t first = handle(acls, feature_access_levels_new(0, tiers));
+    UINT_EQ_OK(first, DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT,
+               "first feature access levels changes tiers");
+    DP_FeatureTiers after = DP_acl_state_feature_tiers(acls);
+    NOK(DP_feature_tiers_equal(&before, &after), "tiers differ after change");
+
+    uint8_t second = handle(acls, feature_access_levels_new(0, tiers));
+    UINT_EQ_OK(second, 0, "identical feature access levels change nothing");
+    DP_acl_state_free(acls);
+}
+
+
+static bool push_to_acl_state(void *user, DP_Message *msg)
+{
+    (void)handle(user, msg);
+    return true;
+}
+
+static void default_layer_acl(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, DP_msg_trusted_users_new(0, set_uint8s, 1,
+                                                (uint8_t[]){3}));
+    NULL_OK(DP_acl_state_default_layer_acl(acls), "no default layer acl");
+
+    uint8_t guest_result = handle(
+        acls, layer_acl_new(2, DP_ACL_DEFAULT_LAYER_ID, DP_ACCESS_TIER_TRUSTED,
+                            0, NULL));
+    UINT_EQ_OK(guest_result, DP_ACL_STATE_FILTERED_BIT,
+               "guest can't set default layer acl");
+    NULL_OK(DP_acl_state_default_layer_acl(acls), "still no default");
+
+    uint8_t op_result = handle(
+        acls, layer_acl_new(1, DP_ACL_DEFAULT_LAYER_ID, DP_ACCESS_TIER_TRUSTED,
+                            0, NULL));
+    UINT_EQ_OK(op_result, DP_ACL_STATE_CHANGE_LAYERS_BIT,
+               "op sets default layer acl");
+    const DP_LayerAcl *l = DP_acl_state_default_layer_acl(acls);
+    if (NOT_NULL_OK(l, "got default layer acl")) {
+        INT_EQ_OK(l->tier, DP_ACCESS_TIER_TRUSTED, "default tier is trusted");
+        NOK(l->locked, "default is not locked entirely");
+    }
+
+    DP_AclState *copy = DP_acl_state_new();
+    OK(DP_acl_state_reset_image_build(acls, 0,
+                                      DP_ACL_STATE_RESET_IMAGE_RECORDING_FLAGS,
+                                      push_to_acl_state, copy),
+       "reset image built");
+    NOT_NULL_OK(DP_acl_state_default_layer_acl(copy),
+                "reset image contains default layer acl");
+    DP_acl_state_free(copy);
+
+    uint8_t create_result = handle(acls, layer_create_new(2, 0x201));
+    UINT_EQ_OK(create_result, DP_ACL_STATE_CHANGE_LAYERS_BIT,
+               "creating a layer applies the default acl");
+    OK(DP_acl_state_layer_locked_for(acls, 2, 0x201),
+       "new layer is locked for guest creator");
+    NOK(DP_acl_state_layer_locked_for(acls, 3, 0x201),
+        "new layer is unlocked for trusted user");
+    NOK(DP_acl_state_layer_locked_for(acls, 1, 0x201),
+        "new layer is unlocked for operator");
+
+    (void)handle(acls, layer_acl_new(1, DP_ACL_DEFAULT_LAYER_ID,
+                                     DP_ACCESS_TIER_GUEST, 0, NULL));
+    NULL_OK(DP_acl_state_default_layer_acl(acls), "default layer acl cleared");
+    uint8_t plain_result = handle(acls, layer_create_new(2, 0x202));
+    UINT_EQ_OK(plain_result, 0, "creating a layer without default is no change");
+    NOK(DP_acl_state_layer_locked_for(acls, 2, 0x202),
+        "layer without default is unlocked");
+    OK(DP_acl_state_layer_locked_for(acls, 2, 0x201),
+       "previous layer stays locked");
+    DP_acl_state_free(acls);
+}
+
+static void default_layer_id_reserved(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    UINT_EQ_OK(handle(acls, layer_create_new(1, DP_ACL_DEFAULT_LAYER_ID)),
+               DP_ACL_STATE_FILTERED_BIT,
+               "op can't create a layer with the default layer id");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_INVALID,
+              "creating it is invalid");
+    UINT_EQ_OK(handle(acls,
+                      DP_msg_layer_tree_create_new(1, DP_ACL_DEFAULT_LAYER_ID,
+                                                   0, 0, 0, 0, "x", 1)),
+               DP_ACL_STATE_FILTERED_BIT,
+               "op can't create a layer tree with the default layer id");
+    DP_Message *msg = layer_create_new(0, DP_ACL_DEFAULT_LAYER_ID);
+    UINT_EQ_OK(DP_acl_state_handle(acls, msg, true) & DP_ACL_STATE_FILTERED_BIT,
+               DP_ACL_STATE_FILTERED_BIT, "override doesn't let it through");
+    DP_message_decref(msg);
+    UINT_EQ_OK(handle(acls, layer_create_new(1, 0xfe)), 0,
+               "op creates other layer with user 0 prefix");
+
+    (void)handle(acls, layer_acl_new(1, DP_ACL_DEFAULT_LAYER_ID,
+                                     DP_ACL_ALL_LOCKED_BIT, 0, NULL));
+    (void)handle(acls, layer_create_new(1, 0x102));
+    OK(DP_acl_state_layer_locked_for(acls, 1, 0x102),
+       "default layer acl still applies to new layers");
+    DP_acl_state_free(acls);
+}
+
+
+static void rejection_reasons(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+
+    uint8_t tiers[DP_FEATURE_COUNT];
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        tiers[i] = DP_ACCESS_TIER_OPERATOR;
+    }
+    UINT_EQ_OK(handle(acls, feature_access_levels_new(2, tiers)),
+               DP_ACL_STATE_FILTERED_BIT, "guest can't set feature tiers");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_NOT_OPERATOR,
+              "feature tiers rejected because guest isn't an operator");
+    STR_EQ_OK(DP_acl_rejection_enum_name(DP_acl_state_last_rejection(acls)),
+              "DP_ACL_REJECTION_NOT_OPERATOR", "rejection enum name");
+
+    UINT_EQ_OK(handle(acls, DP_msg_user_acl_new(2, set_uint8s, 1,
+                                                (uint8_t[]){2})),
+               DP_ACL_STATE_FILTERED_BIT, "guest can't lock users");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_NOT_OPERATOR,
+              "user acl rejected because guest isn't an operator");
+
+    UINT_EQ_OK(handle(acls, feature_access_levels_new(1, tiers)),
+               DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT, "op sets feature tiers");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_NONE,
+              "accepted message has no rejection");
+
+    UINT_EQ_OK(handle(acls, DP_msg_canvas_resize_new(2, 0, 1, 0, 0)),
+               DP_ACL_STATE_FILTERED_BIT, "guest can't resize");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_FEATURE_TIER,
+              "resize rejected because of feature tier");
+
+    (void)handle(acls, layer_acl_new(1, 0, DP_ACL_ALL_LOCKED_BIT, 0, NULL));
+    UINT_EQ_OK(handle(acls, DP_msg_canvas_resize_new(1, 0, 1, 0, 0)),
+               DP_ACL_STATE_FILTERED_BIT, "op can't resize locked session");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls),
+              DP_ACL_REJECTION_SESSION_LOCKED,
+              "resize rejected because session is locked");
+    DP_acl_state_free(acls);
+}
+
+static void undo_other_users(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    UINT_EQ_OK(handle(acls, DP_msg_undo_new(2, 0, false)), 0,
+               "guest undoes themselves");
+    UINT_EQ_OK(handle(acls, DP_msg_undo_new(2, 2, false)), 0,
+               "guest undoes themselves explicitly");
+    UINT_EQ_OK(handle(acls, DP_msg_undo_new(2, 3, false)),
+               DP_ACL_STATE_FILTERED_BIT, "guest can't undo another user");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_NOT_OPERATOR,
+              "undoing another user rejected because guest isn't an operator");
+    UINT_EQ_OK(handle(acls, DP_msg_undo_new(2, 3, true)),
+               DP_ACL_STATE_FILTERED_BIT, "guest can't redo another user");
+    UINT_EQ_OK(handle(acls, DP_msg_undo_new(1, 3, false)), 0,
+               "operator undoes another user");
+    UINT_EQ_OK(handle(acls, DP_msg_undo_new(1, 3, true)), 0,
+               "operator redoes another user");
+    DP_acl_state_free(acls);
+}
+
+
+static void policy_round_trip(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    uint8_t tiers[DP_FEATURE_COUNT];
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        tiers[i] = DP_int_to_uint8(i % DP_ACCESS_TIER_COUNT);
+    }
+    (void)handle(acls, feature_access_levels_new(0, tiers));
+    (void)handle(acls, layer_acl_new(0, DP_ACL_DEFAULT_LAYER_ID,
+                                     DP_ACCESS_TIER_AUTHENTICATED, 1,
+                                     (uint8_t[]){7}));
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){5}));
+
+    unsigned char blob[DP_ACL_POLICY_LENGTH];
+    DP_acl_state_policy_export(acls, blob);
+    INT_EQ_OK(blob[0], DP_ACL_POLICY_VERSION, "blob starts with version");
+
+    DP_AclState *copy = DP_acl_state_new();
+    UINT_EQ_OK(DP_acl_state_policy_import(copy, blob, sizeof(blob)),
+               DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT
+                   | DP_ACL_STATE_CHANGE_LAYERS_BIT,
+               "import changes feature tiers and layers");
+    DP_FeatureTiers expected = DP_acl_state_feature_tiers(acls);
+    DP_FeatureTiers actual = DP_acl_state_feature_tiers(copy);
+    OK(DP_feature_tiers_equal(&expected, &actual), "feature tiers round trip");
+
+    const DP_LayerAcl *l = DP_acl_state_default_layer_acl(copy);
+    if (NOT_NULL_OK(l, "default layer acl round trips")) {
+        INT_EQ_OK(l->tier, DP_ACCESS_TIER_AUTHENTICATED, "default tier");
+        OK(DP_user_bit_get(l->exclusive, 8), "exclusive users not included");
+    }
+    NOK(DP_acl_state_is_op(copy, 5), "operators not included");
+
+    UINT_EQ_OK(DP_acl_state_policy_import(copy, blob, sizeof(blob)), 0,
+               "reimporting the same policy changes nothing");
+    DP_acl_state_free(copy);
+    DP_acl_state_free(acls);
+}
+
+static void policy_version_mismatch(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    DP_FeatureTiers before = DP_acl_state_feature_tiers(acls);
+
+    unsigned char blob[DP_ACL_POLICY_LENGTH];
+    DP_acl_state_policy_export(acls, blob);
+    blob[0] = DP_ACL_POLICY_VERSION + 1;
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        blob[2 + i] = DP_ACCESS_TIER_OPERATOR;
+    }
+    UINT_EQ_OK(DP_acl_state_policy_import(acls, blob, sizeof(blob)),
+               DP_ACL_STATE_FILTERED_BIT, "version mismatch is rejected");
+    DP_FeatureTiers after = DP_acl_state_feature_tiers(acls);
+    OK(DP_feature_tiers_equal(&before, &after), "tiers are unchanged");
+
+    blob[0] = DP_ACL_POLICY_VERSION;
+    UINT_EQ_OK(DP_acl_state_policy_import(acls, blob, sizeof(blob) - 1),
+               DP_ACL_STATE_FILTERED_BIT, "truncated blob is rejected");
+    DP_acl_state_free(acls);
+}
+
+
+static void set_classic_dab(DP_UNUSED int count, DP_ClassicDab *dabs,
+                            DP_UNUSED void *user)
+{
+    DP_classic_dab_init(dabs, 0, 0, 0, 256, 255, 255);
+}
+
+static void set_mypaint_dab(DP_UNUSED int count, DP_MyPaintDab *dabs,
+                            DP_UNUSED void *user)
+{
+    DP_mypaint_dab_init(dabs, 0, 0, 0, 256, 255, 255, 0, 0);
+}
+
+static DP_Message *draw_dabs_classic_new(unsigned int context_id,
+                                         int layer_id, int blend_mode)
+{
+    return DP_msg_draw_dabs_classic_new(context_id, DP_int_to_uint16(layer_id),
+                                        0, 0, 0xff000000u,
+                                        DP_int_to_uint8(blend_mode),
+                                        set_classic_dab, 1, NULL);
+}
+
+static void set_pixel_dab(DP_UNUSED int count, DP_PixelDab *dabs,
+                          DP_UNUSED void *user)
+{
+    DP_pixel_dab_init(dabs, 0, 0, 0, 1, 255);
+}
+
+static DP_Message *draw_dabs_pixel_new(unsigned int context_id, int layer_id,
+                                       int blend_mode)
+{
+    return DP_msg_draw_dabs_pixel_new(context_id, DP_int_to_uint16(layer_id),
+                                      0, 0, 0xff000000u,
+                                      DP_int_to_uint8(blend_mode),
+                                      set_pixel_dab, 1, NULL);
+}
+
+static void alpha_locked_layer(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, layer_create_new(2, 0x201));
+    UINT_EQ_OK(handle(acls, layer_acl_new(2, 0x201,
+                                          DP_ACL_ALPHA_LOCKED_BIT
+                                              | DP_ACCESS_TIER_GUEST,
+                                          0, NULL)),
+               DP_ACL_STATE_CHANGE_LAYERS_BIT, "owner alpha locks layer");
+    OK(DP_acl_state_layer_alpha_locked_for(acls, 3, 0x201),
+       "layer is alpha locked for other user");
+    NOK(DP_acl_state_layer_alpha_locked_for(acls, 2, 0x201),
+        "layer is not alpha locked for owner");
+    NOK(DP_acl_state_layer_alpha_locked_for(acls, 1, 0x201),
+        "layer is not alpha locked for operator");
+    NOK(DP_acl_state_layer_locked_for(acls, 3, 0x201),
+        "alpha locked layer is not fully locked");
+
+    UINT_EQ_OK(handle(acls, draw_dabs_classic_new(3, 0x201,
+                                                  DP_BLEND_MODE_NORMAL)),
+               0, "other user can paint");
+    UINT_EQ_OK(handle(acls, draw_dabs_classic_new(3, 0x201,
+                                                  DP_BLEND_MODE_ERASE)),
+               DP_ACL_STATE_FILTERED_BIT, "other user can't erase");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_ALPHA_LOCKED,
+              "erase rejected because of alpha lock");
+    UINT_EQ_OK(handle(acls, DP_msg_fill_rect_new(3, 0x201, DP_BLEND_MODE_ERASE,
+                                                 0, 0, 10, 10, 0xff000000u)),
+               DP_ACL_STATE_FILTERED_BIT, "other user can't erase by filling");
+    UINT_EQ_OK(handle(acls, DP_msg_draw_dabs_mypaint_new(
+                                3, 0x201, 0, 0, 0xff000000u, 0, 0, 0, 0,
+                                set_mypaint_dab, 1, NULL)),
+               DP_ACL_STATE_FILTERED_BIT,
+               "other user can't use mypaint dabs that may erase");
+
+    UINT_EQ_OK(handle(acls, draw_dabs_classic_new(2, 0x201,
+                                                  DP_BLEND_MODE_ERASE)),
+               0, "owner can erase");
+    UINT_EQ_OK(handle(acls, draw_dabs_classic_new(1, 0x201,
+                                                  DP_BLEND_MODE_ERASE)),
+               0, "operator can erase");
+    DP_acl_state_free(acls);
+}
+
+static void immutable_layer(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, layer_create_new(2, 0x201));
+    uint8_t immutable = DP_ACL_IMMUTABLE_BIT | DP_ACCESS_TIER_GUEST;
+    UINT_EQ_OK(handle(acls, layer_acl_new(2, 0x201, immutable, 0, NULL)),
+               DP_ACL_STATE_FILTERED_BIT, "owner can't make layer immutable");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_NOT_OPERATOR,
+              "making layer immutable takes an operator");
+    UINT_EQ_OK(handle(acls, layer_acl_new(1, 0x201, immutable, 0, NULL)),
+               DP_ACL_STATE_CHANGE_LAYERS_BIT,
+               "operator makes layer immutable");
+    OK(DP_acl_state_layer_immutable(acls, 0x201), "layer is immutable");
+    OK(DP_acl_state_layer_locked_for(acls, 1, 0x201),
+       "immutable layer is locked for operator");
+
+    UINT_EQ_OK(handle(acls, draw_dabs_classic_new(1, 0x201,
+                                                  DP_BLEND_MODE_NORMAL)),
+               DP_ACL_STATE_FILTERED_BIT, "operator can't draw");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls),
+              DP_ACL_REJECTION_LAYER_IMMUTABLE,
+              "drawing rejected because layer is immutable");
+    UINT_EQ_OK(handle(acls, draw_dabs_classic_new(2, 0x201,
+                                                  DP_BLEND_MODE_NORMAL)),
+               DP_ACL_STATE_FILTERED_BIT, "owner can't draw");
+    UINT_EQ_OK(handle(acls, DP_msg_layer_retitle_new(1, 0x201, "x", 1)),
+               DP_ACL_STATE_FILTERED_BIT, "operator can't retitle");
+    UINT_EQ_OK(handle(acls, DP_msg_layer_delete_new(1, 0x201, false)),
+               DP_ACL_STATE_FILTERED_BIT, "operator can't delete");
+    UINT_EQ_OK(handle(acls, layer_acl_new(2, 0x201, DP_ACCESS_TIER_GUEST, 0,
+                                          NULL)),
+               DP_ACL_STATE_FILTERED_BIT, "owner can't clear immutability");
+    UINT_EQ_OK(DP_acl_state_unlock_layer(acls, 0x201), 0,
+               "unlocking immutable layer changes nothing");
+    OK(DP_acl_state_layer_immutable(acls, 0x201),
+       "layer stays immutable after unlocking");
+    DP_Message *msg = draw_dabs_classic_new(1, 0x201, DP_BLEND_MODE_NORMAL);
+    UINT_EQ_OK(DP_acl_state_handle(acls, msg, true), 0,
+               "overridden messages still get through");
+    DP_message_decref(msg);
+
+    UINT_EQ_OK(handle(acls, layer_acl_new(1, 0x201, DP_ACCESS_TIER_GUEST, 0,
+                                          NULL)),
+               DP_ACL_STATE_CHANGE_LAYERS_BIT,
+               "operator clears immutability");
+    NOK(DP_acl_state_layer_immutable(acls, 0x201), "layer is mutable again");
+    UINT_EQ_OK(handle(acls, draw_dabs_classic_new(1, 0x201,
+                                                  DP_BLEND_MODE_NORMAL)),
+               0, "operator can draw again");
+    UINT_EQ_OK(handle(acls, draw_dabs_classic_new(2, 0x201,
+                                                  DP_BLEND_MODE_NORMAL)),
+               0, "owner can draw again");
+
+    UINT_EQ_OK(handle(acls, layer_acl_new(1, DP_ACL_DEFAULT_LAYER_ID,
+                                          immutable, 0, NULL)),
+               0, "default layer ACL can't be immutable");
+    NULL_OK(DP_acl_state_default_layer_acl(acls), "no default layer ACL");
+    DP_acl_state_free(acls);
+}
+
+static void brush_tiers(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, DP_msg_trusted_users_new(1, set_uint8s, 1,
+                                                (uint8_t[]){2}));
+    UINT_EQ_OK(handle(acls, draw_dabs_classic_new(3, 0x101,
+                                                  DP_BLEND_MODE_NORMAL)),
+               0, "guest uses classic brush by default");
+
+    DP_FeatureTiers ft = DP_acl_state_feature_tiers(acls);
+    uint8_t tiers[DP_FEATURE_COUNT];
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        tiers[i] = DP_int_to_uint8(ft.tiers[i]);
+    }
+    tiers[DP_FEATURE_CLASSIC_BRUSH] = DP_ACCESS_TIER_TRUSTED;
+    (void)handle(acls, feature_access_levels_new(1, tiers));
+    UINT_EQ_OK(handle(acls, draw_dabs_classic_new(3, 0x101,
+                                                  DP_BLEND_MODE_NORMAL)),
+               DP_ACL_STATE_FILTERED_BIT, "guest can't use classic brush");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_FEATURE_TIER,
+              "classic brush rejected because of feature tier");
+    UINT_EQ_OK(handle(acls, draw_dabs_pixel_new(3, 0x101,
+                                                DP_BLEND_MODE_NORMAL)),
+               0, "guest still uses pixel brush");
+    UINT_EQ_OK(handle(acls, draw_dabs_classic_new(2, 0x101,
+                                                  DP_BLEND_MODE_NORMAL)),
+               0, "trusted user uses classic brush");
+
+    tiers[DP_FEATURE_CLASSIC_BRUSH] = DP_ACCESS_TIER_GUEST;
+    tiers[DP_FEATURE_PIXEL_BRUSH] = DP_ACCESS_TIER_TRUSTED;
+    (void)handle(acls, feature_access_levels_new(1, tiers));
+    UINT_EQ_OK(handle(acls, draw_dabs_pixel_new(3, 0x101,
+                                                DP_BLEND_MODE_NORMAL)),
+               DP_ACL_STATE_FILTERED_BIT, "guest can't use pixel brush");
+    UINT_EQ_OK(handle(acls, draw_dabs_classic_new(3, 0x101,
+                                                  DP_BLEND_MODE_NORMAL)),
+               0, "guest uses classic brush again");
+    DP_acl_state_free(acls);
+}
+
+
+static DP_Message *layer_tree_create_new(unsigned int context_id,
+                                         int layer_id, int target_id,
+                                         uint8_t flags)
+{
+    return DP_msg_layer_tree_create_new(
+        context_id, DP_int_to_uint16(layer_id), 0, DP_int_to_uint16(target_id),
+        0, flags, "layer", 5);
+}
+
+static void layer_limit(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    DP_acl_state_max_layers_set(acls, 3);
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    UINT_EQ_OK(handle(acls, layer_create_new(1, 0x101)), 0, "create layer 1");
+    UINT_EQ_OK(handle(acls, layer_tree_create_new(
+                                1, 0x102, 0,
+                                DP_MSG_LAYER_TREE_CREATE_FLAGS_GROUP)),
+               0, "create group");
+    UINT_EQ_OK(handle(acls, layer_tree_create_new(
+                                1, 0x103, 0x102,
+                                DP_MSG_LAYER_TREE_CREATE_FLAGS_INTO)),
+               0, "create layer in group");
+    INT_EQ_OK(DP_acl_state_layer_count(acls), 3, "three layers tracked");
+
+    UINT_EQ_OK(handle(acls, layer_create_new(1, 0x104)),
+               DP_ACL_STATE_FILTERED_BIT, "fourth layer is over the limit");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_LAYER_LIMIT,
+              "rejected because of layer limit");
+
+    (void)handle(acls, DP_msg_layer_delete_new(1, 0x101, false));
+    INT_EQ_OK(DP_acl_state_layer_count(acls), 2, "deleting frees one layer");
+    UINT_EQ_OK(handle(acls, layer_create_new(1, 0x104)), 0,
+               "can create a layer after deleting one");
+
+    (void)handle(acls, DP_msg_layer_tree_delete_new(1, 0x102, 0));
+    INT_EQ_OK(DP_acl_state_layer_count(acls), 1,
+              "deleting group frees its children too");
+
+    DP_acl_state_reset(acls, 0);
+    INT_EQ_OK(DP_acl_state_layer_count(acls), 0, "reset clears layers");
+    INT_EQ_OK(DP_acl_state_max_layers(acls), 3, "limit survives reset");
+    DP_acl_state_free(acls);
+}
+
+static DP_Message *canvas_resize_new(unsigned int context_id, int right,
+                                     int bottom)
+{
+    return DP_msg_canvas_resize_new(context_id, 0, right, bottom, 0);
+}
+
+static void canvas_size_limit(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    DP_acl_state_max_canvas_size_set(acls, 1000, 800);
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    UINT_EQ_OK(handle(acls, canvas_resize_new(1, 1000, 800)), 0,
+               "resize up to the limit");
+    int width, height;
+    DP_acl_state_canvas_size(acls, &width, &height);
+    INT_EQ_OK(width, 1000, "canvas width tracked");
+    INT_EQ_OK(height, 800, "canvas height tracked");
+
+    UINT_EQ_OK(handle(acls, canvas_resize_new(1, 1, 0)),
+               DP_ACL_STATE_FILTERED_BIT, "resize beyond max width");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls),
+              DP_ACL_REJECTION_CANVAS_TOO_LARGE, "rejected as too large");
+    UINT_EQ_OK(handle(acls, canvas_resize_new(1, 0, 1)),
+               DP_ACL_STATE_FILTERED_BIT, "resize beyond max height");
+    UINT_EQ_OK(handle(acls, canvas_resize_new(1, -500, -400)), 0,
+               "shrinking is fine");
+    UINT_EQ_OK(handle(acls, canvas_resize_new(1, 500, 0)), 0,
+               "growing back within the limit is fine");
+    DP_acl_state_canvas_size(acls, &width, &height);
+    INT_EQ_OK(width, 1000, "rejected resizes don't change tracked width");
+    INT_EQ_OK(height, 400, "rejected resizes don't change tracked height");
+    DP_Message *msg = canvas_resize_new(1, 1000, 0);
+    UINT_EQ_OK(DP_acl_state_handle(acls, msg, true), 0,
+               "override resizes beyond the limit");
+    DP_message_decref(msg);
+
+    DP_acl_state_max_canvas_size_set(acls, 999999, 0);
+    int max_width, max_height;
+    DP_acl_state_max_canvas_size(acls, &max_width, &max_height);
+    INT_EQ_OK(max_width, DP_ACL_MAX_CANVAS_DIMENSION,
+              "max width clamped to ceiling");
+    INT_EQ_OK(max_height, 0, "zero max height means the ceiling");
+    UINT_EQ_OK(handle(acls, canvas_resize_new(1, DP_ACL_MAX_CANVAS_DIMENSION,
+                                              0)),
+               DP_ACL_STATE_FILTERED_BIT, "can't resize beyond the ceiling");
+    UINT_EQ_OK(handle(acls, canvas_resize_new(1, 0, 20000)), 0,
+               "can resize up to the ceiling when uncapped");
+
+    DP_acl_state_reset(acls, 0);
+    DP_acl_state_canvas_size(acls, &width, &height);
+    INT_EQ_OK(width, 0, "reset clears tracked width");
+    INT_EQ_OK(height, 0, "reset clears tracked height");
+    DP_acl_state_max_canvas_size(acls, &max_width, &max_height);
+    INT_EQ_OK(max_width, DP_ACL_MAX_CANVAS_DIMENSION, "limit survives reset");
+    DP_acl_state_free(acls);
+}
+
+
+static DP_Message *annotation_create_new(unsigned int context_id,
+                                         int annotation_id)
+{
+    return DP_msg_annotation_create_new(
+        context_id, DP_int_to_uint16(annotation_id), 0, 0, 100, 100);
+}
+
+static DP_Message *annotation_edit_new(unsigned int context_id,
+                                       int annotation_id, const char *text)
+{
+    return DP_msg_annotation_edit_new(context_id,
+                                      DP_int_to_uint16(annotation_id), 0, 0,
+                                      0, text, strlen(text));
+}
+
+static DP_Message *annotation_protect_new(unsigned int context_id,
+                                          int annotation_id, bool protect)
+{
+    return DP_msg_annotation_edit_new(
+        context_id, DP_int_to_uint16(annotation_id), 0,
+        protect ? DP_MSG_ANNOTATION_EDIT_FLAGS_PROTECT : 0, 0, "", 0);
+}
+
+static DP_Message *annotation_text_edit_new(unsigned int context_id,
+                                            int annotation_id, int offset,
+                                            int count, const char *text)
+{
+    return DP_msg_annotation_text_edit_new(
+        context_id, DP_int_to_uint16(annotation_id), DP_int_to_uint16(offset),
+        DP_int_to_uint16(count), text, strlen(text));
+}
+
+static void annotation_limits(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    DP_acl_state_max_annotations_set(acls, 2);
+    DP_acl_state_max_annotation_text_length_set(acls, 5);
+    UINT_EQ_OK(handle(acls, annotation_create_new(1, 0x101)), 0,
+               "create annotation 1");
+    UINT_EQ_OK(handle(acls, annotation_create_new(1, 0x102)), 0,
+               "create annotation 2");
+    INT_EQ_OK(DP_acl_state_annotation_count(acls), 2,
+              "two annotations tracked");
+    UINT_EQ_OK(handle(acls, annotation_create_new(1, 0x103)),
+               DP_ACL_STATE_FILTERED_BIT, "third annotation is over the limit");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls),
+              DP_ACL_REJECTION_ANNOTATION_LIMIT,
+              "rejected because of annotation limit");
+
+    (void)handle(acls, DP_msg_annotation_delete_new(1, 0x101));
+    UINT_EQ_OK(handle(acls, annotation_create_new(1, 0x103)), 0,
+               "can create an annotation after deleting one");
+
+    UINT_EQ_OK(handle(acls, annotation_edit_new(1, 0x102, "hello")), 0,
+               "text at the length limit is allowed");
+    UINT_EQ_OK(handle(acls, annotation_edit_new(1, 0x102, "hello!")),
+               DP_ACL_STATE_FILTERED_BIT, "text over the length limit");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls),
+              DP_ACL_REJECTION_ANNOTATION_TOO_LONG,
+              "rejected because text is too long");
+
+    DP_acl_state_reset(acls, 0);
+    INT_EQ_OK(DP_acl_state_annotation_count(acls), 0,
+              "reset clears annotations");
+    INT_EQ_OK(DP_acl_state_max_annotations(acls), 2,
+              "annotation limit survives reset");
+    INT_EQ_OK(DP_acl_state_max_annotation_text_length(acls), 5,
+              "text length limit survives reset");
+    DP_acl_state_free(acls);
+}
+
+static void annotation_locks(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, annotation_create_new(1, 0x101));
+    (void)handle(acls, annotation_create_new(1, 0x102));
+
+    UINT_EQ_OK(handle(acls, annotation_protect_new(1, 0x101, true)),
+               DP_ACL_STATE_CHANGE_ANNOTATIONS_BIT, "protecting changes acls");
+    OK(DP_acl_state_annotation_locked(acls, 0x101), "annotation is locked");
+    UINT_EQ_OK(handle(acls, annotation_protect_new(1, 0x101, true)), 0,
+               "protecting again doesn't change acls");
+    UINT_EQ_OK(handle(acls, annotation_protect_new(2, 0x101, false)),
+               DP_ACL_STATE_FILTERED_BIT,
+               "filtered unprotect doesn't change acls");
+    OK(DP_acl_state_annotation_locked(acls, 0x101),
+       "annotation is still locked");
+    UINT_EQ_OK(handle(acls, annotation_protect_new(1, 0x101, false)),
+               DP_ACL_STATE_CHANGE_ANNOTATIONS_BIT,
+               "unprotecting changes acls");
+    NOK(DP_acl_state_annotation_locked(acls, 0x101),
+        "annotation is unlocked");
+    UINT_EQ_OK(handle(acls, annotation_protect_new(1, 0x101, false)), 0,
+               "unprotecting again doesn't change acls");
+
+    (void)handle(acls, annotation_protect_new(1, 0x102, true));
+    UINT_EQ_OK(handle(acls, DP_msg_annotation_delete_new(1, 0x102)),
+               DP_ACL_STATE_CHANGE_ANNOTATIONS_BIT,
+               "deleting a protected annotation changes acls");
+    NOK(DP_acl_state_annotation_locked(acls, 0x102),
+        "deleted annotation is unlocked");
+    UINT_EQ_OK(handle(acls, DP_msg_annotation_delete_new(1, 0x101)), 0,
+               "deleting an unprotected annotation doesn't change acls");
+    DP_acl_state_free(acls);
+}
+
+static void annotation_text_edits(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    DP_acl_state_strict_set(acls, true);
+    DP_acl_state_max_annotation_text_length_set(acls, 5);
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){3}));
+    (void)handle(acls, annotation_create_new(1, 0x101));
+
+    UINT_EQ_OK(handle(acls, annotation_text_edit_new(1, 0x101, 0, 0, "hello")),
+               0, "owner can insert text");
+    UINT_EQ_OK(handle(acls, annotation_text_edit_new(1, 0x101, 2, 3, "")), 0,
+               "owner can delete text");
+    UINT_EQ_OK(handle(acls, annotation_text_edit_new(3, 0x101, 9, 9, "x")), 0,
+               "operator can edit someone else's text");
+    UINT_EQ_OK(handle(acls, annotation_text_edit_new(2, 0x101, 0, 0, "x")),
+               DP_ACL_STATE_FILTERED_BIT, "other user can't edit text");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_NOT_OWNER,
+              "rejected because user doesn't own the annotation");
+    UINT_EQ_OK(
+        handle(acls, annotation_text_edit_new(1, 0x101, 0, 0, "hello!")),
+        DP_ACL_STATE_FILTERED_BIT, "inserted text over the length limit");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls),
+              DP_ACL_REJECTION_ANNOTATION_TOO_LONG,
+              "rejected because inserted text is too long");
+    UINT_EQ_OK(handle(acls, annotation_text_edit_new(1, 0x102, 0, 0, "x")),
+               DP_ACL_STATE_FILTERED_BIT, "can't edit nonexistent annotation");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls),
+              DP_ACL_REJECTION_NO_SUCH_ANNOTATION,
+              "rejected because annotation doesn't exist");
+    DP_acl_state_free(acls);
+}
+
+static void annotation_id_prefix(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+
+    UINT_EQ_OK(handle(acls, annotation_create_new(2, 0x201)), 0,
+               "guest can create an annotation with their own prefix");
+    UINT_EQ_OK(handle(acls, annotation_create_new(2, 0x301)),
+               DP_ACL_STATE_FILTERED_BIT,
+               "guest can't create an annotation with another user's prefix");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_NOT_OWNER,
+              "mismatched annotation prefix rejection reason");
+    UINT_EQ_OK(handle(acls, layer_create_new(2, 0x301)),
+               DP_ACL_STATE_FILTERED_BIT,
+               "same goes for a layer with another user's prefix");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_NOT_OWNER,
+              "mismatched layer prefix rejection reason");
+    UINT_EQ_OK(handle(acls, annotation_create_new(1, 0x302)), 0,
+               "operator can create an annotation under a different owner");
+
+    // Even without permission to create annotations, the spoofed prefix is
+    // what gets reported.
+    uint8_t tiers[DP_FEATURE_COUNT];
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        tiers[i] = DP_ACCESS_TIER_GUEST;
+    }
+    tiers[DP_FEATURE_CREATE_ANNOTATION] = DP_ACCESS_TIER_OPERATOR;
+    (void)handle(acls, feature_access_levels_new(1, tiers));
+    UINT_EQ_OK(handle(acls, annotation_create_new(2, 0x303)),
+               DP_ACL_STATE_FILTERED_BIT,
+               "guest without access can't spoof a prefix either");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_NOT_OWNER,
+              "spoofed prefix is reported over missing access");
+    DP_acl_state_free(acls);
+}
+
+
+static void check_governing_feature(TEST_PARAMS, DP_Message *msg,
+                                    int expected_feature)
+{
+    DP_Feature feature;
+    bool governed = DP_message_governing_feature(msg, &feature);
+    const char *type_name = DP_message_type_enum_name_unprefixed(
+        DP_message_type(msg));
+    if (expected_feature == -1) {
+        NOK(governed, "%s is not governed by a feature", type_name);
+    }
+    else if (OK(governed, "%s is governed by a feature", type_name)) {
+        INT_EQ_OK(feature, expected_feature, "%s is governed by %s",
+                  type_name, DP_feature_enum_name(expected_feature));
+    }
+    DP_message_decref(msg);
+}
+
+static void governing_features(TEST_PARAMS)
+{
+    check_governing_feature(TEST_ARGS, DP_msg_laser_trail_new(1, 0, 1),
+                            DP_FEATURE_LASER);
+    check_governing_feature(TEST_ARGS, DP_msg_canvas_resize_new(1, 0, 1, 1, 0),
+                            DP_FEATURE_RESIZE);
+    check_governing_feature(TEST_ARGS, layer_create_new(1, 0x101),
+                            DP_FEATURE_OWN_LAYERS);
+    check_governing_feature(TEST_ARGS,
+                            DP_msg_layer_retitle_new(1, 0x101, "x", 1),
+                            DP_FEATURE_OWN_LAYERS);
+    check_governing_feature(TEST_ARGS,
+                            DP_msg_layer_tree_move_new(1, 0x101, 0, 0),
+                            DP_FEATURE_OWN_LAYERS);
+    check_governing_feature(TEST_ARGS,
+                            DP_msg_layer_order_new(1, NULL, 0, NULL),
+                            DP_FEATURE_EDIT_LAYERS);
+    check_governing_feature(
+        TEST_ARGS,
+        DP_msg_layer_order_move_new(1, 0x101,
+                                    DP_MSG_LAYER_ORDER_MOVE_DIRECTION_INDEX, 0),
+        DP_FEATURE_EDIT_LAYERS);
+    check_governing_feature(TEST_ARGS,
+                            DP_msg_fill_rect_new(1, 0x101, DP_BLEND_MODE_NORMAL,
+                                                 0, 0, 1, 1, 0xff000000u),
+                            DP_FEATURE_PUT_IMAGE);
+    check_governing_feature(TEST_ARGS, annotation_create_new(1, 0x101),
+                            DP_FEATURE_CREATE_ANNOTATION);
+    check_governing_feature(TEST_ARGS,
+                            DP_msg_move_rect_new(1, 0x101, 0x101, 0, 0, 1, 1,
+                                                 1, 1, NULL, 0, NULL),
+                            DP_FEATURE_REGION_MOVE);
+    check_governing_feature(
+        TEST_ARGS,
+        DP_msg_draw_dabs_mypaint_new(1, 0x101, 0, 0, 0xff000000u, 0, 0, 0, 0,
+                                     set_mypaint_dab, 1, NULL),
+        DP_FEATURE_MYPAINT);
+    check_governing_feature(
+        TEST_ARGS,
+        DP_msg_set_metadata_int_new(1, DP_MSG_SET_METADATA_INT_FIELD_DPIX, 72),
+        DP_FEATURE_METADATA);
+    check_governing_feature(
+        TEST_ARGS,
+        DP_msg_set_metadata_int_new(
+            1, DP_MSG_SET_METADATA_INT_FIELD_FRAMERATE, 24),
+        DP_FEATURE_TIMELINE);
+    check_governing_feature(TEST_ARGS,
+                            DP_msg_track_create_new(1, 0x101, 0, 0, "t", 1),
+                            DP_FEATURE_TIMELINE);
+    check_governing_feature(TEST_ARGS,
+                            DP_msg_key_frame_set_new(1, 0x101, 0, 0, 0, 0),
+                            DP_FEATURE_TIMELINE);
+    check_governing_feature(TEST_ARGS, DP_msg_undo_new(1, 0, false),
+                            DP_FEATURE_UNDO);
+    check_governing_feature(TEST_ARGS,
+                            draw_dabs_classic_new(1, 0x101,
+                                                  DP_BLEND_MODE_NORMAL),
+                            DP_FEATURE_CLASSIC_BRUSH);
+    check_governing_feature(TEST_ARGS,
+                            draw_dabs_pixel_new(1, 0x101,
+                                                DP_BLEND_MODE_NORMAL),
+                            DP_FEATURE_PIXEL_BRUSH);
+    check_governing_feature(TEST_ARGS, DP_msg_undo_point_new(1), -1);
+    check_governing_feature(TEST_ARGS, join_new(1, 0), -1);
+    check_governing_feature(TEST_ARGS, session_owner_new(0, NULL), -1);
+}
+
+static void check_required_tier(TEST_PARAMS, DP_AclState *acls,
+                                DP_Message *msg, int expected_tier)
+{
+    const char *type_name =
+        DP_message_type_enum_name_unprefixed(DP_message_type(msg));
+    UINT_EQ_OK(DP_acl_state_handle(acls, msg, false), DP_ACL_STATE_FILTERED_BIT,
+               "%s is rejected", type_name);
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_FEATURE_TIER,
+              "%s is rejected because of feature tier", type_name);
+    DP_AccessTier tier;
+    if (OK(DP_acl_state_required_tier(acls, msg, &tier),
+           "%s has a required tier", type_name)) {
+        INT_EQ_OK(tier, expected_tier, "%s requires %s", type_name,
+                  DP_access_tier_enum_name(expected_tier));
+    }
+    DP_message_decref(msg);
+}
+
+static void required_tiers(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+
+    DP_FeatureTiers ft = DP_acl_state_feature_tiers(acls);
+    uint8_t tiers[DP_FEATURE_COUNT];
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        tiers[i] = DP_int_to_uint8(ft.tiers[i]);
+    }
+    tiers[DP_FEATURE_RESIZE] = DP_ACCESS_TIER_TRUSTED;
+    tiers[DP_FEATURE_PUT_IMAGE] = DP_ACCESS_TIER_AUTHENTICATED;
+    tiers[DP_FEATURE_LASER] = DP_ACCESS_TIER_OPERATOR;
+    tiers[DP_FEATURE_CREATE_ANNOTATION] = DP_ACCESS_TIER_TRUSTED;
+    (void)handle(acls, feature_access_levels_new(1, tiers));
+
+    check_required_tier(TEST_ARGS, acls,
+                        DP_msg_canvas_resize_new(2, 0, 1, 0, 0),
+                        DP_ACCESS_TIER_TRUSTED);
+    check_required_tier(TEST_ARGS, acls,
+                        DP_msg_fill_rect_new(2, 0x201, DP_BLEND_MODE_NORMAL, 0,
+                                             0, 1, 1, 0xff000000u),
+                        DP_ACCESS_TIER_AUTHENTICATED);
+    check_required_tier(TEST_ARGS, acls, DP_msg_laser_trail_new(2, 0, 1),
+                        DP_ACCESS_TIER_OPERATOR);
+    check_required_tier(TEST_ARGS, acls, annotation_create_new(2, 0x201),
+                        DP_ACCESS_TIER_TRUSTED);
+
+    DP_Message *msg = DP_msg_undo_point_new(2);
+    DP_AccessTier tier;
+    NOK(DP_acl_state_required_tier(acls, msg, &tier),
+        "ungoverned command has no required tier");
+    DP_message_decref(msg);
+
+    tiers[DP_FEATURE_RESIZE] = DP_ACCESS_TIER_GUEST;
+    (void)handle(acls, feature_access_levels_new(1, tiers));
+    msg = DP_msg_canvas_resize_new(2, 0, 1, 0, 0);
+    if (OK(DP_acl_state_required_tier(acls, msg, &tier),
+           "resize still has a required tier")) {
+        INT_EQ_OK(tier, DP_ACCESS_TIER_GUEST,
+                  "required tier follows the feature tiers");
+    }
+    DP_message_decref(msg);
+    DP_acl_state_free(acls);
+}
+
+
+static void handle_local_mode_messages(DP_AclState *acls, uint8_t *results)
+{
+    uint8_t *r = results;
+    *r++ = handle(acls, layer_create_new(1, 0x101));
+    *r++ = handle(acls, layer_create_new(1, 0x202));
+    *r++ = handle(acls, DP_msg_layer_retitle_new(1, 0x101, "x", 1));
+    *r++ = handle(acls, draw_dabs_classic_new(1, 0x101, DP_BLEND_MODE_ERASE));
+    *r++ = handle(acls, annotation_create_new(1, 0x101));
+    *r++ = handle(acls, annotation_edit_new(1, 0x101, "hello"));
+    *r++ = handle(acls, DP_msg_layer_visibility_new(1, 0x101, false));
+    *r++ = handle(acls, DP_msg_layer_delete_new(1, 0x101, false));
+    *r++ = handle(acls, DP_msg_undo_new(1, 0, false));
+}
+
+static void local_mode(TEST_PARAMS)
+{
+    DP_AclState *fast = DP_acl_state_new();
+    DP_acl_state_reset(fast, 1);
+    OK(DP_acl_state_local_mode(fast), "reset with a local user is local mode");
+
+    DP_AclState *slow = DP_acl_state_new();
+    DP_acl_state_reset(slow, 1);
+    (void)handle(slow, DP_msg_trusted_users_new(0, NULL, 0, NULL));
+    NOK(DP_acl_state_local_mode(slow), "trusted users message ends local mode");
+
+    uint8_t fast_results[9], slow_results[9];
+    handle_local_mode_messages(fast, fast_results);
+    handle_local_mode_messages(slow, slow_results);
+    OK(DP_acl_state_local_mode(fast), "commands don't end local mode");
+    OK(memcmp(fast_results, slow_results, sizeof(fast_results)) == 0,
+       "fast path gives the same results as the slow path");
+    INT_EQ_OK(DP_acl_state_layer_count(fast), DP_acl_state_layer_count(slow),
+              "fast path tracks layers like the slow path");
+    INT_EQ_OK(DP_acl_state_annotation_count(fast),
+              DP_acl_state_annotation_count(slow),
+              "fast path tracks annotations like the slow path");
+    DP_acl_state_free(slow);
+
+    (void)handle(fast, layer_acl_new(1, 0x202, DP_ACL_ALL_LOCKED_BIT, 0, NULL));
+    NOK(DP_acl_state_local_mode(fast), "layer acl ends local mode");
+    UINT_EQ_OK(handle(fast, draw_dabs_classic_new(1, 0x202,
+                                                  DP_BLEND_MODE_NORMAL)),
+               DP_ACL_STATE_FILTERED_BIT, "locked layer is enforced again");
+
+    DP_acl_state_reset(fast, 0);
+    NOK(DP_acl_state_local_mode(fast), "no local mode without a local user");
+    DP_acl_state_max_layers_set(fast, 10);
+    DP_acl_state_reset(fast, 1);
+    NOK(DP_acl_state_local_mode(fast), "no local mode with a layer limit");
+    DP_acl_state_free(fast);
+}
+
+static void local_mode_lock_layer(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    DP_acl_state_reset(acls, 1);
+    (void)handle(acls, layer_create_new(1, 0x101));
+    DP_acl_state_lock_layer(acls, 0x101, DP_ACCESS_TIER_GUEST, 0, NULL);
+    NOK(DP_acl_state_local_mode(acls), "locking a layer ends local mode");
+    UINT_EQ_OK(handle(acls, draw_dabs_classic_new(1, 0x101,
+                                                  DP_BLEND_MODE_NORMAL)),
+               DP_ACL_STATE_FILTERED_BIT,
+               "local user can't draw on the locked layer");
+    DP_acl_state_free(acls);
+}
+
+
+static void layer_acl_exclusive(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, layer_create_new(1, 0x101));
+
+    UINT_EQ_OK(handle(acls, DP_msg_layer_acl_exclusive_new(1, 0x101, 2, true)),
+               DP_ACL_STATE_CHANGE_LAYERS_BIT, "grant user 2");
+    NOK(DP_acl_state_layer_locked_for(acls, 2, 0x101),
+        "layer is open to user 2");
+    OK(DP_acl_state_layer_locked_for(acls, 3, 0x101),
+       "granting starts an exclusive list that excludes user 3");
+
+    UINT_EQ_OK(handle(acls, DP_msg_layer_acl_exclusive_new(1, 0x101, 3, true)),
+               DP_ACL_STATE_CHANGE_LAYERS_BIT, "grant user 3");
+    NOK(DP_acl_state_layer_locked_for(acls, 2, 0x101),
+        "layer is still open to user 2");
+    NOK(DP_acl_state_layer_locked_for(acls, 3, 0x101),
+        "layer is now open to user 3");
+    UINT_EQ_OK(handle(acls, DP_msg_layer_acl_exclusive_new(1, 0x101, 3, true)),
+               0, "granting again changes nothing");
+
+    UINT_EQ_OK(handle(acls, DP_msg_layer_acl_exclusive_new(1, 0x101, 2, false)),
+               DP_ACL_STATE_CHANGE_LAYERS_BIT, "revoke user 2");
+    OK(DP_acl_state_layer_locked_for(acls, 2, 0x101),
+       "layer is no longer open to user 2");
+    NOK(DP_acl_state_layer_locked_for(acls, 3, 0x101),
+        "layer is still open to user 3");
+
+    UINT_EQ_OK(handle(acls, DP_msg_layer_acl_exclusive_new(1, 0x101, 3, false)),
+               DP_ACL_STATE_CHANGE_LAYERS_BIT, "revoke last user");
+    NOK(DP_acl_state_layer_locked_for(acls, 2, 0x101),
+        "revoking the last user opens the layer to all");
+    UINT_EQ_OK(handle(acls, DP_msg_layer_acl_exclusive_new(1, 0x101, 3, false)),
+               0, "revoking without an exclusive list changes nothing");
+
+    UINT_EQ_OK(handle(acls, DP_msg_layer_acl_exclusive_new(3, 0x101, 3, true)),
+               DP_ACL_STATE_FILTERED_BIT, "non-owner can't grant access");
+    UINT_EQ_OK(handle(acls, DP_msg_layer_acl_exclusive_new(1, 0, 3, true)),
+               DP_ACL_STATE_FILTERED_BIT, "layer 0 is invalid");
+    DP_acl_state_free(acls);
+}
+
+
+static void snapshot_restore(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    DP_acl_state_max_layers_set(acls, 10);
+    DP_acl_state_operator_policy_set(acls, DP_ACL_OPERATOR_POLICY_KEEP_LAST);
+    (void)handle(acls, join_new(1, 0));
+    (void)handle(acls, join_new(2, 0));
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, layer_create_new(1, 0x101));
+    (void)handle(acls, layer_create_new(2, 0x201));
+    (void)handle(acls, layer_acl_new(1, 0x101, DP_ACL_ALL_LOCKED_BIT, 0, NULL));
+    (void)handle(acls, annotation_create_new(1, 0x101));
+
+    DP_AclSnapshot snapshot;
+    DP_acl_state_snapshot(acls, &snapshot);
+    INT_EQ_OK(snapshot.layer_count, 1, "snapshot has the layer acl");
+    INT_EQ_OK(snapshot.live_layer_count, 2, "snapshot has the live layers");
+    INT_EQ_OK(snapshot.live_annotation_count, 1,
+              "snapshot has the live annotation");
+    char *before = DP_acl_state_dump(acls);
+
+    (void)handle(acls, layer_acl_new(1, 0x101, 0, 0, NULL));
+    (void)handle(acls, DP_msg_layer_delete_new(2, 0x201, false));
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){2}));
+    (void)handle(acls, join_new(3, 0));
+    DP_acl_state_max_layers_set(acls, 0);
+    DP_acl_state_operator_policy_set(acls, DP_ACL_OPERATOR_POLICY_ALLOW_EMPTY);
+
+    DP_AclSnapshot mutated;
+    DP_acl_state_snapshot(acls, &mutated);
+    NOK(DP_acl_snapshot_equal(&snapshot, &mutated),
+        "mutated state differs from snapshot");
+    DP_acl_snapshot_dispose(&mutated);
+
+    DP_acl_state_restore(acls, &snapshot);
+    DP_AclSnapshot restored;
+    DP_acl_state_snapshot(acls, &restored);
+    OK(DP_acl_snapshot_equal(&snapshot, &restored),
+       "restored state equals snapshot");
+    DP_acl_snapshot_dispose(&restored);
+
+    char *after = DP_acl_state_dump(acls);
+    STR_EQ_OK(after, before, "restored state dumps the same");
+    DP_free(after);
+    DP_free(before);
+
+    INT_EQ_OK(DP_acl_state_max_layers(acls), 10, "layer limit restored");
+    INT_EQ_OK(DP_acl_state_layer_count(acls), 2, "live layers restored");
+    OK(DP_acl_state_layer_locked_for(acls, 2, 0x101), "layer lock restored");
+    OK(DP_acl_state_is_op(acls, 1), "operator restored");
+    NOK(DP_acl_state_is_op(acls, 2), "mutated operator gone");
+
+    DP_acl_snapshot_dispose(&snapshot);
+    DP_acl_state_free(acls);
+}
+
+static void summary_contains(TEST_PARAMS, const char *summary,
+                             const char *expected)
+{
+    OK(strstr(summary, expected) != NULL, "summary contains '%s'", expected);
+}
+
+static void summary(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    char *initial = DP_acl_state_summary(acls);
+    summary_contains(TEST_ARGS, initial, "Operators: none\n");
+    summary_contains(TEST_ARGS, initial, "Locked layers: none\n");
+    DP_free(initial);
+
+    (void)handle(acls, session_owner_new(2, (uint8_t[]){1, 7}));
+    (void)handle(acls, DP_msg_trusted_users_new(1, set_uint8s, 1,
+                                                (uint8_t[]){3}));
+    (void)handle(acls, DP_msg_user_acl_new(1, set_uint8s, 1, (uint8_t[]){4}));
+    uint8_t tiers[DP_FEATURE_COUNT];
+    memset(tiers, 255, sizeof(tiers));
+    tiers[DP_FEATURE_UNDO] = DP_ACCESS_TIER_TRUSTED;
+    (void)handle(acls, feature_access_levels_new(1, tiers));
+    (void)handle(acls, layer_create_new(1, 0x101));
+    (void)handle(acls, layer_acl_new(1, 0x101, DP_ACL_ALL_LOCKED_BIT, 0, NULL));
+    (void)handle(acls,
+                 layer_acl_new(1, 0x102, DP_ACCESS_TIER_TRUSTED, 0, NULL));
+    (void)handle(acls, DP_msg_layer_acl_exclusive_new(1, 0x103, 3, true));
+    (void)handle(acls, layer_acl_new(1, 0x104, DP_ACL_ALPHA_LOCKED_BIT
+                                                   | DP_ACCESS_TIER_GUEST,
+                                     0, NULL));
+
+    char *summary = DP_acl_state_summary(acls);
+    summary_contains(TEST_ARGS, summary, "Operators: 1, 7\n");
+    summary_contains(TEST_ARGS, summary, "Trusted: 3\n");
+    summary_contains(TEST_ARGS, summary, "Locked users: 4\n");
+    summary_contains(TEST_ARGS, summary, "undo trusted,");
+    summary_contains(TEST_ARGS, summary, "put_image guest,");
+    summary_contains(TEST_ARGS, summary,
+                     "Locked layers:\n    0x0101: locked\n"
+                     "    0x0102: trusted only\n"
+                     "    0x0103: exclusive to 3\n");
+    OK(strstr(summary, "0x0104") == NULL, "alpha lock isn't in the summary");
+    DP_free(summary);
+    DP_acl_state_free(acls);
+}
+
+
+static void handle_edit_foreign_layer(DP_AclState *acls, uint8_t user_id,
+                                      uint8_t *results)
+{
+    uint8_t *r = results;
+    *r++ = handle(acls, DP_msg_layer_attributes_new(user_id, 0x101, 0, 0,
+                                                    255, DP_BLEND_MODE_NORMAL));
+    *r++ = handle(acls, DP_msg_layer_retitle_new(user_id, 0x101, "x", 1));
+    *r++ = handle(acls, DP_msg_layer_order_new(user_id, set_uint16s, 2,
+                                               (uint16_t[]){0x102, 0x101}));
+    *r++ = handle(acls, DP_msg_layer_order_move_new(
+                            user_id, 0x101,
+                            DP_MSG_LAYER_ORDER_MOVE_DIRECTION_UP, 0));
+    *r++ = handle(acls, DP_msg_layer_delete_new(user_id, 0x101, false));
+}
+
+static void trusted_edit_layers(TEST_PARAMS)
+{
+    static const char *commands[] = {"attributes", "retitle", "order",
+                                     "order move", "delete"};
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls,
+                 DP_msg_trusted_users_new(0, set_uint8s, 1, (uint8_t[]){2}));
+    (void)handle(acls, layer_create_new(1, 0x101));
+    (void)handle(acls, layer_create_new(1, 0x102));
+
+    uint8_t results[5];
+    handle_edit_foreign_layer(acls, 2, results);
+    for (int i = 0; i < 5; ++i) {
+        UINT_EQ_OK(results[i], DP_ACL_STATE_FILTERED_BIT,
+                   "trusted can't %s foreign layer by default", commands[i]);
+    }
+
+    uint8_t tiers[DP_FEATURE_COUNT];
+    DP_FeatureTiers feature = DP_acl_state_feature_tiers(acls);
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        tiers[i] = (uint8_t)feature.tiers[i];
+    }
+    tiers[DP_FEATURE_EDIT_LAYERS] = DP_ACCESS_TIER_TRUSTED;
+    (void)handle(acls, feature_access_levels_new(1, tiers));
+
+    handle_edit_foreign_layer(acls, 3, results);
+    for (int i = 0; i < 5; ++i) {
+        UINT_EQ_OK(results[i], DP_ACL_STATE_FILTERED_BIT,
+                   "guest still can't %s foreign layer", commands[i]);
+    }
+
+    handle_edit_foreign_layer(acls, 2, results);
+    for (int i = 0; i < 5; ++i) {
+        UINT_EQ_OK(results[i], 0,
+                   "trusted can %s foreign layer with edit_layers trusted",
+                   commands[i]);
+    }
+    DP_acl_state_free(acls);
+}
+
+
+static void default_layer_existence(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, layer_create_new(1, 0x101));
+
+    // Layers may come from places the ACL state doesn't know about, so only
+    // strict mode checks whether they exist.
+    UINT_EQ_OK(handle(acls, DP_msg_default_layer_new(1, 0x102)), 0,
+               "default layer can be set to an untracked layer when lax");
+    DP_acl_state_strict_set(acls, true);
+
+    UINT_EQ_OK(handle(acls, DP_msg_default_layer_new(1, 0x101)), 0,
+               "default layer can be set to an existing layer");
+    UINT_EQ_OK(handle(acls, DP_msg_default_layer_new(1, 0x102)),
+               DP_ACL_STATE_FILTERED_BIT,
+               "default layer can't be set to a nonexistent layer");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls),
+              DP_ACL_REJECTION_NO_SUCH_LAYER,
+              "nonexistent default layer rejection reason");
+
+    (void)handle(acls, DP_msg_layer_delete_new(1, 0x101, false));
+    UINT_EQ_OK(handle(acls, DP_msg_default_layer_new(1, 0x101)),
+               DP_ACL_STATE_FILTERED_BIT,
+               "default layer can't be set to a deleted layer");
+    UINT_EQ_OK(handle(acls, DP_msg_default_layer_new(1, 0)), 0,
+               "default layer can be unset");
+    UINT_EQ_OK(handle(acls, DP_msg_default_layer_new(2, 0)),
+               DP_ACL_STATE_FILTERED_BIT, "non-operator can't set it");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_NOT_OPERATOR,
+              "non-operator rejection reason");
+    DP_acl_state_free(acls);
+}
+
+static void client_meta_own_color(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+
+    UINT_EQ_OK(handle(acls, DP_msg_client_meta_new(2, 2, 0xffff0000u)), 0,
+               "user can set their own color");
+    UINT_EQ_OK(handle(acls, DP_msg_client_meta_new(2, 3, 0xffff0000u)),
+               DP_ACL_STATE_FILTERED_BIT,
+               "user can't set someone else's color");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_INVALID,
+              "someone else's color rejection reason");
+    UINT_EQ_OK(handle(acls, DP_msg_client_meta_new(1, 2, 0xff00ff00u)),
+               DP_ACL_STATE_FILTERED_BIT,
+               "operator can't set someone else's color either");
+
+    DP_Message *msg = DP_msg_client_meta_new(2, 3, 0xffff0000u);
+    UINT_EQ_OK(DP_acl_state_handle(acls, msg, true), 0,
+               "override lets someone else's color through");
+    DP_message_decref(msg);
+    DP_acl_state_free(acls);
+}
+
+
+static void check_audit_entry(TEST_PARAMS, DP_AclState *acls, int index,
+                              uint8_t actor_id, int type, int target_id,
+                              uint8_t changes, long long start)
+{
+    const DP_AclAuditEntry *entry = DP_acl_state_audit_at(acls, index);
+    INT_EQ_OK(entry->actor_id, actor_id, "audit entry %d actor", index);
+    INT_EQ_OK(entry->type, type, "audit entry %d type", index);
+    INT_EQ_OK(entry->target_id, target_id, "audit entry %d target", index);
+    UINT_EQ_OK(entry->changes, changes, "audit entry %d changes", index);
+    OK(entry->timestamp >= start, "audit entry %d timestamp", index);
+}
+
+static void audit_log(TEST_PARAMS)
+{
+    long long start = (long long)time(NULL);
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    INT_EQ_OK(DP_acl_state_audit_count(acls), 0, "audit log off by default");
+
+    DP_acl_state_audit_capacity_set(acls, 3);
+    (void)handle(acls, layer_create_new(1, 0x101));
+    (void)handle(acls, session_owner_new(2, (uint8_t[]){1, 2}));
+    (void)handle(acls, layer_acl_new(2, 0x101, DP_ACL_ALL_LOCKED_BIT, 0, NULL));
+    (void)handle(acls, layer_acl_new(3, 0x101, 0, 0, NULL));
+    INT_EQ_OK(DP_acl_state_audit_count(acls), 2,
+              "commands and filtered messages aren't logged");
+    check_audit_entry(TEST_ARGS, acls, 0, 0, DP_MSG_SESSION_OWNER, 0,
+                      DP_ACL_STATE_CHANGE_USERS_BIT, start);
+    check_audit_entry(TEST_ARGS, acls, 1, 2, DP_MSG_LAYER_ACL, 0x101,
+                      DP_ACL_STATE_CHANGE_LAYERS_BIT, start);
+
+    (void)handle(acls, DP_msg_user_acl_new(1, set_uint8s, 1, (uint8_t[]){3}));
+    (void)handle(acls, DP_msg_layer_acl_exclusive_new(1, 0x101, 3, true));
+    INT_EQ_OK(DP_acl_state_audit_count(acls), 3, "capacity bounds the log");
+    check_audit_entry(TEST_ARGS, acls, 0, 2, DP_MSG_LAYER_ACL, 0x101,
+                      DP_ACL_STATE_CHANGE_LAYERS_BIT, start);
+    check_audit_entry(TEST_ARGS, acls, 1, 1, DP_MSG_USER_ACL, 0,
+                      DP_ACL_STATE_CHANGE_USERS_BIT, start);
+    check_audit_entry(TEST_ARGS, acls, 2, 1, DP_MSG_LAYER_ACL_EXCLUSIVE, 0x101,
+                      DP_ACL_STATE_CHANGE_LAYERS_BIT, start);
+
+    DP_acl_state_reset(acls, 1);
+    INT_EQ_OK(DP_acl_state_audit_count(acls), 3, "log survives reset");
+
+    DP_acl_state_audit_capacity_set(acls, 1);
+    INT_EQ_OK(DP_acl_state_audit_count(acls), 1, "shrinking drops entries");
+    check_audit_entry(TEST_ARGS, acls, 0, 1, DP_MSG_LAYER_ACL_EXCLUSIVE, 0x101,
+                      DP_ACL_STATE_CHANGE_LAYERS_BIT, start);
+
+    DP_acl_state_audit_capacity_set(acls, 0);
+    INT_EQ_OK(DP_acl_state_audit_count(acls), 0, "disabling clears the log");
+    DP_acl_state_free(acls);
+}
+
+static void last_change_message(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    NULL_OK(DP_acl_state_last_change_message_noinc(acls),
+            "no last change initially");
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, layer_create_new(1, 0x101));
+    (void)handle(acls, layer_acl_new(1, 0x101, DP_ACL_ALL_LOCKED_BIT, 0, NULL));
+    DP_Message *msg = DP_acl_state_last_change_message_noinc(acls);
+    FATAL(NOT_NULL_OK(msg, "layer lock is the last change"));
+    INT_EQ_OK(DP_message_type(msg), DP_MSG_LAYER_ACL,
+              "last change is layer acl");
+
+    (void)handle(acls, layer_acl_new(3, 0x101, 0, 0, NULL));
+    (void)handle(acls, layer_create_new(1, 0x102));
+    OK(DP_acl_state_last_change_message_noinc(acls) == msg,
+       "filtered messages and commands don't replace the last change");
+
+    // Replaying the captured message on top of the prior state must lead to
+    // the same layer lock.
+    DP_AclState *replay = DP_acl_state_new();
+    (void)handle(replay, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(replay, layer_create_new(1, 0x101));
+    UINT_EQ_OK(handle(replay, DP_message_incref(msg)),
+               DP_ACL_STATE_CHANGE_LAYERS_BIT, "captured message applies");
+    OK(DP_acl_state_layer_locked_for(replay, 2, 0x101),
+       "captured message reconstructs the layer lock");
+    OK(DP_acl_state_layer_locked_for(replay, 2, 0x101)
+           == DP_acl_state_layer_locked_for(acls, 2, 0x101),
+       "reconstructed lock matches the original");
+    DP_acl_state_free(replay);
+
+    DP_acl_state_reset(acls, 1);
+    NULL_OK(DP_acl_state_last_change_message_noinc(acls),
+            "reset clears the last change");
+    DP_acl_state_free(acls);
+}
+
+
+static void dry_run(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, layer_create_new(1, 0x101));
+
+    DP_Message *msgs[] = {
+        layer_acl_new(1, 0x101, DP_ACL_ALL_LOCKED_BIT, 0, NULL),
+        draw_dabs_classic_new(2, 0x101, DP_BLEND_MODE_NORMAL),
+        layer_create_new(2, 0x201),
+        session_owner_new(0, NULL),
+        layer_create_new(1, 0x102),
+    };
+    int count = DP_ARRAY_LENGTH(msgs);
+    DP_AclSnapshot before;
+    DP_acl_state_snapshot(acls, &before);
+
+    bool accepted[DP_ARRAY_LENGTH(msgs)];
+    DP_acl_state_dry_run(acls, count, msgs, accepted);
+    OK(accepted[0], "op can lock the layer");
+    NOK(accepted[1], "drawing on the layer locked in the batch is rejected");
+    OK(accepted[2], "guest can create own layer");
+    OK(accepted[3], "session owner can be cleared");
+    OK(accepted[4], "clearing operators doesn't affect own layer creation");
+
+    DP_AclSnapshot after;
+    DP_acl_state_snapshot(acls, &after);
+    OK(DP_acl_snapshot_equal(&before, &after), "dry run leaves state alone");
+    NOK(DP_acl_state_layer_locked_for(acls, 2, 0x101),
+        "layer isn't locked in the real state");
+    OK(DP_acl_state_is_op(acls, 1), "user 1 is still operator");
+    INT_EQ_OK(DP_acl_state_layer_count(acls), 1,
+              "no layers created in the real state");
+    DP_acl_snapshot_dispose(&after);
+    DP_acl_snapshot_dispose(&before);
+
+    for (int i = 0; i < count; ++i) {
+        DP_message_decref(msgs[i]);
+    }
+    DP_acl_state_free(acls);
+}
+
+static void handle_fast(TEST_PARAMS)
+{
+    DP_Message *msgs[] = {
+        session_owner_new(1, (uint8_t[]){1}),
+        layer_create_new(1, 0x101),
+        layer_acl_new(1, 0x101, DP_ACL_ALL_LOCKED_BIT, 0, NULL),
+        draw_dabs_classic_new(2, 0x101, DP_BLEND_MODE_NORMAL),
+        layer_create_new(2, 0x201),
+        layer_create_new(2, 0x301),
+        annotation_create_new(2, 0x201),
+        DP_msg_user_acl_new(1, set_uint8s, 1, (uint8_t[]){2}),
+        draw_dabs_classic_new(2, 0x201, DP_BLEND_MODE_NORMAL),
+        DP_msg_leave_new(2),
+    };
+    int count = DP_ARRAY_LENGTH(msgs);
+    DP_AclState *acls = DP_acl_state_new();
+    DP_AclState *fast = DP_acl_state_new();
+    int mismatches = 0;
+    for (int i = 0; i < count; ++i) {
+        uint8_t result = DP_acl_state_handle(acls, msgs[i], false);
+        bool accepted = DP_acl_state_handle_fast(fast, msgs[i], false);
+        if (accepted != !(result & DP_ACL_STATE_FILTERED_BIT)) {
+            ++mismatches;
+        }
+    }
+    INT_EQ_OK(mismatches, 0, "fast handling accepts the same messages");
+
+    DP_AclSnapshot expected, actual;
+    DP_acl_state_snapshot(acls, &expected);
+    DP_acl_state_snapshot(fast, &actual);
+    OK(DP_acl_snapshot_equal(&expected, &actual),
+       "fast handling results in the same state");
+    DP_acl_snapshot_dispose(&actual);
+    DP_acl_snapshot_dispose(&expected);
+    INT_EQ_OK(DP_acl_state_layer_count(fast), DP_acl_state_layer_count(acls),
+              "same number of layers tracked");
+
+    for (int i = 0; i < count; ++i) {
+        DP_message_decref(msgs[i]);
+    }
+    DP_acl_state_free(fast);
+    DP_acl_state_free(acls);
+}
+
+
+typedef struct DP_CanvasBounds {
+    uint32_t width, height;
+} DP_CanvasBounds;
+
+static void set_bytes(size_t size, unsigned char *out, void *user)
+{
+    memcpy(out, user, size);
+}
+
+static DP_Message *put_image_new(unsigned int context_id, uint32_t x,
+                                 uint32_t y, size_t image_size,
+                                 const unsigned char *image)
+{
+    return DP_msg_put_image_new(context_id, 0x101, DP_BLEND_MODE_NORMAL, x, y,
+                                1, 1, set_bytes, image_size, (void *)image);
+}
+
+static DP_Message *clamp_put_image(void *user, DP_Message *msg)
+{
+    DP_CanvasBounds *bounds = user;
+    DP_MsgPutImage *mpi = DP_message_cast(msg, DP_MSG_PUT_IMAGE);
+    if (mpi) {
+        uint32_t x = DP_msg_put_image_x(mpi);
+        uint32_t y = DP_msg_put_image_y(mpi);
+        if (x >= bounds->width || y >= bounds->height) {
+            size_t image_size;
+            const unsigned char *image =
+                DP_msg_put_image_image(mpi, &image_size);
+            return put_image_new(
+                DP_message_context_id(msg),
+                x < bounds->width ? x : bounds->width - 1,
+                y < bounds->height ? y : bounds->height - 1, image_size, image);
+        }
+    }
+    return DP_message_incref(msg);
+}
+
+static DP_Message *drop_laser_trail(DP_UNUSED void *user, DP_Message *msg)
+{
+    return DP_message_type(msg) == DP_MSG_LASER_TRAIL
+             ? NULL
+             : DP_message_incref(msg);
+}
+
+static uint8_t handle_preprocessed(DP_AclState *acls, DP_Message *msg,
+                                   DP_AclStatePreprocessFn preprocess,
+                                   void *user, DP_Message **out_msg)
+{
+    uint8_t result = DP_acl_state_handle_preprocessed(
+        acls, msg, false, preprocess, user, out_msg);
+    DP_message_decref(msg);
+    return result;
+}
+
+static void preprocess_clamp(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, layer_create_new(1, 0x101));
+    DP_CanvasBounds bounds = {100, 80};
+    const unsigned char image[] = {1, 2, 3, 4};
+    DP_Message *out;
+
+    uint8_t result = handle_preprocessed(
+        acls, put_image_new(2, 10, 20, sizeof(image), image), clamp_put_image,
+        &bounds, &out);
+    NOK(result & DP_ACL_STATE_FILTERED_BIT, "in-bounds put image accepted");
+    if (NOT_NULL_OK(out, "got in-bounds put image")) {
+        DP_MsgPutImage *mpi = DP_message_cast(out, DP_MSG_PUT_IMAGE);
+        UINT_EQ_OK(DP_msg_put_image_x(mpi), 10, "x is unchanged");
+        UINT_EQ_OK(DP_msg_put_image_y(mpi), 20, "y is unchanged");
+        DP_message_decref(out);
+    }
+
+    result = handle_preprocessed(
+        acls, put_image_new(2, 500, 20, sizeof(image), image), clamp_put_image,
+        &bounds, &out);
+    NOK(result & DP_ACL_STATE_FILTERED_BIT, "clamped put image accepted");
+    if (NOT_NULL_OK(out, "got clamped put image")) {
+        DP_MsgPutImage *mpi = DP_message_cast(out, DP_MSG_PUT_IMAGE);
+        UINT_EQ_OK(DP_message_context_id(out), 2, "context id is kept");
+        UINT_EQ_OK(DP_msg_put_image_x(mpi), 99, "x is clamped");
+        UINT_EQ_OK(DP_msg_put_image_y(mpi), 20, "y is unchanged");
+        size_t image_size;
+        const unsigned char *out_image =
+            DP_msg_put_image_image(mpi, &image_size);
+        OK(image_size == sizeof(image)
+               && memcmp(out_image, image, sizeof(image)) == 0,
+           "image data is kept");
+        DP_message_decref(out);
+    }
+
+    // The ACL checks still apply to the preprocessed message.
+    uint8_t tiers[DP_FEATURE_COUNT];
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        tiers[i] = DP_ACCESS_TIER_GUEST;
+    }
+    tiers[DP_FEATURE_PUT_IMAGE] = DP_ACCESS_TIER_OPERATOR;
+    (void)handle(acls, feature_access_levels_new(1, tiers));
+    result = handle_preprocessed(
+        acls, put_image_new(2, 10, 999, sizeof(image), image), clamp_put_image,
+        &bounds, &out);
+    OK(result & DP_ACL_STATE_FILTERED_BIT, "clamped put image still filtered");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_FEATURE_TIER,
+              "filtered by feature tier");
+    if (NOT_NULL_OK(out, "got filtered put image")) {
+        UINT_EQ_OK(DP_msg_put_image_y(DP_message_cast(out, DP_MSG_PUT_IMAGE)),
+                   79, "y is clamped");
+        DP_message_decref(out);
+    }
+
+    DP_acl_state_free(acls);
+}
+
+static void preprocess_drop(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    DP_Message *out;
+
+    uint8_t result =
+        handle_preprocessed(acls, DP_msg_laser_trail_new(1, 0xffff0000u, 10),
+                            drop_laser_trail, NULL, &out);
+    OK(result & DP_ACL_STATE_FILTERED_BIT, "laser trail is dropped");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls),
+              DP_ACL_REJECTION_PREPROCESSOR_DROPPED,
+              "dropped by the preprocessor");
+    NULL_OK(out, "no message to pass on");
+
+    // Even operators can't get around the preprocessor.
+    DP_Message *msg = DP_msg_laser_trail_new(1, 0xffff0000u, 10);
+    result = DP_acl_state_handle_preprocessed(acls, msg, true,
+                                              drop_laser_trail, NULL, &out);
+    OK(result & DP_ACL_STATE_FILTERED_BIT, "overridden laser trail dropped");
+    NULL_OK(out, "no overridden message to pass on");
+    DP_message_decref(msg);
+
+    msg = layer_create_new(1, 0x101);
+    result = DP_acl_state_handle_preprocessed(acls, msg, false,
+                                              drop_laser_trail, NULL, &out);
+    NOK(result & DP_ACL_STATE_FILTERED_BIT, "other messages pass through");
+    OK(out == msg, "message is passed on as it is");
+    INT_EQ_OK(DP_acl_state_layer_count(acls), 1, "layer was created");
+    DP_message_decref(out);
+    DP_message_decref(msg);
+
+    DP_acl_state_free(acls);
+}
+
+
+static void feature_tiers_delta(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    DP_FeatureTiers prev = DP_acl_state_feature_tiers(acls);
+    DP_FeatureTierChange changes[DP_FEATURE_COUNT];
+    INT_EQ_OK(DP_feature_tiers_delta(&prev, &prev, changes), 0,
+              "no delta between equal tiers");
+
+    DP_FeatureTiers next = prev;
+    next.tiers[DP_FEATURE_RESIZE] = DP_ACCESS_TIER_TRUSTED;
+    next.tiers[DP_FEATURE_LASER] = DP_ACCESS_TIER_AUTHENTICATED;
+    if (INT_EQ_OK(DP_feature_tiers_delta(&next, &prev, changes), 2,
+                  "delta has exactly the two changed features")) {
+        INT_EQ_OK(changes[0].feature, DP_FEATURE_RESIZE, "first feature");
+        INT_EQ_OK(changes[0].tier, DP_ACCESS_TIER_TRUSTED, "first tier");
+        INT_EQ_OK(changes[1].feature, DP_FEATURE_LASER, "second feature");
+        INT_EQ_OK(changes[1].tier, DP_ACCESS_TIER_AUTHENTICATED,
+                  "second tier");
+    }
+    INT_EQ_OK(DP_feature_tiers_delta(&prev, &next, changes), 2,
+              "reverse delta has the same two features");
+    INT_EQ_OK(changes[0].tier, prev.tiers[DP_FEATURE_RESIZE],
+              "reverse delta reverts the tier");
+    DP_acl_state_free(acls);
+}
+
+static void feature_tiers_non_default(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    DP_FeatureTiers feature = DP_acl_state_feature_tiers(acls);
+    DP_FeatureTierChange changes[DP_FEATURE_COUNT];
+    INT_EQ_OK(DP_feature_tiers_non_default(&feature, changes), 0,
+              "new state has default tiers");
+
+    uint8_t tiers[DP_FEATURE_COUNT];
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        tiers[i] = (uint8_t)feature.tiers[i];
+    }
+    tiers[DP_FEATURE_RESIZE] = DP_ACCESS_TIER_GUEST;
+    tiers[DP_FEATURE_BACKGROUND] = DP_ACCESS_TIER_TRUSTED;
+    (void)handle(acls, feature_access_levels_new(0, tiers));
+
+    feature = DP_acl_state_feature_tiers(acls);
+    if (INT_EQ_OK(DP_feature_tiers_non_default(&feature, changes), 2,
+                  "two features differ from the default")) {
+        INT_EQ_OK(changes[0].feature, DP_FEATURE_RESIZE, "resize differs");
+        INT_EQ_OK(changes[0].tier, DP_ACCESS_TIER_GUEST, "resize is guest");
+        INT_EQ_OK(changes[1].feature, DP_FEATURE_BACKGROUND,
+                  "background differs");
+        INT_EQ_OK(changes[1].tier, DP_ACCESS_TIER_TRUSTED,
+                  "background is trusted");
+    }
+
+    DP_FeatureTiers default_tiers = DP_feature_tiers_default();
+    INT_EQ_OK(default_tiers.tiers[DP_FEATURE_RESIZE], DP_ACCESS_TIER_OPERATOR,
+              "resize is operator-only by default");
+    DP_acl_state_free(acls);
+}
+
+static void apply_feature_tiers(DP_AclState *acls, DP_FeatureTiers feature)
+{
+    uint8_t tiers[DP_FEATURE_COUNT];
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        tiers[i] = (uint8_t)feature.tiers[i];
+    }
+    (void)handle(acls, feature_access_levels_new(1, tiers));
+}
+
+static void feature_tiers_presets(TEST_PARAMS)
+{
+    DP_FeatureTiers open = DP_feature_tiers_open();
+    DP_FeatureTiers moderated = DP_feature_tiers_moderated();
+    DP_FeatureTiers locked_down = DP_feature_tiers_locked_down();
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        const char *name = DP_feature_enum_name(i);
+        INT_EQ_OK(open.tiers[i], DP_ACCESS_TIER_GUEST, "open %s is guest",
+                  name);
+        INT_EQ_OK(locked_down.tiers[i], DP_ACCESS_TIER_OPERATOR,
+                  "locked down %s is operator", name);
+    }
+
+    int trusted_features[] = {
+        DP_FEATURE_PUT_IMAGE,  DP_FEATURE_RESIZE,     DP_FEATURE_BACKGROUND,
+        DP_FEATURE_EDIT_LAYERS, DP_FEATURE_OWN_LAYERS, DP_FEATURE_METADATA,
+        DP_FEATURE_TIMELINE,
+    };
+    bool trusted[DP_FEATURE_COUNT] = {false};
+    for (int i = 0; i < (int)DP_ARRAY_LENGTH(trusted_features); ++i) {
+        trusted[trusted_features[i]] = true;
+    }
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        INT_EQ_OK(moderated.tiers[i],
+                  trusted[i] ? DP_ACCESS_TIER_TRUSTED : DP_ACCESS_TIER_GUEST,
+                  "moderated %s is %s", DP_feature_enum_name(i),
+                  trusted[i] ? "trusted" : "guest");
+    }
+
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, layer_create_new(2, 0x201));
+
+    apply_feature_tiers(acls, moderated);
+    UINT_EQ_OK(handle(acls, draw_dabs_classic_new(2, 0x201,
+                                                  DP_BLEND_MODE_NORMAL)),
+               0, "guest can draw when moderated");
+    UINT_EQ_OK(handle(acls, layer_create_new(2, 0x202)),
+               DP_ACL_STATE_FILTERED_BIT,
+               "guest can't create layers when moderated");
+
+    apply_feature_tiers(acls, locked_down);
+    UINT_EQ_OK(handle(acls, draw_dabs_classic_new(2, 0x201,
+                                                  DP_BLEND_MODE_NORMAL)),
+               DP_ACL_STATE_FILTERED_BIT,
+               "guest can't draw when locked down");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_FEATURE_TIER,
+              "guest drawing is rejected by feature tier");
+    UINT_EQ_OK(handle(acls, draw_dabs_classic_new(1, 0x201,
+                                                  DP_BLEND_MODE_NORMAL)),
+               0, "operator can still draw when locked down");
+    DP_acl_state_free(acls);
+}
+
+static void feature_tiers_restore(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    DP_FeatureTiers original = DP_acl_state_feature_tiers(acls);
+
+    UINT_EQ_OK(DP_acl_state_feature_tiers_restore(acls, &original), 0,
+               "restoring unchanged tiers changes nothing");
+
+    apply_feature_tiers(acls, DP_feature_tiers_locked_down());
+    DP_FeatureTiers changed = DP_acl_state_feature_tiers(acls);
+    NOK(DP_feature_tiers_equal(&original, &changed), "tiers were changed");
+
+    UINT_EQ_OK(DP_acl_state_feature_tiers_restore(acls, &original),
+               DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT,
+               "restoring changed tiers reports a change");
+    DP_FeatureTiers restored = DP_acl_state_feature_tiers(acls);
+    OK(DP_feature_tiers_equal(&original, &restored),
+       "original tiers are back");
+    UINT_EQ_OK(DP_acl_state_feature_tiers_restore(acls, &original), 0,
+               "restoring again changes nothing");
+
+    (void)DP_acl_state_feature_freeze(acls, DP_FEATURE_TIMELINE,
+                                      DP_ACCESS_TIER_OPERATOR);
+    UINT_EQ_OK(DP_acl_state_feature_tiers_restore(acls, &changed),
+               DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT,
+               "restoring with a frozen feature");
+    restored = DP_acl_state_feature_tiers(acls);
+    INT_EQ_OK(restored.tiers[DP_FEATURE_PUT_IMAGE], DP_ACCESS_TIER_OPERATOR,
+              "unfrozen feature restored");
+    changed.tiers[DP_FEATURE_TIMELINE] = DP_ACCESS_TIER_GUEST;
+    (void)DP_acl_state_feature_tiers_restore(acls, &changed);
+    restored = DP_acl_state_feature_tiers(acls);
+    INT_EQ_OK(restored.tiers[DP_FEATURE_TIMELINE], DP_ACCESS_TIER_OPERATOR,
+              "frozen feature keeps its tier");
+    DP_acl_state_free(acls);
+}
+
+static void check_blocked_for(TEST_PARAMS, DP_AclState *acls,
+                              DP_AccessTier tier, int expected_count,
+                              const DP_Feature *expected)
+{
+    const char *name = DP_access_tier_name(tier);
+    DP_Feature blocked[DP_FEATURE_COUNT];
+    int count = DP_acl_state_blocked_features_for_tier(acls, tier, blocked);
+    if (INT_EQ_OK(count, expected_count, "%s has %d blocked features", name,
+                  expected_count)) {
+        for (int i = 0; i < count; ++i) {
+            INT_EQ_OK(blocked[i], expected[i], "%s blocked feature %d is %s",
+                      name, i, DP_feature_enum_name(expected[i]));
+        }
+    }
+    INT_EQ_OK(DP_acl_state_blocked_features_for_tier(acls, tier, NULL), count,
+              "%s count without output matches", name);
+}
+
+static void feature_tiers_blocked_for(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    DP_FeatureTiers tiers = DP_feature_tiers_open();
+    tiers.tiers[DP_FEATURE_PUT_IMAGE] = DP_ACCESS_TIER_OPERATOR;
+    tiers.tiers[DP_FEATURE_RESIZE] = DP_ACCESS_TIER_TRUSTED;
+    tiers.tiers[DP_FEATURE_LASER] = DP_ACCESS_TIER_TRUSTED;
+    tiers.tiers[DP_FEATURE_TIMELINE] = DP_ACCESS_TIER_AUTHENTICATED;
+    apply_feature_tiers(acls, tiers);
+
+    check_blocked_for(TEST_ARGS, acls, DP_ACCESS_TIER_OPERATOR, 0, NULL);
+    check_blocked_for(TEST_ARGS, acls, DP_ACCESS_TIER_TRUSTED, 1,
+                      (DP_Feature[]){DP_FEATURE_PUT_IMAGE});
+    check_blocked_for(TEST_ARGS, acls, DP_ACCESS_TIER_AUTHENTICATED, 3,
+                      (DP_Feature[]){DP_FEATURE_PUT_IMAGE, DP_FEATURE_RESIZE,
+                                     DP_FEATURE_LASER});
+    check_blocked_for(TEST_ARGS, acls, DP_ACCESS_TIER_GUEST, 4,
+                      (DP_Feature[]){DP_FEATURE_PUT_IMAGE, DP_FEATURE_RESIZE,
+                                     DP_FEATURE_LASER, DP_FEATURE_TIMELINE});
+
+    DP_FeatureTiers open = DP_feature_tiers_open();
+    INT_EQ_OK(DP_feature_tiers_blocked_for(&open, DP_ACCESS_TIER_GUEST, NULL),
+              0, "guests aren't blocked from anything when open");
+    DP_FeatureTiers locked_down = DP_feature_tiers_locked_down();
+    INT_EQ_OK(DP_feature_tiers_blocked_for(&locked_down,
+                                           DP_ACCESS_TIER_OPERATOR, NULL),
+              0, "operators aren't blocked from anything when locked down");
+    DP_acl_state_free(acls);
+}
+
+static void feature_tiers_validate(TEST_PARAMS)
+{
+    uint8_t tiers[DP_FEATURE_COUNT + 1];
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        tiers[i] = (uint8_t)(i % DP_ACCESS_TIER_COUNT);
+    }
+    int index = 99;
+    OK(DP_feature_tiers_validate(tiers, DP_FEATURE_COUNT, &index),
+       "all tiers in range are valid");
+    INT_EQ_OK(index, 99, "index isn't touched when valid");
+    OK(DP_feature_tiers_validate(tiers, DP_FEATURE_COUNT, NULL),
+       "valid without an index output");
+
+    tiers[DP_FEATURE_UNDO] = 255;
+    OK(DP_feature_tiers_validate(tiers, DP_FEATURE_COUNT, &index),
+       "tier left alone is valid");
+
+    tiers[DP_FEATURE_LASER] = DP_ACCESS_TIER_COUNT;
+    tiers[DP_FEATURE_TIMELINE] = 200;
+    NOK(DP_feature_tiers_validate(tiers, DP_FEATURE_COUNT, &index),
+        "out of range tier is invalid");
+    INT_EQ_OK(index, DP_FEATURE_LASER, "first invalid tier's index reported");
+    tiers[DP_FEATURE_LASER] = DP_ACCESS_TIER_GUEST;
+    NOK(DP_feature_tiers_validate(tiers, DP_FEATURE_COUNT, &index),
+        "other out of range tier is invalid");
+    INT_EQ_OK(index, DP_FEATURE_TIMELINE, "other invalid tier's index");
+    tiers[DP_FEATURE_TIMELINE] = DP_ACCESS_TIER_OPERATOR;
+
+    index = 99;
+    NOK(DP_feature_tiers_validate(tiers, DP_FEATURE_COUNT - 1, &index),
+        "too few tiers are invalid");
+    INT_EQ_OK(index, -1, "wrong count reports no index");
+    tiers[DP_FEATURE_COUNT] = DP_ACCESS_TIER_GUEST;
+    NOK(DP_feature_tiers_validate(tiers, DP_FEATURE_COUNT + 1, NULL),
+        "too many tiers are invalid");
+    NOK(DP_feature_tiers_validate(NULL, 0, &index), "no tiers are invalid");
+    OK(DP_feature_tiers_validate(tiers, DP_FEATURE_COUNT, NULL),
+       "fixed up tiers are valid again");
+}
+
+
+static void access_tier_privilege(TEST_PARAMS)
+{
+    INT_EQ_OK(DP_access_tier_most_privileged(DP_ACCESS_TIER_GUEST,
+                                             DP_ACCESS_TIER_OPERATOR),
+              DP_ACCESS_TIER_OPERATOR,
+              "operator is more privileged than guest");
+    INT_EQ_OK(DP_access_tier_least_privileged(DP_ACCESS_TIER_GUEST,
+                                              DP_ACCESS_TIER_OPERATOR),
+              DP_ACCESS_TIER_GUEST, "guest is less privileged than operator");
+
+    for (int i = 0; i < DP_ACCESS_TIER_COUNT; ++i) {
+        for (int j = 0; j < DP_ACCESS_TIER_COUNT; ++j) {
+            DP_AccessTier a = (DP_AccessTier)i;
+            DP_AccessTier b = (DP_AccessTier)j;
+            DP_AccessTier most = DP_access_tier_most_privileged(a, b);
+            DP_AccessTier least = DP_access_tier_least_privileged(a, b);
+            INT_EQ_OK(most, i < j ? i : j, "most privileged of %s and %s",
+                      DP_access_tier_name(i), DP_access_tier_name(j));
+            INT_EQ_OK(least, i < j ? j : i, "least privileged of %s and %s",
+                      DP_access_tier_name(i), DP_access_tier_name(j));
+            OK(most == DP_access_tier_most_privileged(b, a)
+                   && least == DP_access_tier_least_privileged(b, a),
+               "privilege of %s and %s doesn't depend on order",
+               DP_access_tier_name(i), DP_access_tier_name(j));
+        }
+    }
+}
+
+static void authenticated_tier(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, join_new(2, DP_MSG_JOIN_FLAGS_AUTH));
+    (void)handle(acls, join_new(3, 0));
+    INT_EQ_OK(DP_acl_state_user_tier(acls, 2), DP_ACCESS_TIER_AUTHENTICATED,
+              "authenticated user is on authenticated tier");
+    INT_EQ_OK(DP_acl_state_user_tier(acls, 3), DP_ACCESS_TIER_GUEST,
+              "unauthenticated user is on guest tier");
+
+    uint8_t tiers[DP_FEATURE_COUNT];
+    DP_FeatureTiers feature = DP_acl_state_feature_tiers(acls);
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        tiers[i] = (uint8_t)feature.tiers[i];
+    }
+    tiers[DP_FEATURE_OWN_LAYERS] = DP_ACCESS_TIER_AUTHENTICATED;
+    tiers[DP_FEATURE_LASER] = DP_ACCESS_TIER_AUTHENTICATED;
+    (void)handle(acls, feature_access_levels_new(1, tiers));
+
+    UINT_EQ_OK(handle(acls, layer_create_new(2, 0x201)), 0,
+               "authenticated user can create a layer");
+    UINT_EQ_OK(handle(acls, layer_create_new(3, 0x301)),
+               DP_ACL_STATE_FILTERED_BIT,
+               "unauthenticated user can't create a layer");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_FEATURE_TIER,
+              "unauthenticated user is rejected because of feature tier");
+    OK(DP_acl_state_can_use_feature(acls, DP_FEATURE_LASER, 2),
+       "authenticated user can use the laser");
+    NOK(DP_acl_state_can_use_feature(acls, DP_FEATURE_LASER, 3),
+        "unauthenticated user can't use the laser");
+
+    (void)handle(acls, DP_msg_leave_new(2));
+    NOK(DP_acl_state_can_use_feature(acls, DP_FEATURE_LASER, 2),
+        "authentication is gone after leaving");
+    DP_acl_state_free(acls);
+}
+
+static void authenticated_are_trusted(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, join_new(2, DP_MSG_JOIN_FLAGS_AUTH));
+    (void)handle(acls, join_new(3, 0));
+    NOK(DP_acl_state_authenticated_are_trusted(acls), "policy off by default");
+    INT_EQ_OK(DP_acl_state_user_tier(acls, 2), DP_ACCESS_TIER_AUTHENTICATED,
+              "authenticated user is on authenticated tier with policy off");
+
+    DP_acl_state_authenticated_are_trusted_set(acls, true);
+    INT_EQ_OK(DP_acl_state_user_tier(acls, 2), DP_ACCESS_TIER_TRUSTED,
+              "authenticated user is on trusted tier with policy on");
+    INT_EQ_OK(DP_acl_state_user_tier(acls, 3), DP_ACCESS_TIER_GUEST,
+              "unauthenticated user stays a guest with policy on");
+    INT_EQ_OK(DP_acl_state_user_tier(acls, 1), DP_ACCESS_TIER_OPERATOR,
+              "operator stays an operator with policy on");
+    DP_UserAcls users = DP_acl_state_users(acls);
+    NOK(DP_user_acls_is_trusted(&users, 2),
+        "policy doesn't set the trusted bit");
+
+    DP_acl_state_reset(acls, 1);
+    OK(DP_acl_state_authenticated_are_trusted(acls), "policy survives reset");
+
+    DP_acl_state_authenticated_are_trusted_set(acls, false);
+    (void)handle(acls, join_new(2, DP_MSG_JOIN_FLAGS_AUTH));
+    INT_EQ_OK(DP_acl_state_user_tier(acls, 2), DP_ACCESS_TIER_AUTHENTICATED,
+              "authenticated user is back on authenticated tier");
+    DP_acl_state_free(acls);
+}
+
+static void elevated_users(TEST_PARAMS)
+{
+    DP_UserAcls users = {0};
+    DP_user_bits_set(users.operators, 2, (uint8_t[]){1, 9});
+    DP_user_bits_set(users.trusted, 4, (uint8_t[]){1, 2, 9, 200});
+    DP_user_bits_set(users.authenticated, 5, (uint8_t[]){0, 2, 3, 9, 255});
+    DP_user_bits_set(users.locked, 2, (uint8_t[]){4, 200});
+
+    DP_UserTier elevated[255];
+    int count = DP_user_acls_elevated(&users, elevated);
+    DP_UserTier expected[] = {
+        {1, DP_ACCESS_TIER_OPERATOR},
+        {2, DP_ACCESS_TIER_TRUSTED},
+        {3, DP_ACCESS_TIER_AUTHENTICATED},
+        {9, DP_ACCESS_TIER_OPERATOR},
+        {200, DP_ACCESS_TIER_TRUSTED},
+        {255, DP_ACCESS_TIER_AUTHENTICATED},
+    };
+    int expected_count = DP_ARRAY_LENGTH(expected);
+    if (INT_EQ_OK(count, expected_count, "got elevated users")) {
+        for (int i = 0; i < count; ++i) {
+            UINT_EQ_OK(elevated[i].user_id, expected[i].user_id,
+                       "elevated user %d has expected id", i);
+            INT_EQ_OK(elevated[i].tier, expected[i].tier,
+                      "user %d reports its highest tier", elevated[i].user_id);
+            INT_EQ_OK(elevated[i].tier,
+                      DP_user_acls_tier(&users, elevated[i].user_id),
+                      "user %d tier matches individual lookup",
+                      elevated[i].user_id);
+        }
+    }
+
+    DP_UserAcls nobody = {0};
+    INT_EQ_OK(DP_user_acls_elevated(&nobody, elevated), 0,
+              "no elevated users without any bits set");
+}
+
+static void check_user_bits_iterator(TEST_PARAMS, const uint8_t *users,
+                                     int expected_count,
+                                     const uint8_t *expected_ids,
+                                     const char *title)
+{
+    DP_UserBitsIterator ubi = DP_user_bits_iterator_make(users);
+    int count = 0;
+    while (DP_user_bits_iterator_next(&ubi)) {
+        if (count < expected_count) {
+            UINT_EQ_OK(ubi.user_id, expected_ids[count], "%s user %d", title,
+                       count);
+        }
+        ++count;
+    }
+    INT_EQ_OK(count, expected_count, "%s iterates all users", title);
+    NOK(DP_user_bits_iterator_next(&ubi), "%s iterator stays done", title);
+    INT_EQ_OK(DP_user_bits_count(users), expected_count, "%s count", title);
+
+    uint8_t ids[256];
+    INT_EQ_OK(DP_user_bits_ids(users, ids), expected_count, "%s ids count",
+              title);
+    OK(memcmp(ids, expected_ids, DP_int_to_size(expected_count)) == 0,
+       "%s ids match", title);
+}
+
+static void user_bits_iterator(TEST_PARAMS)
+{
+    DP_UserBits users = {0};
+    check_user_bits_iterator(TEST_ARGS, users, 0, (uint8_t[]){0}, "empty");
+
+    uint8_t sparse[] = {0, 7, 8, 100, 255};
+    DP_user_bits_set(users, (int)DP_ARRAY_LENGTH(sparse), sparse);
+    check_user_bits_iterator(TEST_ARGS, users, (int)DP_ARRAY_LENGTH(sparse),
+                             sparse, "sparse");
+
+    uint8_t dense[256];
+    for (int i = 0; i < 256; ++i) {
+        dense[i] = DP_int_to_uint8(i);
+    }
+    memset(users, 0xff, sizeof(users));
+    check_user_bits_iterator(TEST_ARGS, users, 256, dense, "dense");
+}
+
+
+static DP_Message *fill_rect_new(unsigned int context_id, int layer_id)
+{
+    return DP_msg_fill_rect_new(context_id, DP_int_to_uint16(layer_id),
+                                DP_BLEND_MODE_NORMAL, 0, 0, 10, 10,
+                                0xff000000u);
+}
+
+static void layer_contributors(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, layer_create_new(1, 0x101));
+    (void)handle(acls, draw_dabs_classic_new(1, 0x101, DP_BLEND_MODE_NORMAL));
+
+    uint8_t user_ids[256];
+    NOK(DP_acl_state_contributors_tracked(acls), "not tracked by default");
+    INT_EQ_OK(DP_acl_state_layer_contributors(acls, 0x101, user_ids), 0,
+              "nothing recorded while not tracking");
+
+    DP_acl_state_contributors_tracked_set(acls, true);
+    (void)handle(acls, draw_dabs_classic_new(2, 0x101, DP_BLEND_MODE_NORMAL));
+    (void)handle(acls, fill_rect_new(1, 0x101));
+    (void)handle(acls, draw_dabs_classic_new(2, 0x101, DP_BLEND_MODE_NORMAL));
+    if (INT_EQ_OK(DP_acl_state_layer_contributors(acls, 0x101, user_ids), 2,
+                  "two users drew on the layer")) {
+        UINT_EQ_OK(user_ids[0], 1, "first contributor");
+        UINT_EQ_OK(user_ids[1], 2, "second contributor");
+    }
+    INT_EQ_OK(DP_acl_state_layer_contributors(acls, 0x102, user_ids), 0,
+              "nobody drew on another layer");
+
+    (void)handle(acls, layer_acl_new(1, 0x101, DP_ACL_ALL_LOCKED_BIT, 0, NULL));
+    UINT_EQ_OK(handle(acls, fill_rect_new(3, 0x101)),
+               DP_ACL_STATE_FILTERED_BIT, "drawing on locked layer filtered");
+    INT_EQ_OK(DP_acl_state_layer_contributors(acls, 0x101, user_ids), 2,
+              "rejected drawing isn't recorded");
+
+    (void)handle(acls, DP_msg_layer_delete_new(1, 0x101, 0));
+    INT_EQ_OK(DP_acl_state_layer_contributors(acls, 0x101, user_ids), 0,
+              "deleting the layer forgets its contributors");
+
+    (void)handle(acls, layer_create_new(1, 0x102));
+    (void)handle(acls, fill_rect_new(1, 0x102));
+    DP_acl_state_contributors_tracked_set(acls, false);
+    INT_EQ_OK(DP_acl_state_layer_contributors(acls, 0x102, user_ids), 0,
+              "disabling tracking drops contributors");
+    DP_acl_state_free(acls);
+}
+
+
+static void strict_mode(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    UINT_EQ_OK(handle(acls, fill_rect_new(1, 0x101)), 0,
+               "drawing on unknown layer allowed when not strict");
+    UINT_EQ_OK(handle(acls, annotation_edit_new(1, 0x101, "hi")), 0,
+               "editing unknown annotation allowed when not strict");
+
+    DP_acl_state_strict_set(acls, true);
+    OK(DP_acl_state_strict(acls), "strict mode enabled");
+    UINT_EQ_OK(handle(acls, draw_dabs_classic_new(1, 0x101,
+                                                  DP_BLEND_MODE_NORMAL)),
+               DP_ACL_STATE_FILTERED_BIT, "drawing before create is rejected");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls),
+              DP_ACL_REJECTION_NO_SUCH_LAYER,
+              "drawing rejected because layer doesn't exist");
+    UINT_EQ_OK(handle(acls, annotation_edit_new(1, 0x101, "hi")),
+               DP_ACL_STATE_FILTERED_BIT, "editing before create is rejected");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls),
+              DP_ACL_REJECTION_NO_SUCH_ANNOTATION,
+              "editing rejected because annotation doesn't exist");
+
+    (void)handle(acls, layer_create_new(1, 0x101));
+    (void)handle(acls, annotation_create_new(1, 0x101));
+    UINT_EQ_OK(handle(acls, draw_dabs_classic_new(1, 0x101,
+                                                  DP_BLEND_MODE_NORMAL)),
+               0, "drawing after create is allowed");
+    UINT_EQ_OK(handle(acls, annotation_edit_new(1, 0x101, "hi")), 0,
+               "editing after create is allowed");
+
+    (void)handle(acls, DP_msg_layer_delete_new(1, 0x101, 0));
+    UINT_EQ_OK(handle(acls, fill_rect_new(1, 0x101)),
+               DP_ACL_STATE_FILTERED_BIT, "drawing after delete is rejected");
+    DP_Message *msg = fill_rect_new(1, 0x101);
+    UINT_EQ_OK(DP_acl_state_handle(acls, msg, true), 0,
+               "override skips the check");
+    DP_message_decref(msg);
+
+    DP_acl_state_reset(acls, 1);
+    OK(DP_acl_state_strict(acls), "strict mode survives reset");
+    NOK(DP_acl_state_local_mode(acls), "strict mode prevents local mode");
+    DP_acl_state_free(acls);
+}
+
+
+static void permission_report(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, join_new(2, 0));
+    (void)handle(acls, layer_acl_new(1, 0x101, 0, 1, (uint8_t[]){1}));
+    (void)handle(acls, layer_acl_new(1, 0x102, DP_ACL_ALPHA_LOCKED_BIT, 0,
+                                     NULL));
+
+    DP_AclPermissionReport op, guest;
+    DP_acl_state_permission_report(acls, 1, &op);
+    DP_acl_state_permission_report(acls, 2, &guest);
+
+    INT_EQ_OK(op.tier, DP_ACCESS_TIER_OPERATOR, "op tier");
+    INT_EQ_OK(guest.tier, DP_ACCESS_TIER_GUEST, "guest tier");
+    OK(op.op, "op is operator");
+    NOK(guest.op, "guest isn't operator");
+    OK(op.features[DP_FEATURE_RESIZE], "op can resize");
+    NOK(guest.features[DP_FEATURE_RESIZE], "guest can't resize");
+    OK(guest.features[DP_FEATURE_PUT_IMAGE], "guest can put images");
+
+    if (INT_EQ_OK(guest.layer_count, 2, "two layers with ACLs for guest")
+        && INT_EQ_OK(op.layer_count, 2, "two layers with ACLs for op")) {
+        INT_EQ_OK(guest.layers[0].layer_id, 0x101, "first layer id");
+        INT_EQ_OK(guest.layers[1].layer_id, 0x102, "second layer id");
+        NOK(op.layers[0].locked, "exclusive layer unlocked for op");
+        OK(guest.layers[0].locked, "exclusive layer locked for guest");
+        NOK(op.layers[1].alpha_locked, "op ignores alpha lock");
+        OK(guest.layers[1].alpha_locked, "guest is alpha locked");
+    }
+
+    JSON_Value *value = DP_acl_permission_report_to_json(&guest);
+    JSON_Object *obj = json_value_get_object(value);
+    STR_EQ_OK(json_object_get_string(obj, "tier"), "guest", "json tier");
+    INT_EQ_OK(json_object_dotget_boolean(obj, "features.resize"), 0,
+              "json resize feature");
+    JSON_Array *layers = json_object_get_array(obj, "layers");
+    if (INT_EQ_OK(DP_size_to_int(json_array_get_count(layers)), 2,
+                  "json layer count")) {
+        INT_EQ_OK(json_object_get_boolean(json_array_get_object(layers, 0),
+                                          "locked"),
+                  1, "json first layer locked");
+    }
+    json_value_free(value);
+
+    DP_acl_permission_report_dispose(&guest);
+    DP_acl_permission_report_dispose(&op);
+    DP_acl_state_free(acls);
+}
+
+
+static void system_messages(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, layer_acl_new(1, 0, DP_ACL_ALL_LOCKED_BIT, 0, NULL));
+    OK(DP_acl_state_users(acls).all_locked, "session is locked");
+
+    DP_Message *chat = DP_msg_chat_new(0, 0, 0, "notice", 6);
+    OK(DP_message_is_system(chat), "server chat is a system message");
+    UINT_EQ_OK(handle(acls, chat), 0, "system chat passes in locked session");
+
+    DP_Message *lock = layer_acl_new(0, 0x101, DP_ACL_ALL_LOCKED_BIT, 0, NULL);
+    OK(DP_message_is_system(lock), "server layer acl is a system message");
+    UINT_EQ_OK(handle(acls, lock), DP_ACL_STATE_CHANGE_LAYERS_BIT,
+               "system layer acl passes in locked session");
+    OK(DP_acl_state_layer_locked_for(acls, 1, 0x101),
+       "system message still has its effect");
+
+    DP_Message *fill = DP_msg_fill_rect_new(0, 0x101, DP_BLEND_MODE_NORMAL, 0,
+                                            0, 1, 1, 0xff000000u);
+    NOK(DP_message_is_system(fill), "commands from the server aren't system");
+    DP_message_decref(fill);
+
+    DP_Message *user_chat = DP_msg_chat_new(2, 0, 0, "hi", 2);
+    NOK(DP_message_is_system(user_chat), "user chat isn't a system message");
+    DP_message_decref(user_chat);
+
+    DP_Message *filtered = DP_msg_filtered_new(0, NULL, 0, NULL);
+    NOK(DP_message_is_system(filtered), "filtered isn't a system message");
+    UINT_EQ_OK(handle(acls, filtered), DP_ACL_STATE_FILTERED_BIT,
+               "filtered message from server is still filtered");
+    DP_acl_state_free(acls);
+}
+
+
+static void layer_acl_versions(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(2, (uint8_t[]){1, 2}));
+    UINT_EQ_OK(DP_acl_state_layer_acl_version(acls, 0x101), 0,
+               "version starts at zero");
+
+    // Both operators look at the layer at version 0, the first one wins.
+    DP_Message *first = layer_acl_new(1, 0x101, DP_ACL_ALL_LOCKED_BIT, 0, NULL);
+    DP_Message *second = layer_acl_new(2, 0x101, DP_ACL_ALPHA_LOCKED_BIT, 0,
+                                       NULL);
+    UINT_EQ_OK(DP_acl_state_handle_versioned(acls, first, 0, false),
+               DP_ACL_STATE_CHANGE_LAYERS_BIT, "first edit is accepted");
+    UINT_EQ_OK(DP_acl_state_layer_acl_version(acls, 0x101), 1,
+               "version bumped after edit");
+    UINT_EQ_OK(DP_acl_state_handle_versioned(acls, second, 0, false),
+               DP_ACL_STATE_FILTERED_BIT, "stale edit is rejected");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls),
+              DP_ACL_REJECTION_STALE_LAYER_ACL, "rejected as stale");
+    NOK(DP_acl_state_layer_alpha_locked_for(acls, 3, 0x101),
+        "stale edit didn't apply");
+
+    UINT_EQ_OK(DP_acl_state_handle_versioned(acls, second, 1, false),
+               DP_ACL_STATE_CHANGE_LAYERS_BIT, "retried edit is accepted");
+    OK(DP_acl_state_layer_alpha_locked_for(acls, 3, 0x101),
+       "retried edit applied");
+    UINT_EQ_OK(DP_acl_state_layer_acl_version(acls, 0x101), 2,
+               "version bumped again");
+    UINT_EQ_OK(DP_acl_state_layer_acl_version(acls, 0x102), 0,
+               "other layers are unaffected");
+    DP_message_decref(second);
+    DP_message_decref(first);
+
+    UINT_EQ_OK(handle(acls, layer_acl_new(3, 0x101, 0, 0, NULL)),
+               DP_ACL_STATE_FILTERED_BIT, "non-operator edit is filtered");
+    UINT_EQ_OK(DP_acl_state_layer_acl_version(acls, 0x101), 2,
+               "filtered edit doesn't bump the version");
+
+    DP_acl_state_reset(acls, 0);
+    UINT_EQ_OK(DP_acl_state_layer_acl_version(acls, 0x101), 0,
+               "reset clears versions");
+    DP_acl_state_free(acls);
+}
+
+static uint8_t handle_sequenced(DP_AclState *acls, DP_Message *msg,
+                                long long sequence)
+{
+    uint8_t result = DP_acl_state_handle_sequenced(acls, msg, sequence, false);
+    DP_message_decref(msg);
+    return result;
+}
+
+static void sequenced_layer_acls(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, layer_create_new(1, 0x101));
+    UINT_EQ_OK(handle_sequenced(acls, fill_rect_new(2, 0x101), 5), 0,
+               "draw on unlocked layer passes");
+
+    UINT_EQ_OK(handle_sequenced(acls,
+                                layer_acl_new(1, 0x101,
+                                              DP_ACL_ALL_LOCKED_BIT
+                                                  | DP_ACCESS_TIER_GUEST,
+                                              0, NULL),
+                                10),
+               DP_ACL_STATE_CHANGE_LAYERS_BIT, "lock effective from 10");
+    UINT_EQ_OK(handle_sequenced(acls, fill_rect_new(2, 0x101), 9), 0,
+               "draw sequenced before the lock still passes");
+    UINT_EQ_OK(handle_sequenced(acls, fill_rect_new(2, 0x101), 10),
+               DP_ACL_STATE_FILTERED_BIT,
+               "draw sequenced at the lock is filtered");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_LAYER_LOCKED,
+              "rejected because the layer is locked");
+    UINT_EQ_OK(handle_sequenced(acls, fill_rect_new(2, 0x101), 11),
+               DP_ACL_STATE_FILTERED_BIT,
+               "draw sequenced after the lock is filtered");
+    UINT_EQ_OK(handle(acls, fill_rect_new(2, 0x101)), DP_ACL_STATE_FILTERED_BIT,
+               "unsequenced draw is filtered");
+    OK(DP_acl_state_layer_locked_for(acls, 2, 0x101),
+       "layer is locked outside of sequenced handling");
+
+    UINT_EQ_OK(handle_sequenced(acls,
+                                DP_msg_layer_acl_exclusive_new(1, 0x102, 3,
+                                                               true),
+                                20),
+               DP_ACL_STATE_CHANGE_LAYERS_BIT, "exclusive effective from 20");
+    UINT_EQ_OK(handle_sequenced(acls, fill_rect_new(2, 0x102), 19), 0,
+               "non-exclusive draw sequenced before the grant passes");
+    UINT_EQ_OK(handle_sequenced(acls, fill_rect_new(2, 0x102), 20),
+               DP_ACL_STATE_FILTERED_BIT,
+               "non-exclusive draw sequenced from the grant is filtered");
+
+    // Locks that don't come from a sequenced message apply right away.
+    DP_acl_state_lock_layer(acls, 0x101, DP_ACCESS_TIER_OPERATOR, 0, NULL);
+    UINT_EQ_OK(handle_sequenced(acls, fill_rect_new(2, 0x101), 9),
+               DP_ACL_STATE_FILTERED_BIT,
+               "draw sequenced early is filtered by unsequenced lock");
+    DP_acl_state_free(acls);
+}
+
+
+static void local_authority(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    INT_EQ_OK(DP_acl_state_local_authority(acls), -1, "no authority default");
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, layer_acl_new(1, 0x101, DP_ACL_ALL_LOCKED_BIT, 0, NULL));
+    (void)handle(acls, DP_msg_user_acl_new(1, set_uint8s, 1, (uint8_t[]){5}));
+
+    DP_acl_state_local_authority_set(acls, 5);
+    OK(DP_acl_state_is_op(acls, 5), "authority counts as operator");
+    INT_EQ_OK(DP_acl_state_user_tier(acls, 5), DP_ACCESS_TIER_OPERATOR,
+              "authority has operator tier");
+    NOK(DP_acl_state_layer_locked_for(acls, 5, 0x101),
+        "layer isn't locked for authority");
+    UINT_EQ_OK(handle(acls, fill_rect_new(5, 0x101)), 0,
+               "authority draws on locked layer despite being locked");
+    UINT_EQ_OK(handle(acls, fill_rect_new(1, 0x101)),
+               DP_ACL_STATE_FILTERED_BIT,
+               "operator can't draw on locked layer");
+    UINT_EQ_OK(handle(acls, fill_rect_new(3, 0x101)),
+               DP_ACL_STATE_FILTERED_BIT, "guest can't draw on locked layer");
+
+    (void)handle(acls, layer_acl_new(1, 0, DP_ACL_ALL_LOCKED_BIT, 0, NULL));
+    UINT_EQ_OK(handle(acls, fill_rect_new(5, 0x102)), 0,
+               "authority draws in locked session");
+    UINT_EQ_OK(handle(acls, fill_rect_new(3, 0x102)),
+               DP_ACL_STATE_FILTERED_BIT, "guest can't draw in locked session");
+
+    DP_acl_state_reset(acls, 0);
+    INT_EQ_OK(DP_acl_state_local_authority(acls), 5,
+              "authority survives reset");
+    DP_acl_state_local_authority_set(acls, -1);
+    NOK(DP_acl_state_is_op(acls, 5), "unset authority is no operator");
+    DP_acl_state_free(acls);
+}
+
+static void lock_layer(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    UINT_EQ_OK(handle(acls, fill_rect_new(3, 0x101)), 0,
+               "guest draws on unlocked layer");
+
+    UINT_EQ_OK(
+        DP_acl_state_lock_layer(acls, 0x101, DP_ACCESS_TIER_GUEST, 0, NULL),
+        DP_ACL_STATE_CHANGE_LAYERS_BIT, "locking layer changes layers");
+    UINT_EQ_OK(
+        DP_acl_state_lock_layer(acls, 0x101, DP_ACCESS_TIER_GUEST, 0, NULL), 0,
+        "locking layer again changes nothing");
+    OK(DP_acl_state_layer_locked_for(acls, 1, 0x101),
+       "layer locked for operator");
+    UINT_EQ_OK(handle(acls, fill_rect_new(3, 0x101)),
+               DP_ACL_STATE_FILTERED_BIT, "guest can't draw on locked layer");
+    UINT_EQ_OK(handle(acls, fill_rect_new(3, 0x102)), 0,
+               "guest draws on other layer");
+    UINT_EQ_OK(DP_acl_state_layer_acl_version(acls, 0x101), 1,
+               "locking bumps layer ACL version");
+
+    UINT_EQ_OK(
+        DP_acl_state_lock_layer(acls, 0x101, DP_ACCESS_TIER_OPERATOR, 0, NULL),
+        DP_ACL_STATE_CHANGE_LAYERS_BIT, "changing lock tier changes layers");
+    UINT_EQ_OK(handle(acls, fill_rect_new(1, 0x101)), 0,
+               "operator draws on operator-tier layer");
+    UINT_EQ_OK(handle(acls, fill_rect_new(3, 0x101)),
+               DP_ACL_STATE_FILTERED_BIT,
+               "guest can't draw on operator-tier layer");
+
+    DP_acl_state_lock_layer(acls, 0x101, DP_ACCESS_TIER_GUEST, 1,
+                            (uint8_t[]){3});
+    UINT_EQ_OK(handle(acls, fill_rect_new(3, 0x101)), 0,
+               "exclusive guest draws on layer");
+    UINT_EQ_OK(handle(acls, fill_rect_new(4, 0x101)),
+               DP_ACL_STATE_FILTERED_BIT, "other guest can't draw on layer");
+
+    UINT_EQ_OK(DP_acl_state_unlock_layer(acls, 0x101),
+               DP_ACL_STATE_CHANGE_LAYERS_BIT, "unlocking changes layers");
+    UINT_EQ_OK(DP_acl_state_unlock_layer(acls, 0x101), 0,
+               "unlocking again changes nothing");
+    UINT_EQ_OK(handle(acls, fill_rect_new(4, 0x101)), 0,
+               "guest draws on unlocked layer again");
+    DP_acl_state_free(acls);
+}
+
+static void open_all(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    DP_acl_state_lock_layer(acls, 0x101, DP_ACCESS_TIER_GUEST, 0, NULL);
+    DP_acl_state_lock_layer(acls, 0x102, DP_ACCESS_TIER_OPERATOR, 0, NULL);
+    (void)handle(acls, DP_msg_layer_acl_new(1, DP_ACL_DEFAULT_LAYER_ID,
+                                            DP_ACL_ALL_LOCKED_BIT, NULL, 0,
+                                            NULL));
+    (void)handle(acls, DP_msg_user_acl_new(1, set_uint8s, 1, (uint8_t[]){3}));
+    (void)handle(acls, DP_msg_layer_acl_new(1, 0, DP_ACL_ALL_LOCKED_BIT, NULL,
+                                            0, NULL));
+    uint8_t tiers[DP_FEATURE_COUNT];
+    for (int i = 0; i < DP_FEATURE_COUNT; ++i) {
+        tiers[i] = DP_ACCESS_TIER_OPERATOR;
+    }
+    (void)handle(acls, feature_access_levels_new(1, tiers));
+    (void)DP_acl_state_feature_freeze(acls, DP_FEATURE_TIMELINE,
+                                      DP_ACCESS_TIER_OPERATOR);
+    UINT_EQ_OK(handle(acls, fill_rect_new(4, 0x103)),
+               DP_ACL_STATE_FILTERED_BIT, "guest can't draw while locked");
+
+    UINT_EQ_OK(DP_acl_state_open_all(acls),
+               DP_ACL_STATE_CHANGE_USERS_BIT | DP_ACL_STATE_CHANGE_LAYERS_BIT
+                   | DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT,
+               "opening everything changes users, layers and features");
+    NOK(DP_acl_state_any_layer_locked(acls), "no layer is locked");
+    NULL_OK(DP_acl_state_default_layer_acl(acls), "no default layer ACL");
+    DP_UserAcls users = DP_acl_state_users(acls);
+    NOK(users.all_locked, "session is unlocked");
+    NOK(DP_user_acls_is_locked(&users, 3), "user is unlocked");
+    OK(DP_acl_state_can_use_feature(acls, DP_FEATURE_PUT_IMAGE, 3),
+       "guest can use features");
+    NOK(DP_acl_state_can_use_feature(acls, DP_FEATURE_TIMELINE, 3),
+        "frozen feature stays restricted");
+    UINT_EQ_OK(handle(acls, fill_rect_new(3, 0x101)), 0,
+               "formerly locked user draws on formerly locked layer");
+    UINT_EQ_OK(handle(acls, layer_create_new(4, 0x401)), 0,
+               "guest creates a layer");
+    NOK(DP_acl_state_layer_locked_for(acls, 4, 0x401),
+        "new layer isn't locked");
+
+    UINT_EQ_OK(DP_acl_state_open_all(acls), 0,
+               "opening everything again changes nothing");
+    DP_acl_state_free(acls);
+}
+
+typedef struct LayerAclSearch {
+    int layer_id;
+    const DP_LayerAcl *l;
+} LayerAclSearch;
+
+static void search_layer_acl(void *user, int layer_id, const DP_LayerAcl *l)
+{
+    LayerAclSearch *search = user;
+    if (search->layer_id == layer_id) {
+        search->l = l;
+    }
+}
+
+static bool layer_locked_slow(DP_AclState *acls, uint8_t user_id, int layer_id)
+{
+    LayerAclSearch search = {layer_id, NULL};
+    DP_acl_state_layers_each(acls, search_layer_acl, &search);
+    const DP_LayerAcl *l = search.l;
+    return l
+        && (l->locked || !DP_user_bit_get(l->exclusive, user_id)
+            || l->tier < DP_acl_state_user_tier(acls, user_id));
+}
+
+static void check_any_layer_locked(TEST_PARAMS, DP_AclState *acls,
+                                   bool expected, const char *title)
+{
+    OK(DP_acl_state_any_layer_locked(acls) == expected, "%s: any locked is %s",
+       title, expected ? "true" : "false");
+    int mismatches = 0;
+    for (int layer_id = 0x101; layer_id <= 0x104; ++layer_id) {
+        for (uint8_t user_id = 1; user_id <= 3; ++user_id) {
+            if (DP_acl_state_layer_locked_for(acls, user_id, layer_id)
+                != layer_locked_slow(acls, user_id, layer_id)) {
+                ++mismatches;
+            }
+        }
+    }
+    INT_EQ_OK(mismatches, 0, "%s: fast path matches slow path", title);
+}
+
+static void any_layer_locked(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    check_any_layer_locked(TEST_ARGS, acls, false, "initial");
+
+    (void)handle(acls, layer_acl_new(1, 0x101,
+                                     DP_ACL_ALPHA_LOCKED_BIT
+                                         | DP_ACCESS_TIER_GUEST,
+                                     0, NULL));
+    check_any_layer_locked(TEST_ARGS, acls, false, "alpha lock");
+
+    (void)handle(acls, layer_acl_new(1, 0x102,
+                                     DP_ACL_ALL_LOCKED_BIT
+                                         | DP_ACCESS_TIER_GUEST,
+                                     0, NULL));
+    check_any_layer_locked(TEST_ARGS, acls, true, "layer lock");
+
+    (void)handle(acls, DP_msg_layer_acl_exclusive_new(1, 0x103, 2, true));
+    check_any_layer_locked(TEST_ARGS, acls, true, "exclusive grant");
+
+    (void)handle(acls,
+                 layer_acl_new(1, 0x102, DP_ACCESS_TIER_GUEST, 0, NULL));
+    check_any_layer_locked(TEST_ARGS, acls, true, "one of two unlocked");
+
+    (void)handle(acls, DP_msg_layer_acl_exclusive_new(1, 0x103, 2, false));
+    check_any_layer_locked(TEST_ARGS, acls, false, "exclusive revoke");
+
+    (void)handle(acls, layer_acl_new(1, 0x104, DP_ACCESS_TIER_TRUSTED, 0,
+                                     NULL));
+    check_any_layer_locked(TEST_ARGS, acls, true, "tier lock");
+
+    (void)handle(acls, DP_msg_layer_delete_new(1, 0x104, false));
+    check_any_layer_locked(TEST_ARGS, acls, false, "locked layer deleted");
+
+    DP_acl_state_lock_layer(acls, 0x101, DP_ACCESS_TIER_GUEST, 0, NULL);
+    check_any_layer_locked(TEST_ARGS, acls, true, "lock layer");
+    DP_AclState *clone = DP_acl_state_new_clone(acls, 2);
+    check_any_layer_locked(TEST_ARGS, clone, true, "clone");
+    DP_acl_state_free(clone);
+
+    DP_acl_state_unlock_layer(acls, 0x101);
+    check_any_layer_locked(TEST_ARGS, acls, false, "unlock layer");
+
+    DP_acl_state_lock_layer(acls, 0x101, DP_ACCESS_TIER_GUEST, 0, NULL);
+    DP_acl_state_reset(acls, 0);
+    check_any_layer_locked(TEST_ARGS, acls, false, "reset");
+    DP_acl_state_free(acls);
+}
+
+
+static void lock_new_users(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    NOK(DP_acl_state_lock_new_users(acls), "new users aren't locked by default");
+    UINT_EQ_OK(handle(acls, join_new(3, 0)), 0,
+               "join without the policy doesn't change users");
+
+    DP_acl_state_lock_new_users_set(acls, true);
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    UINT_EQ_OK(handle(acls, join_new(1, 0)), 0,
+               "operator joining doesn't get locked");
+    (void)handle(acls, layer_create_new(1, 0x101));
+
+    UINT_EQ_OK(handle(acls, join_new(2, 0)), DP_ACL_STATE_CHANGE_USERS_BIT,
+               "join under the policy changes users");
+    DP_UserAcls users = DP_acl_state_users(acls);
+    OK(DP_user_acls_is_locked(&users, 2), "joined user is locked");
+    NOK(DP_user_acls_is_locked(&users, 3), "earlier user isn't locked");
+    UINT_EQ_OK(handle(acls, fill_rect_new(2, 0x101)),
+               DP_ACL_STATE_FILTERED_BIT, "locked user's command is dropped");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_USER_LOCKED,
+              "rejected because user is locked");
+
+    UINT_EQ_OK(handle(acls, DP_msg_user_acl_new(1, set_uint8s, 0, NULL)),
+               DP_ACL_STATE_CHANGE_USERS_BIT, "operator unlocks user");
+    UINT_EQ_OK(handle(acls, fill_rect_new(2, 0x101)), 0,
+               "unlocked user's command goes through");
+
+    DP_acl_state_reset(acls, 0);
+    OK(DP_acl_state_lock_new_users(acls), "policy survives reset");
+    DP_acl_state_free(acls);
+}
+
+static void locked_categories(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, layer_create_new(2, 0x201));
+    UINT_EQ_OK(DP_acl_state_locked_categories(acls), 0,
+               "no categories are locked by default");
+
+    UINT_EQ_OK(handle(acls, DP_msg_session_lock_categories_new(
+                                2, DP_ACL_LOCK_CATEGORY_DRAW)),
+               DP_ACL_STATE_FILTERED_BIT, "non-operator can't lock drawing");
+    UINT_EQ_OK(handle(acls, DP_msg_session_lock_categories_new(
+                                1, DP_ACL_LOCK_CATEGORY_DRAW)),
+               DP_ACL_STATE_CHANGE_USERS_BIT, "operator locks drawing");
+    UINT_EQ_OK(DP_acl_state_locked_categories(acls), DP_ACL_LOCK_CATEGORY_DRAW,
+               "only drawing is locked");
+    UINT_EQ_OK(handle(acls, DP_msg_session_lock_categories_new(
+                                1, DP_ACL_LOCK_CATEGORY_DRAW)),
+               0, "locking drawing again changes nothing");
+
+    UINT_EQ_OK(handle(acls, fill_rect_new(2, 0x201)),
+               DP_ACL_STATE_FILTERED_BIT, "drawing is dropped");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls),
+              DP_ACL_REJECTION_CATEGORY_LOCKED,
+              "rejected because the category is locked");
+    UINT_EQ_OK(handle(acls, draw_dabs_classic_new(1, 0x201,
+                                                  DP_BLEND_MODE_NORMAL)),
+               DP_ACL_STATE_FILTERED_BIT, "operator's drawing is dropped too");
+    UINT_EQ_OK(handle(acls, layer_create_new(2, 0x202)), 0,
+               "layer creation still goes through");
+    UINT_EQ_OK(handle(acls, annotation_create_new(2, 0x201)), 0,
+               "annotation creation still goes through");
+    DP_Message *msg = fill_rect_new(2, 0x201);
+    UINT_EQ_OK(DP_acl_state_handle(acls, msg, true), 0,
+               "override bypasses the category lock");
+    DP_message_decref(msg);
+
+    UINT_EQ_OK(DP_acl_state_lock_categories(
+                   acls, DP_ACL_LOCK_CATEGORY_LAYERS
+                             | DP_ACL_LOCK_CATEGORY_ANNOTATIONS),
+               DP_ACL_STATE_CHANGE_USERS_BIT, "lock other categories directly");
+    UINT_EQ_OK(handle(acls, fill_rect_new(2, 0x201)), 0,
+               "drawing goes through again");
+    UINT_EQ_OK(handle(acls, layer_create_new(2, 0x203)),
+               DP_ACL_STATE_FILTERED_BIT, "layer creation is dropped");
+    UINT_EQ_OK(handle(acls, annotation_create_new(2, 0x202)),
+               DP_ACL_STATE_FILTERED_BIT, "annotation creation is dropped");
+
+    DP_acl_state_reset(acls, 0);
+    UINT_EQ_OK(DP_acl_state_locked_categories(acls), 0,
+               "category locks are cleared on reset");
+    DP_acl_state_free(acls);
+}
+
+static void frozen_features(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    NOK(DP_acl_state_feature_frozen(acls, DP_FEATURE_PUT_IMAGE),
+        "put image isn't frozen by default");
+    UINT_EQ_OK(DP_acl_state_feature_freeze(acls, DP_FEATURE_PUT_IMAGE,
+                                           DP_ACCESS_TIER_OPERATOR),
+               DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT,
+               "freezing put image changes its tier");
+    OK(DP_acl_state_feature_frozen(acls, DP_FEATURE_PUT_IMAGE),
+       "put image is frozen");
+
+    uint8_t tiers[DP_FEATURE_COUNT];
+    memset(tiers, 255, sizeof(tiers));
+    tiers[DP_FEATURE_PUT_IMAGE] = DP_ACCESS_TIER_GUEST;
+    tiers[DP_FEATURE_UNDO] = DP_ACCESS_TIER_TRUSTED;
+    UINT_EQ_OK(handle(acls, feature_access_levels_new(1, tiers)),
+               DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT,
+               "operator changes the unfrozen feature");
+    DP_FeatureTiers feature = DP_acl_state_feature_tiers(acls);
+    INT_EQ_OK(feature.tiers[DP_FEATURE_PUT_IMAGE], DP_ACCESS_TIER_OPERATOR,
+              "operator can't lower frozen put image");
+    INT_EQ_OK(feature.tiers[DP_FEATURE_UNDO], DP_ACCESS_TIER_TRUSTED,
+              "rest of the message is applied");
+
+    tiers[DP_FEATURE_UNDO] = 255;
+    UINT_EQ_OK(handle(acls, feature_access_levels_new(1, tiers)), 0,
+               "changing only the frozen feature changes nothing");
+
+    DP_acl_state_reset(acls, 0);
+    feature = DP_acl_state_feature_tiers(acls);
+    INT_EQ_OK(feature.tiers[DP_FEATURE_PUT_IMAGE], DP_ACCESS_TIER_OPERATOR,
+              "frozen tier survives reset");
+    INT_EQ_OK(feature.tiers[DP_FEATURE_UNDO], DP_ACCESS_TIER_GUEST,
+              "unfrozen tier is reset");
+    OK(DP_acl_state_feature_frozen(acls, DP_FEATURE_PUT_IMAGE),
+       "freeze survives reset");
+
+    DP_acl_state_feature_unfreeze(acls, DP_FEATURE_PUT_IMAGE);
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    UINT_EQ_OK(handle(acls, feature_access_levels_new(1, tiers)),
+               DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT,
+               "operator can lower unfrozen put image");
+    DP_acl_state_free(acls);
+}
+
+static bool is_cosmetic_muted(DP_AclState *acls, uint8_t user_id)
+{
+    DP_UserAcls users = DP_acl_state_users(acls);
+    return DP_user_acls_is_cosmetic_muted(&users, user_id);
+}
+
+static void cosmetic_mute(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, layer_create_new(2, 0x201));
+
+    UINT_EQ_OK(handle(acls, DP_msg_cosmetic_mute_new(3, 2, true)),
+               DP_ACL_STATE_FILTERED_BIT, "non-operator can't mute");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_NOT_OPERATOR,
+              "non-operator mute rejection reason");
+    UINT_EQ_OK(handle(acls, DP_msg_cosmetic_mute_new(1, 2, true)),
+               DP_ACL_STATE_CHANGE_USERS_BIT, "operator can mute");
+    UINT_EQ_OK(handle(acls, DP_msg_cosmetic_mute_new(1, 2, true)), 0,
+               "muting again changes nothing");
+    OK(is_cosmetic_muted(acls, 2), "user 2 is muted");
+    NOK(is_cosmetic_muted(acls, 3), "user 3 isn't muted");
+
+    UINT_EQ_OK(handle(acls, DP_msg_laser_trail_new(2, 0xffff0000u, 1)),
+               DP_ACL_STATE_FILTERED_BIT, "muted laser trail is dropped");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls),
+              DP_ACL_REJECTION_COSMETIC_MUTED, "muted rejection reason");
+    UINT_EQ_OK(handle(acls, DP_msg_move_pointer_new(2, 4, 4)),
+               DP_ACL_STATE_FILTERED_BIT, "muted pointer movement is dropped");
+    UINT_EQ_OK(handle(acls, DP_msg_marker_new(2, "hi", 2)),
+               DP_ACL_STATE_FILTERED_BIT, "muted marker is dropped");
+    UINT_EQ_OK(handle(acls, DP_msg_move_pointer_new(3, 4, 4)), 0,
+               "unmuted user's pointer movement passes");
+    UINT_EQ_OK(
+        handle(acls, draw_dabs_classic_new(2, 0x201, DP_BLEND_MODE_NORMAL)), 0,
+        "muted user can still draw");
+
+    UINT_EQ_OK(handle(acls, DP_msg_cosmetic_mute_new(1, 2, false)),
+               DP_ACL_STATE_CHANGE_USERS_BIT, "operator can unmute");
+    UINT_EQ_OK(handle(acls, DP_msg_move_pointer_new(2, 4, 4)), 0,
+               "unmuted pointer movement passes");
+
+    (void)handle(acls, DP_msg_cosmetic_mute_new(1, 2, true));
+    (void)handle(acls, DP_msg_leave_new(2));
+    NOK(is_cosmetic_muted(acls, 2), "leaving ends the mute");
+    DP_acl_state_free(acls);
+}
+
+
+static void check_drawable_layers(TEST_PARAMS, DP_AclState *acls,
+                                  uint8_t user_id, int expected_count,
+                                  const int *expected)
+{
+    int layer_ids[] = {0x101, 0x102, 0x103, 0x104, 0x105};
+    int out_layer_ids[DP_ARRAY_LENGTH(layer_ids)];
+    int count = DP_acl_state_drawable_layers(
+        acls, user_id, (int)DP_ARRAY_LENGTH(layer_ids), layer_ids,
+        out_layer_ids);
+    if (INT_EQ_OK(count, expected_count, "user %d has %d drawable layers",
+                  (int)user_id, expected_count)) {
+        for (int i = 0; i < count; ++i) {
+            INT_EQ_OK(out_layer_ids[i], expected[i],
+                      "user %d drawable layer %d", (int)user_id, i);
+        }
+    }
+}
+
+static void drawable_layers(TEST_PARAMS)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    for (int layer_id = 0x101; layer_id <= 0x105; ++layer_id) {
+        (void)handle(acls, layer_create_new(1, layer_id));
+    }
+    (void)handle(acls, layer_acl_new(1, 0x102,
+                                     DP_ACL_ALL_LOCKED_BIT | DP_ACCESS_TIER_GUEST,
+                                     0, NULL));
+    (void)handle(acls, layer_acl_new(1, 0x103, DP_ACCESS_TIER_GUEST, 1,
+                                     (uint8_t[]){2}));
+    (void)handle(acls, layer_acl_new(1, 0x104, DP_ACCESS_TIER_GUEST, 1,
+                                     (uint8_t[]){3}));
+    (void)handle(acls,
+                 layer_acl_new(1, 0x105, DP_ACCESS_TIER_TRUSTED, 0, NULL));
+
+    check_drawable_layers(TEST_ARGS, acls, 1, 2, (int[]){0x101, 0x105});
+    check_drawable_layers(TEST_ARGS, acls, 2, 2, (int[]){0x101, 0x103});
+    check_drawable_layers(TEST_ARGS, acls, 3, 2, (int[]){0x101, 0x104});
+
+    int layer_ids[] = {0x105, 0x104, 0x103, 0x102, 0x101};
+    INT_EQ_OK(DP_acl_state_drawable_layers(acls, 2, 5, layer_ids, layer_ids), 2,
+              "filtering in place");
+    INT_EQ_OK(layer_ids[0], 0x103, "first layer filtered in place");
+    INT_EQ_OK(layer_ids[1], 0x101, "second layer filtered in place");
+    INT_EQ_OK(DP_acl_state_drawable_layers(acls, 2, 0, NULL, NULL), 0,
+              "no layers given means no drawable layers");
+    DP_acl_state_free(acls);
+}
+
+
+static DP_AclState *orphan_policy_setup(DP_AclOrphanPolicy policy,
+                                        uint8_t heir_id)
+{
+    DP_AclState *acls = DP_acl_state_new();
+    DP_acl_state_orphan_policy_set(acls, policy, heir_id);
+    for (uint8_t user_id = 1; user_id <= 3; ++user_id) {
+        (void)handle(acls, join_new(user_id, 0));
+    }
+    (void)handle(acls, session_owner_new(1, (uint8_t[]){1}));
+    (void)handle(acls, layer_create_new(3, 0x301));
+    (void)handle(acls, layer_create_new(3, 0x302));
+    (void)handle(acls, layer_create_new(2, 0x201));
+    return acls;
+}
+
+static DP_Message *layer_retitle_new(unsigned int context_id, int layer_id)
+{
+    return DP_msg_layer_retitle_new(context_id, DP_int_to_uint16(layer_id),
+                                    "x", 1);
+}
+
+static void orphan_policy_ignore(TEST_PARAMS)
+{
+    DP_AclState *acls = orphan_policy_setup(DP_ACL_ORPHAN_POLICY_IGNORE, 1);
+    UINT_EQ_OK(handle(acls, DP_msg_leave_new(3)),
+               DP_ACL_STATE_CHANGE_USERS_BIT, "leave only changes users");
+    INT_EQ_OK(DP_acl_state_orphaned_layers(acls, NULL), 0,
+              "no orphaned layers");
+    UINT_EQ_OK(DP_acl_state_layer_owner(acls, 0x301), 3,
+               "layer still owned by absent user");
+    DP_acl_state_free(acls);
+}
+
+static void orphan_policy_record(TEST_PARAMS)
+{
+    DP_AclState *acls = orphan_policy_setup(DP_ACL_ORPHAN_POLICY_RECORD, 0);
+    UINT_EQ_OK(handle(acls, DP_msg_leave_new(3)),
+               DP_ACL_STATE_CHANGE_USERS_BIT | DP_ACL_STATE_CHANGE_LAYERS_BIT,
+               "leave orphans layers");
+    int layer_ids[2];
+    if (INT_EQ_OK(DP_acl_state_orphaned_layers(acls, layer_ids), 2,
+                  "two orphaned layers")) {
+        OK((layer_ids[0] == 0x301 && layer_ids[1] == 0x302)
+               || (layer_ids[0] == 0x302 && layer_ids[1] == 0x301),
+           "orphaned layers are the ones of the leaving user");
+    }
+    OK(DP_acl_state_layer_orphaned(acls, 0x301), "layer is orphaned");
+    UINT_EQ_OK(DP_acl_state_layer_owner(acls, 0x301), 0,
+               "orphaned layer has no owner");
+    NOK(DP_acl_state_layer_orphaned(acls, 0x201),
+        "other user's layer isn't orphaned");
+
+    // A new user that gets the same id doesn't inherit the layers.
+    (void)handle(acls, join_new(3, 0));
+    UINT_EQ_OK(handle(acls, layer_retitle_new(3, 0x301)),
+               DP_ACL_STATE_FILTERED_BIT,
+               "user with same id can't edit orphaned layer");
+    INT_EQ_OK(DP_acl_state_last_rejection(acls), DP_ACL_REJECTION_NOT_OWNER,
+              "rejected because not the owner");
+
+    UINT_EQ_OK(DP_acl_state_claim_layer(acls, 0x301, 2), 0,
+               "non-operator can't claim orphaned layer");
+    UINT_EQ_OK(DP_acl_state_claim_layer(acls, 0x201, 1), 0,
+               "operator can't claim layer that isn't orphaned");
+    UINT_EQ_OK(DP_acl_state_claim_layer(acls, 0x301, 1),
+               DP_ACL_STATE_CHANGE_LAYERS_BIT,
+               "operator claims orphaned layer");
+    UINT_EQ_OK(DP_acl_state_layer_owner(acls, 0x301), 1,
+               "claimed layer is owned by operator");
+    INT_EQ_OK(DP_acl_state_orphaned_layers(acls, NULL), 1,
+              "one orphaned layer left");
+
+    (void)handle(acls, DP_msg_layer_delete_new(1, 0x302, 0));
+    INT_EQ_OK(DP_acl_state_orphaned_layers(acls, NULL), 0,
+              "deleting orphaned layer forgets it");
+
+    DP_acl_state_reset(acls, 0);
+    UINT_EQ_OK(DP_acl_state_layer_owner(acls, 0x301), 3,
+               "reset drops ownership changes");
+    INT_EQ_OK(DP_acl_state_orphan_policy(acls), DP_ACL_ORPHAN_POLICY_RECORD,
+              "orphan policy survives reset");
+    DP_acl_state_free(acls);
+}
+
+static void orphan_policy_transfer(TEST_PARAMS)
+{
+    DP_AclState *acls = orphan_policy_setup(DP_ACL_ORPHAN_POLICY_TRANSFER, 1);
+    UINT_EQ_OK(handle(acls, DP_msg_leave_new(3)),
+               DP_ACL_STATE_CHANGE_USERS_BIT | DP_ACL_STATE_CHANGE_LAYERS_BIT,
+               "leave transfers layers");
+    UINT_EQ_OK(DP_acl_state_layer_owner(acls, 0x301), 1,
+               "first layer transferred to heir");
+    UINT_EQ_OK(DP_acl_state_layer_owner(acls, 0x302), 1,
+               "second layer transferred to heir");
+    UINT_EQ_OK(DP_acl_state_layer_owner(acls, 0x201), 2,
+               "other user's layer is untouched");
+    INT_EQ_OK(DP_acl_state_orphaned_layers(acls, NULL), 0,
+              "no orphaned layers");
+    UINT_EQ_OK(handle(acls, layer_retitle_new(1, 0x301)), 0,
+               "heir can edit transferred layer");
+
+    // With the heir gone, there's nobody to transfer to.
+    (void)handle(acls, DP_msg_leave_new(1));
+    OK(DP_acl_state_layer_orphaned(acls, 0x301),
+       "heir's layers get orphaned when they leave");
+    (void)handle(acls, DP_msg_leave_new(2));
+    OK(DP_acl_state_layer_orphaned(acls, 0x201),
+       "layers get orphaned when heir is absent");
+    DP_acl_state_free(acls);
+}
+
+static void access_conflicts(TEST_PARAMS)
+{
+    DP_AclState *acls = orphan_policy_setup(DP_ACL_ORPHAN_POLICY_RECORD, 0);
+    INT_EQ_OK(DP_acl_state_access_conflicts(acls, NULL), 0,
+              "no conflicts initially");
+
+    (void)handle(acls, DP_msg_layer_acl_exclusive_new(1, 0x201, 2, true));
+    INT_EQ_OK(DP_acl_state_access_conflicts(acls, NULL), 0,
+              "owner getting exclusive access isn't a conflict");
+
+    (void)handle(acls, DP_msg_layer_acl_exclusive_new(1, 0x301, 2, true));
+    DP_AclAccessConflict conflicts[1];
+    if (INT_EQ_OK(DP_acl_state_access_conflicts(acls, conflicts), 1,
+                  "exclusive access for someone else is a conflict")) {
+        INT_EQ_OK(conflicts[0].layer_id, 0x301, "conflicting layer reported");
+        UINT_EQ_OK(conflicts[0].owner_id, 3, "excluded owner reported");
+    }
+    OK(DP_acl_state_layer_locked_for(acls, 3, 0x301),
+       "owner is locked out of their layer");
+
+    (void)handle(acls, DP_msg_layer_acl_exclusive_new(1, 0x301, 3, true));
+    INT_EQ_OK(DP_acl_state_access_conflicts(acls, NULL), 0,
+              "adding the owner to the exclusive list resolves the conflict");
+
+    (void)handle(acls, DP_msg_layer_acl_exclusive_new(1, 0x301, 3, false));
+    INT_EQ_OK(DP_acl_state_access_conflicts(acls, NULL), 1,
+              "removing the owner again brings the conflict back");
+    (void)handle(acls, DP_msg_leave_new(3));
+    INT_EQ_OK(DP_acl_state_access_conflicts(acls, NULL), 0,
+              "orphaned layers don't conflict");
+    DP_acl_state_free(acls);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(operator_policy_allow_empty);
+    REGISTER_TEST(operator_policy_keep_last);
+    REGISTER_TEST(operator_policy_promote_oldest);
+    REGISTER_TEST(operator_policy_promote_oldest_after_reset);
+    REGISTER_TEST(reset_with_operators);
+    REGISTER_TEST(feature_tiers_change_detection);
+    REGISTER_TEST(default_layer_acl);
+    REGISTER_TEST(default_layer_id_reserved);
+    REGISTER_TEST(rejection_reasons);
+    REGISTER_TEST(undo_other_users);
+    REGISTER_TEST(policy_round_trip);
+    REGISTER_TEST(policy_version_mismatch);
+    REGISTER_TEST(alpha_locked_layer);
+    REGISTER_TEST(immutable_layer);
+    REGISTER_TEST(brush_tiers);
+    REGISTER_TEST(layer_limit);
+    REGISTER_TEST(canvas_size_limit);
+    REGISTER_TEST(annotation_limits);
+    REGISTER_TEST(annotation_locks);
+    REGISTER_TEST(annotation_text_edits);
+    REGISTER_TEST(annotation_id_prefix);
+    REGISTER_TEST(governing_features);
+    REGISTER_TEST(required_tiers);
+    REGISTER_TEST(local_mode);
+    REGISTER_TEST(local_mode_lock_layer);
+    REGISTER_TEST(layer_acl_exclusive);
+    REGISTER_TEST(snapshot_restore);
+    REGISTER_TEST(summary);
+    REGISTER_TEST(trusted_edit_layers);
+    REGISTER_TEST(default_layer_existence);
+    REGISTER_TEST(client_meta_own_color);
+    REGISTER_TEST(audit_log);
+    REGISTER_TEST(last_change_message);
+    REGISTER_TEST(dry_run);
+    REGISTER_TEST(handle_fast);
+    REGISTER_TEST(preprocess_clamp);
+    REGISTER_TEST(preprocess_drop);
+    REGISTER_TEST(feature_tiers_blocked_for);
+    REGISTER_TEST(feature_tiers_delta);
+    REGISTER_TEST(feature_tiers_non_default);
+    REGISTER_TEST(feature_tiers_presets);
+    REGISTER_TEST(feature_tiers_restore);
+    REGISTER_TEST(feature_tiers_validate);
+    REGISTER_TEST(access_tier_privilege);
+    REGISTER_TEST(authenticated_tier);
+    REGISTER_TEST(authenticated_are_trusted);
+    REGISTER_TEST(elevated_users);
+    REGISTER_TEST(user_bits_iterator);
+    REGISTER_TEST(layer_contributors);
+    REGISTER_TEST(strict_mode);
+    REGISTER_TEST(permission_report);
+    REGISTER_TEST(system_messages);
+    REGISTER_TEST(layer_acl_versions);
+    REGISTER_TEST(sequenced_layer_acls);
+    REGISTER_TEST(local_authority);
+    REGISTER_TEST(lock_layer);
+    REGISTER_TEST(open_all);
+    REGISTER_TEST(any_layer_locked);
+    REGISTER_TEST(lock_new_users);
+    REGISTER_TEST(locked_categories);
+    REGISTER_TEST(frozen_features);
+    REGISTER_TEST(cosmetic_mute);
+    REGISTER_TEST(drawable_layers);
+    REGISTER_TEST(orphan_policy_ignore);
+    REGISTER_TEST(orphan_policy_record);
+    REGISTER_TEST(orphan_policy_transfer);
+    REGISTER_TEST(access_conflicts);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,92 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include <dpcommon/common.h>
+#include <dpmsg/acl.h>
+#include <dpmsg/acl_change_accumulator.h>
+#include <dptest.h>
+
+
+static void coalesce_after_quiet_period(TEST_PARAMS)
+{
+    DP_AclChangeAccumulator acc = DP_acl_change_accumulator_make(100, 0);
+    NOK(DP_acl_change_accumulator_pending(&acc), "nothing pending initially");
+    INT_EQ_OK(DP_acl_change_accumulator_deadline_ms(&acc), -1,
+              "no deadline initially");
+    INT_EQ_OK(DP_acl_change_accumulator_poll(&acc, 1000), 0,
+              "nothing emitted without changes");
+
+    int emissions = 0;
+    uint8_t emitted = 0;
+    uint8_t changes[] = {
+        DP_ACL_STATE_CHANGE_USERS_BIT,
+        DP_ACL_STATE_CHANGE_LAYERS_BIT,
+        DP_ACL_STATE_FILTERED_BIT,
+        DP_ACL_STATE_CHANGE_USERS_BIT,
+        DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT,
+    };
+    for (int i = 0; i < (int)DP_ARRAY_LENGTH(changes); ++i) {
+        long long now_ms = 1000 + i * 50;
+        uint8_t result =
+            DP_acl_change_accumulator_push(&acc, changes[i], now_ms);
+        result |= DP_acl_change_accumulator_poll(&acc, now_ms + 49);
+        if (result != 0) {
+            ++emissions;
+            emitted |= result;
+        }
+    }
+    INT_EQ_OK(emissions, 0, "nothing emitted while changes keep coming in");
+    OK(DP_acl_change_accumulator_pending(&acc), "changes are pending");
+    INT_EQ_OK(DP_acl_change_accumulator_deadline_ms(&acc), 1300,
+              "deadline is a quiet period after the last change");
+
+    INT_EQ_OK(DP_acl_change_accumulator_poll(&acc, 1299), 0,
+              "nothing emitted before the quiet period ends");
+    emitted = DP_acl_change_accumulator_poll(&acc, 1300);
+    INT_EQ_OK(emitted,
+              DP_ACL_STATE_CHANGE_USERS_BIT | DP_ACL_STATE_CHANGE_LAYERS_BIT
+                  | DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT,
+              "single coalesced emission without the filtered bit");
+    NOK(DP_acl_change_accumulator_pending(&acc), "nothing pending after");
+    INT_EQ_OK(DP_acl_change_accumulator_poll(&acc, 5000), 0,
+              "nothing emitted twice");
+}
+
+static void coalesce_after_max_count(TEST_PARAMS)
+{
+    DP_AclChangeAccumulator acc = DP_acl_change_accumulator_make(100, 3);
+    INT_EQ_OK(DP_acl_change_accumulator_push(
+                  &acc, DP_ACL_STATE_CHANGE_LAYERS_BIT, 0),
+              0, "first change not emitted");
+    INT_EQ_OK(DP_acl_change_accumulator_push(&acc, 0, 1), 0,
+              "zero change not emitted");
+    INT_EQ_OK(DP_acl_change_accumulator_push(
+                  &acc, DP_ACL_STATE_FILTERED_BIT, 2),
+              0, "filtered change not emitted");
+    INT_EQ_OK(DP_acl_change_accumulator_push(
+                  &acc, DP_ACL_STATE_CHANGE_LAYERS_BIT, 3),
+              0, "second change not emitted");
+    INT_EQ_OK(DP_acl_change_accumulator_push(
+                  &acc, DP_ACL_STATE_CHANGE_ANNOTATIONS_BIT, 4),
+              DP_ACL_STATE_CHANGE_LAYERS_BIT
+                  | DP_ACL_STATE_CHANGE_ANNOTATIONS_BIT,
+              "third change emits coalesced changes");
+    INT_EQ_OK(DP_acl_change_accumulator_poll(&acc, 1000), 0,
+              "nothing left to emit after the quiet period");
+
+    DP_acl_change_accumulator_push(&acc, DP_ACL_STATE_CHANGE_USERS_BIT, 2000);
+    INT_EQ_OK(DP_acl_change_accumulator_flush(&acc),
+              DP_ACL_STATE_CHANGE_USERS_BIT, "flush emits right away");
+    INT_EQ_OK(DP_acl_change_accumulator_flush(&acc), 0,
+              "flush with nothing pending emits nothing");
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(coalesce_after_quiet_period);
+    REGISTER_TEST(coalesce_after_max_count);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,41 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include <dpcommon/common.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+
+
+static void layer_id_round_trip(TEST_PARAMS)
+{
+    INT_EQ_OK(DP_layer_id_make(1, 1), 0x101, "user 1, index 1");
+    INT_EQ_OK(DP_layer_id_make(0, 0), 0, "zero");
+    INT_EQ_OK(DP_layer_id_make(0xff, 0xff), 0xffff, "largest id");
+    INT_EQ_OK(DP_layer_id_make(0x12, 0), 0x1200, "index 0");
+
+    UINT_EQ_OK(DP_layer_id_creator(0x1234), 0x12, "creator is the high byte");
+    UINT_EQ_OK(DP_layer_id_index(0x1234), 0x34, "index is the low byte");
+    UINT_EQ_OK(DP_layer_id_creator(0xff), 0, "creator of low id");
+    UINT_EQ_OK(DP_layer_id_index(0xff00), 0, "index of high id");
+
+    int mismatches = 0;
+    for (int creator = 0; creator <= 0xff; ++creator) {
+        for (int index = 0; index <= 0xff; ++index) {
+            int layer_id = DP_layer_id_make((uint8_t)creator, (uint8_t)index);
+            if (DP_layer_id_creator(layer_id) != creator
+                || DP_layer_id_index(layer_id) != index) {
+                ++mismatches;
+            }
+        }
+    }
+    INT_EQ_OK(mismatches, 0, "every creator and index round-trips");
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(layer_id_round_trip);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,176 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include <dpcommon/common.h>
+#include <dpcommon/conversions.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+
+
+static void decode_fails(TEST_PARAMS, const unsigned char *buf, size_t size,
+                         const char *title)
+{
+    DP_Message *msg = DP_message_deserialize_exact(buf, size);
+    if (!NULL_OK(msg, "%s fails to decode", title)) {
+        DP_message_decref(msg);
+    }
+}
+
+static void decode_malformed_frames(TEST_PARAMS)
+{
+    decode_fails(TEST_ARGS, NULL, 0, "NULL buffer");
+    decode_fails(TEST_ARGS, (unsigned char[]){0}, 0, "empty buffer");
+    decode_fails(TEST_ARGS, (unsigned char[]){0, 0, 32}, 3, "truncated header");
+    // Join with a body length of 10, but only 2 bytes of body.
+    decode_fails(TEST_ARGS, (unsigned char[]){0, 10, 32, 1, 0, 4}, 6,
+                 "truncated body");
+    // Leave has no body, but the frame has a trailing byte.
+    decode_fails(TEST_ARGS, (unsigned char[]){0, 0, 33, 1, 0}, 5,
+                 "trailing bytes");
+    // Internal messages must never come in over the wire.
+    decode_fails(TEST_ARGS, (unsigned char[]){0, 0, 31, 1}, 4,
+                 "reserved type byte");
+    decode_fails(TEST_ARGS, (unsigned char[]){0, 0, 254, 1}, 4,
+                 "unknown type byte");
+    // Join whose name length points past the end of the body.
+    decode_fails(TEST_ARGS, (unsigned char[]){0, 3, 32, 1, 0, 200, 'a'}, 7,
+                 "bad field length");
+    // Leave has no body, so a body length of 1 isn't valid.
+    decode_fails(TEST_ARGS, (unsigned char[]){0, 1, 33, 1, 0}, 5,
+                 "bad body length");
+}
+
+
+static unsigned char *get_serialize_buffer(void *user, size_t length)
+{
+    unsigned char **buffer_ptr = user;
+    *buffer_ptr = DP_malloc(length);
+    return *buffer_ptr;
+}
+
+static void decode_valid_frame(TEST_PARAMS)
+{
+    DP_Message *chat = DP_msg_chat_new(3, 0, 0, "hello", 5);
+    unsigned char *buf = NULL;
+    size_t size = DP_message_serialize(chat, true, get_serialize_buffer, &buf);
+    if (NOT_NULL_OK(buf, "serialized chat message")) {
+        DP_Message *msg = DP_message_deserialize_exact(buf, size);
+        if (NOT_NULL_OK(msg, "valid frame decodes")) {
+            OK(DP_message_equals(msg, chat), "decoded message matches");
+            DP_message_decref(msg);
+        }
+
+        for (size_t i = 0; i < size; ++i) {
+            DP_Message *prefix = DP_message_deserialize_exact(buf, i);
+            if (!NULL_OK(prefix, "prefix of length %zu fails to decode", i)) {
+                DP_message_decref(prefix);
+            }
+        }
+        DP_free(buf);
+    }
+    DP_message_decref(chat);
+}
+
+static void decode_unknown_type(TEST_PARAMS, const unsigned char *frame,
+                                size_t size, const char *title)
+{
+    DP_Message *msg = DP_message_deserialize_with_policy(
+        frame, size, true, DP_MESSAGE_UNKNOWN_ERROR);
+    if (!NULL_OK(msg, "%s fails to decode when erroring", title)) {
+        DP_message_decref(msg);
+    }
+
+    msg = DP_message_deserialize_with_policy(frame, size, true,
+                                             DP_MESSAGE_UNKNOWN_PASSTHROUGH);
+    if (NOT_NULL_OK(msg, "%s decodes when passing through", title)) {
+        INT_EQ_OK(DP_message_type(msg), frame[2], "%s keeps its type", title);
+        UINT_EQ_OK(DP_message_context_id(msg), frame[3],
+                   "%s keeps its context id", title);
+        OK(DP_message_opaque(msg), "%s is opaque", title);
+        NOK(DP_message_type_known(DP_message_type(msg)), "%s type is unknown",
+            title);
+
+        unsigned char *buf = NULL;
+        size_t out_size =
+            DP_message_serialize(msg, true, get_serialize_buffer, &buf);
+        OK(out_size == size && buf && memcmp(buf, frame, size) == 0,
+           "%s is forwarded byte for byte", title);
+        DP_free(buf);
+        DP_message_decref(msg);
+    }
+}
+
+static void decode_unknown_types(TEST_PARAMS)
+{
+    decode_unknown_type(TEST_ARGS, (unsigned char[]){0, 3, 120, 7, 1, 2, 3},
+                        7, "unknown meta message");
+    decode_unknown_type(TEST_ARGS, (unsigned char[]){0, 2, 254, 9, 0xff, 0}, 6,
+                        "unknown command message");
+    decode_unknown_type(TEST_ARGS, (unsigned char[]){0, 0, 254, 9}, 4,
+                        "unknown empty message");
+
+    // Known types still go through the regular decoding.
+    DP_Message *msg = DP_message_deserialize_with_policy(
+        (unsigned char[]){0, 0, 33, 1}, 4, true,
+        DP_MESSAGE_UNKNOWN_PASSTHROUGH);
+    if (NOT_NULL_OK(msg, "known type decodes when passing through")) {
+        INT_EQ_OK(DP_message_type(msg), DP_MSG_LEAVE, "known type is leave");
+        NOK(DP_message_opaque(msg), "known type isn't opaque");
+        DP_message_decref(msg);
+    }
+    msg = DP_message_deserialize_with_policy((unsigned char[]){0, 0, 31, 1}, 4,
+                                             true,
+                                             DP_MESSAGE_UNKNOWN_PASSTHROUGH);
+    if (!NULL_OK(msg, "reserved type still fails when passing through")) {
+        DP_message_decref(msg);
+    }
+}
+
+
+// Deterministic xorshift, so that any failure is reproducible.
+static uint32_t next_random(uint32_t *state)
+{
+    uint32_t x = *state;
+    x ^= x << 13;
+    x ^= x >> 17;
+    x ^= x << 5;
+    *state = x;
+    return x;
+}
+
+static void decode_random_frames(TEST_PARAMS)
+{
+    uint32_t state = 0x2545f491u;
+    unsigned char buf[64];
+    int decoded = 0;
+    for (int i = 0; i < 20000; ++i) {
+        size_t size = next_random(&state) % sizeof(buf);
+        for (size_t j = 0; j < size; ++j) {
+            buf[j] = (unsigned char)next_random(&state);
+        }
+        // Make the length field agree with the buffer size half of the time,
+        // otherwise nearly every input gets thrown out by the length check.
+        if (size >= DP_MESSAGE_HEADER_LENGTH && (i % 2) == 0) {
+            buf[0] = 0;
+            buf[1] = DP_size_to_uint8(size - DP_MESSAGE_HEADER_LENGTH);
+        }
+        DP_Message *msg = DP_message_deserialize_exact(buf, size);
+        if (msg) {
+            ++decoded;
+            DP_message_decref(msg);
+        }
+    }
+    OK(decoded > 0, "%d random frames got past validation", decoded);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(decode_malformed_frames);
+    REGISTER_TEST(decode_valid_frame);
+    REGISTER_TEST(decode_unknown_types);
+    REGISTER_TEST(decode_random_frames);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,53 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include <dpcommon/common.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dptest.h>
+
+
+static void check_origin(TEST_PARAMS, DP_Message *msg, unsigned int expected,
+                         const char *title)
+{
+    UINT_EQ_OK(DP_message_origin_user(msg), expected, "%s origin user", title);
+    DP_message_decref(msg);
+}
+
+
+static void message_origin_user(TEST_PARAMS)
+{
+    check_origin(TEST_ARGS, DP_msg_server_command_new(0, "{}", 2), 0,
+                 "server command");
+    check_origin(TEST_ARGS, DP_msg_ping_new(3, false), 0,
+                 "ping with a context id");
+    check_origin(TEST_ARGS, DP_msg_disconnect_new(0, 0, "bye", 3), 0,
+                 "disconnect");
+
+    check_origin(TEST_ARGS, DP_msg_join_new(3, 0, "user", 4, NULL, 0, NULL), 3,
+                 "server meta join");
+    check_origin(TEST_ARGS, DP_msg_leave_new(3), 3, "server meta leave");
+    check_origin(TEST_ARGS, DP_msg_chat_new(0, 0, 0, "hi", 2), 0,
+                 "system chat");
+    check_origin(TEST_ARGS, DP_msg_private_chat_new(4, 3, 0, "hi", 2), 4,
+                 "private chat sender, not target");
+
+    check_origin(TEST_ARGS, DP_msg_laser_trail_new(5, 0xffff0000u, 10), 5,
+                 "client meta laser trail");
+    check_origin(TEST_ARGS, DP_msg_undo_point_new(6), 6, "command undo point");
+    check_origin(TEST_ARGS,
+                 DP_msg_fill_rect_new(7, 0x701, DP_BLEND_MODE_NORMAL, 0, 0, 1,
+                                      1, 0xff000000u),
+                 7, "command fill rect");
+    check_origin(TEST_ARGS, DP_msg_undo_point_new(0), 0,
+                 "command from context id 0");
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(message_origin_user);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
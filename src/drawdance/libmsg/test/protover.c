@@ -150,6 +150,11 @@ static void compare(TEST_PARAMS)
                   false, false, true, false,
                   DP_PROTOCOL_COMPATIBILITY_BACKWARD_COMPATIBLE);
 
+    DP_ProtocolVersion *past_minor = DP_protocol_version_new("dp", 4, 24, 0);
+    compare_check(TEST_ARGS, "dp:4.24.0", past_minor, "dp", 4, 24, 0, false,
+                  false, false, true,
+                  DP_PROTOCOL_COMPATIBILITY_MINOR_INCOMPATIBILITY);
+
     DP_ProtocolVersion *minor_future = DP_protocol_version_new(
         DP_PROTOCOL_VERSION_NAMESPACE, DP_PROTOCOL_VERSION_SERVER,
         DP_PROTOCOL_VERSION_MAJOR, DP_PROTOCOL_VERSION_MINOR + 1);
@@ -257,6 +262,7 @@ static void compare(TEST_PARAMS)
     DP_protocol_version_free(server_future);
     DP_protocol_version_free(major_future);
     DP_protocol_version_free(minor_future);
+    DP_protocol_version_free(past_minor);
     DP_protocol_version_free(past_compatible);
     DP_protocol_version_free(past_incompatible);
     DP_protocol_version_free(current);
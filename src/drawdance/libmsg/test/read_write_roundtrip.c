@@ -452,6 +452,31 @@ static DP_Message *generate_local_change(void)
         NULL);
 }
 
+static DP_Message *generate_layer_acl_exclusive(void)
+{
+    return DP_msg_layer_acl_exclusive_new(generate_context_id(),
+                                          random_uint16(), random_uint8(),
+                                          random_bool());
+}
+
+static DP_Message *generate_client_meta(void)
+{
+    return DP_msg_client_meta_new(generate_context_id(), random_uint8(),
+                                  random_uint32());
+}
+
+static DP_Message *generate_cosmetic_mute(void)
+{
+    return DP_msg_cosmetic_mute_new(generate_context_id(), random_uint8(),
+                                    random_bool());
+}
+
+static DP_Message *generate_session_lock_categories(void)
+{
+    return DP_msg_session_lock_categories_new(generate_context_id(),
+                                              random_uint8());
+}
+
 static DP_Message *generate_undo_point(void)
 {
     return DP_msg_undo_point_new(generate_context_id());
@@ -800,6 +825,30 @@ static DP_Message *generate_key_frame_delete(void)
                                        random_uint16());
 }
 
+static DP_Message *generate_layer_order_move(void)
+{
+    return DP_msg_layer_order_move_new(
+        generate_context_id(), random_uint16(),
+        generate_variant(
+            (unsigned int[]){DP_MSG_LAYER_ORDER_MOVE_ALL_DIRECTION},
+            DP_MSG_LAYER_ORDER_MOVE_NUM_DIRECTION),
+        random_uint16());
+}
+
+static DP_Message *generate_annotation_text_edit(void)
+{
+    size_t text_len;
+    char *text =
+        generate_string(size_between(DP_MSG_ANNOTATION_TEXT_EDIT_TEXT_MIN_LEN,
+                                     DP_MSG_ANNOTATION_TEXT_EDIT_TEXT_MAX_LEN),
+                        &text_len);
+    DP_Message *msg = DP_msg_annotation_text_edit_new(
+        generate_context_id(), random_uint16(), random_uint16(),
+        random_uint16(), text, text_len);
+    DP_free(text);
+    return msg;
+}
+
 static DP_Message *generate_undo(void)
 {
     return DP_msg_undo_new(generate_context_id(), random_uint8(),
@@ -831,6 +880,10 @@ static GenerateFn generate_fns[] = {
     generate_undo_depth,
     generate_data,
     generate_local_change,
+    generate_layer_acl_exclusive,
+    generate_client_meta,
+    generate_cosmetic_mute,
+    generate_session_lock_categories,
     generate_undo_point,
     generate_canvas_resize,
     generate_layer_create,
@@ -867,6 +920,8 @@ static GenerateFn generate_fns[] = {
     generate_key_frame_retitle,
     generate_key_frame_layer_attributes,
     generate_key_frame_delete,
+    generate_layer_order_move,
+    generate_annotation_text_edit,
     generate_undo,
 };
 
@@ -0,0 +1,153 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include <dpcommon/common.h>
+#include <dpcommon/input.h>
+#include <dpcommon/output.h>
+#include <dpmsg/binary_reader.h>
+#include <dpmsg/binary_writer.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dpmsg/recording_markers.h>
+#include <dptest.h>
+#include <parson.h>
+
+#define RECORDING_PATH "test/tmp/recording_markers.dprec"
+
+
+struct Markers {
+    int count;
+    DP_RecordingMarker markers[4];
+    char *texts[4];
+};
+
+static DP_Message *marker_new(unsigned int user_id, const char *text)
+{
+    return DP_msg_marker_new(user_id, text, strlen(text));
+}
+
+static DP_Message *fill_rect_new(unsigned int user_id, uint16_t layer_id)
+{
+    return DP_msg_fill_rect_new(user_id, layer_id, DP_BLEND_MODE_NORMAL, 0, 0,
+                                10, 10, 0xff000000u);
+}
+
+static void write_recording(TEST_PARAMS, int count, DP_Message **msgs)
+{
+    DP_Output *output = DP_file_output_new_from_path(RECORDING_PATH);
+    FATAL(NOT_NULL_OK(output, "got output for %s", RECORDING_PATH));
+    DP_BinaryWriter *writer = DP_binary_writer_new(output);
+
+    JSON_Value *header_value = json_value_init_object();
+    JSON_Object *header = json_value_get_object(header_value);
+    json_object_set_string(header, "version", DP_PROTOCOL_VERSION);
+    OK(DP_binary_writer_write_header(writer, header), "wrote header");
+    json_value_free(header_value);
+
+    for (int i = 0; i < count; ++i) {
+        OK(DP_binary_writer_write_message(writer, msgs[i]) != 0,
+           "wrote message %d", i);
+        DP_message_decref(msgs[i]);
+    }
+    DP_binary_writer_free(writer);
+}
+
+static DP_BinaryReader *open_reader(TEST_PARAMS)
+{
+    DP_Input *input = DP_file_input_new_from_path(RECORDING_PATH);
+    FATAL(NOT_NULL_OK(input, "got input for %s", RECORDING_PATH));
+    DP_BinaryReader *reader = DP_binary_reader_new(input, 0);
+    FATAL(NOT_NULL_OK(reader, "got reader for %s", RECORDING_PATH));
+    return reader;
+}
+
+static void collect_marker(void *user, const DP_RecordingMarker *marker)
+{
+    struct Markers *m = user;
+    if (m->count < (int)DP_ARRAY_LENGTH(m->markers)) {
+        char *text = DP_malloc(marker->text_length + 1);
+        memcpy(text, marker->text, marker->text_length);
+        text[marker->text_length] = '\0';
+        m->markers[m->count] = *marker;
+        m->markers[m->count].text = text;
+        m->texts[m->count] = text;
+    }
+    ++m->count;
+}
+
+static void markers_dispose(struct Markers *m)
+{
+    int count = DP_min_int(m->count, (int)DP_ARRAY_LENGTH(m->markers));
+    for (int i = 0; i < count; ++i) {
+        DP_free(m->texts[i]);
+    }
+}
+
+
+static void markers_found(TEST_PARAMS)
+{
+    write_recording(
+        TEST_ARGS, 6,
+        (DP_Message *[]){
+            DP_msg_canvas_resize_new(1, 0, 100, 100, 0),
+            marker_new(1, "Sketch"),
+            DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "layer", 5),
+            fill_rect_new(1, 0x101),
+            marker_new(2, "Colors"),
+            fill_rect_new(2, 0x101),
+        });
+
+    DP_BinaryReader *reader = open_reader(TEST_ARGS);
+    struct Markers m = {0};
+    INT_EQ_OK(DP_recording_markers(reader, collect_marker, &m), 2,
+              "two markers");
+    if (INT_EQ_OK(m.count, 2, "two markers reported")) {
+        INT_EQ_OK(m.markers[0].position, 1, "first marker position");
+        STR_EQ_OK(m.markers[0].text, "Sketch", "first marker text");
+        INT_EQ_OK(m.markers[1].position, 4, "second marker position");
+        STR_EQ_OK(m.markers[1].text, "Colors", "second marker text");
+        OK(m.markers[0].offset > DP_binary_reader_body_offset(reader),
+           "first marker comes after the first message");
+        OK(m.markers[1].offset > m.markers[0].offset,
+           "second marker comes after the first");
+
+        // Seeking to a marker's offset must land on the marker message.
+        FATAL(OK(DP_binary_reader_seek(reader, m.markers[1].offset),
+                 "seek to second marker"));
+        DP_Message *msg;
+        if (OK(DP_binary_reader_read_message(reader, true, &msg)
+                   == DP_BINARY_READER_SUCCESS,
+               "read message at second marker")) {
+            INT_EQ_OK(DP_message_type(msg), DP_MSG_MARKER,
+                      "second marker offset points at a marker");
+            UINT_EQ_OK(DP_message_context_id(msg), 2,
+                       "marker comes from the second user");
+            DP_message_decref(msg);
+        }
+    }
+    markers_dispose(&m);
+    DP_binary_reader_free(reader);
+}
+
+static void markers_none(TEST_PARAMS)
+{
+    write_recording(TEST_ARGS, 1,
+                    (DP_Message *[]){
+                        DP_msg_canvas_resize_new(1, 0, 100, 100, 0),
+                    });
+
+    DP_BinaryReader *reader = open_reader(TEST_ARGS);
+    INT_EQ_OK(DP_recording_markers(reader, NULL, NULL), 0,
+              "recording without markers has none");
+    DP_binary_reader_free(reader);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(markers_found);
+    REGISTER_TEST(markers_none);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -0,0 +1,212 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+#include <dpcommon/common.h>
+#include <dpcommon/conversions.h>
+#include <dpcommon/input.h>
+#include <dpcommon/output.h>
+#include <dpmsg/acl.h>
+#include <dpmsg/binary_reader.h>
+#include <dpmsg/binary_writer.h>
+#include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
+#include <dpmsg/recording_verify.h>
+#include <dptest.h>
+#include <parson.h>
+
+#define RECORDING_PATH "test/tmp/recording_verify.dprec"
+
+
+static void set_uint8s(int count, uint8_t *out, void *user)
+{
+    memcpy(out, user, DP_int_to_size(count));
+}
+
+static void set_bytes(size_t size, unsigned char *out, void *user)
+{
+    memcpy(out, user, size);
+}
+
+static unsigned char *get_serialize_buffer(void *user, size_t length)
+{
+    unsigned char **out_buffer = user;
+    *out_buffer = DP_malloc(length);
+    return *out_buffer;
+}
+
+// Wraps the message the way a recording stores messages that the filter
+// dropped, taking over the given reference.
+static DP_Message *filtered_new(DP_Message *msg)
+{
+    unsigned char *buffer = NULL;
+    size_t length =
+        DP_message_serialize(msg, true, get_serialize_buffer, &buffer);
+    DP_Message *filtered = DP_msg_filtered_new(DP_message_context_id(msg),
+                                               set_bytes, length, buffer);
+    DP_free(buffer);
+    DP_message_decref(msg);
+    return filtered;
+}
+
+static DP_Message *join_new(unsigned int user_id)
+{
+    return DP_msg_join_new(user_id, 0, "user", 4, NULL, 0, NULL);
+}
+
+static DP_Message *fill_rect_new(unsigned int user_id, uint16_t layer_id)
+{
+    return DP_msg_fill_rect_new(user_id, layer_id, DP_BLEND_MODE_NORMAL, 0, 0,
+                                10, 10, 0xff000000u);
+}
+
+static void write_recording(TEST_PARAMS, int count, DP_Message **msgs)
+{
+    DP_Output *output = DP_file_output_new_from_path(RECORDING_PATH);
+    FATAL(NOT_NULL_OK(output, "got output for %s", RECORDING_PATH));
+    DP_BinaryWriter *writer = DP_binary_writer_new(output);
+
+    JSON_Value *header_value = json_value_init_object();
+    JSON_Object *header = json_value_get_object(header_value);
+    json_object_set_string(header, "version", DP_PROTOCOL_VERSION);
+    OK(DP_binary_writer_write_header(writer, header), "wrote header");
+    json_value_free(header_value);
+
+    for (int i = 0; i < count; ++i) {
+        OK(DP_binary_writer_write_message(writer, msgs[i]) != 0,
+           "wrote message %d", i);
+        DP_message_decref(msgs[i]);
+    }
+    DP_binary_writer_free(writer);
+}
+
+static DP_BinaryReader *open_reader(TEST_PARAMS)
+{
+    DP_Input *input = DP_file_input_new_from_path(RECORDING_PATH);
+    FATAL(NOT_NULL_OK(input, "got input for %s", RECORDING_PATH));
+    DP_BinaryReader *reader = DP_binary_reader_new(input, 0);
+    FATAL(NOT_NULL_OK(reader, "got reader for %s", RECORDING_PATH));
+    return reader;
+}
+
+struct Mismatches {
+    int count;
+    DP_RecordingFilterMismatch mismatches[8];
+};
+
+static void collect_mismatch(void *user,
+                             const DP_RecordingFilterMismatch *mismatch)
+{
+    struct Mismatches *m = user;
+    if (m->count < (int)DP_ARRAY_LENGTH(m->mismatches)) {
+        m->mismatches[m->count] = *mismatch;
+        DP_message_incref(mismatch->msg);
+    }
+    ++m->count;
+}
+
+static void mismatches_dispose(struct Mismatches *m)
+{
+    int count = DP_min_int(m->count, (int)DP_ARRAY_LENGTH(m->mismatches));
+    for (int i = 0; i < count; ++i) {
+        DP_message_decref(m->mismatches[i].msg);
+    }
+}
+
+static int verify(TEST_PARAMS, struct Mismatches *m)
+{
+    DP_BinaryReader *reader = open_reader(TEST_ARGS);
+    DP_AclState *acls = DP_acl_state_new();
+    int count = DP_recording_verify_filter(reader, acls, collect_mismatch, m);
+    DP_acl_state_free(acls);
+    DP_binary_reader_free(reader);
+    return count;
+}
+
+
+static void verify_filter_consistent(TEST_PARAMS)
+{
+    write_recording(
+        TEST_ARGS, 7,
+        (DP_Message *[]){
+            join_new(1),
+            join_new(2),
+            DP_msg_session_owner_new(0, set_uint8s, 1, (uint8_t[]){1}),
+            DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "layer", 5),
+            fill_rect_new(2, 0x101),
+            // Only operators may resize the canvas or fiddle with layer ACLs.
+            filtered_new(DP_msg_canvas_resize_new(2, 0, 100, 100, 0)),
+            filtered_new(DP_msg_layer_acl_new(2, 0x101, 0, set_uint8s, 0,
+                                              NULL)),
+        });
+
+    struct Mismatches m = {0};
+    INT_EQ_OK(verify(TEST_ARGS, &m), 0, "no mismatches");
+    INT_EQ_OK(m.count, 0, "no mismatches reported");
+    mismatches_dispose(&m);
+}
+
+static void verify_filter_mismatches(TEST_PARAMS)
+{
+    write_recording(
+        TEST_ARGS, 7,
+        (DP_Message *[]){
+            join_new(1),
+            join_new(2),
+            DP_msg_session_owner_new(0, set_uint8s, 1, (uint8_t[]){1}),
+            // Recorded as filtered, but an operator is allowed to do this.
+            filtered_new(DP_msg_canvas_resize_new(1, 0, 100, 100, 0)),
+            DP_msg_layer_tree_create_new(1, 0x101, 0, 0, 0, 0, "layer", 5),
+            // Recorded as accepted, but only operators may do this.
+            DP_msg_canvas_resize_new(2, 0, 100, 100, 0),
+            fill_rect_new(2, 0x101),
+        });
+
+    struct Mismatches m = {0};
+    INT_EQ_OK(verify(TEST_ARGS, &m), 2, "two mismatches");
+    if (INT_EQ_OK(m.count, 2, "two mismatches reported")) {
+        DP_RecordingFilterMismatch *first = &m.mismatches[0];
+        INT_EQ_OK(first->index, 3, "first mismatch index");
+        INT_EQ_OK(DP_message_type(first->msg), DP_MSG_CANVAS_RESIZE,
+                  "first mismatch is unwrapped");
+        OK(first->recorded_filtered, "first mismatch was recorded filtered");
+        INT_EQ_OK(first->rejection, DP_ACL_REJECTION_NONE,
+                  "first mismatch is accepted now");
+
+        DP_RecordingFilterMismatch *second = &m.mismatches[1];
+        INT_EQ_OK(second->index, 5, "second mismatch index");
+        UINT_EQ_OK(DP_message_context_id(second->msg), 2,
+                   "second mismatch is from the guest");
+        NOK(second->recorded_filtered,
+            "second mismatch was recorded accepted");
+        NOK(second->rejection == DP_ACL_REJECTION_NONE,
+            "second mismatch has a rejection reason now");
+    }
+    mismatches_dispose(&m);
+}
+
+static void verify_filter_undecodable(TEST_PARAMS)
+{
+    unsigned char garbage[] = {0x00, 0x01, 0x02};
+    write_recording(TEST_ARGS, 2,
+                    (DP_Message *[]){
+                        join_new(1),
+                        DP_msg_filtered_new(1, set_bytes, sizeof(garbage),
+                                            garbage),
+                    });
+
+    struct Mismatches m = {0};
+    INT_EQ_OK(verify(TEST_ARGS, &m), -1,
+              "undecodable filtered message is an error");
+    mismatches_dispose(&m);
+}
+
+
+static void register_tests(REGISTER_PARAMS)
+{
+    REGISTER_TEST(verify_filter_consistent);
+    REGISTER_TEST(verify_filter_mismatches);
+    REGISTER_TEST(verify_filter_undecodable);
+}
+
+int main(int argc, char **argv)
+{
+    return DP_test_main(argc, argv, register_tests, NULL);
+}
@@ -38,11 +38,13 @@ pub const DP_PREVIEW_BASE_SUBLAYER_ID: i32 = -100;
 pub const DP_PREVIEW_TRANSFORM_COUNT: u32 = 16;
 pub const DP_PAINT_ENGINE_FILTER_MESSAGE_FLAG_NO_TIME: u32 = 1;
 pub const DP_ACL_ALL_LOCKED_BIT: u32 = 128;
+pub const DP_ACL_IMMUTABLE_BIT: u32 = 16;
 pub const DP_ACL_STATE_FILTERED_BIT: u32 = 1;
 pub const DP_ACL_STATE_CHANGE_USERS_BIT: u32 = 2;
 pub const DP_ACL_STATE_CHANGE_LAYERS_BIT: u32 = 4;
 pub const DP_ACL_STATE_CHANGE_FEATURE_TIERS_BIT: u32 = 8;
-pub const DP_ACL_STATE_CHANGE_MASK: u32 = 14;
+pub const DP_ACL_STATE_CHANGE_ANNOTATIONS_BIT: u32 = 16;
+pub const DP_ACL_STATE_CHANGE_MASK: u32 = 30;
 pub const DP_ACL_STATE_RESET_IMAGE_INCLUDE_SESSION_OWNER: u32 = 1;
 pub const DP_ACL_STATE_RESET_IMAGE_INCLUDE_TRUSTED_USERS: u32 = 2;
 pub const DP_ACL_STATE_RESET_IMAGE_INCLUDE_USER_ACL: u32 = 4;
@@ -53,9 +55,9 @@ pub const DP_ACL_STATE_RESET_IMAGE_TEMPLATE_FLAGS: u32 = 0;
 pub const DP_BLEND_MODE_MAX: u32 = 255;
 pub const DP_PROTOCOL_VERSION_NAMESPACE: &[u8; 3] = b"dp\0";
 pub const DP_PROTOCOL_VERSION_SERVER: u32 = 4;
-pub const DP_PROTOCOL_VERSION_MAJOR: u32 = 24;
+pub const DP_PROTOCOL_VERSION_MAJOR: u32 = 25;
 pub const DP_PROTOCOL_VERSION_MINOR: u32 = 0;
-pub const DP_PROTOCOL_VERSION: &[u8; 10] = b"dp:4.24.0\0";
+pub const DP_PROTOCOL_VERSION: &[u8; 10] = b"dp:4.25.0\0";
 pub const DP_UNDO_DEPTH_DEFAULT: u32 = 30;
 pub const DP_MSG_SERVER_COMMAND_STATIC_LENGTH: u32 = 0;
 pub const DP_MSG_SERVER_COMMAND_MSG_MIN_LEN: u32 = 0;
@@ -4910,6 +4912,15 @@ extern "C" {
 extern "C" {
     pub fn DP_canvas_history_want_dump_set(ch: *mut DP_CanvasHistory, want_dump: bool);
 }
+extern "C" {
+    pub fn DP_canvas_history_compact_undo_points(ch: *mut DP_CanvasHistory) -> bool;
+}
+extern "C" {
+    pub fn DP_canvas_history_compact_undo_points_set(
+        ch: *mut DP_CanvasHistory,
+        compact_undo_points: bool,
+    );
+}
 extern "C" {
     pub fn DP_canvas_history_get(ch: *mut DP_CanvasHistory) -> *mut DP_CanvasState;
 }
@@ -6138,6 +6149,9 @@ extern "C" {
 extern "C" {
     pub fn DP_player_compatible(player: *mut DP_Player) -> bool;
 }
+extern "C" {
+    pub fn DP_player_compact_undo_points(player: *mut DP_Player) -> bool;
+}
 extern "C" {
     pub fn DP_player_acl_override_set(player: *mut DP_Player, override_: bool);
 }
@@ -6665,6 +6679,15 @@ extern "C" {
         want_canvas_history_dump: bool,
     );
 }
+extern "C" {
+    pub fn DP_paint_engine_compact_undo_points(pe: *mut DP_PaintEngine) -> bool;
+}
+extern "C" {
+    pub fn DP_paint_engine_compact_undo_points_set(
+        pe: *mut DP_PaintEngine,
+        compact_undo_points: bool,
+    );
+}
 extern "C" {
     pub fn DP_paint_engine_local_state_reset_image_build(
         pe: *mut DP_PaintEngine,
@@ -8063,12 +8086,14 @@ pub const DP_FEATURE_UNDO: DP_Feature = 8;
 pub const DP_FEATURE_METADATA: DP_Feature = 9;
 pub const DP_FEATURE_TIMELINE: DP_Feature = 10;
 pub const DP_FEATURE_MYPAINT: DP_Feature = 11;
-pub const DP_FEATURE_COUNT: DP_Feature = 12;
+pub const DP_FEATURE_CLASSIC_BRUSH: DP_Feature = 12;
+pub const DP_FEATURE_PIXEL_BRUSH: DP_Feature = 13;
+pub const DP_FEATURE_COUNT: DP_Feature = 14;
 pub type DP_Feature = ::std::os::raw::c_uint;
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct DP_FeatureTiers {
-    pub tiers: [DP_AccessTier; 12usize],
+    pub tiers: [DP_AccessTier; 14usize],
 }
 #[test]
 fn bindgen_test_layout_DP_FeatureTiers() {
@@ -8076,7 +8101,7 @@ fn bindgen_test_layout_DP_FeatureTiers() {
     let ptr = UNINIT.as_ptr();
     assert_eq!(
         ::std::mem::size_of::<DP_FeatureTiers>(),
-        48usize,
+        56usize,
         concat!("Size of: ", stringify!(DP_FeatureTiers))
     );
     assert_eq!(
@@ -8174,6 +8199,8 @@ fn bindgen_test_layout_DP_UserAcls() {
 #[derive(Debug, Copy, Clone)]
 pub struct DP_LayerAcl {
     pub locked: bool,
+    pub alpha_locked: bool,
+    pub immutable: bool,
     pub tier: DP_AccessTier,
     pub exclusive: DP_UserBits,
 }
@@ -8201,6 +8228,26 @@ fn bindgen_test_layout_DP_LayerAcl() {
             stringify!(locked)
         )
     );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).alpha_locked) as usize - ptr as usize },
+        1usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(DP_LayerAcl),
+            "::",
+            stringify!(alpha_locked)
+        )
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).immutable) as usize - ptr as usize },
+        2usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(DP_LayerAcl),
+            "::",
+            stringify!(immutable)
+        )
+    );
     assert_eq!(
         unsafe { ::std::ptr::addr_of!((*ptr).tier) as usize - ptr as usize },
         4usize,
@@ -8328,6 +8375,12 @@ extern "C" {
         layer_id: ::std::os::raw::c_int,
     ) -> bool;
 }
+extern "C" {
+    pub fn DP_acl_state_layer_immutable(
+        acls: *mut DP_AclState,
+        layer_id: ::std::os::raw::c_int,
+    ) -> bool;
+}
 extern "C" {
     pub fn DP_acl_state_annotation_locked(
         acls: *mut DP_AclState,
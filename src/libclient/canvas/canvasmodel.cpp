@@ -137,6 +137,8 @@ void CanvasModel::connectedToServer(
 
 	m_localUserId = myUserId;
 	m_compatibilityMode = compatibilityMode;
+	// Older clients treat every undo point as an undo step.
+	m_paintengine->setCompactUndoPoints(!compatibilityMode);
 	m_layerlist->setAutoselectAny(true);
 
 	m_aclstate->setLocalUserId(myUserId);
@@ -153,6 +155,7 @@ void CanvasModel::connectedToServer(
 void CanvasModel::disconnectedFromServer()
 {
 	m_compatibilityMode = false;
+	m_paintengine->setCompactUndoPoints(true);
 	m_paintengine->cleanup();
 	m_userlist->allLogout();
 	m_paintengine->resetAcl(m_localUserId);
@@ -1019,9 +1019,8 @@ void LayerListModel::updateCheckedLayerAcl(int layerId)
 int LayerListModel::searchAvailableLayerId(
 	const QSet<int> &takenIds, int contextId)
 {
-	int prefix = contextId << 8;
 	for(int i = 0; i < 256; ++i) {
-		int id = prefix | i;
+		int id = DP_layer_id_make(uint8_t(contextId), uint8_t(i));
 		if(!takenIds.contains(id)) {
 			return id;
 		}
@@ -3,6 +3,7 @@
 #define LIBCLIENT_CANVAS_LAYERLIST_H
 extern "C" {
 #include <dpmsg/blend_mode.h>
+#include <dpmsg/message.h>
 }
 #include "libclient/canvas/acl.h"
 #include "libclient/utils/keyframelayermodel.h"
@@ -70,7 +71,7 @@ struct LayerListItem {
 	uint8_t attributeFlags() const;
 
 	//! Get the ID of the user who created this layer
-	uint8_t creatorId() const { return uint8_t((id & 0xff00) >> 8); }
+	uint8_t creatorId() const { return DP_layer_id_creator(id); }
 
 	bool actuallyCensored() const { return censored || revealed; }
 };
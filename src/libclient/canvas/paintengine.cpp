@@ -109,6 +109,11 @@ void PaintEngine::setWantCanvasHistoryDump(bool wantCanvasHistoryDump)
 	m_paintEngine.setWantCanvasHistoryDump(wantCanvasHistoryDump);
 }
 
+void PaintEngine::setCompactUndoPoints(bool compactUndoPoints)
+{
+	m_paintEngine.setCompactUndoPoints(compactUndoPoints);
+}
+
 void PaintEngine::start()
 {
 	if(m_timerId != 0) {
@@ -53,6 +53,7 @@ public:
 	void setSnapshotMaxCount(int snapshotMaxCount);
 	void setSnapshotMinDelayMs(long long snapshotMinDelayMs);
 	void setWantCanvasHistoryDump(bool wantCanvasHistoryDump);
+	void setCompactUndoPoints(bool compactUndoPoints);
 
 	/// Reset the paint engine to its default state
 	void reset(
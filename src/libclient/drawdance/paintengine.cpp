@@ -64,6 +64,7 @@ net::MessageList PaintEngine::reset(
 		m_data, pushResetMessage, &localResetImage);
 	bool wantCanvasHistoryDump =
 		DP_paint_engine_want_canvas_history_dump(m_data);
+	bool compactUndoPoints = DP_paint_engine_compact_undo_points(m_data);
 	QColor checkerColor1(DP_paint_engine_checker_color1(m_data));
 	QColor checkerColor2(DP_paint_engine_checker_color2(m_data));
 	DP_paint_engine_free_join(m_data);
@@ -77,6 +78,10 @@ net::MessageList PaintEngine::reset(
 		getDumpDir().toUtf8().constData(), &PaintEngine::getTimeMs, nullptr,
 		player, playbackFn, dumpPlaybackFn, playbackUser, streamResetStartFn,
 		streamResetUser);
+	// Recordings decide this for themselves, sessions keep what they had.
+	if(!player) {
+		DP_paint_engine_compact_undo_points_set(m_data, compactUndoPoints);
+	}
 	return localResetImage;
 }
 
@@ -96,6 +101,11 @@ void PaintEngine::setWantCanvasHistoryDump(bool wantCanvasHistoryDump)
 	DP_paint_engine_want_canvas_history_dump_set(m_data, wantCanvasHistoryDump);
 }
 
+void PaintEngine::setCompactUndoPoints(bool compactUndoPoints)
+{
+	DP_paint_engine_compact_undo_points_set(m_data, compactUndoPoints);
+}
+
 QSet<int> PaintEngine::getLayersVisibleInFrame()
 {
 	QSet<int> layersVisibleInFrame;
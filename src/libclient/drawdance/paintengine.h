@@ -70,6 +70,8 @@ public:
 
 	void setWantCanvasHistoryDump(bool wantCanvasHistoryDump);
 
+	void setCompactUndoPoints(bool compactUndoPoints);
+
 	QSet<int> getLayersVisibleInFrame();
 
 	int activeLayerId() const;